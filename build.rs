@@ -0,0 +1,22 @@
+//! Compiles the protocol buffers of the gRPC service when the `grpc` feature is enabled.
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    #[cfg(feature = "grpc")]
+    compile_protos();
+}
+
+/// Generates the messages, server and client of `proto/resizer.proto`, with the `protoc`
+/// binary given by the `PROTOC` variable or else the one vendored for this platform.
+#[cfg(feature = "grpc")]
+fn compile_protos() {
+    println!("cargo:rerun-if-changed=proto/resizer.proto");
+    println!("cargo:rerun-if-env-changed=PROTOC");
+    if std::env::var_os("PROTOC").is_none() {
+        let protoc = protoc_bin_vendored::protoc_bin_path()
+            .expect("No vendored protoc for this platform, set PROTOC to a protoc binary");
+        std::env::set_var("PROTOC", protoc);
+    }
+    tonic_prost_build::compile_protos("proto/resizer.proto")
+        .expect("Failed to compile proto/resizer.proto");
+}
@@ -0,0 +1,147 @@
+//! RGBA pixel buffers.
+//!
+//! Pixels are stored row by row, four bytes per pixel in red, green, blue, alpha order,
+//! without padding between rows. Alpha is straight, not premultiplied. This is the layout
+//! of `image::RgbaImage` and of the `U8x4` images of `fast_image_resize`, so buffers can be
+//! passed between them without copying.
+
+use alloc::{vec, vec::Vec};
+
+/// The number of bytes of a pixel.
+pub const BYTES_PER_PIXEL: usize = 4;
+
+/// An RGBA image with 8 bits per channel.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RgbaBuffer {
+    width: u32,
+    height: u32,
+    pixels: Vec<u8>,
+}
+
+impl RgbaBuffer {
+    /// Creates a transparent black image.
+    ///
+    /// # Returns
+    ///
+    /// The image, or `None` if its size in bytes does not fit in a `usize`.
+    pub fn new(width: u32, height: u32) -> Option<Self> {
+        Some(RgbaBuffer {
+            width,
+            height,
+            pixels: vec![0; byte_len(width, height)?],
+        })
+    }
+
+    /// Wraps pixels, e.g. a frame copied from a camera buffer.
+    ///
+    /// # Returns
+    ///
+    /// The image, or `None` if `pixels` does not have exactly four bytes per pixel.
+    pub fn from_raw(width: u32, height: u32, pixels: Vec<u8>) -> Option<Self> {
+        (pixels.len() == byte_len(width, height)?).then_some(RgbaBuffer {
+            width,
+            height,
+            pixels,
+        })
+    }
+
+    /// Creates an image with every pixel set to `pixel`.
+    ///
+    /// # Returns
+    ///
+    /// The image, or `None` if its size in bytes does not fit in a `usize`.
+    pub fn from_pixel(width: u32, height: u32, pixel: [u8; 4]) -> Option<Self> {
+        let count = byte_len(width, height)? / BYTES_PER_PIXEL;
+        Some(RgbaBuffer {
+            width,
+            height,
+            pixels: pixel.repeat(count),
+        })
+    }
+
+    /// Returns the width in pixels.
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    /// Returns the height in pixels.
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// Returns the pixel at `x`, `y`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the pixel is outside the image.
+    pub fn pixel(&self, x: u32, y: u32) -> [u8; 4] {
+        assert!(x < self.width && y < self.height, "Pixel outside the image");
+        let start = (y as usize * self.width as usize + x as usize) * BYTES_PER_PIXEL;
+        let mut pixel = [0; 4];
+        pixel.copy_from_slice(&self.pixels[start..start + BYTES_PER_PIXEL]);
+        pixel
+    }
+
+    /// Returns the pixels of row `y`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the row is outside the image.
+    pub fn row(&self, y: u32) -> &[u8] {
+        let len = self.width as usize * BYTES_PER_PIXEL;
+        &self.pixels[y as usize * len..][..len]
+    }
+
+    /// Returns all the pixels.
+    pub fn as_raw(&self) -> &[u8] {
+        &self.pixels
+    }
+
+    /// Returns all the pixels, to modify them.
+    pub fn as_raw_mut(&mut self) -> &mut [u8] {
+        &mut self.pixels
+    }
+
+    /// Returns the pixels, without copying them.
+    pub fn into_raw(self) -> Vec<u8> {
+        self.pixels
+    }
+}
+
+/// Returns the number of bytes of a `width` x `height` image, or `None` on overflow.
+fn byte_len(width: u32, height: u32) -> Option<usize> {
+    (width as usize)
+        .checked_mul(height as usize)?
+        .checked_mul(BYTES_PER_PIXEL)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checks_the_length_of_raw_pixels() {
+        assert!(RgbaBuffer::from_raw(2, 3, vec![0; 24]).is_some());
+        assert!(RgbaBuffer::from_raw(2, 3, vec![0; 23]).is_none());
+        assert!(RgbaBuffer::from_raw(u32::MAX, u32::MAX, Vec::new()).is_none());
+        assert_eq!(RgbaBuffer::new(0, 5).unwrap().as_raw().len(), 0);
+    }
+
+    #[test]
+    fn addresses_pixels_row_by_row() {
+        let pixels = (0..24).collect();
+        let image = RgbaBuffer::from_raw(2, 3, pixels).unwrap();
+        assert_eq!(image.pixel(1, 0), [4, 5, 6, 7]);
+        assert_eq!(image.pixel(0, 2), [16, 17, 18, 19]);
+        assert_eq!(image.row(1), &[8, 9, 10, 11, 12, 13, 14, 15]);
+        let solid = RgbaBuffer::from_pixel(3, 2, [1, 2, 3, 4]).unwrap();
+        assert_eq!(solid.pixel(2, 1), [1, 2, 3, 4]);
+        assert_eq!(solid.into_raw().len(), 24);
+    }
+
+    #[test]
+    fn rejects_sizes_overflowing_usize() {
+        assert!(RgbaBuffer::new(u32::MAX, u32::MAX).is_none());
+        assert!(RgbaBuffer::from_pixel(u32::MAX, u32::MAX, [0; 4]).is_none());
+    }
+}
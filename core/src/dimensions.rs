@@ -0,0 +1,294 @@
+//! Output sizes of resizes.
+//!
+//! A resize is requested with a width, a height, or both, and optionally a maximum number
+//! of output pixels. The missing side is computed from the aspect ratio of the source and
+//! rounded with a `Rounding` mode, and both sides can be rounded to a multiple, e.g. `2` for
+//! video encoders requiring even dimensions. All computations are exact integer
+//! arithmetic, so that sizes never depend on floating-point error or on the platform.
+
+use alloc::{format, string::String};
+use core::{fmt, str::FromStr};
+
+/// How a side computed from the aspect ratio is rounded to whole pixels. Whatever the mode,
+/// the result is at least one pixel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Rounding {
+    /// Round to the nearest pixel, halves up.
+    #[default]
+    Round,
+    /// Round down, never exceeding the aspect ratio.
+    Floor,
+    /// Round up, never falling short of the aspect ratio.
+    Ceil,
+}
+
+impl Rounding {
+    /// Divides `numerator` by `denominator`, rounding the quotient with this mode to at
+    /// least one. The division is exact, so that sizes never depend on floating-point error.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `denominator` is zero.
+    pub fn divide(self, numerator: u64, denominator: u64) -> u64 {
+        let quotient = match self {
+            Rounding::Round => {
+                (2 * numerator as u128 + denominator as u128) / (2 * denominator as u128)
+            }
+            Rounding::Floor => numerator as u128 / denominator as u128,
+            Rounding::Ceil => (numerator as u128).div_ceil(denominator as u128),
+        };
+        (quotient as u64).max(1)
+    }
+}
+
+impl fmt::Display for Rounding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Rounding::Round => "round",
+            Rounding::Floor => "floor",
+            Rounding::Ceil => "ceil",
+        };
+        f.write_str(name)
+    }
+}
+
+impl FromStr for Rounding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "round" => Ok(Rounding::Round),
+            "floor" => Ok(Rounding::Floor),
+            "ceil" => Ok(Rounding::Ceil),
+            _ => Err(format!("Unknown rounding mode '{}'", s)),
+        }
+    }
+}
+
+/// How output sizes are rounded and bounded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sizing {
+    /// How the side computed from the aspect ratio is rounded to whole pixels.
+    pub rounding: Rounding,
+    /// Both sides of the output are rounded to a multiple of this, with `rounding`. `1`
+    /// keeps the sizes as they are.
+    pub multiple_of: u32,
+    /// The maximum number of output pixels, if any. Larger outputs are scaled down to fit,
+    /// keeping their aspect ratio.
+    pub max_output_pixels: Option<u64>,
+}
+
+impl Default for Sizing {
+    fn default() -> Self {
+        Sizing {
+            rounding: Rounding::default(),
+            multiple_of: 1,
+            max_output_pixels: None,
+        }
+    }
+}
+
+/// The reasons an output size cannot be computed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DimensionError {
+    /// The source has a zero width or height.
+    EmptySource {
+        /// The width of the source.
+        width: u32,
+        /// The height of the source.
+        height: u32,
+    },
+    /// The requested width or height is zero.
+    ZeroSide,
+    /// The sides are to be rounded to a multiple of zero.
+    ZeroMultiple,
+    /// Neither a width, nor a height, nor a maximum number of pixels was given.
+    Unspecified,
+    /// The output is too large to be allocated.
+    TooLarge {
+        /// The width of the output.
+        width: u32,
+        /// The height of the output.
+        height: u32,
+    },
+}
+
+impl fmt::Display for DimensionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DimensionError::EmptySource { width, height } => {
+                write!(f, "Invalid image dimensions {}x{}", width, height)
+            }
+            DimensionError::ZeroSide => write!(f, "The width and height must be greater than zero"),
+            DimensionError::ZeroMultiple => write!(f, "Dimensions cannot be a multiple of zero"),
+            DimensionError::Unspecified => {
+                write!(f, "At least one of width or height must be specified")
+            }
+            DimensionError::TooLarge { width, height } => {
+                write!(
+                    f,
+                    "A {}x{} image is too large to be allocated",
+                    width, height
+                )
+            }
+        }
+    }
+}
+
+impl core::error::Error for DimensionError {}
+
+/// Determines the output size of a resize.
+///
+/// # Arguments
+///
+/// * `src_width` - The width of the source image.
+/// * `src_height` - The height of the source image.
+/// * `width` - An optional new width. If None, it is computed from the height.
+/// * `height` - An optional new height. If None, it is computed from the width.
+/// * `sizing` - How the computed side is rounded, the multiple both sides are rounded to,
+///   and the maximum number of output pixels. With a maximum, the width and height are
+///   optional: the source size is scaled down to fit it.
+///
+/// # Returns
+///
+/// The new width and height, each at least one pixel (or one multiple).
+///
+/// # Errors
+///
+/// This function will return an error if the source has a zero side, if a requested side
+/// or the multiple is zero, or if neither a width, a height nor a maximum number of pixels
+/// is given.
+pub fn new_dimensions(
+    src_width: u32,
+    src_height: u32,
+    width: Option<u32>,
+    height: Option<u32>,
+    sizing: &Sizing,
+) -> Result<(u32, u32), DimensionError> {
+    if src_width == 0 || src_height == 0 {
+        return Err(DimensionError::EmptySource {
+            width: src_width,
+            height: src_height,
+        });
+    }
+    if width == Some(0) || height == Some(0) {
+        return Err(DimensionError::ZeroSide);
+    }
+    if sizing.multiple_of == 0 {
+        return Err(DimensionError::ZeroMultiple);
+    }
+    // Sides are rounded once, straight to a multiple, so that the computed side of a very
+    // narrow image is kept at least one multiple (or pixel) wide
+    let multiple = sizing.multiple_of as u64;
+    let scale = |side: u32, numerator: u32, denominator: u32| {
+        let units = sizing.rounding.divide(
+            side as u64 * numerator as u64,
+            denominator as u64 * multiple,
+        );
+        (units * multiple).min(u32::MAX as u64) as u32
+    };
+    let (new_width, new_height) = match (width, height) {
+        (Some(w), Some(h)) => (scale(w, 1, 1), scale(h, 1, 1)),
+        (Some(w), None) => (scale(w, 1, 1), scale(w, src_height, src_width)),
+        (None, Some(h)) => (scale(h, src_width, src_height), scale(h, 1, 1)),
+        (None, None) if sizing.max_output_pixels.is_some() => {
+            (scale(src_width, 1, 1), scale(src_height, 1, 1))
+        }
+        (None, None) => return Err(DimensionError::Unspecified),
+    };
+
+    match sizing.max_output_pixels {
+        Some(max_pixels) if new_width as u64 * new_height as u64 > max_pixels => {
+            Ok(fit_pixels(new_width, new_height, max_pixels, sizing))
+        }
+        _ => Ok((new_width, new_height)),
+    }
+}
+
+/// Scales dimensions down to at most `max_pixels` pixels, keeping their aspect ratio.
+///
+/// The longer side is the largest multiple of `sizing.multiple_of` for which the shorter
+/// side, rounded with `sizing.rounding`, fits the budget. Both sides are kept at least one
+/// multiple long, even if that exceeds a tiny budget.
+fn fit_pixels(width: u32, height: u32, max_pixels: u64, sizing: &Sizing) -> (u32, u32) {
+    if width < height {
+        let (new_height, new_width) = fit_pixels(height, width, max_pixels, sizing);
+        return (new_width, new_height);
+    }
+    let multiple = sizing.multiple_of as u64;
+    let (long, short) = (width as u64, height as u64);
+    // Start from the exact solution and step down until the rounded size fits
+    let exact = (max_pixels as u128 * long as u128 / short as u128).isqrt() as u64;
+    let mut units = (exact / multiple).clamp(1, (long / multiple).max(1));
+    loop {
+        let new_long = units * multiple;
+        let new_short = sizing.rounding.divide(new_long * short, long * multiple) * multiple;
+        if new_long * new_short <= max_pixels || units == 1 {
+            return (new_long as u32, new_short.min(u32::MAX as u64) as u32);
+        }
+        units = (units - 1).min(max_pixels / new_short / multiple).max(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_the_aspect_ratio() {
+        let sizing = Sizing::default();
+        assert_eq!(new_dimensions(40, 20, Some(10), None, &sizing), Ok((10, 5)));
+        assert_eq!(
+            new_dimensions(30, 60, None, Some(20), &sizing),
+            Ok((10, 20))
+        );
+        assert_eq!(
+            new_dimensions(1000, 1, Some(10), None, &sizing),
+            Ok((10, 1))
+        );
+        let even = Sizing {
+            multiple_of: 2,
+            ..sizing
+        };
+        assert_eq!(
+            new_dimensions(1920, 1080, Some(853), None, &even),
+            Ok((854, 480))
+        );
+        let budget = Sizing {
+            max_output_pixels: Some(12_000_000),
+            ..sizing
+        };
+        assert_eq!(
+            new_dimensions(6000, 4000, None, None, &budget),
+            Ok((4242, 2828))
+        );
+    }
+
+    #[test]
+    fn rejects_impossible_sizes() {
+        let sizing = Sizing::default();
+        assert_eq!(
+            new_dimensions(0, 10, Some(5), None, &sizing),
+            Err(DimensionError::EmptySource {
+                width: 0,
+                height: 10
+            })
+        );
+        assert_eq!(
+            new_dimensions(10, 10, Some(0), None, &sizing),
+            Err(DimensionError::ZeroSide)
+        );
+        assert_eq!(
+            new_dimensions(10, 10, None, None, &sizing),
+            Err(DimensionError::Unspecified)
+        );
+        let zero = Sizing {
+            multiple_of: 0,
+            ..sizing
+        };
+        assert_eq!(
+            new_dimensions(10, 10, Some(5), None, &zero),
+            Err(DimensionError::ZeroMultiple)
+        );
+    }
+}
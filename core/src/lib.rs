@@ -0,0 +1,28 @@
+//! # Image Resizing Core
+//!
+//! The resize math of `image-resizer-rust`, without `std`. It only needs an allocator, so
+//! that devices without a filesystem or an operating system, such as cameras resizing
+//! frames straight from their sensor buffers, can compute output sizes and resize images
+//! the same way as the command line tool.
+//!
+//! The main items provided are:
+//! - `dimensions::new_dimensions`: Computes the output size of a resize from the requested
+//!   width, height and pixel budget, with the `Rounding` of the command line tool
+//! - `buffer::RgbaBuffer`: An RGBA image with 8 bits per channel, wrapping a `Vec<u8>`
+//! - `resample::resize`: Resizes an `RgbaBuffer` with a convolution `Filter`
+//!
+//! The resizer is portable and has no SIMD code. On systems with `std`,
+//! `image-resizer-rust` resizes with `fast_image_resize` instead, which is much faster;
+//! both resize to the same dimensions.
+
+#![no_std]
+
+extern crate alloc;
+
+pub mod buffer;
+pub mod dimensions;
+pub mod resample;
+
+pub use buffer::RgbaBuffer;
+pub use dimensions::{new_dimensions, DimensionError, Rounding, Sizing};
+pub use resample::{resize, resize_to, Filter};
@@ -0,0 +1,257 @@
+//! A portable resizer.
+//!
+//! Images are resized with a separable convolution: rows are resampled first, then columns.
+//! When downscaling, the filter is stretched over the source pixels covered by each output
+//! pixel, so that they are all averaged instead of skipped. Colors are premultiplied by
+//! their alpha while resampling, so that transparent pixels do not bleed their color into
+//! the opaque pixels next to them.
+//!
+//! Only arithmetic available in `core` is used, so results are identical on every platform
+//! for the same inputs.
+
+use crate::{
+    buffer::{RgbaBuffer, BYTES_PER_PIXEL},
+    dimensions::{new_dimensions, DimensionError, Sizing},
+};
+use alloc::{vec, vec::Vec};
+
+/// The resampling filter of a resize.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Filter {
+    /// Takes the source pixel nearest to each output pixel. The fastest filter, which keeps
+    /// hard edges, e.g. of pixel art, but aliases photos.
+    Nearest,
+    /// Interpolates linearly between source pixels (a triangle filter).
+    Bilinear,
+    /// A cubic filter, sharper than bilinear, which suits most photos.
+    #[default]
+    CatmullRom,
+}
+
+impl Filter {
+    /// Returns the radius of the filter, in source pixels when not downscaling.
+    fn support(self) -> f32 {
+        match self {
+            Filter::Nearest => 0.5,
+            Filter::Bilinear => 1.0,
+            Filter::CatmullRom => 2.0,
+        }
+    }
+
+    /// Returns the weight of a source pixel at `x` pixels from the center of the filter.
+    fn weight(self, x: f32) -> f32 {
+        let x = if x < 0.0 { -x } else { x };
+        match self {
+            Filter::Nearest => {
+                if x < 0.5 {
+                    1.0
+                } else {
+                    0.0
+                }
+            }
+            Filter::Bilinear => (1.0 - x).max(0.0),
+            Filter::CatmullRom => {
+                if x < 1.0 {
+                    (1.5 * x - 2.5) * x * x + 1.0
+                } else if x < 2.0 {
+                    ((-0.5 * x + 2.5) * x - 4.0) * x + 2.0
+                } else {
+                    0.0
+                }
+            }
+        }
+    }
+}
+
+/// The source pixels contributing to an output pixel, along one axis.
+struct Taps {
+    /// The index of the first source pixel.
+    start: usize,
+    /// The weights of the source pixels from `start`, summing to one.
+    weights: Vec<f32>,
+}
+
+/// Computes the taps of every output pixel along an axis of `src_len` source pixels.
+fn taps(src_len: u32, dst_len: u32, filter: Filter) -> Vec<Taps> {
+    let scale = src_len as f32 / dst_len as f32;
+    if filter == Filter::Nearest {
+        return (0..dst_len)
+            .map(|i| Taps {
+                start: (((i as f32 + 0.5) * scale) as usize).min(src_len as usize - 1),
+                weights: vec![1.0],
+            })
+            .collect();
+    }
+    let filter_scale = scale.max(1.0);
+    let radius = filter.support() * filter_scale;
+    (0..dst_len)
+        .map(|i| {
+            let center = (i as f32 + 0.5) * scale;
+            // Truncation rounds down, since both bounds are clamped to zero first
+            let start = ((center - radius).max(0.0) as usize).min(src_len as usize - 1);
+            let end = (((center + radius).max(0.0) as usize) + 1).min(src_len as usize);
+            let mut weights: Vec<f32> = (start..end)
+                .map(|j| filter.weight((j as f32 + 0.5 - center) / filter_scale))
+                .collect();
+            let sum: f32 = weights.iter().sum();
+            if sum == 0.0 {
+                return Taps {
+                    start: (center as usize).min(src_len as usize - 1),
+                    weights: vec![1.0],
+                };
+            }
+            weights.iter_mut().for_each(|weight| *weight /= sum);
+            Taps { start, weights }
+        })
+        .collect()
+}
+
+/// Resizes an image to exactly `width` by `height` pixels.
+///
+/// An empty source, or an empty output size, gives an empty image of the output size.
+///
+/// # Returns
+///
+/// The resized image, or `None` if its size in bytes does not fit in a `usize`.
+pub fn resize(src: &RgbaBuffer, width: u32, height: u32, filter: Filter) -> Option<RgbaBuffer> {
+    if src.width() == 0 || src.height() == 0 || width == 0 || height == 0 {
+        return RgbaBuffer::new(width, height);
+    }
+    let mut output = RgbaBuffer::new(width, height)?;
+    let columns = taps(src.width(), width, filter);
+    let rows = taps(src.height(), height, filter);
+
+    // Resample every source row horizontally, as premultiplied colors
+    let row_len = width as usize * BYTES_PER_PIXEL;
+    let mut horizontal = vec![0.0f32; row_len * src.height() as usize];
+    let mut premultiplied = vec![0.0f32; src.width() as usize * BYTES_PER_PIXEL];
+    for y in 0..src.height() {
+        for (pixel, source) in premultiplied
+            .chunks_exact_mut(BYTES_PER_PIXEL)
+            .zip(src.row(y).chunks_exact(BYTES_PER_PIXEL))
+        {
+            let alpha = source[3] as f32 / 255.0;
+            for channel in 0..3 {
+                pixel[channel] = source[channel] as f32 * alpha;
+            }
+            pixel[3] = source[3] as f32;
+        }
+        let out = &mut horizontal[y as usize * row_len..][..row_len];
+        for (pixel, taps) in out.chunks_exact_mut(BYTES_PER_PIXEL).zip(&columns) {
+            for (k, weight) in taps.weights.iter().enumerate() {
+                let source = &premultiplied[(taps.start + k) * BYTES_PER_PIXEL..];
+                for channel in 0..BYTES_PER_PIXEL {
+                    pixel[channel] += source[channel] * weight;
+                }
+            }
+        }
+    }
+
+    // Resample the columns vertically, then restore straight alpha
+    let mut sums = vec![0.0f32; row_len];
+    for (y, taps) in rows.iter().enumerate() {
+        sums.iter_mut().for_each(|sum| *sum = 0.0);
+        for (k, weight) in taps.weights.iter().enumerate() {
+            let source = &horizontal[(taps.start + k) * row_len..][..row_len];
+            for (sum, value) in sums.iter_mut().zip(source) {
+                *sum += value * weight;
+            }
+        }
+        let out = &mut output.as_raw_mut()[y * row_len..][..row_len];
+        for (pixel, sum) in out
+            .chunks_exact_mut(BYTES_PER_PIXEL)
+            .zip(sums.chunks_exact(BYTES_PER_PIXEL))
+        {
+            let alpha = sum[3].clamp(0.0, 255.0);
+            for channel in 0..3 {
+                let value = if alpha > 0.0 {
+                    sum[channel] * 255.0 / alpha
+                } else {
+                    0.0
+                };
+                pixel[channel] = to_u8(value);
+            }
+            pixel[3] = to_u8(alpha);
+        }
+    }
+    Some(output)
+}
+
+/// Resizes an image to the size computed by `dimensions::new_dimensions`.
+///
+/// # Errors
+///
+/// This function will return an error if the output size cannot be computed, or
+/// `DimensionError::TooLarge` if the output does not fit in memory.
+pub fn resize_to(
+    src: &RgbaBuffer,
+    width: Option<u32>,
+    height: Option<u32>,
+    sizing: &Sizing,
+    filter: Filter,
+) -> Result<RgbaBuffer, DimensionError> {
+    let (new_width, new_height) = new_dimensions(src.width(), src.height(), width, height, sizing)?;
+    resize(src, new_width, new_height, filter).ok_or(DimensionError::TooLarge {
+        width: new_width,
+        height: new_height,
+    })
+}
+
+/// Rounds a channel value to the nearest byte, clamping it to the valid range.
+fn to_u8(value: f32) -> u8 {
+    (value.clamp(0.0, 255.0) + 0.5) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_uniform_images_uniform() {
+        let src = RgbaBuffer::from_pixel(40, 20, [10, 200, 30, 255]).unwrap();
+        for filter in [Filter::Nearest, Filter::Bilinear, Filter::CatmullRom] {
+            for (width, height) in [(10, 5), (100, 50), (7, 33)] {
+                let resized = resize(&src, width, height, filter).unwrap();
+                assert_eq!((resized.width(), resized.height()), (width, height));
+                assert!(resized
+                    .as_raw()
+                    .chunks_exact(4)
+                    .all(|pixel| pixel == [10, 200, 30, 255]));
+            }
+        }
+        let resized = resize_to(&src, Some(8), None, &Sizing::default(), Filter::default());
+        assert_eq!(resized.unwrap().height(), 4);
+        assert_eq!(
+            resize(&RgbaBuffer::new(0, 3).unwrap(), 4, 4, Filter::Bilinear)
+                .unwrap()
+                .width(),
+            4
+        );
+    }
+
+    #[test]
+    fn averages_without_bleeding_transparent_colors() {
+        // Opaque red on the left, transparent green on the right
+        let mut src = RgbaBuffer::new(4, 1).unwrap();
+        src.as_raw_mut()[..8].copy_from_slice(&[255, 0, 0, 255, 255, 0, 0, 255]);
+        src.as_raw_mut()[8..].copy_from_slice(&[0, 255, 0, 0, 0, 255, 0, 0]);
+        let resized = resize(&src, 1, 1, Filter::Bilinear).unwrap();
+        assert_eq!(resized.pixel(0, 0), [255, 0, 0, 128]);
+
+        // Downscaling averages the source pixels instead of skipping every other one
+        let stripes = RgbaBuffer::from_raw(
+            4,
+            1,
+            [[0, 0, 0, 255], [200, 200, 200, 255]].repeat(2).concat(),
+        )
+        .unwrap();
+        let resized = resize(&stripes, 2, 1, Filter::Bilinear).unwrap();
+        for x in 0..2 {
+            let [red, green, blue, alpha] = resized.pixel(x, 0);
+            assert!((80..=120).contains(&red), "{}", red);
+            assert_eq!((green, blue, alpha), (red, red, 255));
+        }
+        let nearest = resize(&stripes, 2, 1, Filter::Nearest).unwrap();
+        assert_eq!(nearest.pixel(0, 0), [200, 200, 200, 255]);
+    }
+}
@@ -0,0 +1,302 @@
+//! App icon sets.
+//!
+//! Mobile platforms need an app icon in many sizes, laid out in a fixed structure:
+//! - iOS: an `AppIcon.appiconset` asset catalog folder with one PNG per size and a
+//!   `Contents.json` describing which file is used for each device, point size and scale.
+//! - Android: one `ic_launcher.png` per density in the `mipmap-*` resource folders, plus the
+//!   512px icon for the Play Store listing.
+//!
+//! `generate` produces these from a single square source image of at least 1024x1024
+//! pixels. The source is decoded once and resized for every size. iOS icons are drawn over
+//! white, since the App Store rejects icons with an alpha channel.
+
+use crate::{
+    encode::{encode_png, EncodeOptions},
+    resize_image,
+    storage::{FileSystem, Storage},
+};
+use image::{DynamicImage, GenericImageView, Rgba, RgbaImage};
+use std::{
+    error::Error,
+    fmt, fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+/// The minimum width and height of the source image.
+pub const MIN_SOURCE_SIZE: u32 = 1024;
+
+/// The name of the iOS asset catalog folder.
+const IOS_ICON_SET: &str = "AppIcon.appiconset";
+
+/// The icons of an iOS icon set, as `(idiom, size in points, scale)`.
+const IOS_ICONS: [(&str, &str, u32); 18] = [
+    ("iphone", "20", 2),
+    ("iphone", "20", 3),
+    ("iphone", "29", 2),
+    ("iphone", "29", 3),
+    ("iphone", "40", 2),
+    ("iphone", "40", 3),
+    ("iphone", "60", 2),
+    ("iphone", "60", 3),
+    ("ipad", "20", 1),
+    ("ipad", "20", 2),
+    ("ipad", "29", 1),
+    ("ipad", "29", 2),
+    ("ipad", "40", 1),
+    ("ipad", "40", 2),
+    ("ipad", "76", 1),
+    ("ipad", "76", 2),
+    ("ipad", "83.5", 2),
+    ("ios-marketing", "1024", 1),
+];
+
+/// The Android launcher icons, as `(path, size in pixels)`.
+const ANDROID_ICONS: [(&str, u32); 6] = [
+    ("mipmap-mdpi/ic_launcher.png", 48),
+    ("mipmap-hdpi/ic_launcher.png", 72),
+    ("mipmap-xhdpi/ic_launcher.png", 96),
+    ("mipmap-xxhdpi/ic_launcher.png", 144),
+    ("mipmap-xxxhdpi/ic_launcher.png", 192),
+    ("ic_launcher-playstore.png", 512),
+];
+
+/// The platforms an icon set can be generated for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Platform {
+    /// iOS and iPadOS.
+    Ios,
+    /// Android.
+    Android,
+}
+
+impl Platform {
+    /// Returns the name of the folder the icons of this platform are written to.
+    pub fn dir_name(&self) -> &'static str {
+        match self {
+            Platform::Ios => "ios",
+            Platform::Android => "android",
+        }
+    }
+
+    /// Returns the icons of this platform.
+    pub fn icons(&self) -> Vec<Icon> {
+        match self {
+            Platform::Ios => {
+                let mut icons: Vec<Icon> = Vec::new();
+                for (_, size, scale) in IOS_ICONS {
+                    let icon = Icon {
+                        path: Path::new(IOS_ICON_SET).join(ios_file_name(size, scale)),
+                        size: ios_pixels(size, scale),
+                    };
+                    // Several idioms share the same file
+                    if !icons.contains(&icon) {
+                        icons.push(icon);
+                    }
+                }
+                icons
+            }
+            Platform::Android => ANDROID_ICONS
+                .iter()
+                .map(|&(path, size)| Icon {
+                    path: PathBuf::from(path),
+                    size,
+                })
+                .collect(),
+        }
+    }
+}
+
+impl fmt::Display for Platform {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.dir_name())
+    }
+}
+
+impl FromStr for Platform {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "ios" => Ok(Platform::Ios),
+            "android" => Ok(Platform::Android),
+            _ => Err(format!("Unknown platform '{}'", s)),
+        }
+    }
+}
+
+/// A square icon of an icon set.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Icon {
+    /// The path of the icon, relative to the folder of its platform.
+    pub path: PathBuf,
+    /// The width and height of the icon in pixels.
+    pub size: u32,
+}
+
+/// Generates the icon sets of the given platforms from a source image.
+///
+/// The icons of each platform are written to a subfolder of `dir` named after the platform
+/// (`ios` or `android`). Existing icons are replaced.
+///
+/// # Arguments
+///
+/// * `source` - The source image, square and at least `MIN_SOURCE_SIZE` pixels wide.
+/// * `dir` - The folder to write the icon sets to.
+/// * `platforms` - The platforms to generate icon sets for.
+/// * `options` - The encoder settings of the PNG icons.
+///
+/// # Returns
+///
+/// The paths of the written files.
+///
+/// # Errors
+///
+/// This function will return an error if the source image is not square or too small, or
+/// if an icon cannot be resized, encoded or written.
+pub fn generate(
+    source: &DynamicImage,
+    dir: &Path,
+    platforms: &[Platform],
+    options: &EncodeOptions,
+) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let (width, height) = source.dimensions();
+    if width != height {
+        return Err(format!("The icon source must be square, got {}x{}", width, height).into());
+    }
+    if width < MIN_SOURCE_SIZE {
+        return Err(format!(
+            "The icon source must be at least {0}x{0} pixels, got {1}x{1}",
+            MIN_SOURCE_SIZE, width
+        )
+        .into());
+    }
+
+    let mut written = Vec::new();
+    for platform in platforms {
+        let platform_dir = dir.join(platform.dir_name());
+        for icon in platform.icons() {
+            let mut image = resize_image(source.clone(), Some(&icon.size), Some(&icon.size))?;
+            if *platform == Platform::Ios {
+                image = flatten_on_white(&image);
+            }
+            let path = platform_dir.join(&icon.path);
+            write_file(&path, &encode_png(&image, options)?)?;
+            written.push(path);
+        }
+        if *platform == Platform::Ios {
+            let path = platform_dir.join(IOS_ICON_SET).join("Contents.json");
+            write_file(&path, ios_contents_json().as_bytes())?;
+            written.push(path);
+        }
+    }
+    Ok(written)
+}
+
+/// Returns the `Contents.json` of the iOS asset catalog folder.
+pub fn ios_contents_json() -> String {
+    let images: Vec<String> = IOS_ICONS
+        .iter()
+        .map(|&(idiom, size, scale)| {
+            format!(
+                "    {{\n      \"filename\" : \"{}\",\n      \"idiom\" : \"{}\",\n      \"scale\" : \"{}x\",\n      \"size\" : \"{}x{}\"\n    }}",
+                ios_file_name(size, scale),
+                idiom,
+                scale,
+                size,
+                size
+            )
+        })
+        .collect();
+    format!(
+        "{{\n  \"images\" : [\n{}\n  ],\n  \"info\" : {{\n    \"author\" : \"xcode\",\n    \"version\" : 1\n  }}\n}}\n",
+        images.join(",\n")
+    )
+}
+
+/// Returns the file name of an iOS icon, such as `Icon-60@3x.png`.
+fn ios_file_name(size: &str, scale: u32) -> String {
+    format!("Icon-{}@{}x.png", size, scale)
+}
+
+/// Returns the size in pixels of an iOS icon.
+fn ios_pixels(size: &str, scale: u32) -> u32 {
+    let points: f64 = size.parse().unwrap_or_default();
+    (points * scale as f64).round() as u32
+}
+
+/// Draws an image over a white background, removing its transparency.
+fn flatten_on_white(image: &RgbaImage) -> RgbaImage {
+    RgbaImage::from_fn(image.width(), image.height(), |x, y| {
+        let Rgba([r, g, b, a]) = *image.get_pixel(x, y);
+        let blend = |c: u8| ((c as u32 * a as u32 + 255 * (255 - a as u32)) / 255) as u8;
+        Rgba([blend(r), blend(g), blend(b), 255])
+    })
+}
+
+/// Writes a file, creating its parent folders.
+fn write_file(path: &Path, contents: &[u8]) -> Result<(), Box<dyn Error>> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    FileSystem
+        .write_file(path, contents, true)
+        .map_err(|e| format!("Failed to write {:?}: {}", path, e).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn lists_platform_icons() {
+        let ios = Platform::Ios.icons();
+        // The 18 entries of Contents.json share 15 distinct files
+        assert_eq!(ios.len(), 15);
+        assert!(ios.contains(&Icon {
+            path: PathBuf::from("AppIcon.appiconset/Icon-83.5@2x.png"),
+            size: 167,
+        }));
+
+        let json = ios_contents_json();
+        assert_eq!(json.matches("\"filename\"").count(), IOS_ICONS.len());
+        assert!(json.contains("\"size\" : \"83.5x83.5\""));
+
+        assert_eq!(Platform::Android.icons()[4].size, 192);
+        assert_eq!("Android".parse::<Platform>(), Ok(Platform::Android));
+    }
+
+    #[test]
+    fn generates_icon_sets() {
+        let dir = TempDir::new().expect("Failed to create a temp dir");
+        let source =
+            DynamicImage::ImageRgba8(RgbaImage::from_pixel(1024, 1024, Rgba([255, 0, 0, 0])));
+
+        let written = generate(
+            &source,
+            dir.path(),
+            &[Platform::Ios, Platform::Android],
+            &EncodeOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(written.len(), 15 + 1 + 6);
+
+        let icon = image::open(dir.path().join("ios/AppIcon.appiconset/Icon-20@3x.png"))
+            .unwrap()
+            .into_rgba8();
+        assert_eq!(icon.dimensions(), (60, 60));
+        assert_eq!(icon.get_pixel(0, 0), &Rgba([255, 255, 255, 255]));
+        let icon = image::open(dir.path().join("android/mipmap-hdpi/ic_launcher.png")).unwrap();
+        assert_eq!(icon.dimensions(), (72, 72));
+
+        let small = DynamicImage::ImageRgba8(RgbaImage::new(512, 512));
+        assert!(generate(
+            &small,
+            dir.path(),
+            &[Platform::Ios],
+            &EncodeOptions::default()
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,347 @@
+//! Archive inputs.
+//!
+//! A ZIP or tar archive can be given as an input to resize every image inside it. The
+//! images are extracted to a temporary directory, so that they go through the same
+//! pipeline as regular files, and each one keeps its path within the archive. The batch
+//! uses that path to reproduce the internal directory structure in the output directory
+//! or archive.
+//!
+//! Only regular files with an image extension are extracted. Entries whose path would
+//! leave the archive (absolute paths or `..` components) are skipped. Since a small archive
+//! can expand to an enormous size (a zip bomb), extraction stops with an error once the
+//! archive exceeds the entry count or the total size of `Limits`, before filling the temporary
+//! directory. Archives with two images of the same name are rejected, rather than letting
+//! one replace the other.
+
+use crate::limits::Limits;
+use flate2::read::GzDecoder;
+use image::ImageFormat;
+use std::{
+    error::Error,
+    fs::{self, File, OpenOptions},
+    io::{self, Read},
+    path::{Component, Path, PathBuf},
+};
+use tempfile::TempDir;
+
+/// The number of bytes extracted from an archive when `Limits::max_archive_size` is not set.
+pub const DEFAULT_MAX_SIZE: u64 = 4 << 30;
+/// The number of entries of an archive when `Limits::max_archive_entries` is not set.
+pub const DEFAULT_MAX_ENTRIES: u64 = 100_000;
+
+/// The archive formats accepted as inputs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveFormat {
+    /// A ZIP archive (`.zip`).
+    Zip,
+    /// An uncompressed tar archive (`.tar`).
+    Tar,
+    /// A gzip-compressed tar archive (`.tar.gz` or `.tgz`).
+    TarGz,
+}
+
+impl ArchiveFormat {
+    /// Detects the archive format from the extension of `path`.
+    ///
+    /// Returns `None` if the path does not look like a supported archive.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let name = path.file_name()?.to_string_lossy().to_lowercase();
+        if name.ends_with(".zip") {
+            Some(ArchiveFormat::Zip)
+        } else if name.ends_with(".tar") {
+            Some(ArchiveFormat::Tar)
+        } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            Some(ArchiveFormat::TarGz)
+        } else {
+            None
+        }
+    }
+}
+
+/// An image extracted from an archive.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArchiveEntry {
+    /// The relative path of the image within the archive.
+    pub name: PathBuf,
+    /// The path of the extracted file.
+    pub path: PathBuf,
+}
+
+/// The images of an archive, extracted to a temporary directory.
+///
+/// The directory and the extracted files are deleted when this value is dropped.
+#[derive(Debug)]
+pub struct ExtractedArchive {
+    /// The path of the archive.
+    pub source: PathBuf,
+    /// The extracted images, in archive order.
+    pub entries: Vec<ArchiveEntry>,
+    _dir: TempDir,
+}
+
+/// Extracts the images of the archive at `path` to a temporary directory.
+///
+/// # Arguments
+///
+/// * `path` - The path of the archive.
+/// * `limits` - The limits on the number of entries and the extracted size of the archive.
+///
+/// # Errors
+///
+/// This function will return an error if:
+/// - The path is not a supported archive.
+/// - The archive cannot be read or extracted.
+/// - The archive has more entries, or its images more bytes, than `limits` allow.
+/// - Two images of the archive have the same name.
+pub fn extract_images(path: &Path, limits: &Limits) -> Result<ExtractedArchive, Box<dyn Error>> {
+    let format = ArchiveFormat::from_path(path)
+        .ok_or_else(|| format!("{:?} is not a supported archive", path))?;
+    let dir = TempDir::new()?;
+    let file = File::open(path).map_err(|e| format!("Failed to open {:?}: {}", path, e))?;
+
+    let mut budget = Budget::new(limits);
+    let entries = match format {
+        ArchiveFormat::Zip => extract_zip(file, dir.path(), &mut budget)?,
+        ArchiveFormat::Tar => extract_tar(file, dir.path(), &mut budget)?,
+        ArchiveFormat::TarGz => extract_tar(GzDecoder::new(file), dir.path(), &mut budget)?,
+    };
+
+    Ok(ExtractedArchive {
+        source: path.to_path_buf(),
+        entries,
+        _dir: dir,
+    })
+}
+
+/// The entries and bytes an archive may still use.
+struct Budget {
+    entries: u64,
+    bytes: u64,
+    max_entries: u64,
+    max_bytes: u64,
+}
+
+impl Budget {
+    fn new(limits: &Limits) -> Self {
+        let max_entries = limits.max_archive_entries.unwrap_or(DEFAULT_MAX_ENTRIES);
+        let max_bytes = limits.max_archive_size.unwrap_or(DEFAULT_MAX_SIZE);
+        Budget {
+            entries: max_entries,
+            bytes: max_bytes,
+            max_entries,
+            max_bytes,
+        }
+    }
+
+    /// Counts one more entry.
+    fn take_entry(&mut self) -> Result<(), Box<dyn Error>> {
+        self.entries = self.entries.checked_sub(1).ok_or_else(|| {
+            format!(
+                "The archive has more than {} entries (see --max-archive-entries)",
+                self.max_entries
+            )
+        })?;
+        Ok(())
+    }
+}
+
+/// Extracts the images of a ZIP archive into `dir`.
+fn extract_zip(
+    file: File,
+    dir: &Path,
+    budget: &mut Budget,
+) -> Result<Vec<ArchiveEntry>, Box<dyn Error>> {
+    let mut archive = zip::ZipArchive::new(file)?;
+    let mut entries = Vec::new();
+    for index in 0..archive.len() {
+        budget.take_entry()?;
+        let mut entry = archive.by_index(index)?;
+        if !entry.is_file() {
+            continue;
+        }
+        if let Some(name) = entry.enclosed_name().and_then(|name| image_name(&name)) {
+            entries.push(write_entry(&mut entry, name, dir, budget)?);
+        }
+    }
+    Ok(entries)
+}
+
+/// Extracts the images of a tar archive into `dir`.
+fn extract_tar(
+    reader: impl Read,
+    dir: &Path,
+    budget: &mut Budget,
+) -> Result<Vec<ArchiveEntry>, Box<dyn Error>> {
+    let mut archive = tar::Archive::new(reader);
+    let mut entries = Vec::new();
+    for entry in archive.entries()? {
+        budget.take_entry()?;
+        let mut entry = entry?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        if let Some(name) = image_name(&entry.path()?) {
+            entries.push(write_entry(&mut entry, name, dir, budget)?);
+        }
+    }
+    Ok(entries)
+}
+
+/// Returns the normalized relative path of an entry, if it is an image that stays inside
+/// the archive.
+fn image_name(path: &Path) -> Option<PathBuf> {
+    let mut name = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(part) => name.push(part),
+            Component::CurDir => (),
+            _ => return None,
+        }
+    }
+    ImageFormat::from_path(&name).ok().map(|_| name)
+}
+
+/// Copies the contents of an entry to `dir/name`, within the byte budget.
+///
+/// The size is counted while copying, since the sizes recorded in an archive can lie.
+fn write_entry(
+    contents: &mut impl Read,
+    name: PathBuf,
+    dir: &Path,
+    budget: &mut Budget,
+) -> Result<ArchiveEntry, Box<dyn Error>> {
+    let path = dir.join(&name);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    // A new file is required, also catching names that only differ by case on
+    // case-insensitive file systems
+    let mut file = match OpenOptions::new().write(true).create_new(true).open(&path) {
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+            return Err(format!("The archive has several images named {:?}", name).into())
+        }
+        file => file?,
+    };
+    let copied = io::copy(&mut contents.take(budget.bytes + 1), &mut file)?;
+    budget.bytes = budget.bytes.checked_sub(copied).ok_or_else(|| {
+        format!(
+            "The images of the archive exceed {} bytes (see --max-archive-size)",
+            budget.max_bytes
+        )
+    })?;
+    Ok(ArchiveEntry { name, path })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn detects_archive_formats() {
+        assert_eq!(
+            ArchiveFormat::from_path(Path::new("photos.ZIP")),
+            Some(ArchiveFormat::Zip)
+        );
+        assert_eq!(
+            ArchiveFormat::from_path(Path::new("a/photos.tar.gz")),
+            Some(ArchiveFormat::TarGz)
+        );
+        assert_eq!(ArchiveFormat::from_path(Path::new("photo.jpg")), None);
+    }
+
+    #[test]
+    fn extracts_images_from_zip() {
+        let dir = TempDir::new().expect("Failed to create a temp dir");
+        let path = dir.path().join("photos.zip");
+        let mut zip = zip::ZipWriter::new(File::create(&path).unwrap());
+        for name in ["a.png", "nested/b.jpg", "notes.txt", "../evil.png"] {
+            zip.start_file(name, zip::write::SimpleFileOptions::default())
+                .unwrap();
+            zip.write_all(name.as_bytes()).unwrap();
+        }
+        zip.finish().unwrap();
+
+        let extracted = extract_images(&path, &Limits::default()).unwrap();
+        let names: Vec<_> = extracted.entries.iter().map(|e| e.name.clone()).collect();
+        assert_eq!(
+            names,
+            vec![PathBuf::from("a.png"), PathBuf::from("nested/b.jpg")]
+        );
+        assert_eq!(
+            fs::read(&extracted.entries[1].path).unwrap(),
+            b"nested/b.jpg"
+        );
+    }
+
+    #[test]
+    fn extracts_images_from_tar() {
+        let dir = TempDir::new().expect("Failed to create a temp dir");
+        let path = dir.path().join("photos.tar");
+        let mut builder = tar::Builder::new(File::create(&path).unwrap());
+        for name in ["a.png", "nested/b.jpg", "notes.txt"] {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(name.len() as u64);
+            header.set_cksum();
+            builder
+                .append_data(&mut header, name, name.as_bytes())
+                .unwrap();
+        }
+        builder.finish().unwrap();
+        drop(builder);
+
+        let extracted = extract_images(&path, &Limits::default()).unwrap();
+        assert_eq!(extracted.entries.len(), 2);
+        assert_eq!(extracted.entries[1].name, PathBuf::from("nested/b.jpg"));
+        assert!(extracted.entries[1].path.is_file());
+    }
+
+    fn write_zip(path: &Path, files: &[(&str, &[u8])]) {
+        let mut zip = zip::ZipWriter::new(File::create(path).unwrap());
+        for (name, contents) in files {
+            zip.start_file(*name, zip::write::SimpleFileOptions::default())
+                .unwrap();
+            zip.write_all(contents).unwrap();
+        }
+        zip.finish().unwrap();
+    }
+
+    #[test]
+    fn enforces_limits_and_unique_names() {
+        let dir = TempDir::new().expect("Failed to create a temp dir");
+        let path = dir.path().join("photos.zip");
+        // Compresses to a few hundred bytes
+        let zeros = vec![0u8; 1 << 20];
+        write_zip(
+            &path,
+            &[("a.png", &zeros), ("b.png", &zeros), ("c.txt", b"c")],
+        );
+
+        let limits = |size, entries| Limits {
+            max_archive_size: size,
+            max_archive_entries: entries,
+            ..Default::default()
+        };
+        assert!(extract_images(&path, &limits(Some(2 << 20), Some(3))).is_ok());
+        let error = extract_images(&path, &limits(Some((2 << 20) - 1), None)).unwrap_err();
+        assert!(
+            error.to_string().contains("exceed 2097151 bytes"),
+            "{}",
+            error
+        );
+        let error = extract_images(&path, &limits(None, Some(2))).unwrap_err();
+        assert!(
+            error.to_string().contains("more than 2 entries"),
+            "{}",
+            error
+        );
+
+        write_zip(&path, &[("a.png", b"first"), ("./a.png", b"second")]);
+        let error = extract_images(&path, &Limits::default()).unwrap_err();
+        assert!(
+            error.to_string().contains("several images named"),
+            "{}",
+            error
+        );
+    }
+}
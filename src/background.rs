@@ -0,0 +1,317 @@
+//! Background removal.
+//!
+//! Removing the background keeps the subject of an image on a transparent background, a
+//! common step when preparing product images. The `Segmenter` trait finds the subject as a
+//! grayscale mask, white being the subject, and `RemoveBackgroundStage` multiplies the alpha
+//! channel of the image by it.
+//!
+//! Two segmenters are provided:
+//! - `FloodSegmenter`: removes the uniform background around the subject by flood filling
+//!   it from the edges of the image, always available. It works well on studio shots and
+//!   scans with a plain background, but not on photos with a busy one.
+//! - `OnnxSegmenter`: a salient object segmentation model in the ONNX format, such as
+//!   U²-Net or IS-Net, run with the pure Rust `tract` runtime. It needs the `onnx` feature
+//!   and a model file, which is not shipped with this crate.
+
+use crate::{
+    options::JobOptions,
+    pipeline::{PipelineState, Stage},
+};
+use image::{imageops, DynamicImage, GrayImage, Luma, RgbaImage};
+use std::{collections::VecDeque, error::Error, path::Path};
+use tracing::debug;
+
+/// The name of the background removal stage.
+pub const REMOVE_BACKGROUND: &str = "remove-background";
+
+/// Finds the subject of images.
+pub trait Segmenter {
+    /// Returns the name of the segmenter, for logging.
+    fn name(&self) -> &str;
+
+    /// Returns the mask of the subject of `image`, with the same dimensions, from 0 for the
+    /// background to 255 for the subject.
+    ///
+    /// # Errors
+    ///
+    /// Returning an error fails the job.
+    fn mask(&self, image: &RgbaImage) -> Result<GrayImage, Box<dyn Error>>;
+}
+
+/// Creates a segmenter.
+///
+/// # Arguments
+///
+/// * `model` - The ONNX segmentation model to use, if any.
+/// * `fuzz` - The color tolerance of the flood fill used without a model, in percent.
+///
+/// # Errors
+///
+/// This function will return an error if the model cannot be loaded, or if a model is
+/// given and the crate was built without the `onnx` feature.
+pub fn segmenter(model: Option<&Path>, fuzz: f32) -> Result<Box<dyn Segmenter>, Box<dyn Error>> {
+    match model {
+        Some(model) => onnx_segmenter(model),
+        None => Ok(Box::new(FloodSegmenter::new(fuzz))),
+    }
+}
+
+#[cfg(feature = "onnx")]
+fn onnx_segmenter(model: &Path) -> Result<Box<dyn Segmenter>, Box<dyn Error>> {
+    Ok(Box::new(onnx::OnnxSegmenter::load(model)?))
+}
+
+#[cfg(not(feature = "onnx"))]
+fn onnx_segmenter(_model: &Path) -> Result<Box<dyn Segmenter>, Box<dyn Error>> {
+    Err("Background models are not available: this build does not have the 'onnx' feature".into())
+}
+
+/// Segments images by flood filling their background from the edges.
+///
+/// The background color is the color of the top left corner. Every edge pixel within the
+/// tolerance of that color is a starting point, and the fill spreads to the neighboring
+/// pixels within the tolerance, so that areas of the background color enclosed by the
+/// subject are kept.
+pub struct FloodSegmenter {
+    fuzz: f32,
+}
+
+impl FloodSegmenter {
+    /// Creates a segmenter with a color tolerance of `fuzz` percent of 255 per channel.
+    pub fn new(fuzz: f32) -> Self {
+        FloodSegmenter {
+            fuzz: fuzz.clamp(0.0, 100.0),
+        }
+    }
+}
+
+impl Segmenter for FloodSegmenter {
+    fn name(&self) -> &str {
+        "flood"
+    }
+
+    fn mask(&self, image: &RgbaImage) -> Result<GrayImage, Box<dyn Error>> {
+        let (width, height) = image.dimensions();
+        let mut mask = GrayImage::from_pixel(width, height, Luma([255]));
+        if width == 0 || height == 0 {
+            return Ok(mask);
+        }
+        let background = *image.get_pixel(0, 0);
+        let tolerance = (self.fuzz / 100.0 * 255.0).round() as i32;
+        let is_background = |x: u32, y: u32| {
+            image
+                .get_pixel(x, y)
+                .0
+                .iter()
+                .zip(background.0)
+                .all(|(&a, b)| (a as i32 - b as i32).abs() <= tolerance)
+        };
+
+        let mut queue: VecDeque<(u32, u32)> = (0..width)
+            .flat_map(|x| [(x, 0), (x, height - 1)])
+            .chain((0..height).flat_map(|y| [(0, y), (width - 1, y)]))
+            .collect();
+        while let Some((x, y)) = queue.pop_front() {
+            if mask.get_pixel(x, y)[0] == 0 || !is_background(x, y) {
+                continue;
+            }
+            mask.put_pixel(x, y, Luma([0]));
+            if x > 0 {
+                queue.push_back((x - 1, y));
+            }
+            if x + 1 < width {
+                queue.push_back((x + 1, y));
+            }
+            if y > 0 {
+                queue.push_back((x, y - 1));
+            }
+            if y + 1 < height {
+                queue.push_back((x, y + 1));
+            }
+        }
+        Ok(mask)
+    }
+}
+
+#[cfg(feature = "onnx")]
+mod onnx {
+    use super::Segmenter;
+    use image::{imageops, GrayImage, RgbaImage};
+    use std::{error::Error, path::Path};
+    use tract_onnx::prelude::*;
+
+    /// The input size used when the model does not fix it, the one of U²-Net.
+    const DEFAULT_INPUT_SIZE: usize = 320;
+    /// The ImageNet normalization expected by most segmentation models.
+    const MEAN: [f32; 3] = [0.485, 0.456, 0.406];
+    const STD: [f32; 3] = [0.229, 0.224, 0.225];
+
+    /// Segments images with a salient object segmentation ONNX model taking a normalized
+    /// NCHW RGB image and returning a single channel map, such as U²-Net or IS-Net.
+    pub struct OnnxSegmenter {
+        plan: TypedSimplePlan<TypedModel>,
+        width: usize,
+        height: usize,
+    }
+
+    impl OnnxSegmenter {
+        /// Loads an ONNX model.
+        ///
+        /// # Errors
+        ///
+        /// This function will return an error if the model cannot be read or parsed.
+        pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+            let load = || -> TractResult<Self> {
+                let mut model = tract_onnx::onnx().model_for_path(path)?;
+                let (height, width) = match model.input_fact(0)?.shape.as_concrete_finite()? {
+                    Some(shape) if shape.len() == 4 => (shape[2], shape[3]),
+                    _ => (DEFAULT_INPUT_SIZE, DEFAULT_INPUT_SIZE),
+                };
+                model.set_input_fact(0, f32::fact([1, 3, height, width]).into())?;
+                let plan = model.into_optimized()?.into_runnable()?;
+                Ok(OnnxSegmenter {
+                    plan,
+                    width,
+                    height,
+                })
+            };
+            load().map_err(|e| format!("Failed to load the ONNX model {:?}: {}", path, e).into())
+        }
+    }
+
+    impl Segmenter for OnnxSegmenter {
+        fn name(&self) -> &str {
+            "onnx"
+        }
+
+        fn mask(&self, image: &RgbaImage) -> Result<GrayImage, Box<dyn Error>> {
+            let (width, height) = (self.width, self.height);
+            let scaled = imageops::resize(
+                image,
+                width as u32,
+                height as u32,
+                imageops::FilterType::Triangle,
+            );
+            let input: Tensor =
+                tract_ndarray::Array4::from_shape_fn((1, 3, height, width), |(_, c, y, x)| {
+                    let value = scaled.get_pixel(x as u32, y as u32)[c] as f32 / 255.0;
+                    (value - MEAN[c]) / STD[c]
+                })
+                .into();
+            let outputs = self.plan.run(tvec!(input.into()))?;
+            let output = outputs[0].to_array_view::<f32>()?;
+            let map: Vec<f32> = output.iter().copied().collect();
+            if map.len() != width * height {
+                return Err(format!("Unexpected model output shape {:?}", output.shape()).into());
+            }
+
+            // The maps of salient object models are not calibrated, stretch them to 0..=255
+            let min = map.iter().copied().fold(f32::INFINITY, f32::min);
+            let max = map.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+            let range = (max - min).max(f32::EPSILON);
+            let raw = map
+                .iter()
+                .map(|value| ((value - min) / range * 255.0).round() as u8)
+                .collect();
+            let mask = GrayImage::from_raw(width as u32, height as u32, raw)
+                .ok_or("The model output does not match its input size")?;
+            Ok(imageops::resize(
+                &mask,
+                image.width(),
+                image.height(),
+                imageops::FilterType::Triangle,
+            ))
+        }
+    }
+}
+
+/// Makes the background of an image transparent, keeping any existing transparency.
+///
+/// # Arguments
+///
+/// * `image` - The image to cut out.
+/// * `mask` - The mask of the subject, stretched to the size of the image if needed.
+pub fn remove_background(image: &mut RgbaImage, mask: &GrayImage) {
+    let (width, height) = image.dimensions();
+    let resized;
+    let mask = if mask.dimensions() == (width, height) {
+        mask
+    } else {
+        resized = imageops::resize(mask, width, height, imageops::FilterType::Triangle);
+        &resized
+    };
+    for (pixel, subject) in image.pixels_mut().zip(mask.pixels()) {
+        pixel[3] = ((pixel[3] as u16 * subject[0] as u16 + 127) / 255) as u8;
+    }
+}
+
+/// A stage making the background of the current image transparent.
+pub struct RemoveBackgroundStage {
+    segmenter: Box<dyn Segmenter>,
+}
+
+impl RemoveBackgroundStage {
+    /// Creates a stage finding the subject of images with `segmenter`.
+    pub fn new(segmenter: Box<dyn Segmenter>) -> Self {
+        RemoveBackgroundStage { segmenter }
+    }
+}
+
+impl Stage for RemoveBackgroundStage {
+    fn name(&self) -> &str {
+        REMOVE_BACKGROUND
+    }
+
+    fn run(&self, state: &mut PipelineState, _options: &JobOptions) -> Result<(), Box<dyn Error>> {
+        let mut image = state.take_image()?.into_rgba8();
+        debug!(
+            "Removing the background of {}x{} with the {} segmenter",
+            image.width(),
+            image.height(),
+            self.segmenter.name()
+        );
+        let mask = self.segmenter.mask(&image)?;
+        remove_background(&mut image, &mask);
+        state.image = Some(DynamicImage::ImageRgba8(image));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    /// A white image with a red ring, whose white center is enclosed by the subject.
+    fn ring() -> RgbaImage {
+        RgbaImage::from_fn(9, 9, |x, y| {
+            let inside = (2..7).contains(&x) && (2..7).contains(&y);
+            let center = (3..6).contains(&x) && (3..6).contains(&y);
+            if inside && !center {
+                Rgba([200, 0, 0, 255])
+            } else {
+                Rgba([250, 250, 250, 255])
+            }
+        })
+    }
+
+    #[test]
+    fn floods_the_background_from_the_edges() {
+        let mask = segmenter(None, 5.0).unwrap().mask(&ring()).unwrap();
+        assert_eq!(mask.get_pixel(0, 0)[0], 0);
+        assert_eq!(mask.get_pixel(8, 4)[0], 0);
+        assert_eq!(mask.get_pixel(2, 2)[0], 255);
+        // The center has the background color, but is not connected to the edges
+        assert_eq!(mask.get_pixel(4, 4)[0], 255);
+    }
+
+    #[test]
+    fn keeps_existing_transparency() {
+        let mut image = RgbaImage::from_pixel(2, 1, Rgba([10, 20, 30, 128]));
+        image.put_pixel(1, 0, Rgba([10, 20, 30, 255]));
+        let mask = GrayImage::from_raw(2, 1, vec![255, 0]).unwrap();
+        remove_background(&mut image, &mask);
+        assert_eq!(image.get_pixel(0, 0), &Rgba([10, 20, 30, 128]));
+        assert_eq!(image.get_pixel(1, 0)[3], 0);
+    }
+}
@@ -0,0 +1,322 @@
+//! Batch processing support.
+//!
+//! This module provides a small runner for applying the same operation to several
+//! input files, together with the types used to aggregate per-file errors and to
+//! summarize the overall outcome of a batch.
+
+use crate::{cancel::CancellationToken, error::ResizeError};
+use std::{
+    error::Error,
+    fmt,
+    path::{Path, PathBuf},
+};
+
+/// Controls how the batch runner reacts when processing an input fails.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ErrorPolicy {
+    /// Stop at the first failure; remaining inputs are skipped.
+    FailFast,
+    /// Record the failure and continue with the next input.
+    #[default]
+    KeepGoing,
+}
+
+/// The overall result of a batch run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BatchOutcome {
+    /// Every input was processed successfully.
+    Success,
+    /// Some inputs were processed, but at least one failed or was skipped.
+    PartialFailure,
+    /// No input was processed successfully.
+    TotalFailure,
+    /// The run was stopped by a `CancellationToken` before every input was processed.
+    Cancelled,
+}
+
+/// A single failed input together with the error that caused the failure.
+#[derive(Debug)]
+pub struct BatchFailure {
+    /// The input that could not be processed.
+    pub input: PathBuf,
+    /// The error returned while processing the input.
+    pub error: Box<dyn Error>,
+}
+
+/// Aggregates the errors produced during a batch run.
+///
+/// `BatchError` implements `std::error::Error`, so a batch with failures can be
+/// propagated with `?` like any other error.
+#[derive(Debug, Default)]
+pub struct BatchError {
+    /// The failed inputs, in processing order.
+    pub failures: Vec<BatchFailure>,
+}
+
+impl BatchError {
+    /// Returns `true` if no failures were recorded.
+    pub fn is_empty(&self) -> bool {
+        self.failures.is_empty()
+    }
+
+    /// Returns the number of recorded failures.
+    pub fn len(&self) -> usize {
+        self.failures.len()
+    }
+}
+
+impl fmt::Display for BatchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} input(s) failed:", self.failures.len())?;
+        for failure in &self.failures {
+            write!(f, "\n  {:?}: {}", failure.input, failure.error)?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for BatchError {}
+
+/// Summarizes a batch run.
+#[derive(Debug)]
+pub struct BatchReport<T> {
+    /// The number of inputs passed to the runner.
+    pub total: usize,
+    /// The results of the successfully processed inputs, in processing order.
+    pub succeeded: Vec<T>,
+    /// The aggregated failures.
+    pub errors: BatchError,
+    /// Whether the run was stopped by a `CancellationToken`.
+    pub cancelled: bool,
+}
+
+impl<T> BatchReport<T> {
+    /// Returns the number of inputs that were never attempted because the run stopped early.
+    pub fn skipped(&self) -> usize {
+        self.total - self.succeeded.len() - self.errors.len()
+    }
+
+    /// Classifies the run as a full success, a partial failure or a total failure, or as
+    /// cancelled if it was stopped early by a `CancellationToken`.
+    pub fn outcome(&self) -> BatchOutcome {
+        if self.cancelled {
+            BatchOutcome::Cancelled
+        } else if self.succeeded.len() == self.total {
+            BatchOutcome::Success
+        } else if self.succeeded.is_empty() {
+            BatchOutcome::TotalFailure
+        } else {
+            BatchOutcome::PartialFailure
+        }
+    }
+}
+
+/// Applies `process` to every input, following the given error policy.
+///
+/// The cancellation token is checked before every input. If it is cancelled, or if
+/// `process` returns `ResizeError::Cancelled`, the run stops and the remaining inputs,
+/// including the interrupted one, are counted as skipped rather than failed.
+///
+/// # Arguments
+///
+/// * `inputs` - The input paths to process, in order.
+/// * `policy` - Whether to stop at the first failure or keep going.
+/// * `cancel` - A token that stops the run when cancelled.
+/// * `process` - The operation to run on each input.
+///
+/// # Returns
+///
+/// A `BatchReport` with the results of the successful inputs and the aggregated failures.
+pub fn run_batch<T, F>(
+    inputs: &[PathBuf],
+    policy: ErrorPolicy,
+    cancel: &CancellationToken,
+    process: F,
+) -> BatchReport<T>
+where
+    F: FnMut(&Path) -> Result<T, Box<dyn Error>>,
+{
+    let report = run_batch_stream(inputs.iter().cloned().map(Ok), policy, cancel, process);
+    BatchReport {
+        total: inputs.len(),
+        ..report
+    }
+}
+
+/// Applies `process` to every input like `run_batch`, taking the inputs from an iterator as
+/// they are needed, e.g. while they are read from stdin.
+///
+/// The total of the report only counts the inputs taken from the iterator, so inputs never
+/// reached are not counted as skipped.
+///
+/// # Arguments
+///
+/// * `inputs` - The input paths to process, in order. An `Err` item, such as a failure to
+///   read a list of inputs, is recorded as a failed input and stops the run.
+/// * `policy` - Whether to stop at the first failure or keep going.
+/// * `cancel` - A token that stops the run when cancelled.
+/// * `process` - The operation to run on each input.
+///
+/// # Returns
+///
+/// A `BatchReport` with the results of the successful inputs and the aggregated failures.
+pub fn run_batch_stream<T, I, F>(
+    inputs: I,
+    policy: ErrorPolicy,
+    cancel: &CancellationToken,
+    mut process: F,
+) -> BatchReport<T>
+where
+    I: IntoIterator<Item = Result<PathBuf, BatchFailure>>,
+    F: FnMut(&Path) -> Result<T, Box<dyn Error>>,
+{
+    let mut report = BatchReport {
+        total: 0,
+        succeeded: Vec::new(),
+        errors: BatchError::default(),
+        cancelled: false,
+    };
+
+    for input in inputs {
+        if cancel.is_cancelled() {
+            report.cancelled = true;
+            break;
+        }
+        report.total += 1;
+        let input = match input {
+            Ok(input) => input,
+            Err(failure) => {
+                report.errors.failures.push(failure);
+                break;
+            }
+        };
+        match process(&input) {
+            Ok(result) => report.succeeded.push(result),
+            Err(error) if ResizeError::is_cancelled(error.as_ref()) => {
+                report.cancelled = true;
+                break;
+            }
+            Err(error) => {
+                report.errors.failures.push(BatchFailure { input, error });
+                if policy == ErrorPolicy::FailFast {
+                    break;
+                }
+            }
+        }
+    }
+
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn inputs() -> Vec<PathBuf> {
+        vec![
+            PathBuf::from("a.jpg"),
+            PathBuf::from("bad.jpg"),
+            PathBuf::from("c.jpg"),
+        ]
+    }
+
+    fn process(path: &Path) -> Result<String, Box<dyn Error>> {
+        if path.to_string_lossy().starts_with("bad") {
+            return Err("broken image".into());
+        }
+        Ok(path.to_string_lossy().to_string())
+    }
+
+    #[test]
+    fn keep_going_processes_all_inputs() {
+        let report = run_batch(
+            &inputs(),
+            ErrorPolicy::KeepGoing,
+            &CancellationToken::new(),
+            process,
+        );
+        assert_eq!(report.succeeded, vec!["a.jpg", "c.jpg"]);
+        assert_eq!(report.errors.len(), 1);
+        assert_eq!(report.skipped(), 0);
+        assert_eq!(report.outcome(), BatchOutcome::PartialFailure);
+    }
+
+    #[test]
+    fn fail_fast_stops_at_first_failure() {
+        let report = run_batch(
+            &inputs(),
+            ErrorPolicy::FailFast,
+            &CancellationToken::new(),
+            process,
+        );
+        assert_eq!(report.succeeded, vec!["a.jpg"]);
+        assert_eq!(report.errors.len(), 1);
+        assert_eq!(report.skipped(), 1);
+        assert_eq!(report.outcome(), BatchOutcome::PartialFailure);
+    }
+
+    #[test]
+    fn outcome_success_and_total_failure() {
+        let ok = run_batch(
+            &[PathBuf::from("a.jpg")],
+            ErrorPolicy::KeepGoing,
+            &CancellationToken::new(),
+            process,
+        );
+        assert_eq!(ok.outcome(), BatchOutcome::Success);
+
+        let failed = run_batch(
+            &[PathBuf::from("bad.jpg")],
+            ErrorPolicy::KeepGoing,
+            &CancellationToken::new(),
+            process,
+        );
+        assert_eq!(failed.outcome(), BatchOutcome::TotalFailure);
+        assert!(failed.errors.to_string().contains("broken image"));
+    }
+
+    #[test]
+    fn cancellation_skips_remaining_inputs() {
+        let cancel = CancellationToken::new();
+        let report = run_batch(&inputs(), ErrorPolicy::KeepGoing, &cancel, |path| {
+            if path.to_string_lossy().starts_with("bad") {
+                cancel.cancel();
+                return Err(ResizeError::Cancelled.into());
+            }
+            process(path)
+        });
+        assert!(report.cancelled);
+        assert_eq!(report.outcome(), BatchOutcome::Cancelled);
+        assert_eq!(report.succeeded, vec!["a.jpg"]);
+        assert!(report.errors.is_empty());
+        assert_eq!(report.skipped(), 2);
+
+        let report = run_batch(&inputs(), ErrorPolicy::KeepGoing, &cancel, process);
+        assert!(report.cancelled);
+        assert_eq!(report.skipped(), 3);
+    }
+
+    #[test]
+    fn streams_inputs_until_a_read_error() {
+        let cancel = CancellationToken::new();
+        let inputs = inputs().into_iter().map(Ok);
+        let report = run_batch_stream(inputs, ErrorPolicy::FailFast, &cancel, process);
+        // The inputs after the failure are never read
+        assert_eq!(report.total, 2);
+        assert_eq!(report.skipped(), 0);
+
+        let inputs = vec![
+            Ok(PathBuf::from("a.jpg")),
+            Err(BatchFailure {
+                input: PathBuf::from("-"),
+                error: "stream did not contain valid UTF-8".into(),
+            }),
+            Ok(PathBuf::from("c.jpg")),
+        ];
+        let report = run_batch_stream(inputs, ErrorPolicy::KeepGoing, &cancel, process);
+        assert_eq!(report.succeeded, vec!["a.jpg"]);
+        assert_eq!(report.errors.failures[0].input, PathBuf::from("-"));
+        assert_eq!(report.total, 2);
+        assert_eq!(report.outcome(), BatchOutcome::PartialFailure);
+    }
+}
@@ -0,0 +1,159 @@
+//! BlurHash placeholders.
+//!
+//! A BlurHash is a short string, typically 20 to 30 characters, encoding a very blurred
+//! version of an image as a few cosine components. Web and mobile clients decode it into a
+//! placeholder shown while the image loads. This module implements the encoder of the
+//! reference implementation (<https://blurha.sh>), so that any BlurHash decoder can read its
+//! output.
+
+use image::RgbaImage;
+use std::{error::Error, f32::consts::PI};
+
+/// The alphabet of the base 83 encoding.
+const CHARACTERS: &[u8; 83] =
+    b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz#$%*+,-.:;=?@[]^_{|}~";
+
+/// The largest side of the thumbnail the components are computed from. A BlurHash only
+/// keeps the lowest frequencies, so larger images give the same result, only slower.
+pub const THUMBNAIL_SIZE: u32 = 64;
+
+/// Computes the BlurHash of an image.
+///
+/// # Arguments
+///
+/// * `image` - The image. The alpha channel is ignored. Large images should be scaled down
+///   first, e.g. to `THUMBNAIL_SIZE`.
+/// * `x_components` - The number of horizontal components, from 1 to 9.
+/// * `y_components` - The number of vertical components, from 1 to 9.
+///
+/// # Returns
+///
+/// The BlurHash string, of `4 + 2 * x_components * y_components` characters.
+///
+/// # Errors
+///
+/// This function will return an error if the image is empty or a number of components is
+/// out of range.
+pub fn encode(
+    image: &RgbaImage,
+    x_components: u32,
+    y_components: u32,
+) -> Result<String, Box<dyn Error>> {
+    if !(1..=9).contains(&x_components) || !(1..=9).contains(&y_components) {
+        return Err("The number of BlurHash components must be between 1 and 9".into());
+    }
+    let (width, height) = image.dimensions();
+    if width == 0 || height == 0 {
+        return Err("Cannot compute the BlurHash of an empty image".into());
+    }
+
+    let linear: Vec<[f32; 3]> = image
+        .pixels()
+        .map(|pixel| [0, 1, 2].map(|channel| srgb_to_linear(pixel[channel])))
+        .collect();
+    let mut factors = Vec::with_capacity((x_components * y_components) as usize);
+    for j in 0..y_components {
+        for i in 0..x_components {
+            let normalisation = if i == 0 && j == 0 { 1.0 } else { 2.0 };
+            let mut factor = [0.0f32; 3];
+            for y in 0..height {
+                let basis_y = (PI * j as f32 * y as f32 / height as f32).cos();
+                for x in 0..width {
+                    let basis = basis_y * (PI * i as f32 * x as f32 / width as f32).cos();
+                    let color = linear[(y * width + x) as usize];
+                    for (value, channel) in factor.iter_mut().zip(color) {
+                        *value += basis * channel;
+                    }
+                }
+            }
+            let scale = normalisation / (width * height) as f32;
+            factors.push(factor.map(|value| value * scale));
+        }
+    }
+
+    let (dc, ac) = factors.split_first().unwrap();
+    let mut hash = String::with_capacity(4 + 2 * ac.len());
+    push_base83(&mut hash, (x_components - 1) + (y_components - 1) * 9, 1);
+    let maximum = if ac.is_empty() {
+        push_base83(&mut hash, 0, 1);
+        1.0
+    } else {
+        let actual = ac
+            .iter()
+            .flatten()
+            .fold(0.0f32, |max, value| max.max(value.abs()));
+        let quantised = (actual * 166.0 - 0.5).floor().clamp(0.0, 82.0) as u32;
+        push_base83(&mut hash, quantised, 1);
+        (quantised + 1) as f32 / 166.0
+    };
+    let [r, g, b] = dc.map(linear_to_srgb);
+    push_base83(&mut hash, (r << 16) + (g << 8) + b, 4);
+    for factor in ac {
+        let [r, g, b] = factor.map(|value| {
+            let scaled = (value / maximum).abs().sqrt().copysign(value);
+            (scaled * 9.0 + 9.5).floor().clamp(0.0, 18.0) as u32
+        });
+        push_base83(&mut hash, r * 19 * 19 + g * 19 + b, 2);
+    }
+    Ok(hash)
+}
+
+/// Appends `value` in base 83, with `length` digits.
+fn push_base83(hash: &mut String, value: u32, length: u32) {
+    for position in (0..length).rev() {
+        let digit = value / 83u32.pow(position) % 83;
+        hash.push(CHARACTERS[digit as usize] as char);
+    }
+}
+
+fn srgb_to_linear(value: u8) -> f32 {
+    let value = value as f32 / 255.0;
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn linear_to_srgb(value: f32) -> u32 {
+    let value = value.clamp(0.0, 1.0);
+    if value <= 0.0031308 {
+        (value * 12.92 * 255.0 + 0.5) as u32
+    } else {
+        ((1.055 * value.powf(1.0 / 2.4) - 0.055) * 255.0 + 0.5) as u32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    #[test]
+    fn encodes_plain_images() {
+        let red = RgbaImage::from_pixel(8, 6, Rgba([255, 0, 0, 255]));
+        let hash = encode(&red, 4, 3).unwrap();
+        // 4x3 components, the maximum AC value, pure red, then 11 AC components
+        assert_eq!(hash.len(), 28);
+        assert_eq!(&hash[..1], "L");
+        assert_eq!(&hash[2..6], "TI:j");
+
+        assert_eq!(encode(&red, 1, 1).unwrap(), "00TI:j");
+        assert!(encode(&red, 0, 3).is_err());
+        assert!(encode(&RgbaImage::new(0, 0), 1, 1).is_err());
+    }
+
+    #[test]
+    fn encodes_gradients() {
+        let gradient = RgbaImage::from_fn(32, 8, |x, _| {
+            let value = (x * 8) as u8;
+            Rgba([value, value, value, 255])
+        });
+        let hash = encode(&gradient, 3, 2).unwrap();
+        assert_eq!(hash.len(), 4 + 2 * 6);
+        assert_eq!(&hash[..1], "B");
+        // The first horizontal component carries most of the energy
+        assert_ne!(&hash[1..2], "0");
+        assert_ne!(&hash[6..8], "fQ");
+    }
+}
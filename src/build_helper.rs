@@ -0,0 +1,291 @@
+//! Resizing assets from build scripts.
+//!
+//! `ResizePlan` lists the images a crate needs at build time and produces them in the
+//! `OUT_DIR` of its build script, where they can be embedded with `include_bytes!`:
+//!
+//! ```no_run
+//! // build.rs
+//! use image_resizer_rust::build_helper::ResizePlan;
+//!
+//! fn main() {
+//!     ResizePlan::from_env()
+//!         .unwrap()
+//!         .resize("assets/logo.png", "logo_64.png", Some(64), None)
+//!         .resize("assets/banner.jpg", "banner.jpg", Some(1200), None)
+//!         .run()
+//!         .unwrap();
+//! }
+//! ```
+//!
+//! ```ignore
+//! // src/main.rs
+//! const LOGO: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/logo_64.png"));
+//! ```
+//!
+//! Running the plan prints a `cargo:rerun-if-changed` line for every input, so that Cargo
+//! only runs the build script again when an input or the build script itself changes. Even
+//! then, outputs whose input contents and settings did not change are not produced again:
+//! a fingerprint of each output is kept in a stamp file next to the outputs.
+
+use crate::{options::JobOptions, overwrite::OverwritePolicy, resize_file};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::BTreeMap,
+    env,
+    error::Error,
+    fmt::Write as _,
+    fs,
+    io::{self, Read, Write},
+    path::PathBuf,
+};
+
+/// The name of the stamp file holding the fingerprints of the outputs.
+pub const STAMP_FILE: &str = ".image-resizer-plan";
+
+/// One image of a plan.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlannedResize {
+    /// The path of the input, usually relative to the crate root.
+    pub input: PathBuf,
+    /// The path of the output, relative to the output directory. Its extension selects the
+    /// output format.
+    pub output: PathBuf,
+    /// The new width, or None to compute it from the height.
+    pub width: Option<u32>,
+    /// The new height, or None to compute it from the width.
+    pub height: Option<u32>,
+}
+
+/// A list of images to resize into an output directory.
+#[derive(Debug, Clone)]
+pub struct ResizePlan {
+    out_dir: PathBuf,
+    options: JobOptions,
+    resizes: Vec<PlannedResize>,
+}
+
+impl ResizePlan {
+    /// Creates an empty plan writing to `out_dir`.
+    pub fn new(out_dir: impl Into<PathBuf>) -> Self {
+        ResizePlan {
+            out_dir: out_dir.into(),
+            options: JobOptions::default(),
+            resizes: Vec::new(),
+        }
+    }
+
+    /// Creates an empty plan writing to the `OUT_DIR` of the running build script.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if `OUT_DIR` is not set, i.e. when not called
+    /// from a build script.
+    pub fn from_env() -> Result<Self, Box<dyn Error>> {
+        let out_dir =
+            env::var_os("OUT_DIR").ok_or("OUT_DIR is not set: not running in a build script")?;
+        Ok(ResizePlan::new(out_dir))
+    }
+
+    /// Sets the job options used for every image, such as the encoder settings. The
+    /// overwrite policy is ignored: outputs are always replaced.
+    pub fn options(mut self, options: JobOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Adds an image to the plan.
+    ///
+    /// # Arguments
+    ///
+    /// * `input` - The path of the input image.
+    /// * `output` - The path of the output, relative to the output directory.
+    /// * `width` - The new width, or None to compute it from the height.
+    /// * `height` - The new height, or None to compute it from the width.
+    pub fn resize(
+        mut self,
+        input: impl Into<PathBuf>,
+        output: impl Into<PathBuf>,
+        width: Option<u32>,
+        height: Option<u32>,
+    ) -> Self {
+        self.resizes.push(PlannedResize {
+            input: input.into(),
+            output: output.into(),
+            width,
+            height,
+        });
+        self
+    }
+
+    /// Returns the images of the plan.
+    pub fn resizes(&self) -> &[PlannedResize] {
+        &self.resizes
+    }
+
+    /// Produces the outputs of the plan, printing the `cargo:` directives to stdout.
+    ///
+    /// # Returns
+    ///
+    /// The paths of the outputs, in the order of the plan.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if an input cannot be read or resized, or if an
+    /// output or the stamp file cannot be written. Build scripts should fail in that case.
+    pub fn run(&self) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+        self.run_with(&mut io::stdout().lock())
+    }
+
+    /// Produces the outputs of the plan like `run`, printing the `cargo:` directives to
+    /// `directives`.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error in the same cases as `run`, or if the directives
+    /// cannot be written.
+    pub fn run_with(&self, directives: &mut dyn Write) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+        let options = JobOptions {
+            overwrite: OverwritePolicy::Always,
+            ..self.options.clone()
+        };
+        let storage = &*options.storage;
+        let stamp_path = self.out_dir.join(STAMP_FILE);
+        let old_stamps = match storage.open_read(&stamp_path) {
+            Ok(mut file) => {
+                let mut text = String::new();
+                file.read_to_string(&mut text)?;
+                parse_stamps(&text)
+            }
+            Err(_) => BTreeMap::new(),
+        };
+
+        let mut stamps = BTreeMap::new();
+        let mut outputs = Vec::with_capacity(self.resizes.len());
+        for planned in &self.resizes {
+            writeln!(
+                directives,
+                "cargo:rerun-if-changed={}",
+                planned.input.display()
+            )?;
+            let mut source = Vec::new();
+            storage
+                .open_read(&planned.input)
+                .and_then(|mut file| file.read_to_end(&mut source))
+                .map_err(|e| format!("Failed to read {:?}: {}", planned.input, e))?;
+            let fingerprint = fingerprint(&source, planned, &options);
+
+            let output = self.out_dir.join(&planned.output);
+            let up_to_date =
+                old_stamps.get(&planned.output) == Some(&fingerprint) && storage.exists(&output)?;
+            if !up_to_date {
+                if let Some(parent) = output.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                let info = resize_file(
+                    &planned.input,
+                    &output,
+                    planned.width.as_ref(),
+                    planned.height.as_ref(),
+                    None,
+                    &options,
+                )
+                .map_err(|e| format!("Failed to resize {:?}: {}", planned.input, e))?;
+                if info.path != output {
+                    return Err(format!(
+                        "The extension of {:?} does not match its format",
+                        planned.output
+                    )
+                    .into());
+                }
+            }
+            stamps.insert(planned.output.clone(), fingerprint);
+            outputs.push(output);
+        }
+
+        let text: String = stamps
+            .iter()
+            .map(|(output, fingerprint)| format!("{} {}\n", fingerprint, output.display()))
+            .collect();
+        storage.write_file(&stamp_path, text.as_bytes(), true)?;
+        Ok(outputs)
+    }
+}
+
+/// Returns the fingerprint of an output: a hash of the contents of its input, of its
+/// settings and of the version of this crate.
+fn fingerprint(source: &[u8], planned: &PlannedResize, options: &JobOptions) -> String {
+    let settings = format!(
+        "{} {:?} {:?} {:?} {:?} {:?}",
+        env!("CARGO_PKG_VERSION"),
+        planned.output,
+        planned.width,
+        planned.height,
+        options.encode,
+        options.resize
+    );
+    let mut hasher = Sha256::new();
+    hasher.update((settings.len() as u64).to_le_bytes());
+    hasher.update(settings);
+    hasher.update(source);
+    let mut fingerprint = String::new();
+    for byte in hasher.finalize() {
+        let _ = write!(fingerprint, "{:02x}", byte);
+    }
+    fingerprint
+}
+
+/// Parses a stamp file, made of `<fingerprint> <output>` lines.
+fn parse_stamps(text: &str) -> BTreeMap<PathBuf, String> {
+    text.lines()
+        .filter_map(|line| line.split_once(' '))
+        .map(|(fingerprint, output)| (PathBuf::from(output), fingerprint.to_string()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{GenericImageView, RgbaImage};
+    use tempfile::TempDir;
+
+    #[test]
+    fn resizes_only_what_changed() {
+        let dir = TempDir::new().unwrap();
+        let input = dir.path().join("logo.png");
+        RgbaImage::new(40, 20).save(&input).unwrap();
+        let out_dir = dir.path().join("out");
+        let plan = ResizePlan::new(&out_dir)
+            .resize(&input, "icons/logo_10.png", Some(10), None)
+            .resize(&input, "logo_20.jpg", None, Some(10));
+
+        let mut directives = Vec::new();
+        let outputs = plan.run_with(&mut directives).unwrap();
+        assert_eq!(outputs[0], out_dir.join("icons/logo_10.png"));
+        assert_eq!(image::open(&outputs[0]).unwrap().dimensions(), (10, 5));
+        assert_eq!(image::open(&outputs[1]).unwrap().dimensions(), (20, 10));
+        let directive = format!("cargo:rerun-if-changed={}\n", input.display());
+        assert_eq!(String::from_utf8(directives).unwrap(), directive.repeat(2));
+
+        // Up-to-date outputs are kept as they are
+        fs::write(&outputs[0], b"kept").unwrap();
+        plan.run_with(&mut io::sink()).unwrap();
+        assert_eq!(fs::read(&outputs[0]).unwrap(), b"kept");
+
+        // Changing the input or the settings produces the outputs again
+        let plan = ResizePlan::new(&out_dir).resize(&input, "icons/logo_10.png", Some(8), None);
+        plan.run_with(&mut io::sink()).unwrap();
+        assert_eq!(image::open(&outputs[0]).unwrap().dimensions(), (8, 4));
+        RgbaImage::new(16, 16).save(&input).unwrap();
+        plan.run_with(&mut io::sink()).unwrap();
+        assert_eq!(image::open(&outputs[0]).unwrap().dimensions(), (8, 8));
+    }
+
+    #[test]
+    fn reports_missing_inputs() {
+        let dir = TempDir::new().unwrap();
+        let plan = ResizePlan::new(dir.path()).resize("missing.png", "a.png", Some(8), None);
+        let error = plan.run_with(&mut io::sink()).unwrap_err();
+        assert!(error.to_string().contains("missing.png"), "{}", error);
+        assert!(!dir.path().join(STAMP_FILE).exists());
+    }
+}
@@ -0,0 +1,267 @@
+//! A cache of encoded outputs.
+//!
+//! Static site generators and build scripts often resize the same images with the same
+//! options on every run. With a cache, each encoded output is stored in a directory under
+//! a key derived from the contents of the source and from everything else that affects the
+//! output: the requested geometry and format, the job options, the pipeline and a caller
+//! supplied salt covering custom stage settings. A later job with the same key reuses the
+//! stored bytes instead of decoding, resizing and encoding the image again.
+//!
+//! Entries are plain files named after their key. Their modification time is refreshed on
+//! every hit, so that `Cache::evict` can remove the least recently used entries first.
+
+use sha2::{Digest, Sha256};
+use std::{
+    error::Error,
+    fmt::Write as _,
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+use tracing::{debug, warn};
+
+/// The length of keys, which are hexadecimal SHA-256 digests.
+const KEY_LENGTH: usize = 64;
+
+/// A directory of cached outputs.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cache {
+    /// The directory holding the entries, created on the first write.
+    pub dir: PathBuf,
+    /// Settings that affect outputs but are not visible in the job options and pipeline
+    /// stage names, such as the parameters of custom stages, mixed into every key.
+    pub salt: String,
+    /// The maximum total size of the entries, in bytes, enforced by `evict`.
+    pub max_size: Option<u64>,
+    /// The maximum time since an entry was last used, enforced by `evict`.
+    pub max_age: Option<Duration>,
+}
+
+/// The entries removed by `Cache::evict`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Eviction {
+    /// The number of entries removed.
+    pub removed: usize,
+    /// The total size of the removed entries, in bytes.
+    pub freed: u64,
+    /// The total size of the remaining entries, in bytes.
+    pub kept: u64,
+}
+
+impl Cache {
+    /// Creates a cache in `dir`, without salt or eviction limits.
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Cache {
+            dir: dir.into(),
+            salt: String::new(),
+            max_size: None,
+            max_age: None,
+        }
+    }
+
+    /// Returns the key of an output.
+    ///
+    /// # Arguments
+    ///
+    /// * `source` - The contents of the source image.
+    /// * `recipe` - A description of how the output is produced from the source.
+    pub fn key(&self, source: &[u8], recipe: &str) -> String {
+        let mut hasher = Sha256::new();
+        for part in [
+            env!("CARGO_PKG_VERSION").as_bytes(),
+            recipe.as_bytes(),
+            self.salt.as_bytes(),
+        ] {
+            hasher.update((part.len() as u64).to_le_bytes());
+            hasher.update(part);
+        }
+        hasher.update(source);
+        let mut key = String::with_capacity(KEY_LENGTH);
+        for byte in hasher.finalize() {
+            let _ = write!(key, "{:02x}", byte);
+        }
+        key
+    }
+
+    /// Returns the path of the entry of a key.
+    fn path(&self, key: &str) -> PathBuf {
+        self.dir.join(key)
+    }
+
+    /// Returns the cached output of a key, if any, and marks it as recently used.
+    ///
+    /// Unreadable entries are reported as misses, since the output can always be produced
+    /// again.
+    pub fn get(&self, key: &str) -> Option<Vec<u8>> {
+        let path = self.path(key);
+        match fs::read(&path) {
+            Ok(contents) => {
+                let touched = fs::File::options()
+                    .write(true)
+                    .open(&path)
+                    .and_then(|file| file.set_modified(SystemTime::now()));
+                if let Err(e) = touched {
+                    debug!("Failed to refresh the cache entry {:?}: {}", path, e);
+                }
+                Some(contents)
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+            Err(e) => {
+                warn!("Ignoring the unreadable cache entry {:?}: {}", path, e);
+                None
+            }
+        }
+    }
+
+    /// Stores the output of a key, replacing any previous entry. Entries are written to a
+    /// temporary file that is renamed once complete, so that concurrent jobs never read a
+    /// partial entry.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the directory or the entry cannot be written.
+    pub fn put(&self, key: &str, contents: &[u8]) -> Result<(), Box<dyn Error>> {
+        fs::create_dir_all(&self.dir)?;
+        let mut file = tempfile::Builder::new()
+            .prefix(".tmp")
+            .tempfile_in(&self.dir)?;
+        file.write_all(contents)?;
+        file.persist(self.path(key))?;
+        Ok(())
+    }
+
+    /// Removes the entries unused for longer than `max_age`, then the least recently used
+    /// entries until the total size is at most `max_size`. Files that are not entries are
+    /// left untouched.
+    ///
+    /// # Returns
+    ///
+    /// What was removed and kept. A missing directory is an empty cache.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the directory cannot be listed or an entry
+    /// cannot be removed.
+    pub fn evict(&self) -> Result<Eviction, Box<dyn Error>> {
+        let mut entries = Vec::new();
+        let listing = match fs::read_dir(&self.dir) {
+            Ok(listing) => listing,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Eviction::default()),
+            Err(e) => return Err(e.into()),
+        };
+        for entry in listing {
+            let entry = entry?;
+            if !is_key(&entry.file_name().to_string_lossy()) {
+                continue;
+            }
+            let metadata = entry.metadata()?;
+            if metadata.is_file() {
+                entries.push((entry.path(), metadata.modified()?, metadata.len()));
+            }
+        }
+        // Most recently used first
+        entries.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+
+        let now = SystemTime::now();
+        let mut eviction = Eviction::default();
+        let mut full = false;
+        for (path, modified, size) in entries {
+            let expired = self
+                .max_age
+                .is_some_and(|max_age| now.duration_since(modified).unwrap_or_default() > max_age);
+            // Once an entry does not fit, older entries are removed even if they would
+            full = full
+                || self
+                    .max_size
+                    .is_some_and(|max_size| eviction.kept + size > max_size);
+            if expired || full {
+                debug!("Evicting the cache entry {:?}", path);
+                remove(&path)?;
+                eviction.removed += 1;
+                eviction.freed += size;
+            } else {
+                eviction.kept += size;
+            }
+        }
+        Ok(eviction)
+    }
+}
+
+/// Returns true if a file name is a cache key.
+fn is_key(name: &str) -> bool {
+    name.len() == KEY_LENGTH && name.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+/// Removes a file, ignoring files already removed, e.g. by a concurrent eviction.
+fn remove(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn keys_depend_on_everything() {
+        let cache = Cache::new("cache");
+        let key = cache.key(b"source", "recipe");
+        assert!(is_key(&key));
+        assert_eq!(key, cache.key(b"source", "recipe"));
+        assert_ne!(key, cache.key(b"other", "recipe"));
+        assert_ne!(key, cache.key(b"source", "other"));
+        // The parts are delimited, so that they cannot be shifted into each other
+        assert_ne!(cache.key(b"esource", "recip"), key);
+        let salted = Cache {
+            salt: "--negate".to_string(),
+            ..Cache::new("cache")
+        };
+        assert_ne!(key, salted.key(b"source", "recipe"));
+    }
+
+    #[test]
+    fn evicts_old_and_least_recently_used_entries() {
+        let dir = TempDir::new().unwrap();
+        let mut cache = Cache::new(dir.path().join("cache"));
+        assert_eq!(cache.evict().unwrap(), Eviction::default());
+        assert_eq!(cache.get(&cache.key(b"a", "")), None);
+
+        let keys: Vec<String> = ["a", "b", "c"]
+            .iter()
+            .map(|source| cache.key(source.as_bytes(), ""))
+            .collect();
+        let hour = Duration::from_secs(3600);
+        for (age, key) in keys.iter().enumerate() {
+            cache.put(key, &[0; 10]).unwrap();
+            let used = SystemTime::now() - hour * (age as u32 + 1);
+            let file = fs::File::options()
+                .write(true)
+                .open(cache.path(key))
+                .unwrap();
+            file.set_modified(used).unwrap();
+        }
+        fs::write(cache.dir.join("notes.txt"), "not an entry").unwrap();
+        // Using the oldest entry makes it the most recent one
+        assert_eq!(cache.get(&keys[2]), Some(vec![0; 10]));
+
+        // The second entry is too old, and the first one no longer fits
+        cache.max_age = Some(hour * 3 / 2);
+        cache.max_size = Some(15);
+        let eviction = cache.evict().unwrap();
+        assert_eq!(
+            eviction,
+            Eviction {
+                removed: 2,
+                freed: 20,
+                kept: 10
+            }
+        );
+        assert!(cache.get(&keys[2]).is_some());
+        assert!(cache.get(&keys[0]).is_none() && cache.get(&keys[1]).is_none());
+        assert!(cache.dir.join("notes.txt").exists());
+    }
+}
@@ -0,0 +1,101 @@
+//! Cooperative cancellation.
+//!
+//! A `CancellationToken` is shared between the code running a job and the code that may
+//! abort it, such as a signal handler or a server shutting down. The pipeline checks the
+//! token between stages and the batch runner checks it between inputs; work already in
+//! progress within a stage is not interrupted.
+//!
+//! `CancellationToken::cancel_on_interrupt` cancels a token when the process receives
+//! Ctrl-C or a termination signal. Since outputs are only written once encoded, and through
+//! a temporary file renamed on success, a cancelled run never leaves a partial output: the
+//! image in progress is either finished or discarded before it is written. A second signal
+//! exits at once instead, without waiting for the write in progress, if any: it can leave
+//! its temporary file behind, or a truncated output if outputs are written directly.
+
+use crate::error::ResizeError;
+use std::{
+    error::Error,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+use tracing::warn;
+
+/// A cloneable flag used to request the cancellation of a job.
+///
+/// All clones share the same state, so cancelling one clone cancels them all.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    /// Creates a token that is not cancelled.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Requests cancellation of every job using this token.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    /// Returns `true` if cancellation was requested.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Returns `Err(ResizeError::Cancelled)` if cancellation was requested.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the token was cancelled.
+    pub fn check(&self) -> Result<(), ResizeError> {
+        if self.is_cancelled() {
+            Err(ResizeError::Cancelled)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Cancels this token when the process receives Ctrl-C, or `SIGTERM` or `SIGHUP` on
+    /// Unix. A second signal, received while the cancelled job is still finishing, exits
+    /// the process immediately, even in the middle of writing an output.
+    ///
+    /// # Arguments
+    ///
+    /// * `exit_code` - The exit code used on the second signal.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the handler cannot be installed, e.g. because
+    /// a handler was already installed by this process.
+    pub fn cancel_on_interrupt(&self, exit_code: i32) -> Result<(), Box<dyn Error>> {
+        let token = self.clone();
+        ctrlc::set_handler(move || {
+            if token.is_cancelled() {
+                std::process::exit(exit_code);
+            }
+            warn!("Interrupted, finishing the current image (interrupt again to quit now)");
+            token.cancel();
+        })
+        .map_err(|e| format!("Failed to install the interrupt handler: {}", e).into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clones_share_state() {
+        let token = CancellationToken::new();
+        let clone = token.clone();
+        assert!(clone.check().is_ok());
+
+        token.cancel();
+        assert!(clone.is_cancelled());
+        assert_eq!(clone.check(), Err(ResizeError::Cancelled));
+    }
+}
@@ -4,23 +4,185 @@
 //! including argument parsing, output path determination, and input/output path validation.
 //! It defines the structure of the CLI and handles user input processing for the application.
 
-use clap::{error::ErrorKind, value_parser, Arg, Command, Error};
+use clap::{
+    error::ErrorKind, parser::ValueSource, value_parser, Arg, ArgAction, ArgMatches, Command, Error,
+};
 use image::ImageFormat;
+use image_resizer_rust::{
+    archive::ArchiveFormat,
+    codec::EncoderRegistry,
+    filters::MAX_DENOISE_STRENGTH,
+    format::{self, OutputFormat},
+    lqip,
+    montage::parse_color,
+    paths,
+    schedule::RateLimit,
+    select,
+    sequence::FramePattern,
+    split::parse_pair,
+    units::PrintSize,
+    upscale::parse_factor,
+};
+use log::warn;
 use std::{
     ffi::OsStr,
-    fs::File,
+    fmt,
+    fs::{self, File},
     io::Read,
+    net::SocketAddr,
     path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
 };
 
+/// What a relative `--output` path is relative to.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OutputBase {
+    /// The directory of each input, so that `-o small.jpg` is written next to the input.
+    #[default]
+    Input,
+    /// The current working directory, like the paths of most command-line tools.
+    Cwd,
+}
+
+impl fmt::Display for OutputBase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            OutputBase::Input => "input",
+            OutputBase::Cwd => "cwd",
+        };
+        f.write_str(name)
+    }
+}
+
+impl FromStr for OutputBase {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "input" => Ok(OutputBase::Input),
+            "cwd" => Ok(OutputBase::Cwd),
+            _ => Err(format!("Unknown output base '{}'", s)),
+        }
+    }
+}
+
 /// Builds and returns the command-line interface for the Image Resizer application.
 ///
 /// This function defines the following CLI arguments:
-/// - `input` (required): Path to the input image file.
+/// - `input` (required): Path to one or more input image files, glob patterns or ZIP/tar archives.
 /// - `width` (optional): New width of the image. Required if `height` not provided.
 /// - `height` (optional): New height of the image. Required if `width` not provided.
-/// - `format` (optional): Specify the output image format (jpeg or png).
+/// - `max-megapixels` (optional): Scale outputs down to at most this many megapixels.
+/// - `format` (optional): Specify the output image format by name or MIME type, one of `format::OutputFormat`.
 /// - `output` (optional): Path for the output image file.
+/// - `mkdir` (optional): Create the output directory and its parents if they do not exist.
+/// - `fix-extension` (optional): Replace the extension of `output` when it does not match `format`.
+/// - `output-relative-to` (optional): Resolve a relative `output` against the input's directory or the CWD.
+/// - `job-file` (optional): CSV or JSON file giving the input, output, size and format of each image.
+/// - `files-from` (optional): File listing more input paths, one per line, or `-` for stdin.
+/// - `exclude` (optional): Skip the inputs matching these glob patterns.
+/// - `min-width`, `max-width`, `min-height`, `max-height` (optional): Only process inputs within these dimensions.
+/// - `larger-than`, `smaller-than` (optional): Only process input files within these sizes.
+/// - `newer-than`, `older-than` (optional): Only process input files modified within these dates.
+/// - `null` (optional): Paths of the `files-from` list are separated by NUL bytes.
+/// - `from-clipboard` (optional): Resize the image on the clipboard instead of input files.
+/// - `to-clipboard` (optional): Put the resized PNG image on the clipboard instead of a file.
+/// - `zip` (optional): Write the resized images into this ZIP archive instead of loose files.
+/// - `fail-fast` (optional): Stop at the first input that fails to process.
+/// - `keep-going` (optional): Continue with the remaining inputs after a failure (default).
+/// - `low-memory` (optional): Resize in bands to cap peak memory usage, streaming PNG and JPEG rows.
+/// - The limit arguments of `limit_args`: `max-pixels`, `max-memory`, `max-dimensions` and
+///   `decode-timeout`.
+/// - `max-archive-size` (optional): Maximum number of bytes extracted from an archive input
+///   (default: 4G).
+/// - `max-archive-entries` (optional): Maximum number of entries of an archive input
+///   (default: 100000).
+/// - `threads` (optional): Number of threads used to resize a single image.
+/// - `cpu-ext` (optional): SIMD instruction set used by the resizer.
+/// - `rounding` (optional): How the side computed from the aspect ratio is rounded (round, floor or ceil).
+/// - `multiple-of` (optional): Round both sides of the output to a multiple of N.
+/// - `even` (optional): Round both sides of the output to even numbers, like `--multiple-of 2`.
+/// - `progressive` (optional): Emit progressive JPEGs and interlaced PNGs.
+/// - `quality` (optional): Quality of JPEG and AVIF outputs (1-100).
+/// - `chroma-subsampling` (optional): Chroma subsampling of JPEG outputs (444, 422 or 420).
+/// - `encoder` (optional): Library encoding JPEG outputs (builtin or mozjpeg).
+/// - `png-compression` (optional): Zlib compression level of PNG outputs (0-9).
+/// - `png-filter` (optional): Scanline filter of PNG outputs (none, sub, up, average, paeth or adaptive).
+/// - `palette` (optional): Quantize PNG outputs to a palette of at most this many colors.
+/// - `dither` (optional): Dithering algorithm used with `palette` (none, ordered or floyd-steinberg).
+/// - `tga-rle` (optional): Run-length encode TGA outputs.
+/// - `avif-speed` (optional): Speed of the AVIF encoder (1-10).
+/// - `preview` (optional): Print a small preview of each output in the terminal before saving it.
+/// - `compare-output` (optional): Also save a before/after comparison image next to each output.
+/// - `recipe` (optional): YAML or TOML file describing the processing steps and encoder settings, or several outputs.
+/// - `upscale` (optional): Enlarge each image by this factor (2x to 8x) instead of resizing it to a size.
+/// - `engine` (optional): Upscaling engine (resize or realesrgan).
+/// - `model` (optional): ONNX model file used by neural upscaling engines.
+/// - `gravity` (optional): Cover crop to the exact width and height, keeping this part of the image.
+/// - `seam-carve` (optional): Change the aspect ratio with seam carving instead of stretching (experimental).
+/// - `remove-background` (optional): Make the background transparent before resizing, flood filling it with this color tolerance in percent.
+/// - `background-model` (optional): ONNX segmentation model used by `remove-background` instead of the flood fill.
+/// - `trim` (optional): Crop uniform borders, with this color tolerance in percent, before resizing.
+/// - `denoise` (optional): Reduce noise with a median filter of this strength before resizing.
+/// - `apply-alpha` (optional): Grayscale image used as the alpha channel of each output.
+/// - `extract-alpha` (optional): Save the alpha channel of each output as a grayscale mask.
+/// - `negate` (optional): Invert the colors of each output.
+/// - `swap-rb` (optional): Swap the red and blue channels of each output.
+/// - `channel` (optional): Output a single channel (r, g, b or a) as a grayscale image.
+/// - `lut` (optional): 3D LUT in the .cube format used to color grade each output.
+/// - `tonemap` (optional): Tone mapping operator of HDR inputs (reinhard or aces).
+/// - `tone` (optional): Tone preset applied to each output (sepia, cool or warm).
+/// - `vignette` (optional): Darken the corners of each output by this percentage.
+/// - `dominant-colors` (optional): Print the given number of dominant colors of each input.
+/// - `lqip` (optional): Print a tiny blurred placeholder of each input as a base64 data URI.
+/// - `picture` (optional): Write an HTML `<picture>` element of the outputs of each input.
+/// - `hash` (optional): Print the perceptual hash (dhash or phash) of each input.
+/// - `dedupe` (optional): Skip or flag inputs that look like an earlier input.
+/// - `similarity-threshold` (optional): Maximum hash distance between near-duplicates.
+/// - `force-reencode` (optional): Re-encode images that would otherwise be copied unchanged.
+/// - `dpi` (optional): Resolution written to the output metadata.
+/// - `print-size` (optional): Physical output size, converted to pixels with `dpi`.
+/// - `accept` (optional): Comma-separated list of the only input formats to process.
+/// - `reject` (optional): Comma-separated list of input formats to reject.
+/// - `overwrite` (optional): What to do when an output file exists (prompt, always, never or rename).
+/// - `yes` (optional): Replace existing output files without asking (same as `--overwrite always`).
+/// - `in-place` (optional): Replace each input with its resized version.
+/// - `backup` (optional): With `in-place`, keep each original as `<name>.bak`.
+/// - `backup-dir` (optional): With `in-place`, move each original to this directory first.
+/// - `delete-original` (optional): Move each input to the trash once its output is verified.
+/// - `no-trash` (optional): With `delete-original`, delete inputs permanently.
+/// - `verify` (optional): Read each output back, check it and report its SHA-256 checksum.
+/// - `retries` (optional): Retry reads and writes failing with a transient error this many times.
+/// - `retry-delay` (optional): Delay before the first retry, doubled before every further one.
+/// - `direct-write` (optional): Write outputs in place instead of through a renamed temp file.
+/// - `copy-xattrs` (optional): Copy extended attributes (e.g. Finder tags) from each input.
+/// - `strip-quarantine` (optional): Leave out the macOS quarantine flag when copying them.
+/// - `keep-metadata` (optional): Copy the EXIF metadata of JPEG and PNG inputs to JPEG and PNG outputs.
+/// - `strip-gps` (optional): Keep the EXIF metadata without the GPS location.
+/// - `privacy` (optional): Keep the EXIF metadata without the location and the tags identifying the camera or its owner.
+/// - `metadata` (optional): How much EXIF metadata is kept (all, safe or none).
+/// - `set-comment` (optional): Comment written to the EXIF and XMP metadata of JPEG and PNG outputs.
+/// - `set-copyright` (optional): Copyright notice written to the EXIF and XMP metadata of JPEG and PNG outputs.
+/// - `set-artist` (optional): Author written to the EXIF and XMP metadata of JPEG and PNG outputs.
+/// - `sidecars` (optional): Apply the crop stored in the XMP sidecar of each input before resizing.
+/// - `min-rating` (optional): Skip inputs whose XMP sidecar rating is below this value.
+/// - `copy-sidecars` (optional): Copy the XMP sidecar of each input next to its output.
+/// - `deterministic` (optional): Produce byte-identical outputs for identical inputs and options.
+/// - `hash-names` (optional): Name outputs after a hash of their contents and write a manifest.
+/// - `manifest` (optional): Path of the JSON manifest listing the variants of each input.
+/// - `cache-dir` (optional): Directory caching encoded outputs across runs.
+/// - `cache-max-size` (optional): Maximum total size of the cache, enforced after each run.
+/// - `cache-max-age` (optional): Remove cached outputs unused for longer than this after each run.
+/// - `verbose` (optional): Print more details; repeat for even more (`-vv`).
+/// - `quiet` (optional): Only print errors.
+///
+/// It also defines the `compare`, `interactive`, `appicon`, `montage`, `contact-sheet`,
+/// `join`, `split`, `pyramid`, `generate`, `daemon`, `grpc`, `sequence` and `texture`
+/// subcommands (see `compare_command`, `interactive_command`, `appicon_command`,
+/// `montage_command`, `contact_sheet_command`, `join_command`, `split_command`,
+/// `pyramid_command`, `generate_command`, `daemon_command`, `grpc_command`,
+/// `sequence_command` and `texture_command`).
 ///
 /// # Returns
 ///
@@ -29,11 +191,27 @@ pub fn cli() -> Command {
     Command::new("image-resizer-rust")
         .version("1.0")
         .about("Resizes images based on provided dimensions")
+        .args_conflicts_with_subcommands(true)
+        .subcommand_negates_reqs(true)
+        .subcommand(compare_command())
+        .subcommand(interactive_command())
+        .subcommand(appicon_command())
+        .subcommand(montage_command())
+        .subcommand(contact_sheet_command())
+        .subcommand(join_command())
+        .subcommand(split_command())
+        .subcommand(pyramid_command())
+        .subcommand(generate_command())
+        .subcommand(daemon_command())
+        .subcommand(grpc_command())
+        .subcommand(sequence_command())
+        .subcommand(texture_command())
         .arg(
             Arg::new("input")
-                .help("Path to the input image")
-                .required(true)
-                .value_parser(value_parser_for_path)
+                .help("Path to the input image. Several images, glob patterns such as 'photos/**/*.jpg', or ZIP/tar archives of images, can be given to resize them in batch.")
+                .required_unless_present_any(["from-clipboard", "job-file", "files-from"])
+                .num_args(1..)
+                .value_parser(value_parser_for_input)
                 .index(1)
         )
         .arg(
@@ -41,21 +219,32 @@ pub fn cli() -> Command {
                 .short('W')
                 .long("width")
                 .help("New width of the image. Required if --height not provided.")
-                .value_parser(clap::value_parser!(u32))
+                .value_parser(clap::value_parser!(u32).range(1..))
         )
         .arg(
             Arg::new("height")
                 .short('H')
                 .long("height")
                 .help("New height of the image. Required if --width not provided.")
-                .value_parser(clap::value_parser!(u32))
+                .value_parser(clap::value_parser!(u32).range(1..))
+        )
+        .arg(
+            Arg::new("max-megapixels")
+                .long("max-megapixels")
+                .value_name("MP")
+                .help("Scale outputs down, keeping their aspect ratio, so that they have at most MP million pixels (e.g. 12 or 0.5); smaller outputs are not enlarged")
+                .value_parser(value_parser_for_megapixels)
         )
         .arg(
             Arg::new("format")
                 .short('F')
                 .long("format")
-                .help("Specify the image format")
-                .value_parser(["jpeg", "png"])
+                .value_name("FORMAT")
+                .help(format!(
+                    "Specify the image format: {}, or a MIME type such as image/webp",
+                    OutputFormat::list(EncoderRegistry::builtin())
+                ))
+                .value_parser(value_parser_for_output_format)
         )
         .arg(
             Arg::new("output")
@@ -65,76 +254,1884 @@ pub fn cli() -> Command {
                 .required(false)
                 .value_parser(value_parser!(String))
         )
+        .arg(
+            Arg::new("mkdir")
+                .long("mkdir")
+                .help("Create the output directory and its parents if they do not exist; an --output ending with a path separator is a directory")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(["in-place", "zip", "to-clipboard"]),
+        )
+        .arg(
+            Arg::new("fix-extension")
+                .long("fix-extension")
+                .help("Replace the extension of an --output file that does not match --format, with a warning, instead of failing")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("output-relative-to")
+                .long("output-relative-to")
+                .value_name("BASE")
+                .help("What a relative --output file is relative to: the directory of each input, or the current working directory")
+                .default_value("input")
+                .value_parser(["input", "cwd"]),
+        )
+        .arg(
+            Arg::new("job-file")
+                .long("job-file")
+                .value_name("FILE")
+                .help("CSV or JSON file listing the inputs, with an optional output, width, height and format for each; empty values fall back to the other options")
+                .value_parser(value_parser!(PathBuf))
+                .conflicts_with_all(["input", "from-clipboard", "to-clipboard", "min-rating", "dedupe"])
+        )
+        .arg(
+            Arg::new("files-from")
+                .long("files-from")
+                .value_name("FILE")
+                .help("Also resize the images listed in FILE, one path per line, or in stdin for `-`; they are processed as they are read, e.g. from `find` or `fd`")
+                .value_parser(value_parser!(PathBuf))
+                .conflicts_with_all(["job-file", "from-clipboard", "to-clipboard", "min-rating", "dedupe", "hash"])
+        )
+        .arg(
+            Arg::new("exclude")
+                .long("exclude")
+                .value_name("PATTERN")
+                .help("Skip the inputs matching this glob pattern, matched against the file name if it has no path separator and against the whole path otherwise; can be repeated")
+                .action(ArgAction::Append)
+                .conflicts_with("job-file")
+        )
+        .arg(
+            Arg::new("min-width")
+                .long("min-width")
+                .value_name("PIXELS")
+                .help("Only process inputs at least this wide; the size is read from the image header")
+                .value_parser(value_parser!(u32).range(1..))
+                .conflicts_with("job-file")
+        )
+        .arg(
+            Arg::new("max-width")
+                .long("max-width")
+                .value_name("PIXELS")
+                .help("Only process inputs at most this wide")
+                .value_parser(value_parser!(u32).range(1..))
+                .conflicts_with("job-file")
+        )
+        .arg(
+            Arg::new("min-height")
+                .long("min-height")
+                .value_name("PIXELS")
+                .help("Only process inputs at least this high")
+                .value_parser(value_parser!(u32).range(1..))
+                .conflicts_with("job-file")
+        )
+        .arg(
+            Arg::new("max-height")
+                .long("max-height")
+                .value_name("PIXELS")
+                .help("Only process inputs at most this high")
+                .value_parser(value_parser!(u32).range(1..))
+                .conflicts_with("job-file")
+        )
+        .arg(
+            Arg::new("larger-than")
+                .long("larger-than")
+                .value_name("SIZE")
+                .help("Only process input files larger than SIZE (e.g. 500K, 5MB)")
+                .value_parser(value_parser_for_size)
+                .conflicts_with("job-file")
+        )
+        .arg(
+            Arg::new("smaller-than")
+                .long("smaller-than")
+                .value_name("SIZE")
+                .help("Only process input files smaller than SIZE (e.g. 500K, 5MB)")
+                .value_parser(value_parser_for_size)
+                .conflicts_with("job-file")
+        )
+        .arg(
+            Arg::new("newer-than")
+                .long("newer-than")
+                .value_name("DATE")
+                .help("Only process input files modified on or after DATE (YYYY-MM-DD, UTC)")
+                .value_parser(select::parse_date)
+                .conflicts_with("job-file")
+        )
+        .arg(
+            Arg::new("older-than")
+                .long("older-than")
+                .value_name("DATE")
+                .help("Only process input files modified before DATE (YYYY-MM-DD, UTC)")
+                .value_parser(select::parse_date)
+                .conflicts_with("job-file")
+        )
+        .arg(
+            Arg::new("null")
+                .short('0')
+                .long("null")
+                .help("Paths read with --files-from are separated by NUL bytes, as printed by `find -print0` or `fd -0`")
+                .action(ArgAction::SetTrue)
+                .requires("files-from")
+        )
+        .arg(
+            Arg::new("from-clipboard")
+                .long("from-clipboard")
+                .help("Resize the image on the system clipboard (requires the `clipboard` feature)")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("input")
+        )
+        .arg(
+            Arg::new("to-clipboard")
+                .long("to-clipboard")
+                .help("Put the resized image on the system clipboard as PNG (requires the `clipboard` feature)")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(["output", "zip", "format"])
+        )
+        .arg(
+            Arg::new("zip")
+                .long("zip")
+                .value_name("FILE")
+                .help("Write all resized images into this ZIP archive instead of loose files")
+                .value_parser(value_parser!(PathBuf))
+                .conflicts_with("output")
+        )
+        .arg(
+            Arg::new("fail-fast")
+                .long("fail-fast")
+                .help("Stop processing at the first input that fails")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("keep-going")
+        )
+        .arg(
+            Arg::new("keep-going")
+                .long("keep-going")
+                .help("Keep processing the remaining inputs after a failure (default)")
+                .action(ArgAction::SetTrue)
+        )
+        .arg(
+            Arg::new("low-memory")
+                .long("low-memory")
+                .help("Resize in bands to cap peak memory usage, streaming PNG and JPEG rows when possible.\nEnabled automatically for images above 100 megapixels.")
+                .action(ArgAction::SetTrue)
+        )
+        .args(limit_args())
+        .arg(
+            Arg::new("max-archive-size")
+                .long("max-archive-size")
+                .value_name("SIZE")
+                .help("Reject archive inputs whose images add up to more than this once extracted (e.g. 512M, default: 4G)")
+                .value_parser(value_parser_for_size)
+        )
+        .arg(
+            Arg::new("max-archive-entries")
+                .long("max-archive-entries")
+                .value_name("N")
+                .help("Reject archive inputs with more entries than this (default: 100000)")
+                .value_parser(value_parser!(u64).range(1..))
+        )
+        .arg(
+            Arg::new("threads")
+                .long("threads")
+                .help("Number of threads used to resize a single image (0 = one per CPU core)")
+                .default_value("1")
+                .value_parser(value_parser!(usize))
+        )
+        .arg(
+            Arg::new("cpu-ext")
+                .long("cpu-ext")
+                .help("SIMD instruction set used by the resizer")
+                .default_value("auto")
+                .value_parser(["auto", "none", "sse4.1", "avx2", "neon"])
+        )
+        .arg(
+            Arg::new("rounding")
+                .long("rounding")
+                .value_name("MODE")
+                .help("How the side computed from the aspect ratio is rounded to whole pixels; the result is always at least 1 pixel")
+                .default_value("round")
+                .value_parser(["round", "floor", "ceil"])
+        )
+        .arg(
+            Arg::new("multiple-of")
+                .long("multiple-of")
+                .value_name("N")
+                .help("Round both sides of the output to a multiple of N, following --rounding, e.g. for video encoders")
+                .value_parser(value_parser!(u32).range(1..))
+        )
+        .arg(
+            Arg::new("even")
+                .long("even")
+                .help("Round both sides of the output to even numbers, like --multiple-of 2")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("multiple-of")
+        )
+        .arg(
+            Arg::new("progressive")
+                .long("progressive")
+                .help("Emit progressive JPEGs and Adam7-interlaced PNGs")
+                .action(ArgAction::SetTrue)
+        )
+        .arg(
+            Arg::new("quality")
+                .long("quality")
+                .value_name("QUALITY")
+                .help("Quality of JPEG and AVIF outputs, from 1 (smallest) to 100 (best); WebP outputs are lossless [default: 75 for JPEG, 80 for AVIF]")
+                .value_parser(value_parser!(u8).range(1..=100))
+        )
+        .arg(
+            Arg::new("chroma-subsampling")
+                .long("chroma-subsampling")
+                .value_name("MODE")
+                .help("Chroma subsampling of JPEG outputs: 444 keeps colored edges sharp, 420 is the smallest [default: 420]")
+                .value_parser(["444", "422", "420"])
+        )
+        .arg(
+            Arg::new("encoder")
+                .long("encoder")
+                .value_name("ENCODER")
+                .help("Library encoding JPEG outputs; mozjpeg makes smaller files but is slower, and needs the mozjpeg feature")
+                .value_parser(["builtin", "mozjpeg"])
+                .default_value("builtin")
+        )
+        .arg(
+            Arg::new("png-compression")
+                .long("png-compression")
+                .value_name("LEVEL")
+                .help("Zlib compression level of PNG outputs, from 0 (none) to 9 (best)")
+                .value_parser(value_parser!(u8).range(0..=9))
+        )
+        .arg(
+            Arg::new("png-filter")
+                .long("png-filter")
+                .value_name("FILTER")
+                .help("Scanline filter of PNG outputs; adaptive picks the best one for each scanline [default: paeth, none for --palette]")
+                .value_parser(["none", "sub", "up", "average", "paeth", "adaptive"])
+        )
+        .arg(
+            Arg::new("palette")
+                .long("palette")
+                .value_name("COLORS")
+                .help("Quantize PNG outputs to an 8-bit palette (default 256 colors), dithered as set by --dither")
+                .num_args(0..=1)
+                .default_missing_value("256")
+                .value_parser(value_parser!(u16).range(2..=256))
+        )
+        .arg(
+            Arg::new("dither")
+                .long("dither")
+                .value_name("ALGORITHM")
+                .help("Dithering algorithm used by --palette")
+                .value_parser(["none", "ordered", "floyd-steinberg"])
+                .default_value("floyd-steinberg")
+        )
+        .arg(
+            Arg::new("tga-rle")
+                .long("tga-rle")
+                .help("Run-length encode TGA outputs")
+                .action(ArgAction::SetTrue)
+        )
+        .arg(
+            Arg::new("avif-speed")
+                .long("avif-speed")
+                .value_name("SPEED")
+                .help("Speed of the AVIF encoder, from 1 (slowest, smallest) to 10 (fastest) [default: 4]")
+                .value_parser(value_parser!(u8).range(1..=10))
+        )
+        .arg(
+            Arg::new("preview")
+                .long("preview")
+                .value_name("PROTOCOL")
+                .help("Print a small preview of each output in the terminal before saving it (default: detect the terminal's graphics protocol)")
+                .num_args(0..=1)
+                .default_missing_value("auto")
+                .value_parser(["auto", "kitty", "iterm", "sixel", "blocks"])
+                .conflicts_with_all(["zip", "to-clipboard"])
+        )
+        .arg(
+            Arg::new("compare-output")
+                .long("compare-output")
+                .value_name("LAYOUT")
+                .help("Also save a PNG comparing each input and its output, as <output>_compare.png (default layout: side-by-side)")
+                .num_args(0..=1)
+                .default_missing_value("side-by-side")
+                .value_parser(["side-by-side", "split"])
+                .conflicts_with_all(["zip", "to-clipboard"])
+        )
+        .arg(
+            Arg::new("recipe")
+                .long("recipe")
+                .value_name("FILE")
+                .help("YAML or TOML recipe listing the processing steps (resize, crop, trim, denoise, sharpen, watermark, tone, vignette, negate) and encoder settings, or several named outputs; other options take precedence over its settings")
+                .value_parser(value_parser!(PathBuf))
+                .conflicts_with_all([
+                    "zip",
+                    "to-clipboard",
+                    "upscale",
+                    "gravity",
+                    "seam-carve",
+                    "sidecars",
+                    "remove-background",
+                    "trim",
+                    "denoise",
+                    "apply-alpha",
+                ])
+        )
+        .arg(
+            Arg::new("upscale")
+                .long("upscale")
+                .value_name("FACTOR")
+                .help("Enlarge each image FACTOR times (2x to 8x) instead of resizing it to --width or --height")
+                .value_parser(parse_factor)
+                .conflicts_with_all(["width", "height", "print-size", "zip", "to-clipboard", "low-memory"])
+        )
+        .arg(
+            Arg::new("engine")
+                .long("engine")
+                .value_name("ENGINE")
+                .help("Upscaling engine used by --upscale; realesrgan needs a build with the onnx feature and --model")
+                .value_parser(["resize", "realesrgan"])
+                .default_value("resize")
+                .requires("upscale")
+        )
+        .arg(
+            Arg::new("model")
+                .long("model")
+                .value_name("FILE")
+                .help("ONNX super-resolution model used by --engine realesrgan, e.g. RealESRGAN_x4plus.onnx")
+                .value_parser(value_parser!(PathBuf))
+                .requires("upscale")
+        )
+        .arg(
+            Arg::new("gravity")
+                .long("gravity")
+                .value_name("GRAVITY")
+                .help("Scale to cover the exact --width and --height and crop the rest, keeping this part of the image (smart: the most detailed and contrasted part)")
+                .value_parser([
+                    "center",
+                    "north",
+                    "south",
+                    "east",
+                    "west",
+                    "northeast",
+                    "northwest",
+                    "southeast",
+                    "southwest",
+                    "smart",
+                ])
+                .requires_all(["width", "height"])
+                .conflicts_with_all(["zip", "to-clipboard", "low-memory"])
+        )
+        .arg(
+            Arg::new("seam-carve")
+                .long("seam-carve")
+                .help("Reach the exact --width and --height by removing or inserting low-detail seams instead of stretching the image (experimental, slow)")
+                .action(ArgAction::SetTrue)
+                .requires_all(["width", "height"])
+                .conflicts_with_all(["zip", "to-clipboard", "low-memory", "gravity"])
+        )
+        .arg(
+            Arg::new("remove-background")
+                .long("remove-background")
+                .value_name("FUZZ")
+                .help("Make the background transparent before resizing, flood filling the uniform background from the edges with a color tolerance of FUZZ percent (default 10%)")
+                .num_args(0..=1)
+                .default_missing_value("10")
+                .value_parser(value_parser_for_percent)
+                .conflicts_with_all(["zip", "to-clipboard"])
+        )
+        .arg(
+            Arg::new("background-model")
+                .long("background-model")
+                .value_name("FILE")
+                .help("ONNX segmentation model used by --remove-background instead of the flood fill, e.g. u2net.onnx (needs a build with the onnx feature)")
+                .value_parser(value_parser!(PathBuf))
+                .requires("remove-background")
+        )
+        .arg(
+            Arg::new("trim")
+                .long("trim")
+                .value_name("FUZZ")
+                .help("Crop uniform borders before resizing, such as white backgrounds or letterbox bars, with a color tolerance of FUZZ percent (default 10%)")
+                .num_args(0..=1)
+                .default_missing_value("10")
+                .value_parser(value_parser_for_percent)
+                .conflicts_with_all(["zip", "to-clipboard"])
+        )
+        .arg(
+            Arg::new("denoise")
+                .long("denoise")
+                .value_name("STRENGTH")
+                .help("Reduce noise with a median filter before resizing, from 1 (light, the default) to 5 (strong)")
+                .num_args(0..=1)
+                .default_missing_value("1")
+                .value_parser(value_parser!(u32).range(1..=MAX_DENOISE_STRENGTH as i64))
+                .conflicts_with_all(["zip", "to-clipboard"])
+        )
+        .arg(
+            Arg::new("apply-alpha")
+                .long("apply-alpha")
+                .value_name("MASK")
+                .help("Use a grayscale image as the alpha channel of each output, white being opaque (stretched to the output size)")
+                .value_parser(value_parser_for_path)
+                .conflicts_with_all(["zip", "to-clipboard"])
+        )
+        .arg(
+            Arg::new("extract-alpha")
+                .long("extract-alpha")
+                .value_name("FILE")
+                .help("Save the alpha channel of each output as a grayscale mask; with several inputs, each mask is named <FILE stem>_<input stem>")
+                .value_parser(value_parser!(PathBuf))
+                .conflicts_with_all(["zip", "to-clipboard"])
+        )
+        .arg(
+            Arg::new("negate")
+                .long("negate")
+                .help("Invert the colors of each output, keeping its transparency")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(["zip", "to-clipboard"])
+        )
+        .arg(
+            Arg::new("swap-rb")
+                .long("swap-rb")
+                .help("Swap the red and blue channels of each output, e.g. to fix BGR textures")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(["zip", "to-clipboard"])
+        )
+        .arg(
+            Arg::new("channel")
+                .long("channel")
+                .value_name("CHANNEL")
+                .help("Output a single channel of each image as a grayscale image")
+                .value_parser(["r", "g", "b", "a"])
+                .conflicts_with_all(["zip", "to-clipboard", "extract-alpha"])
+        )
+        .arg(
+            Arg::new("lut")
+                .long("lut")
+                .value_name("FILE")
+                .help("Color grade each output with a 3D LUT in the .cube format")
+                .value_parser(value_parser!(PathBuf))
+                .conflicts_with_all(["zip", "to-clipboard"])
+        )
+        .arg(
+            Arg::new("tonemap")
+                .long("tonemap")
+                .value_name("OPERATOR")
+                .help("Tone map HDR inputs (OpenEXR, Radiance HDR) instead of clipping their highlights")
+                .value_parser(["reinhard", "aces"])
+        )
+        .arg(
+            Arg::new("tone")
+                .long("tone")
+                .value_name("TONE")
+                .help("Apply a tone preset to each output")
+                .value_parser(["sepia", "cool", "warm"])
+                .conflicts_with_all(["zip", "to-clipboard"])
+        )
+        .arg(
+            Arg::new("vignette")
+                .long("vignette")
+                .value_name("STRENGTH")
+                .help("Darken the corners of each output by STRENGTH percent (default 50)")
+                .num_args(0..=1)
+                .default_missing_value("50")
+                .value_parser(value_parser!(u8).range(1..=100))
+                .conflicts_with_all(["zip", "to-clipboard"])
+        )
+        .arg(
+            Arg::new("dominant-colors")
+                .long("dominant-colors")
+                .value_name("COUNT")
+                .help("Print the COUNT most common colors of each input as hex values")
+                .value_parser(value_parser!(u16).range(1..=256))
+        )
+        .arg(
+            Arg::new("lqip")
+                .long("lqip")
+                .value_name("SIZE")
+                .help("Print a blurred placeholder of each input, SIZE pixels wide at most (default 16), as a base64 data URI")
+                .num_args(0..=1)
+                .default_missing_value("16")
+                .value_parser(value_parser!(u32).range(1..=lqip::MAX_SIZE as i64))
+        )
+        .arg(
+            Arg::new("picture")
+                .long("picture")
+                .value_name("FILE")
+                .help("Write an HTML <picture> element offering the outputs of each input, e.g. those of a recipe, with AVIF and WebP sources and a fallback, to FILE or to stdout with -")
+                .value_parser(value_parser!(PathBuf))
+                .conflicts_with_all(["zip", "to-clipboard"])
+        )
+        .arg(
+            Arg::new("hash")
+                .long("hash")
+                .value_name("ALGORITHM")
+                .help("Print the perceptual hash of each input, also used by --dedupe (default: dhash)")
+                .num_args(0..=1)
+                .default_missing_value("dhash")
+                .value_parser(["dhash", "phash"])
+        )
+        .arg(
+            Arg::new("dedupe")
+                .long("dedupe")
+                .value_name("ACTION")
+                .help("Skip or flag inputs that are near-duplicates of an earlier input")
+                .value_parser(["skip", "flag"])
+        )
+        .arg(
+            Arg::new("similarity-threshold")
+                .long("similarity-threshold")
+                .value_name("BITS")
+                .help("Maximum number of differing hash bits between near-duplicates (0 to 64)")
+                .default_value("10")
+                .value_parser(value_parser!(u32).range(0..=64))
+                .requires("dedupe")
+        )
+        .arg(
+            Arg::new("force-reencode")
+                .long("force-reencode")
+                .help("Re-encode images even if their dimensions and format would not change")
+                .action(ArgAction::SetTrue)
+        )
+        .arg(
+            Arg::new("dpi")
+                .long("dpi")
+                .help("Resolution in dots per inch, written to the output file's metadata")
+                .value_parser(value_parser!(u16).range(1..))
+        )
+        .arg(
+            Arg::new("print-size")
+                .long("print-size")
+                .value_name("SIZE")
+                .help("Physical output size (e.g. 4x6in, 10x15cm, 90x130mm).\nThe pixel dimensions are computed from it and --dpi.")
+                .requires("dpi")
+                .conflicts_with_all(["width", "height"])
+                .value_parser(|s: &str| s.parse::<PrintSize>())
+        )
+        .arg(
+            Arg::new("accept")
+                .long("accept")
+                .value_name("FORMATS")
+                .help("Only process inputs in these formats (e.g. jpeg,png,webp). Other inputs are rejected before decoding.")
+                .value_delimiter(',')
+                .value_parser(value_parser_for_format)
+        )
+        .arg(
+            Arg::new("reject")
+                .long("reject")
+                .value_name("FORMATS")
+                .help("Reject inputs in these formats (e.g. gif,tiff) before decoding")
+                .value_delimiter(',')
+                .value_parser(value_parser_for_format)
+        )
+        .arg(
+            Arg::new("overwrite")
+                .long("overwrite")
+                .value_name("POLICY")
+                .help("What to do when an output file already exists")
+                .default_value("prompt")
+                .value_parser(["prompt", "always", "never", "rename"])
+        )
+        .arg(
+            Arg::new("yes")
+                .short('y')
+                .long("yes")
+                .help("Replace existing output files without asking (same as --overwrite always)")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("overwrite")
+        )
+        .arg(
+            Arg::new("in-place")
+                .long("in-place")
+                .help("Replace each input with its resized version, keeping its path and format")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(["output", "format", "zip", "to-clipboard", "from-clipboard", "job-file", "hash-names", "direct-write", "overwrite", "yes", "compare-output"])
+        )
+        .arg(
+            Arg::new("backup")
+                .long("backup")
+                .help("With --in-place, keep each original next to it with the .bak suffix")
+                .action(ArgAction::SetTrue)
+                .requires("in-place")
+        )
+        .arg(
+            Arg::new("backup-dir")
+                .long("backup-dir")
+                .value_name("DIR")
+                .help("With --in-place, move each original to DIR first, keeping its file name")
+                .value_parser(value_parser!(PathBuf))
+                .requires("in-place")
+                .conflicts_with("backup")
+        )
+        .arg(
+            Arg::new("delete-original")
+                .long("delete-original")
+                .help("Move each input to the trash once its output is written and read back successfully")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(["in-place", "zip", "to-clipboard", "from-clipboard"])
+        )
+        .arg(
+            Arg::new("no-trash")
+                .long("no-trash")
+                .help("With --delete-original, delete inputs permanently instead of moving them to the trash")
+                .action(ArgAction::SetTrue)
+                .requires("delete-original")
+        )
+        .arg(
+            Arg::new("verify")
+                .long("verify")
+                .help("Read each output back after writing it, check that it decodes with the expected dimensions and print its SHA-256 checksum")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(["zip", "to-clipboard"])
+        )
+        .arg(
+            Arg::new("retries")
+                .long("retries")
+                .value_name("N")
+                .help("Retry reading inputs and writing outputs up to N times after a transient error, such as a timeout or a dropped connection to a network share")
+                .default_value("0")
+                .value_parser(value_parser!(u32))
+        )
+        .arg(
+            Arg::new("retry-delay")
+                .long("retry-delay")
+                .value_name("DURATION")
+                .help("Wait this long before the first retry, doubling the delay before every further retry (e.g. 500ms, 2s)")
+                .default_value("500ms")
+                .value_parser(value_parser_for_duration)
+        )
+        .arg(
+            Arg::new("direct-write")
+                .long("direct-write")
+                .help("Write output files in place instead of through a temporary file (for filesystems without atomic renames)")
+                .action(ArgAction::SetTrue)
+        )
+        .arg(
+            Arg::new("copy-xattrs")
+                .long("copy-xattrs")
+                .help("Copy extended attributes, such as Finder tags, from each input to its output (Unix only)")
+                .action(ArgAction::SetTrue)
+        )
+        .arg(
+            Arg::new("strip-quarantine")
+                .long("strip-quarantine")
+                .help("Do not copy the macOS quarantine flag with --copy-xattrs")
+                .action(ArgAction::SetTrue)
+                .requires("copy-xattrs")
+        )
+        .arg(
+            Arg::new("keep-metadata")
+                .long("keep-metadata")
+                .help("Copy the EXIF metadata of JPEG and PNG inputs to JPEG and PNG outputs (dropped by default)")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(["zip", "to-clipboard"])
+        )
+        .arg(
+            Arg::new("strip-gps")
+                .long("strip-gps")
+                .help("Keep the EXIF metadata like --keep-metadata, but without the GPS location")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(["zip", "to-clipboard"])
+        )
+        .arg(
+            Arg::new("privacy")
+                .long("privacy")
+                .help("Keep the EXIF metadata like --keep-metadata, but without the location, serial numbers, owner name and maker notes; the orientation and dates are kept")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(["zip", "to-clipboard"])
+        )
+        .arg(
+            Arg::new("metadata")
+                .long("metadata")
+                .value_name("LEVEL")
+                .help("How much EXIF metadata of JPEG and PNG inputs is kept: all (like --keep-metadata), safe (only the orientation, color space and copyright) or none")
+                .value_parser(["all", "safe", "none"])
+                .conflicts_with_all(["zip", "to-clipboard", "keep-metadata", "strip-gps", "privacy"])
+        )
+        .arg(
+            Arg::new("set-comment")
+                .long("set-comment")
+                .value_name("TEXT")
+                .help("Write a comment to the EXIF and XMP metadata of JPEG and PNG outputs")
+                .conflicts_with_all(["zip", "to-clipboard"])
+        )
+        .arg(
+            Arg::new("set-copyright")
+                .long("set-copyright")
+                .value_name("TEXT")
+                .help("Write a copyright notice to the EXIF and XMP metadata of JPEG and PNG outputs")
+                .conflicts_with_all(["zip", "to-clipboard"])
+        )
+        .arg(
+            Arg::new("set-artist")
+                .long("set-artist")
+                .value_name("NAME")
+                .help("Write the author to the EXIF and XMP metadata of JPEG and PNG outputs")
+                .conflicts_with_all(["zip", "to-clipboard"])
+        )
+        .arg(
+            Arg::new("sidecars")
+                .long("sidecars")
+                .help("Apply the crop stored in the XMP sidecar of each input (photo.xmp or photo.jpg.xmp), e.g. by Lightroom, before resizing")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(["zip", "to-clipboard"])
+        )
+        .arg(
+            Arg::new("min-rating")
+                .long("min-rating")
+                .value_name("STARS")
+                .help("Skip inputs whose XMP sidecar rating is below STARS (0 to 5); inputs without a rating count as 0")
+                .value_parser(value_parser!(i32).range(0..=5))
+        )
+        .arg(
+            Arg::new("copy-sidecars")
+                .long("copy-sidecars")
+                .help("Copy the XMP sidecar of each input next to its output, as <output>.xmp")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(["zip", "to-clipboard"])
+        )
+        .arg(
+            Arg::new("deterministic")
+                .long("deterministic")
+                .help("Produce byte-identical outputs for identical inputs and options on any machine, e.g. for build caches (single-threaded scalar resizing, no copied metadata, inputs processed in sorted order)")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all([
+                    "threads",
+                    "cpu-ext",
+                    "keep-metadata",
+                    "strip-gps",
+                    "privacy",
+                    "metadata",
+                    "copy-xattrs",
+                ])
+        )
+        .arg(
+            Arg::new("hash-names")
+                .long("hash-names")
+                .help("Name outputs after a hash of their contents, as <name>.<hash>.<ext>, and write a manifest mapping each input to its output")
+                .action(ArgAction::SetTrue)
+        )
+        .arg(
+            Arg::new("manifest")
+                .long("manifest")
+                .value_name("FILE")
+                .help("Write a JSON manifest listing the outputs of each input with their dimensions, formats, sizes and checksums, e.g. for static site generators [default with --hash-names: manifest.json in the output directory or archive, or in the current directory]")
+                .value_parser(value_parser!(PathBuf))
+        )
+        .arg(
+            Arg::new("cache-dir")
+                .long("cache-dir")
+                .value_name("DIR")
+                .help("Cache encoded outputs in this directory, keyed by the contents of the input and the options, and reuse them in later runs")
+                .value_parser(value_parser!(PathBuf))
+                .conflicts_with_all(["preview", "extract-alpha", "sidecars"])
+        )
+        .arg(
+            Arg::new("cache-max-size")
+                .long("cache-max-size")
+                .value_name("SIZE")
+                .help("Remove the least recently used cached outputs after each run until the cache fits in this size (e.g. 500M, 2G)")
+                .value_parser(value_parser_for_size)
+                .requires("cache-dir")
+        )
+        .arg(
+            Arg::new("cache-max-age")
+                .long("cache-max-age")
+                .value_name("DURATION")
+                .help("Remove cached outputs unused for longer than this after each run (e.g. 12h, 30d)")
+                .value_parser(value_parser_for_age)
+                .requires("cache-dir")
+        )
+        .arg(
+            Arg::new("verbose")
+                .short('v')
+                .long("verbose")
+                .help("Print stage timings and decode/encode details (-vv for more)")
+                .action(ArgAction::Count)
+                .global(true)
+        )
+        .arg(
+            Arg::new("quiet")
+                .short('q')
+                .long("quiet")
+                .help("Only print errors")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("verbose")
+                .global(true)
+        )
 }
 
-/// Determines the output path for the resized image.
-///
-/// # Arguments
+/// Builds the `compare` subcommand.
 ///
-/// * `input` - A reference to the `Path` of the input image.
-/// * `output` - An optional `String` specifying the desired output path.
+/// The subcommand takes two positional arguments:
+/// - `original` (required): Path to the reference image.
+/// - `other` (required): Path to the resized or re-encoded image to evaluate.
 ///
 /// # Returns
 ///
-/// A `Result` containing either the determined `PathBuf` for the output or an error.
-pub fn determine_output_path(
-    input: &Path,
-    output: Option<&String>,
-) -> Result<PathBuf, Box<dyn std::error::Error>> {
-    let parent = input.parent().unwrap_or(Path::new(""));
-    let stem = input.file_stem().unwrap_or(OsStr::new("output"));
-    let extension = input.extension().unwrap_or(OsStr::new("jpeg"));
-    let new_stem = PathBuf::from(format!("{}_resized", stem.to_string_lossy()));
-
-    match output {
-        Some(p) => {
-            // check if given output is a directory. If yes, return directory/new_stem.jpeg
-            if Path::new(&p).is_dir() {
-                let output_path = PathBuf::from(p).join(new_stem).with_extension(extension);
-                return Ok(output_path);
-            };
-            let validated_output = validate_output_path(p)?;
-            let path_new = Path::new(&validated_output);
-            let mut path_new_buf = match path_new.extension() {
-                Some(_) => path_new.to_path_buf(),
-                None => path_new.with_extension(extension),
-            };
-            if path_new_buf.is_absolute() {
-                Ok(path_new_buf)
-            } else {
-                path_new_buf = parent.join(path_new_buf);
-                Ok(path_new_buf)
-            }
-        }
-        None => Ok(parent.join(new_stem).with_extension(extension)),
-    }
+/// A `Command` struct representing the subcommand configuration.
+fn compare_command() -> Command {
+    Command::new("compare")
+        .about("Computes PSNR and SSIM quality metrics between an original and a resized image")
+        .arg(
+            Arg::new("original")
+                .help("Path to the original image")
+                .required(true)
+                .value_parser(value_parser_for_path)
+                .index(1),
+        )
+        .arg(
+            Arg::new("other")
+                .help("Path to the resized or re-encoded image")
+                .required(true)
+                .value_parser(value_parser_for_path)
+                .index(2),
+        )
 }
 
-/// Validates the provided output path.
+/// Builds the `interactive` subcommand.
+///
+/// The subcommand takes one positional argument:
+/// - `paths` (optional): Images, or directories of images, to list. Defaults to the current
+///   directory.
+///
+/// # Returns
+///
+/// A `Command` struct representing the subcommand configuration.
+fn interactive_command() -> Command {
+    Command::new("interactive")
+        .about("Picks images and settings in a terminal interface, then resizes them (requires the `tui` feature)")
+        .arg(
+            Arg::new("paths")
+                .help("Images, or directories of images, to choose from [default: current directory]")
+                .num_args(1..)
+                .value_parser(value_parser!(PathBuf))
+                .index(1),
+        )
+}
+
+/// Builds the `appicon` subcommand.
+///
+/// The subcommand takes the following arguments:
+/// - `source` (required): Path to the square source image, at least 1024x1024 pixels.
+/// - `output` (optional): Folder the icon sets are written to (default: `AppIcon`).
+/// - `platform` (optional): Platform to generate icons for (ios, android or all).
+///
+/// # Returns
+///
+/// A `Command` struct representing the subcommand configuration.
+fn appicon_command() -> Command {
+    Command::new("appicon")
+        .about("Generates the iOS and Android app icon sets from a single 1024x1024 image")
+        .arg(
+            Arg::new("source")
+                .help("Path to the square source image, at least 1024x1024 pixels")
+                .required(true)
+                .value_parser(value_parser_for_path)
+                .index(1),
+        )
+        .arg(
+            Arg::new("output")
+                .short('o')
+                .long("output")
+                .value_name("DIR")
+                .help("Folder to write the icon sets to")
+                .default_value("AppIcon")
+                .value_parser(value_parser!(PathBuf)),
+        )
+        .arg(
+            Arg::new("platform")
+                .long("platform")
+                .value_name("PLATFORM")
+                .help("Platform to generate icons for")
+                .default_value("all")
+                .value_parser(["ios", "android", "all"]),
+        )
+}
+
+/// Builds the `montage` subcommand.
+///
+/// The subcommand takes the following arguments:
+/// - `inputs` (required): Paths to the images to tile.
+/// - `output` (required): Path of the sheet image (.png or .jpg).
+/// - `width` (optional): Width to resize every tile to.
+/// - `height` (optional): Height to resize every tile to.
+/// - `columns` (optional): Number of tiles per row (default: a roughly square sheet).
+/// - `spacing` (optional): Space between the tiles in pixels (default: 0).
+/// - `background` (optional): Color around the tiles, `#rrggbb[aa]` or `transparent`.
+/// - `map` (optional): Also write a JSON or CSS map of the tile positions next to the sheet.
+///
+/// # Returns
+///
+/// A `Command` struct representing the subcommand configuration.
+fn montage_command() -> Command {
+    Command::new("montage")
+        .about("Tiles several images into a single sprite sheet")
+        .arg(
+            Arg::new("inputs")
+                .help("Paths to the images to tile, in order")
+                .required(true)
+                .num_args(1..)
+                .value_parser(value_parser_for_path)
+                .index(1),
+        )
+        .arg(
+            Arg::new("output")
+                .short('o')
+                .long("output")
+                .value_name("FILE")
+                .help("Path of the sheet image (.png or .jpg)")
+                .required(true)
+                .value_parser(value_parser!(PathBuf)),
+        )
+        .arg(
+            Arg::new("width")
+                .short('W')
+                .long("width")
+                .help("Width to resize every tile to, keeping its aspect ratio if --height is not given")
+                .value_parser(value_parser!(u32).range(1..)),
+        )
+        .arg(
+            Arg::new("height")
+                .short('H')
+                .long("height")
+                .help("Height to resize every tile to, keeping its aspect ratio if --width is not given")
+                .value_parser(value_parser!(u32).range(1..)),
+        )
+        .arg(
+            Arg::new("columns")
+                .long("columns")
+                .value_name("N")
+                .help("Number of tiles per row [default: a roughly square sheet]")
+                .value_parser(value_parser!(u32).range(1..)),
+        )
+        .arg(
+            Arg::new("spacing")
+                .long("spacing")
+                .value_name("PX")
+                .help("Space between the tiles in pixels")
+                .default_value("0")
+                .value_parser(value_parser!(u32)),
+        )
+        .arg(
+            Arg::new("background")
+                .long("background")
+                .value_name("COLOR")
+                .help("Color around the tiles, as #rrggbb, #rrggbbaa or transparent")
+                .default_value("transparent")
+                .value_parser(parse_color),
+        )
+        .arg(
+            Arg::new("map")
+                .long("map")
+                .value_name("FORMAT")
+                .help("Also write a map of the tile positions next to the sheet")
+                .value_parser(["json", "css"]),
+        )
+}
+
+/// Builds the `contact-sheet` subcommand.
+///
+/// The subcommand takes the following arguments:
+/// - `inputs` (required): Paths to the images to review.
+/// - `output` (required): Path of the sheet image (.png or .jpg).
+/// - `size` (optional): Width and height of each thumbnail cell (default: 160).
+/// - `columns` (optional): Number of thumbnails per row (default: a roughly square sheet).
+/// - `spacing` (optional): Space between and around the thumbnails in pixels (default: 8).
+/// - `background` (optional): Background color, `#rrggbb[aa]` or `transparent` (default: white).
+/// - `dimensions` (optional): Add the dimensions of each image to its label.
+/// - `date` (optional): Add the modification date of each file to its label.
+///
+/// # Returns
+///
+/// A `Command` struct representing the subcommand configuration.
+fn contact_sheet_command() -> Command {
+    Command::new("contact-sheet")
+        .about("Lays out thumbnails of several images in a grid, labelled with their file names")
+        .arg(
+            Arg::new("inputs")
+                .help("Paths to the images to review, in order")
+                .required(true)
+                .num_args(1..)
+                .value_parser(value_parser_for_path)
+                .index(1),
+        )
+        .arg(
+            Arg::new("output")
+                .short('o')
+                .long("output")
+                .value_name("FILE")
+                .help("Path of the sheet image (.png or .jpg)")
+                .required(true)
+                .value_parser(value_parser!(PathBuf)),
+        )
+        .arg(
+            Arg::new("size")
+                .long("size")
+                .value_name("PX")
+                .help("Width and height of each thumbnail; labels are scaled up from 320 pixels")
+                .default_value("160")
+                .value_parser(value_parser!(u32).range(16..=4096)),
+        )
+        .arg(
+            Arg::new("columns")
+                .long("columns")
+                .value_name("N")
+                .help("Number of thumbnails per row [default: a roughly square sheet]")
+                .value_parser(value_parser!(u32).range(1..)),
+        )
+        .arg(
+            Arg::new("spacing")
+                .long("spacing")
+                .value_name("PX")
+                .help("Space between and around the thumbnails in pixels")
+                .default_value("8")
+                .value_parser(value_parser!(u32)),
+        )
+        .arg(
+            Arg::new("background")
+                .long("background")
+                .value_name("COLOR")
+                .help("Background color, as #rrggbb, #rrggbbaa or transparent")
+                .default_value("#ffffff")
+                .value_parser(parse_color),
+        )
+        .arg(
+            Arg::new("dimensions")
+                .long("dimensions")
+                .help("Add the dimensions of each image to its label")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("date")
+                .long("date")
+                .help("Add the modification date of each file to its label")
+                .action(ArgAction::SetTrue),
+        )
+}
+
+/// Builds the `join` subcommand.
+///
+/// The subcommand takes the following arguments:
+/// - `inputs` (required): Paths to the images to concatenate.
+/// - `output` (required): Path of the joined image (.png or .jpg).
+/// - `direction` (optional): Concatenate in a row (horizontal, default) or a column (vertical).
+/// - `edge` (optional): Common height (in a row) or width (in a column) to resize the images to.
+/// - `keep-size` (optional): Keep the images at their size instead of resizing them.
+/// - `align` (optional): Where smaller images are placed with `keep-size` (start, center or end).
+/// - `gap` (optional): Space between the images in pixels (default: 0).
+/// - `background` (optional): Color of the gaps, `#rrggbb[aa]` or `transparent`.
+///
+/// # Returns
+///
+/// A `Command` struct representing the subcommand configuration.
+fn join_command() -> Command {
+    Command::new("join")
+        .about("Concatenates several images horizontally or vertically, e.g. to stitch screenshots")
+        .arg(
+            Arg::new("inputs")
+                .help("Paths to the images to concatenate, in order")
+                .required(true)
+                .num_args(1..)
+                .value_parser(value_parser_for_path)
+                .index(1),
+        )
+        .arg(
+            Arg::new("output")
+                .short('o')
+                .long("output")
+                .value_name("FILE")
+                .help("Path of the joined image (.png or .jpg)")
+                .required(true)
+                .value_parser(value_parser!(PathBuf)),
+        )
+        .arg(
+            Arg::new("direction")
+                .long("direction")
+                .help("Concatenate the images in a row or in a column")
+                .default_value("horizontal")
+                .value_parser(["horizontal", "vertical"]),
+        )
+        .arg(
+            Arg::new("edge")
+                .long("edge")
+                .value_name("PX")
+                .help("Height (in a row) or width (in a column) to resize every image to [default: the smallest one]")
+                .value_parser(value_parser!(u32).range(1..)),
+        )
+        .arg(
+            Arg::new("keep-size")
+                .long("keep-size")
+                .help("Keep the images at their size instead of resizing them to a common edge")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("edge"),
+        )
+        .arg(
+            Arg::new("align")
+                .long("align")
+                .help("Where to place images smaller than the others with --keep-size")
+                .default_value("center")
+                .value_parser(["start", "center", "end"]),
+        )
+        .arg(
+            Arg::new("gap")
+                .long("gap")
+                .value_name("PX")
+                .help("Space between the images in pixels")
+                .default_value("0")
+                .value_parser(value_parser!(u32)),
+        )
+        .arg(
+            Arg::new("background")
+                .long("background")
+                .value_name("COLOR")
+                .help("Color of the gaps, as #rrggbb, #rrggbbaa or transparent")
+                .default_value("transparent")
+                .value_parser(parse_color),
+        )
+}
+
+/// Builds the `split` subcommand.
+///
+/// The subcommand takes the following arguments:
+/// - `input` (required): Path to the image to split.
+/// - `output` (optional): Folder the tiles are written to (default: `<input name>_tiles`
+///   next to the input).
+/// - `grid` (required unless `tile-size`): Number of columns and rows, e.g. `3x2`.
+/// - `tile-size` (required unless `grid`): Size of the tiles in pixels, e.g. `256x256`.
+/// - `overlap` (optional): Pixels each tile extends into its right and bottom neighbors.
+/// - `edges` (optional): What to do with smaller tiles at the right and bottom with `tile-size`
+///   (keep, pad or drop).
+/// - `background` (optional): Color used to pad edge tiles, `#rrggbb[aa]` or `transparent`.
+/// - `format` (optional): Format of the tiles (default: the input format if supported, else PNG).
+///
+/// # Returns
+///
+/// A `Command` struct representing the subcommand configuration.
+fn split_command() -> Command {
+    Command::new("split")
+        .about("Cuts an image into a grid of tiles, saved with their row and column in the name")
+        .arg(
+            Arg::new("input")
+                .help("Path to the image to split")
+                .required(true)
+                .value_parser(value_parser_for_path)
+                .index(1),
+        )
+        .arg(
+            Arg::new("output")
+                .short('o')
+                .long("output")
+                .value_name("DIR")
+                .help("Folder to write the tiles to [default: <input name>_tiles next to the input]")
+                .value_parser(value_parser!(PathBuf)),
+        )
+        .arg(
+            Arg::new("grid")
+                .long("grid")
+                .value_name("COLUMNSxROWS")
+                .help("Number of columns and rows to split the image evenly into, e.g. 3x2")
+                .required_unless_present("tile-size")
+                .conflicts_with("tile-size")
+                .value_parser(parse_pair),
+        )
+        .arg(
+            Arg::new("tile-size")
+                .long("tile-size")
+                .value_name("WIDTHxHEIGHT")
+                .help("Size of the tiles in pixels, e.g. 256x256 or 256")
+                .value_parser(parse_pair),
+        )
+        .arg(
+            Arg::new("overlap")
+                .long("overlap")
+                .value_name("PX")
+                .help("Pixels each tile extends into its right and bottom neighbors")
+                .default_value("0")
+                .value_parser(value_parser!(u32)),
+        )
+        .arg(
+            Arg::new("edges")
+                .long("edges")
+                .help("What to do with the smaller tiles of the last column and row with --tile-size")
+                .default_value("keep")
+                .value_parser(["keep", "pad", "drop"]),
+        )
+        .arg(
+            Arg::new("background")
+                .long("background")
+                .value_name("COLOR")
+                .help("Color used to pad edge tiles with --edges pad, as #rrggbb, #rrggbbaa or transparent")
+                .default_value("transparent")
+                .value_parser(parse_color),
+        )
+        .arg(
+            Arg::new("format")
+                .short('F')
+                .long("format")
+                .help("Format of the tiles [default: the input format if JPEG or PNG, else PNG]")
+                .value_parser(["jpeg", "png"]),
+        )
+}
+
+/// Builds the `pyramid` subcommand.
+///
+/// The subcommand takes the following arguments:
+/// - `input` (required): Path to the image to generate the pyramid from.
+/// - `output` (optional): Folder the pyramid is written to (default: `<input name>_pyramid`
+///   next to the input).
+/// - `layout` (optional): File layout of the tiles (dzi or zxy, default: dzi).
+/// - `tile-size` (optional): Width and height of the tiles (default: 254 for DZI, 256 for z/x/y).
+/// - `overlap` (optional): Pixels shared by neighboring DZI tiles (default: 1).
+/// - `format` (optional): Format of the tiles (default: jpeg).
+/// - `quality` (optional): Quality of JPEG tiles (1-100).
+///
+/// # Returns
+///
+/// A `Command` struct representing the subcommand configuration.
+fn pyramid_command() -> Command {
+    Command::new("pyramid")
+        .about("Generates a multi-resolution tile pyramid (DZI or z/x/y) for deep-zoom viewers")
+        .arg(
+            Arg::new("input")
+                .help("Path to the image to generate the pyramid from")
+                .required(true)
+                .value_parser(value_parser_for_path)
+                .index(1),
+        )
+        .arg(
+            Arg::new("output")
+                .short('o')
+                .long("output")
+                .value_name("DIR")
+                .help("Folder to write the pyramid to [default: <input name>_pyramid next to the input]")
+                .value_parser(value_parser!(PathBuf)),
+        )
+        .arg(
+            Arg::new("layout")
+                .long("layout")
+                .help("File layout of the tiles: a DZI descriptor and folders, or z/x/y folders")
+                .default_value("dzi")
+                .value_parser(["dzi", "zxy"]),
+        )
+        .arg(
+            Arg::new("tile-size")
+                .long("tile-size")
+                .value_name("PX")
+                .help("Width and height of the tiles, without overlap [default: 254 for dzi, 256 for zxy]")
+                .value_parser(value_parser!(u32).range(1..=8192)),
+        )
+        .arg(
+            Arg::new("overlap")
+                .long("overlap")
+                .value_name("PX")
+                .help("Pixels shared by neighboring tiles of a DZI pyramid")
+                .default_value("1")
+                .value_parser(value_parser!(u32)),
+        )
+        .arg(
+            Arg::new("format")
+                .short('F')
+                .long("format")
+                .help("Format of the tiles")
+                .default_value("jpeg")
+                .value_parser(["jpeg", "png"]),
+        )
+        .arg(
+            Arg::new("quality")
+                .long("quality")
+                .help("Quality of JPEG tiles, from 1 to 100")
+                .value_parser(value_parser!(u8).range(1..=100)),
+        )
+}
+
+/// Builds the `generate` subcommand.
+///
+/// The subcommand takes the following arguments:
+/// - `pattern` (required): The pattern to draw (gradient, checkerboard, noise, solid or bars).
+/// - `output` (required): Path of the generated image, whose extension selects the format.
+/// - `width` (optional): Width of the image (default: 1920).
+/// - `height` (optional): Height of the image (default: 1080).
+/// - `color` (optional): First color of the gradient and checkerboard, and the solid color.
+/// - `color2` (optional): Second color of the gradient and checkerboard.
+/// - `cell-size` (optional): Size of the checkerboard cells (default: 32).
+/// - `seed` (optional): Seed of the noise (default: 0).
+/// - `quality` (optional): Quality of JPEG outputs (1-100).
+///
+/// # Returns
+///
+/// A `Command` struct representing the subcommand configuration.
+fn generate_command() -> Command {
+    Command::new("generate")
+        .about("Generates a test image: a gradient, checkerboard, noise, solid color or SMPTE color bars")
+        .arg(
+            Arg::new("pattern")
+                .help("The pattern to draw")
+                .required(true)
+                .value_parser(["gradient", "checkerboard", "noise", "solid", "bars"])
+                .index(1),
+        )
+        .arg(
+            Arg::new("output")
+                .help("Path of the generated image (.png or .jpg)")
+                .required(true)
+                .value_parser(value_parser!(PathBuf))
+                .index(2),
+        )
+        .arg(
+            Arg::new("width")
+                .short('W')
+                .long("width")
+                .help("Width of the image")
+                .default_value("1920")
+                .value_parser(value_parser!(u32).range(1..)),
+        )
+        .arg(
+            Arg::new("height")
+                .short('H')
+                .long("height")
+                .help("Height of the image")
+                .default_value("1080")
+                .value_parser(value_parser!(u32).range(1..)),
+        )
+        .arg(
+            Arg::new("color")
+                .long("color")
+                .value_name("COLOR")
+                .help("First color of the gradient and checkerboard, and the color of solid images, as #rrggbb, #rrggbbaa or transparent")
+                .default_value("#000000")
+                .value_parser(parse_color),
+        )
+        .arg(
+            Arg::new("color2")
+                .long("color2")
+                .value_name("COLOR")
+                .help("Second color of the gradient and checkerboard")
+                .default_value("#ffffff")
+                .value_parser(parse_color),
+        )
+        .arg(
+            Arg::new("cell-size")
+                .long("cell-size")
+                .value_name("PX")
+                .help("Width and height of the checkerboard cells")
+                .default_value("32")
+                .value_parser(value_parser!(u32).range(1..)),
+        )
+        .arg(
+            Arg::new("seed")
+                .long("seed")
+                .help("Seed of the noise; the same seed always gives the same image")
+                .default_value("0")
+                .value_parser(value_parser!(u64)),
+        )
+        .arg(
+            Arg::new("quality")
+                .long("quality")
+                .help("Quality of JPEG outputs, from 1 to 100")
+                .value_parser(value_parser!(u8).range(1..=100)),
+        )
+}
+
+/// Builds the `daemon` subcommand.
+///
+/// The subcommand takes the following arguments:
+/// - `socket` (required): Path of the Unix domain socket to listen on.
+/// - The scheduler arguments of `scheduler_args`.
+/// - The limit arguments of `limit_args`.
+///
+/// # Returns
+///
+/// A `Command` struct representing the subcommand configuration.
+fn daemon_command() -> Command {
+    Command::new("daemon")
+        .about("Keeps running and resizes images sent as JSON lines on a Unix domain socket")
+        .arg(
+            Arg::new("socket")
+                .help("Path of the Unix domain socket to listen on")
+                .required(true)
+                .value_parser(value_parser!(PathBuf))
+                .index(1),
+        )
+        .args(scheduler_args())
+        .args(limit_args())
+}
+
+/// Builds the `grpc` subcommand.
+///
+/// The subcommand takes the following arguments:
+/// - `address` (optional): Address to listen on (default: 127.0.0.1:50051).
+/// - `root` (optional): Directory requests can read images from instead of sending them.
+/// - The scheduler arguments of `scheduler_args`.
+/// - The limit arguments of `limit_args`. Without `max-pixels`, the server rejects images
+///   above `grpc::DEFAULT_MAX_PIXELS`.
+///
+/// # Returns
+///
+/// A `Command` struct representing the subcommand configuration.
+fn grpc_command() -> Command {
+    Command::new("grpc")
+        .about("Serves the gRPC ResizeService (needs a build with the `grpc` feature)")
+        .arg(
+            Arg::new("address")
+                .help("Address to listen on")
+                .default_value("127.0.0.1:50051")
+                .value_parser(value_parser!(SocketAddr))
+                .index(1),
+        )
+        .arg(
+            Arg::new("root")
+                .long("root")
+                .value_name("DIR")
+                .help("Let requests name images in this directory instead of sending them")
+                .value_parser(value_parser!(PathBuf)),
+        )
+        .args(scheduler_args())
+        .args(limit_args())
+}
+
+/// Builds the arguments shared by the `daemon` and `grpc` subcommands, which configure
+/// their `Scheduler`:
+/// - `max-concurrent` (optional): Maximum number of images resized at once (default: one
+///   per CPU core).
+/// - `queue-timeout` (optional): How long a request waits for a free slot (default: 30s).
+/// - `rate-limit` (optional): Maximum number of requests of each client per period, as
+///   `N/DURATION` (e.g. `60/1m`).
+///
+/// # Returns
+///
+/// The arguments, to add with `Command::args`.
+fn scheduler_args() -> [Arg; 3] {
+    [
+        Arg::new("max-concurrent")
+            .long("max-concurrent")
+            .value_name("N")
+            .help("Maximum number of images resized at once (default: one per CPU core)")
+            .value_parser(value_parser!(u32).range(1..)),
+        Arg::new("queue-timeout")
+            .long("queue-timeout")
+            .value_name("DURATION")
+            .help("Fail requests waiting longer than this for a free slot (e.g. 500ms, 30s, 2m)")
+            .default_value("30s")
+            .value_parser(value_parser_for_duration),
+        Arg::new("rate-limit")
+            .long("rate-limit")
+            .value_name("N/DURATION")
+            .help("Reject requests of a client beyond N per DURATION, allowing bursts of N (e.g. 60/1m)")
+            .value_parser(value_parser_for_rate_limit),
+    ]
+}
+
+/// Builds the arguments limiting the resources used by each image, shared by the main
+/// command and the `daemon` and `grpc` subcommands:
+/// - `max-pixels` (optional): Reject images with more pixels than this.
+/// - `max-memory` (optional): Memory budget per image (e.g. 512M, 2G).
+/// - `max-dimensions` (optional): Maximum width and height claimed by an image header.
+/// - `decode-timeout` (optional): Maximum time spent decoding an image.
+///
+/// # Returns
+///
+/// The arguments, to add with `Command::args`.
+fn limit_args() -> [Arg; 4] {
+    [
+        Arg::new("max-pixels")
+            .long("max-pixels")
+            .help("Reject images whose source has more pixels than this")
+            .value_parser(value_parser!(u64).range(1..)),
+        Arg::new("max-memory")
+            .long("max-memory")
+            .help("Memory budget per image (e.g. 512M, 2G).\nImages that would exceed it are resized in bands, or rejected if that is not enough.")
+            .value_parser(value_parser_for_size),
+        Arg::new("max-dimensions")
+            .long("max-dimensions")
+            .value_name("WxH")
+            .help("Reject images whose header claims a larger width or height (e.g. 20000x20000)")
+            .value_parser(value_parser_for_dimensions),
+        Arg::new("decode-timeout")
+            .long("decode-timeout")
+            .value_name("DURATION")
+            .help("Give up decoding an image after this long (e.g. 500ms, 30s, 2m)")
+            .value_parser(value_parser_for_duration),
+    ]
+}
+
+/// Builds the `sequence` subcommand.
+///
+/// The subcommand takes the following arguments:
+/// - `input` (required): Pattern of the input frames, such as `frame_%04d.png`, or `-` to
+///   read raw frames from stdin.
+/// - `output` (required): Pattern of the output frames, or `-` to write raw frames to stdout.
+/// - `width` (optional): Width of the output frames. Required if `height` not provided.
+/// - `height` (optional): Height of the output frames. Required if `width` not provided.
+/// - `start-number` (optional): Number of the first input frame (default: the first found
+///   from 0 to 4).
+/// - `size` (optional): Width and height of raw input frames. Required with `-`.
+/// - `pix-fmt` (optional): Pixel format of raw frames (gray, ya8, rgb24 or rgba, default:
+///   rgb24).
+/// - `even` (optional): Round both sides of the output to even numbers.
+/// - `threads` (optional): Number of threads used to resize each frame.
+/// - `quality` (optional): Quality of JPEG output frames (1-100).
+///
+/// # Returns
+///
+/// A `Command` struct representing the subcommand configuration.
+fn sequence_command() -> Command {
+    Command::new("sequence")
+        .about("Resizes a numbered image sequence (frame_%04d.png) or raw frames piped from a video tool")
+        .arg(
+            Arg::new("input")
+                .help("Pattern of the input frames, such as frame_%04d.png, or - to read raw frames from stdin")
+                .required(true)
+                .value_parser(value_parser_for_frames)
+                .index(1),
+        )
+        .arg(
+            Arg::new("output")
+                .help("Pattern of the output frames (.png or .jpg), or - to write raw frames to stdout")
+                .required(true)
+                .value_parser(value_parser_for_frames)
+                .index(2),
+        )
+        .arg(
+            Arg::new("width")
+                .short('W')
+                .long("width")
+                .help("Width of the output frames")
+                .required_unless_present("height")
+                .value_parser(value_parser!(u32).range(1..)),
+        )
+        .arg(
+            Arg::new("height")
+                .short('H')
+                .long("height")
+                .help("Height of the output frames")
+                .value_parser(value_parser!(u32).range(1..)),
+        )
+        .arg(
+            Arg::new("start-number")
+                .long("start-number")
+                .value_name("N")
+                .help("Number of the first input frame [default: the first found from 0 to 4]")
+                .value_parser(value_parser!(u64)),
+        )
+        .arg(
+            Arg::new("size")
+                .long("size")
+                .value_name("WxH")
+                .help("Width and height of the raw input frames, required when reading from stdin")
+                .value_parser(parse_pair),
+        )
+        .arg(
+            Arg::new("pix-fmt")
+                .long("pix-fmt")
+                .help("Pixel format of the raw frames, as named by ffmpeg")
+                .default_value("rgb24")
+                .value_parser(["gray", "ya8", "rgb24", "rgba"]),
+        )
+        .arg(
+            Arg::new("even")
+                .long("even")
+                .help("Round both sides of the output to even numbers, as most video encoders require")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("threads")
+                .long("threads")
+                .help("Number of threads used to resize each frame (0 = one per CPU core)")
+                .default_value("1")
+                .value_parser(value_parser!(usize)),
+        )
+        .arg(
+            Arg::new("quality")
+                .long("quality")
+                .help("Quality of JPEG output frames, from 1 to 100")
+                .value_parser(value_parser!(u8).range(1..=100)),
+        )
+}
+
+/// Builds the `texture` subcommand.
+///
+/// The subcommand takes the following arguments:
+/// - `input` (required): Path to the image to make a texture of.
+/// - `output` (optional): Path of the texture, whose extension (`.dds` or `.ktx2`) selects
+///   the container (default: `<input name>.dds` next to the input).
+/// - `width` (optional): Width of the base level (default: the width of the input).
+/// - `height` (optional): Height of the base level (default: the height of the input).
+/// - `compression` (optional): Pixel format (none, bc1 or bc3, default: none). BC1 and BC3
+///   need the `bc` feature.
+/// - `no-mipmaps` (optional): Only write the base level.
+/// - `linear` (optional): Tag the texture as linear data instead of sRGB colors.
+///
+/// # Returns
+///
+/// A `Command` struct representing the subcommand configuration.
+fn texture_command() -> Command {
+    Command::new("texture")
+        .about("Bakes a DDS or KTX2 texture with a full mipmap chain for game engines")
+        .arg(
+            Arg::new("input")
+                .help("Path to the image to make a texture of")
+                .required(true)
+                .value_parser(value_parser_for_path)
+                .index(1),
+        )
+        .arg(
+            Arg::new("output")
+                .short('o')
+                .long("output")
+                .value_name("FILE")
+                .help("Path of the texture, ending in .dds or .ktx2 [default: <input name>.dds next to the input]")
+                .value_parser(value_parser!(PathBuf)),
+        )
+        .arg(
+            Arg::new("width")
+                .short('W')
+                .long("width")
+                .help("Width of the base level [default: the width of the input]")
+                .value_parser(value_parser!(u32).range(1..)),
+        )
+        .arg(
+            Arg::new("height")
+                .short('H')
+                .long("height")
+                .help("Height of the base level [default: the height of the input]")
+                .value_parser(value_parser!(u32).range(1..)),
+        )
+        .arg(
+            Arg::new("compression")
+                .long("compression")
+                .help("Pixel format: 8-bit RGBA, or BC1 (opaque) or BC3 (with alpha) blocks, which need the bc feature")
+                .default_value("none")
+                .value_parser(["none", "bc1", "bc3"]),
+        )
+        .arg(
+            Arg::new("no-mipmaps")
+                .long("no-mipmaps")
+                .help("Only write the base level, without the mipmap chain")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("linear")
+                .long("linear")
+                .help("Tag the texture as linear data, such as a normal or roughness map, instead of sRGB colors")
+                .action(ArgAction::SetTrue),
+        )
+}
+
+/// Determines the output path for the resized image.
+///
+/// # Arguments
+///
+/// * `input` - A reference to the `Path` of the input image.
+/// * `output` - An optional `String` specifying the desired output path.
+/// * `base` - What a relative `output` is relative to. An `output` naming an existing
+///   directory, or ending with a path separator with `create_dirs`, is always taken as
+///   given, relative to the current working directory; without `output`, the output is
+///   written next to the input.
+/// * `create_dirs` - Whether to create the directory of the output if it does not exist,
+///   as with `--mkdir`. An output ending with a path separator is then a directory.
+///
+/// # Returns
+///
+/// A `Result` containing either the determined `PathBuf` for the output or an error.
+pub fn determine_output_path(
+    input: &Path,
+    output: Option<&String>,
+    base: OutputBase,
+    create_dirs: bool,
+) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    let parent = input.parent().unwrap_or(Path::new(""));
+    let stem = input.file_stem().unwrap_or(OsStr::new("output"));
+    let extension = input.extension().unwrap_or(OsStr::new("jpeg"));
+    let new_stem = PathBuf::from(format!("{}_resized", stem.to_string_lossy()));
+
+    let output_path = match output {
+        Some(p) => {
+            // check if given output is a directory. If yes, return directory/new_stem.jpeg
+            if Path::new(&p).is_dir() || (create_dirs && p.ends_with(std::path::is_separator)) {
+                paths::join(Path::new(p), &new_stem).with_extension(extension)
+            } else {
+                let validated_output = validate_output_path(p, create_dirs)?;
+                let path_new = Path::new(&validated_output);
+                let path_new_buf = match path_new.extension() {
+                    Some(_) => path_new.to_path_buf(),
+                    None => path_new.with_extension(extension),
+                };
+                match base {
+                    _ if path_new_buf.is_absolute() => path_new_buf,
+                    OutputBase::Input => paths::join(parent, &path_new_buf),
+                    OutputBase::Cwd => path_new_buf,
+                }
+            }
+        }
+        None => paths::join(parent, &new_stem).with_extension(extension),
+    };
+    if create_dirs {
+        if let Some(dir) = output_path
+            .parent()
+            .filter(|dir| !dir.as_os_str().is_empty())
+        {
+            fs::create_dir_all(paths::for_filesystem(dir))
+                .map_err(|e| format!("Failed to create the output directory {:?}: {}", dir, e))?;
+        }
+    }
+    Ok(output_path)
+}
+
+/// Validates the provided output path.
+///
+/// The path is returned as given, so that extended-length (`\\?\C:\...`) and UNC
+/// (`\\server\share\...`) paths on Windows are kept intact.
 ///
 /// # Arguments
 ///
 /// * `path` - A reference to the `String` containing the output path to validate.
+/// * `create_dirs` - Whether a missing output directory will be created, in which case it
+///   is not an error.
 ///
 /// # Returns
 ///
 /// A `Result` containing either the validated output path as a `String` or an error.
-fn validate_output_path(path: &String) -> Result<String, Box<dyn std::error::Error>> {
+fn validate_output_path(
+    path: &String,
+    create_dirs: bool,
+) -> Result<String, Box<dyn std::error::Error>> {
     let parent = Path::new(&path).parent().unwrap_or(Path::new(""));
 
-    if !parent.is_dir() && parent != Path::new("") {
-        return Err(format!("The given output directory {:?} cannot be found.", parent).into());
+    if !create_dirs && !paths::for_filesystem(parent).is_dir() && parent != Path::new("") {
+        return Err(format!(
+            "The given output directory {:?} cannot be found. Use --mkdir to create it.",
+            parent
+        )
+        .into());
     };
 
-    let stem = Path::new(&path).file_stem().unwrap_or(OsStr::new("output"));
     let extension = Path::new(&path).extension().unwrap_or(OsStr::new(""));
 
+    // `.JPG` is as valid as `.jpg`; the case is kept in the output name
+    let encoders = EncoderRegistry::builtin();
     match extension.to_str() {
-        Some("jpeg") | Some("jpg") | Some("png") | Some("") => {
-            let validated_path = parent.join(stem).with_extension(extension);
-            Ok(validated_path.to_string_lossy().to_string())
+        Some("") => Ok(path.clone()),
+        Some(extension)
+            if format::resolve(extension).is_some_and(|format| encoders.supports(format)) =>
+        {
+            Ok(path.clone())
+        }
+        _ => {
+            let names: Vec<String> = OutputFormat::all(encoders)
+                .iter()
+                .map(OutputFormat::to_string)
+                .collect();
+            Err(format!(
+                "You need to specify a valid extension, either {} or no extension.",
+                names.join(", ")
+            )
+            .into())
+        }
+    }
+}
+
+/// Checks that the extension of an `--output` file matches `--format`.
+///
+/// Outputs whose name is not given with an extension, such as directories, take the
+/// extension of the format and are not checked.
+///
+/// # Arguments
+///
+/// * `output` - The `--output` path, if any.
+/// * `format` - The `--format` value, if any.
+/// * `fix` - Whether a mismatching extension is replaced by the one of the format, as with
+///   `--fix-extension`, instead of being an error.
+///
+/// # Errors
+///
+/// Returns an error if the extension does not match the format and `fix` is false.
+pub fn check_output_extension(
+    output: Option<&String>,
+    format: Option<&String>,
+    fix: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let (Some(output), Some(format)) = (output, format) else {
+        return Ok(());
+    };
+    let path = Path::new(output);
+    let Some(extension) = path.extension().and_then(OsStr::to_str) else {
+        return Ok(());
+    };
+    if path.is_dir() || format::resolve(extension) == format::resolve(format) {
+        return Ok(());
+    }
+
+    if fix {
+        warn!(
+            "The extension of {:?} does not match the {} format, it is replaced",
+            path, format
+        );
+        Ok(())
+    } else {
+        Err(format!(
+            "The extension of {:?} does not match the {} format. Use --fix-extension to replace it.",
+            path, format
+        )
+        .into())
+    }
+}
+
+/// Returns the output format requested on the command line: the `--format` value, or else
+/// the extension of the `--output` file. None if the format follows each input.
+fn requested_format(matches: &ArgMatches) -> Option<ImageFormat> {
+    match matches.get_one::<String>("format") {
+        Some(format) => format::resolve(format),
+        None => matches
+            .get_one::<String>("output")
+            .map(Path::new)
+            .filter(|path| !path.is_dir())
+            .and_then(|path| path.extension())
+            .and_then(OsStr::to_str)
+            .and_then(format::resolve),
+    }
+}
+
+/// Checks that the encoder options given on the command line apply to the requested output
+/// format.
+///
+/// # Errors
+///
+/// Returns an error if:
+/// - `--quality` is given for WebP outputs, which are always lossless.
+/// - `--palette`, `--dither` or `--png-compression` is given for outputs that are not PNG.
+pub fn check_encoder_options(matches: &ArgMatches) -> Result<(), String> {
+    let format = requested_format(matches);
+    if format == Some(ImageFormat::WebP) && matches.contains_id("quality") {
+        return Err("--quality does not apply to WebP outputs, which are lossless.".to_string());
+    }
+    if format.is_some_and(|format| format != ImageFormat::Png) {
+        let given = ["palette", "dither", "png-compression"]
+            .into_iter()
+            .find(|id| matches.value_source(id) == Some(ValueSource::CommandLine));
+        if let Some(id) = given {
+            return Err(format!("--{} only applies to PNG outputs.", id));
         }
-        _ => Err("You need to specify a valid extension, either jpeg, png or no extension.".into()),
+    }
+    Ok(())
+}
+
+/// Returns whether the command line only gives inputs, as when image files are dropped onto
+/// the binary. Only then are the size and format asked interactively.
+pub fn only_inputs_given(matches: &ArgMatches) -> bool {
+    matches.ids().all(|id| {
+        id == "input" || matches.value_source(id.as_str()) != Some(ValueSource::CommandLine)
+    })
+}
+
+/// Parses the frames of the `sequence` subcommand: `-` for raw frames on stdin or stdout
+/// (`None`), or a frame pattern.
+///
+/// # Errors
+///
+/// Returns an error message if the value is neither `-` nor a valid frame pattern.
+fn value_parser_for_frames(s: &str) -> Result<Option<FramePattern>, String> {
+    match s {
+        "-" => Ok(None),
+        pattern => pattern.parse().map(Some),
     }
 }
 
@@ -175,6 +2172,236 @@ fn value_parser_for_path(p: &str) -> Result<PathBuf, Error> {
     Ok(path)
 }
 
+/// Custom value parser for the inputs of the resize command.
+///
+/// Accepts the same paths as `value_parser_for_path`, and also ZIP and tar archives, whose
+/// images are resized, and glob patterns, expanded with `select::expand`.
+///
+/// # Errors
+///
+/// Returns an error if the path is neither a supported archive, a pattern nor a valid image
+/// file.
+fn value_parser_for_input(p: &str) -> Result<PathBuf, Error> {
+    let path = PathBuf::from(p);
+    if path.is_file() && ArchiveFormat::from_path(&path).is_some() {
+        return Ok(path);
+    }
+    // Patterns are expanded once all the arguments are parsed
+    if !path.exists() && select::is_pattern(p) {
+        return Ok(path);
+    }
+    value_parser_for_path(p)
+}
+
+/// Custom value parser for input image formats.
+///
+/// Accepts a format name or file extension, such as `jpeg`, `jpg`, `png` or `webp`
+/// (case-insensitive), or its MIME type.
+///
+/// # Arguments
+///
+/// * `s` - A string slice containing the format to parse.
+///
+/// # Returns
+///
+/// A `Result` containing either the `ImageFormat` or an error message.
+fn value_parser_for_format(s: &str) -> Result<ImageFormat, String> {
+    format::resolve(s).ok_or_else(|| format!("'{}' is not a known image format", s))
+}
+
+/// Custom value parser for the output format, given by name or MIME type.
+///
+/// # Arguments
+///
+/// * `s` - A string slice containing the format to parse, e.g. `jpg` or `image/webp`.
+///
+/// # Returns
+///
+/// A `Result` containing either the name of a format of the built-in encoders (see
+/// `OutputFormat`) or an error message listing them.
+fn value_parser_for_output_format(s: &str) -> Result<String, String> {
+    s.parse::<OutputFormat>().map(|format| format.to_string())
+}
+
+/// Custom value parser for maximum dimensions.
+///
+/// Accepts a width and a height separated by `x` (e.g. `20000x20000`).
+///
+/// # Arguments
+///
+/// * `s` - A string slice containing the dimensions to parse.
+///
+/// # Returns
+///
+/// A `Result` containing either the width and height or an error message.
+fn value_parser_for_dimensions(s: &str) -> Result<(u32, u32), String> {
+    let invalid = || {
+        format!(
+            "'{}' is not valid, expected WIDTHxHEIGHT (e.g. 20000x20000)",
+            s
+        )
+    };
+    let (width, height) = s.trim().to_lowercase().split_once('x').map_or_else(
+        || Err(invalid()),
+        |(w, h)| Ok((w.trim().to_string(), h.trim().to_string())),
+    )?;
+
+    match (width.parse::<u32>(), height.parse::<u32>()) {
+        (Ok(w), Ok(h)) if w > 0 && h > 0 => Ok((w, h)),
+        _ => Err(invalid()),
+    }
+}
+
+/// Custom value parser for durations.
+///
+/// Accepts a number followed by `ms`, `s` or `m`. A plain number is a number of seconds.
+///
+/// # Arguments
+///
+/// * `s` - A string slice containing the duration to parse (e.g. `30s`).
+///
+/// # Returns
+///
+/// A `Result` containing either the `Duration` or an error message.
+fn value_parser_for_duration(s: &str) -> Result<Duration, String> {
+    let s = s.trim();
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+
+    let number: u64 = number
+        .parse()
+        .map_err(|_| format!("'{}' is not a valid duration", s))?;
+    match unit.to_lowercase().as_str() {
+        "ms" => Ok(Duration::from_millis(number)),
+        "" | "s" => Ok(Duration::from_secs(number)),
+        "m" => Ok(Duration::from_secs(number * 60)),
+        _ => Err(format!("'{}' has an unknown duration unit '{}'", s, unit)),
+    }
+}
+
+/// Custom value parser for ages, which are usually much longer than the durations accepted
+/// by `value_parser_for_duration`.
+///
+/// Accepts a number followed by `h` or `d`, or any duration accepted by
+/// `value_parser_for_duration`.
+///
+/// # Arguments
+///
+/// * `s` - A string slice containing the age to parse (e.g. `30d`).
+///
+/// # Returns
+///
+/// A `Result` containing either the `Duration` or an error message.
+fn value_parser_for_age(s: &str) -> Result<Duration, String> {
+    let s = s.trim();
+    let hours = match s.to_lowercase() {
+        lower if lower.ends_with('h') => 1,
+        lower if lower.ends_with('d') => 24,
+        _ => return value_parser_for_duration(s),
+    };
+    s[..s.len() - 1]
+        .parse::<u64>()
+        .ok()
+        .and_then(|number| number.checked_mul(hours * 3600))
+        .map(Duration::from_secs)
+        .ok_or_else(|| format!("'{}' is not a valid age", s))
+}
+
+/// Custom value parser for rate limits.
+///
+/// Accepts a number of requests and a duration accepted by `value_parser_for_duration`,
+/// separated by `/`. Both must be greater than zero.
+///
+/// # Arguments
+///
+/// * `s` - A string slice containing the rate limit to parse (e.g. `60/1m`).
+///
+/// # Returns
+///
+/// A `Result` containing either the `RateLimit` or an error message.
+fn value_parser_for_rate_limit(s: &str) -> Result<RateLimit, String> {
+    let invalid = || format!("'{}' is not valid, expected N/DURATION (e.g. 60/1m)", s);
+    let (requests, per) = s.trim().split_once('/').ok_or_else(invalid)?;
+    let requests: u32 = requests.trim().parse().map_err(|_| invalid())?;
+    let per = value_parser_for_duration(per)?;
+    if requests == 0 || per.is_zero() {
+        return Err(format!(
+            "'{}' is not valid, the number of requests and the duration must be greater than zero",
+            s
+        ));
+    }
+    Ok(RateLimit { requests, per })
+}
+
+/// Custom value parser for percentages.
+///
+/// Accepts a number from 0 to 100, optionally followed by `%`.
+///
+/// # Arguments
+///
+/// * `s` - A string slice containing the percentage to parse (e.g. `10%`).
+///
+/// # Returns
+///
+/// A `Result` containing either the percentage or an error message.
+fn value_parser_for_percent(s: &str) -> Result<f32, String> {
+    let s = s.trim();
+    match s.strip_suffix('%').unwrap_or(s).trim().parse::<f32>() {
+        Ok(value) if (0.0..=100.0).contains(&value) => Ok(value),
+        _ => Err(format!("'{}' is not a percentage between 0 and 100", s)),
+    }
+}
+
+/// Custom value parser for numbers of megapixels.
+///
+/// # Arguments
+///
+/// * `s` - A string slice containing the number of megapixels to parse (e.g. `12` or `0.5`).
+///
+/// # Returns
+///
+/// A `Result` containing either the number of megapixels or an error message.
+fn value_parser_for_megapixels(s: &str) -> Result<f64, String> {
+    match s.trim().parse::<f64>() {
+        Ok(value) if value.is_finite() && value * 1_000_000.0 >= 1.0 => Ok(value),
+        _ => Err(format!("'{}' is not a positive number of megapixels", s)),
+    }
+}
+
+/// Custom value parser for byte sizes.
+///
+/// Accepts a plain number of bytes or a number followed by a binary unit suffix
+/// (`K`, `M`, `G` or `T`, optionally followed by `B` or `iB`, case-insensitive).
+///
+/// # Arguments
+///
+/// * `s` - A string slice containing the size to parse (e.g. `512M`).
+///
+/// # Returns
+///
+/// A `Result` containing either the size in bytes or an error message.
+fn value_parser_for_size(s: &str) -> Result<u64, String> {
+    let s = s.trim();
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+
+    let number: u64 = number
+        .parse()
+        .map_err(|_| format!("'{}' is not a valid size", s))?;
+    let multiplier: u64 = match unit.to_lowercase().as_str() {
+        "" | "b" => 1,
+        "k" | "kb" | "kib" => 1 << 10,
+        "m" | "mb" | "mib" => 1 << 20,
+        "g" | "gb" | "gib" => 1 << 30,
+        "t" | "tb" | "tib" => 1 << 40,
+        _ => return Err(format!("'{}' has an unknown size unit '{}'", s, unit)),
+    };
+
+    number
+        .checked_mul(multiplier)
+        .ok_or_else(|| format!("'{}' is too large", s))
+}
+
 /// Checks if the given file path points to a valid image file.
 ///
 /// This function attempts to open the file, read its first 16 bytes,
@@ -199,7 +2426,9 @@ fn is_image(path: &Path) -> bool {
         return false;
     }
 
+    // TGA files have no signature, so their format is taken from their extension
     image::guess_format(&buffer)
+        .or_else(|_| ImageFormat::from_path(path))
         .map(|format| supported_image_formats().contains(&format))
         .unwrap_or(false)
 }
@@ -251,14 +2480,15 @@ mod tests {
         fn with_output() {
             let input = PathBuf::from("/path/to/input.jpg");
             let output = String::from("output.png");
-            let result = determine_output_path(&input, Some(&output)).unwrap();
+            let result =
+                determine_output_path(&input, Some(&output), OutputBase::Input, false).unwrap();
             assert_eq!(result, Path::new("/path/to/output.png"));
         }
 
         #[test]
         fn without_output() {
             let input = PathBuf::from("/path/to/input.jpg");
-            let result = determine_output_path(&input, None).unwrap();
+            let result = determine_output_path(&input, None, OutputBase::Input, false).unwrap();
             assert_eq!(result, Path::new("/path/to/input_resized.jpg"));
         }
 
@@ -268,8 +2498,13 @@ mod tests {
             let input = temp_dir.path().join("input.jpg");
             let output = temp_dir.path().join("output.png");
 
-            let result =
-                determine_output_path(&input, Some(&output.to_string_lossy().to_string())).unwrap();
+            let result = determine_output_path(
+                &input,
+                Some(&output.to_string_lossy().to_string()),
+                OutputBase::Input,
+                false,
+            )
+            .unwrap();
             assert_eq!(result, output);
             assert!(result.is_absolute());
             assert_eq!(result.extension().unwrap(), "png");
@@ -280,7 +2515,8 @@ mod tests {
         fn with_current_dir() {
             let input = PathBuf::from("/path/to/input.jpg");
             let output = String::from("./output.png");
-            let result = determine_output_path(&input, Some(&output)).unwrap();
+            let result =
+                determine_output_path(&input, Some(&output), OutputBase::Input, false).unwrap();
             assert_eq!(result, Path::new("/path/to/output.png"));
         }
 
@@ -302,7 +2538,12 @@ mod tests {
                     .trim()
                     .to_string();
 
-                let result = determine_output_path(Path::new(&shell_path), Some(&output));
+                let result = determine_output_path(
+                    Path::new(&shell_path),
+                    Some(&output),
+                    OutputBase::Input,
+                    false,
+                );
                 assert!(result.is_ok());
                 assert_eq!(
                     result.unwrap(),
@@ -310,6 +2551,46 @@ mod tests {
                 );
             }
         }
+
+        #[test]
+        fn relative_to_cwd() {
+            let input = PathBuf::from("/path/to/input.jpg");
+            let output = String::from("./output.png");
+            let result = determine_output_path(&input, Some(&output), OutputBase::Cwd, false);
+            assert_eq!(result.unwrap(), Path::new("./output.png"));
+            let output = String::from("output");
+            let result = determine_output_path(&input, Some(&output), OutputBase::Cwd, false);
+            assert_eq!(result.unwrap(), Path::new("output.jpg"));
+
+            let output = String::from("/srv/output.png");
+            let result = determine_output_path(&input, Some(&output), OutputBase::Cwd, false);
+            assert_eq!(result.unwrap(), Path::new("/srv/output.png"));
+            let result = determine_output_path(&input, None, OutputBase::Cwd, false);
+            assert_eq!(result.unwrap(), Path::new("/path/to/input_resized.jpg"));
+            assert_eq!("CWD".parse::<OutputBase>(), Ok(OutputBase::Cwd));
+        }
+
+        #[test]
+        fn creates_missing_directories() {
+            let temp_dir = create_temp_dir();
+            let input = temp_dir.path().join("input.jpg");
+            let output = temp_dir.path().join("web/2024/thumb.png");
+            let output = output.to_string_lossy().to_string();
+            assert!(
+                determine_output_path(&input, Some(&output), OutputBase::Input, false).is_err()
+            );
+
+            let result =
+                determine_output_path(&input, Some(&output), OutputBase::Input, true).unwrap();
+            assert_eq!(result, Path::new(&output));
+            assert!(temp_dir.path().join("web/2024").is_dir());
+
+            let dir = format!("{}/", temp_dir.path().join("raw/thumbs").display());
+            let result =
+                determine_output_path(&input, Some(&dir), OutputBase::Input, true).unwrap();
+            assert_eq!(result, temp_dir.path().join("raw/thumbs/input_resized.jpg"));
+            assert!(temp_dir.path().join("raw/thumbs").is_dir());
+        }
     }
 
     mod validate_output_path_tests {
@@ -320,7 +2601,7 @@ mod tests {
         fn valid_path() {
             let temp_dir = create_temp_dir();
             let path = temp_dir.path().join("output.jpeg");
-            let result = validate_output_path(&path.to_string_lossy().to_string());
+            let result = validate_output_path(&path.to_string_lossy().to_string(), false);
             assert!(result.is_ok());
             assert_eq!(result.unwrap(), path.to_string_lossy().to_string());
         }
@@ -328,18 +2609,26 @@ mod tests {
         #[test]
         fn invalid_extension() {
             let path = String::from("/tmp/output.gif");
-            let result = validate_output_path(&path);
+            let result = validate_output_path(&path, false);
             assert!(result.is_err());
             assert_eq!(
                 result.unwrap_err().to_string(),
-                "You need to specify a valid extension, either jpeg, png or no extension."
+                "You need to specify a valid extension, either jpeg, png, bmp, tga, webp, avif or no extension."
             );
         }
 
+        #[test]
+        fn uppercase_extension() {
+            for path in ["output.JPG", "output.JPEG", "output.Png", "output.WebP"] {
+                let result = validate_output_path(&path.to_string(), false);
+                assert_eq!(result.unwrap(), path);
+            }
+        }
+
         #[test]
         fn nonexistent_directory() {
             let path = String::from("/nonexistent/directory/output.jpeg");
-            let result = validate_output_path(&path);
+            let result = validate_output_path(&path, false);
             assert!(result.is_err());
             assert!(result.unwrap_err().to_string().contains("cannot be found"));
         }
@@ -356,12 +2645,54 @@ mod tests {
 
             let shell_path = String::from_utf8(output.stdout).unwrap().trim().to_string();
 
-            let result = validate_output_path(&shell_path);
+            let result = validate_output_path(&shell_path, false);
             assert!(result.is_ok());
             assert_eq!(result.unwrap(), path.to_string_lossy().to_string());
         }
     }
 
+    mod check_output_extension_tests {
+        use super::*;
+
+        #[test]
+        fn mismatching_extension() {
+            let output = String::from("out.jpg");
+            let result = check_output_extension(Some(&output), Some(&"png".to_string()), false);
+            assert!(result.unwrap_err().to_string().contains("--fix-extension"));
+            assert!(check_output_extension(Some(&output), Some(&"png".to_string()), true).is_ok());
+        }
+
+        #[test]
+        fn matching_or_missing_extension() {
+            let jpeg = String::from("jpeg");
+            for output in ["out.JPG", "out.jpeg", "out", "."] {
+                let output = output.to_string();
+                assert!(check_output_extension(Some(&output), Some(&jpeg), false).is_ok());
+            }
+            let output = String::from("out.png");
+            assert!(check_output_extension(Some(&output), None, false).is_ok());
+        }
+    }
+
+    mod value_parser_for_size_test {
+        use super::*;
+
+        #[test]
+        fn with_units() {
+            assert_eq!(value_parser_for_size("1024").unwrap(), 1024);
+            assert_eq!(value_parser_for_size("512K").unwrap(), 512 * 1024);
+            assert_eq!(value_parser_for_size("2mb").unwrap(), 2 * 1024 * 1024);
+            assert_eq!(value_parser_for_size("1GiB").unwrap(), 1 << 30);
+        }
+
+        #[test]
+        fn invalid_sizes() {
+            assert!(value_parser_for_size("G").is_err());
+            assert!(value_parser_for_size("12X").is_err());
+            assert!(value_parser_for_size("99999999999T").is_err());
+        }
+    }
+
     mod value_parser_for_path_test {
         use super::*;
         use std::fs::File;
@@ -404,4 +2735,197 @@ mod tests {
             }
         }
     }
+
+    mod value_parser_for_format_test {
+        use super::*;
+
+        #[test]
+        fn parses_names_and_extensions() {
+            assert_eq!(value_parser_for_format("jpg"), Ok(ImageFormat::Jpeg));
+            assert_eq!(value_parser_for_format("JPEG"), Ok(ImageFormat::Jpeg));
+            assert_eq!(value_parser_for_format("webp"), Ok(ImageFormat::WebP));
+            assert!(value_parser_for_format("doc").is_err());
+            assert_eq!(value_parser_for_format("image/GIF"), Ok(ImageFormat::Gif));
+        }
+
+        #[test]
+        fn output_formats() {
+            assert_eq!(
+                value_parser_for_output_format("JPG"),
+                Ok("jpeg".to_string())
+            );
+            assert_eq!(
+                value_parser_for_output_format("image/webp"),
+                Ok("webp".to_string())
+            );
+            assert_eq!(
+                value_parser_for_output_format("image/x-tga"),
+                Ok("tga".to_string())
+            );
+            assert!(value_parser_for_output_format("image/gif").is_err());
+        }
+    }
+
+    mod value_parser_for_duration_test {
+        use super::*;
+
+        #[test]
+        fn with_units() {
+            assert_eq!(
+                value_parser_for_duration("500ms"),
+                Ok(Duration::from_millis(500))
+            );
+            assert_eq!(value_parser_for_duration("30"), Ok(Duration::from_secs(30)));
+            assert_eq!(
+                value_parser_for_duration("2m"),
+                Ok(Duration::from_secs(120))
+            );
+            assert!(value_parser_for_duration("1h").is_err());
+            assert!(value_parser_for_duration("s").is_err());
+        }
+
+        #[test]
+        fn ages() {
+            assert_eq!(value_parser_for_age("12h"), Ok(Duration::from_secs(43200)));
+            assert_eq!(
+                value_parser_for_age("30d"),
+                Ok(Duration::from_secs(30 * 86400))
+            );
+            assert_eq!(value_parser_for_age("2m"), Ok(Duration::from_secs(120)));
+            assert!(value_parser_for_age("d").is_err());
+            assert!(value_parser_for_age("1w").is_err());
+        }
+
+        #[test]
+        fn rate_limits() {
+            assert_eq!(
+                value_parser_for_rate_limit("60/1m"),
+                Ok(RateLimit {
+                    requests: 60,
+                    per: Duration::from_secs(60)
+                })
+            );
+            for invalid in ["60", "0/1m", "10/0s", "x/1s", "10/1w"] {
+                assert!(value_parser_for_rate_limit(invalid).is_err(), "{}", invalid);
+            }
+        }
+
+        #[test]
+        fn dimensions() {
+            assert_eq!(value_parser_for_dimensions("200x100"), Ok((200, 100)));
+            assert!(value_parser_for_dimensions("200").is_err());
+            assert!(value_parser_for_dimensions("0x100").is_err());
+        }
+
+        #[test]
+        fn percentages() {
+            assert_eq!(value_parser_for_percent("10%"), Ok(10.0));
+            assert_eq!(value_parser_for_percent("2.5"), Ok(2.5));
+            assert!(value_parser_for_percent("101%").is_err());
+            assert!(value_parser_for_percent("-1").is_err());
+        }
+    }
+
+    mod cli_test {
+        use super::*;
+
+        #[test]
+        fn has_no_conflicting_arguments() {
+            // Checks the arguments of every subcommand, e.g. that no short option is used twice
+            cli().debug_assert();
+        }
+
+        #[test]
+        fn services_take_limits() {
+            for service in [&["daemon", "/tmp/resizer.sock"][..], &["grpc"]] {
+                let matches = cli()
+                    .try_get_matches_from(
+                        [
+                            &["image-resizer-rust"],
+                            service,
+                            &["--max-pixels", "1000", "--decode-timeout", "2s"],
+                        ]
+                        .concat(),
+                    )
+                    .unwrap();
+                let (_, sub_matches) = matches.subcommand().unwrap();
+                assert_eq!(sub_matches.get_one::<u64>("max-pixels"), Some(&1000));
+                assert_eq!(
+                    sub_matches.get_one::<Duration>("decode-timeout"),
+                    Some(&Duration::from_secs(2))
+                );
+            }
+        }
+    }
+
+    mod check_encoder_options_test {
+        use super::*;
+
+        #[test]
+        fn rejects_quality_for_webp() {
+            let dir = tempfile::TempDir::new().expect("Failed to create a temp dir");
+            let input = dir.path().join("a.png");
+            image::RgbaImage::new(1, 1).save(&input).unwrap();
+            let check = |args: &[&str]| {
+                let matches = cli()
+                    .try_get_matches_from(
+                        [&["image-resizer-rust", input.to_str().unwrap()], args].concat(),
+                    )
+                    .unwrap();
+                check_encoder_options(&matches)
+            };
+            assert!(check(&["--format", "webp", "--quality", "80"]).is_err());
+            assert!(check(&["--format", "image/webp", "--quality", "80"]).is_err());
+            assert!(check(&["--output", "out.webp", "--quality", "80"]).is_err());
+            assert!(check(&["--format", "webp"]).is_ok());
+            assert!(check(&["--format", "jpeg", "--quality", "80"]).is_ok());
+            assert!(check(&["--output", "out.avif", "--quality", "80"]).is_ok());
+            assert!(check(&["--quality", "80"]).is_ok());
+        }
+
+        #[test]
+        fn rejects_png_options_for_other_formats() {
+            let dir = tempfile::TempDir::new().expect("Failed to create a temp dir");
+            let input = dir.path().join("a.png");
+            image::RgbaImage::new(1, 1).save(&input).unwrap();
+            let check = |args: &[&str]| {
+                let matches = cli()
+                    .try_get_matches_from(
+                        [&["image-resizer-rust", input.to_str().unwrap()], args].concat(),
+                    )
+                    .unwrap();
+                check_encoder_options(&matches)
+            };
+            assert!(check(&["--format", "jpeg", "--palette"]).is_err());
+            assert!(check(&["--format", "webp", "--dither", "none"]).is_err());
+            assert!(check(&["--output", "out.jpg", "--png-compression", "9"]).is_err());
+            assert!(check(&["--format", "jpeg"]).is_ok());
+            assert!(check(&["--format", "png", "--palette", "16", "--dither", "none"]).is_ok());
+            assert!(check(&["--output", "out.png", "--png-compression", "9"]).is_ok());
+            assert!(check(&["--palette"]).is_ok());
+        }
+    }
+
+    mod only_inputs_given_test {
+        use super::*;
+
+        #[test]
+        fn only_with_inputs() {
+            let dir = tempfile::TempDir::new().expect("Failed to create a temp dir");
+            let inputs = ["a.png", "b.png"].map(|name| {
+                let path = dir.path().join(name);
+                image::RgbaImage::new(1, 1).save(&path).unwrap();
+                path.to_string_lossy().into_owned()
+            });
+            let matches = |args: &[&str]| {
+                cli()
+                    .try_get_matches_from([&["image-resizer-rust", &inputs[0]], args].concat())
+                    .unwrap()
+            };
+            assert!(only_inputs_given(&matches(&[])));
+            assert!(only_inputs_given(&matches(&[&inputs[1]])));
+            assert!(!only_inputs_given(&matches(&["--format", "png"])));
+            assert!(!only_inputs_given(&matches(&["--progressive"])));
+        }
+    }
 }
@@ -0,0 +1,197 @@
+//! System clipboard integration.
+//!
+//! Images are exchanged with the clipboard as PNG data, through the tools each platform
+//! provides: `wl-paste`/`wl-copy` on Wayland, `xclip` on X11 and `osascript` on macOS.
+//! Clipboard support is only compiled in with the `clipboard` feature; without it, every
+//! function returns an error explaining how to enable it.
+//!
+//! `ClipboardSink` puts the output of a job back on the clipboard.
+
+use crate::sink::OutputSink;
+use std::{
+    error::Error,
+    path::{Path, PathBuf},
+};
+
+/// Reads the image currently on the clipboard.
+///
+/// # Returns
+///
+/// The image as PNG data.
+///
+/// # Errors
+///
+/// This function will return an error if the clipboard does not contain an image, the
+/// clipboard tool of the platform is missing, or clipboard support is not compiled in.
+pub fn read_image() -> Result<Vec<u8>, Box<dyn Error>> {
+    platform::read_image()
+}
+
+/// Replaces the contents of the clipboard with a PNG image.
+///
+/// # Errors
+///
+/// This function will return an error if the clipboard tool of the platform is missing or
+/// fails, or if clipboard support is not compiled in.
+pub fn write_image(png: &[u8]) -> Result<(), Box<dyn Error>> {
+    platform::write_image(png)
+}
+
+/// An output sink putting each image on the clipboard. Only PNG images are accepted.
+#[derive(Debug, Default)]
+pub struct ClipboardSink;
+
+impl OutputSink for ClipboardSink {
+    fn write(&mut self, _name: &Path, contents: &[u8]) -> Result<PathBuf, Box<dyn Error>> {
+        if !matches!(image::guess_format(contents), Ok(image::ImageFormat::Png)) {
+            return Err("Only PNG images can be put on the clipboard".into());
+        }
+        write_image(contents)?;
+        Ok(PathBuf::from("<clipboard>"))
+    }
+}
+
+/// Parses the `«data PNGf...»` literal printed by AppleScript for clipboard data.
+#[cfg_attr(not(all(feature = "clipboard", target_os = "macos")), allow(dead_code))]
+fn parse_applescript_data(output: &str) -> Option<Vec<u8>> {
+    let hex = output
+        .trim()
+        .strip_prefix("«data PNGf")?
+        .strip_suffix('»')?;
+    if hex.len() % 2 != 0 {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(all(feature = "clipboard", any(target_os = "linux", target_os = "freebsd")))]
+mod platform {
+    use std::{
+        error::Error,
+        io::Write,
+        process::{Command, Stdio},
+    };
+
+    /// Returns the command reading or writing the clipboard, preferring Wayland.
+    fn command(read: bool) -> Command {
+        if std::env::var_os("WAYLAND_DISPLAY").is_some() {
+            let mut command = Command::new(if read { "wl-paste" } else { "wl-copy" });
+            command.args(["--type", "image/png"]);
+            command
+        } else {
+            let mut command = Command::new("xclip");
+            command.args(["-selection", "clipboard", "-t", "image/png"]);
+            command.arg(if read { "-o" } else { "-i" });
+            command
+        }
+    }
+
+    pub fn read_image() -> Result<Vec<u8>, Box<dyn Error>> {
+        let mut command = command(true);
+        let output = command
+            .output()
+            .map_err(|e| format!("Failed to run {:?}: {}", command.get_program(), e))?;
+        if !output.status.success() || output.stdout.is_empty() {
+            return Err("The clipboard does not contain an image".into());
+        }
+        Ok(output.stdout)
+    }
+
+    pub fn write_image(png: &[u8]) -> Result<(), Box<dyn Error>> {
+        let mut command = command(false);
+        let mut child = command
+            .stdin(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("Failed to run {:?}: {}", command.get_program(), e))?;
+        child
+            .stdin
+            .take()
+            .ok_or("Failed to open the clipboard tool's input")?
+            .write_all(png)?;
+        if !child.wait()?.success() {
+            return Err("Failed to put the image on the clipboard".into());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(all(feature = "clipboard", target_os = "macos"))]
+mod platform {
+    use std::{error::Error, process::Command};
+
+    fn osascript(script: &str) -> Result<std::process::Output, Box<dyn Error>> {
+        Ok(Command::new("osascript")
+            .args(["-e", script])
+            .output()
+            .map_err(|e| format!("Failed to run osascript: {}", e))?)
+    }
+
+    pub fn read_image() -> Result<Vec<u8>, Box<dyn Error>> {
+        let output = osascript("the clipboard as «class PNGf»")?;
+        if !output.status.success() {
+            return Err("The clipboard does not contain an image".into());
+        }
+        super::parse_applescript_data(&String::from_utf8_lossy(&output.stdout))
+            .ok_or_else(|| "Unexpected clipboard data".into())
+    }
+
+    pub fn write_image(png: &[u8]) -> Result<(), Box<dyn Error>> {
+        let file = tempfile::Builder::new().suffix(".png").tempfile()?;
+        std::fs::write(file.path(), png)?;
+        let script = format!(
+            "set the clipboard to (read (POSIX file {:?}) as «class PNGf»)",
+            file.path()
+        );
+        if !osascript(&script)?.status.success() {
+            return Err("Failed to put the image on the clipboard".into());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(not(all(
+    feature = "clipboard",
+    any(target_os = "linux", target_os = "freebsd", target_os = "macos")
+)))]
+mod platform {
+    use std::error::Error;
+
+    const UNSUPPORTED: &str = if cfg!(feature = "clipboard") {
+        "The clipboard is not supported on this platform"
+    } else {
+        "Clipboard support is not included in this build (enable the `clipboard` feature)"
+    };
+
+    pub fn read_image() -> Result<Vec<u8>, Box<dyn Error>> {
+        Err(UNSUPPORTED.into())
+    }
+
+    pub fn write_image(_png: &[u8]) -> Result<(), Box<dyn Error>> {
+        Err(UNSUPPORTED.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_applescript_data() {
+        assert_eq!(
+            parse_applescript_data("«data PNGf89504E47»\n"),
+            Some(vec![0x89, 0x50, 0x4E, 0x47])
+        );
+        assert_eq!(parse_applescript_data("«data TIFF4D4D»"), None);
+        assert_eq!(parse_applescript_data("«data PNGf895»"), None);
+    }
+
+    #[test]
+    fn sink_rejects_non_png_data() {
+        assert!(ClipboardSink
+            .write(Path::new("a.jpg"), b"not a png")
+            .is_err());
+    }
+}
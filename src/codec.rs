@@ -0,0 +1,475 @@
+//! Pluggable encoders and decoders.
+//!
+//! Every output format is produced by an `Encoder`, looked up by format in an
+//! `EncoderRegistry`. The registry used by a job is `JobOptions::encoders`, which starts out
+//! with the built-in encoders of the `encode` module. Applications can register their own
+//! encoders, either to add a format the built-ins do not write (e.g. `ImageFormat::Qoi` or
+//! `ImageFormat::Tiff`) or to replace a built-in one, without forking the crate.
+//!
+//! On the input side, the `Decoder`s of `JobOptions::decoders` are offered every input before
+//! the decoders of the `image` crate, so that formats it does not know, such as proprietary
+//! camera formats or DICOM, can be resized by the standard pipeline.
+
+use crate::{
+    encode::{self, EncodeOptions},
+    storage::Storage,
+};
+use image::{DynamicImage, ImageFormat, RgbaImage};
+use std::{
+    error::Error,
+    fmt,
+    io::Read,
+    path::Path,
+    sync::{Arc, OnceLock},
+};
+
+/// The number of bytes at the start of an input passed to `Decoder::matches`.
+pub const HEADER_LEN: usize = 64;
+
+/// Encodes RGBA images into a single output format.
+pub trait Encoder: fmt::Debug + Send + Sync {
+    /// The format of the encoded images.
+    fn format(&self) -> ImageFormat;
+
+    /// Encodes an image.
+    ///
+    /// # Arguments
+    ///
+    /// * `image` - The image to encode.
+    /// * `options` - The encoder settings. Encoders ignore the settings which do not apply to
+    ///   their format.
+    ///
+    /// # Returns
+    ///
+    /// The encoded image.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the image cannot be encoded.
+    fn encode(&self, image: &RgbaImage, options: &EncodeOptions)
+        -> Result<Vec<u8>, Box<dyn Error>>;
+}
+
+/// The built-in JPEG encoder (see `encode::encode_jpeg`).
+#[derive(Debug, Clone, Copy, Default)]
+pub struct JpegEncoder;
+
+impl Encoder for JpegEncoder {
+    fn format(&self) -> ImageFormat {
+        ImageFormat::Jpeg
+    }
+
+    fn encode(
+        &self,
+        image: &RgbaImage,
+        options: &EncodeOptions,
+    ) -> Result<Vec<u8>, Box<dyn Error>> {
+        encode::encode_jpeg(image, options)
+    }
+}
+
+/// The built-in PNG encoder (see `encode::encode_png`).
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PngEncoder;
+
+impl Encoder for PngEncoder {
+    fn format(&self) -> ImageFormat {
+        ImageFormat::Png
+    }
+
+    fn encode(
+        &self,
+        image: &RgbaImage,
+        options: &EncodeOptions,
+    ) -> Result<Vec<u8>, Box<dyn Error>> {
+        encode::encode_png(image, options)
+    }
+}
+
+/// The built-in BMP encoder (see `encode::encode_bmp`).
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BmpEncoder;
+
+impl Encoder for BmpEncoder {
+    fn format(&self) -> ImageFormat {
+        ImageFormat::Bmp
+    }
+
+    fn encode(&self, image: &RgbaImage, _: &EncodeOptions) -> Result<Vec<u8>, Box<dyn Error>> {
+        encode::encode_bmp(image)
+    }
+}
+
+/// The built-in TGA encoder (see `encode::encode_tga`).
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TgaEncoder;
+
+impl Encoder for TgaEncoder {
+    fn format(&self) -> ImageFormat {
+        ImageFormat::Tga
+    }
+
+    fn encode(
+        &self,
+        image: &RgbaImage,
+        options: &EncodeOptions,
+    ) -> Result<Vec<u8>, Box<dyn Error>> {
+        encode::encode_tga(image, options)
+    }
+}
+
+/// The built-in lossless WebP encoder (see `encode::encode_webp`).
+#[derive(Debug, Clone, Copy, Default)]
+pub struct WebpEncoder;
+
+impl Encoder for WebpEncoder {
+    fn format(&self) -> ImageFormat {
+        ImageFormat::WebP
+    }
+
+    fn encode(&self, image: &RgbaImage, _: &EncodeOptions) -> Result<Vec<u8>, Box<dyn Error>> {
+        encode::encode_webp(image)
+    }
+}
+
+/// The built-in AVIF encoder (see `encode::encode_avif`).
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AvifEncoder;
+
+impl Encoder for AvifEncoder {
+    fn format(&self) -> ImageFormat {
+        ImageFormat::Avif
+    }
+
+    fn encode(
+        &self,
+        image: &RgbaImage,
+        options: &EncodeOptions,
+    ) -> Result<Vec<u8>, Box<dyn Error>> {
+        encode::encode_avif(image, options)
+    }
+}
+
+/// The encoders available to a job, by output format.
+///
+/// The default registry holds the built-in encoders. Cloning a registry is cheap, since the
+/// encoders are shared. Formats are kept in the order they were first registered, which is
+/// the order in which they are listed to users (see `format::OutputFormat`).
+#[derive(Debug, Clone)]
+pub struct EncoderRegistry {
+    encoders: Vec<Arc<dyn Encoder>>,
+    /// The formats whose encoder was registered by the application.
+    custom: Vec<ImageFormat>,
+}
+
+impl EncoderRegistry {
+    /// Creates a registry without any encoder.
+    pub fn empty() -> Self {
+        EncoderRegistry {
+            encoders: Vec::new(),
+            custom: Vec::new(),
+        }
+    }
+
+    /// Returns a shared registry holding the built-in encoders.
+    pub fn builtin() -> &'static EncoderRegistry {
+        static BUILTIN: OnceLock<EncoderRegistry> = OnceLock::new();
+        BUILTIN.get_or_init(EncoderRegistry::default)
+    }
+
+    /// Registers an encoder for its format, replacing any encoder previously registered for
+    /// that format.
+    pub fn register(&mut self, encoder: impl Encoder + 'static) {
+        let encoder: Arc<dyn Encoder> = Arc::new(encoder);
+        if !self.custom.contains(&encoder.format()) {
+            self.custom.push(encoder.format());
+        }
+        match self
+            .encoders
+            .iter_mut()
+            .find(|registered| registered.format() == encoder.format())
+        {
+            Some(registered) => *registered = encoder,
+            None => self.encoders.push(encoder),
+        }
+    }
+
+    /// Returns the encoder registered for a format, if any.
+    pub fn get(&self, format: ImageFormat) -> Option<&dyn Encoder> {
+        self.encoders
+            .iter()
+            .find(|encoder| encoder.format() == format)
+            .map(|encoder| encoder.as_ref())
+    }
+
+    /// Returns whether an encoder is registered for a format.
+    pub fn supports(&self, format: ImageFormat) -> bool {
+        self.get(format).is_some()
+    }
+
+    /// Returns whether a format is encoded by the built-in encoder of a default registry,
+    /// which some code paths, such as streaming resizes, can drive directly.
+    pub fn is_builtin(&self, format: ImageFormat) -> bool {
+        self.supports(format) && !self.custom.contains(&format)
+    }
+
+    /// Returns the formats with a registered encoder, in registration order.
+    pub fn formats(&self) -> impl Iterator<Item = ImageFormat> + '_ {
+        self.encoders.iter().map(|encoder| encoder.format())
+    }
+
+    /// Encodes an image with the encoder registered for a format.
+    ///
+    /// # Arguments
+    ///
+    /// * `image` - The image to encode.
+    /// * `format` - The output format.
+    /// * `options` - The encoder settings.
+    ///
+    /// # Returns
+    ///
+    /// The encoded image.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if no encoder is registered for `format`, or if the encoder fails.
+    pub fn encode(
+        &self,
+        image: &RgbaImage,
+        format: ImageFormat,
+        options: &EncodeOptions,
+    ) -> Result<Vec<u8>, Box<dyn Error>> {
+        let encoder = self
+            .get(format)
+            .ok_or_else(|| format!("No encoder registered for {:?}", format))?;
+        encoder.encode(image, options)
+    }
+}
+
+impl Default for EncoderRegistry {
+    fn default() -> Self {
+        let mut registry = EncoderRegistry::empty();
+        registry.register(JpegEncoder);
+        registry.register(PngEncoder);
+        registry.register(BmpEncoder);
+        registry.register(TgaEncoder);
+        registry.register(WebpEncoder);
+        registry.register(AvifEncoder);
+        registry.custom.clear();
+        registry
+    }
+}
+
+/// Decodes an input format not supported by the `image` crate.
+pub trait Decoder: fmt::Debug + Send + Sync {
+    /// A short name for the decoder, used in log messages.
+    fn name(&self) -> &str;
+
+    /// Returns whether the decoder handles an input.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the input, e.g. to check its extension.
+    /// * `header` - Up to `HEADER_LEN` bytes from the start of the input, e.g. to check a
+    ///   magic number.
+    fn matches(&self, path: &Path, header: &[u8]) -> bool;
+
+    /// Reads the dimensions of an image without decoding its pixels, so that the job limits
+    /// are enforced before decoding.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the input is not a valid image.
+    fn dimensions(&self, data: &[u8]) -> Result<(u32, u32), Box<dyn Error>>;
+
+    /// Decodes an image.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the input is not a valid image.
+    fn decode(&self, data: &[u8]) -> Result<DynamicImage, Box<dyn Error>>;
+}
+
+/// The custom decoders available to a job, tried in registration order.
+///
+/// The default registry is empty, leaving every input to the `image` crate.
+#[derive(Debug, Clone, Default)]
+pub struct DecoderRegistry {
+    decoders: Vec<Arc<dyn Decoder>>,
+}
+
+impl DecoderRegistry {
+    /// Registers a decoder, tried after the decoders registered before it.
+    pub fn register(&mut self, decoder: impl Decoder + 'static) {
+        self.decoders.push(Arc::new(decoder));
+    }
+
+    /// Returns whether no decoder is registered.
+    pub fn is_empty(&self) -> bool {
+        self.decoders.is_empty()
+    }
+
+    /// Returns the first decoder handling an input, given its path and first bytes.
+    pub fn find(&self, path: &Path, header: &[u8]) -> Option<&dyn Decoder> {
+        self.decoders
+            .iter()
+            .find(|decoder| decoder.matches(path, header))
+            .map(|decoder| decoder.as_ref())
+    }
+
+    /// Returns the first decoder handling an input stored in a storage backend.
+    ///
+    /// The input is not read at all if no decoder is registered.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the input cannot be read.
+    pub fn find_in(
+        &self,
+        storage: &dyn Storage,
+        path: &Path,
+    ) -> Result<Option<&dyn Decoder>, Box<dyn Error>> {
+        if self.is_empty() {
+            return Ok(None);
+        }
+        let mut header = Vec::with_capacity(HEADER_LEN);
+        storage
+            .open_read(path)?
+            .take(HEADER_LEN as u64)
+            .read_to_end(&mut header)?;
+        Ok(self.find(path, &header))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug)]
+    struct Ppm;
+
+    impl Encoder for Ppm {
+        fn format(&self) -> ImageFormat {
+            ImageFormat::Pnm
+        }
+
+        fn encode(&self, image: &RgbaImage, _: &EncodeOptions) -> Result<Vec<u8>, Box<dyn Error>> {
+            let mut buffer = format!("P6 {} {} 255\n", image.width(), image.height()).into_bytes();
+            for pixel in image.pixels() {
+                buffer.extend_from_slice(&pixel.0[..3]);
+            }
+            Ok(buffer)
+        }
+    }
+
+    #[test]
+    fn builtin_encoders() {
+        let image = RgbaImage::from_pixel(4, 3, image::Rgba([10, 20, 30, 255]));
+        let registry = EncoderRegistry::builtin();
+        for format in [
+            ImageFormat::Jpeg,
+            ImageFormat::Png,
+            ImageFormat::Bmp,
+            ImageFormat::Tga,
+            ImageFormat::WebP,
+            ImageFormat::Avif,
+        ] {
+            let encoded = registry
+                .encode(&image, format, &EncodeOptions::default())
+                .unwrap();
+            assert!(!encoded.is_empty(), "{:?}", format);
+        }
+        let error = registry
+            .encode(&image, ImageFormat::Pnm, &EncodeOptions::default())
+            .unwrap_err();
+        assert_eq!(error.to_string(), "No encoder registered for Pnm");
+    }
+
+    #[test]
+    fn registers_custom_encoders() {
+        let image = RgbaImage::from_pixel(2, 1, image::Rgba([1, 2, 3, 255]));
+        let mut registry = EncoderRegistry::default();
+        registry.register(Ppm);
+        assert!(registry.supports(ImageFormat::Pnm));
+        assert!(!EncoderRegistry::builtin().supports(ImageFormat::Pnm));
+        assert!(registry.is_builtin(ImageFormat::Png));
+        assert!(!registry.is_builtin(ImageFormat::Pnm));
+
+        let encoded = registry
+            .encode(&image, ImageFormat::Pnm, &EncodeOptions::default())
+            .unwrap();
+        assert_eq!(encoded, b"P6 2 1 255\n\x01\x02\x03\x01\x02\x03");
+        let decoded = image::load_from_memory_with_format(&encoded, ImageFormat::Pnm).unwrap();
+        assert_eq!(decoded.to_rgb8().get_pixel(1, 0).0, [1, 2, 3]);
+    }
+
+    /// Decodes `GRAY` followed by the width, the height and one byte per pixel.
+    #[derive(Debug)]
+    struct RawGray;
+
+    impl Decoder for RawGray {
+        fn name(&self) -> &str {
+            "raw gray"
+        }
+
+        fn matches(&self, _: &Path, header: &[u8]) -> bool {
+            header.starts_with(b"GRAY")
+        }
+
+        fn dimensions(&self, data: &[u8]) -> Result<(u32, u32), Box<dyn Error>> {
+            match data.get(4..6) {
+                Some(&[width, height]) => Ok((width as u32, height as u32)),
+                _ => Err("Truncated header".into()),
+            }
+        }
+
+        fn decode(&self, data: &[u8]) -> Result<DynamicImage, Box<dyn Error>> {
+            let (width, height) = self.dimensions(data)?;
+            let image = image::GrayImage::from_raw(width, height, data[6..].to_vec())
+                .ok_or("Truncated pixels")?;
+            Ok(DynamicImage::ImageLuma8(image))
+        }
+    }
+
+    #[test]
+    fn finds_registered_decoders() {
+        let storage = crate::storage::MemoryStorage::new();
+        storage.insert("a.gray", b"GRAY\x02\x01\x10\x20".to_vec());
+        storage.insert("b.png", b"\x89PNG".to_vec());
+
+        let mut registry = DecoderRegistry::default();
+        let path = Path::new("a.gray");
+        assert!(registry.find_in(&storage, path).unwrap().is_none());
+        registry.register(RawGray);
+        let decoder = registry.find_in(&storage, path).unwrap().unwrap();
+        assert_eq!(decoder.name(), "raw gray");
+        assert!(registry
+            .find_in(&storage, Path::new("b.png"))
+            .unwrap()
+            .is_none());
+
+        let image = decoder.decode(&storage.get(path).unwrap()).unwrap();
+        assert_eq!(image.to_luma8().into_raw(), [0x10, 0x20]);
+    }
+
+    #[test]
+    fn custom_decoders_feed_the_pipeline() {
+        use crate::{options::JobOptions, pipeline::*, storage::*};
+
+        let storage = MemoryStorage::new();
+        let mut data = b"GRAY\x08\x04".to_vec();
+        data.extend((0..32).map(|i| i * 8));
+        storage.insert("scan.gray", data);
+        let mut options = JobOptions {
+            storage: SharedStorage::new(storage),
+            ..JobOptions::default()
+        };
+        options.decoders.register(RawGray);
+
+        let mut state = PipelineState::new("scan.gray", Some(4), None);
+        state.format = Some(ImageFormat::Png);
+        Pipeline::standard().run(&mut state, &options).unwrap();
+        let resized = image::load_from_memory(&state.encoded.unwrap()).unwrap();
+        assert_eq!((resized.width(), resized.height()), (4, 2));
+    }
+}
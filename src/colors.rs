@@ -0,0 +1,119 @@
+//! Dominant color extraction.
+//!
+//! The dominant colors of an image are found with the median cut algorithm of the
+//! `quantize` module: the colors of the image are split into as many boxes as requested,
+//! and each box is represented by its average color. The boxes holding the most pixels come
+//! first. This is useful to pick accent or placeholder colors matching an image.
+//!
+//! The image is sampled at a reduced size, and mostly transparent pixels are ignored.
+
+use crate::quantize;
+use image::{DynamicImage, Rgb};
+use std::{collections::HashMap, fmt};
+
+/// The maximum width and height of the sample the colors are computed from.
+const SAMPLE_SIZE: u32 = 256;
+
+/// A dominant color of an image.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DominantColor {
+    /// The color.
+    pub color: Rgb<u8>,
+    /// The fraction of the (opaque) pixels of the image closest to this color, from 0 to 1.
+    pub share: f64,
+}
+
+impl DominantColor {
+    /// Returns the color as a hex triplet such as `#1a2b3c`.
+    pub fn hex(&self) -> String {
+        let Rgb([r, g, b]) = self.color;
+        format!("#{:02x}{:02x}{:02x}", r, g, b)
+    }
+}
+
+impl fmt::Display for DominantColor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({:.1}%)", self.hex(), self.share * 100.0)
+    }
+}
+
+/// Computes the `count` dominant colors of an image.
+///
+/// # Arguments
+///
+/// * `image` - The image to analyze.
+/// * `count` - The maximum number of colors to return.
+///
+/// # Returns
+///
+/// At most `count` colors, most common first. Fewer colors are returned if the image has
+/// fewer distinct colors, and none if it is fully transparent.
+pub fn dominant_colors(image: &DynamicImage, count: usize) -> Vec<DominantColor> {
+    let sample = if image.width() > SAMPLE_SIZE || image.height() > SAMPLE_SIZE {
+        image.thumbnail(SAMPLE_SIZE, SAMPLE_SIZE).into_rgba8()
+    } else {
+        image.to_rgba8()
+    };
+
+    let mut histogram: HashMap<[u8; 4], u32> = HashMap::new();
+    for pixel in sample.pixels().filter(|pixel| pixel[3] >= 128) {
+        *histogram
+            .entry([pixel[0], pixel[1], pixel[2], 255])
+            .or_insert(0) += 1;
+    }
+    let total: u64 = histogram.values().map(|&count| count as u64).sum();
+    if total == 0 || count == 0 {
+        return Vec::new();
+    }
+
+    let mut colors: Vec<DominantColor> =
+        quantize::median_cut_boxes(histogram.into_iter().collect(), count)
+            .iter()
+            .map(|colors| {
+                let pixels: u64 = colors.iter().map(|&(_, count)| count as u64).sum();
+                let [r, g, b, _] = quantize::average(colors).0;
+                DominantColor {
+                    color: Rgb([r, g, b]),
+                    share: pixels as f64 / total as f64,
+                }
+            })
+            .collect();
+    colors.sort_by(|a, b| b.share.total_cmp(&a.share));
+    colors
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgba, RgbaImage};
+
+    #[test]
+    fn finds_the_most_common_colors() {
+        // Three quarters red, one quarter blue, and a transparent row that is ignored
+        let image = RgbaImage::from_fn(8, 9, |x, y| match (x, y) {
+            (_, 8) => Rgba([0, 255, 0, 0]),
+            (0..=5, _) => Rgba([200, 10, 10, 255]),
+            _ => Rgba([10, 10, 200, 255]),
+        });
+        let colors = dominant_colors(&DynamicImage::ImageRgba8(image), 4);
+
+        assert_eq!(colors.len(), 2);
+        assert_eq!(colors[0].hex(), "#c80a0a");
+        assert_eq!(colors[0].share, 0.75);
+        assert_eq!(colors[1].to_string(), "#0a0ac8 (25.0%)");
+    }
+
+    #[test]
+    fn merges_colors_into_the_requested_count() {
+        let image = RgbaImage::from_fn(16, 16, |x, y| {
+            Rgba([(x * 16) as u8, (y * 16) as u8, 0, 255])
+        });
+        let colors = dominant_colors(&DynamicImage::ImageRgba8(image), 3);
+        assert_eq!(colors.len(), 3);
+        assert!(colors.windows(2).all(|pair| pair[0].share >= pair[1].share));
+        assert!((colors.iter().map(|c| c.share).sum::<f64>() - 1.0).abs() < 1e-9);
+
+        let transparent = RgbaImage::new(4, 4);
+        assert!(dominant_colors(&DynamicImage::ImageRgba8(transparent), 3).is_empty());
+    }
+}
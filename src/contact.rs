@@ -0,0 +1,251 @@
+//! Contact sheets.
+//!
+//! A contact sheet is a grid of thumbnails with a label under each of them, to review a
+//! folder of photos at a glance. Each thumbnail is fitted in a square cell, and its label
+//! lines (usually the file name, and optionally its dimensions and date) are drawn below
+//! it with the built-in font of the `font` module. The labelled cells are then laid out
+//! with `montage::compose`, with a margin of the same size as the spacing around them.
+
+use crate::{
+    font,
+    montage::{self, MontageOptions},
+    resize_image,
+};
+use image::{imageops, DynamicImage, GenericImageView, Rgba, RgbaImage};
+use std::{
+    error::Error,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// The default width and height of a thumbnail in pixels.
+pub const DEFAULT_THUMBNAIL_SIZE: u32 = 160;
+
+/// The space between a thumbnail and its label in pixels.
+const LABEL_GAP: u32 = 4;
+/// The thumbnail size per unit of label scale: larger thumbnails get larger labels.
+const LABEL_SCALE_STEP: u32 = 160;
+
+/// The layout settings of a contact sheet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContactSheetOptions {
+    /// The number of thumbnails per row. If `None`, the sheet is kept roughly square.
+    pub columns: Option<u32>,
+    /// The width and height of the cell of a thumbnail in pixels.
+    pub thumbnail_size: u32,
+    /// The space between two cells, and around the sheet, in pixels.
+    pub spacing: u32,
+    /// The background color. Labels are drawn in black or white, whichever contrasts more.
+    pub background: Rgba<u8>,
+}
+
+impl Default for ContactSheetOptions {
+    fn default() -> Self {
+        Self {
+            columns: None,
+            thumbnail_size: DEFAULT_THUMBNAIL_SIZE,
+            spacing: 8,
+            background: Rgba([255, 255, 255, 255]),
+        }
+    }
+}
+
+/// A thumbnail of a contact sheet and the lines of its label.
+#[derive(Debug, Clone)]
+pub struct ContactEntry {
+    /// The thumbnail, at most `thumbnail_size` pixels wide and high.
+    pub thumbnail: RgbaImage,
+    /// The lines drawn under the thumbnail. Lines too long for the cell are shortened.
+    pub label: Vec<String>,
+}
+
+/// Resizes an image to fit in a `size` by `size` square, keeping its aspect ratio.
+///
+/// Images that already fit are kept at their size.
+///
+/// # Errors
+///
+/// This function will return an error if the image cannot be resized.
+pub fn thumbnail(image: DynamicImage, size: u32) -> Result<RgbaImage, Box<dyn Error>> {
+    let (width, height) = image.dimensions();
+    if width <= size && height <= size {
+        return Ok(image.into_rgba8());
+    }
+    let (width, height) = if width >= height {
+        (
+            size,
+            (height as u64 * size as u64 / width as u64).max(1) as u32,
+        )
+    } else {
+        (
+            (width as u64 * size as u64 / height as u64).max(1) as u32,
+            size,
+        )
+    };
+    resize_image(image, Some(&width), Some(&height))
+}
+
+/// Lays out labelled thumbnails into a contact sheet.
+///
+/// # Arguments
+///
+/// * `entries` - The thumbnails and their labels, in order.
+/// * `options` - The layout of the sheet.
+///
+/// # Returns
+///
+/// The contact sheet image.
+///
+/// # Errors
+///
+/// This function will return an error if there are no entries, if `options.columns` is
+/// zero, or if the sheet would be too large (see `montage::compose`).
+pub fn compose(
+    entries: &[ContactEntry],
+    options: &ContactSheetOptions,
+) -> Result<RgbaImage, Box<dyn Error>> {
+    let size = options.thumbnail_size;
+    let scale = (size / LABEL_SCALE_STEP).max(1);
+    let lines = entries
+        .iter()
+        .map(|entry| entry.label.len() as u32)
+        .max()
+        .unwrap_or(0);
+    let label_height = match lines {
+        0 => 0,
+        lines => LABEL_GAP + lines * font::line_height(scale),
+    };
+    let color = text_color(options.background);
+
+    let tiles: Vec<(String, RgbaImage)> = entries
+        .iter()
+        .map(|entry| {
+            let mut tile = RgbaImage::from_pixel(size, size + label_height, options.background);
+            let (width, height) = entry.thumbnail.dimensions();
+            imageops::overlay(
+                &mut tile,
+                &entry.thumbnail,
+                (size.saturating_sub(width) / 2) as i64,
+                (size.saturating_sub(height) / 2) as i64,
+            );
+            for (index, line) in entry.label.iter().enumerate() {
+                let line = font::truncate(line, size, scale);
+                let x = size.saturating_sub(font::text_width(&line, scale)) / 2;
+                let y = size + LABEL_GAP + index as u32 * font::line_height(scale);
+                font::draw_text(&mut tile, &line, x as i64, y as i64, scale, color);
+            }
+            (String::new(), tile)
+        })
+        .collect();
+
+    let grid = montage::compose(
+        &tiles,
+        &MontageOptions {
+            columns: options.columns,
+            spacing: options.spacing,
+            background: options.background,
+        },
+    )?
+    .image;
+
+    let margin = options.spacing;
+    let (width, height) = grid.dimensions();
+    let mut sheet = RgbaImage::from_pixel(
+        width
+            .checked_add(2 * margin)
+            .ok_or("The sheet would be too large")?,
+        height
+            .checked_add(2 * margin)
+            .ok_or("The sheet would be too large")?,
+        options.background,
+    );
+    imageops::replace(&mut sheet, &grid, margin as i64, margin as i64);
+    Ok(sheet)
+}
+
+/// Formats a time as a `YYYY-MM-DD` date in UTC.
+pub fn format_date(time: SystemTime) -> String {
+    let days = match time.duration_since(UNIX_EPOCH) {
+        Ok(elapsed) => (elapsed.as_secs() / 86_400) as i64,
+        Err(e) => -(e.duration().as_secs().div_ceil(86_400) as i64),
+    };
+    // Converts days since 1970-01-01 to a civil date (Howard Hinnant's algorithm)
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+/// Returns black or white, whichever contrasts more with the background.
+fn text_color(background: Rgba<u8>) -> Rgba<u8> {
+    let Rgba([r, g, b, a]) = background;
+    let luma = (299 * r as u32 + 587 * g as u32 + 114 * b as u32) / 1000;
+    // Transparent backgrounds are usually shown over white
+    if a < 128 || luma >= 128 {
+        Rgba([0, 0, 0, 255])
+    } else {
+        Rgba([255, 255, 255, 255])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn fits_thumbnails_in_a_square() {
+        let wide = DynamicImage::ImageRgba8(RgbaImage::new(400, 100));
+        assert_eq!(thumbnail(wide, 160).unwrap().dimensions(), (160, 40));
+        let tall = DynamicImage::ImageRgba8(RgbaImage::new(100, 400));
+        assert_eq!(thumbnail(tall, 160).unwrap().dimensions(), (40, 160));
+        let small = DynamicImage::ImageRgba8(RgbaImage::new(30, 20));
+        assert_eq!(thumbnail(small, 160).unwrap().dimensions(), (30, 20));
+    }
+
+    #[test]
+    fn lays_out_labelled_thumbnails() {
+        let red = Rgba([255, 0, 0, 255]);
+        let entry = |lines: &[&str]| ContactEntry {
+            thumbnail: RgbaImage::from_pixel(40, 20, red),
+            label: lines.iter().map(|line| line.to_string()).collect(),
+        };
+        let entries = [entry(&["a.png", "40x20"]), entry(&["b.png"]), entry(&[])];
+        let options = ContactSheetOptions {
+            columns: Some(3),
+            thumbnail_size: 40,
+            spacing: 5,
+            ..Default::default()
+        };
+        let sheet = compose(&entries, &options).unwrap();
+
+        // Cells of 40x(40 + 4 + 2 lines of 10) in one row, with 5 pixels between and around
+        assert_eq!(sheet.dimensions(), (5 + 3 * 40 + 2 * 5 + 5, 5 + 64 + 5));
+        // The thumbnail is centered vertically in its square
+        assert_eq!(sheet.get_pixel(5, 5 + 10), &red);
+        assert_eq!(sheet.get_pixel(5, 5 + 9), &options.background);
+        // The label is drawn in black below it
+        let mut label = (5..45).flat_map(|x| (49..69).map(move |y| (x, y)));
+        assert!(label.any(|(x, y)| sheet.get_pixel(x, y) == &Rgba([0, 0, 0, 255])));
+    }
+
+    #[test]
+    fn formats_dates() {
+        assert_eq!(format_date(UNIX_EPOCH), "1970-01-01");
+        let leap_day = UNIX_EPOCH + Duration::from_secs(1_709_164_800 + 3600);
+        assert_eq!(format_date(leap_day), "2024-02-29");
+        assert_eq!(
+            format_date(UNIX_EPOCH - Duration::from_secs(1)),
+            "1969-12-31"
+        );
+        assert_eq!(
+            text_color(Rgba([20, 20, 20, 255])),
+            Rgba([255, 255, 255, 255])
+        );
+    }
+}
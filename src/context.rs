@@ -0,0 +1,228 @@
+//! Reusable resizers.
+//!
+//! A `fast_image_resize` resizer keeps scratch buffers and precomputed filter
+//! coefficients between calls. Functions such as `resize_image_with` create a new one for
+//! every image, which is fine for a batch, but wasteful in a service or a video pipeline
+//! resizing thousands of images. A `ResizeContext` owns the resizers and the
+//! `ResizeOptions`, and is reused from one image to the next: resizing images of the same
+//! sizes then allocates nothing besides the output.
+//!
+//! A context is `Send` but its methods take `&mut self`: keep one context per worker
+//! thread, or share one behind a `Mutex`.
+
+use crate::{options::ResizeOptions, raw::RawImage, ImageContainer};
+use fast_image_resize::{self as fr, images::Image, IntoImageView};
+use image::{DynamicImage, ImageBuffer, Rgba};
+use std::error::Error;
+use tracing::{info, trace};
+
+/// Resizers and options kept across resizes.
+pub struct ResizeContext {
+    options: ResizeOptions,
+    /// One resizer per thread a resize is split across.
+    resizers: Vec<fr::Resizer>,
+}
+
+impl std::fmt::Debug for ResizeContext {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ResizeContext")
+            .field("options", &self.options)
+            .field("resizers", &self.resizers.len())
+            .finish()
+    }
+}
+
+impl ResizeContext {
+    /// Creates the resizers for the given options.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the selected CPU extension is not available.
+    pub fn new(options: ResizeOptions) -> Result<Self, Box<dyn Error>> {
+        let resizers = (0..options.thread_count().max(1))
+            .map(|_| options.resizer())
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(ResizeContext { options, resizers })
+    }
+
+    /// Returns the options of the context.
+    pub fn options(&self) -> &ResizeOptions {
+        &self.options
+    }
+
+    /// Resizes an image like `resize_image_with`, with the options of the context.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the new dimensions cannot be determined, e.g.
+    /// if neither width nor height is specified, or if the resizing operation fails.
+    pub fn resize_image(
+        &mut self,
+        input: DynamicImage,
+        width: Option<&u32>,
+        height: Option<&u32>,
+    ) -> Result<ImageBuffer<Rgba<u8>, Vec<u8>>, Box<dyn Error>> {
+        let img = ImageContainer::new(input, width, height, &self.options)?;
+        info!(
+            "New image dimensions: width {} x height {}",
+            img.new_width, img.new_height
+        );
+        img.resize(self)
+    }
+
+    /// Resizes a borrowed raw image into a buffer owned by the caller, like
+    /// `raw::resize_raw_into`, with the options of the context.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error in the same cases as `raw::resize_raw_into`.
+    pub fn resize_into(
+        &mut self,
+        src: &RawImage<'_>,
+        dst: &mut [u8],
+        dst_width: u32,
+        dst_height: u32,
+    ) -> Result<(), Box<dyn Error>> {
+        crate::raw::check_buffer(dst.len(), dst_width, dst_height, src.layout())?;
+        if (src.width(), src.height()) == (dst_width, dst_height) {
+            dst.copy_from_slice(src.pixels());
+            return Ok(());
+        }
+
+        let pixel_type = src.layout().pixel_type();
+        let src_image =
+            fr::images::ImageRef::new(src.width(), src.height(), src.pixels(), pixel_type)?;
+        let mut dst_image =
+            fr::images::Image::from_slice_u8(dst_width, dst_height, dst, pixel_type)?;
+        self.resize_view(&src_image, &mut dst_image)
+    }
+
+    /// Resizes `src_image` into `dst_image`, which must have the same pixel type, splitting
+    /// the destination rows across the threads of the options.
+    pub(crate) fn resize_view(
+        &mut self,
+        src_image: &(impl IntoImageView + Sync),
+        dst_image: &mut Image<'_>,
+    ) -> Result<(), Box<dyn Error>> {
+        let threads = self.resizers.len().min(dst_image.height() as usize);
+        if threads <= 1 {
+            self.resizers[0].resize(src_image, dst_image, &fr::ResizeOptions::default())?;
+            Ok(())
+        } else {
+            resize_in_parallel(src_image, dst_image, &mut self.resizers[..threads])
+        }
+    }
+}
+
+/// Resizes `src_image` into `dst_image`, splitting the destination rows across threads,
+/// one per resizer.
+///
+/// Every thread resizes a crop of the source image that maps exactly onto its chunk of
+/// destination rows. Since the resampling filter may read source pixels outside the crop
+/// box, the result is the same as a single-threaded resize. Both images must have the same
+/// pixel type.
+///
+/// # Errors
+///
+/// This function will return an error if any chunk fails.
+fn resize_in_parallel(
+    src_image: &(impl IntoImageView + Sync),
+    dst_image: &mut Image<'_>,
+    resizers: &mut [fr::Resizer],
+) -> Result<(), Box<dyn Error>> {
+    let threads = resizers.len();
+    let (src_width, src_height) = (src_image.width(), src_image.height());
+    let (dst_width, dst_height) = (dst_image.width(), dst_image.height());
+    let pixel_type = dst_image.pixel_type();
+    let scale = src_height as f64 / dst_height as f64;
+    let rows_per_chunk = (dst_height as usize).div_ceil(threads);
+    let row_bytes = dst_width as usize * pixel_type.size();
+    trace!(
+        "Resizing on {} threads, {} destination rows per thread",
+        threads,
+        rows_per_chunk
+    );
+
+    std::thread::scope(|scope| {
+        let handles: Vec<_> = dst_image
+            .buffer_mut()
+            .chunks_mut(rows_per_chunk * row_bytes)
+            .zip(resizers)
+            .enumerate()
+            .map(|(index, (chunk, resizer))| {
+                scope.spawn(move || -> Result<(), Box<dyn Error + Send + Sync>> {
+                    let top = (index * rows_per_chunk) as f64;
+                    let rows = (chunk.len() / row_bytes) as u32;
+                    let mut dst =
+                        fr::images::Image::from_slice_u8(dst_width, rows, chunk, pixel_type)?;
+                    // Clamp the crop to absorb floating-point error in the last chunk
+                    let crop_top = top * scale;
+                    let crop_height = (rows as f64 * scale).min(src_height as f64 - crop_top);
+                    let crop =
+                        fr::ResizeOptions::new().crop(0.0, crop_top, src_width as f64, crop_height);
+                    resizer.resize(src_image, &mut dst, &crop)?;
+                    Ok(())
+                })
+            })
+            .collect();
+
+        for handle in handles {
+            match handle.join() {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => return Err(e as Box<dyn Error>),
+                Err(_) => return Err("A resize thread panicked".into()),
+            }
+        }
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{raw::PixelLayout, resize_image, resize_image_with};
+
+    #[test]
+    fn reuses_resizers_across_images() {
+        let mut context = ResizeContext::new(ResizeOptions::default()).unwrap();
+        for (width, height) in [(64, 48), (30, 90), (64, 48)] {
+            let input = DynamicImage::ImageRgba8(ImageBuffer::from_fn(width, height, |x, y| {
+                Rgba([(x * 4) as u8, (y * 2) as u8, 90, 255])
+            }));
+            let expected = resize_image(input.clone(), Some(&20), None).unwrap();
+            let resized = context.resize_image(input, Some(&20), None).unwrap();
+            assert_eq!(resized, expected);
+        }
+        assert!(context
+            .resize_image(DynamicImage::new_rgb8(4, 4), None, None)
+            .is_err());
+
+        let frame = vec![77; 16 * 8 * 3];
+        let src = RawImage::new(&frame, 16, 8, PixelLayout::Rgb).unwrap();
+        let mut dst = vec![0; 4 * 2 * 3];
+        context.resize_into(&src, &mut dst, 4, 2).unwrap();
+        assert_eq!(dst, vec![77; 4 * 2 * 3]);
+    }
+
+    #[test]
+    fn splits_resizes_across_threads() {
+        fn assert_send<T: Send>(_: &T) {}
+        let options = ResizeOptions {
+            threads: 3,
+            ..Default::default()
+        };
+        let mut context = ResizeContext::new(options).unwrap();
+        assert_send(&context);
+        assert_eq!(context.options().threads, 3);
+        let input = DynamicImage::ImageRgba8(ImageBuffer::from_fn(64, 48, |x, y| {
+            Rgba([(x * 4) as u8, (y * 5) as u8, ((x + y) * 2) as u8, 255])
+        }));
+        let expected = resize_image_with(input.clone(), Some(&20), None, &options).unwrap();
+        for _ in 0..2 {
+            let resized = context
+                .resize_image(input.clone(), Some(&20), None)
+                .unwrap();
+            assert_eq!(resized, expected);
+        }
+    }
+}
@@ -0,0 +1,268 @@
+//! Cover crops.
+//!
+//! A cover crop resizes an image to an exact size without distorting it: the image is
+//! scaled uniformly to the smallest size covering the requested one, and the part sticking
+//! out along one dimension is cropped away. The `Gravity` decides which part is kept.
+//!
+//! `Gravity::Smart` keeps the most salient part of the image, without any face or object
+//! detector. The saliency of a pixel combines its edge strength (the luma gradient, high on
+//! textured and detailed areas) and its color contrast (the distance of its color from the
+//! average color of the image, high on subjects standing out from a plain background). The
+//! crop window with the highest total saliency is selected with a running sum.
+
+use crate::{
+    determine_new_dimensions,
+    options::{JobOptions, ResizeOptions},
+    pipeline::{PipelineState, Stage},
+    resize_image_with,
+};
+use image::{imageops, DynamicImage, RgbaImage};
+use std::{error::Error, fmt, str::FromStr};
+
+/// The name of the cover crop stage.
+pub const COVER_CROP: &str = "cover-crop";
+
+/// Which part of an image a cover crop keeps.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Gravity {
+    #[default]
+    Center,
+    North,
+    South,
+    East,
+    West,
+    NorthEast,
+    NorthWest,
+    SouthEast,
+    SouthWest,
+    /// The most salient part of the image.
+    Smart,
+}
+
+impl fmt::Display for Gravity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Gravity::Center => "center",
+            Gravity::North => "north",
+            Gravity::South => "south",
+            Gravity::East => "east",
+            Gravity::West => "west",
+            Gravity::NorthEast => "northeast",
+            Gravity::NorthWest => "northwest",
+            Gravity::SouthEast => "southeast",
+            Gravity::SouthWest => "southwest",
+            Gravity::Smart => "smart",
+        };
+        f.write_str(name)
+    }
+}
+
+impl FromStr for Gravity {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "center" | "centre" => Ok(Gravity::Center),
+            "north" => Ok(Gravity::North),
+            "south" => Ok(Gravity::South),
+            "east" => Ok(Gravity::East),
+            "west" => Ok(Gravity::West),
+            "northeast" => Ok(Gravity::NorthEast),
+            "northwest" => Ok(Gravity::NorthWest),
+            "southeast" => Ok(Gravity::SouthEast),
+            "southwest" => Ok(Gravity::SouthWest),
+            "smart" => Ok(Gravity::Smart),
+            _ => Err(format!("Unknown gravity '{}'", s)),
+        }
+    }
+}
+
+/// Returns the saliency of every pixel of an image, in row-major order.
+pub fn saliency(image: &RgbaImage) -> Vec<f32> {
+    let (width, height) = (image.width() as usize, image.height() as usize);
+    let pixels: Vec<[f32; 3]> = image
+        .pixels()
+        .map(|pixel| {
+            // Transparent pixels count as background
+            let alpha = pixel[3] as f32 / 255.0;
+            [0, 1, 2].map(|c| pixel[c] as f32 * alpha)
+        })
+        .collect();
+    let count = pixels.len().max(1) as f32;
+    let mean = [0, 1, 2].map(|c| pixels.iter().map(|p| p[c]).sum::<f32>() / count);
+    let luma: Vec<f32> = pixels
+        .iter()
+        .map(|[r, g, b]| 0.299 * r + 0.587 * g + 0.114 * b)
+        .collect();
+
+    let mut saliency = Vec::with_capacity(pixels.len());
+    for y in 0..height {
+        for x in 0..width {
+            let at = |x: usize, y: usize| luma[y * width + x];
+            let dx = at((x + 1).min(width - 1), y) - at(x.saturating_sub(1), y);
+            let dy = at(x, (y + 1).min(height - 1)) - at(x, y.saturating_sub(1));
+            let edge = (dx * dx + dy * dy).sqrt();
+            let color = pixels[y * width + x];
+            let contrast = (0..3)
+                .map(|c| (color[c] - mean[c]).powi(2))
+                .sum::<f32>()
+                .sqrt();
+            saliency.push(edge + contrast);
+        }
+    }
+    saliency
+}
+
+/// Returns the start of the window of `window` values with the highest sum, preferring the
+/// window closest to the middle among equal ones.
+fn best_window(values: &[f32], window: usize) -> usize {
+    let slack = values.len().saturating_sub(window);
+    let mut sum: f32 = values[..window.min(values.len())].iter().sum();
+    let (mut best, mut best_sum) = (0usize, sum);
+    for start in 1..=slack {
+        sum += values[start + window - 1] - values[start - 1];
+        let closer = start.abs_diff(slack / 2) < best.abs_diff(slack / 2);
+        if sum > best_sum || (sum == best_sum && closer) {
+            (best, best_sum) = (start, sum);
+        }
+    }
+    best
+}
+
+/// Returns the top left corner of a `width` x `height` crop of an image.
+///
+/// # Arguments
+///
+/// * `image` - The image to crop, at least as large as the crop.
+/// * `width` - The width of the crop.
+/// * `height` - The height of the crop.
+/// * `gravity` - Which part of the image to keep.
+pub fn crop_position(image: &RgbaImage, width: u32, height: u32, gravity: Gravity) -> (u32, u32) {
+    let (slack_x, slack_y) = (
+        image.width().saturating_sub(width),
+        image.height().saturating_sub(height),
+    );
+    let (x, y) = match gravity {
+        Gravity::Center => (slack_x / 2, slack_y / 2),
+        Gravity::North => (slack_x / 2, 0),
+        Gravity::South => (slack_x / 2, slack_y),
+        Gravity::East => (slack_x, slack_y / 2),
+        Gravity::West => (0, slack_y / 2),
+        Gravity::NorthEast => (slack_x, 0),
+        Gravity::NorthWest => (0, 0),
+        Gravity::SouthEast => (slack_x, slack_y),
+        Gravity::SouthWest => (0, slack_y),
+        Gravity::Smart => {
+            let map = saliency(image);
+            let image_width = image.width() as usize;
+            // Sum the saliency of each column and row to find the best window along each axis
+            let mut columns = vec![0f32; image_width];
+            let mut rows = vec![0f32; image.height() as usize];
+            for (i, value) in map.iter().enumerate() {
+                columns[i % image_width] += value;
+                rows[i / image_width] += value;
+            }
+            (
+                best_window(&columns, width as usize) as u32,
+                best_window(&rows, height as usize) as u32,
+            )
+        }
+    };
+    (x, y)
+}
+
+/// Resizes an image to an exact size by scaling it to cover the size and cropping it.
+///
+/// # Errors
+///
+/// This function will return an error if the resize fails.
+pub fn cover_crop(
+    image: DynamicImage,
+    width: u32,
+    height: u32,
+    gravity: Gravity,
+    options: &ResizeOptions,
+) -> Result<RgbaImage, Box<dyn Error>> {
+    let (src_width, src_height) = (image.width() as f64, image.height() as f64);
+    let scale = (width as f64 / src_width).max(height as f64 / src_height);
+    let cover_width = ((src_width * scale).round() as u32).max(width);
+    let cover_height = ((src_height * scale).round() as u32).max(height);
+    let scaled = resize_image_with(image, Some(&cover_width), Some(&cover_height), options)?;
+    let (x, y) = crop_position(&scaled, width, height, gravity);
+    Ok(imageops::crop_imm(&scaled, x, y, width, height).to_image())
+}
+
+/// A stage resizing the current image with a cover crop, meant to replace the standard
+/// resize stage.
+///
+/// If only one of the width and height is requested, there is nothing to crop and the
+/// image is simply scaled.
+pub struct CoverCropStage {
+    gravity: Gravity,
+}
+
+impl CoverCropStage {
+    /// Creates a stage keeping the part of the image given by `gravity`.
+    pub fn new(gravity: Gravity) -> Self {
+        CoverCropStage { gravity }
+    }
+}
+
+impl Stage for CoverCropStage {
+    fn name(&self) -> &str {
+        COVER_CROP
+    }
+
+    fn run(&self, state: &mut PipelineState, options: &JobOptions) -> Result<(), Box<dyn Error>> {
+        let image = state.take_image()?;
+        let (width, height) = determine_new_dimensions(
+            image.width(),
+            image.height(),
+            state.width.as_ref(),
+            state.height.as_ref(),
+            &options.resize,
+        )?;
+        let resized = cover_crop(image, width, height, self.gravity, &options.resize)?;
+        state.image = Some(DynamicImage::ImageRgba8(resized));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    /// A plain image with a detailed red subject near its right edge.
+    fn subject() -> RgbaImage {
+        RgbaImage::from_fn(40, 10, |x, y| {
+            if (30..36).contains(&x) && (2..8).contains(&y) {
+                Rgba([200 + (x % 2 * 50) as u8, 0, 0, 255])
+            } else {
+                Rgba([240, 240, 240, 255])
+            }
+        })
+    }
+
+    #[test]
+    fn positions_crops_by_gravity() {
+        let image = subject();
+        assert_eq!(crop_position(&image, 10, 10, Gravity::Center), (15, 0));
+        assert_eq!(crop_position(&image, 10, 10, Gravity::West), (0, 0));
+        assert_eq!(crop_position(&image, 10, 10, Gravity::SouthEast), (30, 0));
+        assert_eq!("NorthWest".parse::<Gravity>(), Ok(Gravity::NorthWest));
+        assert_eq!(Gravity::Smart.to_string().parse(), Ok(Gravity::Smart));
+    }
+
+    #[test]
+    fn smart_crops_keep_the_subject() {
+        let image = subject();
+        let (x, y) = crop_position(&image, 10, 10, Gravity::Smart);
+        assert_eq!(y, 0);
+        assert!(x <= 30 && x + 10 >= 36, "crop at {}", x);
+
+        // A uniform image falls back to the center
+        let plain = RgbaImage::from_pixel(40, 10, Rgba([9, 9, 9, 255]));
+        assert_eq!(crop_position(&plain, 10, 10, Gravity::Smart), (15, 0));
+    }
+}
@@ -0,0 +1,147 @@
+//! Error types.
+//!
+//! Most functions of this crate return `Box<dyn std::error::Error>`. Errors that callers
+//! may want to handle specifically are represented by `ResizeError`, which can be
+//! recovered from the boxed error with `downcast_ref`.
+
+use image::ImageFormat;
+use std::{error::Error, fmt, time::Duration};
+
+/// Errors with a specific meaning for callers of the library.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ResizeError {
+    /// The operation was aborted through a `CancellationToken`.
+    Cancelled,
+    /// The input format is not accepted by the job's `InputPolicy`. The format is None if
+    /// it could not be detected.
+    FormatRejected(Option<ImageFormat>),
+    /// The dimensions claimed by the image header exceed the job's `SafetyLimits`.
+    DimensionsExceeded {
+        /// The width claimed by the header.
+        width: u32,
+        /// The height claimed by the header.
+        height: u32,
+    },
+    /// Decoding the image took longer than the job's decode timeout.
+    DecodeTimeout(Duration),
+    /// The image has a zero width or height.
+    InvalidDimensions {
+        /// The width of the image.
+        width: u32,
+        /// The height of the image.
+        height: u32,
+    },
+    /// A side requested for the output is zero.
+    InvalidRequestedSize {
+        /// The requested side, `"width"` or `"height"`.
+        side: &'static str,
+        /// The requested value.
+        value: u32,
+    },
+    /// The image has more pixels than the job's `Limits` allow.
+    PixelsExceeded {
+        /// The width of the image.
+        width: u32,
+        /// The height of the image.
+        height: u32,
+        /// The maximum number of pixels.
+        max_pixels: u64,
+    },
+    /// The encoded input is larger than allowed.
+    InputTooLarge {
+        /// The size of the input, in bytes.
+        size: u64,
+        /// The maximum size, in bytes.
+        max_size: u64,
+    },
+    /// The decoder needed more memory than allowed.
+    MemoryExceeded,
+    /// The input is not an image in a supported format, or is corrupt.
+    Malformed(String),
+    /// The decoder panicked. This is a bug in the decoder, reported as an error instead of
+    /// aborting the process.
+    DecoderPanicked(String),
+    /// The job waited longer than the `Scheduler` queue timeout for a free slot.
+    QueueTimeout(Duration),
+    /// The client sent more jobs than its `Scheduler` rate limit allows.
+    RateLimited {
+        /// How long the client should wait before sending another job.
+        retry_after: Duration,
+    },
+}
+
+impl ResizeError {
+    /// Returns `true` if the boxed error is `ResizeError::Cancelled`.
+    pub fn is_cancelled(error: &(dyn Error + 'static)) -> bool {
+        matches!(
+            error.downcast_ref::<ResizeError>(),
+            Some(ResizeError::Cancelled)
+        )
+    }
+}
+
+impl fmt::Display for ResizeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ResizeError::Cancelled => write!(f, "Operation cancelled"),
+            ResizeError::FormatRejected(Some(format)) => {
+                write!(f, "Input format {:?} is not accepted", format)
+            }
+            ResizeError::FormatRejected(None) => {
+                write!(f, "Input format could not be detected and is not accepted")
+            }
+            ResizeError::DimensionsExceeded { width, height } => write!(
+                f,
+                "Image dimensions {}x{} exceed the configured maximum",
+                width, height
+            ),
+            ResizeError::DecodeTimeout(timeout) => {
+                write!(f, "Decoding did not finish within {:?}", timeout)
+            }
+            ResizeError::InvalidDimensions { width, height } => {
+                write!(
+                    f,
+                    "Invalid image dimensions {}x{}: the width and height must be at least 1 pixel",
+                    width, height
+                )
+            }
+            ResizeError::InvalidRequestedSize { side, value } => write!(
+                f,
+                "Invalid requested {} {}: it must be at least 1 pixel",
+                side, value
+            ),
+            ResizeError::PixelsExceeded {
+                width,
+                height,
+                max_pixels,
+            } => write!(
+                f,
+                "Image of {}x{} ({} pixels) exceeds the limit of {} pixels",
+                width,
+                height,
+                *width as u64 * *height as u64,
+                max_pixels
+            ),
+            ResizeError::InputTooLarge { size, max_size } => write!(
+                f,
+                "Input of {} bytes exceeds the limit of {} bytes",
+                size, max_size
+            ),
+            ResizeError::MemoryExceeded => write!(f, "Decoding exceeds the memory limit"),
+            ResizeError::Malformed(reason) => write!(f, "Invalid image: {}", reason),
+            ResizeError::DecoderPanicked(message) => {
+                write!(f, "The decoder crashed on this input: {}", message)
+            }
+            ResizeError::QueueTimeout(timeout) => {
+                write!(f, "No resize slot became free within {:?}", timeout)
+            }
+            ResizeError::RateLimited { retry_after } => write!(
+                f,
+                "Too many requests, retry in {:.1}s",
+                retry_after.as_secs_f64()
+            ),
+        }
+    }
+}
+
+impl Error for ResizeError {}
@@ -0,0 +1,552 @@
+//! Pixel filters.
+//!
+//! Channel-level operations on decoded images, such as inverting colors, swapping the red
+//! and blue channels, extracting a single channel or replacing the alpha channel with a
+//! mask, tone presets applied as per-channel lookup tables, a radial vignette and an unsharp
+//! mask, and the pipeline stages running them between the resize and the encode of a job.
+//!
+//! Filters are plain pipeline stages, so their order is set by where they are inserted.
+//! `DenoiseStage`, for instance, is meant to run before the `RESIZE` stage, where noise is
+//! removed before it gets averaged into the output pixels, but it can be inserted after it
+//! just as well.
+
+use crate::{
+    options::JobOptions,
+    pipeline::{PipelineState, Stage},
+};
+use image::{imageops, DynamicImage, GrayImage, Luma, Rgba, RgbaImage};
+use std::{
+    error::Error,
+    fmt,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+use tracing::info;
+
+/// The name of the stage reducing noise.
+pub const DENOISE: &str = "denoise";
+/// The strongest denoise strength, i.e. the largest median filter radius.
+pub const MAX_DENOISE_STRENGTH: u32 = 5;
+/// The name of the stage applying an alpha mask.
+pub const APPLY_ALPHA: &str = "apply-alpha";
+/// The name of the stage extracting the alpha channel.
+pub const EXTRACT_ALPHA: &str = "extract-alpha";
+
+/// A channel of an RGBA image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Channel {
+    Red,
+    Green,
+    Blue,
+    Alpha,
+}
+
+impl Channel {
+    /// Returns the index of the channel in an RGBA pixel.
+    pub fn index(self) -> usize {
+        match self {
+            Channel::Red => 0,
+            Channel::Green => 1,
+            Channel::Blue => 2,
+            Channel::Alpha => 3,
+        }
+    }
+}
+
+impl fmt::Display for Channel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Channel::Red => "r",
+            Channel::Green => "g",
+            Channel::Blue => "b",
+            Channel::Alpha => "a",
+        };
+        f.write_str(name)
+    }
+}
+
+impl FromStr for Channel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "r" | "red" => Ok(Channel::Red),
+            "g" | "green" => Ok(Channel::Green),
+            "b" | "blue" => Ok(Channel::Blue),
+            "a" | "alpha" => Ok(Channel::Alpha),
+            _ => Err(format!("Unknown channel '{}'", s)),
+        }
+    }
+}
+
+/// Returns one channel of an image as a grayscale image.
+pub fn extract_channel(image: &RgbaImage, channel: Channel) -> GrayImage {
+    let index = channel.index();
+    GrayImage::from_fn(image.width(), image.height(), |x, y| {
+        Luma([image.get_pixel(x, y)[index]])
+    })
+}
+
+/// Returns the alpha channel of an image as a grayscale mask, opaque pixels being white.
+pub fn extract_alpha(image: &RgbaImage) -> GrayImage {
+    extract_channel(image, Channel::Alpha)
+}
+
+/// Inverts the color channels of an image, leaving the alpha channel unchanged.
+pub fn negate(image: &mut RgbaImage) {
+    for pixel in image.pixels_mut() {
+        for value in &mut pixel.0[..3] {
+            *value = 255 - *value;
+        }
+    }
+}
+
+/// Swaps the red and blue channels of an image, e.g. to fix textures saved as BGRA.
+pub fn swap_rb(image: &mut RgbaImage) {
+    for pixel in image.pixels_mut() {
+        pixel.0.swap(0, 2);
+    }
+}
+
+/// A tone preset, applied as per-channel curves.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tone {
+    /// Brown monochrome, as in old photographs.
+    Sepia,
+    /// Bluish shadows and highlights.
+    Cool,
+    /// Reddish-yellow shadows and highlights.
+    Warm,
+}
+
+impl Tone {
+    /// Returns the curves of the preset.
+    pub fn curves(self) -> ToneCurves {
+        match self {
+            // The gray level times the row sums of the usual sepia matrix
+            Tone::Sepia => {
+                ToneCurves::from_fn(|channel, value| value * [1.351, 1.203, 0.937][channel])
+            }
+            Tone::Cool => ToneCurves::from_fn(|channel, value| {
+                255.0 * (value / 255.0).powf([1.15, 1.0, 0.87][channel])
+            }),
+            Tone::Warm => ToneCurves::from_fn(|channel, value| {
+                255.0 * (value / 255.0).powf([0.87, 0.97, 1.15][channel])
+            }),
+        }
+    }
+
+    /// Returns whether the image is converted to grayscale before the curves are applied.
+    pub fn is_monochrome(self) -> bool {
+        self == Tone::Sepia
+    }
+}
+
+impl fmt::Display for Tone {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Tone::Sepia => "sepia",
+            Tone::Cool => "cool",
+            Tone::Warm => "warm",
+        };
+        f.write_str(name)
+    }
+}
+
+impl FromStr for Tone {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "sepia" => Ok(Tone::Sepia),
+            "cool" => Ok(Tone::Cool),
+            "warm" => Ok(Tone::Warm),
+            _ => Err(format!("Unknown tone '{}'", s)),
+        }
+    }
+}
+
+/// Lookup tables mapping each value of the red, green and blue channels to a new value.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ToneCurves {
+    /// The tables of the red, green and blue channels, in that order.
+    pub tables: [[u8; 256]; 3],
+}
+
+impl ToneCurves {
+    /// Builds the tables from a function of the channel index (0 to 2) and the input value,
+    /// whose result is rounded and clamped to 0..=255.
+    pub fn from_fn(curve: impl Fn(usize, f32) -> f32) -> Self {
+        let mut tables = [[0u8; 256]; 3];
+        for (channel, table) in tables.iter_mut().enumerate() {
+            for (value, entry) in table.iter_mut().enumerate() {
+                *entry = curve(channel, value as f32).round().clamp(0.0, 255.0) as u8;
+            }
+        }
+        ToneCurves { tables }
+    }
+
+    /// Maps the color channels of an image through the tables, leaving the alpha channel
+    /// unchanged.
+    pub fn apply(&self, image: &mut RgbaImage) {
+        for pixel in image.pixels_mut() {
+            for (value, table) in pixel.0.iter_mut().zip(&self.tables) {
+                *value = table[*value as usize];
+            }
+        }
+    }
+}
+
+/// Applies a tone preset to an image.
+pub fn apply_tone(image: &mut RgbaImage, tone: Tone) {
+    if tone.is_monochrome() {
+        for pixel in image.pixels_mut() {
+            let [r, g, b, _] = pixel.0;
+            // Rec. 601 luma
+            let luma = (0.299 * r as f32 + 0.587 * g as f32 + 0.114 * b as f32).round() as u8;
+            pixel.0[..3].fill(luma);
+        }
+    }
+    tone.curves().apply(image);
+}
+
+/// Darkens the corners of an image with a radial mask.
+///
+/// The mask is an ellipse fitting the image: the center is left unchanged, and pixels are
+/// darkened progressively from halfway to the corners, where the darkening is full.
+///
+/// # Arguments
+///
+/// * `image` - The image to darken.
+/// * `strength` - How much the corners are darkened, from 0.0 (not at all) to 1.0 (black).
+pub fn vignette(image: &mut RgbaImage, strength: f32) {
+    let (width, height) = image.dimensions();
+    let (center_x, center_y) = (width as f32 / 2.0, height as f32 / 2.0);
+    let strength = strength.clamp(0.0, 1.0);
+    for (x, y, pixel) in image.enumerate_pixels_mut() {
+        let dx = (x as f32 + 0.5 - center_x) / center_x;
+        let dy = (y as f32 + 0.5 - center_y) / center_y;
+        // 0 at the center, 1 at the corners
+        let distance = ((dx * dx + dy * dy) / 2.0).sqrt();
+        let t = ((distance - 0.5) / 0.5).clamp(0.0, 1.0);
+        let factor = 1.0 - strength * t * t * (3.0 - 2.0 * t);
+        for value in &mut pixel.0[..3] {
+            *value = (*value as f32 * factor).round() as u8;
+        }
+    }
+}
+
+/// Sharpens an image with an unsharp mask: the difference between the image and a blurred
+/// copy of it is added back, increasing the contrast along edges.
+///
+/// # Arguments
+///
+/// * `image` - The image to sharpen.
+/// * `sigma` - The standard deviation of the blur, i.e. the size of the sharpened details.
+/// * `threshold` - The smallest difference, from 0 to 255, that is sharpened, so that noise
+///   in flat areas is left alone.
+///
+/// # Returns
+///
+/// The sharpened image.
+pub fn sharpen(image: &RgbaImage, sigma: f32, threshold: i32) -> RgbaImage {
+    let mut sharpened = imageops::unsharpen(image, sigma, threshold);
+    // Blurring blends the alpha channel too, which would fringe the edges of transparency
+    for (pixel, original) in sharpened.pixels_mut().zip(image.pixels()) {
+        pixel[3] = original[3];
+    }
+    sharpened
+}
+
+/// Reduces the noise of an image with a median filter.
+///
+/// Each channel of each pixel is replaced with the median of the square window of side
+/// `2 * radius + 1` around it, with edge pixels repeated past the borders. Unlike a blur,
+/// this removes speckles while keeping edges sharp.
+///
+/// # Arguments
+///
+/// * `image` - The image to filter.
+/// * `radius` - The radius of the window. A radius of 0 returns a copy of the image.
+///
+/// # Returns
+///
+/// The filtered image.
+pub fn denoise(image: &RgbaImage, radius: u32) -> RgbaImage {
+    let (width, height) = image.dimensions();
+    if radius == 0 || width == 0 || height == 0 {
+        return image.clone();
+    }
+    let radius = radius as i64;
+    let side = (2 * radius + 1) as usize;
+    let mut window = vec![[0u8; 4]; side * side];
+    let mut values = vec![0u8; side * side];
+    let clamp = |value: i64, max: u32| value.clamp(0, max as i64 - 1) as u32;
+    RgbaImage::from_fn(width, height, |x, y| {
+        let mut i = 0;
+        for dy in -radius..=radius {
+            for dx in -radius..=radius {
+                let (sx, sy) = (clamp(x as i64 + dx, width), clamp(y as i64 + dy, height));
+                window[i] = image.get_pixel(sx, sy).0;
+                i += 1;
+            }
+        }
+        let middle = values.len() / 2;
+        let mut median = [0u8; 4];
+        for (channel, value) in median.iter_mut().enumerate() {
+            for (slot, pixel) in values.iter_mut().zip(&window) {
+                *slot = pixel[channel];
+            }
+            *value = *values.select_nth_unstable(middle).1;
+        }
+        Rgba(median)
+    })
+}
+
+/// Replaces the alpha channel of an image with a grayscale mask, white being opaque.
+///
+/// The mask is stretched to the size of the image if their sizes differ, so the same mask
+/// can be applied to outputs of any size.
+///
+/// # Arguments
+///
+/// * `image` - The image whose alpha channel is replaced.
+/// * `mask` - The grayscale mask.
+pub fn apply_alpha(image: &mut RgbaImage, mask: &GrayImage) {
+    let (width, height) = image.dimensions();
+    let resized;
+    let mask = if mask.dimensions() == (width, height) {
+        mask
+    } else {
+        resized = imageops::resize(mask, width, height, imageops::FilterType::Triangle);
+        &resized
+    };
+    for (pixel, alpha) in image.pixels_mut().zip(mask.pixels()) {
+        pixel[3] = alpha[0];
+    }
+}
+
+/// A stage reducing the noise of the current image with a median filter.
+pub struct DenoiseStage {
+    strength: u32,
+}
+
+impl DenoiseStage {
+    /// Creates a stage filtering with a median window of radius `strength`, from 1 to
+    /// `MAX_DENOISE_STRENGTH`.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if `strength` is out of range.
+    pub fn new(strength: u32) -> Result<Self, Box<dyn Error>> {
+        if !(1..=MAX_DENOISE_STRENGTH).contains(&strength) {
+            return Err(format!(
+                "The denoise strength must be between 1 and {}",
+                MAX_DENOISE_STRENGTH
+            )
+            .into());
+        }
+        Ok(DenoiseStage { strength })
+    }
+}
+
+impl Stage for DenoiseStage {
+    fn name(&self) -> &str {
+        DENOISE
+    }
+
+    fn run(&self, state: &mut PipelineState, _options: &JobOptions) -> Result<(), Box<dyn Error>> {
+        let image = state.take_image()?.into_rgba8();
+        state.image = Some(DynamicImage::ImageRgba8(denoise(&image, self.strength)));
+        Ok(())
+    }
+}
+
+/// A stage replacing the alpha channel of the current image with a mask.
+pub struct ApplyAlphaStage {
+    mask: GrayImage,
+}
+
+impl ApplyAlphaStage {
+    /// Creates a stage applying `mask`, converted to grayscale.
+    pub fn new(mask: &DynamicImage) -> Self {
+        ApplyAlphaStage {
+            mask: mask.to_luma8(),
+        }
+    }
+}
+
+impl Stage for ApplyAlphaStage {
+    fn name(&self) -> &str {
+        APPLY_ALPHA
+    }
+
+    fn run(&self, state: &mut PipelineState, _options: &JobOptions) -> Result<(), Box<dyn Error>> {
+        let mut image = state.take_image()?.into_rgba8();
+        apply_alpha(&mut image, &self.mask);
+        state.image = Some(DynamicImage::ImageRgba8(image));
+        Ok(())
+    }
+}
+
+/// A stage saving the alpha channel of the current image as a grayscale mask, leaving the
+/// image unchanged.
+pub struct ExtractAlphaStage {
+    path: PathBuf,
+    per_input: bool,
+}
+
+impl ExtractAlphaStage {
+    /// Creates a stage saving the mask to `path`, in the format given by its extension.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the mask.
+    /// * `per_input` - Whether several inputs are processed, in which case the stem of each
+    ///   input is appended to the file name of its mask, e.g. `mask_photo.png`.
+    pub fn new(path: impl Into<PathBuf>, per_input: bool) -> Self {
+        ExtractAlphaStage {
+            path: path.into(),
+            per_input,
+        }
+    }
+
+    /// Returns the path of the mask of `input`.
+    pub fn mask_path(&self, input: &Path) -> PathBuf {
+        if !self.per_input {
+            return self.path.clone();
+        }
+        let stem = self.path.file_stem().unwrap_or_default().to_string_lossy();
+        let input_stem = input.file_stem().unwrap_or_default().to_string_lossy();
+        let name = match self.path.extension() {
+            Some(extension) => format!("{}_{}.{}", stem, input_stem, extension.to_string_lossy()),
+            None => format!("{}_{}", stem, input_stem),
+        };
+        self.path.with_file_name(name)
+    }
+}
+
+impl Stage for ExtractAlphaStage {
+    fn name(&self) -> &str {
+        EXTRACT_ALPHA
+    }
+
+    fn run(&self, state: &mut PipelineState, _options: &JobOptions) -> Result<(), Box<dyn Error>> {
+        let image = state
+            .image
+            .as_ref()
+            .ok_or("No image to extract the alpha channel from")?;
+        let path = self.mask_path(&state.input);
+        extract_alpha(&image.to_rgba8())
+            .save(&path)
+            .map_err(|e| format!("Failed to save the alpha mask {:?}: {}", path, e))?;
+        info!("Alpha mask saved to {:?}", path);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extracts_and_applies_alpha() {
+        let image = RgbaImage::from_fn(4, 2, |x, _| Rgba([10, 20, 30, x as u8 * 80]));
+        let mask = extract_alpha(&image);
+        assert_eq!(mask.get_pixel(3, 1), &Luma([240]));
+
+        let mut opaque = RgbaImage::from_pixel(4, 2, Rgba([1, 2, 3, 255]));
+        apply_alpha(&mut opaque, &mask);
+        assert_eq!(opaque.get_pixel(2, 0), &Rgba([1, 2, 3, 160]));
+
+        // A mask of another size is stretched to the image
+        let mut large = RgbaImage::from_pixel(8, 8, Rgba([0, 0, 0, 255]));
+        apply_alpha(&mut large, &GrayImage::from_pixel(2, 2, Luma([7])));
+        assert!(large.pixels().all(|pixel| pixel[3] == 7));
+    }
+
+    #[test]
+    fn operates_on_channels() {
+        let mut image = RgbaImage::from_pixel(2, 1, Rgba([10, 20, 30, 40]));
+        negate(&mut image);
+        assert_eq!(image.get_pixel(0, 0), &Rgba([245, 235, 225, 40]));
+        swap_rb(&mut image);
+        assert_eq!(image.get_pixel(1, 0), &Rgba([225, 235, 245, 40]));
+        assert_eq!(
+            extract_channel(&image, Channel::Green).get_pixel(0, 0),
+            &Luma([235])
+        );
+        assert_eq!("Blue".parse::<Channel>(), Ok(Channel::Blue));
+        assert_eq!(Channel::Alpha.to_string().parse(), Ok(Channel::Alpha));
+        assert!("x".parse::<Channel>().is_err());
+    }
+
+    #[test]
+    fn applies_tones_and_vignettes() {
+        let mut image = RgbaImage::from_pixel(9, 9, Rgba([200, 100, 50, 128]));
+        apply_tone(&mut image, Tone::Sepia);
+        // Luma 124, mapped through the sepia curves
+        assert_eq!(image.get_pixel(0, 0), &Rgba([168, 149, 116, 128]));
+
+        let mut warm = RgbaImage::from_pixel(1, 1, Rgba([128, 128, 128, 255]));
+        apply_tone(&mut warm, Tone::Warm);
+        let [r, _, b, _] = warm.get_pixel(0, 0).0;
+        assert!(r > 128 && b < 128);
+        assert_eq!("COOL".parse::<Tone>(), Ok(Tone::Cool));
+
+        let mut image = RgbaImage::from_pixel(9, 9, Rgba([200, 200, 200, 255]));
+        vignette(&mut image, 1.0);
+        assert_eq!(image.get_pixel(4, 4), &Rgba([200, 200, 200, 255]));
+        assert!(image.get_pixel(0, 0)[0] < 30);
+        assert_eq!(image.get_pixel(0, 0)[3], 255);
+    }
+
+    #[test]
+    fn sharpens_edges() {
+        let image = RgbaImage::from_fn(8, 1, |x, _| {
+            if x < 4 {
+                Rgba([100, 100, 100, 255])
+            } else {
+                Rgba([150, 150, 150, 128])
+            }
+        });
+        let sharpened = sharpen(&image, 1.0, 0);
+        assert!(sharpened.get_pixel(3, 0)[0] < 100);
+        assert!(sharpened.get_pixel(4, 0)[0] > 150);
+        assert_eq!(sharpened.get_pixel(0, 0), &Rgba([100, 100, 100, 255]));
+        assert_eq!(sharpened.get_pixel(4, 0)[3], 128);
+
+        // Differences below the threshold are left alone
+        assert_eq!(sharpen(&image, 1.0, 60), image);
+    }
+
+    #[test]
+    fn removes_speckles() {
+        let mut image = RgbaImage::from_pixel(5, 5, Rgba([100, 100, 100, 255]));
+        image.put_pixel(2, 2, Rgba([255, 0, 255, 255]));
+        // A sharp edge along the last column
+        for y in 0..5 {
+            image.put_pixel(4, y, Rgba([0, 0, 0, 255]));
+        }
+
+        let filtered = denoise(&image, 1);
+        assert_eq!(filtered.get_pixel(2, 2), &Rgba([100, 100, 100, 255]));
+        assert_eq!(filtered.get_pixel(4, 0), &Rgba([0, 0, 0, 255]));
+        assert_eq!(filtered.get_pixel(3, 0), &Rgba([100, 100, 100, 255]));
+        assert!(DenoiseStage::new(0).is_err());
+    }
+
+    #[test]
+    fn names_masks_per_input() {
+        let single = ExtractAlphaStage::new("out/mask.png", false);
+        assert_eq!(
+            single.mask_path(Path::new("a/photo.jpg")),
+            Path::new("out/mask.png")
+        );
+        let several = ExtractAlphaStage::new("out/mask.png", true);
+        assert_eq!(
+            several.mask_path(Path::new("a/photo.jpg")),
+            Path::new("out/mask_photo.png")
+        );
+    }
+}
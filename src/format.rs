@@ -0,0 +1,199 @@
+//! Output formats.
+//!
+//! Formats are named after the `ImageFormat` they stand for (`jpeg`, `webp`), by one of their
+//! extensions (`jpg`) or by MIME type (`image/webp`), case-insensitively. `resolve` accepts
+//! every format of the `image` crate, while an `OutputFormat` is one that an
+//! `codec::EncoderRegistry` can write. The formats accepted by `--format`, listed in its help
+//! and in error messages are those of the registry, so registering an encoder is enough to
+//! add an output format.
+
+use crate::{codec::EncoderRegistry, image_format_to_string};
+use image::ImageFormat;
+use std::{fmt, str::FromStr};
+
+/// MIME types in common use which the `image` crate does not know.
+const MIME_ALIASES: [(&str, ImageFormat); 3] = [
+    ("image/jpg", ImageFormat::Jpeg),
+    ("image/x-bmp", ImageFormat::Bmp),
+    ("image/x-ms-bmp", ImageFormat::Bmp),
+];
+
+/// Returns the format given by a name, an extension or a MIME type, if any.
+///
+/// # Examples
+///
+/// ```
+/// use image::ImageFormat;
+/// use image_resizer_rust::format::resolve;
+///
+/// assert_eq!(resolve("JPG"), Some(ImageFormat::Jpeg));
+/// assert_eq!(resolve("image/webp"), Some(ImageFormat::WebP));
+/// assert_eq!(resolve("openexr"), Some(ImageFormat::OpenExr));
+/// assert_eq!(resolve("doc"), None);
+/// ```
+pub fn resolve(name: &str) -> Option<ImageFormat> {
+    let name = name.trim().to_lowercase();
+    if name.contains('/') {
+        return ImageFormat::from_mime_type(&name).or_else(|| {
+            MIME_ALIASES
+                .iter()
+                .find(|(alias, _)| *alias == name)
+                .map(|(_, format)| *format)
+        });
+    }
+    ImageFormat::all().find(|&format| {
+        format_name(format) == name || format.extensions_str().contains(&name.as_str())
+    })
+}
+
+/// Returns the name of a format, e.g. `jpeg` or `webp`.
+fn format_name(format: ImageFormat) -> String {
+    image_format_to_string(format).to_lowercase()
+}
+
+/// A format that can be written, since an encoder is registered for it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct OutputFormat(ImageFormat);
+
+impl OutputFormat {
+    /// Checks that a format can be written with the given encoders.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error listing the formats of `encoders` if none is registered for `format`.
+    pub fn new(format: ImageFormat, encoders: &EncoderRegistry) -> Result<Self, String> {
+        if encoders.supports(format) {
+            Ok(OutputFormat(format))
+        } else {
+            Err(format!(
+                "Unsupported conversion to image format '{}'. Specify {} with --format.",
+                format_name(format),
+                OutputFormat::list(encoders)
+            ))
+        }
+    }
+
+    /// Parses a format name, extension or MIME type that can be written with the given
+    /// encoders.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error listing the formats of `encoders` if the format is unknown or cannot
+    /// be written.
+    pub fn parse(name: &str, encoders: &EncoderRegistry) -> Result<Self, String> {
+        match resolve(name) {
+            Some(format) if encoders.supports(format) => Ok(OutputFormat(format)),
+            _ => Err(format!(
+                "'{}' is not an output format: use {}, or their MIME type",
+                name,
+                OutputFormat::list(encoders)
+            )),
+        }
+    }
+
+    /// Returns the formats that can be written with the given encoders, in registration
+    /// order.
+    pub fn all(encoders: &EncoderRegistry) -> Vec<OutputFormat> {
+        encoders.formats().map(OutputFormat).collect()
+    }
+
+    /// Lists the names of the formats that can be written with the given encoders, e.g.
+    /// `jpeg, png or webp`.
+    pub fn list(encoders: &EncoderRegistry) -> String {
+        let mut names: Vec<String> = OutputFormat::all(encoders)
+            .into_iter()
+            .map(|format| format.to_string())
+            .collect();
+        match names.pop() {
+            Some(last) if !names.is_empty() => format!("{} or {}", names.join(", "), last),
+            Some(last) => last,
+            None => "no format".to_string(),
+        }
+    }
+
+    /// Returns the `ImageFormat` of the output.
+    pub fn image_format(self) -> ImageFormat {
+        self.0
+    }
+
+    /// Returns the MIME type of the format, e.g. `image/webp`.
+    pub fn mime_type(self) -> &'static str {
+        self.0.to_mime_type()
+    }
+}
+
+impl fmt::Display for OutputFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&format_name(self.0))
+    }
+}
+
+/// Parses a format that the built-in encoders can write.
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        OutputFormat::parse(s, EncoderRegistry::builtin())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{codec::Encoder, encode::EncodeOptions};
+    use image::RgbaImage;
+    use std::error::Error;
+
+    #[test]
+    fn parses_builtin_formats() {
+        assert_eq!(
+            "image/x-tga"
+                .parse::<OutputFormat>()
+                .map(OutputFormat::image_format),
+            Ok(ImageFormat::Tga)
+        );
+        assert_eq!("Jpg".parse::<OutputFormat>().unwrap().to_string(), "jpeg");
+        assert_eq!(
+            OutputFormat::list(EncoderRegistry::builtin()),
+            "jpeg, png, bmp, tga, webp or avif"
+        );
+        let error = "gif".parse::<OutputFormat>().unwrap_err();
+        assert_eq!(
+            error,
+            "'gif' is not an output format: use jpeg, png, bmp, tga, webp or avif, or their MIME type"
+        );
+    }
+
+    #[derive(Debug)]
+    struct Qoi;
+
+    impl Encoder for Qoi {
+        fn format(&self) -> ImageFormat {
+            ImageFormat::Qoi
+        }
+
+        fn encode(&self, _: &RgbaImage, _: &EncodeOptions) -> Result<Vec<u8>, Box<dyn Error>> {
+            Ok(b"qoif".to_vec())
+        }
+    }
+
+    #[test]
+    fn follows_the_registered_encoders() {
+        let mut encoders = EncoderRegistry::empty();
+        assert_eq!(OutputFormat::list(&encoders), "no format");
+        encoders.register(Qoi);
+        let qoi = OutputFormat::parse("image/x-qoi", &encoders).unwrap();
+        assert_eq!(
+            (qoi.to_string(), qoi.mime_type()),
+            ("qoi".into(), "image/x-qoi")
+        );
+        assert_eq!(OutputFormat::list(&encoders), "qoi");
+
+        let error = OutputFormat::new(ImageFormat::Png, &encoders).unwrap_err();
+        assert!(
+            error.contains("'png'") && error.contains("Specify qoi"),
+            "{}",
+            error
+        );
+    }
+}
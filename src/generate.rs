@@ -0,0 +1,267 @@
+//! Procedural test images.
+//!
+//! The patterns are chosen to exercise different parts of the resizer and encoders:
+//! - `Gradient`: a smooth horizontal ramp between two colors, which shows banding
+//! - `Checkerboard`: hard edges at every scale of the cell size, which show aliasing and
+//!   ringing
+//! - `Noise`: seeded random pixels, the worst case for compression
+//! - `Solid`: a single color, the best case for compression
+//! - `Bars`: SMPTE color bars, for checking color handling at a glance
+//!
+//! Every pattern is deterministic: the same options always produce the same pixels.
+
+use image::{Rgba, RgbaImage};
+use std::{error::Error, fmt, str::FromStr};
+
+/// The generated patterns.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Pattern {
+    /// A horizontal ramp from the first color to the second one.
+    #[default]
+    Gradient,
+    /// Square cells alternating between the two colors.
+    Checkerboard,
+    /// Random opaque pixels.
+    Noise,
+    /// The first color.
+    Solid,
+    /// SMPTE color bars.
+    Bars,
+}
+
+impl fmt::Display for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Pattern::Gradient => "gradient",
+            Pattern::Checkerboard => "checkerboard",
+            Pattern::Noise => "noise",
+            Pattern::Solid => "solid",
+            Pattern::Bars => "bars",
+        };
+        f.write_str(name)
+    }
+}
+
+impl FromStr for Pattern {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "gradient" => Ok(Pattern::Gradient),
+            "checkerboard" => Ok(Pattern::Checkerboard),
+            "noise" => Ok(Pattern::Noise),
+            "solid" => Ok(Pattern::Solid),
+            "bars" => Ok(Pattern::Bars),
+            _ => Err(format!("Unknown pattern '{}'", s)),
+        }
+    }
+}
+
+/// The settings of a generated image.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GenerateOptions {
+    /// The pattern to draw.
+    pub pattern: Pattern,
+    /// The width of the image, in pixels.
+    pub width: u32,
+    /// The height of the image, in pixels.
+    pub height: u32,
+    /// The colors of the gradient, checkerboard and solid patterns.
+    pub colors: [Rgba<u8>; 2],
+    /// The width and height of the checkerboard cells, in pixels.
+    pub cell_size: u32,
+    /// The seed of the noise pattern.
+    pub seed: u64,
+}
+
+impl Default for GenerateOptions {
+    fn default() -> Self {
+        GenerateOptions {
+            pattern: Pattern::default(),
+            width: 1920,
+            height: 1080,
+            colors: [Rgba([0, 0, 0, 255]), Rgba([255, 255, 255, 255])],
+            cell_size: 32,
+            seed: 0,
+        }
+    }
+}
+
+/// The 75% bars of the top two thirds of the SMPTE pattern, from left to right.
+const BARS: [[u8; 3]; 7] = [
+    [191, 191, 191],
+    [191, 191, 0],
+    [0, 191, 191],
+    [0, 191, 0],
+    [191, 0, 191],
+    [191, 0, 0],
+    [0, 0, 191],
+];
+/// The reversed blue bars of the thin middle strip.
+const REVERSED_BARS: [[u8; 3]; 7] = [
+    [0, 0, 191],
+    [19, 19, 19],
+    [191, 0, 191],
+    [19, 19, 19],
+    [0, 191, 191],
+    [19, 19, 19],
+    [191, 191, 191],
+];
+/// The bottom row, in twelfths of a bar: -I, white, +Q, black, then the PLUGE (below
+/// black, black and above black) and black.
+const BOTTOM: [(u32, [u8; 3]); 8] = [
+    (15, [0, 33, 76]),
+    (15, [255, 255, 255]),
+    (15, [50, 0, 106]),
+    (15, [19, 19, 19]),
+    (4, [9, 9, 9]),
+    (4, [19, 19, 19]),
+    (4, [29, 29, 29]),
+    (12, [19, 19, 19]),
+];
+
+/// Generates a test image.
+///
+/// # Errors
+///
+/// This function will return an error if the width, height or cell size is zero.
+///
+/// # Examples
+///
+/// ```
+/// use image_resizer_rust::generate::{generate, GenerateOptions, Pattern};
+///
+/// let options = GenerateOptions {
+///     pattern: Pattern::Checkerboard,
+///     width: 64,
+///     height: 64,
+///     cell_size: 8,
+///     ..Default::default()
+/// };
+/// let image = generate(&options).unwrap();
+/// assert_ne!(image.get_pixel(0, 0), image.get_pixel(8, 0));
+/// ```
+pub fn generate(options: &GenerateOptions) -> Result<RgbaImage, Box<dyn Error>> {
+    let (width, height) = (options.width, options.height);
+    if width == 0 || height == 0 {
+        return Err("Generated images must be at least 1x1 pixels".into());
+    }
+    let [first, second] = options.colors;
+    let image = match options.pattern {
+        Pattern::Solid => RgbaImage::from_pixel(width, height, first),
+        Pattern::Gradient => {
+            let span = (width - 1).max(1) as f32;
+            RgbaImage::from_fn(width, height, |x, _| {
+                let t = x as f32 / span;
+                Rgba(std::array::from_fn(|c| {
+                    (first[c] as f32 + (second[c] as f32 - first[c] as f32) * t).round() as u8
+                }))
+            })
+        }
+        Pattern::Checkerboard => {
+            let cell = options.cell_size;
+            if cell == 0 {
+                return Err("The cell size must be at least 1 pixel".into());
+            }
+            RgbaImage::from_fn(width, height, |x, y| {
+                if (x / cell + y / cell).is_multiple_of(2) {
+                    first
+                } else {
+                    second
+                }
+            })
+        }
+        Pattern::Noise => {
+            let mut state = options.seed;
+            let mut image = RgbaImage::new(width, height);
+            for pixel in image.pixels_mut() {
+                let [r, g, b, ..] = splitmix64(&mut state).to_le_bytes();
+                *pixel = Rgba([r, g, b, 255]);
+            }
+            image
+        }
+        Pattern::Bars => bars(width, height),
+    };
+    Ok(image)
+}
+
+/// Draws SMPTE color bars: seven bars over two thirds of the height, a thin strip of
+/// reversed bars, and the bottom row with the PLUGE.
+fn bars(width: u32, height: u32) -> RgbaImage {
+    let top = height * 2 / 3;
+    let middle = height * 3 / 4;
+    let column = |x: u32, parts: u32| (x as u64 * parts as u64 / width as u64) as u32;
+    RgbaImage::from_fn(width, height, |x, y| {
+        let [r, g, b] = if y < top {
+            BARS[column(x, 7) as usize]
+        } else if y < middle {
+            REVERSED_BARS[column(x, 7) as usize]
+        } else {
+            let mut position = column(x, 7 * 12);
+            let mut color = BOTTOM[BOTTOM.len() - 1].1;
+            for (span, bottom) in BOTTOM {
+                if position < span {
+                    color = bottom;
+                    break;
+                }
+                position -= span;
+            }
+            color
+        };
+        Rgba([r, g, b, 255])
+    })
+}
+
+/// Returns the next number of the SplitMix64 generator, which is fast and good enough for
+/// noise, and gives the same sequence on every platform.
+pub(crate) fn splitmix64(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(pattern: Pattern) -> GenerateOptions {
+        GenerateOptions {
+            pattern,
+            width: 70,
+            height: 12,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn draws_patterns() {
+        let gradient = generate(&options(Pattern::Gradient)).unwrap();
+        assert_eq!(gradient.get_pixel(0, 5), &Rgba([0, 0, 0, 255]));
+        assert_eq!(gradient.get_pixel(69, 5), &Rgba([255, 255, 255, 255]));
+
+        let bars = generate(&options(Pattern::Bars)).unwrap();
+        assert_eq!(bars.get_pixel(0, 0), &Rgba([191, 191, 191, 255]));
+        assert_eq!(bars.get_pixel(69, 0), &Rgba([0, 0, 191, 255]));
+        assert_eq!(bars.get_pixel(0, 8), &Rgba([0, 0, 191, 255]));
+        assert_eq!(bars.get_pixel(0, 11), &Rgba([0, 33, 76, 255]));
+
+        let mut zero = options(Pattern::Solid);
+        zero.height = 0;
+        assert!(generate(&zero).is_err());
+        assert_eq!("Checkerboard".parse(), Ok(Pattern::Checkerboard));
+    }
+
+    #[test]
+    fn noise_depends_only_on_the_seed() {
+        let noise = generate(&options(Pattern::Noise)).unwrap();
+        assert_eq!(noise, generate(&options(Pattern::Noise)).unwrap());
+        let reseeded = GenerateOptions {
+            seed: 1,
+            ..options(Pattern::Noise)
+        };
+        assert_ne!(noise, generate(&reseeded).unwrap());
+        assert!(noise.pixels().all(|pixel| pixel[3] == 255));
+    }
+}
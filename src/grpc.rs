@@ -0,0 +1,388 @@
+//! A gRPC resize service, built with the `grpc` feature.
+//!
+//! Services written in other languages can resize images over the network through the
+//! `ResizeService` of `proto/resizer.proto`, with clients generated from the same file. A
+//! request carries the encoded image, or the location of an image on the server, and the
+//! resize options; the response carries the encoded output and its metadata:
+//!
+//! ```text
+//! grpcurl -plaintext -d '{"uri": "a.jpg", "options": {"width": 800}}' \
+//!     localhost:50051 image_resizer.v1.ResizeService/Resize
+//! ```
+//!
+//! Images are resized in memory, with the job options of the server, and nothing is
+//! written to disk. Files are only read from the directory given with
+//! `ResizeServer::with_root`: without it, requests must send the image itself.
+//!
+//! Jobs are run through a `Scheduler`, each client being identified by its IP address.
+//! Errors are reported with the status code matching their cause, e.g. `RESOURCE_EXHAUSTED`
+//! with a `grpc-retry-pushback-ms` header for a client over its rate limit.
+
+use crate::{
+    error::ResizeError,
+    image_format_to_string,
+    options::JobOptions,
+    pipeline::Pipeline,
+    resize_file_with,
+    schedule::Scheduler,
+    storage::{MemoryStorage, SharedStorage},
+    xattrs::XattrPolicy,
+    ImageInfo,
+};
+use image::ImageFormat;
+use std::{
+    error::Error,
+    fs, io,
+    io::Read,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use tonic::{Request, Response, Status};
+use tracing::info;
+
+/// The messages, server and client generated from `proto/resizer.proto`.
+#[allow(missing_docs, clippy::all)]
+pub mod proto {
+    tonic::include_proto!("image_resizer.v1");
+}
+
+use proto::{
+    resize_request::Source,
+    resize_service_server::{ResizeService, ResizeServiceServer},
+    ImageMetadata, ResizeRequest, ResizeResponse, SourceMetadata,
+};
+
+/// The largest request or response accepted, in bytes. gRPC defaults to 4 MiB, which is
+/// less than many photos.
+pub const MAX_MESSAGE_SIZE: usize = 64 * 1024 * 1024;
+
+/// The maximum number of pixels of a source image when the job options set no
+/// `Limits::max_pixels`, so that a request cannot make the server decode an enormous image.
+pub const DEFAULT_MAX_PIXELS: u64 = 100_000_000;
+
+/// How often the server checks whether it was cancelled.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Implements `ResizeService` with the resizing functions of this crate.
+#[derive(Debug, Clone)]
+pub struct ResizeServer {
+    options: JobOptions,
+    scheduler: Scheduler,
+    root: Option<PathBuf>,
+}
+
+impl ResizeServer {
+    /// Creates a service resizing images with the given job options, through the given
+    /// scheduler. Requests can change the JPEG quality; the storage, overwrite policy and
+    /// extended attributes of the options are not used.
+    ///
+    /// Images with more than `DEFAULT_MAX_PIXELS` pixels are rejected unless the options
+    /// set another `Limits::max_pixels`.
+    pub fn new(mut options: JobOptions, scheduler: Scheduler) -> Self {
+        options.limits.max_pixels = options.limits.max_pixels.or(Some(DEFAULT_MAX_PIXELS));
+        ResizeServer {
+            options,
+            scheduler,
+            root: None,
+        }
+    }
+
+    /// Lets requests name images in `root`, or in its subdirectories, instead of sending
+    /// them. The images are read through the storage of the job options.
+    pub fn with_root(mut self, root: impl Into<PathBuf>) -> Self {
+        self.root = Some(root.into());
+        self
+    }
+
+    /// Answers requests on `address` until the job options are cancelled.
+    ///
+    /// Once cancelled, the server stops accepting connections and waits for the requests
+    /// in progress, whose jobs stop at their next stage.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the address cannot be bound.
+    pub fn serve(self, address: SocketAddr) -> Result<(), Box<dyn Error>> {
+        let cancel = self.options.cancel.clone();
+        let service = ResizeServiceServer::new(self)
+            .max_decoding_message_size(MAX_MESSAGE_SIZE)
+            .max_encoding_message_size(MAX_MESSAGE_SIZE);
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()?;
+        runtime.block_on(async {
+            let listener = tokio::net::TcpListener::bind(address)
+                .await
+                .map_err(|e| format!("Failed to listen on {}: {}", address, e))?;
+            info!("Listening on {}", listener.local_addr()?);
+            let incoming = tonic::transport::server::TcpIncoming::from(listener);
+            tonic::transport::Server::builder()
+                .add_service(service)
+                .serve_with_incoming_shutdown(incoming, async {
+                    while !cancel.is_cancelled() {
+                        tokio::time::sleep(POLL_INTERVAL).await;
+                    }
+                    info!("Stopping");
+                })
+                .await?;
+            Ok(())
+        })
+    }
+
+    /// Runs the job of a request on the calling thread.
+    ///
+    /// Images named by the request are only read once the scheduler admits the job, so that
+    /// a client over its rate limit cannot make the server read files.
+    fn run(&self, request: ResizeRequest, client: &str) -> Result<ResizeResponse, Status> {
+        let options = request.options.unwrap_or_default();
+        let quality = match options.quality {
+            None => None,
+            Some(quality @ 1..=100) => Some(quality as u8),
+            Some(quality) => {
+                return Err(Status::invalid_argument(format!(
+                    "Invalid quality {}",
+                    quality
+                )))
+            }
+        };
+        let source = request
+            .source
+            .ok_or_else(|| Status::invalid_argument("The image is missing"))?;
+
+        let files = MemoryStorage::new();
+        let mut job_options = JobOptions {
+            storage: SharedStorage::new(files.clone()),
+            xattrs: XattrPolicy::default(),
+            ..self.options.clone()
+        };
+        if quality.is_some() {
+            job_options.encode.jpeg_quality = quality;
+        }
+        let info = self
+            .scheduler
+            .run(client, || {
+                let image = match source {
+                    Source::Image(image) => image,
+                    Source::Uri(uri) => self.read(&uri)?,
+                };
+                let format = options.format.clone().unwrap_or_else(|| {
+                    match image::guess_format(&image) {
+                        Ok(ImageFormat::Png) => "png",
+                        _ => "jpeg",
+                    }
+                    .to_string()
+                });
+                files.insert("input", image);
+                resize_file_with(
+                    Path::new("input"),
+                    Path::new("output"),
+                    options.width.as_ref(),
+                    options.height.as_ref(),
+                    Some(&format),
+                    &job_options,
+                    &Pipeline::standard(),
+                )
+            })
+            .map_err(status)?;
+        let image = files
+            .get(&info.path)
+            .ok_or_else(|| Status::internal("The output was not written"))?;
+        Ok(ResizeResponse {
+            image,
+            metadata: Some(metadata(&info)),
+        })
+    }
+
+    /// Reads the image at `uri`, which must be within the root directory.
+    fn read(&self, uri: &str) -> Result<Vec<u8>, Box<dyn Error>> {
+        let root = self
+            .root
+            .as_ref()
+            .ok_or("This server does not read files, send the image instead")?;
+        let path = root.join(uri.strip_prefix("file://").unwrap_or(uri));
+        let within_root = fs::canonicalize(root)
+            .and_then(|root| Ok(fs::canonicalize(&path)?.starts_with(root)))
+            .unwrap_or(false);
+        if !within_root {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{} is not an image of this server", uri),
+            )
+            .into());
+        }
+        let mut contents = Vec::new();
+        self.options
+            .storage
+            .open_read(&path)?
+            .read_to_end(&mut contents)?;
+        Ok(contents)
+    }
+}
+
+#[tonic::async_trait]
+impl ResizeService for ResizeServer {
+    async fn resize(
+        &self,
+        request: Request<ResizeRequest>,
+    ) -> Result<Response<ResizeResponse>, Status> {
+        let client = request
+            .remote_addr()
+            .map_or_else(|| "unknown".to_string(), |address| address.ip().to_string());
+        let server = self.clone();
+        let response =
+            tokio::task::spawn_blocking(move || server.run(request.into_inner(), &client))
+                .await
+                .map_err(|e| Status::internal(e.to_string()))??;
+        Ok(Response::new(response))
+    }
+}
+
+/// Returns the metadata of an output.
+fn metadata(info: &ImageInfo) -> ImageMetadata {
+    let format_name = |format| image_format_to_string(format).to_lowercase();
+    ImageMetadata {
+        width: info.width,
+        height: info.height,
+        format: format_name(info.format),
+        file_size: info.file_size,
+        source: info.source.as_ref().map(|source| SourceMetadata {
+            width: source.width,
+            height: source.height,
+            format: format_name(source.format),
+            file_size: source.file_size,
+        }),
+        duration_ms: info
+            .duration
+            .map_or(0.0, |duration| duration.as_secs_f64() * 1000.0),
+    }
+}
+
+/// Converts the error of a job to the status with the matching code.
+fn status(error: Box<dyn Error>) -> Status {
+    let message = error.to_string();
+    if let Some(error) = error.downcast_ref::<ResizeError>() {
+        return match error {
+            ResizeError::Cancelled => Status::cancelled(message),
+            ResizeError::QueueTimeout(_) => Status::unavailable(message),
+            ResizeError::RateLimited { retry_after } => {
+                let mut status = Status::resource_exhausted(message);
+                status.metadata_mut().insert(
+                    "grpc-retry-pushback-ms",
+                    (retry_after.as_millis() as u64).into(),
+                );
+                status
+            }
+            _ => Status::invalid_argument(message),
+        };
+    }
+    match error.downcast_ref::<io::Error>().map(io::Error::kind) {
+        Some(io::ErrorKind::NotFound) => Status::not_found(message),
+        Some(io::ErrorKind::PermissionDenied) => Status::permission_denied(message),
+        Some(_) => Status::internal(message),
+        None => Status::invalid_argument(message),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schedule::{RateLimit, SchedulerConfig};
+    use image::RgbImage;
+    use std::io::Cursor;
+    use tempfile::TempDir;
+    use tonic::Code;
+
+    fn png(width: u32, height: u32) -> Vec<u8> {
+        let mut contents = Vec::new();
+        RgbImage::new(width, height)
+            .write_to(&mut Cursor::new(&mut contents), ImageFormat::Png)
+            .unwrap();
+        contents
+    }
+
+    fn request(source: Source, width: Option<u32>) -> Request<ResizeRequest> {
+        Request::new(ResizeRequest {
+            source: Some(source),
+            options: Some(proto::ResizeOptions {
+                width,
+                ..Default::default()
+            }),
+        })
+    }
+
+    fn resize(
+        server: &ResizeServer,
+        request: Request<ResizeRequest>,
+    ) -> Result<ResizeResponse, Status> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        runtime
+            .block_on(server.resize(request))
+            .map(Response::into_inner)
+    }
+
+    #[test]
+    fn resizes_sent_images() {
+        let server = ResizeServer::new(
+            JobOptions::default(),
+            Scheduler::new(SchedulerConfig::default()).unwrap(),
+        );
+        assert_eq!(server.options.limits.max_pixels, Some(DEFAULT_MAX_PIXELS));
+        let response = resize(&server, request(Source::Image(png(40, 20)), Some(20))).unwrap();
+        let metadata = response.metadata.unwrap();
+        assert_eq!((metadata.width, metadata.height), (20, 10));
+        assert_eq!(metadata.format, "png");
+        assert_eq!(metadata.file_size, response.image.len() as u64);
+        assert_eq!(metadata.source.unwrap().width, 40);
+        let output = image::load_from_memory(&response.image).unwrap();
+        assert_eq!(output.width(), 20);
+
+        let mut jpeg = request(Source::Image(png(40, 20)), Some(10));
+        jpeg.get_mut().options.as_mut().unwrap().format = Some("jpg".into());
+        let response = resize(&server, jpeg).unwrap();
+        assert_eq!(response.metadata.unwrap().format, "jpeg");
+
+        let error = resize(&server, request(Source::Image(b"nope".to_vec()), Some(10)));
+        assert_eq!(error.unwrap_err().code(), Code::InvalidArgument);
+        let error = resize(&server, request(Source::Uri("a.png".into()), Some(10)));
+        assert_eq!(error.unwrap_err().code(), Code::InvalidArgument);
+    }
+
+    #[test]
+    fn reads_images_within_the_root() {
+        let dir = TempDir::new().expect("Failed to create a temp dir");
+        let root = dir.path().join("root");
+        fs::create_dir(&root).unwrap();
+        fs::write(root.join("a.png"), png(40, 20)).unwrap();
+        fs::write(dir.path().join("secret.png"), png(40, 20)).unwrap();
+        let scheduler = Scheduler::new(SchedulerConfig {
+            rate_limit: Some(RateLimit {
+                requests: 3,
+                per: Duration::from_secs(60),
+            }),
+            ..Default::default()
+        })
+        .unwrap();
+        let server = ResizeServer::new(JobOptions::default(), scheduler).with_root(&root);
+
+        let response = resize(
+            &server,
+            request(Source::Uri("file://a.png".into()), Some(8)),
+        );
+        assert_eq!(response.unwrap().metadata.unwrap().height, 4);
+        let error = resize(
+            &server,
+            request(Source::Uri("../secret.png".into()), Some(8)),
+        );
+        assert_eq!(error.unwrap_err().code(), Code::NotFound);
+
+        let response = resize(&server, request(Source::Uri("a.png".into()), Some(8)));
+        assert!(response.is_ok());
+        // Over the rate limit, files are not read, even missing ones
+        let error = resize(&server, request(Source::Uri("b.png".into()), Some(8))).unwrap_err();
+        assert_eq!(error.code(), Code::ResourceExhausted);
+        assert!(error.metadata().get("grpc-retry-pushback-ms").is_some());
+    }
+}
@@ -0,0 +1,228 @@
+//! Perceptual image hashes.
+//!
+//! A perceptual hash condenses the appearance of an image into 64 bits, such that images
+//! that look alike get hashes differing in few bits, even after resizing, re-encoding or
+//! small edits. The number of differing bits (the Hamming distance) measures how different
+//! two images look; this is used to find near-duplicate inputs in a batch.
+//!
+//! Two algorithms are available:
+//! - dHash compares the brightness of neighboring pixels of a 9x8 grayscale thumbnail. It
+//!   is fast and works well for finding copies of the same image.
+//! - pHash compares the low frequencies of the discrete cosine transform of a 32x32
+//!   grayscale thumbnail to their median. It is slower but more robust to edits such as
+//!   changes of contrast or small crops.
+
+use image::{imageops::FilterType, DynamicImage, GrayImage};
+use std::{f64::consts::PI, fmt, str::FromStr};
+
+/// The size of the thumbnail the pHash is computed from.
+const PHASH_SIZE: usize = 32;
+/// The size of the block of low frequencies kept by the pHash.
+const PHASH_BLOCK: usize = 8;
+
+/// A 64-bit perceptual hash.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ImageHash(pub u64);
+
+impl ImageHash {
+    /// Returns the number of bits that differ between two hashes, from 0 (identical) to 64.
+    pub fn distance(&self, other: &ImageHash) -> u32 {
+        (self.0 ^ other.0).count_ones()
+    }
+}
+
+impl fmt::Display for ImageHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:016x}", self.0)
+    }
+}
+
+/// The perceptual hash algorithms.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum HashAlgorithm {
+    /// The difference hash.
+    #[default]
+    DHash,
+    /// The DCT-based perceptual hash.
+    PHash,
+}
+
+impl HashAlgorithm {
+    /// Computes the hash of an image.
+    pub fn hash(&self, image: &DynamicImage) -> ImageHash {
+        match self {
+            HashAlgorithm::DHash => dhash(image),
+            HashAlgorithm::PHash => phash(image),
+        }
+    }
+}
+
+impl fmt::Display for HashAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            HashAlgorithm::DHash => "dhash",
+            HashAlgorithm::PHash => "phash",
+        };
+        f.write_str(name)
+    }
+}
+
+impl FromStr for HashAlgorithm {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "dhash" => Ok(HashAlgorithm::DHash),
+            "phash" => Ok(HashAlgorithm::PHash),
+            _ => Err(format!("Unknown hash algorithm '{}'", s)),
+        }
+    }
+}
+
+/// Finds near-duplicates in a list of hashes.
+///
+/// Each hash is compared to the earlier hashes that are not duplicates themselves, so that
+/// the first image of a group of similar images is the one kept.
+///
+/// # Arguments
+///
+/// * `hashes` - The hashes to compare, in order. `None` entries are never duplicates.
+/// * `threshold` - The maximum distance between two hashes of similar images.
+///
+/// # Returns
+///
+/// For each hash, the index of the earlier hash it is a near-duplicate of, if any.
+pub fn find_duplicates(hashes: &[Option<ImageHash>], threshold: u32) -> Vec<Option<usize>> {
+    let mut originals: Vec<(usize, ImageHash)> = Vec::new();
+    hashes
+        .iter()
+        .enumerate()
+        .map(|(index, hash)| {
+            let hash = (*hash)?;
+            let original = originals
+                .iter()
+                .find(|(_, original)| original.distance(&hash) <= threshold)
+                .map(|&(original, _)| original);
+            if original.is_none() {
+                originals.push((index, hash));
+            }
+            original
+        })
+        .collect()
+}
+
+/// Returns a grayscale thumbnail of exactly `width` by `height` pixels.
+fn grayscale(image: &DynamicImage, width: u32, height: u32) -> GrayImage {
+    image
+        .resize_exact(width, height, FilterType::Triangle)
+        .into_luma8()
+}
+
+/// Computes the difference hash: one bit per pixel of an 8x8 grid, set if the pixel is
+/// darker than its right neighbor.
+fn dhash(image: &DynamicImage) -> ImageHash {
+    let pixels = grayscale(image, 9, 8);
+    let mut hash = 0u64;
+    for y in 0..8 {
+        for x in 0..8 {
+            let darker = pixels.get_pixel(x, y)[0] < pixels.get_pixel(x + 1, y)[0];
+            hash = hash << 1 | u64::from(darker);
+        }
+    }
+    ImageHash(hash)
+}
+
+/// Computes the DCT hash: one bit per coefficient of the 8x8 lowest frequencies, set if the
+/// coefficient is above their median.
+fn phash(image: &DynamicImage) -> ImageHash {
+    let pixels = grayscale(image, PHASH_SIZE as u32, PHASH_SIZE as u32);
+    let values: Vec<f64> = pixels.pixels().map(|pixel| pixel[0] as f64).collect();
+
+    // Separable 2D DCT-II, only computing the kept frequencies
+    let cosines: Vec<f64> = (0..PHASH_BLOCK * PHASH_SIZE)
+        .map(|i| {
+            let (u, x) = (i / PHASH_SIZE, i % PHASH_SIZE);
+            ((2 * x + 1) as f64 * u as f64 * PI / (2 * PHASH_SIZE) as f64).cos()
+        })
+        .collect();
+    let cosine = |u: usize, x: usize| cosines[u * PHASH_SIZE + x];
+    let rows: Vec<f64> = (0..PHASH_SIZE * PHASH_BLOCK)
+        .map(|i| {
+            let (y, u) = (i / PHASH_BLOCK, i % PHASH_BLOCK);
+            (0..PHASH_SIZE)
+                .map(|x| values[y * PHASH_SIZE + x] * cosine(u, x))
+                .sum()
+        })
+        .collect();
+    let coefficients: Vec<f64> = (0..PHASH_BLOCK * PHASH_BLOCK)
+        .map(|i| {
+            let (v, u) = (i / PHASH_BLOCK, i % PHASH_BLOCK);
+            (0..PHASH_SIZE)
+                .map(|y| rows[y * PHASH_BLOCK + u] * cosine(v, y))
+                .sum()
+        })
+        .collect();
+
+    // The DC coefficient only reflects the average brightness and is left out of the median
+    let mut sorted = coefficients[1..].to_vec();
+    sorted.sort_by(f64::total_cmp);
+    let median = sorted[sorted.len() / 2];
+
+    ImageHash(
+        coefficients
+            .iter()
+            .fold(0, |hash, &c| hash << 1 | u64::from(c > median)),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgb, RgbImage};
+
+    fn waves(width: u32, height: u32) -> DynamicImage {
+        DynamicImage::ImageRgb8(RgbImage::from_fn(width, height, |x, y| {
+            let (x, y) = (x as f64 / 20.0, y as f64 / 30.0);
+            let value = (127.5 + 127.5 * (x.sin() * y.cos() + x * 0.2).sin()) as u8;
+            Rgb([value, value / 2, 255 - value])
+        }))
+    }
+
+    #[test]
+    fn similar_images_have_close_hashes() {
+        let image = waves(256, 192);
+        for algorithm in [HashAlgorithm::DHash, HashAlgorithm::PHash] {
+            let original = algorithm.hash(&image);
+            let resized = algorithm.hash(&image.resize_exact(100, 75, FilterType::Lanczos3));
+            let different = algorithm.hash(&image.fliph());
+
+            assert!(original.distance(&resized) <= 10, "{}", algorithm);
+            assert!(original.distance(&different) > 10, "{}", algorithm);
+            assert_eq!(
+                algorithm.to_string().parse::<HashAlgorithm>(),
+                Ok(algorithm)
+            );
+        }
+    }
+
+    #[test]
+    fn finds_duplicates_of_the_first_image() {
+        let hashes = [
+            Some(ImageHash(0b0000)),
+            Some(ImageHash(0b0001)),
+            None,
+            Some(ImageHash(0xff00)),
+            Some(ImageHash(0b0011)),
+        ];
+        // The last hash is close to the second one, but that one is a duplicate itself
+        assert_eq!(
+            find_duplicates(&hashes, 1),
+            vec![None, Some(0), None, None, None]
+        );
+        assert_eq!(
+            find_duplicates(&hashes, 2),
+            vec![None, Some(0), None, None, Some(0)]
+        );
+        assert_eq!(ImageHash(0xff00).to_string(), "000000000000ff00");
+    }
+}
@@ -0,0 +1,252 @@
+//! In-place resizing.
+//!
+//! An image resized in place replaces its source file, keeping its path and format. The
+//! result is written to a temporary file and renamed over the source, so an interrupted run
+//! never leaves a truncated image behind. The original can be kept as a backup, either next
+//! to the source with the `.bak` suffix or in a backup directory. It is moved there before
+//! the image is resized from the backup, and moved back if resizing fails.
+//!
+//! Backups are moved with the file system, so in-place resizing does not support other
+//! storage backends.
+
+use crate::{
+    options::JobOptions, overwrite::OverwritePolicy, pipeline::Pipeline, resize_file_with,
+    save_format_and_path, ImageInfo, SourceInfo,
+};
+use image::ImageFormat;
+use std::{
+    error::Error,
+    fs, io,
+    path::{Path, PathBuf},
+};
+use tracing::info;
+
+/// The suffix appended to the name of backups kept next to their source.
+pub const BACKUP_SUFFIX: &str = ".bak";
+
+/// Where the originals of images resized in place are kept.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum Backup {
+    /// The originals are replaced.
+    #[default]
+    None,
+    /// The originals are kept next to their source, as `photo.jpg.bak`.
+    Suffix,
+    /// The originals are moved to this directory, keeping their file name.
+    Dir(PathBuf),
+}
+
+impl Backup {
+    /// Returns the path of the backup of `input`, or `None` if originals are not kept.
+    pub fn path_of(&self, input: &Path) -> Option<PathBuf> {
+        let name = input.file_name()?;
+        match self {
+            Backup::None => None,
+            Backup::Suffix => {
+                let mut name = name.to_os_string();
+                name.push(BACKUP_SUFFIX);
+                Some(input.with_file_name(name))
+            }
+            Backup::Dir(dir) => Some(dir.join(name)),
+        }
+    }
+}
+
+/// Resizes an image file and replaces it with the result.
+///
+/// # Arguments
+///
+/// * `input` - The path of the image file, which must be a JPEG or PNG image with a
+///   matching extension.
+/// * `width` - An optional new width for the image.
+/// * `height` - An optional new height for the image.
+/// * `backup` - Where the original is kept.
+/// * `options` - The job options. Their overwrite policy is ignored, since the source is
+///   always replaced.
+/// * `pipeline` - The stages to run.
+///
+/// # Returns
+///
+/// A `Result` containing an `ImageInfo` describing the resized image.
+///
+/// # Errors
+///
+/// This function will return an error if:
+/// - The image is not a JPEG or PNG image, or its extension does not match its format.
+/// - The backup already exists, or the original cannot be moved to it.
+/// - Resizing the image fails, as with `resize_file_with`. The original is then moved back
+///   from its backup.
+pub fn resize_in_place(
+    input: &Path,
+    width: Option<&u32>,
+    height: Option<&u32>,
+    backup: &Backup,
+    options: &JobOptions,
+    pipeline: &Pipeline,
+) -> Result<ImageInfo, Box<dyn Error>> {
+    options.cancel.check()?;
+    let source = SourceInfo::read_from(&*options.storage, input)?;
+    let format = match source.format {
+        ImageFormat::Jpeg => String::from("jpeg"),
+        ImageFormat::Png => String::from("png"),
+        ImageFormat::Bmp => String::from("bmp"),
+        ImageFormat::Tga => String::from("tga"),
+        other => {
+            return Err(format!(
+                "Cannot resize {:?} in place: {:?} images cannot be written",
+                input, other
+            )
+            .into())
+        }
+    };
+    let (_, path) =
+        save_format_and_path(input, Some(&format), &options.encoders, || source.format)?;
+    if path != input {
+        return Err(format!(
+            "Cannot resize {:?} in place: its extension does not match its {:?} format",
+            input, source.format
+        )
+        .into());
+    }
+    let options = JobOptions {
+        overwrite: OverwritePolicy::Always,
+        ..options.clone()
+    };
+
+    let Some(backup_path) = backup.path_of(input) else {
+        return resize_file_with(
+            input,
+            input,
+            width,
+            height,
+            Some(&format),
+            &options,
+            pipeline,
+        );
+    };
+    if backup_path.exists() {
+        return Err(format!("The backup {:?} already exists", backup_path).into());
+    }
+    if let Some(dir) = backup_path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    move_file(input, &backup_path)
+        .map_err(|e| format!("Failed to move {:?} to {:?}: {}", input, backup_path, e))?;
+    info!("Moved the original to {:?}", backup_path);
+
+    match resize_file_with(
+        &backup_path,
+        input,
+        width,
+        height,
+        Some(&format),
+        &options,
+        pipeline,
+    ) {
+        Ok(info) => Ok(info),
+        Err(error) => match move_file(&backup_path, input) {
+            Ok(()) => Err(error),
+            Err(e) => Err(format!(
+                "{} (the original could not be restored from {:?}: {})",
+                error, backup_path, e
+            )
+            .into()),
+        },
+    }
+}
+
+/// Moves a file, copying it if it cannot be renamed, e.g. to another file system.
+fn move_file(from: &Path, to: &Path) -> io::Result<()> {
+    if fs::rename(from, to).is_ok() {
+        return Ok(());
+    }
+    fs::copy(from, to)?;
+    fs::remove_file(from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{GenericImageView, RgbImage};
+    use tempfile::TempDir;
+
+    #[test]
+    fn replaces_the_source() {
+        let dir = TempDir::new().expect("Failed to create a temp dir");
+        let input = dir.path().join("photo.png");
+        RgbImage::new(40, 20).save(&input).unwrap();
+        let pipeline = Pipeline::standard();
+        let options = JobOptions::default();
+
+        let info =
+            resize_in_place(&input, Some(&20), None, &Backup::None, &options, &pipeline).unwrap();
+        assert_eq!(info.path, input);
+        assert_eq!(image::open(&input).unwrap().dimensions(), (20, 10));
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+
+        let backup = Backup::Suffix;
+        resize_in_place(&input, Some(&10), None, &backup, &options, &pipeline).unwrap();
+        assert_eq!(image::open(&input).unwrap().dimensions(), (10, 5));
+        let kept = dir.path().join("photo.png.bak");
+        let reader = image::ImageReader::open(&kept).unwrap();
+        let dimensions = reader.with_guessed_format().unwrap().into_dimensions();
+        assert_eq!(dimensions.unwrap(), (20, 10));
+        // Existing backups are never replaced
+        assert!(resize_in_place(&input, Some(&5), None, &backup, &options, &pipeline).is_err());
+        assert_eq!(image::open(&input).unwrap().dimensions(), (10, 5));
+
+        let misnamed = dir.path().join("photo.jpg");
+        fs::copy(&input, &misnamed).unwrap();
+        let error = resize_in_place(
+            &misnamed,
+            Some(&5),
+            None,
+            &Backup::None,
+            &options,
+            &pipeline,
+        )
+        .unwrap_err();
+        assert!(error.to_string().contains("does not match"));
+    }
+
+    #[test]
+    fn restores_the_original_on_failure() {
+        let dir = TempDir::new().expect("Failed to create a temp dir");
+        let input = dir.path().join("photo.png");
+        RgbImage::new(40, 20).save(&input).unwrap();
+        let backups = dir.path().join("originals");
+        let backup = Backup::Dir(backups.clone());
+        let mut pipeline = Pipeline::standard();
+        pipeline
+            .insert_before(
+                crate::pipeline::ENCODE,
+                crate::pipeline::ImageStage::new("fail", |_| Err("broken stage".into())),
+            )
+            .unwrap();
+
+        let error = resize_in_place(
+            &input,
+            Some(&20),
+            None,
+            &backup,
+            &JobOptions::default(),
+            &pipeline,
+        )
+        .unwrap_err();
+        assert!(error.to_string().contains("broken stage"));
+        assert_eq!(image::open(&input).unwrap().dimensions(), (40, 20));
+        assert!(!backups.join("photo.png").exists());
+
+        resize_in_place(
+            &input,
+            Some(&20),
+            None,
+            &backup,
+            &JobOptions::default(),
+            &Pipeline::standard(),
+        )
+        .unwrap();
+        assert_eq!(image::open(&input).unwrap().dimensions(), (20, 10));
+        assert!(backups.join("photo.png").exists());
+    }
+}
@@ -0,0 +1,510 @@
+//! The protocol of the resize daemon.
+//!
+//! Tools resizing images often, such as editors generating previews, can keep a daemon
+//! running instead of starting the resizer for every image. The daemon listens on a Unix
+//! domain socket and reads jobs as JSON lines, one object per line, with the keys of a JSON
+//! job file (see `jobfile`), an optional JPEG `quality`, an optional `lqip`, and an optional
+//! `id` of any type:
+//!
+//! ```text
+//! {"id": 1, "input": "photos/a.jpg", "output": "previews/a.jpg", "width": 800, "quality": 80}
+//! ```
+//!
+//! `output` is required. `lqip` is `true` or the largest side of a placeholder in pixels,
+//! adding the output's low-quality image placeholder to the response as a data URI (see
+//! `lqip::data_uri`). Each job is answered by a line, in the order the jobs were sent on the
+//! connection, repeating its `id`:
+//!
+//! ```text
+//! {"duration_ms":41.2,"file_size":81234,"format":"jpeg","height":533,"id":1,"mime_type":"image/jpeg","ok":true,"output":"previews/a.jpg","width":800}
+//! {"error":"No such file or directory (os error 2)","id":2,"ok":false}
+//! ```
+//!
+//! Jobs are run through a `Scheduler`, which caps the number of jobs running at once across
+//! all connections. Clients are identified by the user id of the peer process, so a rate
+//! limit applies to all the connections of a user together, and cannot be avoided by opening
+//! new connections. Existing outputs are replaced. The socket is only accessible to the
+//! user running the daemon, since its clients can read and write any file that user can.
+//!
+//! Request lines longer than `MAX_LINE_LENGTH` bytes are answered with an error and the
+//! connection is closed, so a client cannot make the daemon buffer an endless line.
+
+use crate::{
+    image_format_to_string, jobfile, lqip, options::JobOptions, overwrite::OverwritePolicy,
+    pipeline::Pipeline, resize_file_with, schedule::Scheduler,
+};
+use serde_json::{json, Map, Value};
+use std::{
+    error::Error,
+    io::{self, BufRead, Read, Write},
+};
+
+/// The longest request line accepted, in bytes, without its line break.
+pub const MAX_LINE_LENGTH: usize = 64 * 1024;
+
+/// Runs the job of one request line and returns the response line, without its line
+/// break.
+///
+/// # Arguments
+///
+/// * `line` - The request, a JSON object.
+/// * `options` - The job options. The JPEG quality can be changed by the request, and
+///   existing outputs are always replaced.
+/// * `scheduler` - The scheduler the job is run through.
+/// * `client` - Identifies the client of the request for the scheduler, e.g. its user id.
+pub fn handle_line(
+    line: &str,
+    options: &JobOptions,
+    scheduler: &Scheduler,
+    client: &str,
+) -> String {
+    let mut request = match serde_json::from_str::<Value>(line) {
+        Ok(Value::Object(request)) => request,
+        Ok(_) => return error_response(Value::Null, "Expected a JSON object".into()),
+        Err(e) => return error_response(Value::Null, format!("Invalid JSON: {}", e)),
+    };
+    let id = request.remove("id").unwrap_or(Value::Null);
+    match run_request(request, options, scheduler, client) {
+        Ok(mut response) => {
+            response.insert("id".into(), id);
+            response.insert("ok".into(), Value::Bool(true));
+            Value::Object(response).to_string()
+        }
+        Err(e) => error_response(id, e.to_string()),
+    }
+}
+
+/// Answers the requests of a connection, one line at a time, until the end of its input.
+///
+/// A line longer than `MAX_LINE_LENGTH` is answered with an error, and ends the connection.
+///
+/// # Errors
+///
+/// This function will return an error if the connection cannot be read or written, or
+/// sends a line that is too long or not valid UTF-8.
+pub fn serve_connection(
+    mut reader: impl BufRead,
+    mut writer: impl Write,
+    options: &JobOptions,
+    scheduler: &Scheduler,
+    client: &str,
+) -> io::Result<()> {
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        // One byte more than the limit tells a line that is too long from one that fits
+        let read = (&mut reader)
+            .take(MAX_LINE_LENGTH as u64 + 2)
+            .read_until(b'\n', &mut line)?;
+        if read == 0 {
+            return Ok(());
+        }
+        if line.last() == Some(&b'\n') {
+            line.pop();
+            if line.last() == Some(&b'\r') {
+                line.pop();
+            }
+        }
+        if line.len() > MAX_LINE_LENGTH {
+            let message = format!("Request lines are limited to {} bytes", MAX_LINE_LENGTH);
+            writeln!(writer, "{}", error_response(Value::Null, message.clone()))?;
+            writer.flush()?;
+            return Err(io::Error::new(io::ErrorKind::InvalidData, message));
+        }
+        let line = std::str::from_utf8(&line)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let response = handle_line(line, options, scheduler, client);
+        writeln!(writer, "{}", response)?;
+        writer.flush()?;
+    }
+}
+
+/// Runs the job of a request and returns the fields of its response.
+fn run_request(
+    mut request: Map<String, Value>,
+    options: &JobOptions,
+    scheduler: &Scheduler,
+    client: &str,
+) -> Result<Map<String, Value>, Box<dyn Error>> {
+    let quality = match request.remove("quality") {
+        None | Some(Value::Null) => None,
+        Some(value) => Some(
+            value
+                .as_u64()
+                .and_then(|quality| u8::try_from(quality).ok())
+                .filter(|quality| (1..=100).contains(quality))
+                .ok_or_else(|| format!("Invalid quality {}", value))?,
+        ),
+    };
+    let lqip_size = match request.remove("lqip") {
+        None | Some(Value::Null) | Some(Value::Bool(false)) => None,
+        Some(Value::Bool(true)) => Some(lqip::DEFAULT_SIZE),
+        Some(value) => Some(
+            value
+                .as_u64()
+                .and_then(|size| u32::try_from(size).ok())
+                .filter(|size| (1..=lqip::MAX_SIZE).contains(size))
+                .ok_or_else(|| format!("Invalid lqip {}", value))?,
+        ),
+    };
+    let job = jobfile::parse_object(&Value::Object(request))?;
+    let output = job.output.as_ref().ok_or("The output is missing")?;
+    let mut options = JobOptions {
+        overwrite: OverwritePolicy::Always,
+        ..options.clone()
+    };
+    if quality.is_some() {
+        options.encode.jpeg_quality = quality;
+    }
+
+    let info = scheduler.run(client, || {
+        resize_file_with(
+            &job.input,
+            output.as_ref(),
+            job.width.as_ref(),
+            job.height.as_ref(),
+            job.format.as_ref(),
+            &options,
+            &Pipeline::standard(),
+        )
+    })?;
+    let duration_ms = info
+        .duration
+        .map(|duration| duration.as_secs_f64() * 1000.0);
+    let mut response = Map::from_iter([
+        ("output".to_string(), json!(info.path)),
+        ("width".to_string(), json!(info.width)),
+        ("height".to_string(), json!(info.height)),
+        (
+            "format".to_string(),
+            json!(image_format_to_string(info.format).to_lowercase()),
+        ),
+        ("mime_type".to_string(), json!(info.mime_type())),
+        ("file_size".to_string(), json!(info.file_size)),
+        ("duration_ms".to_string(), json!(duration_ms)),
+    ]);
+    if let Some(size) = lqip_size {
+        // The output is decoded rather than the input, since it is smaller
+        let mut data = Vec::new();
+        options
+            .storage
+            .open_read(&info.path)?
+            .read_to_end(&mut data)?;
+        let uri = lqip::data_uri(&image::load_from_memory(&data)?, size)?;
+        response.insert("lqip".to_string(), json!(uri));
+    }
+    Ok(response)
+}
+
+/// Returns the response line of a failed request.
+fn error_response(id: Value, error: String) -> String {
+    json!({ "id": id, "ok": false, "error": error }).to_string()
+}
+
+#[cfg(unix)]
+pub use self::unix::serve;
+
+#[cfg(unix)]
+mod unix {
+    use super::serve_connection;
+    use crate::{options::JobOptions, schedule::Scheduler};
+    use std::{
+        error::Error,
+        fs,
+        io::{self, BufReader},
+        net::Shutdown,
+        os::unix::{
+            fs::{FileTypeExt, PermissionsExt},
+            io::AsRawFd,
+            net::{UnixListener, UnixStream},
+        },
+        path::Path,
+        thread,
+        time::Duration,
+    };
+    use tracing::{info, warn};
+
+    /// How often the listener checks whether the daemon was cancelled.
+    const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+    /// Listens on a Unix domain socket and answers the requests of every connection, each
+    /// on its own thread, until `options.cancel` is cancelled.
+    ///
+    /// Once cancelled, no connection is accepted and no further request is read. The jobs
+    /// in progress stop at their next stage and are answered with an error. The socket file
+    /// is removed before returning.
+    ///
+    /// # Arguments
+    ///
+    /// * `socket` - The path of the socket. A stale socket left by a daemon that did not
+    ///   stop cleanly is replaced.
+    /// * `options` - The job options.
+    /// * `scheduler` - The scheduler the jobs of all connections are run through.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if another daemon listens on the socket, or if
+    /// the socket cannot be created or accept connections.
+    pub fn serve(
+        socket: &Path,
+        options: &JobOptions,
+        scheduler: &Scheduler,
+    ) -> Result<(), Box<dyn Error>> {
+        if let Ok(metadata) = fs::symlink_metadata(socket) {
+            if !metadata.file_type().is_socket() {
+                return Err(format!("{:?} exists and is not a socket", socket).into());
+            }
+            if UnixStream::connect(socket).is_ok() {
+                return Err(format!("A daemon is already listening on {:?}", socket).into());
+            }
+            fs::remove_file(socket)
+                .map_err(|e| format!("Failed to remove the stale socket {:?}: {}", socket, e))?;
+        }
+        let listener = UnixListener::bind(socket)
+            .map_err(|e| format!("Failed to listen on {:?}: {}", socket, e))?;
+        let result = accept_until_cancelled(&listener, socket, options, scheduler);
+        let _ = fs::remove_file(socket);
+        result
+    }
+
+    /// Accepts connections on a bound socket, then waits for them once cancelled.
+    fn accept_until_cancelled(
+        listener: &UnixListener,
+        socket: &Path,
+        options: &JobOptions,
+        scheduler: &Scheduler,
+    ) -> Result<(), Box<dyn Error>> {
+        fs::set_permissions(socket, fs::Permissions::from_mode(0o600))?;
+        listener.set_nonblocking(true)?;
+        info!("Listening on {:?}", socket);
+
+        let mut connections = Vec::new();
+        let mut count = 0u64;
+        while !options.cancel.is_cancelled() {
+            let stream = match listener.accept() {
+                Ok((stream, _)) => stream,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    thread::sleep(POLL_INTERVAL);
+                    continue;
+                }
+                Err(e)
+                    if matches!(
+                        e.kind(),
+                        io::ErrorKind::Interrupted | io::ErrorKind::ConnectionAborted
+                    ) =>
+                {
+                    continue
+                }
+                Err(e) => return Err(e.into()),
+            };
+            count += 1;
+            let client = match peer_uid(&stream) {
+                Ok(uid) => format!("uid {}", uid),
+                Err(e) => {
+                    warn!("Failed to read the user id of connection {}: {}", count, e);
+                    format!("connection {}", count)
+                }
+            };
+            stream.set_nonblocking(false)?;
+            let reader = stream.try_clone()?;
+            let control = stream.try_clone()?;
+            let (options, scheduler) = (options.clone(), scheduler.clone());
+            let handle = thread::spawn(move || {
+                if let Err(e) = serve_connection(
+                    BufReader::new(reader),
+                    &stream,
+                    &options,
+                    &scheduler,
+                    &client,
+                ) {
+                    warn!("{}: {}", client, e);
+                }
+            });
+            connections.push((control, handle));
+            connections.retain(|(_, handle)| !handle.is_finished());
+        }
+
+        info!("Stopping, waiting for {} connections", connections.len());
+        for (control, handle) in connections {
+            // Ends the loop of the connection once its current request is answered
+            let _ = control.shutdown(Shutdown::Read);
+            let _ = handle.join();
+        }
+        Ok(())
+    }
+
+    /// Returns the user id of the process at the other end of a connection.
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub(super) fn peer_uid(stream: &UnixStream) -> io::Result<u32> {
+        let mut credentials = libc::ucred {
+            pid: 0,
+            uid: 0,
+            gid: 0,
+        };
+        let mut length = std::mem::size_of::<libc::ucred>() as libc::socklen_t;
+        // SAFETY: `credentials` and `length` are valid for writes, and `length` is the size
+        // of `credentials`
+        let result = unsafe {
+            libc::getsockopt(
+                stream.as_raw_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_PEERCRED,
+                &mut credentials as *mut libc::ucred as *mut libc::c_void,
+                &mut length,
+            )
+        };
+        if result != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(credentials.uid)
+    }
+
+    /// Returns the user id of the process at the other end of a connection.
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    pub(super) fn peer_uid(stream: &UnixStream) -> io::Result<u32> {
+        let (mut uid, mut gid) = (0, 0);
+        // SAFETY: `uid` and `gid` are valid for writes
+        if unsafe { libc::getpeereid(stream.as_raw_fd(), &mut uid, &mut gid) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(uid)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schedule::SchedulerConfig;
+    use image::RgbImage;
+    use std::io::Cursor;
+    use tempfile::TempDir;
+
+    #[test]
+    fn answers_requests() {
+        let dir = TempDir::new().expect("Failed to create a temp dir");
+        let input = dir.path().join("input.png");
+        RgbImage::new(40, 20).save(&input).unwrap();
+        let output = dir.path().join("output.jpg");
+        let requests = format!(
+            "{}\n\n{}\nnot json\n{}\n",
+            json!({"id": 1, "input": input, "output": output, "width": 20, "quality": 70, "lqip": true}),
+            json!({"id": "b", "input": input}),
+            json!({"input": input, "output": output, "quality": 0}),
+        );
+
+        let mut written = Vec::new();
+        let scheduler = Scheduler::new(SchedulerConfig::default()).unwrap();
+        let options = JobOptions::default();
+        serve_connection(
+            Cursor::new(requests),
+            &mut written,
+            &options,
+            &scheduler,
+            "test",
+        )
+        .unwrap();
+        let responses: Vec<Value> = String::from_utf8(written)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(responses.len(), 4);
+        assert_eq!(responses[0]["id"], 1);
+        assert_eq!(responses[0]["ok"], true);
+        assert_eq!(responses[0]["output"], json!(output));
+        assert_eq!(responses[0]["width"], 20);
+        assert_eq!(responses[0]["height"], 10);
+        assert_eq!(responses[0]["format"], "jpeg");
+        assert!(responses[0]["lqip"]
+            .as_str()
+            .unwrap()
+            .starts_with("data:image/jpeg;base64,"));
+        assert_eq!(responses[1]["id"], "b");
+        assert_eq!(responses[1]["error"], "The output is missing");
+        assert!(responses[2]["error"]
+            .as_str()
+            .unwrap()
+            .starts_with("Invalid JSON"));
+        assert_eq!(responses[3]["error"], "Invalid quality 0");
+        assert!(output.exists());
+    }
+
+    #[test]
+    fn rejects_long_lines() {
+        let requests = format!("{}\n{{}}\n", "x".repeat(MAX_LINE_LENGTH + 1));
+        let mut written = Vec::new();
+        let scheduler = Scheduler::new(SchedulerConfig::default()).unwrap();
+        let error = serve_connection(
+            Cursor::new(requests),
+            &mut written,
+            &JobOptions::default(),
+            &scheduler,
+            "test",
+        )
+        .unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        let written = String::from_utf8(written).unwrap();
+        assert_eq!(written.lines().count(), 1);
+        assert!(written.contains("Request lines are limited"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn reads_the_peer_uid() {
+        use std::os::unix::{fs::MetadataExt, net::UnixStream};
+
+        // Files are owned by the user creating them, here the peer of the pair
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let uid = file.as_file().metadata().unwrap().uid();
+        let (stream, _) = UnixStream::pair().unwrap();
+        assert_eq!(unix::peer_uid(&stream).unwrap(), uid);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn serves_a_socket() {
+        use std::{
+            io::{BufReader, Write},
+            os::unix::net::UnixStream,
+            thread,
+            time::Duration,
+        };
+
+        let dir = TempDir::new().expect("Failed to create a temp dir");
+        let input = dir.path().join("input.png");
+        RgbImage::new(40, 20).save(&input).unwrap();
+        let socket = dir.path().join("resizer.sock");
+        let options = JobOptions::default();
+        let cancel = options.cancel.clone();
+        let daemon = {
+            let socket = socket.clone();
+            thread::spawn(move || {
+                let scheduler = Scheduler::new(SchedulerConfig::default()).unwrap();
+                serve(&socket, &options, &scheduler).map_err(|e| e.to_string())
+            })
+        };
+
+        let mut stream = loop {
+            match UnixStream::connect(&socket) {
+                Ok(stream) => break stream,
+                Err(_) => thread::sleep(Duration::from_millis(10)),
+            }
+        };
+        let output = dir.path().join("output.png");
+        let request = json!({"id": 7, "input": input, "output": output, "height": 5});
+        writeln!(stream, "{}", request).unwrap();
+        let mut response = String::new();
+        BufReader::new(&stream).read_line(&mut response).unwrap();
+        let response: Value = serde_json::from_str(&response).unwrap();
+        assert_eq!(response["id"], 7);
+        assert_eq!(response["width"], 10);
+
+        // The connection stays open, and is closed by the daemon when it stops
+        cancel.cancel();
+        daemon.join().unwrap().unwrap();
+        assert!(!socket.exists());
+    }
+}
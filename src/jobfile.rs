@@ -0,0 +1,291 @@
+//! Batch jobs described in a file.
+//!
+//! A job file lists the images of a batch with their own output and size, so that batches
+//! with different settings per image can be prepared in a spreadsheet. Two formats are read:
+//!
+//! - CSV, with a header row naming the columns, in any order:
+//!
+//!   ```text
+//!   input,output,width,height,format
+//!   photos/a.jpg,web/a.jpg,1600,,
+//!   photos/a.jpg,thumbs/a.png,200,200,png
+//!   "photos/b, final.png",,800,,jpeg
+//!   ```
+//!
+//! - JSON, with an array of objects using the same keys:
+//!
+//!   ```text
+//!   [{"input": "photos/a.jpg", "output": "web/a.jpg", "width": 1600}]
+//!   ```
+//!
+//! Only `input` is required. Empty cells and missing keys fall back to the command-line
+//! options, so that a file can for example list inputs and outputs while `--width` gives
+//! the size of all of them. A row giving a width or a height replaces both command-line
+//! sizes, so that its aspect ratio is kept. Paths are relative to the current directory,
+//! and inputs are read as single images, not archives.
+
+use crate::format::OutputFormat;
+use serde_json::Value;
+use std::{error::Error, fs, path::PathBuf};
+
+/// The columns of a CSV job file, and the keys of a JSON one.
+pub const COLUMNS: [&str; 5] = ["input", "output", "width", "height", "format"];
+
+/// One image of a job file.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Job {
+    /// The path of the input image.
+    pub input: PathBuf,
+    /// The output path, or None to use the command-line output.
+    pub output: Option<String>,
+    /// The new width. Without a width and a height, the command-line sizes are used.
+    pub width: Option<u32>,
+    /// The new height. Without a width and a height, the command-line sizes are used.
+    pub height: Option<u32>,
+    /// The output format (`jpeg` or `png`), or None to use the command-line format.
+    pub format: Option<String>,
+}
+
+impl Job {
+    /// Sets a field from its column name and text value. Empty values are ignored.
+    fn set(&mut self, column: &str, value: &str) -> Result<(), String> {
+        let value = value.trim();
+        if value.is_empty() {
+            return Ok(());
+        }
+        let size = || match value.parse::<u32>() {
+            Ok(size) if size > 0 => Ok(size),
+            _ => Err(format!("Invalid {} '{}'", column, value)),
+        };
+        match column {
+            "input" => self.input = PathBuf::from(value),
+            "output" => self.output = Some(value.to_string()),
+            "width" => self.width = Some(size()?),
+            "height" => self.height = Some(size()?),
+            "format" => self.format = Some(value.parse::<OutputFormat>()?.to_string()),
+            _ => return Err(format!("Unknown column '{}'", column)),
+        }
+        Ok(())
+    }
+
+    /// Checks that the job has an input.
+    fn check(self) -> Result<Self, String> {
+        if self.input.as_os_str().is_empty() {
+            return Err("The input is missing".to_string());
+        }
+        Ok(self)
+    }
+}
+
+/// Reads a job file, as JSON if its extension is `.json` and as CSV otherwise.
+///
+/// # Errors
+///
+/// This function will return an error if the file cannot be read or is invalid.
+pub fn load(path: &std::path::Path) -> Result<Vec<Job>, Box<dyn Error>> {
+    let text = fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read the job file {:?}: {}", path, e))?;
+    let is_json = path
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("json"));
+    let jobs = if is_json {
+        parse_json(&text)
+    } else {
+        parse_csv(&text)
+    };
+    jobs.map_err(|e| format!("Invalid job file {:?}: {}", path, e).into())
+}
+
+/// Parses a CSV job file.
+///
+/// Fields may be quoted with double quotes, to contain commas, line breaks or doubled
+/// quotes. Blank lines are skipped, and a leading byte order mark, as written by some
+/// spreadsheets, is ignored.
+///
+/// # Errors
+///
+/// This function will return an error, with its line number, if the header is missing or
+/// names an unknown column, or if a row has too many fields or an invalid value.
+pub fn parse_csv(text: &str) -> Result<Vec<Job>, Box<dyn Error>> {
+    let mut records = csv_records(text.strip_prefix('\u{feff}').unwrap_or(text))?.into_iter();
+    let (_, header) = records.next().ok_or("The header row is missing")?;
+    let columns: Vec<String> = header.iter().map(|c| c.trim().to_lowercase()).collect();
+    if let Some(unknown) = columns.iter().find(|c| !COLUMNS.contains(&c.as_str())) {
+        return Err(format!("Line 1: Unknown column '{}'", unknown).into());
+    }
+    if !columns.iter().any(|c| c == "input") {
+        return Err("Line 1: The 'input' column is missing".into());
+    }
+
+    let mut jobs = Vec::new();
+    for (line, fields) in records {
+        if fields.len() > columns.len() {
+            return Err(format!(
+                "Line {}: {} fields for {} columns",
+                line,
+                fields.len(),
+                columns.len()
+            )
+            .into());
+        }
+        let mut job = Job::default();
+        for (column, field) in columns.iter().zip(&fields) {
+            job.set(column, field)
+                .map_err(|e| format!("Line {}: {}", line, e))?;
+        }
+        jobs.push(job.check().map_err(|e| format!("Line {}: {}", line, e))?);
+    }
+    Ok(jobs)
+}
+
+/// Splits CSV text into records of fields, each with the line number it starts on.
+fn csv_records(text: &str) -> Result<Vec<(usize, Vec<String>)>, String> {
+    let mut records = Vec::new();
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let (mut line, mut start) = (1, 1);
+    let (mut quoted, mut in_quotes) = (false, false);
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if in_quotes => {
+                if chars.peek() == Some(&'"') {
+                    chars.next();
+                    field.push('"');
+                } else {
+                    in_quotes = false;
+                }
+            }
+            '"' if field.is_empty() && !quoted => (quoted, in_quotes) = (true, true),
+            '\n' if in_quotes => {
+                field.push(c);
+                line += 1;
+            }
+            ',' if !in_quotes => {
+                fields.push(std::mem::take(&mut field));
+                quoted = false;
+            }
+            '\r' if !in_quotes && chars.peek() == Some(&'\n') => {}
+            '\n' if !in_quotes => {
+                fields.push(std::mem::take(&mut field));
+                if fields.iter().any(|f| !f.trim().is_empty()) {
+                    records.push((start, std::mem::take(&mut fields)));
+                }
+                fields.clear();
+                quoted = false;
+                line += 1;
+                start = line;
+            }
+            _ => field.push(c),
+        }
+    }
+    if in_quotes {
+        return Err(format!("Line {}: Unterminated quoted field", start));
+    }
+    fields.push(field);
+    if fields.iter().any(|f| !f.trim().is_empty()) {
+        records.push((start, fields));
+    }
+    Ok(records)
+}
+
+/// Parses a JSON job file: an array of objects with the keys of `COLUMNS`. Sizes may be
+/// numbers or strings, and null values are ignored like empty cells.
+///
+/// # Errors
+///
+/// This function will return an error, with the index of the job, if the JSON is invalid,
+/// is not an array of objects, or has an unknown key or an invalid value.
+pub fn parse_json(text: &str) -> Result<Vec<Job>, Box<dyn Error>> {
+    let value: Value = serde_json::from_str(text)?;
+    let items = value.as_array().ok_or("Expected an array of jobs")?;
+    let mut jobs = Vec::with_capacity(items.len());
+    for (index, item) in items.iter().enumerate() {
+        jobs.push(parse_object(item).map_err(|e| format!("Job {}: {}", index, e))?);
+    }
+    Ok(jobs)
+}
+
+/// Parses one job of a JSON job file: an object with the keys of `COLUMNS`.
+///
+/// # Errors
+///
+/// This function will return an error if the value is not an object, or has an unknown
+/// key, an invalid value or no input.
+pub fn parse_object(item: &Value) -> Result<Job, String> {
+    let object = item.as_object().ok_or("Expected an object")?;
+    let mut job = Job::default();
+    for (key, value) in object {
+        let text = match value {
+            Value::Null => continue,
+            Value::String(text) => text.clone(),
+            Value::Number(number) => number.to_string(),
+            _ => return Err(format!("Invalid {} {}", key, value)),
+        };
+        job.set(key, &text)?;
+    }
+    job.check()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_csv() {
+        let text = "\u{feff}Input,width,output,format\r\n\
+                    a.jpg,100,out/a.jpg,\r\n\
+                    \r\n\
+                    \"b, \"\"final\"\".png\",,,PNG\n\
+                    \"multi\nline.png\",20";
+        let jobs = parse_csv(text).unwrap();
+        assert_eq!(
+            jobs,
+            [
+                Job {
+                    input: "a.jpg".into(),
+                    output: Some("out/a.jpg".to_string()),
+                    width: Some(100),
+                    ..Default::default()
+                },
+                Job {
+                    input: "b, \"final\".png".into(),
+                    format: Some("png".to_string()),
+                    ..Default::default()
+                },
+                Job {
+                    input: "multi\nline.png".into(),
+                    width: Some(20),
+                    ..Default::default()
+                },
+            ]
+        );
+
+        let error = |text: &str| parse_csv(text).unwrap_err().to_string();
+        assert!(error("input,widht\na.png,1").contains("Unknown column 'widht'"));
+        assert!(error("output\nb.png").contains("'input' column"));
+        assert!(error("input,width\na.png,1\nb.png,0").starts_with("Line 3: Invalid width"));
+        assert!(error("input,format\na.png,gif").starts_with("Line 2:"));
+        assert!(error("input,width\n,10").contains("input is missing"));
+        assert!(error("input\n\"a.png").contains("Unterminated"));
+    }
+
+    #[test]
+    fn parses_json() {
+        let text = r#"[
+            {"input": "a.jpg", "width": 100, "height": "50", "format": null},
+            {"input": "b.png", "output": "b.jpg", "format": "jpeg"}
+        ]"#;
+        let jobs = parse_json(text).unwrap();
+        assert_eq!(jobs[0].width, Some(100));
+        assert_eq!(jobs[0].height, Some(50));
+        assert_eq!(jobs[1].output.as_deref(), Some("b.jpg"));
+        assert_eq!(jobs[1].format.as_deref(), Some("jpeg"));
+
+        let error = |text: &str| parse_json(text).unwrap_err().to_string();
+        assert!(error(r#"{"input": "a.png"}"#).contains("array"));
+        assert!(error(r#"[{"input": "a.png", "width": -1}]"#).starts_with("Job 0:"));
+        assert!(error(r#"[{"input": "a.png", "size": 1}]"#).contains("Unknown column"));
+        assert!(error(r#"[{"input": ["a.png"]}]"#).contains("Invalid input"));
+    }
+}
@@ -6,23 +6,155 @@
 //!
 //! Key features:
 //! - Resize images while maintaining aspect ratio
-//! - Support for JPEG and PNG formats
+//! - Support for JPEG, PNG, BMP, TGA, WebP and AVIF outputs
 //! - Automatic format detection and conversion
 //! - Efficient resizing using the `fast_image_resize` library
 //!
 //! The main functions provided are:
 //! - `resize_image`: Resizes an image file to specified dimensions
 //! - `save_image`: Saves a resized image buffer to a file
+//! - `batch::run_batch`: Applies an operation to several inputs with a configurable error policy
+//! - `pathlist::PathList`: Reads input paths as they arrive from a newline or NUL separated list
+//! - `paths::extended_length`: Converts Windows paths to the extended-length form for long and UNC paths
+//! - `select::expand`: Expands glob patterns into input files, filtered with `select::Excludes`
+//! - `metrics::compare_images`: Computes PSNR and SSIM between an original and a resized image
+//! - `tiled::resize_image_tiled`: Resizes huge images band by band to cap peak memory
+//! - `resize_image_with`: Resizes an image using explicit thread and CPU extension settings
+//! - `context::ResizeContext`: Keeps resizers and their scratch buffers across many resizes
+//! - `raw::resize_raw`: Resizes decoded gray, RGB or RGBA pixels without a `DynamicImage`
+//! - `raw::resize_raw_into`: Resizes a borrowed `raw::RawImage` into a caller's buffer
+//! - `sequence::resize_sequence`: Resizes numbered frames (`frame_%04d.png`) or piped raw frames
+//! - `image_resizer_core`: The output size math and a portable resizer of RGBA buffers, in a
+//!   separate crate which only needs `alloc`, for `no_std` targets such as cameras
+//! - `load_and_resize`: Decodes and resizes an image file, enforcing the limits in `JobOptions`
+//! - `resize_file`: Runs the full pipeline for one file and reports source and output details
+//! - `pipeline::Pipeline`: Runs decode, resize and encode stages, extensible with custom stages
+//! - `texture::encode_texture`: Writes DDS or KTX2 textures with a full mipmap chain, optionally
+//!   BC-compressed (with the `bc` feature)
+//! - `tonemap::ToneMapStage`: Tone maps OpenEXR and Radiance HDR inputs (Reinhard or ACES)
+//! - `cancel::CancellationToken`: Aborts a running job or batch between stages
+//! - `schedule::Scheduler`: Caps concurrent jobs and rate-limits clients of a resize service
+//! - `ipc::serve`: Answers JSON lines resize requests on a Unix domain socket
+//! - `grpc::ResizeServer`: Serves the gRPC `ResizeService` (with the `grpc` feature)
+//! - `resize_file_into`: Runs the full pipeline for one file and writes the result to a
+//!   `sink::OutputSink`, such as a ZIP archive
+//! - `storage::Storage`: Reads inputs and writes outputs through a pluggable backend
+//! - `codec::EncoderRegistry`: Looks up the encoder of each output format, extensible with
+//!   custom `codec::Encoder`s
+//! - `codec::DecoderRegistry`: Plugs custom `codec::Decoder`s for exotic input formats into the
+//!   pipeline
+//! - `format::OutputFormat`: Parses output formats by name or MIME type, following the encoders
+//!   of a `codec::EncoderRegistry`
+//! - `retry::Retrying`: Retries the operations of a storage backend failing with transient errors
+//! - `build_helper::ResizePlan`: Resizes the assets of a crate from its build script
+//! - `inplace::resize_in_place`: Replaces an image file with its resized version, optionally
+//!   keeping a backup
+//! - `originals::remove_original`: Trashes or deletes an original once its outputs are verified
+//! - `verify::verify_output`: Reads an output back and checks it decodes to the expected image
+//! - `jobfile::load`: Reads per-image outputs, sizes and formats from a CSV or JSON job file
+//! - `recipe::Recipe`: Builds a pipeline and encoder settings from a YAML or TOML recipe
+//! - `blurhash::encode`: Computes the BlurHash placeholder of an image
+//! - `lqip::data_uri`: Computes a tiny blurred placeholder of an image as a base64 data URI
+//! - `picture::render`: Writes an HTML `<picture>` element offering several outputs of an image
+//! - `untrusted::decode_untrusted`: Decodes hostile input with strict limits, never panicking
+//! - `testing::check_golden`: Compares outputs with reference images in regression tests
+//!
+//! Progress is reported through `tracing`: every call to `resize_file` runs in a
+//! `resize_file` span, with a span per pipeline stage (`decode`, `resize`, `encode`) and a
+//! `save` span, carrying dimensions, sizes and durations as fields. Applications embedding
+//! the library can collect them with any `tracing` subscriber, e.g. to draw flamegraphs.
+//! Without a subscriber, events are forwarded to the `log` facade.
 //!
 //! This library is designed to be easy to use while providing robust error handling and
 //! flexibility in image processing tasks.
 
+pub mod appicon;
+pub mod archive;
+pub mod background;
+pub mod batch;
+pub mod blurhash;
+pub mod build_helper;
+pub mod cache;
+pub mod cancel;
+pub mod clipboard;
+pub mod codec;
+pub mod colors;
+pub mod contact;
+pub mod context;
+pub mod crop;
+pub mod encode;
+pub mod error;
+pub mod filters;
+pub mod font;
+pub mod format;
+pub mod generate;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+pub mod hash;
+pub mod inplace;
+pub mod ipc;
+pub mod jobfile;
+pub mod limits;
+pub mod lqip;
+pub mod lut;
+pub mod manifest;
+pub mod metadata;
+pub mod metrics;
+pub mod montage;
+pub mod options;
+pub mod originals;
+pub mod overwrite;
+pub mod pathlist;
+pub mod paths;
+pub mod picture;
+pub mod pipeline;
+pub mod policy;
+pub mod preview;
+pub mod pyramid;
+pub mod quantize;
+pub mod raw;
+pub mod recipe;
+pub mod retry;
+pub mod safety;
+pub mod schedule;
+pub mod seam;
+pub mod select;
+pub mod sequence;
+pub mod sidecar;
+pub mod sink;
+pub mod split;
+pub mod storage;
+pub(crate) mod streaming;
+pub mod testing;
+pub mod texture;
+pub mod tiled;
+pub mod tonemap;
+pub mod trim;
+pub mod units;
+pub mod untrusted;
+pub mod upscale;
+pub mod verify;
+pub mod watermark;
+pub mod xattrs;
+
+use codec::EncoderRegistry;
+use context::ResizeContext;
+use encode::EncodeOptions;
 use fast_image_resize::{self as fr, images::Image};
-use image::{guess_format, DynamicImage, ImageBuffer, ImageEncoder, ImageFormat, Rgba};
+use format::OutputFormat;
+use image::{guess_format, DynamicImage, GenericImageView, ImageBuffer, ImageFormat, Rgba};
+use image_resizer_core::{DimensionError, Sizing};
+use options::{JobOptions, ResizeOptions};
+use overwrite::OverwritePolicy;
+use pipeline::{Pipeline, PipelineState};
+use sink::OutputSink;
 use std::{
-    io::Write,
+    io::{self, Read},
     path::{Path, PathBuf},
+    time::{Duration, Instant},
 };
+use storage::{FileSystem, Storage};
+use tracing::{info, info_span, warn};
 
 /// A container for holding source and destination images during the resizing process.
 ///
@@ -47,6 +179,7 @@ impl ImageContainer {
     /// * `img` - The input image as a `DynamicImage`.
     /// * `width` - An optional new width for the image. If None, it will be calculated based on the height.
     /// * `height` - An optional new height for the image. If None, it will be calculated based on the width.
+    /// * `options` - How the new dimensions are rounded.
     ///
     /// # Returns
     ///
@@ -61,24 +194,21 @@ impl ImageContainer {
         img: DynamicImage,
         width: Option<&u32>,
         height: Option<&u32>,
+        options: &ResizeOptions,
     ) -> Result<Self, Box<dyn std::error::Error>> {
-        let (new_width, new_height) = determine_new_dimensions(&img, width, height)?;
+        let (new_width, new_height) =
+            determine_new_dimensions(img.width(), img.height(), width, height, options)?;
 
         // Create a fast_image_resize::Image from the opened image
-        let src_width = std::num::NonZeroU32::new(img.width()).unwrap();
-        let src_height = std::num::NonZeroU32::new(img.height()).unwrap();
         let src_image = fr::images::Image::from_vec_u8(
-            src_width.get(),
-            src_height.get(),
+            img.width(),
+            img.height(),
             img.into_rgba8().into_raw(),
             fr::PixelType::U8x4,
         )?;
 
         // Create destination image
-        let dst_width = std::num::NonZeroU32::new(new_width).unwrap();
-        let dst_height = std::num::NonZeroU32::new(new_height).unwrap();
-        let dst_image =
-            fr::images::Image::new(dst_width.get(), dst_height.get(), src_image.pixel_type());
+        let dst_image = fr::images::Image::new(new_width, new_height, src_image.pixel_type());
 
         Ok(Self {
             new_width,
@@ -87,9 +217,44 @@ impl ImageContainer {
             dst_image,
         })
     }
+
+    /// Resizes the source image into the destination image and returns the result.
+    ///
+    /// The container is consumed: the source buffer is freed as soon as the resize is done,
+    /// and the destination buffer is moved into the returned `ImageBuffer` without copying.
+    ///
+    /// If the options of the context ask for several threads, the destination rows are split
+    /// into contiguous chunks and each chunk is resized on its own thread from the shared
+    /// source image.
+    ///
+    /// # Arguments
+    ///
+    /// * `context` - The resizers and CPU settings.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the resizing operation fails.
+    fn resize(
+        self,
+        context: &mut ResizeContext,
+    ) -> Result<ImageBuffer<Rgba<u8>, Vec<u8>>, Box<dyn std::error::Error>> {
+        let Self {
+            new_width,
+            new_height,
+            src_image,
+            mut dst_image,
+        } = self;
+
+        context.resize_view(&src_image, &mut dst_image)?;
+        drop(src_image);
+
+        ImageBuffer::from_raw(new_width, new_height, dst_image.into_vec())
+            .ok_or_else(|| "Failed to create the resized image buffer".into())
+    }
 }
 
 /// Represents information about an image.
+#[derive(Debug, Clone)]
 pub struct ImageInfo {
     /// The width of the image in pixels.
     pub width: u32,
@@ -101,42 +266,160 @@ pub struct ImageInfo {
     pub path: PathBuf,
     /// The size of the image file in bytes.
     pub file_size: u64,
+    /// The source image this image was produced from. Only set by `resize_file`.
+    pub source: Option<SourceInfo>,
+    /// The time taken to produce this image. Only set by `resize_file`.
+    pub duration: Option<Duration>,
+    /// The result of reading the image back after writing it. Only set by `resize_file`
+    /// when `JobOptions::verify` is enabled.
+    pub verification: Option<verify::Verification>,
+}
+
+impl ImageInfo {
+    /// Returns the ratio between the source file size and this file's size, e.g. `4.0` if
+    /// the output is four times smaller than the input.
+    ///
+    /// Returns None if the source is unknown or this file is empty.
+    pub fn compression_ratio(&self) -> Option<f64> {
+        match &self.source {
+            Some(source) if self.file_size > 0 => {
+                Some(source.file_size as f64 / self.file_size as f64)
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns the MIME type of the image, e.g. `image/webp`.
+    pub fn mime_type(&self) -> &'static str {
+        self.format.to_mime_type()
+    }
+}
+
+/// Detects the format of an image file from its first bytes.
+///
+/// # Returns
+///
+/// A `Result` containing the detected `ImageFormat`, or None if the format is not recognized.
+///
+/// # Errors
+///
+/// This function will return an error if the file cannot be read.
+pub fn detect_format(path: &Path) -> Result<Option<ImageFormat>, Box<dyn std::error::Error>> {
+    detect_format_in(&FileSystem, path)
+}
+
+/// Detects the format of an image file stored in a storage backend from its first bytes.
+///
+/// # Errors
+///
+/// This function will return an error if the file cannot be read.
+pub fn detect_format_in(
+    storage: &dyn Storage,
+    path: &Path,
+) -> Result<Option<ImageFormat>, Box<dyn std::error::Error>> {
+    Ok(storage::image_reader(storage, path)?.format())
+}
+
+/// Represents information about a source image file, read from its header.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceInfo {
+    /// The width of the source image in pixels.
+    pub width: u32,
+    /// The height of the source image in pixels.
+    pub height: u32,
+    /// The format of the source image.
+    pub format: ImageFormat,
+    /// The size of the source file in bytes.
+    pub file_size: u64,
+}
+
+impl SourceInfo {
+    /// Reads the dimensions and format of an image file without decoding it.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the file cannot be read or its format is not
+    /// recognized.
+    pub fn read(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        Self::read_from(&FileSystem, path)
+    }
+
+    /// Reads the dimensions and format of an image file stored in a storage backend.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the file cannot be read or its format is not
+    /// recognized.
+    pub fn read_from(
+        storage: &dyn Storage,
+        path: &Path,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let reader = storage::image_reader(storage, path)?;
+        let format = reader
+            .format()
+            .ok_or_else(|| format!("Unknown image format: {:?}", path))?;
+        let (width, height) = reader.into_dimensions()?;
+
+        Ok(SourceInfo {
+            width,
+            height,
+            format,
+            file_size: storage::file_size(storage, path)?,
+        })
+    }
 }
 
 /// Determines the new dimensions for an image based on the provided width and height options.
 ///
+/// The computation is shared with the `no_std` resizer of `image_resizer_core` (see
+/// `image_resizer_core::new_dimensions`), so that both produce the same sizes.
+///
 /// # Arguments
 ///
-/// * `img` - The original image.
+/// * `src_width` - The width of the original image.
+/// * `src_height` - The height of the original image.
 /// * `width` - An optional new width for the image.
 /// * `height` - An optional new height for the image.
+/// * `options` - How the side computed from the aspect ratio is rounded, the multiple both
+///   sides are rounded to, and the maximum number of output pixels. With a maximum, the
+///   width and height are optional: the source size is scaled down to fit it.
 ///
 /// # Returns
 ///
-/// A tuple containing the new width and height, or an error if neither width nor height is specified.
+/// A tuple containing the new width and height, each at least one pixel, or an error if
+/// neither width nor height nor a maximum number of pixels is specified. A requested side of
+/// zero is a `ResizeError::InvalidRequestedSize`, and a source with a zero side is a
+/// `ResizeError::InvalidDimensions`.
 fn determine_new_dimensions(
-    img: &DynamicImage,
+    src_width: u32,
+    src_height: u32,
     width: Option<&u32>,
     height: Option<&u32>,
+    options: &ResizeOptions,
 ) -> Result<(u32, u32), Box<dyn std::error::Error>> {
-    let (new_width, new_height) = match (width, height) {
-        (Some(w), Some(h)) => (*w, *h),
-        (Some(w), None) => {
-            let aspect_ratio = img.height() as f32 / img.width() as f32;
-            // println!("src_width {}, src_height {}", img.width(), img.height());
-            (*w, (*w as f32 * aspect_ratio) as u32)
-        }
-        (None, Some(h)) => {
-            let aspect_ratio = img.width() as f32 / img.height() as f32;
-            // println!("src_width {}, src_height {}", img.width(), img.height());
-            ((*h as f32 * aspect_ratio) as u32, *h)
-        }
-        (None, None) => {
-            return Err("Error: At least one of width or height must be specified".into())
+    for (side, value) in [("width", width), ("height", height)] {
+        if let Some(&value @ 0) = value {
+            return Err(error::ResizeError::InvalidRequestedSize { side, value }.into());
         }
+    }
+    let sizing = Sizing {
+        rounding: options.rounding,
+        multiple_of: options.multiple_of,
+        max_output_pixels: options.max_output_pixels,
     };
-
-    Ok((new_width, new_height))
+    image_resizer_core::new_dimensions(
+        src_width,
+        src_height,
+        width.copied(),
+        height.copied(),
+        &sizing,
+    )
+    .map_err(|e| match e {
+        DimensionError::EmptySource { width, height } => {
+            error::ResizeError::InvalidDimensions { width, height }.into()
+        }
+        e => format!("Error: {}", e).into(),
+    })
 }
 
 /// Resizes an image to the specified dimensions.
@@ -161,36 +444,533 @@ pub fn resize_image(
     width: Option<&u32>,
     height: Option<&u32>,
 ) -> Result<ImageBuffer<Rgba<u8>, Vec<u8>>, Box<dyn std::error::Error>> {
-    // Create Image instance from a DynamicImage input
-    let mut img = ImageContainer::new(input, width, height)?;
+    resize_image_with(input, width, height, &ResizeOptions::default())
+}
 
-    println!(
-        "New image dimensions: width {} x height {}",
-        img.new_width, img.new_height
-    );
+/// Resizes an image to the specified dimensions using the given CPU settings.
+///
+/// # Arguments
+///
+/// * `input` - The input image as a `DynamicImage`.
+/// * `width` - An optional new width for the image. If None, it will be calculated based on the height.
+/// * `height` - An optional new height for the image. If None, it will be calculated based on the width.
+/// * `options` - The number of threads and the CPU extension used by the resizer, and the
+///   rounding of the computed side.
+///
+/// # Returns
+///
+/// A `Result` containing the resized image as an `ImageBuffer<Rgba<u8>, Vec<u8>>`, or an error if the operation fails.
+///
+/// # Errors
+///
+/// This function will return an error if:
+/// - Neither width nor height is specified.
+/// - The selected CPU extension is not available.
+/// - The resizing operation fails.
+pub fn resize_image_with(
+    input: DynamicImage,
+    width: Option<&u32>,
+    height: Option<&u32>,
+    options: &ResizeOptions,
+) -> Result<ImageBuffer<Rgba<u8>, Vec<u8>>, Box<dyn std::error::Error>> {
+    ResizeContext::new(*options)?.resize_image(input, width, height)
+}
+
+/// Decodes an image file and resizes it, enforcing the limits of the job options.
+///
+/// This runs the decode and resize stages of the standard pipeline (see `pipeline`). The
+/// image header is read first, so that images exceeding the limits are rejected before
+/// their pixels are decoded. The resize path (regular or tiled) is then selected with
+/// `limits::plan_resize`.
+///
+/// # Arguments
+///
+/// * `input` - The path of the image file.
+/// * `width` - An optional new width for the image. If None, it will be calculated based on the height.
+/// * `height` - An optional new height for the image. If None, it will be calculated based on the width.
+/// * `options` - The job options, including the resource limits.
+///
+/// # Returns
+///
+/// A `Result` containing the resized image as an `ImageBuffer<Rgba<u8>, Vec<u8>>`, or an error if the operation fails.
+///
+/// # Errors
+///
+/// This function will return an error if:
+/// - The file cannot be read or is not a valid image.
+/// - Neither width nor height is specified.
+/// - The image exceeds the configured limits.
+/// - The resizing operation fails.
+/// - The job is cancelled through `options.cancel` (`ResizeError::Cancelled`).
+pub fn load_and_resize(
+    input: &Path,
+    width: Option<&u32>,
+    height: Option<&u32>,
+    options: &JobOptions,
+) -> Result<ImageBuffer<Rgba<u8>, Vec<u8>>, Box<dyn std::error::Error>> {
+    let mut state = PipelineState::new(input, width.copied(), height.copied());
+    Pipeline::standard()
+        .until(pipeline::RESIZE)?
+        .run(&mut state, options)?;
+    Ok(state.take_image()?.into_rgba8())
+}
+
+/// Copies an image to its output unchanged when resizing and re-encoding it would not change it.
+///
+/// An image is copied through when the requested dimensions equal the source dimensions, the
+/// output format equals the source format and the encoder options are the defaults. Only the
+/// image header is read. Decoding and re-encoding such an image would only lose quality,
+/// especially for JPEG. If the output path is the input itself, nothing is written.
+///
+/// # Arguments
+///
+/// * `input` - The path of the image file.
+/// * `output_path` - The path where the image should be saved.
+/// * `width` - An optional new width for the image.
+/// * `height` - An optional new height for the image.
+/// * `output_format` - An optional reference to a `String` specifying the desired output format.
+/// * `options` - The job options. Their encoder options must be the defaults, their limits
+///   are checked like those of a resize, and their storage, overwrite policy and atomic
+///   write setting are used to write the copy.
+///
+/// # Returns
+///
+/// A `Result` containing `Some(ImageInfo)` describing the output if the image was copied
+/// through, or `None` if it must be resized and re-encoded.
+///
+/// # Errors
+///
+/// This function will return an error if:
+/// - The file cannot be read or is not a valid image.
+/// - The image has more pixels than `options.limits` allow, even if it would be copied
+///   unchanged.
+/// - Neither width nor height is specified.
+/// - The output path already exists and the overwrite policy does not allow replacing it.
+/// - The file cannot be copied.
+pub fn copy_through(
+    input: &Path,
+    output_path: &Path,
+    width: Option<&u32>,
+    height: Option<&u32>,
+    output_format: Option<&String>,
+    options: &JobOptions,
+) -> Result<Option<ImageInfo>, Box<dyn std::error::Error>> {
+    if options.encode != EncodeOptions::default() {
+        return Ok(None);
+    }
+
+    let storage = &*options.storage;
+    let source = SourceInfo::read_from(storage, input)?;
+    let (src_width, src_height) = (source.width, source.height);
+    options.limits.check_pixels(src_width, src_height)?;
+    if determine_new_dimensions(src_width, src_height, width, height, &options.resize)?
+        != (src_width, src_height)
+    {
+        return Ok(None);
+    }
 
-    // Create Resizer instance and resize
-    let mut resizer = fr::Resizer::new();
-    let resize_options = fr::ResizeOptions::default();
-    resizer.resize(&img.src_image, &mut img.dst_image, &resize_options)?;
+    let save_format = match output_format {
+        Some(f) => OutputFormat::parse(f, &options.encoders)?.image_format(),
+        None => match ImageFormat::from_path(output_path) {
+            Ok(format) => format,
+            Err(_) => return Ok(None),
+        },
+    };
+    if save_format != source.format {
+        return Ok(None);
+    }
 
-    // After resizing, create image buffer
-    let resized_img: ImageBuffer<Rgba<u8>, Vec<u8>> = ImageBuffer::from_vec(
-        img.new_width,
-        img.new_height,
-        img.dst_image.buffer().to_vec(),
+    let mut new_output = output_path.with_extension(determine_extension(output_path, save_format));
+    let mut contents = Vec::new();
+    if options.hash_names {
+        storage.open_read(input)?.read_to_end(&mut contents)?;
+        new_output = manifest::hashed_path(&new_output, &manifest::content_hash(&contents));
+    }
+    let same_file = input == new_output
+        || match (input.canonicalize(), new_output.canonicalize()) {
+            (Ok(a), Ok(b)) => a == b,
+            _ => false,
+        };
+    let new_output = if same_file {
+        info!("Image already has the requested dimensions and format, nothing to do.");
+        new_output
+    } else {
+        let new_output = if options.hash_names {
+            new_output
+        } else {
+            storage.open_read(input)?.read_to_end(&mut contents)?;
+            options.overwrite.resolve_in(storage, &new_output)?
+        };
+        info!("Image already has the requested dimensions and format, copying it unchanged.");
+        storage.write_file(&new_output, &contents, !options.direct_write)?;
+        new_output
+    };
+
+    Ok(Some(ImageInfo {
+        width: src_width,
+        height: src_height,
+        format: save_format,
+        file_size: storage::file_size(storage, &new_output)?,
+        path: new_output,
+        source: Some(source),
+        duration: None,
+        verification: None,
+    }))
+}
+
+/// Resizes an image file and saves the result, copying it through unchanged when possible.
+///
+/// This is the full pipeline used for every input of the command-line tool:
+/// 1. Reads the source image information
+/// 2. Copies the input through if no resize or conversion is needed (see `copy_through`),
+///    unless `options.force_reencode` is set or the pipeline has custom stages
+/// 3. Determines the save format and final output path
+/// 4. Checks if the output path already exists
+/// 5. Runs the standard pipeline (see `pipeline::Pipeline::standard`) to decode, resize and
+///    encode the image, enforcing the limits of the job options, unless `options.cache`
+///    holds the output of an identical earlier job
+/// 6. Saves the encoded image, named after a hash of its contents if `options.hash_names`
+///    is set
+/// 7. Copies the extended attributes of the input if `options.xattrs` asks for it
+///
+/// # Arguments
+///
+/// * `input` - The path of the image file.
+/// * `output_path` - The path where the image should be saved.
+/// * `width` - An optional new width for the image. If None, it will be calculated based on the height.
+/// * `height` - An optional new height for the image. If None, it will be calculated based on the width.
+/// * `output_format` - An optional reference to a `String` specifying the desired output format.
+/// * `options` - The job options.
+///
+/// # Returns
+///
+/// A `Result` containing an `ImageInfo` describing the output, including the source image
+/// information and the processing duration.
+///
+/// # Errors
+///
+/// This function will return an error if:
+/// - The input file cannot be read or is not a valid image.
+/// - The input format is rejected by the input policy (`ResizeError::FormatRejected`).
+/// - Neither width nor height is specified.
+/// - The input image exceeds the configured limits.
+/// - The resizing operation fails.
+/// - The output format cannot be determined.
+/// - The output path already exists and the user declines to replace it.
+/// - The resized image cannot be encoded or saved.
+/// - The extended attributes cannot be copied.
+/// - The job is cancelled through `options.cancel` (`ResizeError::Cancelled`). The output
+///   file is only written once every earlier stage has completed.
+pub fn resize_file(
+    input: &Path,
+    output_path: &Path,
+    width: Option<&u32>,
+    height: Option<&u32>,
+    output_format: Option<&String>,
+    options: &JobOptions,
+) -> Result<ImageInfo, Box<dyn std::error::Error>> {
+    resize_file_with(
+        input,
+        output_path,
+        width,
+        height,
+        output_format,
+        options,
+        &Pipeline::standard(),
     )
-    .unwrap();
+}
+
+/// Resizes an image file and saves the result like `resize_file`, but runs the given
+/// pipeline, which may contain custom stages.
+///
+/// The pipeline must produce an encoded image in `PipelineState::encoded`, and keep the
+/// final image in `PipelineState::image`.
+///
+/// # Errors
+///
+/// This function will return an error in the same cases as `resize_file`, if a stage
+/// fails, or if the pipeline does not produce an encoded image.
+pub fn resize_file_with(
+    input: &Path,
+    output_path: &Path,
+    width: Option<&u32>,
+    height: Option<&u32>,
+    output_format: Option<&String>,
+    options: &JobOptions,
+    pipeline: &Pipeline,
+) -> Result<ImageInfo, Box<dyn std::error::Error>> {
+    let _span = info_span!("resize_file", input = ?input).entered();
+    let start = Instant::now();
+    options.cancel.check()?;
+    let source = read_source(input, options)?;
+
+    // Custom stages, such as filters, must run even if the image is not resized
+    if source.is_some() && !options.force_reencode && pipeline.is_standard() {
+        if let Some(info) = copy_through(input, output_path, width, height, output_format, options)?
+        {
+            options.xattrs.apply(input, &info.path)?;
+            return Ok(ImageInfo {
+                verification: verify_if_requested(options, &info)?,
+                duration: Some(start.elapsed()),
+                ..info
+            });
+        }
+    }
+
+    let (save_format, new_output) =
+        save_format_and_path(output_path, output_format, &options.encoders, || {
+            infer_format_from_path(Some(output_path))
+        })?;
+    let new_output = if options.hash_names {
+        new_output
+    } else {
+        options
+            .overwrite
+            .resolve_in(&*options.storage, &new_output)?
+    };
+
+    if let Some(source) = source
+        .as_ref()
+        .filter(|_| options.low_memory && pipeline.is_standard())
+    {
+        if let Some(info) = streaming::resize_file(
+            input,
+            source.format,
+            &new_output,
+            width,
+            height,
+            save_format,
+            options,
+        )? {
+            options.xattrs.apply(input, &info.path)?;
+            return Ok(ImageInfo {
+                verification: verify_if_requested(options, &info)?,
+                source: Some(source.clone()),
+                duration: Some(start.elapsed()),
+                ..info
+            });
+        }
+    }
+
+    let (image_buffer, new_width, new_height) =
+        encode_file(input, width, height, save_format, options, pipeline)?;
+    let new_output = if options.hash_names {
+        manifest::hashed_path(&new_output, &manifest::content_hash(&image_buffer))
+    } else {
+        new_output
+    };
+
+    let info = save_to(
+        &*options.storage,
+        image_buffer,
+        new_width,
+        new_height,
+        &new_output,
+        save_format,
+        !options.direct_write,
+    )?;
+    options.xattrs.apply(input, &info.path)?;
 
-    Ok(resized_img)
+    Ok(ImageInfo {
+        verification: verify_if_requested(options, &info)?,
+        source,
+        duration: Some(start.elapsed()),
+        ..info
+    })
+}
+
+/// Checks the input against the input policy, reads its header and checks the dimensions it
+/// claims against the safety limits.
+///
+/// This runs before an image is copied through, so that inputs rejected by the decode stage
+/// are not copied either. Inputs handled by a custom decoder of `options.decoders` are left
+/// to the decode stage, and have no `SourceInfo`.
+fn read_source(
+    input: &Path,
+    options: &JobOptions,
+) -> Result<Option<SourceInfo>, Box<dyn std::error::Error>> {
+    let storage = &*options.storage;
+    if options.decoders.find_in(storage, input)?.is_some() {
+        return Ok(None);
+    }
+    options
+        .input_policy
+        .check(detect_format_in(storage, input)?)?;
+    let source = SourceInfo::read_from(storage, input)?;
+    options
+        .safety
+        .check_dimensions(source.width, source.height)?;
+    Ok(Some(source))
+}
+
+/// Verifies a written output with `verify::verify_output` if `options.verify` is enabled.
+pub(crate) fn verify_if_requested(
+    options: &JobOptions,
+    info: &ImageInfo,
+) -> Result<Option<verify::Verification>, Box<dyn std::error::Error>> {
+    if !options.verify {
+        return Ok(None);
+    }
+    Ok(Some(verify::verify_output(&*options.storage, info)?))
+}
+
+/// Resizes an image file like `resize_file`, but writes the result to an `OutputSink`, such
+/// as a ZIP archive, instead of a file.
+///
+/// The image is always re-encoded, and the overwrite policy, atomic writes and extended
+/// attributes of the job options do not apply.
+///
+/// # Arguments
+///
+/// * `input` - The path of the image file.
+/// * `name` - The name of the output within the sink. Its extension is adjusted to the
+///   output format, and a hash of the contents is inserted if `options.hash_names` is set.
+/// * `width` - An optional new width for the image.
+/// * `height` - An optional new height for the image.
+/// * `output_format` - An optional reference to a `String` specifying the desired output format.
+/// * `options` - The job options.
+/// * `sink` - The sink receiving the encoded image.
+///
+/// # Returns
+///
+/// A `Result` containing an `ImageInfo` describing the output. Its path is the one reported
+/// by the sink.
+///
+/// # Errors
+///
+/// This function will return an error in the same cases as `resize_file`, or if the sink
+/// fails to write the image.
+pub fn resize_file_into(
+    input: &Path,
+    name: &Path,
+    width: Option<&u32>,
+    height: Option<&u32>,
+    output_format: Option<&String>,
+    options: &JobOptions,
+    sink: &mut dyn OutputSink,
+) -> Result<ImageInfo, Box<dyn std::error::Error>> {
+    let _span = info_span!("resize_file", input = ?input).entered();
+    let start = Instant::now();
+    options.cancel.check()?;
+    let source = read_source(input, options)?;
+
+    let (save_format, name) = save_format_and_path(name, output_format, &options.encoders, || {
+        infer_format_from_path(Some(name))
+    })?;
+    let (image_buffer, new_width, new_height) = encode_file(
+        input,
+        width,
+        height,
+        save_format,
+        options,
+        &Pipeline::standard(),
+    )?;
+
+    let name = if options.hash_names {
+        manifest::hashed_path(&name, &manifest::content_hash(&image_buffer))
+    } else {
+        name
+    };
+    let path = sink.write(&name, &image_buffer)?;
+    info!("Wrote image to: {:?}", path);
+
+    Ok(ImageInfo {
+        width: new_width,
+        height: new_height,
+        format: save_format,
+        path,
+        file_size: image_buffer.len() as u64,
+        source,
+        duration: Some(start.elapsed()),
+        verification: None,
+    })
+}
+
+/// Runs the pipeline on an image file and returns the encoded image and its dimensions, or
+/// returns the output stored in `options.cache` by an identical earlier job.
+fn encode_file(
+    input: &Path,
+    width: Option<&u32>,
+    height: Option<&u32>,
+    save_format: ImageFormat,
+    options: &JobOptions,
+    pipeline: &Pipeline,
+) -> Result<(Vec<u8>, u32, u32), Box<dyn std::error::Error>> {
+    let cache_key = match &options.cache {
+        Some(cache) => {
+            let mut source = Vec::new();
+            options.storage.open_read(input)?.read_to_end(&mut source)?;
+            let recipe = format!(
+                "{:?} {:?} {:?} {:?} {:?} {} {:?}",
+                width,
+                height,
+                save_format,
+                options.encode,
+                options.resize,
+                options.low_memory,
+                pipeline.stage_names()
+            );
+            let key = cache.key(&source, &recipe);
+            if let Some(cached) = cache.get(&key) {
+                let dimensions = image::ImageReader::new(io::Cursor::new(&cached))
+                    .with_guessed_format()
+                    .map_err(image::ImageError::from)
+                    .and_then(|mut reader| {
+                        // TGA files have no signature to guess the format from
+                        if reader.format().is_none() {
+                            reader.set_format(save_format);
+                        }
+                        reader.into_dimensions()
+                    });
+                match dimensions {
+                    Ok((new_width, new_height)) => {
+                        info!("Using the cached output {}", key);
+                        return Ok((cached, new_width, new_height));
+                    }
+                    Err(e) => warn!("Ignoring the invalid cache entry {}: {}", key, e),
+                }
+            }
+            Some(key)
+        }
+        None => None,
+    };
+
+    let mut state = PipelineState::new(input, width.copied(), height.copied());
+    state.format = Some(save_format);
+    pipeline.run(&mut state, options)?;
+    options.cancel.check()?;
+
+    let image_buffer = state
+        .encoded
+        .ok_or("The pipeline did not produce an encoded image")?;
+    let (new_width, new_height) = state
+        .image
+        .as_ref()
+        .map(|image| image.dimensions())
+        .ok_or("The pipeline did not keep the final image")?;
+    info!(
+        "Estimated size ({}): {} bytes",
+        image_format_to_string(save_format).to_uppercase(),
+        image_buffer.len()
+    );
+    if let (Some(cache), Some(key)) = (&options.cache, cache_key) {
+        if let Err(e) = cache.put(&key, &image_buffer) {
+            warn!("Failed to cache the output: {}", e);
+        }
+    }
+
+    Ok((image_buffer, new_width, new_height))
 }
 
 /// Estimates the size of an encoded image and returns the encoded buffer.
 ///
+/// The image is encoded with the built-in encoders (see `codec::EncoderRegistry::builtin`).
+///
 /// # Arguments
 ///
 /// * `image` - The `ImageBuffer` to encode.
 /// * `format` - The `ImageFormat` specifying the desired output format (e.g., `ImageFormat::Jpeg` or `ImageFormat::Png`).
+/// * `options` - The `EncodeOptions` configuring the encoder (e.g. progressive output).
 ///
 /// # Returns
 ///
@@ -202,40 +982,23 @@ pub fn resize_image(
 ///
 /// This function will return an error if:
 /// - The encoding process fails.
-/// - The specified format is not supported (currently only JPEG and PNG are supported).
+/// - The specified format is not supported (currently JPEG, PNG, BMP, TGA, WebP and AVIF are
+///   supported).
 pub fn estimate_size_and_encode(
     image: &ImageBuffer<Rgba<u8>, Vec<u8>>,
     format: ImageFormat,
+    options: &EncodeOptions,
 ) -> Result<(u64, Vec<u8>), Box<dyn std::error::Error>> {
-    let mut buffer = Vec::new();
-    let (width, height) = image.dimensions();
-
-    match format {
-        ImageFormat::Jpeg => {
-            let rbg_image = image::DynamicImage::ImageRgba8(image.clone()).into_rgb8();
-            image::codecs::jpeg::JpegEncoder::new_with_quality(&mut buffer, 75).encode(
-                &rbg_image,
-                width,
-                height,
-                image::ExtendedColorType::Rgb8,
-            )?;
-        }
-        ImageFormat::Png => {
-            image::codecs::png::PngEncoder::new_with_quality(
-                &mut buffer,
-                image::codecs::png::CompressionType::default(),
-                image::codecs::png::FilterType::default(),
-            )
-            .write_image(image, width, height, image::ExtendedColorType::Rgba8)?;
-        }
-        _ => return Err("Unsoported format for estimation and encoding".into()),
-    }
+    let buffer = codec::EncoderRegistry::builtin().encode(image, format, options)?;
 
     Ok((buffer.len() as u64, buffer))
 }
 
 /// Saves an encoded image buffer to a file.
 ///
+/// The buffer is written atomically (see `save_image_with`), so an existing file is never
+/// left truncated if the write fails.
+///
 /// # Arguments
 ///
 /// * `image_buffer` - The encoded image buffer as a `Vec<u8>`.
@@ -262,31 +1025,76 @@ pub fn save_image(
     output_path: &Path,
     save_format: ImageFormat,
 ) -> Result<ImageInfo, Box<dyn std::error::Error>> {
+    save_image_with(image_buffer, width, height, output_path, save_format, true)
+}
+
+/// Saves an encoded image buffer to a file, optionally writing it in place.
+///
+/// With `atomic` set, the buffer is first written to a temporary file in the destination
+/// directory, which is then renamed over `output_path`. If the write fails or the process is
+/// killed, the previous file at `output_path` is left intact. Clearing `atomic` writes
+/// directly to `output_path`, for filesystems where renaming over a file is not supported.
+///
+/// # Arguments
+///
+/// * `image_buffer` - The encoded image buffer as a `Vec<u8>`.
+/// * `width` - The width of the image in pixels.
+/// * `height` - The height of the image in pixels.
+/// * `output_path` - The path where the image should be saved.
+/// * `save_format` - The `ImageFormat` specifying the desired output format.
+/// * `atomic` - Whether to write through a temporary file and rename it.
+///
+/// # Returns
+///
+/// A `Result` containing an `ImageInfo` struct with metadata about the saved image.
+///
+/// # Errors
+///
+/// This function will return the same errors as `save_image`.
+pub fn save_image_with(
+    image_buffer: Vec<u8>,
+    width: u32,
+    height: u32,
+    output_path: &Path,
+    save_format: ImageFormat,
+    atomic: bool,
+) -> Result<ImageInfo, Box<dyn std::error::Error>> {
+    save_to(
+        &FileSystem,
+        image_buffer,
+        width,
+        height,
+        output_path,
+        save_format,
+        atomic,
+    )
+}
+
+/// Saves an encoded image buffer like `save_image_with`, to the given storage backend.
+fn save_to(
+    storage: &dyn Storage,
+    image_buffer: Vec<u8>,
+    width: u32,
+    height: u32,
+    output_path: &Path,
+    save_format: ImageFormat,
+    atomic: bool,
+) -> Result<ImageInfo, Box<dyn std::error::Error>> {
+    let _span = info_span!("save", path = ?output_path, bytes = image_buffer.len()).entered();
     if width == 0 || height == 0 {
         return Err("Failed to save image: Invalid dimensions".into());
     }
 
-    // Check if the file extension matches the save format
-    if let Some(extension) = output_path.extension().and_then(|ext| ext.to_str()) {
-        let ext_format = string_to_image_format(extension)?;
-        if ext_format != save_format {
-            return Err(format!(
-                "Output file extension is not compatible with the specified format. Expected: {:?}, got: {:?}",
-                save_format, ext_format
-            ).into());
-        }
-    } else {
-        return Err("Output path has no file extension".into());
-    }
+    check_output_path(output_path, save_format)?;
 
-    println!("Saving image to: {:?}", output_path);
-    println!(
+    info!("Saving image to: {:?}", output_path);
+    info!(
         "Using format: {}",
         image_format_to_string(save_format).to_uppercase()
     );
 
     // Write the image buffer to the file
-    std::fs::write(output_path, &image_buffer)?;
+    storage.write_file(output_path, &image_buffer, atomic)?;
 
     // Get the file size of the saved image
     let file_size = image_buffer.len() as u64;
@@ -297,9 +1105,37 @@ pub fn save_image(
         format: save_format,
         path: output_path.to_path_buf(),
         file_size,
+        source: None,
+        duration: None,
+        verification: None,
     })
 }
 
+/// Checks that the extension of an output path matches the format it is saved in.
+///
+/// # Errors
+///
+/// This function will return an error if the path has no extension, or an extension of
+/// another or an unknown format.
+fn check_output_path(
+    output_path: &Path,
+    save_format: ImageFormat,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let extension = output_path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .ok_or("Output path has no file extension")?;
+    let ext_format = format::resolve(extension)
+        .ok_or_else(|| format!("Unsupported image format {}", extension))?;
+    if ext_format != save_format {
+        return Err(format!(
+            "Output file extension is not compatible with the specified format. Expected: {:?}, got: {:?}",
+            save_format, ext_format
+        ).into());
+    }
+    Ok(())
+}
+
 /// Determines the save format and output path for an image.
 ///
 /// This function takes an image buffer, an output path, and an optional output format,
@@ -342,11 +1178,28 @@ pub fn determine_save_format_and_path(
     image: &ImageBuffer<Rgba<u8>, Vec<u8>>,
     output_path: &Path,
     output_format: Option<&String>,
+) -> Result<(ImageFormat, PathBuf), Box<dyn std::error::Error>> {
+    save_format_and_path(
+        output_path,
+        output_format,
+        EncoderRegistry::builtin(),
+        || infer_format(image, Some(output_path)),
+    )
+}
+
+/// Determines the save format and output path, calling `infer` to infer the format if no
+/// output format is given. The format must have an encoder in `encoders`.
+fn save_format_and_path(
+    output_path: &Path,
+    output_format: Option<&String>,
+    encoders: &EncoderRegistry,
+    infer: impl FnOnce() -> ImageFormat,
 ) -> Result<(ImageFormat, PathBuf), Box<dyn std::error::Error>> {
     let save_format = match output_format {
-        Some(f) => string_to_image_format(f),
-        None => validate_new_image_format(infer_format(image, Some(output_path))),
-    }?;
+        Some(f) => OutputFormat::parse(f, encoders),
+        None => OutputFormat::new(infer(), encoders),
+    }?
+    .image_format();
 
     let new_extension = determine_extension(output_path, save_format);
     let new_output = output_path.with_extension(new_extension);
@@ -384,7 +1237,7 @@ pub fn image_format_to_string(format: ImageFormat) -> String {
 ///
 /// # Arguments
 ///
-/// * `path` - A reference to the `PathBuf` to check.
+/// * `path` - A reference to the `Path` to check.
 ///
 /// # Returns
 ///
@@ -395,74 +1248,22 @@ pub fn image_format_to_string(format: ImageFormat) -> String {
 ///
 /// This function will return an error if:
 /// - The path exists and the user chooses not to replace it.
+/// - The path exists and stdin is not a terminal, so the user cannot be asked.
 /// - There's an error while checking if the path exists.
 ///
 /// # Side Effects
 ///
 /// This function prints to stdout and reads from stdin if the path exists.
-pub fn check_if_path_exists(path: &PathBuf) -> Result<(), Box<dyn std::error::Error>> {
-    match path.try_exists() {
-        Ok(true) => {
-            print!(
-                "\n{:?} already exists. Do you want to replace it? (y/n): ",
-                path
-            );
-            std::io::stdout().flush().unwrap();
-
-            let mut input = String::new();
-            std::io::stdin().read_line(&mut input).unwrap();
-
-            if input.trim().to_lowercase() != "y" {
-                return Err(format!("{:?} already exists. Operation cancelled!", path).into());
-            }
-        }
-        Ok(false) => (),
-        Err(e) => return Err(format!("Error checking path {:?}: {}", path, e).into()),
-    }
-    Ok(())
-}
-
-/// Converts a string representation of an image format to the corresponding `ImageFormat`.
-///
-/// # Arguments
-///
-/// * `format` - A string representing the image format ("jpeg", "jpg", or "png").
-///
-/// # Returns
-///
-/// The corresponding `ImageFormat`, or an error if the format is unsupported.
-fn string_to_image_format(format: &str) -> Result<ImageFormat, Box<dyn std::error::Error>> {
-    match format.to_lowercase().as_str() {
-        "jpeg" | "jpg" => Ok(ImageFormat::Jpeg),
-        "png" => Ok(ImageFormat::Png),
-        _ => Err(format!("Unsoported image format {}", format).into()),
-    }
-}
-
-/// Validates that the given image format is supported by this library.
-///
-/// # Arguments
-///
-/// * `format` - The `ImageFormat` to validate.
-///
-/// # Returns
-///
-/// The validated `ImageFormat` if it's supported, or an error if it's not.
-fn validate_new_image_format(
-    format: ImageFormat,
-) -> Result<ImageFormat, Box<dyn std::error::Error>> {
-    match format {
-        ImageFormat::Png | ImageFormat::Jpeg => Ok(format),
-        _ => Err(format!(
-            "Unsoported conversion to image format '{:?}'. Specify a valid format with --format.",
-            format
-        )
-        .into()),
-    }
+/// Use `OverwritePolicy` directly to replace or keep existing files without asking.
+pub fn check_if_path_exists(path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    OverwritePolicy::Prompt.resolve(path).map(|_| ())
 }
 
 /// Determines the appropriate file extension based on the image format and original path.
 ///
+/// Extensions are compared case-insensitively, and the case of the original one is kept:
+/// `photo.JPEG` stays `photo.JPEG` as a JPEG, and becomes `photo.PNG` as a PNG.
+///
 /// # Arguments
 ///
 /// * `path` - The original file path.
@@ -472,11 +1273,14 @@ fn validate_new_image_format(
 ///
 /// A string representing the appropriate file extension.
 fn determine_extension(path: &Path, format: ImageFormat) -> String {
-    path.extension()
-        .and_then(|ext| ext.to_str())
-        .filter(|&ext| (ext == "jpg" || ext == "jpeg") && format == ImageFormat::Jpeg)
-        .map(|ext| ext.to_string())
-        .unwrap_or_else(|| format.extensions_str()[0].to_string())
+    let extension = path.extension().and_then(|ext| ext.to_str());
+    match extension {
+        Some(ext) if ImageFormat::from_extension(ext) == Some(format) => ext.to_string(),
+        Some(ext) if !ext.is_empty() && !ext.chars().any(|c| c.is_ascii_lowercase()) => {
+            format.extensions_str()[0].to_ascii_uppercase()
+        }
+        _ => format.extensions_str()[0].to_string(),
+    }
 }
 
 /// Infers the image format from the image buffer or file path.
@@ -493,23 +1297,25 @@ fn infer_format(image: &ImageBuffer<Rgba<u8>, Vec<u8>>, path: Option<&Path>) ->
     // Convert the image buffer to a byte slice
     let bytes = image.as_raw();
 
-    // Use guess_format to infer the image format
-    match guess_format(bytes) {
-        Ok(format) => format,
-        Err(_) => {
-            // Try to infer format from path if available
-            if let Some(file_path) = path {
-                match ImageFormat::from_path(file_path) {
-                    Ok(format) => format,
-                    Err(_) => {
-                        eprintln!("Warning: Could not guess image format. Defaulting to JPEG.");
-                        ImageFormat::Jpeg
-                    }
-                }
-            } else {
-                eprintln!("Warning: Could not guess image format. Defaulting to JPEG.");
-                ImageFormat::Jpeg
-            }
+    // Use guess_format to infer the image format, falling back to the path
+    guess_format(bytes).unwrap_or_else(|_| infer_format_from_path(path))
+}
+
+/// Infers the image format from a file path.
+///
+/// # Arguments
+///
+/// * `path` - An optional file path whose extension determines the format.
+///
+/// # Returns
+///
+/// The inferred `ImageFormat`, defaulting to JPEG if the format cannot be determined.
+fn infer_format_from_path(path: Option<&Path>) -> ImageFormat {
+    match path.map(ImageFormat::from_path) {
+        Some(Ok(format)) => format,
+        _ => {
+            warn!("Could not guess image format. Defaulting to JPEG.");
+            ImageFormat::Jpeg
         }
     }
 }
@@ -581,6 +1387,179 @@ mod tests {
         }
     }
 
+    mod resize_image_test {
+        use super::*;
+        use crate::options::Rounding;
+        use image::RgbImage;
+
+        #[test]
+        fn resizes_rgba_source() {
+            let input =
+                DynamicImage::ImageRgba8(ImageBuffer::from_pixel(40, 20, Rgba([10, 20, 30, 255])));
+            let resized = resize_image(input, Some(&10), None).unwrap();
+            assert_eq!(resized.dimensions(), (10, 5));
+            assert_eq!(resized.as_raw().len(), 10 * 5 * 4);
+            assert_eq!(resized.get_pixel(3, 3), &Rgba([10, 20, 30, 255]));
+        }
+
+        #[test]
+        fn threads_match_single_threaded() {
+            let input = DynamicImage::ImageRgba8(ImageBuffer::from_fn(64, 48, |x, y| {
+                Rgba([(x * 4) as u8, (y * 5) as u8, ((x + y) * 2) as u8, 255])
+            }));
+            let single = resize_image(input.clone(), Some(&20), None).unwrap();
+            let options = ResizeOptions {
+                threads: 4,
+                ..Default::default()
+            };
+            let parallel = resize_image_with(input, Some(&20), None, &options).unwrap();
+            assert_eq!(parallel.dimensions(), single.dimensions());
+            assert!(metrics::psnr(&single, &parallel).unwrap() > 50.0);
+        }
+
+        #[test]
+        fn resizes_rgb_source() {
+            let input = DynamicImage::ImageRgb8(RgbImage::new(30, 60));
+            let resized = resize_image(input, None, Some(&20)).unwrap();
+            assert_eq!(resized.dimensions(), (10, 20));
+        }
+
+        #[test]
+        fn resizes_one_pixel_wide_sources() {
+            let tall = DynamicImage::ImageRgb8(RgbImage::new(1, 1000));
+            let parallel = ResizeOptions {
+                threads: 4,
+                ..Default::default()
+            };
+            for options in [ResizeOptions::default(), parallel] {
+                let resized = resize_image_with(tall.clone(), None, Some(&10), &options).unwrap();
+                assert_eq!(resized.dimensions(), (1, 10));
+                let resized = resize_image_with(tall.clone(), Some(&3), None, &options).unwrap();
+                assert_eq!(resized.dimensions(), (3, 3000));
+                let resized =
+                    resize_image_with(tall.clone(), Some(&1), Some(&1), &options).unwrap();
+                assert_eq!(resized.dimensions(), (1, 1));
+            }
+            let tiled =
+                tiled::resize_image_tiled(&tall, None, Some(&7), 16, &ResizeOptions::default())
+                    .unwrap();
+            assert_eq!(tiled.dimensions(), (1, 7));
+            let single = DynamicImage::ImageRgb8(RgbImage::new(1, 1));
+            let resized = resize_image(single, Some(&5), None).unwrap();
+            assert_eq!(resized.dimensions(), (5, 5));
+        }
+
+        #[test]
+        fn rejects_zero_sizes_without_panicking() {
+            let empty = DynamicImage::ImageRgb8(RgbImage::new(0, 10));
+            assert!(resize_image(empty, Some(&10), None).is_err());
+            let input = DynamicImage::ImageRgb8(RgbImage::new(10, 10));
+            assert!(resize_image(input, Some(&0), None).is_err());
+            // A very wide image keeps a height of one pixel
+            let wide = DynamicImage::ImageRgb8(RgbImage::new(1000, 1));
+            assert_eq!(
+                resize_image(wide, Some(&10), None).unwrap().dimensions(),
+                (10, 1)
+            );
+        }
+
+        #[test]
+        fn rounds_dimensions_to_multiples() {
+            let even = |rounding| ResizeOptions {
+                rounding,
+                multiple_of: 2,
+                ..Default::default()
+            };
+            let round = even(Rounding::Round);
+            // 1920x1080 to a width of 853 gives a height of 479.8
+            let dimensions = |w, h, options: &ResizeOptions| {
+                determine_new_dimensions(1920, 1080, w, h, options).unwrap()
+            };
+            assert_eq!(dimensions(Some(&853), None, &round), (854, 480));
+            assert_eq!(
+                dimensions(Some(&853), None, &even(Rounding::Floor)),
+                (852, 478)
+            );
+            assert_eq!(dimensions(None, Some(&1), &round), (2, 2));
+            assert_eq!(dimensions(Some(&7), Some(&9), &round), (8, 10));
+            let wide = DynamicImage::ImageRgb8(RgbImage::new(1000, 1));
+            let resized = resize_image_with(wide, Some(&10), None, &round).unwrap();
+            assert_eq!(resized.dimensions(), (10, 2));
+        }
+
+        #[test]
+        fn fits_a_pixel_budget() {
+            let budget = |max_pixels, multiple_of| ResizeOptions {
+                max_output_pixels: Some(max_pixels),
+                multiple_of,
+                ..Default::default()
+            };
+            let twelve = budget(12_000_000, 1);
+            let dimensions = |w, h, width, height, options: &ResizeOptions| {
+                determine_new_dimensions(w, h, width, height, options).unwrap()
+            };
+            assert_eq!(dimensions(6000, 4000, None, None, &twelve), (4242, 2828));
+            assert_eq!(dimensions(4000, 6000, None, None, &twelve), (2828, 4242));
+            // Outputs within the budget are kept, and never enlarged
+            assert_eq!(dimensions(4000, 3000, None, None, &twelve), (4000, 3000));
+            assert_eq!(dimensions(640, 480, Some(&800), None, &twelve), (800, 600));
+            // The budget caps the requested size
+            assert_eq!(
+                dimensions(640, 480, Some(&8000), None, &twelve),
+                (4000, 3000)
+            );
+            // Rounding never exceeds the budget, even for extreme aspect ratios
+            for (w, h, options) in [
+                (6000, 4000, budget(1_000_000, 16)),
+                (100_000, 3, budget(5000, 1)),
+                (7, 99_999, budget(777, 2)),
+            ] {
+                let (width, height) = dimensions(w, h, None, None, &options);
+                assert!(width as u64 * height as u64 <= options.max_output_pixels.unwrap());
+                assert_eq!(
+                    (width % options.multiple_of, height % options.multiple_of),
+                    (0, 0)
+                );
+            }
+            assert!(determine_new_dimensions(10, 10, None, None, &Default::default()).is_err());
+        }
+
+        #[test]
+        fn zero_sides_are_invalid_requested_sizes() {
+            for (width, height, expected) in
+                [(Some(&0), None, "width"), (Some(&40), Some(&0), "height")]
+            {
+                let error = determine_new_dimensions(100, 50, width, height, &Default::default())
+                    .unwrap_err();
+                match error.downcast_ref::<error::ResizeError>() {
+                    Some(error::ResizeError::InvalidRequestedSize { side, value }) => {
+                        assert_eq!((*side, *value), (expected, 0))
+                    }
+                    _ => panic!("Unexpected error: {}", error),
+                }
+            }
+            let image = DynamicImage::new_rgb8(10, 10);
+            let error = resize_image(image, Some(&0), None).unwrap_err();
+            assert_eq!(
+                error.to_string(),
+                "Invalid requested width 0: it must be at least 1 pixel"
+            );
+        }
+
+        #[test]
+        fn empty_sources_are_invalid_dimensions() {
+            let error =
+                determine_new_dimensions(0, 50, Some(&10), None, &Default::default()).unwrap_err();
+            assert!(matches!(
+                error.downcast_ref::<error::ResizeError>(),
+                Some(error::ResizeError::InvalidDimensions {
+                    width: 0,
+                    height: 50
+                })
+            ));
+        }
+    }
+
     mod save_image_test {
         use super::*;
         use tempfile::TempDir;
@@ -594,7 +1573,8 @@ mod tests {
             let output_path = dir.path().join("output.jpg");
             let format = ImageFormat::Jpeg;
 
-            let (_, image_buffer) = estimate_size_and_encode(&image, format).unwrap();
+            let (_, image_buffer) =
+                estimate_size_and_encode(&image, format, &EncodeOptions::default()).unwrap();
 
             let result =
                 save_image(image_buffer, width, height, output_path.as_path(), format).unwrap();
@@ -605,6 +1585,54 @@ mod tests {
             assert_eq!(result.height, height);
         }
 
+        #[test]
+        fn test_save_image_uppercase_extension() {
+            let dir = TempDir::new().expect("Failed to create a temp dir");
+            let image = create_mock_jpeg();
+            let format = ImageFormat::Png;
+            let (_, image_buffer) =
+                estimate_size_and_encode(&image, format, &EncodeOptions::default()).unwrap();
+
+            let (_, output_path) = determine_save_format_and_path(
+                &image,
+                &dir.path().join("Output.JPG"),
+                Some(&"png".to_string()),
+            )
+            .unwrap();
+            assert_eq!(output_path, dir.path().join("Output.PNG"));
+            let result = save_image(image_buffer, 10, 10, &output_path, format).unwrap();
+            assert_eq!(result.path, dir.path().join("Output.PNG"));
+
+            for (path, format, expected) in [
+                ("a.JPEG", None, "a.JPEG"),
+                ("a.Jpg", None, "a.Jpg"),
+                ("a.PNG", Some("png"), "a.PNG"),
+                ("a.Png", Some("webp"), "a.webp"),
+            ] {
+                let format = format.map(String::from);
+                let (_, output_path) =
+                    determine_save_format_and_path(&image, Path::new(path), format.as_ref())
+                        .unwrap();
+                assert_eq!(output_path, Path::new(expected));
+            }
+        }
+
+        #[test]
+        fn test_save_image_replaces_atomically() {
+            let dir = TempDir::new().expect("Failed to create a temp dir");
+            let output_path = dir.path().join("output.png");
+            std::fs::write(&output_path, b"old").unwrap();
+
+            for atomic in [true, false] {
+                let buffer = format!("new {}", atomic).into_bytes();
+                save_image_with(buffer.clone(), 1, 1, &output_path, ImageFormat::Png, atomic)
+                    .unwrap();
+                assert_eq!(std::fs::read(&output_path).unwrap(), buffer);
+            }
+            // No temporary file is left behind
+            assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+        }
+
         #[test]
         fn test_save_image_different_format() {
             let dir = TempDir::new().expect("Failed to create a temp dir");
@@ -614,7 +1642,8 @@ mod tests {
             let output_path = dir.path().join("output.jpg");
             let format = ImageFormat::Png;
 
-            let (_, image_buffer) = estimate_size_and_encode(&image, format).unwrap();
+            let (_, image_buffer) =
+                estimate_size_and_encode(&image, format, &EncodeOptions::default()).unwrap();
 
             let result = save_image(image_buffer, width, height, output_path.as_path(), format);
 
@@ -638,7 +1667,8 @@ mod tests {
             let output_path = non_existent_dir.join("output.jpg");
             let format = ImageFormat::Jpeg;
 
-            let (_, image_buffer) = estimate_size_and_encode(&image, format).unwrap();
+            let (_, image_buffer) =
+                estimate_size_and_encode(&image, format, &EncodeOptions::default()).unwrap();
 
             let result = save_image(image_buffer, width, height, output_path.as_path(), format);
 
@@ -661,7 +1691,8 @@ mod tests {
             let output_path = dir.path().join("invalid_output.jpg");
             let format = ImageFormat::Jpeg;
 
-            let (_, image_buffer) = estimate_size_and_encode(&image, format).unwrap();
+            let (_, image_buffer) =
+                estimate_size_and_encode(&image, format, &EncodeOptions::default()).unwrap();
 
             let result = save_image(image_buffer, 0, 0, output_path.as_path(), format);
 
@@ -675,4 +1706,376 @@ mod tests {
             }
         }
     }
+
+    mod copy_through_test {
+        use super::*;
+        use tempfile::TempDir;
+
+        fn write_png(dir: &TempDir) -> PathBuf {
+            let path = dir.path().join("input.png");
+            ImageBuffer::from_pixel(8, 6, Rgba([1u8, 2, 3, 255]))
+                .save(&path)
+                .unwrap();
+            path
+        }
+
+        #[test]
+        fn copies_unchanged_image() {
+            let dir = TempDir::new().expect("Failed to create a temp dir");
+            let input = write_png(&dir);
+            let output = dir.path().join("output.png");
+
+            let info = copy_through(
+                &input,
+                &output,
+                Some(&8),
+                None,
+                None,
+                &JobOptions::default(),
+            )
+            .unwrap()
+            .expect("the image should be copied through");
+            assert_eq!((info.width, info.height), (8, 6));
+            assert_eq!(
+                std::fs::read(&input).unwrap(),
+                std::fs::read(&output).unwrap()
+            );
+        }
+
+        #[test]
+        fn reencodes_when_something_changes() {
+            let dir = TempDir::new().expect("Failed to create a temp dir");
+            let input = write_png(&dir);
+            let png = dir.path().join("output.png");
+            let jpeg = dir.path().join("output.jpg");
+            let defaults = JobOptions {
+                overwrite: OverwritePolicy::Never,
+                ..Default::default()
+            };
+            let progressive = JobOptions {
+                encode: EncodeOptions {
+                    progressive: true,
+                    ..Default::default()
+                },
+                ..defaults.clone()
+            };
+
+            let resize = copy_through(&input, &png, Some(&4), None, None, &defaults);
+            let convert = copy_through(&input, &jpeg, None, Some(&6), None, &defaults);
+            let encode = copy_through(&input, &png, Some(&8), None, None, &progressive);
+            assert!(resize.unwrap().is_none());
+            assert!(convert.unwrap().is_none());
+            assert!(encode.unwrap().is_none());
+            assert!(!png.exists() && !jpeg.exists());
+        }
+
+        #[test]
+        fn checks_limits_of_unchanged_images() {
+            let dir = TempDir::new().expect("Failed to create a temp dir");
+            let input = write_png(&dir);
+            let output = dir.path().join("output.png");
+            let options = JobOptions {
+                limits: limits::Limits {
+                    max_pixels: Some(40),
+                    ..Default::default()
+                },
+                ..Default::default()
+            };
+
+            let error = resize_file(&input, &output, Some(&8), None, None, &options).unwrap_err();
+            assert_eq!(
+                error.downcast_ref::<error::ResizeError>(),
+                Some(&error::ResizeError::PixelsExceeded {
+                    width: 8,
+                    height: 6,
+                    max_pixels: 40
+                })
+            );
+            assert!(!output.exists());
+        }
+    }
+
+    mod resize_file_test {
+        use super::*;
+        use tempfile::TempDir;
+
+        #[test]
+        fn reports_source_and_output() {
+            let dir = TempDir::new().expect("Failed to create a temp dir");
+            let input = dir.path().join("input.png");
+            ImageBuffer::from_fn(40, 30, |x, y| Rgba([x as u8, y as u8, 0u8, 255]))
+                .save(&input)
+                .unwrap();
+            let output = dir.path().join("output.jpg");
+
+            let info = resize_file(
+                &input,
+                &output,
+                Some(&20),
+                None,
+                None,
+                &JobOptions::default(),
+            )
+            .unwrap();
+            assert_eq!((info.width, info.height), (20, 15));
+            assert_eq!(info.format, ImageFormat::Jpeg);
+            assert_eq!(info.mime_type(), "image/jpeg");
+            assert_eq!(info.file_size, std::fs::metadata(&output).unwrap().len());
+
+            let source = info.source.as_ref().expect("source info should be set");
+            assert_eq!((source.width, source.height), (40, 30));
+            assert_eq!(source.format, ImageFormat::Png);
+            assert_eq!(source.file_size, std::fs::metadata(&input).unwrap().len());
+
+            let ratio = info.compression_ratio().unwrap();
+            assert!((ratio - source.file_size as f64 / info.file_size as f64).abs() < 1e-9);
+            assert!(info.duration.is_some());
+        }
+
+        #[test]
+        fn reads_and_writes_through_storage() {
+            let storage = storage::MemoryStorage::new();
+            let mut png = Vec::new();
+            image::RgbaImage::new(40, 30)
+                .write_to(&mut std::io::Cursor::new(&mut png), ImageFormat::Png)
+                .unwrap();
+            storage.insert("input.png", png);
+
+            let options = JobOptions {
+                storage: storage::SharedStorage::new(storage.clone()),
+                safety: safety::SafetyLimits {
+                    decode_timeout: Some(Duration::from_secs(10)),
+                    ..Default::default()
+                },
+                ..Default::default()
+            };
+            let input = Path::new("input.png");
+            let output = Path::new("output.jpg");
+            let info = resize_file(input, output, Some(&20), None, None, &options).unwrap();
+            assert_eq!((info.width, info.height), (20, 15));
+            assert!(!output.exists());
+
+            let encoded = storage.get(output).expect("the output should be stored");
+            assert_eq!(encoded.len() as u64, info.file_size);
+            assert_eq!(guess_format(&encoded).unwrap(), ImageFormat::Jpeg);
+        }
+
+        #[test]
+        fn checks_safety_limits_before_copying_through() {
+            let dir = TempDir::new().expect("Failed to create a temp dir");
+            let input = dir.path().join("input.png");
+            image::RgbaImage::new(40, 30).save(&input).unwrap();
+            let output = dir.path().join("output.png");
+
+            let options = JobOptions {
+                safety: safety::SafetyLimits {
+                    max_width: Some(10),
+                    max_height: Some(10),
+                    ..Default::default()
+                },
+                ..Default::default()
+            };
+            let error = resize_file(&input, &output, Some(&40), None, None, &options).unwrap_err();
+            assert_eq!(
+                error.downcast_ref::<error::ResizeError>(),
+                Some(&error::ResizeError::DimensionsExceeded {
+                    width: 40,
+                    height: 30
+                })
+            );
+            assert!(!output.exists());
+        }
+
+        #[test]
+        fn renames_instead_of_replacing() {
+            let dir = TempDir::new().expect("Failed to create a temp dir");
+            let input = dir.path().join("input.png");
+            image::RgbaImage::new(40, 30).save(&input).unwrap();
+            let output = dir.path().join("output.png");
+            std::fs::write(&output, b"existing").unwrap();
+
+            let options = JobOptions {
+                overwrite: OverwritePolicy::Rename,
+                ..Default::default()
+            };
+            let info = resize_file(&input, &output, Some(&20), None, None, &options).unwrap();
+            assert_eq!(info.path, dir.path().join("output_1.png"));
+            assert!(info.path.exists());
+            assert_eq!(std::fs::read(&output).unwrap(), b"existing");
+        }
+
+        #[test]
+        fn names_outputs_after_their_hash() {
+            let storage = storage::MemoryStorage::new();
+            let mut png = Vec::new();
+            image::RgbaImage::new(40, 30)
+                .write_to(&mut std::io::Cursor::new(&mut png), ImageFormat::Png)
+                .unwrap();
+            storage.insert("input.png", png.clone());
+
+            let options = JobOptions {
+                storage: storage::SharedStorage::new(storage.clone()),
+                overwrite: OverwritePolicy::Never,
+                hash_names: true,
+                ..Default::default()
+            };
+            let input = Path::new("input.png");
+            let output = Path::new("out/photo.png");
+            let info = resize_file(input, output, Some(&20), None, None, &options).unwrap();
+            let encoded = storage.get(&info.path).unwrap();
+            let hash = manifest::content_hash(&encoded);
+            assert_eq!(info.path, PathBuf::from(format!("out/photo.{}.png", hash)));
+            // The same contents get the same name, replaced despite the overwrite policy
+            let again = resize_file(input, output, Some(&20), None, None, &options).unwrap();
+            assert_eq!(again.path, info.path);
+
+            // Copied through unchanged, the output is named after the input contents
+            let copied = resize_file(input, output, Some(&40), None, None, &options).unwrap();
+            let hash = manifest::content_hash(&png);
+            assert_eq!(
+                copied.path,
+                PathBuf::from(format!("out/photo.{}.png", hash))
+            );
+        }
+
+        #[test]
+        fn cancelled_job_writes_nothing() {
+            let dir = TempDir::new().expect("Failed to create a temp dir");
+            let input = dir.path().join("input.png");
+            ImageBuffer::from_pixel(8, 8, Rgba([0u8, 0, 0, 255]))
+                .save(&input)
+                .unwrap();
+            let output = dir.path().join("output.png");
+
+            let options = JobOptions::default();
+            options.cancel.cancel();
+            let error = resize_file(&input, &output, Some(&4), None, None, &options).unwrap_err();
+            assert!(error::ResizeError::is_cancelled(error.as_ref()));
+            assert!(!output.exists());
+        }
+    }
+
+    mod reproducibility_test {
+        use super::*;
+        use crate::{options::CpuExtension, quantize::Dither};
+
+        /// A gradient with many colors, so that palettes and dithering have work to do.
+        fn write_input(storage: &storage::MemoryStorage) {
+            let image = ImageBuffer::from_fn(97, 61, |x, y| {
+                Rgba([
+                    (x * 2) as u8,
+                    (y * 4) as u8,
+                    ((x * y) % 256) as u8,
+                    255 - x as u8,
+                ])
+            });
+            let mut png = Vec::new();
+            image
+                .write_to(&mut std::io::Cursor::new(&mut png), ImageFormat::Png)
+                .unwrap();
+            storage.insert("input.png", png);
+        }
+
+        /// Resizes the input twice with fresh options and returns both outputs.
+        fn resize_twice(
+            output: &str,
+            encode: EncodeOptions,
+            resize: ResizeOptions,
+        ) -> [Vec<u8>; 2] {
+            [0, 1].map(|_| {
+                let storage = storage::MemoryStorage::new();
+                write_input(&storage);
+                let mut options = JobOptions {
+                    storage: storage::SharedStorage::new(storage.clone()),
+                    encode: encode.clone(),
+                    resize,
+                    ..Default::default()
+                };
+                options.make_deterministic();
+                let input = Path::new("input.png");
+                resize_file(input, Path::new(output), Some(&40), None, None, &options).unwrap();
+                storage.get(Path::new(output)).unwrap()
+            })
+        }
+
+        #[test]
+        fn encoder_paths_are_reproducible() {
+            let variants = [
+                EncodeOptions::default(),
+                EncodeOptions {
+                    progressive: true,
+                    dpi: Some(300),
+                    jpeg_quality: Some(90),
+                    ..Default::default()
+                },
+                EncodeOptions {
+                    png_compression: Some(9),
+                    ..Default::default()
+                },
+                EncodeOptions {
+                    palette: Some(16),
+                    dither: Dither::FloydSteinberg,
+                    ..Default::default()
+                },
+                EncodeOptions {
+                    palette: Some(16),
+                    dither: Dither::Ordered,
+                    progressive: true,
+                    ..Default::default()
+                },
+            ];
+            for encode in variants {
+                for output in ["output.jpg", "output.png"] {
+                    let [first, second] =
+                        resize_twice(output, encode.clone(), ResizeOptions::default());
+                    assert!(first == second, "{} differs with {:?}", output, encode);
+                }
+            }
+        }
+
+        #[test]
+        fn resizer_settings_do_not_change_the_output() {
+            let parallel = ResizeOptions {
+                threads: 4,
+                cpu_extension: CpuExtension::Auto,
+                ..Default::default()
+            };
+            let [single, _] =
+                resize_twice("output.png", EncodeOptions::default(), Default::default());
+            let [multi, _] = resize_twice("output.png", EncodeOptions::default(), parallel);
+            assert!(single == multi);
+        }
+
+        #[test]
+        fn metadata_of_unchanged_images_is_dropped() {
+            // A first IFD holding the date and time the photo was taken
+            let exif = [
+                &b"II*\0\x08\0\0\0\x01\0\x32\x01\x02\0\x14\0\0\0\x1a\0\0\0\0\0\0\0"[..],
+                b"2024:05:01 12:00:00\0",
+            ]
+            .concat();
+            let jpeg =
+                encode::encode_jpeg(&image::RgbaImage::new(40, 30), &EncodeOptions::default())
+                    .unwrap();
+            let jpeg = metadata::write_exif(&jpeg, ImageFormat::Jpeg, &exif)
+                .unwrap()
+                .unwrap();
+            assert!(metadata::read_exif(&jpeg).is_some());
+
+            let outputs = [0, 1].map(|_| {
+                let storage = storage::MemoryStorage::new();
+                storage.insert("input.jpg", jpeg.clone());
+                let mut options = JobOptions {
+                    storage: storage::SharedStorage::new(storage.clone()),
+                    ..Default::default()
+                };
+                options.make_deterministic();
+                let (input, output) = (Path::new("input.jpg"), Path::new("output.jpg"));
+                resize_file(input, output, Some(&40), None, None, &options).unwrap();
+                storage.get(output).unwrap()
+            });
+            assert!(outputs[0] == outputs[1]);
+            assert_eq!(metadata::read_exif(&outputs[0]), None);
+        }
+    }
 }
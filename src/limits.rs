@@ -0,0 +1,311 @@
+//! Resource limits for resize jobs.
+//!
+//! The limits are checked against the image header before the image is decoded, so an
+//! oversized image (for instance a decompression bomb) is rejected without allocating its
+//! pixel buffer. When a memory limit is set and the regular resize path would exceed it,
+//! the job degrades to the tiled path (see the `tiled` module) if that fits the limit.
+
+use crate::{
+    error::ResizeError,
+    options::JobOptions,
+    safety::SafetyLimits,
+    tiled::{band_margin, band_rows, DEFAULT_BAND_HEIGHT, LOW_MEMORY_PIXEL_THRESHOLD},
+};
+
+/// Resource limits applied to a single image, and to the archives images are extracted
+/// from.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Limits {
+    /// The maximum number of pixels of the source image. Larger images are rejected.
+    pub max_pixels: Option<u64>,
+    /// The maximum number of bytes the decoded image and the resize buffers may use.
+    pub max_memory: Option<u64>,
+    /// The maximum number of bytes extracted from an archive input, all images together.
+    /// Defaults to `archive::DEFAULT_MAX_SIZE`.
+    pub max_archive_size: Option<u64>,
+    /// The maximum number of entries of an archive input, including those that are not
+    /// images. Defaults to `archive::DEFAULT_MAX_ENTRIES`.
+    pub max_archive_entries: Option<u64>,
+}
+
+impl Limits {
+    /// Converts the limits into decoder limits for the `image` crate.
+    ///
+    /// The memory limit caps the allocations of the decoder. Without a memory limit,
+    /// the `image` crate's default allocation limit applies. The maximum width and height
+    /// are those of `safety`, and no side may exceed `max_pixels`, so that decoders refuse
+    /// oversized images even if their header was not checked beforehand.
+    ///
+    /// # Arguments
+    ///
+    /// * `safety` - The header dimension limits of the job.
+    pub fn decoder_limits(&self, safety: &SafetyLimits) -> image::Limits {
+        let mut limits = image::Limits::default();
+        if let Some(max_memory) = self.max_memory {
+            limits.max_alloc = Some(max_memory);
+        }
+        let max_side = self
+            .max_pixels
+            .map(|max_pixels| max_pixels.min(u32::MAX as u64) as u32);
+        let cap = |max: Option<u32>| max.into_iter().chain(max_side).min();
+        limits.max_image_width = cap(safety.max_width);
+        limits.max_image_height = cap(safety.max_height);
+        limits
+    }
+
+    /// Checks the number of pixels of a source image against `max_pixels`.
+    ///
+    /// # Errors
+    ///
+    /// This function will return `ResizeError::PixelsExceeded` if the image has more pixels
+    /// than `max_pixels`.
+    pub fn check_pixels(&self, width: u32, height: u32) -> Result<(), ResizeError> {
+        match self.max_pixels {
+            Some(max_pixels) if width as u64 * height as u64 > max_pixels => {
+                Err(ResizeError::PixelsExceeded {
+                    width,
+                    height,
+                    max_pixels,
+                })
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// The resize path selected for an image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResizeStrategy {
+    /// Convert the whole image to RGBA and resize it in one pass.
+    Regular,
+    /// Resize the image band by band (see `tiled::resize_image_tiled`).
+    Tiled,
+}
+
+/// Estimates the peak number of bytes needed to resize an image with the given strategy.
+///
+/// # Arguments
+///
+/// * `src` - The width and height of the source image.
+/// * `bytes_per_pixel` - The number of bytes per pixel of the decoded source image.
+/// * `dst` - The width and height of the resized image.
+/// * `strategy` - The resize path to estimate.
+pub fn estimate_memory(
+    src: (u32, u32),
+    bytes_per_pixel: u64,
+    dst: (u32, u32),
+    strategy: ResizeStrategy,
+) -> u64 {
+    let src_pixels = src.0 as u64 * src.1 as u64;
+    let dst_pixels = dst.0 as u64 * dst.1 as u64;
+    let decoded = src_pixels * bytes_per_pixel;
+
+    match strategy {
+        ResizeStrategy::Regular => {
+            // An RGBA source is moved rather than converted
+            let converted = if bytes_per_pixel == 4 {
+                0
+            } else {
+                src_pixels * 4
+            };
+            decoded + converted + dst_pixels * 4
+        }
+        ResizeStrategy::Tiled => {
+            let scale = src.1 as f64 / dst.1.max(1) as f64;
+            let strip_rows = (DEFAULT_BAND_HEIGHT + 2 * band_margin(scale)).min(src.1) as u64;
+            let band_rows = band_rows(DEFAULT_BAND_HEIGHT, scale).min(dst.1) as u64;
+            let scratch =
+                src.0 as u64 * strip_rows * (bytes_per_pixel + 4) + dst.0 as u64 * band_rows * 4;
+            decoded + dst_pixels * 4 + scratch
+        }
+    }
+}
+
+/// Checks an image against the job limits and selects the resize path.
+///
+/// The tiled path is preferred if `low_memory` is set or the image has more than
+/// `LOW_MEMORY_PIXEL_THRESHOLD` pixels. If a memory limit is set and the preferred path
+/// would exceed it, the tiled path is used instead when it fits.
+///
+/// # Arguments
+///
+/// * `src` - The width and height of the source image, as read from its header.
+/// * `bytes_per_pixel` - The number of bytes per pixel of the decoded source image.
+/// * `dst` - The width and height of the resized image.
+/// * `options` - The job options containing the limits.
+///
+/// # Returns
+///
+/// A `Result` containing the selected `ResizeStrategy`.
+///
+/// # Errors
+///
+/// This function will return an error if:
+/// - The source image has more pixels than `max_pixels` (`ResizeError::PixelsExceeded`).
+/// - Neither resize path fits within `max_memory`.
+pub fn plan_resize(
+    src: (u32, u32),
+    bytes_per_pixel: u64,
+    dst: (u32, u32),
+    options: &JobOptions,
+) -> Result<ResizeStrategy, Box<dyn std::error::Error>> {
+    let pixels = src.0 as u64 * src.1 as u64;
+    options.limits.check_pixels(src.0, src.1)?;
+
+    let preferred = if options.low_memory || pixels > LOW_MEMORY_PIXEL_THRESHOLD {
+        ResizeStrategy::Tiled
+    } else {
+        ResizeStrategy::Regular
+    };
+
+    let Some(max_memory) = options.limits.max_memory else {
+        return Ok(preferred);
+    };
+
+    if preferred == ResizeStrategy::Regular
+        && estimate_memory(src, bytes_per_pixel, dst, ResizeStrategy::Regular) <= max_memory
+    {
+        return Ok(ResizeStrategy::Regular);
+    }
+
+    let tiled = estimate_memory(src, bytes_per_pixel, dst, ResizeStrategy::Tiled);
+    if tiled <= max_memory {
+        Ok(ResizeStrategy::Tiled)
+    } else {
+        Err(format!(
+            "Resizing a {}x{} image needs about {} bytes, which exceeds the memory limit of {} bytes",
+            src.0, src.1, tiled, max_memory
+        )
+        .into())
+    }
+}
+
+/// Estimates the peak number of bytes needed to resize an image by streaming its rows (see
+/// the `streaming` module), which depends on the widths of the images but not on their
+/// heights.
+///
+/// # Arguments
+///
+/// * `src` - The width and height of the source image.
+/// * `dst` - The width and height of the resized image.
+pub fn estimate_streaming_memory(src: (u32, u32), dst: (u32, u32)) -> u64 {
+    let scale = src.1 as f64 / dst.1.max(1) as f64;
+    let (strip_rows, band_rows) = streaming_band(scale);
+    let strip_rows = strip_rows.min(src.1) as u64;
+    let band_rows = band_rows.min(dst.1) as u64;
+    // The source rows of a band in RGBA, a decoded row, and a resized band with its copy
+    // converted for the encoder
+    src.0 as u64 * (strip_rows * 4 + 8) + dst.0 as u64 * band_rows * 8
+}
+
+/// Returns the number of source rows read and destination rows produced per band of a
+/// streaming resize.
+pub(crate) fn streaming_band(scale: f64) -> (u32, u32) {
+    (
+        DEFAULT_BAND_HEIGHT + 2 * band_margin(scale),
+        band_rows(DEFAULT_BAND_HEIGHT, scale).min(DEFAULT_BAND_HEIGHT),
+    )
+}
+
+/// Checks a streaming resize against the job limits.
+///
+/// # Errors
+///
+/// This function will return an error if:
+/// - The source image has more pixels than `max_pixels` (`ResizeError::PixelsExceeded`).
+/// - The resize does not fit within `max_memory`.
+pub fn check_streaming(
+    src: (u32, u32),
+    dst: (u32, u32),
+    options: &JobOptions,
+) -> Result<(), Box<dyn std::error::Error>> {
+    options.limits.check_pixels(src.0, src.1)?;
+    let needed = estimate_streaming_memory(src, dst);
+    match options.limits.max_memory {
+        Some(max_memory) if needed > max_memory => Err(format!(
+            "Resizing a {}x{} image needs about {} bytes, which exceeds the memory limit of {} bytes",
+            src.0, src.1, needed, max_memory
+        )
+        .into()),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(max_pixels: Option<u64>, max_memory: Option<u64>) -> JobOptions {
+        JobOptions {
+            limits: Limits {
+                max_pixels,
+                max_memory,
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn regular_without_limits() {
+        let strategy = plan_resize((100, 100), 3, (50, 50), &JobOptions::default()).unwrap();
+        assert_eq!(strategy, ResizeStrategy::Regular);
+    }
+
+    #[test]
+    fn rejects_too_many_pixels() {
+        let result = plan_resize((1000, 1000), 3, (50, 50), &options(Some(999_999), None));
+        assert!(result
+            .unwrap_err()
+            .to_string()
+            .contains("exceeds the limit of 999999 pixels"));
+    }
+
+    #[test]
+    fn degrades_to_tiled_under_memory_limit() {
+        let src = (4000, 3000);
+        let dst = (400, 300);
+        let regular = estimate_memory(src, 3, dst, ResizeStrategy::Regular);
+        let tiled = estimate_memory(src, 3, dst, ResizeStrategy::Tiled);
+        assert!(tiled < regular);
+
+        let strategy = plan_resize(src, 3, dst, &options(None, Some(tiled))).unwrap();
+        assert_eq!(strategy, ResizeStrategy::Tiled);
+
+        let result = plan_resize(src, 3, dst, &options(None, Some(tiled - 1)));
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn streaming_memory_does_not_grow_with_the_height() {
+        let short = estimate_streaming_memory((4000, 3000), (1000, 750));
+        let tall = estimate_streaming_memory((4000, 300_000), (1000, 75_000));
+        assert_eq!(short, tall);
+        assert!(
+            tall < estimate_memory((4000, 300_000), 4, (1000, 75_000), ResizeStrategy::Tiled) / 100
+        );
+
+        let options = options(None, Some(short));
+        assert!(check_streaming((4000, 300_000), (1000, 75_000), &options).is_ok());
+        assert!(check_streaming((8000, 300_000), (1000, 75_000), &options).is_err());
+    }
+
+    #[test]
+    fn decoder_limits_cap_the_dimensions() {
+        let safety = SafetyLimits {
+            max_width: Some(5000),
+            max_height: Some(800),
+            ..Default::default()
+        };
+        let limits = options(Some(1000), Some(1 << 20))
+            .limits
+            .decoder_limits(&safety);
+        assert_eq!(limits.max_alloc, Some(1 << 20));
+        assert_eq!(limits.max_image_width, Some(1000));
+        assert_eq!(limits.max_image_height, Some(800));
+
+        let unlimited = Limits::default().decoder_limits(&SafetyLimits::default());
+        assert_eq!(unlimited.max_image_width, None);
+        assert_eq!(unlimited.max_image_height, None);
+    }
+}
@@ -0,0 +1,132 @@
+//! A minimal logger for the command-line tool.
+//!
+//! The library reports progress through `tracing` events, and the tool through the `log`
+//! facade. This logger receives both: it is installed as the `log` logger and as the
+//! `tracing` subscriber, which ignores spans. It prints informational messages on stdout,
+//! like the tool's regular output, and everything else on stderr with a level prefix, so
+//! that `--quiet` output can be piped safely.
+
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use std::fmt::{self, Write};
+use tracing::{
+    field::{Field, Visit},
+    span, Event, Subscriber,
+};
+
+/// The logger installed by `init`.
+struct Logger;
+
+impl Logger {
+    /// Prints a message at the given level.
+    fn print(level: Level, message: fmt::Arguments<'_>) {
+        match level {
+            Level::Info => println!("{}", message),
+            Level::Error => eprintln!("error: {}", message),
+            Level::Warn => eprintln!("warning: {}", message),
+            Level::Debug => eprintln!("debug: {}", message),
+            Level::Trace => eprintln!("trace: {}", message),
+        }
+    }
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if Log::enabled(self, record.metadata()) {
+            Logger::print(record.level(), *record.args());
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+/// Converts a `tracing` level to a `log` level.
+fn log_level(level: &tracing::Level) -> Level {
+    match *level {
+        tracing::Level::ERROR => Level::Error,
+        tracing::Level::WARN => Level::Warn,
+        tracing::Level::INFO => Level::Info,
+        tracing::Level::DEBUG => Level::Debug,
+        _ => Level::Trace,
+    }
+}
+
+/// Formats the message of an event, followed by its other fields as `name=value`.
+#[derive(Default)]
+struct EventText(String);
+
+impl Visit for EventText {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if !self.0.is_empty() {
+            self.0.push(' ');
+        }
+        let _ = match field.name() {
+            "message" => write!(self.0, "{:?}", value),
+            name => write!(self.0, "{}={:?}", name, value),
+        };
+    }
+}
+
+impl Subscriber for Logger {
+    fn enabled(&self, metadata: &tracing::Metadata<'_>) -> bool {
+        metadata.is_event() && log_level(metadata.level()) <= log::max_level()
+    }
+
+    fn new_span(&self, _: &span::Attributes<'_>) -> span::Id {
+        span::Id::from_u64(1)
+    }
+
+    fn record(&self, _: &span::Id, _: &span::Record<'_>) {}
+
+    fn record_follows_from(&self, _: &span::Id, _: &span::Id) {}
+
+    fn event(&self, event: &Event<'_>) {
+        let mut text = EventText::default();
+        event.record(&mut text);
+        Logger::print(
+            log_level(event.metadata().level()),
+            format_args!("{}", text.0),
+        );
+    }
+
+    fn enter(&self, _: &span::Id) {}
+
+    fn exit(&self, _: &span::Id) {}
+}
+
+/// Returns the maximum level to log for the `--verbose` count and the `--quiet` flag.
+///
+/// `--quiet` only keeps errors. Otherwise informational messages are shown, `-v` adds debug
+/// messages (such as the timing of each pipeline stage) and `-vv` adds trace messages.
+pub fn level_filter(verbose: u8, quiet: bool) -> LevelFilter {
+    match (quiet, verbose) {
+        (true, _) => LevelFilter::Error,
+        (false, 0) => LevelFilter::Info,
+        (false, 1) => LevelFilter::Debug,
+        (false, _) => LevelFilter::Trace,
+    }
+}
+
+/// Installs the logger with the given maximum level.
+pub fn init(level: LevelFilter) {
+    log::set_max_level(level);
+    // Only fail if a logger is already installed, in which case it is kept
+    let _ = log::set_logger(&Logger);
+    let _ = tracing::subscriber::set_global_default(Logger);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn levels_from_flags() {
+        assert_eq!(level_filter(0, false), LevelFilter::Info);
+        assert_eq!(level_filter(1, false), LevelFilter::Debug);
+        assert_eq!(level_filter(3, false), LevelFilter::Trace);
+        assert_eq!(level_filter(2, true), LevelFilter::Error);
+    }
+}
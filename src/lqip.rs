@@ -0,0 +1,96 @@
+//! Low-quality image placeholders (LQIP).
+//!
+//! An LQIP is a tiny, blurred version of an image, inlined into HTML as a base64 data URI and
+//! stretched over the area of the image while it loads. Unlike a BlurHash (see `blurhash`),
+//! it needs no decoder on the client. Opaque images are encoded as low quality JPEGs, and
+//! images with transparency as PNGs.
+
+use crate::encode::{self, EncodeOptions};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use image::DynamicImage;
+use std::error::Error;
+
+/// The largest side of a placeholder when no size is given.
+pub const DEFAULT_SIZE: u32 = 16;
+/// The largest accepted side of a placeholder. Larger ones are no longer cheap to inline.
+pub const MAX_SIZE: u32 = 64;
+/// The standard deviation of the blur, in pixels of the placeholder. It hides the blocks of
+/// the JPEG encoding and the pixels of the browser's upscaling.
+const BLUR_SIGMA: f32 = 1.0;
+/// The JPEG quality of placeholders, which are blurred anyway.
+const JPEG_QUALITY: u8 = 40;
+
+/// Computes the placeholder of an image, as a data URI.
+///
+/// # Arguments
+///
+/// * `image` - The image.
+/// * `size` - The largest side of the placeholder, in pixels. The aspect ratio of the image
+///   is kept.
+///
+/// # Returns
+///
+/// A `data:image/jpeg;base64,...` or `data:image/png;base64,...` URI.
+///
+/// # Errors
+///
+/// This function will return an error if the image is empty, `size` is 0, or the
+/// placeholder cannot be encoded.
+pub fn data_uri(image: &DynamicImage, size: u32) -> Result<String, Box<dyn Error>> {
+    if size == 0 {
+        return Err("The LQIP size must be at least 1 pixel".into());
+    }
+    if image.width() == 0 || image.height() == 0 {
+        return Err("Cannot compute the LQIP of an empty image".into());
+    }
+
+    let placeholder = image.thumbnail(size, size).blur(BLUR_SIGMA).into_rgba8();
+    let (mime, data) = if placeholder.pixels().any(|pixel| pixel[3] < 255) {
+        let data = encode::encode_png(&placeholder, &EncodeOptions::default())?;
+        ("png", data)
+    } else {
+        let options = EncodeOptions {
+            jpeg_quality: Some(JPEG_QUALITY),
+            ..EncodeOptions::default()
+        };
+        ("jpeg", encode::encode_jpeg(&placeholder, &options)?)
+    };
+    Ok(format!(
+        "data:image/{};base64,{}",
+        mime,
+        STANDARD.encode(data)
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgba, RgbaImage};
+
+    fn decode(uri: &str, prefix: &str) -> DynamicImage {
+        let data = uri
+            .strip_prefix(prefix)
+            .expect("Unexpected data URI prefix");
+        image::load_from_memory(&STANDARD.decode(data).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn opaque_images_become_jpegs() {
+        let image = RgbaImage::from_fn(64, 32, |x, _| Rgba([(x * 4) as u8, 80, 160, 255]));
+        let uri = data_uri(&DynamicImage::ImageRgba8(image), DEFAULT_SIZE).unwrap();
+        let placeholder = decode(&uri, "data:image/jpeg;base64,");
+        assert_eq!((placeholder.width(), placeholder.height()), (16, 8));
+        assert!(uri.len() < 1024, "{} bytes", uri.len());
+    }
+
+    #[test]
+    fn transparent_images_become_pngs() {
+        let image = RgbaImage::from_fn(30, 60, |_, y| Rgba([200, 0, 0, (y * 4) as u8]));
+        let image = DynamicImage::ImageRgba8(image);
+        let placeholder = decode(&data_uri(&image, 10).unwrap(), "data:image/png;base64,");
+        assert_eq!((placeholder.width(), placeholder.height()), (5, 10));
+        assert!(placeholder.to_rgba8().get_pixel(2, 0)[3] < 128);
+
+        assert!(data_uri(&image, 0).is_err());
+    }
+}
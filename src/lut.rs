@@ -0,0 +1,210 @@
+//! 3D color lookup tables.
+//!
+//! This module reads 3D LUTs in the Adobe/Resolve `.cube` format and applies them to
+//! images. A 3D LUT maps every input color to an output color through a cube of `size`
+//! samples along each axis; colors between the samples are interpolated trilinearly.
+//!
+//! The parser understands the `TITLE`, `LUT_3D_SIZE`, `DOMAIN_MIN` and `DOMAIN_MAX`
+//! keywords and `#` comments. 1D LUTs (`LUT_1D_SIZE`) are not supported.
+
+use image::RgbaImage;
+use std::{error::Error, path::Path};
+
+/// The largest supported number of samples along each axis of the cube.
+pub const MAX_LUT_SIZE: usize = 256;
+
+/// A 3D color lookup table.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Lut3d {
+    /// The title of the LUT, if the file has one.
+    pub title: Option<String>,
+    /// The number of samples along each axis of the cube.
+    pub size: usize,
+    /// The input value mapped to the first sample of each axis.
+    pub domain_min: [f32; 3],
+    /// The input value mapped to the last sample of each axis.
+    pub domain_max: [f32; 3],
+    /// The output colors, with red changing fastest, then green, then blue.
+    pub table: Vec<[f32; 3]>,
+}
+
+impl Lut3d {
+    /// Reads a `.cube` file.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the file cannot be read or is not a valid 3D
+    /// `.cube` LUT.
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read the LUT {:?}: {}", path, e))?;
+        Self::parse(&text).map_err(|e| format!("Invalid LUT {:?}: {}", path, e).into())
+    }
+
+    /// Parses the contents of a `.cube` file.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the size is missing or out of range, if a
+    /// line cannot be parsed, or if the number of samples does not match the size.
+    pub fn parse(text: &str) -> Result<Self, Box<dyn Error>> {
+        let mut title = None;
+        let mut size = None;
+        let mut domain_min = [0.0; 3];
+        let mut domain_max = [1.0; 3];
+        let mut table = Vec::new();
+
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let invalid = || format!("Invalid line {}: '{}'", number + 1, line);
+            let (keyword, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+            match keyword {
+                "TITLE" => title = Some(rest.trim().trim_matches('"').to_string()),
+                "LUT_3D_SIZE" => {
+                    let value: usize = rest.trim().parse().map_err(|_| invalid())?;
+                    if !(2..=MAX_LUT_SIZE).contains(&value) {
+                        return Err(format!(
+                            "The LUT size must be between 2 and {}, got {}",
+                            MAX_LUT_SIZE, value
+                        )
+                        .into());
+                    }
+                    size = Some(value);
+                }
+                "DOMAIN_MIN" => domain_min = parse_triplet(rest).ok_or_else(invalid)?,
+                "DOMAIN_MAX" => domain_max = parse_triplet(rest).ok_or_else(invalid)?,
+                "LUT_1D_SIZE" => return Err("1D LUTs are not supported".into()),
+                _ => table.push(parse_triplet(line).ok_or_else(invalid)?),
+            }
+        }
+
+        let size = size.ok_or("Missing LUT_3D_SIZE")?;
+        if table.len() != size * size * size {
+            return Err(format!(
+                "Expected {} samples for a size of {}, found {}",
+                size * size * size,
+                size,
+                table.len()
+            )
+            .into());
+        }
+        if (0..3).any(|c| domain_max[c] <= domain_min[c]) {
+            return Err("DOMAIN_MAX must be greater than DOMAIN_MIN".into());
+        }
+        Ok(Lut3d {
+            title,
+            size,
+            domain_min,
+            domain_max,
+            table,
+        })
+    }
+
+    /// Maps a color, with channels from 0.0 to 1.0, through the LUT.
+    pub fn map(&self, color: [f32; 3]) -> [f32; 3] {
+        let last = (self.size - 1) as f32;
+        // Position of the color in the cube, split into a sample index and a fraction
+        let mut index = [0usize; 3];
+        let mut fraction = [0f32; 3];
+        for c in 0..3 {
+            let scaled = ((color[c] - self.domain_min[c])
+                / (self.domain_max[c] - self.domain_min[c])
+                * last)
+                .clamp(0.0, last);
+            index[c] = (scaled.floor() as usize).min(self.size - 2);
+            fraction[c] = scaled - index[c] as f32;
+        }
+
+        let sample = |r: usize, g: usize, b: usize| {
+            self.table
+                [(index[2] + b) * self.size * self.size + (index[1] + g) * self.size + index[0] + r]
+        };
+        let lerp = |a: [f32; 3], b: [f32; 3], t: f32| {
+            [
+                a[0] + (b[0] - a[0]) * t,
+                a[1] + (b[1] - a[1]) * t,
+                a[2] + (b[2] - a[2]) * t,
+            ]
+        };
+        let [fr, fg, fb] = fraction;
+        let g0 = lerp(
+            lerp(sample(0, 0, 0), sample(1, 0, 0), fr),
+            lerp(sample(0, 1, 0), sample(1, 1, 0), fr),
+            fg,
+        );
+        let g1 = lerp(
+            lerp(sample(0, 0, 1), sample(1, 0, 1), fr),
+            lerp(sample(0, 1, 1), sample(1, 1, 1), fr),
+            fg,
+        );
+        lerp(g0, g1, fb)
+    }
+
+    /// Maps the color channels of an image through the LUT, leaving the alpha channel
+    /// unchanged.
+    pub fn apply(&self, image: &mut RgbaImage) {
+        for pixel in image.pixels_mut() {
+            let [r, g, b, _] = pixel.0;
+            let mapped = self.map([r as f32 / 255.0, g as f32 / 255.0, b as f32 / 255.0]);
+            for (value, mapped) in pixel.0.iter_mut().zip(mapped) {
+                *value = (mapped * 255.0).round().clamp(0.0, 255.0) as u8;
+            }
+        }
+    }
+}
+
+/// Parses three whitespace-separated numbers.
+fn parse_triplet(s: &str) -> Option<[f32; 3]> {
+    let mut values = s.split_whitespace().map(|value| value.parse::<f32>().ok());
+    let triplet = [values.next()??, values.next()??, values.next()??];
+    values.next().is_none().then_some(triplet)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    /// A LUT of size 2 mapping each color to `f` of its corners.
+    fn cube(f: impl Fn([f32; 3]) -> [f32; 3]) -> String {
+        let mut text = String::from("# Test LUT\nTITLE \"test\"\nLUT_3D_SIZE 2\n\n");
+        for b in 0..2 {
+            for g in 0..2 {
+                for r in 0..2 {
+                    let [r, g, b] = f([r as f32, g as f32, b as f32]);
+                    text.push_str(&format!("{} {} {}\n", r, g, b));
+                }
+            }
+        }
+        text
+    }
+
+    #[test]
+    fn parses_and_interpolates() {
+        let identity = Lut3d::parse(&cube(|color| color)).unwrap();
+        assert_eq!(identity.title.as_deref(), Some("test"));
+        assert_eq!(identity.size, 2);
+        let mapped = identity.map([0.25, 0.5, 0.75]);
+        assert!(mapped
+            .iter()
+            .zip([0.25, 0.5, 0.75])
+            .all(|(a, b)| (a - b).abs() < 1e-6));
+
+        // Swaps red and blue
+        let swap = Lut3d::parse(&cube(|[r, g, b]| [b, g, r])).unwrap();
+        let mut image = RgbaImage::from_pixel(1, 1, Rgba([255, 128, 0, 77]));
+        swap.apply(&mut image);
+        assert_eq!(image.get_pixel(0, 0), &Rgba([0, 128, 255, 77]));
+    }
+
+    #[test]
+    fn rejects_invalid_files() {
+        assert!(Lut3d::parse("0 0 0\n1 1 1\n").is_err());
+        assert!(Lut3d::parse("LUT_3D_SIZE 2\n0 0 0\n").is_err());
+        assert!(Lut3d::parse("LUT_1D_SIZE 2\n0 0 0\n1 1 1\n").is_err());
+        assert!(Lut3d::parse("LUT_3D_SIZE 2\n0 0 zero\n").is_err());
+    }
+}
@@ -6,51 +6,1064 @@
 //! and `clap` for parsing command-line arguments.
 
 mod cli;
+mod logger;
+mod prompt;
+#[cfg(feature = "tui")]
+mod tui;
 
-use clap::error::ErrorKind;
+use clap::{error::ErrorKind, ArgMatches};
+use image::{DynamicImage, ImageFormat};
 use image_resizer_rust::{
-    check_if_path_exists, determine_save_format_and_path, estimate_size_and_encode,
-    image_format_to_string, resize_image, save_image,
+    appicon::{self, Platform},
+    archive::{extract_images, ArchiveEntry, ArchiveFormat, ExtractedArchive},
+    background::{segmenter, RemoveBackgroundStage},
+    batch::{run_batch, run_batch_stream, BatchFailure, BatchOutcome, ErrorPolicy},
+    cache::Cache,
+    clipboard::{self, ClipboardSink},
+    colors::dominant_colors,
+    contact::{self, ContactEntry, ContactSheetOptions},
+    context::ResizeContext,
+    crop::CoverCropStage,
+    encode::EncodeOptions,
+    error::ResizeError,
+    estimate_size_and_encode,
+    filters::{self, ApplyAlphaStage, Channel, DenoiseStage, ExtractAlphaStage, Tone},
+    generate::{generate, GenerateOptions},
+    hash::{find_duplicates, HashAlgorithm, ImageHash},
+    inplace::{resize_in_place, Backup},
+    jobfile,
+    limits::Limits,
+    load_and_resize, lqip,
+    lut::Lut3d,
+    manifest::{self, Asset, Manifest, MANIFEST_NAME},
+    metadata::{ExifFilter, MetadataEdits, MetadataLevel, MetadataStage},
+    metrics::{compare_images, QualityMetrics},
+    montage::{self, CompareLayout, Direction, JoinOptions, MontageOptions},
+    options::{JobOptions, ResizeOptions},
+    originals::{remove_original, Removal},
+    overwrite::OverwritePolicy,
+    pathlist::PathList,
+    picture::{self, PictureOptions},
+    pipeline::{ImageStage, Pipeline, DECODE, ENCODE, RESIZE},
+    policy::InputPolicy,
+    preview::{self, Protocol, PREVIEW_COLUMNS, PREVIEW_ROWS},
+    pyramid::{self, Layout, PyramidOptions},
+    raw::PixelLayout,
+    recipe::Recipe,
+    resize_file_into, resize_file_with, resize_image,
+    retry::{RetryPolicy, Retrying},
+    safety::SafetyLimits,
+    save_image,
+    schedule::{RateLimit, Scheduler, SchedulerConfig},
+    seam::SeamCarveStage,
+    select::{self, Criteria, Excludes},
+    sequence::{self, FramePattern, RawFormat, SequenceOptions},
+    sidecar::{Sidecar, SidecarCropStage},
+    sink::{OutputSink, ZipSink},
+    split::{self, Grid, SplitOptions},
+    storage::{FileSystem, SharedStorage, Storage},
+    texture::{self, Container, TextureOptions},
+    tonemap::ToneMapStage,
+    trim,
+    units::PrintSize,
+    upscale::{upscaler, Engine, UpscaleStage},
+    xattrs::XattrPolicy,
+    ImageInfo,
 };
-use std::path::PathBuf;
+use log::{info, warn};
+use std::{
+    collections::HashMap,
+    fmt,
+    io::{self, IsTerminal},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+/// Exit code returned when every input was processed successfully.
+const EXIT_SUCCESS: i32 = 0;
+/// Exit code returned when some inputs were processed but at least one failed or was skipped.
+const EXIT_PARTIAL_FAILURE: i32 = 1;
+/// Exit code returned for invalid command-line arguments (the code `clap` uses for usage errors).
+const EXIT_INVALID_ARGS: i32 = 2;
+/// Exit code returned when no input could be processed.
+const EXIT_TOTAL_FAILURE: i32 = 3;
+/// Exit code returned when the run was interrupted by Ctrl-C or a termination signal (128 +
+/// `SIGINT`, as reported by shells).
+const EXIT_INTERRUPTED: i32 = 130;
 
 /// The main entry point of the image resizer program.
 ///
-/// This function calls the `run` function and handles any errors that occur.
-/// If an error is encountered, it uses `clap`'s error handling mechanism to
-/// display the error message and exit the program with a non-zero status code.
+/// This function calls the `run` function and maps its result to a process exit code.
+/// If an argument error (a `UsageError`) is encountered, it uses `clap`'s error handling
+/// mechanism to display the error message and exits with `EXIT_INVALID_ARGS`. Other errors
+/// are printed on stderr and exit with `EXIT_TOTAL_FAILURE`. Otherwise the exit code
+/// reflects the outcome of the batch: `EXIT_SUCCESS`, `EXIT_PARTIAL_FAILURE`,
+/// `EXIT_TOTAL_FAILURE` or `EXIT_INTERRUPTED`.
 fn main() {
-    if let Err(e) = run() {
-        let mut cmd = cli::cli();
-        cmd.error(clap::error::ErrorKind::InvalidValue, e).exit();
+    let code = match run() {
+        Ok(outcome) => exit_code(outcome),
+        Err(e) if e.is::<UsageError>() => {
+            let mut cmd = cli::cli();
+            let _ = cmd.error(ErrorKind::InvalidValue, e).print();
+            EXIT_INVALID_ARGS
+        }
+        Err(e) => {
+            eprintln!("error: {}", e);
+            EXIT_TOTAL_FAILURE
+        }
+    };
+    std::process::exit(code);
+}
+
+/// An invalid value or combination of command-line arguments, found after `clap` parsed
+/// them.
+#[derive(Debug)]
+struct UsageError(String);
+
+impl fmt::Display for UsageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for UsageError {}
+
+/// Wraps the error of an invalid argument in a `UsageError`, so that `main` reports it with
+/// the usage of the command.
+fn usage(error: impl fmt::Display) -> Box<dyn std::error::Error> {
+    Box::new(UsageError(error.to_string()))
+}
+
+/// Maps the outcome of a batch run to the process exit code.
+fn exit_code(outcome: BatchOutcome) -> i32 {
+    match outcome {
+        BatchOutcome::Success => EXIT_SUCCESS,
+        BatchOutcome::PartialFailure => EXIT_PARTIAL_FAILURE,
+        BatchOutcome::TotalFailure => EXIT_TOTAL_FAILURE,
+        BatchOutcome::Cancelled => EXIT_INTERRUPTED,
     }
 }
 
 /// The core logic of the image resizer program.
 ///
-/// This function orchestrates the image resizing process by:
-/// 1. Parsing command-line arguments
-/// 2. Validating input parameters
-/// 3. Determining the output path
-/// 4. Loading and resizing the input image
-/// 5. Determining the save format and final output path
-/// 6. Checking if the output path already exists
-/// 7. Saving the resized image
-/// 8. Printing information about the saved image
+/// This function parses the command-line arguments and dispatches to the `compare`,
+/// `interactive`, `appicon`, `montage`, `contact-sheet`, `join`, `split`, `pyramid`,
+/// `generate`, `daemon`, `grpc`, `sequence` and `texture` subcommands or to the resizing
+/// logic in `run_resize`.
+///
+/// # Errors
+///
+/// Returns a `UsageError` if the arguments are invalid, or another error if a subcommand
+/// or the resize fails before processing its inputs (see `run_resize`).
+fn run() -> Result<BatchOutcome, Box<dyn std::error::Error>> {
+    let matches = cli::cli().get_matches();
+    logger::init(logger::level_filter(
+        matches.get_count("verbose"),
+        matches.get_flag("quiet"),
+    ));
+
+    match matches.subcommand() {
+        Some(("compare", sub_matches)) => Ok(run_compare(sub_matches)),
+        Some(("interactive", sub_matches)) => run_interactive(sub_matches),
+        Some(("appicon", sub_matches)) => Ok(run_appicon(sub_matches)),
+        Some(("montage", sub_matches)) => run_montage(sub_matches),
+        Some(("contact-sheet", sub_matches)) => run_contact_sheet(sub_matches),
+        Some(("join", sub_matches)) => run_join(sub_matches),
+        Some(("split", sub_matches)) => Ok(run_split(sub_matches)),
+        Some(("pyramid", sub_matches)) => Ok(run_pyramid(sub_matches)),
+        Some(("generate", sub_matches)) => Ok(run_generate(sub_matches)),
+        Some(("daemon", sub_matches)) => Ok(run_daemon(sub_matches)),
+        Some(("grpc", sub_matches)) => Ok(run_grpc(sub_matches)),
+        Some(("sequence", sub_matches)) => Ok(run_sequence(sub_matches)),
+        Some(("texture", sub_matches)) => Ok(run_texture(sub_matches)),
+        _ => run_resize(&matches),
+    }
+}
+
+/// Runs the `compare` subcommand and prints the quality metrics.
+///
+/// Errors while decoding or comparing the images are printed on stderr and reported
+/// as a `BatchOutcome::TotalFailure`.
+fn run_compare(matches: &ArgMatches) -> BatchOutcome {
+    let original = matches.get_one::<PathBuf>("original").unwrap();
+    let other = matches.get_one::<PathBuf>("other").unwrap();
+
+    match compare_files(original, other) {
+        Ok(metrics) => {
+            println!("PSNR: {:.2} dB", metrics.psnr);
+            println!("SSIM: {:.4}", metrics.ssim);
+            BatchOutcome::Success
+        }
+        Err(e) => {
+            eprintln!("error: {}", e);
+            BatchOutcome::TotalFailure
+        }
+    }
+}
+
+/// Runs the `interactive` subcommand, then resizes the images picked in the interface.
+///
+/// Outputs are saved next to their inputs, as without `--output`.
+///
+/// # Errors
+///
+/// Returns an error if the interface cannot be shown (see `tui::run`).
+#[cfg(feature = "tui")]
+fn run_interactive(matches: &ArgMatches) -> Result<BatchOutcome, Box<dyn std::error::Error>> {
+    let paths: Vec<PathBuf> = match matches.get_many::<PathBuf>("paths") {
+        Some(paths) => paths.cloned().collect(),
+        None => vec![PathBuf::from(".")],
+    };
+    let Some(job) = tui::run(&paths)? else {
+        return Ok(BatchOutcome::Success);
+    };
+
+    let job_options = JobOptions {
+        encode: EncodeOptions {
+            jpeg_quality: Some(job.quality),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let report = run_batch(
+        &job.inputs,
+        ErrorPolicy::KeepGoing,
+        &job_options.cancel,
+        |input| {
+            process_image(
+                input,
+                &cli::determine_output_path(input, None, cli::OutputBase::Input, false)?,
+                job.width.as_ref(),
+                job.height.as_ref(),
+                job.format.as_ref(),
+                &job_options,
+                &Pipeline::standard(),
+            )
+        },
+    );
+
+    for failure in &report.errors.failures {
+        eprintln!("error: {:?}: {}", failure.input, failure.error);
+    }
+    if report.total > 1 {
+        info!(
+            "\nProcessed {} of {} images ({} failed, {} skipped)",
+            report.succeeded.len(),
+            report.total,
+            report.errors.len(),
+            report.skipped()
+        );
+    }
+
+    Ok(report.outcome())
+}
+
+/// Reports that the `interactive` subcommand is not included in this build.
+#[cfg(not(feature = "tui"))]
+fn run_interactive(_matches: &ArgMatches) -> Result<BatchOutcome, Box<dyn std::error::Error>> {
+    Err("The interactive mode is not included in this build (enable the `tui` feature)".into())
+}
+
+/// Runs the `appicon` subcommand and prints the number of icons written.
+///
+/// Errors while decoding the source or writing the icons are printed on stderr and
+/// reported as a `BatchOutcome::TotalFailure`.
+fn run_appicon(matches: &ArgMatches) -> BatchOutcome {
+    let source = matches.get_one::<PathBuf>("source").unwrap();
+    let dir = matches.get_one::<PathBuf>("output").unwrap();
+    let platforms = match matches.get_one::<String>("platform").unwrap().as_str() {
+        "ios" => vec![Platform::Ios],
+        "android" => vec![Platform::Android],
+        _ => vec![Platform::Ios, Platform::Android],
+    };
+
+    let result = decode_input(source)
+        .and_then(|image| appicon::generate(&image, dir, &platforms, &EncodeOptions::default()));
+    match result {
+        Ok(written) => {
+            for platform in &platforms {
+                info!(
+                    "Wrote {} icons to {:?}",
+                    platform,
+                    dir.join(platform.dir_name())
+                );
+            }
+            info!("{} files written", written.len());
+            BatchOutcome::Success
+        }
+        Err(e) => {
+            eprintln!("error: {}", e);
+            BatchOutcome::TotalFailure
+        }
+    }
+}
+
+/// Runs the `montage` subcommand, tiling the inputs into a sprite sheet.
+///
+/// Inputs that cannot be loaded are printed on stderr and left out of the sheet, which makes
+/// the run a partial failure. Errors while composing or saving the sheet are printed on
+/// stderr and reported as a `BatchOutcome::TotalFailure`.
+///
+/// # Errors
+///
+/// Returns an error if the sheet is not a PNG or JPEG file.
+fn run_montage(matches: &ArgMatches) -> Result<BatchOutcome, Box<dyn std::error::Error>> {
+    let inputs: Vec<PathBuf> = matches
+        .get_many::<PathBuf>("inputs")
+        .unwrap()
+        .cloned()
+        .collect();
+    let output = matches.get_one::<PathBuf>("output").unwrap();
+    let format = sheet_format(output)?;
+    let width = matches.get_one::<u32>("width");
+    let height = matches.get_one::<u32>("height");
+    let options = MontageOptions {
+        columns: matches.get_one::<u32>("columns").copied(),
+        spacing: *matches.get_one::<u32>("spacing").unwrap(),
+        background: *matches.get_one("background").unwrap(),
+    };
+
+    let job_options = JobOptions::default();
+    let report = run_batch(
+        &inputs,
+        ErrorPolicy::KeepGoing,
+        &job_options.cancel,
+        |input| {
+            let tile = if width.is_some() || height.is_some() {
+                load_and_resize(input, width, height, &job_options)?
+            } else {
+                decode_input(input)?.into_rgba8()
+            };
+            let name = input
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            Ok((name, tile))
+        },
+    );
+    for failure in &report.errors.failures {
+        eprintln!("error: {:?}: {}", failure.input, failure.error);
+    }
+    let outcome = report.outcome();
+    if report.succeeded.is_empty() {
+        return Ok(outcome);
+    }
+
+    // Inputs sharing a file stem get a numbered suffix, so that the map keys stay unique
+    let mut seen: HashMap<String, usize> = HashMap::new();
+    let tiles: Vec<(String, image::RgbaImage)> = report
+        .succeeded
+        .into_iter()
+        .map(|(name, tile)| {
+            let count = seen.entry(name.clone()).or_insert(0);
+            *count += 1;
+            match *count {
+                1 => (name, tile),
+                n => (format!("{}-{}", name, n), tile),
+            }
+        })
+        .collect();
+
+    match write_montage(&tiles, &options, output, format, matches) {
+        Ok(()) => Ok(outcome),
+        Err(e) => {
+            eprintln!("error: {}", e);
+            Ok(BatchOutcome::TotalFailure)
+        }
+    }
+}
+
+/// Composes a sprite sheet, saves it and writes the map requested with `--map` next to it.
+fn write_montage(
+    tiles: &[(String, image::RgbaImage)],
+    options: &MontageOptions,
+    output: &Path,
+    format: ImageFormat,
+    matches: &ArgMatches,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let sheet = montage::compose(tiles, options)?;
+    save_sheet(&sheet.image, tiles.len(), output, format)?;
+
+    if let Some(map) = matches.get_one::<String>("map") {
+        let contents = match map.as_str() {
+            "css" => {
+                let sheet_url = output.file_name().unwrap_or_default().to_string_lossy();
+                sheet.css(&sheet_url)
+            }
+            _ => sheet.json(),
+        };
+        let path = output.with_extension(map);
+        std::fs::write(&path, contents)
+            .map_err(|e| format!("Failed to write {:?}: {}", path, e))?;
+        info!("Wrote the sprite map to {:?}", path);
+    }
+    Ok(())
+}
+
+/// Runs the `contact-sheet` subcommand, laying out labelled thumbnails of the inputs.
+///
+/// Inputs that cannot be loaded are printed on stderr and left out of the sheet, which makes
+/// the run a partial failure. Errors while composing or saving the sheet are printed on
+/// stderr and reported as a `BatchOutcome::TotalFailure`.
+///
+/// # Errors
+///
+/// Returns an error if the sheet is not a PNG or JPEG file.
+fn run_contact_sheet(matches: &ArgMatches) -> Result<BatchOutcome, Box<dyn std::error::Error>> {
+    let inputs: Vec<PathBuf> = matches
+        .get_many::<PathBuf>("inputs")
+        .unwrap()
+        .cloned()
+        .collect();
+    let output = matches.get_one::<PathBuf>("output").unwrap();
+    let format = sheet_format(output)?;
+    let options = ContactSheetOptions {
+        columns: matches.get_one::<u32>("columns").copied(),
+        thumbnail_size: *matches.get_one::<u32>("size").unwrap(),
+        spacing: *matches.get_one::<u32>("spacing").unwrap(),
+        background: *matches.get_one("background").unwrap(),
+    };
+    let show_dimensions = matches.get_flag("dimensions");
+    let show_date = matches.get_flag("date");
+
+    let job_options = JobOptions::default();
+    let report = run_batch(
+        &inputs,
+        ErrorPolicy::KeepGoing,
+        &job_options.cancel,
+        |input| {
+            let image = decode_input(input)?;
+            let mut label = vec![input
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default()];
+            if show_dimensions {
+                label.push(format!("{}x{}", image.width(), image.height()));
+            }
+            if show_date {
+                label.push(contact::format_date(std::fs::metadata(input)?.modified()?));
+            }
+            Ok(ContactEntry {
+                thumbnail: contact::thumbnail(image, options.thumbnail_size)?,
+                label,
+            })
+        },
+    );
+    for failure in &report.errors.failures {
+        eprintln!("error: {:?}: {}", failure.input, failure.error);
+    }
+    if report.succeeded.is_empty() {
+        return Ok(report.outcome());
+    }
+
+    let result = contact::compose(&report.succeeded, &options)
+        .and_then(|sheet| save_sheet(&sheet, report.succeeded.len(), output, format));
+    match result {
+        Ok(()) => Ok(report.outcome()),
+        Err(e) => {
+            eprintln!("error: {}", e);
+            Ok(BatchOutcome::TotalFailure)
+        }
+    }
+}
+
+/// Runs the `join` subcommand, concatenating the inputs in a row or a column.
+///
+/// Unless `--keep-size` is given, every input is resized to the same height (in a row) or
+/// width (in a column): `--edge`, or the smallest one among the inputs. Inputs that cannot
+/// be loaded are printed on stderr and left out, which makes the run a partial failure.
+/// Errors while joining or saving the image are printed on stderr and reported as a
+/// `BatchOutcome::TotalFailure`.
+///
+/// # Errors
+///
+/// Returns an error if the output is not a PNG or JPEG file.
+fn run_join(matches: &ArgMatches) -> Result<BatchOutcome, Box<dyn std::error::Error>> {
+    let inputs: Vec<PathBuf> = matches
+        .get_many::<PathBuf>("inputs")
+        .unwrap()
+        .cloned()
+        .collect();
+    let output = matches.get_one::<PathBuf>("output").unwrap();
+    let format = sheet_format(output)?;
+    let options = JoinOptions {
+        direction: matches
+            .get_one::<String>("direction")
+            .unwrap()
+            .parse()
+            .map_err(usage)?,
+        align: matches
+            .get_one::<String>("align")
+            .unwrap()
+            .parse()
+            .map_err(usage)?,
+        gap: *matches.get_one::<u32>("gap").unwrap(),
+        background: *matches.get_one("background").unwrap(),
+    };
+
+    // Only the headers are read to find the smallest edge; unreadable inputs fail below
+    let edge = if matches.get_flag("keep-size") {
+        None
+    } else {
+        matches.get_one::<u32>("edge").copied().or_else(|| {
+            inputs
+                .iter()
+                .filter_map(|input| image::image_dimensions(input).ok())
+                .map(|(width, height)| match options.direction {
+                    Direction::Horizontal => height,
+                    Direction::Vertical => width,
+                })
+                .min()
+        })
+    };
+    let (width, height) = match options.direction {
+        Direction::Horizontal => (None, edge),
+        Direction::Vertical => (edge, None),
+    };
+
+    let job_options = JobOptions::default();
+    let report = run_batch(
+        &inputs,
+        ErrorPolicy::KeepGoing,
+        &job_options.cancel,
+        |input| match edge {
+            Some(_) => load_and_resize(input, width.as_ref(), height.as_ref(), &job_options),
+            None => Ok(decode_input(input)?.into_rgba8()),
+        },
+    );
+    for failure in &report.errors.failures {
+        eprintln!("error: {:?}: {}", failure.input, failure.error);
+    }
+    if report.succeeded.is_empty() {
+        return Ok(report.outcome());
+    }
+
+    let result = montage::join(&report.succeeded, &options)
+        .and_then(|joined| save_sheet(&joined, report.succeeded.len(), output, format));
+    match result {
+        Ok(()) => Ok(report.outcome()),
+        Err(e) => {
+            eprintln!("error: {}", e);
+            Ok(BatchOutcome::TotalFailure)
+        }
+    }
+}
+
+/// Runs the `split` subcommand, saving the tiles as `<name>_<row>_<column>` in the output
+/// folder.
+///
+/// Errors while decoding or splitting the input are printed on stderr and reported as a
+/// `BatchOutcome::TotalFailure`. Tiles that cannot be saved are printed on stderr, which
+/// makes the run a partial failure.
+fn run_split(matches: &ArgMatches) -> BatchOutcome {
+    let input = matches.get_one::<PathBuf>("input").unwrap();
+    let stem = input
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let dir = match matches.get_one::<PathBuf>("output") {
+        Some(dir) => dir.clone(),
+        None => input.with_file_name(format!("{}_tiles", stem)),
+    };
+    let format = match matches.get_one::<String>("format").map(String::as_str) {
+        Some("jpeg") => ImageFormat::Jpeg,
+        Some(_) => ImageFormat::Png,
+        None => match ImageFormat::from_path(input) {
+            Ok(ImageFormat::Jpeg) => ImageFormat::Jpeg,
+            _ => ImageFormat::Png,
+        },
+    };
+    let grid = match (
+        matches.get_one::<(u32, u32)>("grid"),
+        matches.get_one::<(u32, u32)>("tile-size"),
+    ) {
+        (Some(&(columns, rows)), _) => Grid::Count { columns, rows },
+        (None, Some(&(width, height))) => Grid::Size { width, height },
+        (None, None) => unreachable!("clap requires --grid or --tile-size"),
+    };
+    let options = SplitOptions {
+        grid,
+        overlap: *matches.get_one::<u32>("overlap").unwrap(),
+        edges: matches.get_one::<String>("edges").unwrap().parse().unwrap(),
+        background: *matches.get_one("background").unwrap(),
+    };
+
+    let tiles = decode_input(input)
+        .and_then(|image| split::split(&image.into_rgba8(), &options))
+        .and_then(|tiles| {
+            std::fs::create_dir_all(&dir)?;
+            Ok(tiles)
+        });
+    let tiles = match tiles {
+        Ok(tiles) => tiles,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            return BatchOutcome::TotalFailure;
+        }
+    };
+
+    // Pad the indices, so that the tiles sort in order
+    let digits = |max: Option<u32>| max.unwrap_or(0).to_string().len();
+    let row_digits = digits(tiles.iter().map(|tile| tile.row).max());
+    let column_digits = digits(tiles.iter().map(|tile| tile.column).max());
+    let mut saved = 0;
+    for tile in &tiles {
+        let path = dir.join(format!(
+            "{}_{:0row_digits$}_{:0column_digits$}.{}",
+            stem,
+            tile.row,
+            tile.column,
+            format.extensions_str()[0]
+        ));
+        let (width, height) = tile.image.dimensions();
+        let result = estimate_size_and_encode(&tile.image, format, &EncodeOptions::default())
+            .and_then(|(_, buffer)| save_image(buffer, width, height, &path, format));
+        match result {
+            Ok(_) => saved += 1,
+            Err(e) => eprintln!("error: {:?}: {}", path, e),
+        }
+    }
+    info!("Wrote {} of {} tiles to {:?}", saved, tiles.len(), dir);
+
+    if saved == tiles.len() {
+        BatchOutcome::Success
+    } else if saved == 0 {
+        BatchOutcome::TotalFailure
+    } else {
+        BatchOutcome::PartialFailure
+    }
+}
+
+/// Runs the `pyramid` subcommand and prints the number of levels and tiles written.
+///
+/// Errors while decoding the input or writing the pyramid are printed on stderr and
+/// reported as a `BatchOutcome::TotalFailure`.
+fn run_pyramid(matches: &ArgMatches) -> BatchOutcome {
+    let input = matches.get_one::<PathBuf>("input").unwrap();
+    let stem = input
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let dir = match matches.get_one::<PathBuf>("output") {
+        Some(dir) => dir.clone(),
+        None => input.with_file_name(format!("{}_pyramid", stem)),
+    };
+    let layout: Layout = matches
+        .get_one::<String>("layout")
+        .unwrap()
+        .parse()
+        .unwrap();
+    let defaults = PyramidOptions::new(layout);
+    let options = PyramidOptions {
+        tile_size: matches
+            .get_one::<u32>("tile-size")
+            .copied()
+            .unwrap_or(defaults.tile_size),
+        overlap: *matches.get_one::<u32>("overlap").unwrap(),
+        format: match matches.get_one::<String>("format").unwrap().as_str() {
+            "png" => ImageFormat::Png,
+            _ => ImageFormat::Jpeg,
+        },
+        encode: EncodeOptions {
+            jpeg_quality: matches.get_one::<u8>("quality").copied(),
+            ..Default::default()
+        },
+        ..defaults
+    };
+
+    let result = decode_input(input).and_then(|image| {
+        pyramid::generate(image, &dir, &stem, &options, &ResizeOptions::default())
+    });
+    match result {
+        Ok(pyramid) => {
+            info!(
+                "Wrote {} tiles in {} levels to {:?}",
+                pyramid.tiles, pyramid.levels, dir
+            );
+            BatchOutcome::Success
+        }
+        Err(e) => {
+            eprintln!("error: {}", e);
+            BatchOutcome::TotalFailure
+        }
+    }
+}
+
+/// Runs the `generate` subcommand, drawing a test image.
+///
+/// Errors are printed on stderr and reported as a `BatchOutcome::TotalFailure`.
+fn run_generate(matches: &ArgMatches) -> BatchOutcome {
+    let output = matches.get_one::<PathBuf>("output").unwrap();
+    let options = GenerateOptions {
+        pattern: matches
+            .get_one::<String>("pattern")
+            .unwrap()
+            .parse()
+            .unwrap(),
+        width: *matches.get_one::<u32>("width").unwrap(),
+        height: *matches.get_one::<u32>("height").unwrap(),
+        colors: [
+            *matches.get_one("color").unwrap(),
+            *matches.get_one("color2").unwrap(),
+        ],
+        cell_size: *matches.get_one::<u32>("cell-size").unwrap(),
+        seed: *matches.get_one::<u64>("seed").unwrap(),
+    };
+    let encode = EncodeOptions {
+        jpeg_quality: matches.get_one::<u8>("quality").copied(),
+        ..Default::default()
+    };
+
+    let result = (|| -> Result<ImageInfo, Box<dyn std::error::Error>> {
+        let format = match ImageFormat::from_path(output) {
+            Ok(format @ (ImageFormat::Png | ImageFormat::Jpeg)) => format,
+            _ => {
+                return Err(
+                    format!("The image must be a .png or .jpg file, got {:?}", output).into(),
+                )
+            }
+        };
+        let image = generate(&options)?;
+        let (_, buffer) = estimate_size_and_encode(&image, format, &encode)?;
+        save_image(buffer, options.width, options.height, output, format)
+    })();
+    match result {
+        Ok(info) => {
+            info!(
+                "Generated a {}x{} {} image at {:?} ({} bytes)",
+                info.width, info.height, options.pattern, info.path, info.file_size
+            );
+            BatchOutcome::Success
+        }
+        Err(e) => {
+            eprintln!("error: {}", e);
+            BatchOutcome::TotalFailure
+        }
+    }
+}
+
+/// Runs the `daemon` subcommand, answering resize requests on a Unix domain socket until
+/// interrupted by Ctrl-C or a termination signal.
+///
+/// Errors are printed on stderr and reported as a `BatchOutcome::TotalFailure`.
+fn run_daemon(matches: &ArgMatches) -> BatchOutcome {
+    let socket = matches.get_one::<PathBuf>("socket").unwrap();
+    let (limits, safety) = limit_options(matches);
+    let options = JobOptions {
+        limits,
+        safety,
+        ..Default::default()
+    };
+    let result = Scheduler::new(scheduler_config(matches)).and_then(|scheduler| {
+        options
+            .cancel
+            .cancel_on_interrupt(EXIT_INTERRUPTED)
+            .and_then(|()| serve_daemon(socket, &options, &scheduler))
+    });
+    match result {
+        Ok(()) => BatchOutcome::Success,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            BatchOutcome::TotalFailure
+        }
+    }
+}
+
+/// Returns the resource and safety limits given with the arguments of `cli::limit_args`.
+fn limit_options(matches: &ArgMatches) -> (Limits, SafetyLimits) {
+    let max_dimensions = matches.get_one::<(u32, u32)>("max-dimensions").copied();
+    let limits = Limits {
+        max_pixels: matches.get_one::<u64>("max-pixels").copied(),
+        max_memory: matches.get_one::<u64>("max-memory").copied(),
+        ..Default::default()
+    };
+    let safety = SafetyLimits {
+        max_width: max_dimensions.map(|(width, _)| width),
+        max_height: max_dimensions.map(|(_, height)| height),
+        decode_timeout: matches.get_one::<Duration>("decode-timeout").copied(),
+    };
+    (limits, safety)
+}
+
+/// Returns the scheduler settings given to the `daemon` or `grpc` subcommand.
+fn scheduler_config(matches: &ArgMatches) -> SchedulerConfig {
+    let config = SchedulerConfig {
+        queue_timeout: *matches.get_one::<Duration>("queue-timeout").unwrap(),
+        rate_limit: matches.get_one::<RateLimit>("rate-limit").copied(),
+        ..Default::default()
+    };
+    match matches.get_one::<u32>("max-concurrent") {
+        Some(&max_concurrent) => SchedulerConfig {
+            max_concurrent: max_concurrent as usize,
+            ..config
+        },
+        None => config,
+    }
+}
+
+#[cfg(unix)]
+fn serve_daemon(
+    socket: &Path,
+    options: &JobOptions,
+    scheduler: &Scheduler,
+) -> Result<(), Box<dyn std::error::Error>> {
+    image_resizer_rust::ipc::serve(socket, options, scheduler)
+}
+
+#[cfg(not(unix))]
+fn serve_daemon(
+    _socket: &Path,
+    _options: &JobOptions,
+    _scheduler: &Scheduler,
+) -> Result<(), Box<dyn std::error::Error>> {
+    Err("The daemon needs Unix domain sockets, which this platform does not support".into())
+}
+
+/// Runs the `grpc` subcommand, serving the gRPC `ResizeService` until interrupted by
+/// Ctrl-C or a termination signal.
+///
+/// Errors are printed on stderr and reported as a `BatchOutcome::TotalFailure`.
+fn run_grpc(matches: &ArgMatches) -> BatchOutcome {
+    let (limits, safety) = limit_options(matches);
+    let options = JobOptions {
+        limits,
+        safety,
+        ..Default::default()
+    };
+    let result = options
+        .cancel
+        .cancel_on_interrupt(EXIT_INTERRUPTED)
+        .and_then(|()| serve_grpc(matches, options));
+    match result {
+        Ok(()) => BatchOutcome::Success,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            BatchOutcome::TotalFailure
+        }
+    }
+}
+
+#[cfg(feature = "grpc")]
+fn serve_grpc(matches: &ArgMatches, options: JobOptions) -> Result<(), Box<dyn std::error::Error>> {
+    let address = *matches.get_one::<std::net::SocketAddr>("address").unwrap();
+    let server = image_resizer_rust::grpc::ResizeServer::new(
+        options,
+        Scheduler::new(scheduler_config(matches))?,
+    );
+    match matches.get_one::<PathBuf>("root") {
+        Some(root) => server.with_root(root).serve(address),
+        None => server.serve(address),
+    }
+}
+
+#[cfg(not(feature = "grpc"))]
+fn serve_grpc(
+    _matches: &ArgMatches,
+    _options: JobOptions,
+) -> Result<(), Box<dyn std::error::Error>> {
+    Err("The gRPC server is not included in this build (enable the `grpc` feature)".into())
+}
+
+/// Runs the `sequence` subcommand, resizing numbered frames or raw frames piped through
+/// stdin and stdout.
+///
+/// Errors are printed on stderr and reported as a `BatchOutcome::TotalFailure`, or as a
+/// `BatchOutcome::Cancelled` if the sequence was interrupted by Ctrl-C.
+fn run_sequence(matches: &ArgMatches) -> BatchOutcome {
+    let options = SequenceOptions {
+        width: matches.get_one::<u32>("width").copied(),
+        height: matches.get_one::<u32>("height").copied(),
+        start_number: matches.get_one::<u64>("start-number").copied(),
+        encode: EncodeOptions {
+            jpeg_quality: matches.get_one::<u8>("quality").copied(),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let resize = ResizeOptions {
+        threads: *matches.get_one::<usize>("threads").unwrap(),
+        multiple_of: if matches.get_flag("even") { 2 } else { 1 },
+        ..Default::default()
+    };
+    let input = matches.get_one::<Option<FramePattern>>("input").unwrap();
+    let output = matches.get_one::<Option<FramePattern>>("output").unwrap();
+
+    let result = options
+        .cancel
+        .cancel_on_interrupt(EXIT_INTERRUPTED)
+        .and_then(|()| ResizeContext::new(resize))
+        .and_then(|mut context| match (input, output) {
+            (Some(input), Some(output)) => {
+                sequence::resize_sequence(input, output, &options, &mut context)
+            }
+            (None, None) => {
+                let (width, height) = *matches
+                    .get_one::<(u32, u32)>("size")
+                    .ok_or("The size of raw frames must be given with --size")?;
+                let layout = match matches.get_one::<String>("pix-fmt").unwrap().as_str() {
+                    "gray" => PixelLayout::Gray,
+                    "ya8" => PixelLayout::GrayAlpha,
+                    "rgba" => PixelLayout::Rgba,
+                    _ => PixelLayout::Rgb,
+                };
+                // Informational messages would be mixed into the frames on stdout
+                log::set_max_level(log::max_level().min(log::LevelFilter::Warn));
+                let format = RawFormat {
+                    width,
+                    height,
+                    layout,
+                };
+                sequence::resize_raw_frames(
+                    io::stdin().lock(),
+                    io::BufWriter::new(io::stdout().lock()),
+                    &format,
+                    &options,
+                    &mut context,
+                )
+            }
+            _ => Err("Raw frames must be both read from stdin and written to stdout".into()),
+        });
+    match result {
+        Ok(sequence) => {
+            info!(
+                "Resized {} frames to {}x{}",
+                sequence.frames, sequence.width, sequence.height
+            );
+            BatchOutcome::Success
+        }
+        Err(e) if matches!(e.downcast_ref(), Some(ResizeError::Cancelled)) => {
+            eprintln!("error: {}", e);
+            BatchOutcome::Cancelled
+        }
+        Err(e) => {
+            eprintln!("error: {}", e);
+            BatchOutcome::TotalFailure
+        }
+    }
+}
+
+/// Runs the `texture` subcommand, writing a DDS or KTX2 texture with its mipmaps.
+///
+/// Errors while decoding the input or writing the texture are printed on stderr and
+/// reported as a `BatchOutcome::TotalFailure`.
+fn run_texture(matches: &ArgMatches) -> BatchOutcome {
+    let input = matches.get_one::<PathBuf>("input").unwrap();
+    let output = match matches.get_one::<PathBuf>("output") {
+        Some(output) => output.clone(),
+        None => input.with_extension("dds"),
+    };
+    let width = matches.get_one::<u32>("width");
+    let height = matches.get_one::<u32>("height");
+
+    let result = Container::from_path(&output)
+        .ok_or_else(|| format!("The texture must be a .dds or .ktx2 file, got {:?}", output))
+        .map_err(Box::<dyn std::error::Error>::from)
+        .and_then(|container| {
+            let options = TextureOptions {
+                container,
+                compression: matches
+                    .get_one::<String>("compression")
+                    .unwrap()
+                    .parse()
+                    .map_err(usage)?,
+                mipmaps: !matches.get_flag("no-mipmaps"),
+                srgb: !matches.get_flag("linear"),
+            };
+            let mut image = decode_input(input)?;
+            if width.is_some() || height.is_some() {
+                image = DynamicImage::ImageRgba8(resize_image(image, width, height)?);
+            }
+            let bytes = texture::encode_texture(&image, &options, &ResizeOptions::default())?;
+            FileSystem.write_file(&output, &bytes, true)?;
+            Ok((image.width(), image.height(), options))
+        });
+    match result {
+        Ok((width, height, options)) => {
+            let levels = if options.mipmaps {
+                texture::mip_dimensions(width, height).len()
+            } else {
+                1
+            };
+            info!(
+                "Wrote a {}x{} {} texture with {} levels to {:?}",
+                width, height, options.compression, levels, output
+            );
+            BatchOutcome::Success
+        }
+        Err(e) => {
+            eprintln!("error: {}", e);
+            BatchOutcome::TotalFailure
+        }
+    }
+}
+
+/// Returns the format of a sheet written by the `montage`, `contact-sheet` or `join`
+/// subcommand.
+///
+/// # Errors
+///
+/// Returns an error if the sheet is not a PNG or JPEG file.
+fn sheet_format(output: &Path) -> Result<ImageFormat, Box<dyn std::error::Error>> {
+    match ImageFormat::from_path(output) {
+        Ok(format @ (ImageFormat::Png | ImageFormat::Jpeg)) => Ok(format),
+        _ => Err(format!("The sheet must be a .png or .jpg file, got {:?}", output).into()),
+    }
+}
+
+/// Encodes and saves a sheet of `count` images.
+fn save_sheet(
+    sheet: &image::RgbaImage,
+    count: usize,
+    output: &Path,
+    format: ImageFormat,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let (width, height) = sheet.dimensions();
+    let (_, buffer) = estimate_size_and_encode(sheet, format, &EncodeOptions::default())?;
+    let info = save_image(buffer, width, height, output, format)?;
+    info!(
+        "Wrote a {}x{} sheet of {} images to {:?} ({} bytes)",
+        width, height, count, output, info.file_size
+    );
+    Ok(())
+}
+
+/// Decodes two image files and computes their quality metrics.
+fn compare_files(
+    original: &Path,
+    other: &Path,
+) -> Result<QualityMetrics, Box<dyn std::error::Error>> {
+    let original = image::ImageReader::open(original)?.decode()?;
+    let other = image::ImageReader::open(other)?.decode()?;
+    compare_images(original, other)
+}
+
+/// Resizes the input images given on the command line.
 ///
-/// It supports resizing images while maintaining aspect ratio and
-/// allows specifying output format (JPEG or PNG).
+/// This function validates the resize arguments and then processes
+/// every input image with `process_image`, following the error policy selected with
+/// `--fail-fast` or `--keep-going`. With `--zip`, the images are written into a ZIP archive
+/// instead, and with `--to-clipboard` the image is put on the clipboard. `--from-clipboard`
+/// adds the image on the clipboard as an input, and `--job-file` reads the inputs, with their
+/// own output, size and format, from a CSV or JSON file (see `jobfile`). `--files-from` adds
+/// the inputs of a list, processed as they are read (see `pathlist`). Glob patterns are expanded
+/// and `--exclude` patterns and selection criteria, such as `--min-width`, skip inputs (see
+/// `select`). `--recipe` replaces
+/// the standard pipeline with the steps of a recipe (see `recipe`). When no size is given and stdin is a
+/// terminal, as when image files are dropped onto the binary, the size and format are asked
+/// for interactively. Archive inputs are extracted first, and their images keep their paths within
+/// the archive in the output directory or archive. Failures are reported on stderr, and a summary is printed when several inputs
+/// were given.
 ///
 /// # Errors
 ///
 /// Returns an error if:
-/// - Neither width nor height is specified
-/// - The input file cannot be read or is not a valid image
-/// - The output path cannot be determined
-/// - The resizing operation fails
-/// - The output format cannot be determined
-/// - The output path already exists (to prevent accidental overwriting)
-/// - The resized image cannot be saved
+/// - Neither width nor height is specified, and they cannot be asked for interactively
+/// - The job file or the recipe cannot be read or is invalid
+/// - Several inputs are given and the output is not an existing directory
+/// - An archive input cannot be read, or is given without `--output` or `--zip`
+///
+/// Errors while processing individual images are not returned; they are reflected in
+/// the returned `BatchOutcome` instead.
 ///
 /// # Example
 ///
@@ -60,54 +1073,1160 @@ fn main() {
 ///
 /// This example resizes 'input.jpg' to a width of 800 pixels (maintaining aspect ratio)
 /// and saves it as 'resized.png'.
-fn run() -> Result<(), Box<dyn std::error::Error>> {
-    let matches = cli::cli().get_matches();
+fn run_resize(matches: &ArgMatches) -> Result<BatchOutcome, Box<dyn std::error::Error>> {
+    let excludes = match matches.get_many::<String>("exclude") {
+        Some(patterns) => Excludes::new(&patterns.collect::<Vec<_>>())?,
+        None => Excludes::default(),
+    };
+    let mut given = Vec::new();
+    for input in matches.get_many::<PathBuf>("input").into_iter().flatten() {
+        let text = input.to_string_lossy();
+        if !input.exists() && select::is_pattern(&text) {
+            given.extend(select::expand(&text)?);
+        } else {
+            given.push(input.clone());
+        }
+    }
+    let (limits, safety) = limit_options(matches);
+    let limits = Limits {
+        max_archive_size: matches.get_one::<u64>("max-archive-size").copied(),
+        max_archive_entries: matches.get_one::<u64>("max-archive-entries").copied(),
+        ..limits
+    };
+    let mut inputs: Vec<PathBuf> = Vec::new();
+    let mut archives = Vec::new();
+    for input in given.iter().filter(|input| !excludes.matches(input)) {
+        if ArchiveFormat::from_path(input).is_some() {
+            let archive = extract_images(input, &limits)
+                .map_err(|e| format!("Failed to read the archive {:?}: {}", input, e))?;
+            inputs.extend(archive.entries.iter().map(|entry| entry.path.clone()));
+            archives.push(archive);
+        } else {
+            inputs.push(input.clone());
+        }
+    }
+    let jobs = match matches.get_one::<PathBuf>("job-file") {
+        Some(path) => jobfile::load(path)?,
+        None => Vec::new(),
+    };
+    inputs.extend(jobs.iter().map(|job| job.input.clone()));
+    // Listed inputs are read while the batch runs, after the inputs given above
+    let listed = match matches.get_one::<PathBuf>("files-from") {
+        Some(path) => {
+            let list = PathList::open(path, matches.get_flag("null"))?;
+            Some((
+                path,
+                list.filter(|entry| !entry.as_ref().is_ok_and(|p| excludes.matches(p))),
+            ))
+        }
+        None => None,
+    };
+    let recipe = match matches.get_one::<PathBuf>("recipe") {
+        Some(path) => Some(Recipe::load(path)?),
+        None => None,
+    };
+    // Images extracted from an archive, by extracted path
+    let entries: HashMap<&Path, (&ExtractedArchive, &ArchiveEntry)> = archives
+        .iter()
+        .flat_map(|archive| {
+            archive
+                .entries
+                .iter()
+                .map(move |entry| (entry.path.as_path(), (archive, entry)))
+        })
+        .collect();
 
-    let input = matches.get_one::<PathBuf>("input").unwrap();
-    let output = matches.get_one::<String>("output");
-    let width = matches.get_one::<u32>("width");
-    let height = matches.get_one::<u32>("height");
-    let new_format = matches.get_one::<String>("format");
+    let to_clipboard = matches.get_flag("to-clipboard");
+    let clipboard_input = if matches.get_flag("from-clipboard") {
+        let dir = tempfile::TempDir::new()?;
+        let path = dir.path().join("clipboard.png");
+        std::fs::write(&path, clipboard::read_image()?)?;
+        inputs.push(path);
+        Some(dir)
+    } else {
+        None
+    };
+    // The clipboard image lives in a temporary directory, so relative outputs and the
+    // default output are resolved against the current directory instead
+    let clipboard_output = match matches.get_one::<String>("output") {
+        output if clipboard_input.is_some() && !to_clipboard => Some(
+            std::env::current_dir()?
+                .join(output.map_or("clipboard_resized.png", |o| o.as_str()))
+                .to_string_lossy()
+                .to_string(),
+        ),
+        _ => None,
+    };
+    let output = clipboard_output
+        .as_ref()
+        .or(matches.get_one::<String>("output"));
+    let dpi = matches.get_one::<u16>("dpi").copied();
+    let print_dimensions = matches
+        .get_one::<PrintSize>("print-size")
+        .zip(dpi)
+        .map(|(size, dpi)| size.to_pixels(dpi));
+    // Ask for the size and format when running in a terminal with nothing but inputs, e.g.
+    // after files were dropped onto the binary
+    let interactive = if cli::only_inputs_given(matches) && io::stdin().is_terminal() {
+        Some(prompt::ask_settings(
+            &mut io::stdin().lock(),
+            &mut io::stdout(),
+        )?)
+    } else {
+        None
+    };
+    let recipe_size = recipe.as_ref().and_then(Recipe::size);
+    let (width, height) = match (&print_dimensions, &interactive, &recipe_size) {
+        (Some((width, height)), _, _) => (Some(width), Some(height)),
+        (None, Some(settings), _) => (settings.width.as_ref(), settings.height.as_ref()),
+        // The size of the recipe, unless one is given on the command line
+        (None, None, Some((width, height)))
+            if !matches.contains_id("width") && !matches.contains_id("height") =>
+        {
+            (width.as_ref(), height.as_ref())
+        }
+        (None, None, _) => (
+            matches.get_one::<u32>("width"),
+            matches.get_one::<u32>("height"),
+        ),
+    };
+    // The clipboard only takes PNG images
+    let png = String::from("png");
+    let new_format = if to_clipboard {
+        Some(&png)
+    } else {
+        match &interactive {
+            Some(settings) => settings.format.as_ref(),
+            None => matches.get_one::<String>("format").or(recipe
+                .as_ref()
+                .and_then(|recipe| recipe.encode.format.as_ref())),
+        }
+    };
+    let mut encode = EncodeOptions {
+        progressive: matches.get_flag("progressive"),
+        png_compression: matches.get_one::<u8>("png-compression").copied(),
+        palette: matches.get_one::<u16>("palette").copied(),
+        dither: matches
+            .get_one::<String>("dither")
+            .unwrap()
+            .parse()
+            .map_err(usage)?,
+        dpi,
+        jpeg_quality: matches.get_one::<u8>("quality").copied(),
+        tga_rle: matches.get_flag("tga-rle"),
+        jpeg_subsampling: matches
+            .get_one::<String>("chroma-subsampling")
+            .map(|mode| mode.parse())
+            .transpose()
+            .map_err(usage)?,
+        png_filter: matches
+            .get_one::<String>("png-filter")
+            .map(|filter| filter.parse())
+            .transpose()
+            .map_err(usage)?,
+        avif_quality: matches.get_one::<u8>("quality").copied(),
+        avif_speed: matches.get_one::<u8>("avif-speed").copied(),
+        jpeg_backend: matches
+            .get_one::<String>("encoder")
+            .unwrap()
+            .parse()
+            .map_err(usage)?,
+    };
+    if let Some(recipe) = &recipe {
+        encode = recipe.encode_options(&encode);
+    }
+    let mut job_options = JobOptions {
+        limits,
+        input_policy: InputPolicy {
+            allow: matches
+                .get_many::<ImageFormat>("accept")
+                .map(|formats| formats.copied().collect()),
+            deny: matches
+                .get_many::<ImageFormat>("reject")
+                .map(|formats| formats.copied().collect())
+                .unwrap_or_default(),
+        },
+        safety,
+        low_memory: matches.get_flag("low-memory"),
+        resize: ResizeOptions {
+            threads: *matches.get_one::<usize>("threads").unwrap(),
+            cpu_extension: matches
+                .get_one::<String>("cpu-ext")
+                .unwrap()
+                .parse()
+                .map_err(usage)?,
+            rounding: matches
+                .get_one::<String>("rounding")
+                .unwrap()
+                .parse()
+                .map_err(usage)?,
+            multiple_of: if matches.get_flag("even") {
+                2
+            } else {
+                matches.get_one::<u32>("multiple-of").copied().unwrap_or(1)
+            },
+            max_output_pixels: matches
+                .get_one::<f64>("max-megapixels")
+                .map(|megapixels| (megapixels * 1_000_000.0) as u64),
+        },
+        encode,
+        force_reencode: matches.get_flag("force-reencode"),
+        overwrite: if matches.get_flag("yes") {
+            OverwritePolicy::Always
+        } else {
+            matches
+                .get_one::<String>("overwrite")
+                .unwrap()
+                .parse()
+                .map_err(usage)?
+        },
+        direct_write: matches.get_flag("direct-write"),
+        xattrs: XattrPolicy {
+            copy: matches.get_flag("copy-xattrs"),
+            strip_quarantine: matches.get_flag("strip-quarantine"),
+        },
+        hash_names: matches.get_flag("hash-names"),
+        verify: matches.get_flag("verify"),
+        ..Default::default()
+    };
+    let retries = *matches.get_one::<u32>("retries").unwrap();
+    if retries > 0 {
+        let policy = RetryPolicy {
+            retries,
+            delay: *matches.get_one::<Duration>("retry-delay").unwrap(),
+        };
+        job_options.storage = SharedStorage::new(Retrying::new(FileSystem, policy));
+    }
+    if matches.get_flag("deterministic") {
+        job_options.make_deterministic();
+        // Names given to colliding outputs depend on the processing order. The rows of a
+        // job file are already in a fixed order, and must stay aligned with the inputs.
+        if jobs.is_empty() {
+            inputs.sort();
+        }
+    }
+    if let Some(dir) = matches.get_one::<PathBuf>("cache-dir") {
+        let mut salt = cache_salt(matches)?;
+        // The recipe itself is part of the salt, but not the files it reads
+        for path in recipe.iter().flat_map(Recipe::files) {
+            let contents =
+                std::fs::read(path).map_err(|e| format!("Failed to read {:?}: {}", path, e))?;
+            salt.push_str(&format!("#{}", manifest::content_hash(&contents)));
+        }
+        job_options.cache = Some(Cache {
+            salt,
+            max_size: matches.get_one::<u64>("cache-max-size").copied(),
+            max_age: matches.get_one::<Duration>("cache-max-age").copied(),
+            ..Cache::new(dir)
+        });
+    }
 
-    if width.is_none() && height.is_none() {
+    let has_default_size = width.is_some()
+        || height.is_some()
+        || matches.contains_id("upscale")
+        || job_options.resize.max_output_pixels.is_some()
+        || recipe.is_some();
+    if !has_default_size && jobs.is_empty() {
         let err = cli::cli().error(
             ErrorKind::InvalidValue,
-            "At least one of --width or --height must be specified.",
+            "At least one of --width, --height, --print-size or --max-megapixels must be specified.",
+        );
+        err.exit();
+    }
+    if !has_default_size
+        && jobs
+            .iter()
+            .any(|job| job.width.is_none() && job.height.is_none())
+    {
+        let err = cli::cli().error(
+            ErrorKind::InvalidValue,
+            "Rows of the job file without a width or height need --width, --height, --print-size or --max-megapixels.",
         );
         err.exit();
     }
 
-    let output_path = cli::determine_output_path(input, output)?;
-    let img = image::ImageReader::open(input)?.decode()?;
-    let resized_img = resize_image(img, width, height)?;
+    if let Some(&min_rating) = matches.get_one::<i32>("min-rating") {
+        let mut rated = Vec::with_capacity(inputs.len());
+        for input in inputs {
+            let rating = Sidecar::load(&*job_options.storage, &input)?
+                .and_then(|sidecar| sidecar.rating)
+                .unwrap_or(0);
+            if rating >= min_rating {
+                rated.push(input);
+            } else {
+                info!("Skipping {:?}: rated {}", input, rating);
+            }
+        }
+        inputs = rated;
+    }
+    let criteria = Criteria {
+        min_width: matches.get_one::<u32>("min-width").copied(),
+        max_width: matches.get_one::<u32>("max-width").copied(),
+        min_height: matches.get_one::<u32>("min-height").copied(),
+        max_height: matches.get_one::<u32>("max-height").copied(),
+        larger_than: matches.get_one::<u64>("larger-than").copied(),
+        smaller_than: matches.get_one::<u64>("smaller-than").copied(),
+        newer_than: matches.get_one::<SystemTime>("newer-than").copied(),
+        older_than: matches.get_one::<SystemTime>("older-than").copied(),
+    };
+    // Inputs that cannot be probed are kept, so that they are reported as failures
+    let selected = |input: &Path| match criteria.reject_reason(&*job_options.storage, input) {
+        Ok(Some(reason)) => {
+            info!("Skipping {:?}: {}", input, reason);
+            false
+        }
+        _ => true,
+    };
+    if !criteria.is_empty() {
+        inputs.retain(|input| selected(input));
+    }
+    let copy_sidecars = matches.get_flag("copy-sidecars");
 
-    let (save_format, new_output) =
-        determine_save_format_and_path(&resized_img, &output_path, new_format)?;
+    // The inputs written to --output, which are all of them unless rows of a job file give
+    // their own output
+    let shared_output = if jobs.is_empty() {
+        inputs.len()
+    } else {
+        jobs.iter().filter(|job| job.output.is_none()).count()
+    };
+    let create_dirs = matches.get_flag("mkdir");
+    let fix_extension = matches.get_flag("fix-extension");
+    let output_base: cli::OutputBase = matches
+        .get_one::<String>("output-relative-to")
+        .unwrap()
+        .parse()
+        .unwrap();
+    if let Some(dir) = output.filter(|_| create_dirs && (shared_output > 1 || listed.is_some())) {
+        std::fs::create_dir_all(dir)
+            .map_err(|e| format!("Failed to create the output directory {:?}: {}", dir, e))?;
+    }
+    if shared_output > 1 && output.is_some_and(|o| !Path::new(o).is_dir()) {
+        return Err(usage(
+            "When resizing several images, --output must be an existing directory.",
+        ));
+    }
+    if listed.is_some() && output.is_some_and(|o| !Path::new(o).is_dir()) {
+        return Err(usage(
+            "With --files-from, --output must be an existing directory.",
+        ));
+    }
+    cli::check_encoder_options(matches).map_err(usage)?;
+    if inputs.len() > 1 && to_clipboard {
+        return Err(usage("Only one image can be put on the clipboard."));
+    }
+    let in_place = matches.get_flag("in-place");
+    let backup = match matches.get_one::<PathBuf>("backup-dir") {
+        Some(dir) => Backup::Dir(dir.clone()),
+        None if matches.get_flag("backup") => Backup::Suffix,
+        None => Backup::None,
+    };
+    if in_place && !archives.is_empty() {
+        return Err(usage("Images of archives cannot be resized in place."));
+    }
+    let removal = match matches.get_flag("delete-original") {
+        true if !archives.is_empty() => {
+            return Err(usage("The originals of archive images cannot be deleted."))
+        }
+        true if matches.get_flag("no-trash") => Some(Removal::Unlink),
+        true => Some(Removal::Trash),
+        false => None,
+    };
+    let zip_path = matches.get_one::<PathBuf>("zip");
+    if !archives.is_empty() && output.is_none() && zip_path.is_none() {
+        return Err(usage(
+            "Archive inputs need an --output directory or a --zip archive.",
+        ));
+    }
 
-    check_if_path_exists(&new_output)?;
+    let print_hashes = matches.contains_id("hash");
+    let dedupe = matches.get_one::<String>("dedupe");
+    if print_hashes || dedupe.is_some() {
+        let algorithm: HashAlgorithm = match matches.get_one::<String>("hash") {
+            Some(algorithm) => algorithm.parse().map_err(usage)?,
+            None => HashAlgorithm::default(),
+        };
+        let display_name = |input: &Path| match entries.get(input) {
+            Some((_, entry)) => entry.name.clone(),
+            None => input.to_path_buf(),
+        };
 
-    // Use get encoded image buffer and size
-    let (estimated_size, image_buffer) = estimate_size_and_encode(&resized_img, save_format)?;
+        let hashes: Vec<Option<ImageHash>> = inputs
+            .iter()
+            .map(|input| match decode_input(input) {
+                Ok(image) => {
+                    let hash = algorithm.hash(&image);
+                    if print_hashes {
+                        println!("{:?}: {} {}", display_name(input), algorithm, hash);
+                    }
+                    Some(hash)
+                }
+                Err(e) => {
+                    warn!("Failed to hash {:?}: {}", display_name(input), e);
+                    None
+                }
+            })
+            .collect();
 
-    println!(
-        "Estimated size ({}): {} bytes",
-        image_format_to_string(save_format).to_uppercase(),
-        estimated_size
-    );
+        if let Some(action) = dedupe {
+            let threshold = *matches.get_one::<u32>("similarity-threshold").unwrap();
+            let duplicates = find_duplicates(&hashes, threshold);
+            for (index, original) in duplicates.iter().enumerate() {
+                if let (Some(original), Some(hash)) = (original, hashes[index]) {
+                    warn!(
+                        "{:?} is a near-duplicate of {:?} (distance {}){}",
+                        display_name(&inputs[index]),
+                        display_name(&inputs[*original]),
+                        hashes[*original].map_or(0, |other| hash.distance(&other)),
+                        if action == "skip" {
+                            ", skipping it"
+                        } else {
+                            ""
+                        }
+                    );
+                }
+            }
+            if action == "skip" {
+                inputs = inputs
+                    .into_iter()
+                    .zip(duplicates)
+                    .filter(|(_, original)| original.is_none())
+                    .map(|(input, _)| input)
+                    .collect();
+            }
+        }
+    }
+
+    let policy = if matches.get_flag("fail-fast") {
+        ErrorPolicy::FailFast
+    } else {
+        ErrorPolicy::KeepGoing
+    };
+
+    let color_count = matches
+        .get_one::<u16>("dominant-colors")
+        .map(|&count| count as usize);
+    let lqip_size = matches.get_one::<u32>("lqip").copied();
+    let picture_path = matches.get_one::<PathBuf>("picture");
+    let picture_options = PictureOptions {
+        base: picture_path
+            .filter(|path| *path != Path::new("-"))
+            .and_then(|path| path.parent())
+            .map(Path::to_path_buf),
+        ..PictureOptions::default()
+    };
+    let mut pictures = Vec::new();
+    let compare_layout = match matches.get_one::<String>("compare-output") {
+        Some(layout) => Some(layout.parse::<CompareLayout>()?),
+        None => None,
+    };
+
+    let mut pipeline = match &recipe {
+        Some(recipe) => recipe.pipeline()?,
+        None => Pipeline::standard(),
+    };
+    let recipe_stages = pipeline.stage_names().len();
+    // Right after decoding, so that every later stage gets 8-bit pixels within range
+    if let Some(operator) = matches.get_one::<String>("tonemap") {
+        pipeline.insert_after(DECODE, ToneMapStage::new(operator.parse().map_err(usage)?))?;
+    }
+    let apply_sidecars = matches.get_flag("sidecars");
+    if apply_sidecars {
+        pipeline.insert_before(RESIZE, SidecarCropStage)?;
+    }
+    // Before trimming, so that the transparent background can be trimmed
+    if let Some(&fuzz) = matches.get_one::<f32>("remove-background") {
+        let model = matches
+            .get_one::<PathBuf>("background-model")
+            .map(PathBuf::as_path);
+        pipeline.insert_before(RESIZE, RemoveBackgroundStage::new(segmenter(model, fuzz)?))?;
+    }
+    if let Some(&fuzz) = matches.get_one::<f32>("trim") {
+        pipeline.insert_before(
+            RESIZE,
+            ImageStage::new("trim", move |image| Ok(trim::trim(image, fuzz))),
+        )?;
+    }
+    if let Some(&strength) = matches.get_one::<u32>("denoise") {
+        pipeline.insert_before(RESIZE, DenoiseStage::new(strength)?)?;
+    }
+    if let Some(mask) = matches.get_one::<PathBuf>("apply-alpha") {
+        let mask = decode_input(mask)
+            .map_err(|e| format!("Failed to read the alpha mask {:?}: {}", mask, e))?;
+        pipeline.insert_after(RESIZE, ApplyAlphaStage::new(&mask))?;
+    }
+    if matches.get_flag("negate") {
+        pipeline.insert_before(
+            ENCODE,
+            ImageStage::new("negate", |image| {
+                let mut image = image.into_rgba8();
+                filters::negate(&mut image);
+                Ok(image.into())
+            }),
+        )?;
+    }
+    if matches.get_flag("swap-rb") {
+        pipeline.insert_before(
+            ENCODE,
+            ImageStage::new("swap-rb", |image| {
+                let mut image = image.into_rgba8();
+                filters::swap_rb(&mut image);
+                Ok(image.into())
+            }),
+        )?;
+    }
+    if let Some(path) = matches.get_one::<PathBuf>("lut") {
+        let lut = Lut3d::load(path)?;
+        pipeline.insert_before(
+            ENCODE,
+            ImageStage::new("lut", move |image| {
+                let mut image = image.into_rgba8();
+                lut.apply(&mut image);
+                Ok(image.into())
+            }),
+        )?;
+    }
+    if let Some(tone) = matches.get_one::<String>("tone") {
+        let tone: Tone = tone.parse().map_err(usage)?;
+        pipeline.insert_before(
+            ENCODE,
+            ImageStage::new("tone", move |image| {
+                let mut image = image.into_rgba8();
+                filters::apply_tone(&mut image, tone);
+                Ok(image.into())
+            }),
+        )?;
+    }
+    if let Some(&strength) = matches.get_one::<u8>("vignette") {
+        pipeline.insert_before(
+            ENCODE,
+            ImageStage::new("vignette", move |image| {
+                let mut image = image.into_rgba8();
+                filters::vignette(&mut image, strength as f32 / 100.0);
+                Ok(image.into())
+            }),
+        )?;
+    }
+    if let Some(channel) = matches.get_one::<String>("channel") {
+        let channel: Channel = channel.parse().map_err(usage)?;
+        pipeline.insert_before(
+            ENCODE,
+            ImageStage::new("channel", move |image| {
+                Ok(filters::extract_channel(&image.into_rgba8(), channel).into())
+            }),
+        )?;
+    }
+    if let Some(path) = matches.get_one::<PathBuf>("extract-alpha") {
+        pipeline.insert_before(
+            ENCODE,
+            ExtractAlphaStage::new(path, inputs.len() > 1 || listed.is_some()),
+        )?;
+    }
+    let exif_filter = if let Some(level) = matches.get_one::<String>("metadata") {
+        level.parse::<MetadataLevel>()?.filter()
+    } else if matches.get_flag("privacy") {
+        Some(ExifFilter::privacy())
+    } else if matches.get_flag("strip-gps") {
+        Some(ExifFilter {
+            strip_gps: true,
+            ..Default::default()
+        })
+    } else if matches.get_flag("keep-metadata") {
+        Some(ExifFilter::default())
+    } else {
+        None
+    };
+    let edits = MetadataEdits {
+        comment: matches.get_one::<String>("set-comment").cloned(),
+        copyright: matches.get_one::<String>("set-copyright").cloned(),
+        artist: matches.get_one::<String>("set-artist").cloned(),
+    };
+    // `--metadata none` adds a stage too, so that images are re-encoded without metadata
+    // instead of being copied through with it
+    if exif_filter.is_some() || !edits.is_empty() || matches.contains_id("metadata") {
+        pipeline.insert_after(ENCODE, MetadataStage::new(exif_filter, edits))?;
+    }
+    if let Some(protocol) = matches.get_one::<String>("preview") {
+        let protocol = match protocol.as_str() {
+            "auto" => Protocol::detect(),
+            name => name.parse().map_err(usage)?,
+        };
+        pipeline.insert_after(
+            ENCODE,
+            ImageStage::new("preview", move |image| {
+                print!(
+                    "{}",
+                    preview::render(&image, protocol, PREVIEW_COLUMNS, PREVIEW_ROWS)?
+                );
+                Ok(image)
+            }),
+        )?;
+    }
+    // Replaced last, since the stages above are positioned relative to the resize stage
+    if matches.get_flag("seam-carve") {
+        pipeline.replace(RESIZE, SeamCarveStage)?;
+    }
+    if let Some(gravity) = matches.get_one::<String>("gravity") {
+        pipeline.replace(RESIZE, CoverCropStage::new(gravity.parse().map_err(usage)?))?;
+    }
+    if let Some(&factor) = matches.get_one::<u32>("upscale") {
+        let engine: Engine = matches
+            .get_one::<String>("engine")
+            .unwrap()
+            .parse()
+            .map_err(usage)?;
+        let model = matches.get_one::<PathBuf>("model").map(PathBuf::as_path);
+        pipeline.replace(RESIZE, UpscaleStage::new(upscaler(engine, model)?, factor))?;
+    }
+
+    // The outputs of a recipe run their own steps, from the recipe only
+    let outputs = recipe.as_ref().filter(|recipe| !recipe.outputs.is_empty());
+    if outputs.is_some() {
+        if ["width", "height", "format", "print-size"]
+            .iter()
+            .any(|id| matches.contains_id(id))
+            || jobs
+                .iter()
+                .any(|job| job.width.is_some() || job.height.is_some() || job.format.is_some())
+        {
+            return Err(usage(
+                "The sizes and formats of a recipe with outputs are given by its outputs.",
+            ));
+        }
+        if in_place {
+            return Err(usage(
+                "A recipe with outputs cannot resize images in place.",
+            ));
+        }
+        if pipeline.stage_names().len() != recipe_stages {
+            return Err(usage(
+                "Options adding pipeline stages cannot be combined with a recipe with outputs.",
+            ));
+        }
+    }
+
+    let mut zip = match zip_path {
+        Some(path) => Some(ZipSink::create(&job_options.overwrite.resolve(path)?)?),
+        None => None,
+    };
+    let mut manifest =
+        (job_options.hash_names || matches.contains_id("manifest")).then(Manifest::new);
+    let mut rows = jobs.iter();
+
+    let process = |input: &Path| {
+        // run_batch processes the inputs in order, so each input gets the next row of the
+        // job file, if any. A row giving a size replaces both sizes of the command line.
+        let job = rows.next();
+        let (width, height) = match job {
+            Some(job) if job.width.is_some() || job.height.is_some() => {
+                (job.width.as_ref(), job.height.as_ref())
+            }
+            _ => (width, height),
+        };
+        let new_format = job.and_then(|job| job.format.as_ref()).or(new_format);
+        let output = job.and_then(|job| job.output.as_ref()).or(output);
+        let name = match entries.get(input) {
+            Some((_, entry)) => entry.name.as_path(),
+            None => Path::new(input.file_name().ok_or("Input path has no file name")?),
+        };
+        if let Some(recipe) = outputs {
+            let base = match output {
+                Some(dir) if entries.contains_key(input) => Path::new(dir).join(name),
+                Some(dir) if Path::new(dir).is_dir() => Path::new(dir).join(name),
+                Some(_) => cli::determine_output_path(input, output, output_base, create_dirs)?,
+                None => input.to_path_buf(),
+            };
+            if let Some(parent) = base.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            let produced = recipe.process_file(input, &base, &job_options)?;
+            for (_, info) in &produced.images {
+                print_image_info(info);
+            }
+            for (name, hash) in &produced.blurhashes {
+                println!("{:?}: {} blurhash {}", input, name, hash);
+            }
+            for (name, uri) in &produced.lqips {
+                println!("{:?}: {} LQIP {}", input, name, uri);
+            }
+            for (name, info) in &produced.images {
+                if let Some(layout) = compare_layout {
+                    save_comparison(input, &info.path, layout)?;
+                }
+                if copy_sidecars {
+                    copy_sidecar(input, &info.path, apply_sidecars, &job_options)?;
+                }
+                if let Some(manifest) = &mut manifest {
+                    let original = match entries.get(input) {
+                        Some((archive, entry)) => archive.source.join(&entry.name),
+                        None => input.to_path_buf(),
+                    };
+                    let sha256 = output_sha256(info, &*job_options.storage)?;
+                    manifest.insert(&original, Asset::new(Some(name), info, Some(sha256)));
+                }
+            }
+            if let Some(count) = color_count {
+                print_dominant_colors(input, count)?;
+            }
+            if let Some(size) = lqip_size {
+                print_lqip(input, size)?;
+            }
+            let images: Vec<&ImageInfo> = produced.images.iter().map(|(_, info)| info).collect();
+            if let Some(path) = picture_path {
+                add_picture(&images, path, &picture_options, &mut pictures)?;
+            }
+            if let Some(removal) = removal {
+                remove_original(input, &images, removal, &*job_options.storage)?;
+            }
+            return Ok(produced.images.len());
+        }
+        let info = match (&mut zip, output) {
+            _ if to_clipboard => process_image_into(
+                input,
+                name,
+                &mut ClipboardSink,
+                width,
+                height,
+                new_format,
+                &job_options,
+            ),
+            (Some(sink), _) => {
+                process_image_into(input, name, sink, width, height, new_format, &job_options)
+            }
+            (None, _) if in_place => {
+                let info = resize_in_place(input, width, height, &backup, &job_options, &pipeline)?;
+                print_image_info(&info);
+                Ok(info)
+            }
+            (None, Some(dir)) if entries.contains_key(input) => {
+                let output_path = Path::new(dir).join(name);
+                if let Some(parent) = output_path.parent() {
+                    std::fs::create_dir_all(parent)?;
+                }
+                let info = resize_file_with(
+                    input,
+                    &output_path,
+                    width,
+                    height,
+                    new_format,
+                    &job_options,
+                    &pipeline,
+                )?;
+                print_image_info(&info);
+                Ok(info)
+            }
+            (None, _) => {
+                cli::check_output_extension(output, new_format, fix_extension)?;
+                process_image(
+                    input,
+                    &cli::determine_output_path(input, output, output_base, create_dirs)?,
+                    width,
+                    height,
+                    new_format,
+                    &job_options,
+                    &pipeline,
+                )
+            }
+        }?;
+        if let Some(count) = color_count {
+            print_dominant_colors(input, count)?;
+        }
+        if let Some(size) = lqip_size {
+            print_lqip(input, size)?;
+        }
+        if let Some(path) = picture_path {
+            add_picture(&[&info], path, &picture_options, &mut pictures)?;
+        }
+        if let Some(layout) = compare_layout {
+            save_comparison(input, &info.path, layout)?;
+        }
+        if copy_sidecars {
+            copy_sidecar(input, &info.path, apply_sidecars, &job_options)?;
+        }
+        if let Some(manifest) = &mut manifest {
+            let original = match entries.get(input) {
+                Some((archive, entry)) => archive.source.join(&entry.name),
+                None => input.to_path_buf(),
+            };
+            // Outputs written to an archive or the clipboard cannot be read back
+            let sha256 = match zip {
+                None if !to_clipboard => Some(output_sha256(&info, &*job_options.storage)?),
+                _ => None,
+            };
+            manifest.insert(&original, Asset::new(None, &info, sha256));
+        }
+        if let Some(removal) = removal {
+            remove_original(input, &[&info], removal, &*job_options.storage)?;
+        }
+        Ok(1)
+    };
+    job_options.cancel.cancel_on_interrupt(EXIT_INTERRUPTED)?;
+    let mut report = match listed {
+        Some((path, list)) => {
+            let list = list
+                .filter(|entry| entry.as_ref().map_or(true, |input| selected(input)))
+                .map(|entry| {
+                    entry.map_err(|e| BatchFailure {
+                        input: path.clone(),
+                        error: format!("Failed to read the list of files: {}", e).into(),
+                    })
+                });
+            let inputs = inputs.iter().cloned().map(Ok).chain(list);
+            run_batch_stream(inputs, policy, &job_options.cancel, process)
+        }
+        None => run_batch(&inputs, policy, &job_options.cancel, process),
+    };
+
+    for failure in &report.errors.failures {
+        match entries.get(failure.input.as_path()) {
+            Some((archive, entry)) => eprintln!(
+                "error: {:?} in {:?}: {}",
+                entry.name, archive.source, failure.error
+            ),
+            None => eprintln!("error: {:?}: {}", failure.input, failure.error),
+        }
+    }
+
+    // Set when an output covering the whole run, such as the manifest or the `<picture>`
+    // elements, cannot be written, so that the run does not report success
+    let mut incomplete = false;
+    if let Some(manifest) = &manifest {
+        let written = match (matches.get_one::<PathBuf>("manifest"), &mut zip) {
+            (Some(path), _) => manifest.write(&*job_options.storage, path),
+            (None, Some(sink)) => sink
+                .write(Path::new(MANIFEST_NAME), manifest.to_json().as_bytes())
+                .map(|_| ()),
+            (None, None) => {
+                let dir = output.map(Path::new).filter(|o| o.is_dir());
+                let path = dir.unwrap_or(Path::new("")).join(MANIFEST_NAME);
+                manifest.write(&*job_options.storage, &path)
+            }
+        };
+        match written {
+            Ok(()) => info!("Wrote the manifest of {} originals", manifest.len()),
+            Err(e) => {
+                eprintln!("error: Failed to write the manifest: {}", e);
+                incomplete = true;
+            }
+        }
+    }
 
-    let save_info = save_image(
-        image_buffer,
-        resized_img.width(),
-        resized_img.height(),
-        &new_output,
-        save_format,
+    if let Some(path) = picture_path.filter(|_| !pictures.is_empty()) {
+        let mut html = pictures.join("\n");
+        html.push('\n');
+        match job_options.storage.write_file(path, html.as_bytes(), true) {
+            Ok(()) => info!("Wrote {} <picture> elements to {:?}", pictures.len(), path),
+            Err(e) => {
+                eprintln!("error: Failed to write {:?}: {}", path, e);
+                incomplete = true;
+            }
+        }
+    }
+
+    if let Some(cache) = &job_options.cache {
+        match cache.evict() {
+            Ok(eviction) if eviction.removed > 0 => info!(
+                "Removed {} cached outputs ({} bytes), {} bytes remain in the cache",
+                eviction.removed, eviction.freed, eviction.kept
+            ),
+            Ok(_) => {}
+            Err(e) => eprintln!("error: Failed to clean up the cache: {}", e),
+        }
+    }
+
+    if let Some(sink) = zip {
+        if let Err(e) = sink.finish() {
+            eprintln!("error: Failed to write the archive: {}", e);
+            report.succeeded.clear();
+        }
+    }
+
+    if report.cancelled {
+        warn!(
+            "Interrupted after processing {} images ({} failed, {} skipped)",
+            report.succeeded.len(),
+            report.errors.len(),
+            report.skipped()
+        );
+    } else if report.total > 1 {
+        info!(
+            "\nProcessed {} of {} images ({} failed, {} skipped)",
+            report.succeeded.len(),
+            report.total,
+            report.errors.len(),
+            report.skipped()
+        );
+    }
+
+    // Keep the console window of a dropped file open until the results have been read
+    if interactive.is_some() {
+        prompt::wait_for_enter(&mut io::stdin().lock(), &mut io::stdout())?;
+    }
+
+    Ok(match report.outcome() {
+        BatchOutcome::Success if incomplete => BatchOutcome::PartialFailure,
+        outcome => outcome,
+    })
+}
+
+/// Resizes a single input image and saves the result.
+///
+/// This function runs `pipeline` with `resize_file_with` and prints information about the
+/// saved image and its source. The output path is usually given by
+/// `cli::determine_output_path`.
+///
+/// # Errors
+///
+/// Returns an error if any step of `resize_file_with` fails (see its documentation).
+fn process_image(
+    input: &Path,
+    output_path: &Path,
+    width: Option<&u32>,
+    height: Option<&u32>,
+    new_format: Option<&String>,
+    job_options: &JobOptions,
+    pipeline: &Pipeline,
+) -> Result<ImageInfo, Box<dyn std::error::Error>> {
+    let save_info = resize_file_with(
+        input,
+        output_path,
+        width,
+        height,
+        new_format,
+        job_options,
+        pipeline,
     )?;
+    print_image_info(&save_info);
+
+    Ok(save_info)
+}
+
+/// Resizes a single input image and adds the result to an archive.
+///
+/// The entry is named `name`, with the extension of the output format.
+///
+/// # Errors
+///
+/// Returns an error if any step of `resize_file_into` fails (see its documentation).
+fn process_image_into(
+    input: &Path,
+    name: &Path,
+    sink: &mut dyn OutputSink,
+    width: Option<&u32>,
+    height: Option<&u32>,
+    new_format: Option<&String>,
+    job_options: &JobOptions,
+) -> Result<ImageInfo, Box<dyn std::error::Error>> {
+    let save_info = resize_file_into(input, name, width, height, new_format, job_options, sink)?;
+    print_image_info(&save_info);
+
+    Ok(save_info)
+}
+
+/// Decodes an input image, guessing its format from its contents.
+fn decode_input(input: &Path) -> Result<DynamicImage, Box<dyn std::error::Error>> {
+    Ok(image::ImageReader::open(input)?
+        .with_guessed_format()?
+        .decode()?)
+}
+
+/// Saves a comparison of an input and its output as `<output>_compare.png`, next to the output.
+///
+/// # Errors
+///
+/// Returns an error if either image cannot be decoded, or if the comparison cannot be saved.
+fn save_comparison(
+    input: &Path,
+    output: &Path,
+    layout: CompareLayout,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let original = decode_input(input)?.into_rgba8();
+    let resized = decode_input(output)?.into_rgba8();
+    let comparison = montage::compare(&original, &resized, layout)?;
+
+    let stem = output
+        .file_stem()
+        .ok_or("Output path has no file name")?
+        .to_string_lossy();
+    let path = output.with_file_name(format!("{}_compare.png", stem));
+    let (width, height) = comparison.dimensions();
+    let (_, buffer) =
+        estimate_size_and_encode(&comparison, ImageFormat::Png, &EncodeOptions::default())?;
+    save_image(buffer, width, height, &path, ImageFormat::Png)?;
+    info!("Comparison saved to {:?}", path);
+    Ok(())
+}
+
+/// Options of the resize command that do not change the encoded outputs, and are left out
+/// of cache keys so that they do not cause misses.
+const CACHE_NEUTRAL_ARGS: &[&str] = &[
+    "input",
+    "output",
+    "from-clipboard",
+    "to-clipboard",
+    "zip",
+    "overwrite",
+    "yes",
+    "fail-fast",
+    "verbose",
+    "quiet",
+    "hash-names",
+    "manifest",
+    "copy-sidecars",
+    "copy-xattrs",
+    "strip-quarantine",
+    "direct-write",
+    "files-from",
+    "null",
+    "exclude",
+    "min-width",
+    "max-width",
+    "min-height",
+    "max-height",
+    "larger-than",
+    "smaller-than",
+    "newer-than",
+    "older-than",
+    "in-place",
+    "backup",
+    "backup-dir",
+    "delete-original",
+    "no-trash",
+    "verify",
+    "retries",
+    "retry-delay",
+    "cache-dir",
+    "cache-max-size",
+    "cache-max-age",
+];
+
+/// Files read by pipeline stages, identified by their contents in cache keys so that
+/// editing them invalidates the cached outputs.
+const CACHE_FILE_ARGS: &[&str] = &["lut", "apply-alpha", "background-model", "model", "recipe"];
+
+/// Returns the salt of the cache keys of a resize command: every option that may change
+/// the outputs, with the contents of the files read by pipeline stages.
+///
+/// # Errors
+///
+/// This function will return an error if one of these files cannot be read.
+fn cache_salt(matches: &ArgMatches) -> Result<String, Box<dyn std::error::Error>> {
+    let mut salt = String::new();
+    for id in matches.ids().map(|id| id.as_str()) {
+        if CACHE_NEUTRAL_ARGS.contains(&id) {
+            continue;
+        }
+        let values: Vec<_> = matches.get_raw(id).into_iter().flatten().collect();
+        salt.push_str(&format!("--{}={:?}", id, values));
+        if CACHE_FILE_ARGS.contains(&id) {
+            for path in &values {
+                let contents =
+                    std::fs::read(path).map_err(|e| format!("Failed to read {:?}: {}", path, e))?;
+                salt.push_str(&format!("#{}", manifest::content_hash(&contents)));
+            }
+        }
+        salt.push('\n');
+    }
+    Ok(salt)
+}
 
-    println!("Image resized and saved!");
-    println!("New dimensions: {}x{}", save_info.width, save_info.height);
-    println!("Format: {:?}", save_info.format);
-    println!("Output path: {:?}", save_info.path);
-    println!("File size: {} bytes", save_info.file_size);
+/// Copies the XMP sidecar of an input, if it has one, next to its output.
+///
+/// # Errors
+///
+/// This function will return an error if the sidecar cannot be read or copied.
+fn copy_sidecar(
+    input: &Path,
+    output: &Path,
+    cropped: bool,
+    options: &JobOptions,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let Some(sidecar) = Sidecar::load(&*options.storage, input)? else {
+        return Ok(());
+    };
+    match sidecar.copy_next_to(&*options.storage, output, cropped)? {
+        Some(path) => info!("Sidecar copied to {:?}", path),
+        None => warn!(
+            "Not copying the sidecar {:?}: the output would replace it",
+            sidecar.path
+        ),
+    }
+    Ok(())
+}
 
+/// Decodes an input image and prints its `count` dominant colors on stdout, even with `--quiet`.
+///
+/// # Errors
+///
+/// Returns an error if the image cannot be decoded.
+fn print_dominant_colors(input: &Path, count: usize) -> Result<(), Box<dyn std::error::Error>> {
+    let colors: Vec<String> = dominant_colors(&decode_input(input)?, count)
+        .iter()
+        .map(ToString::to_string)
+        .collect();
+    println!("Dominant colors: {}", colors.join(", "));
     Ok(())
 }
+
+/// Prints the `<picture>` element of the outputs of an input if `path` is `-`, or keeps it in
+/// `pictures` to write them all to `path` at the end.
+fn add_picture(
+    images: &[&ImageInfo],
+    path: &Path,
+    options: &PictureOptions,
+    pictures: &mut Vec<String>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let html = picture::render(images, options)?;
+    if path == Path::new("-") {
+        println!("{}", html);
+    } else {
+        pictures.push(html);
+    }
+    Ok(())
+}
+
+/// Returns the hexadecimal SHA-256 of a saved output, reading it back unless it was verified.
+fn output_sha256(
+    info: &ImageInfo,
+    storage: &dyn Storage,
+) -> Result<String, Box<dyn std::error::Error>> {
+    if let Some(verification) = &info.verification {
+        return Ok(verification.checksum.clone());
+    }
+    let mut contents = Vec::new();
+    storage
+        .open_read(&info.path)
+        .and_then(|mut reader| reader.read_to_end(&mut contents))
+        .map_err(|e| format!("Failed to read {:?} for the manifest: {}", info.path, e))?;
+    Ok(manifest::sha256_hex(&contents))
+}
+
+/// Prints the low-quality image placeholder of an input as a data URI on stdout, even with
+/// `--quiet`.
+fn print_lqip(input: &Path, size: u32) -> Result<(), Box<dyn std::error::Error>> {
+    println!("LQIP: {}", lqip::data_uri(&decode_input(input)?, size)?);
+    Ok(())
+}
+
+/// Prints information about a saved image and its source.
+fn print_image_info(save_info: &ImageInfo) {
+    info!("Image saved!");
+    info!("New dimensions: {}x{}", save_info.width, save_info.height);
+    info!("Format: {:?} ({})", save_info.format, save_info.mime_type());
+    info!("Output path: {:?}", save_info.path);
+    info!("File size: {} bytes", save_info.file_size);
+    if let Some(source) = &save_info.source {
+        info!(
+            "Original: {}x{}, {:?}, {} bytes",
+            source.width, source.height, source.format, source.file_size
+        );
+    }
+    if let Some(ratio) = save_info.compression_ratio() {
+        info!("Compression ratio: {:.2}", ratio);
+    }
+    if let Some(duration) = save_info.duration {
+        info!("Processing time: {:.1?}", duration);
+    }
+    if let Some(verification) = &save_info.verification {
+        info!("Verified, SHA-256: {}", verification.checksum);
+    }
+}
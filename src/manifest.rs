@@ -0,0 +1,248 @@
+//! Content-hashed output names and asset manifests.
+//!
+//! Static site generators and bundlers serve assets with far-future cache headers, which
+//! only works if the name of a file changes whenever its contents do. With hashed names,
+//! each output is named after a truncated SHA-256 of its encoded bytes
+//! (`photo.a1b2c3d4.jpg`).
+//!
+//! A manifest lists every original with the variants generated from it, so that templates
+//! and static site generator plugins (Hugo, Eleventy) can look up their paths, dimensions,
+//! formats and checksums. Variants are named after the recipe output they come from, and
+//! `name` is `null` for the single output of an image without recipe outputs:
+//!
+//! ```json
+//! {
+//!   "photos/photo.jpg": [
+//!     {"name": "large", "path": "out/photo_large.a1b2c3d4.jpg", "width": 1600, "height": 1067, "format": "jpeg", "mime_type": "image/jpeg", "file_size": 81234, "sha256": "a1b2c3d4..."},
+//!     {"name": "thumb", "path": "out/photo_thumb.e5f6a7b8.webp", "width": 400, "height": 400, "format": "webp", "mime_type": "image/webp", "file_size": 9876, "sha256": "e5f6a7b8..."}
+//!   ]
+//! }
+//! ```
+
+use crate::{image_format_to_string, montage::escape_json, storage::Storage, ImageInfo};
+use image::ImageFormat;
+use sha2::{Digest, Sha256};
+use std::{
+    collections::BTreeMap,
+    error::Error,
+    fmt::Write,
+    path::{Path, PathBuf},
+};
+
+/// The default file name of manifests.
+pub const MANIFEST_NAME: &str = "manifest.json";
+
+/// The number of hexadecimal digits of the hash kept in output names.
+pub const HASH_LENGTH: usize = 8;
+
+/// Returns the truncated hexadecimal SHA-256 of `contents`, `HASH_LENGTH` digits long.
+pub fn content_hash(contents: &[u8]) -> String {
+    let mut hash = String::with_capacity(HASH_LENGTH);
+    for byte in Sha256::digest(contents).iter().take(HASH_LENGTH / 2) {
+        let _ = write!(hash, "{:02x}", byte);
+    }
+    hash
+}
+
+/// Returns the full hexadecimal SHA-256 of `contents`.
+pub fn sha256_hex(contents: &[u8]) -> String {
+    let mut hash = String::with_capacity(64);
+    for byte in Sha256::digest(contents) {
+        let _ = write!(hash, "{:02x}", byte);
+    }
+    hash
+}
+
+/// Inserts a hash before the extension of a path: `photo.jpg` becomes `photo.<hash>.jpg`.
+pub fn hashed_path(path: &Path, hash: &str) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match path.extension() {
+        Some(extension) => format!("{}.{}.{}", stem, hash, extension.to_string_lossy()),
+        None => format!("{}.{}", stem, hash),
+    };
+    path.with_file_name(name)
+}
+
+/// A variant generated from an original.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Asset {
+    /// The name of the recipe output the variant comes from, if any.
+    pub name: Option<String>,
+    /// The path of the variant.
+    pub path: PathBuf,
+    /// The width of the variant in pixels.
+    pub width: u32,
+    /// The height of the variant in pixels.
+    pub height: u32,
+    /// The format of the variant.
+    pub format: ImageFormat,
+    /// The size of the variant in bytes.
+    pub file_size: u64,
+    /// The hexadecimal SHA-256 of the variant, if known.
+    pub sha256: Option<String>,
+}
+
+impl Asset {
+    /// Describes a saved image.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The name of the recipe output the image comes from, if any.
+    /// * `info` - The saved image.
+    /// * `sha256` - The hexadecimal SHA-256 of the image, if known.
+    pub fn new(name: Option<&str>, info: &ImageInfo, sha256: Option<String>) -> Self {
+        Asset {
+            name: name.map(str::to_string),
+            path: info.path.clone(),
+            width: info.width,
+            height: info.height,
+            format: info.format,
+            file_size: info.file_size,
+            sha256,
+        }
+    }
+
+    /// Returns the asset as a single line JSON object.
+    fn to_json(&self) -> String {
+        let text = |value: Option<&str>| match value {
+            Some(value) => format!("\"{}\"", escape_json(value)),
+            None => "null".to_string(),
+        };
+        format!(
+            "{{\"name\": {}, \"path\": \"{}\", \"width\": {}, \"height\": {}, \"format\": \"{}\", \"mime_type\": \"{}\", \"file_size\": {}, \"sha256\": {}}}",
+            text(self.name.as_deref()),
+            escape_json(&self.path.to_string_lossy()),
+            self.width,
+            self.height,
+            image_format_to_string(self.format).to_lowercase(),
+            self.format.to_mime_type(),
+            self.file_size,
+            text(self.sha256.as_deref())
+        )
+    }
+}
+
+/// A mapping from original files to the variants generated from them, written as a JSON
+/// object sorted by original path so that identical batches produce identical manifests.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Manifest {
+    entries: BTreeMap<String, Vec<Asset>>,
+}
+
+impl Manifest {
+    /// Creates an empty manifest.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records a variant of an original, after the variants recorded before.
+    pub fn insert(&mut self, original: &Path, asset: Asset) {
+        self.entries
+            .entry(original.to_string_lossy().into_owned())
+            .or_default()
+            .push(asset);
+    }
+
+    /// Returns the number of originals in the manifest.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns true if the manifest has no entries.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Returns the manifest as a JSON object.
+    pub fn to_json(&self) -> String {
+        if self.entries.is_empty() {
+            return "{}\n".to_string();
+        }
+        let entries: Vec<String> = self
+            .entries
+            .iter()
+            .map(|(original, assets)| {
+                let assets: Vec<String> = assets
+                    .iter()
+                    .map(|asset| format!("    {}", asset.to_json()))
+                    .collect();
+                format!(
+                    "  \"{}\": [\n{}\n  ]",
+                    escape_json(original),
+                    assets.join(",\n")
+                )
+            })
+            .collect();
+        format!("{{\n{}\n}}\n", entries.join(",\n"))
+    }
+
+    /// Writes the manifest as JSON.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the manifest cannot be written.
+    pub fn write(&self, storage: &dyn Storage, path: &Path) -> Result<(), Box<dyn Error>> {
+        storage
+            .write_file(path, self.to_json().as_bytes(), true)
+            .map_err(|e| format!("Failed to write the manifest {:?}: {}", path, e).into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_outputs_after_their_contents() {
+        // The SHA-256 of "abc" starts with ba7816bf
+        assert_eq!(content_hash(b"abc"), "ba7816bf");
+        assert_eq!(
+            hashed_path(Path::new("out/photo.jpg"), "ba7816bf"),
+            PathBuf::from("out/photo.ba7816bf.jpg")
+        );
+        assert_eq!(
+            hashed_path(Path::new("archive.tar.gz"), "ba7816bf"),
+            PathBuf::from("archive.tar.ba7816bf.gz")
+        );
+    }
+
+    fn asset(name: Option<&str>, path: &str, width: u32) -> Asset {
+        Asset {
+            name: name.map(str::to_string),
+            path: PathBuf::from(path),
+            width,
+            height: width / 2,
+            format: ImageFormat::Png,
+            file_size: 100,
+            sha256: None,
+        }
+    }
+
+    #[test]
+    fn writes_sorted_json() {
+        let mut manifest = Manifest::new();
+        assert_eq!(manifest.to_json(), "{}\n");
+        manifest.insert(Path::new("b.png"), asset(None, "out/b.0000.png", 20));
+        manifest.insert(
+            Path::new("a \"1\".jpg"),
+            asset(Some("large"), "out/a.png", 40),
+        );
+        let mut thumb = asset(Some("thumb"), "out/a_thumb.png", 10);
+        thumb.sha256 = Some(sha256_hex(b"abc"));
+        manifest.insert(Path::new("a \"1\".jpg"), thumb);
+        assert_eq!(manifest.len(), 2);
+        assert_eq!(
+            manifest.to_json(),
+            r#"{
+  "a \"1\".jpg": [
+    {"name": "large", "path": "out/a.png", "width": 40, "height": 20, "format": "png", "mime_type": "image/png", "file_size": 100, "sha256": null},
+    {"name": "thumb", "path": "out/a_thumb.png", "width": 10, "height": 5, "format": "png", "mime_type": "image/png", "file_size": 100, "sha256": "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"}
+  ],
+  "b.png": [
+    {"name": null, "path": "out/b.0000.png", "width": 20, "height": 10, "format": "png", "mime_type": "image/png", "file_size": 100, "sha256": null}
+  ]
+}
+"#
+        );
+    }
+}
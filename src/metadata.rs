@@ -0,0 +1,998 @@
+//! EXIF and XMP metadata.
+//!
+//! Re-encoding an image drops its metadata. This module reads the EXIF block of JPEG and PNG
+//! inputs (the APP1 segment and the `eXIf` chunk), parses it into a small model of its
+//! IFDs, removes the tags selected by an `ExifFilter`, and writes the result into encoded
+//! JPEG and PNG outputs. `MetadataLevel` offers graded presets of filters, from keeping
+//! everything to keeping nothing. `MetadataEdits` sets a comment, a copyright notice and an artist,
+//! both as EXIF tags and in an XMP packet, since EXIF text is limited to ASCII while XMP is
+//! Unicode. `MetadataStage` does all of this after the encode stage.
+//!
+//! The model keeps the first IFD and the Exif, GPS and interoperability IFDs it points to.
+//! The second IFD, which holds a thumbnail of the image before resizing, is always dropped.
+//! Values are kept as raw bytes in the byte order of the input, which is also used for the
+//! output, so that tags do not have to be decoded to be copied.
+
+use crate::{
+    options::JobOptions,
+    pipeline::{PipelineState, Stage},
+};
+use image::ImageFormat;
+use std::{error::Error, fmt, io::Read, str::FromStr};
+use tracing::debug;
+
+/// The name of the metadata stage.
+pub const METADATA: &str = "metadata";
+
+/// The tag of the pointer to the Exif IFD.
+const EXIF_IFD: u16 = 0x8769;
+/// The tag of the pointer to the GPS IFD, which holds the location of the photo.
+pub const GPS_IFD: u16 = 0x8825;
+/// The tag of the pointer to the interoperability IFD.
+const INTEROP_IFD: u16 = 0xA005;
+/// The tags pointing to sub-IFDs, which are parsed and rewritten.
+const SUB_IFDS: [u16; 3] = [EXIF_IFD, GPS_IFD, INTEROP_IFD];
+
+/// The tags identifying the camera, its owner or the computer used, removed in privacy mode.
+pub const PRIVATE_TAGS: [u16; 7] = [
+    0x013C, // HostComputer
+    0x927C, // MakerNote, which often holds serial numbers
+    0xA420, // ImageUniqueID
+    0xA430, // CameraOwnerName
+    0xA431, // BodySerialNumber
+    0xA435, // LensSerialNumber
+    0xC62F, // CameraSerialNumber
+];
+
+/// The tag of the orientation of the image, in the first IFD.
+pub const ORIENTATION: u16 = 0x0112;
+/// The tag of the color space (sRGB or uncalibrated), in the Exif IFD.
+pub const COLOR_SPACE: u16 = 0xA001;
+/// The tag of the EXIF version, which the Exif IFD must hold.
+const EXIF_VERSION: u16 = 0x9000;
+/// The tag of the interoperability index (`R98` for sRGB, `R03` for Adobe RGB), in the
+/// interoperability IFD.
+const INTEROP_INDEX: u16 = 0x0001;
+
+/// The tags kept by the safe preset: the orientation, the color space and the copyright,
+/// with the sub-IFDs holding them.
+pub const SAFE_TAGS: [u16; 7] = [
+    ORIENTATION,
+    COPYRIGHT,
+    EXIF_IFD,
+    EXIF_VERSION,
+    COLOR_SPACE,
+    INTEROP_IFD,
+    INTEROP_INDEX,
+];
+
+/// The tag of the author, in the first IFD.
+pub const ARTIST: u16 = 0x013B;
+/// The tag of the copyright notice, in the first IFD.
+pub const COPYRIGHT: u16 = 0x8298;
+/// The tag of the user comment, in the Exif IFD.
+pub const USER_COMMENT: u16 = 0x9286;
+
+/// The pixel dimensions recorded in the Exif IFD, updated to the output size.
+const PIXEL_X_DIMENSION: u16 = 0xA002;
+const PIXEL_Y_DIMENSION: u16 = 0xA003;
+
+/// The TIFF type of text values.
+const ASCII: u16 = 2;
+/// The TIFF type of 32-bit unsigned values.
+const LONG: u16 = 4;
+/// The TIFF type of opaque bytes.
+const UNDEFINED: u16 = 7;
+/// The header of the EXIF APP1 segment of JPEGs.
+const JPEG_EXIF_HEADER: &[u8] = b"Exif\0\0";
+/// The header of the XMP APP1 segment of JPEGs.
+const JPEG_XMP_HEADER: &[u8] = b"http://ns.adobe.com/xap/1.0/\0";
+/// The largest payload of a JPEG segment.
+const MAX_JPEG_SEGMENT_LEN: usize = 0xFFFF - 2;
+/// Sub-IFDs nested deeper than this are ignored, which also breaks reference loops.
+const MAX_DEPTH: usize = 3;
+
+/// Which EXIF tags are removed from the outputs.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ExifFilter {
+    /// Remove the GPS IFD.
+    pub strip_gps: bool,
+    /// Remove `PRIVATE_TAGS`.
+    pub strip_private: bool,
+    /// Remove every tag except `SAFE_TAGS`.
+    pub safe_only: bool,
+}
+
+impl ExifFilter {
+    /// Returns the filter of the privacy preset, removing the location and the tags
+    /// identifying the camera or its owner, but keeping the orientation and dates.
+    pub fn privacy() -> Self {
+        ExifFilter {
+            strip_gps: true,
+            strip_private: true,
+            ..Default::default()
+        }
+    }
+
+    /// Returns the filter of the safe preset, keeping only the orientation, the color space
+    /// and the copyright.
+    pub fn safe() -> Self {
+        ExifFilter {
+            safe_only: true,
+            ..ExifFilter::privacy()
+        }
+    }
+
+    /// Returns whether `tag` is removed.
+    pub fn removes(&self, tag: u16) -> bool {
+        (self.strip_gps && tag == GPS_IFD)
+            || (self.strip_private && PRIVATE_TAGS.contains(&tag))
+            || (self.safe_only && !SAFE_TAGS.contains(&tag))
+    }
+}
+
+/// How much of the EXIF metadata of the inputs is kept in the outputs.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MetadataLevel {
+    /// Keep every tag, except the thumbnail.
+    All,
+    /// Keep the orientation, the color space and the copyright, dropping the location, the
+    /// maker notes and everything else (see `ExifFilter::safe`).
+    Safe,
+    /// Drop all metadata, as re-encoding does by default.
+    #[default]
+    None,
+}
+
+impl MetadataLevel {
+    /// Returns the filter of the tags kept, or None if no metadata is kept.
+    pub fn filter(self) -> Option<ExifFilter> {
+        match self {
+            MetadataLevel::All => Some(ExifFilter::default()),
+            MetadataLevel::Safe => Some(ExifFilter::safe()),
+            MetadataLevel::None => None,
+        }
+    }
+}
+
+impl fmt::Display for MetadataLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            MetadataLevel::All => "all",
+            MetadataLevel::Safe => "safe",
+            MetadataLevel::None => "none",
+        };
+        f.write_str(name)
+    }
+}
+
+impl FromStr for MetadataLevel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "all" => Ok(MetadataLevel::All),
+            "safe" => Ok(MetadataLevel::Safe),
+            "none" => Ok(MetadataLevel::None),
+            _ => Err(format!("Unknown metadata level '{}'", s)),
+        }
+    }
+}
+
+/// Text fields written to the outputs, replacing the ones of the input.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MetadataEdits {
+    /// A comment, written to the EXIF `UserComment` and XMP `exif:UserComment` fields.
+    pub comment: Option<String>,
+    /// A copyright notice, written to the EXIF `Copyright` and XMP `dc:rights` fields.
+    pub copyright: Option<String>,
+    /// The author, written to the EXIF `Artist` and XMP `dc:creator` fields.
+    pub artist: Option<String>,
+}
+
+impl MetadataEdits {
+    /// Returns whether no field is set.
+    pub fn is_empty(&self) -> bool {
+        self.comment.is_none() && self.copyright.is_none() && self.artist.is_none()
+    }
+
+    /// Returns an XMP packet holding the fields that are set.
+    pub fn to_xmp(&self) -> String {
+        let mut properties = String::new();
+        if let Some(artist) = &self.artist {
+            properties.push_str(&format!(
+                "   <dc:creator><rdf:Seq><rdf:li>{}</rdf:li></rdf:Seq></dc:creator>\n",
+                escape_xml(artist)
+            ));
+        }
+        let alternatives = [
+            ("dc:rights", &self.copyright),
+            ("exif:UserComment", &self.comment),
+        ];
+        for (name, value) in alternatives {
+            if let Some(value) = value {
+                properties.push_str(&format!(
+                    "   <{name}><rdf:Alt><rdf:li xml:lang=\"x-default\">{}</rdf:li></rdf:Alt></{name}>\n",
+                    escape_xml(value)
+                ));
+            }
+        }
+        format!(
+            concat!(
+                "<?xpacket begin=\"\u{feff}\" id=\"W5M0MpCehiHzreSzNTczkc9d\"?>\n",
+                "<x:xmpmeta xmlns:x=\"adobe:ns:meta/\">\n",
+                " <rdf:RDF xmlns:rdf=\"http://www.w3.org/1999/02/22-rdf-syntax-ns#\">\n",
+                "  <rdf:Description rdf:about=\"\"",
+                " xmlns:dc=\"http://purl.org/dc/elements/1.1/\"",
+                " xmlns:exif=\"http://ns.adobe.com/exif/1.0/\">\n",
+                "{}",
+                "  </rdf:Description>\n",
+                " </rdf:RDF>\n",
+                "</x:xmpmeta>\n",
+                "<?xpacket end=\"w\"?>",
+            ),
+            properties
+        )
+    }
+}
+
+/// Escapes the characters with a special meaning in XML text and attributes.
+fn escape_xml(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// A tag of an IFD, with its value as raw bytes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Entry {
+    tag: u16,
+    kind: u16,
+    count: u32,
+    value: Vec<u8>,
+}
+
+/// An image file directory, with the sub-IFDs it points to.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct Ifd {
+    entries: Vec<Entry>,
+    children: Vec<(u16, Ifd)>,
+}
+
+impl Ifd {
+    fn retain(&mut self, keep: &impl Fn(u16) -> bool) {
+        self.entries.retain(|entry| keep(entry.tag));
+        self.children.retain(|(tag, _)| keep(*tag));
+        for (_, child) in &mut self.children {
+            child.retain(keep);
+        }
+    }
+
+    /// Adds an entry, replacing the entry with the same tag if any.
+    fn set(&mut self, entry: Entry) {
+        match self.entries.iter_mut().find(|old| old.tag == entry.tag) {
+            Some(old) => *old = entry,
+            None => self.entries.push(entry),
+        }
+    }
+
+    /// Returns the sub-IFD of a pointer tag, creating it if needed.
+    fn child_or_insert(&mut self, tag: u16) -> &mut Ifd {
+        let index = match self.children.iter().position(|(child, _)| *child == tag) {
+            Some(index) => index,
+            None => {
+                self.children.push((tag, Ifd::default()));
+                self.children.len() - 1
+            }
+        };
+        &mut self.children[index].1
+    }
+
+    fn child_mut(&mut self, tag: u16) -> Option<&mut Ifd> {
+        self.children
+            .iter_mut()
+            .find(|(child, _)| *child == tag)
+            .map(|(_, ifd)| ifd)
+    }
+}
+
+/// Returns the size in bytes of one value of a TIFF type, or None for unknown types.
+fn type_size(kind: u16) -> Option<usize> {
+    match kind {
+        1 | 2 | 6 | 7 => Some(1),
+        3 | 8 => Some(2),
+        4 | 9 | 11 | 13 => Some(4),
+        5 | 10 | 12 => Some(8),
+        _ => None,
+    }
+}
+
+/// Reads and writes integers in the byte order of an EXIF block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct ByteOrder {
+    big_endian: bool,
+}
+
+impl ByteOrder {
+    fn u16(&self, data: &[u8], offset: usize) -> Option<u16> {
+        let bytes = data.get(offset..offset + 2)?.try_into().ok()?;
+        Some(if self.big_endian {
+            u16::from_be_bytes(bytes)
+        } else {
+            u16::from_le_bytes(bytes)
+        })
+    }
+
+    fn u32(&self, data: &[u8], offset: usize) -> Option<u32> {
+        let bytes = data.get(offset..offset + 4)?.try_into().ok()?;
+        Some(if self.big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        })
+    }
+
+    fn u16_bytes(&self, value: u16) -> [u8; 2] {
+        if self.big_endian {
+            value.to_be_bytes()
+        } else {
+            value.to_le_bytes()
+        }
+    }
+
+    fn u32_bytes(&self, value: u32) -> [u8; 4] {
+        if self.big_endian {
+            value.to_be_bytes()
+        } else {
+            value.to_le_bytes()
+        }
+    }
+}
+
+/// The EXIF metadata of an image.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Exif {
+    order: ByteOrder,
+    ifd0: Ifd,
+}
+
+impl Default for Exif {
+    fn default() -> Self {
+        Exif::new()
+    }
+}
+
+impl Exif {
+    /// Creates empty, little-endian metadata.
+    pub fn new() -> Self {
+        Exif {
+            order: ByteOrder { big_endian: false },
+            ifd0: Ifd::default(),
+        }
+    }
+
+    /// Parses an EXIF block, i.e. a TIFF header followed by IFDs.
+    ///
+    /// Entries of unknown types and entries whose value lies outside of the block are
+    /// skipped.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the block has no valid TIFF header.
+    pub fn parse(data: &[u8]) -> Result<Self, Box<dyn Error>> {
+        let order = match data.get(..4) {
+            Some(b"II*\0") => ByteOrder { big_endian: false },
+            Some(b"MM\0*") => ByteOrder { big_endian: true },
+            _ => return Err("Invalid EXIF data: missing TIFF header".into()),
+        };
+        let offset = order.u32(data, 4).ok_or("Invalid EXIF data")? as usize;
+        Ok(Exif {
+            order,
+            ifd0: Self::parse_ifd(data, offset, order, 0),
+        })
+    }
+
+    fn parse_ifd(data: &[u8], offset: usize, order: ByteOrder, depth: usize) -> Ifd {
+        let mut ifd = Ifd::default();
+        let count = order.u16(data, offset).unwrap_or(0) as usize;
+        for i in 0..count {
+            let at = offset + 2 + i * 12;
+            let (Some(tag), Some(kind), Some(value_count)) = (
+                order.u16(data, at),
+                order.u16(data, at + 2),
+                order.u32(data, at + 4),
+            ) else {
+                break;
+            };
+            if SUB_IFDS.contains(&tag) {
+                if let Some(child) = order.u32(data, at + 8) {
+                    if depth < MAX_DEPTH {
+                        let child = Self::parse_ifd(data, child as usize, order, depth + 1);
+                        ifd.children.push((tag, child));
+                    }
+                }
+                continue;
+            }
+            let Some(len) = type_size(kind).and_then(|size| size.checked_mul(value_count as usize))
+            else {
+                continue;
+            };
+            let start = if len <= 4 {
+                at + 8
+            } else {
+                match order.u32(data, at + 8) {
+                    Some(start) => start as usize,
+                    None => continue,
+                }
+            };
+            if let Some(value) = data.get(start..start.saturating_add(len)) {
+                ifd.entries.push(Entry {
+                    tag,
+                    kind,
+                    count: value_count,
+                    value: value.to_vec(),
+                });
+            }
+        }
+        ifd
+    }
+
+    /// Returns whether the metadata has no tags left.
+    pub fn is_empty(&self) -> bool {
+        self.ifd0.entries.is_empty() && self.ifd0.children.is_empty()
+    }
+
+    /// Returns whether a tag is present, in any IFD.
+    pub fn contains(&self, tag: u16) -> bool {
+        fn search(ifd: &Ifd, tag: u16) -> bool {
+            ifd.entries.iter().any(|entry| entry.tag == tag)
+                || ifd
+                    .children
+                    .iter()
+                    .any(|(child, ifd)| *child == tag || search(ifd, tag))
+        }
+        search(&self.ifd0, tag)
+    }
+
+    /// Removes the tags, and the sub-IFDs, selected by a filter.
+    pub fn apply(&mut self, filter: &ExifFilter) {
+        self.ifd0.retain(&|tag| !filter.removes(tag));
+    }
+
+    /// Sets a text tag of the first IFD, such as `ARTIST` or `COPYRIGHT`.
+    ///
+    /// EXIF text is meant to be ASCII, but the text is written as UTF-8, which most readers
+    /// accept.
+    pub fn set_text(&mut self, tag: u16, text: &str) {
+        let value = [text.as_bytes(), b"\0"].concat();
+        self.ifd0.set(Entry {
+            tag,
+            kind: ASCII,
+            count: value.len() as u32,
+            value,
+        });
+    }
+
+    /// Sets the user comment of the Exif IFD, as ASCII if possible and UTF-16 otherwise.
+    pub fn set_user_comment(&mut self, text: &str) {
+        let mut value = Vec::with_capacity(8 + text.len() * 2);
+        if text.is_ascii() {
+            value.extend_from_slice(b"ASCII\0\0\0");
+            value.extend_from_slice(text.as_bytes());
+        } else {
+            value.extend_from_slice(b"UNICODE\0");
+            for unit in text.encode_utf16() {
+                value.extend_from_slice(&self.order.u16_bytes(unit));
+            }
+        }
+        self.ifd0.child_or_insert(EXIF_IFD).set(Entry {
+            tag: USER_COMMENT,
+            kind: UNDEFINED,
+            count: value.len() as u32,
+            value,
+        });
+    }
+
+    /// Sets the fields of `edits` that are set.
+    pub fn apply_edits(&mut self, edits: &MetadataEdits) {
+        if let Some(comment) = &edits.comment {
+            self.set_user_comment(comment);
+        }
+        if let Some(copyright) = &edits.copyright {
+            self.set_text(COPYRIGHT, copyright);
+        }
+        if let Some(artist) = &edits.artist {
+            self.set_text(ARTIST, artist);
+        }
+    }
+
+    /// Updates the pixel dimensions recorded in the Exif IFD, if any, to a new image size.
+    pub fn set_dimensions(&mut self, width: u32, height: u32) {
+        let order = self.order;
+        let Some(exif) = self.ifd0.child_mut(EXIF_IFD) else {
+            return;
+        };
+        for entry in &mut exif.entries {
+            let value = match entry.tag {
+                PIXEL_X_DIMENSION => width,
+                PIXEL_Y_DIMENSION => height,
+                _ => continue,
+            };
+            *entry = Entry {
+                tag: entry.tag,
+                kind: LONG,
+                count: 1,
+                value: order.u32_bytes(value).to_vec(),
+            };
+        }
+    }
+
+    /// Serializes the metadata to an EXIF block.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut data = if self.order.big_endian {
+            b"MM\0*".to_vec()
+        } else {
+            b"II*\0".to_vec()
+        };
+        data.extend_from_slice(&self.order.u32_bytes(8));
+        self.write_ifd(&mut data, &self.ifd0);
+        data
+    }
+
+    /// Appends an IFD, followed by its out-of-line values and its sub-IFDs, and returns its
+    /// offset. Entries are sorted by tag, as TIFF requires.
+    fn write_ifd(&self, data: &mut Vec<u8>, ifd: &Ifd) -> u32 {
+        let order = self.order;
+        let start = data.len();
+        let mut tags: Vec<(u16, Option<&Entry>, Option<&Ifd>)> = ifd
+            .entries
+            .iter()
+            .map(|entry| (entry.tag, Some(entry), None))
+            .chain(
+                ifd.children
+                    .iter()
+                    .map(|(tag, child)| (*tag, None, Some(child))),
+            )
+            .collect();
+        tags.sort_by_key(|(tag, _, _)| *tag);
+
+        data.extend_from_slice(&order.u16_bytes(tags.len() as u16));
+        data.resize(start + 2 + tags.len() * 12 + 4, 0);
+        for (i, (tag, entry, child)) in tags.into_iter().enumerate() {
+            let at = start + 2 + i * 12;
+            data[at..at + 2].copy_from_slice(&order.u16_bytes(tag));
+            let (kind, count, value) = match (entry, child) {
+                (Some(entry), _) if entry.value.len() <= 4 => {
+                    let mut value = [0u8; 4];
+                    value[..entry.value.len()].copy_from_slice(&entry.value);
+                    (entry.kind, entry.count, value)
+                }
+                (Some(entry), _) => {
+                    let offset = data.len() as u32;
+                    data.extend_from_slice(&entry.value);
+                    if data.len() % 2 == 1 {
+                        data.push(0);
+                    }
+                    (entry.kind, entry.count, order.u32_bytes(offset))
+                }
+                (None, Some(child)) => (LONG, 1, order.u32_bytes(self.write_ifd(data, child))),
+                (None, None) => unreachable!("Every tag is an entry or a sub-IFD"),
+            };
+            data[at + 2..at + 4].copy_from_slice(&order.u16_bytes(kind));
+            data[at + 4..at + 8].copy_from_slice(&order.u32_bytes(count));
+            data[at + 8..at + 12].copy_from_slice(&value);
+        }
+        start as u32
+    }
+}
+
+/// Returns the EXIF block of an encoded JPEG or PNG image, if it has one.
+pub fn read_exif(data: &[u8]) -> Option<&[u8]> {
+    if data.starts_with(&[0xFF, 0xD8]) {
+        let mut at = 2;
+        while at + 4 <= data.len() && data[at] == 0xFF {
+            let marker = data[at + 1];
+            // Start of scan: the metadata segments come before the image data
+            if marker == 0xDA || marker == 0xD9 {
+                break;
+            }
+            if marker == 0xFF || (0xD0..=0xD7).contains(&marker) || marker == 0x01 {
+                at += if marker == 0xFF { 1 } else { 2 };
+                continue;
+            }
+            let len = u16::from_be_bytes([data[at + 2], data[at + 3]]) as usize;
+            let segment = data.get(at + 4..at + 2 + len)?;
+            if marker == 0xE1 && segment.starts_with(JPEG_EXIF_HEADER) {
+                return Some(&segment[JPEG_EXIF_HEADER.len()..]);
+            }
+            at += 2 + len;
+        }
+        None
+    } else if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+        let mut at = 8;
+        while at + 8 <= data.len() {
+            let len = u32::from_be_bytes(data[at..at + 4].try_into().ok()?) as usize;
+            let chunk_type = &data[at + 4..at + 8];
+            let chunk = data.get(at + 8..(at + 8).checked_add(len)?)?;
+            match chunk_type {
+                b"eXIf" => return Some(chunk),
+                b"IEND" => break,
+                _ => at += 12 + len,
+            }
+        }
+        None
+    } else {
+        None
+    }
+}
+
+/// Inserts a segment into an encoded JPEG, after the JFIF segment if any.
+fn insert_jpeg_segment(
+    encoded: &[u8],
+    marker: u8,
+    payload: &[u8],
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    if !encoded.starts_with(&[0xFF, 0xD8]) {
+        return Err("Invalid JPEG data".into());
+    }
+    if payload.len() > MAX_JPEG_SEGMENT_LEN {
+        return Err("The metadata is too large for a JPEG segment".into());
+    }
+    let mut at = 2;
+    if encoded.get(2..4) == Some(&[0xFF, 0xE0]) {
+        let len = encoded.get(4..6).ok_or("Invalid JPEG data")?;
+        at += 2 + u16::from_be_bytes([len[0], len[1]]) as usize;
+    }
+    let len = (2 + payload.len()) as u16;
+    let mut output = Vec::with_capacity(encoded.len() + payload.len() + 4);
+    output.extend_from_slice(encoded.get(..at).ok_or("Invalid JPEG data")?);
+    output.extend_from_slice(&[0xFF, marker]);
+    output.extend_from_slice(&len.to_be_bytes());
+    output.extend_from_slice(payload);
+    output.extend_from_slice(&encoded[at..]);
+    Ok(output)
+}
+
+/// Inserts a chunk into an encoded PNG, after the header.
+fn insert_png_chunk(
+    encoded: &[u8],
+    chunk_type: &[u8; 4],
+    data: &[u8],
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    // The signature and the IHDR chunk, which always has 13 bytes of data
+    let header_len = 8 + 12 + 13;
+    if !encoded.starts_with(b"\x89PNG\r\n\x1a\n") || encoded.len() < header_len {
+        return Err("Invalid PNG data".into());
+    }
+    let mut output = Vec::with_capacity(encoded.len() + data.len() + 12);
+    output.extend_from_slice(&encoded[..header_len]);
+    crate::encode::write_png_chunk(&mut output, chunk_type, data);
+    output.extend_from_slice(&encoded[header_len..]);
+    Ok(output)
+}
+
+/// Writes an EXIF block into an encoded image, which must not have one yet.
+///
+/// In JPEGs, the EXIF segment is written after the JFIF segment, if any. In PNGs, the
+/// `eXIf` chunk is written after the header.
+///
+/// # Returns
+///
+/// The image with the EXIF block, or None if the format cannot hold EXIF metadata.
+///
+/// # Errors
+///
+/// This function will return an error if the image is not a valid JPEG or PNG, or if the
+/// block is too large for a JPEG segment.
+pub fn write_exif(
+    encoded: &[u8],
+    format: ImageFormat,
+    exif: &[u8],
+) -> Result<Option<Vec<u8>>, Box<dyn Error>> {
+    match format {
+        ImageFormat::Jpeg => {
+            let payload = [JPEG_EXIF_HEADER, exif].concat();
+            insert_jpeg_segment(encoded, 0xE1, &payload).map(Some)
+        }
+        ImageFormat::Png => insert_png_chunk(encoded, b"eXIf", exif).map(Some),
+        _ => Ok(None),
+    }
+}
+
+/// Writes an XMP packet into an encoded image, like `write_exif`.
+///
+/// In JPEGs, the packet is written to an APP1 segment, and in PNGs to an `iTXt` chunk with
+/// the `XML:com.adobe.xmp` keyword.
+///
+/// # Returns
+///
+/// The image with the XMP packet, or None if the format cannot hold XMP metadata.
+///
+/// # Errors
+///
+/// This function will return an error if the image is not a valid JPEG or PNG, or if the
+/// packet is too large for a JPEG segment.
+pub fn write_xmp(
+    encoded: &[u8],
+    format: ImageFormat,
+    packet: &str,
+) -> Result<Option<Vec<u8>>, Box<dyn Error>> {
+    match format {
+        ImageFormat::Jpeg => {
+            let payload = [JPEG_XMP_HEADER, packet.as_bytes()].concat();
+            insert_jpeg_segment(encoded, 0xE1, &payload).map(Some)
+        }
+        ImageFormat::Png => {
+            // Uncompressed, with empty language and translated keyword fields
+            let data = [b"XML:com.adobe.xmp\0\0\0\0\0".as_slice(), packet.as_bytes()].concat();
+            insert_png_chunk(encoded, b"iTXt", &data).map(Some)
+        }
+        _ => Ok(None),
+    }
+}
+
+/// A stage writing metadata to the encoded output: the EXIF metadata of the input without
+/// the tags removed by a filter, and edited text fields.
+///
+/// Only JPEG and PNG inputs and outputs are supported; other outputs are left unchanged.
+/// The pixel dimensions of the Exif IFD are updated to the output size. A stage without a
+/// filter or edits writes nothing, but like any custom stage it keeps `resize_file_with` from
+/// copying the input through with its metadata.
+pub struct MetadataStage {
+    keep: Option<ExifFilter>,
+    edits: MetadataEdits,
+}
+
+impl MetadataStage {
+    /// Creates a stage copying the EXIF metadata of the input without the tags removed by
+    /// `keep`, if set, and writing `edits` over it.
+    pub fn new(keep: Option<ExifFilter>, edits: MetadataEdits) -> Self {
+        MetadataStage { keep, edits }
+    }
+
+    /// Returns the filtered EXIF metadata of the input, if it is kept and has any.
+    fn input_exif(
+        &self,
+        state: &PipelineState,
+        options: &JobOptions,
+    ) -> Result<Option<Exif>, Box<dyn Error>> {
+        let Some(filter) = self.keep else {
+            return Ok(None);
+        };
+        let mut input = Vec::new();
+        options
+            .storage
+            .open_read(&state.input)?
+            .read_to_end(&mut input)?;
+        let Some(block) = read_exif(&input) else {
+            debug!("No EXIF metadata in {:?}", state.input);
+            return Ok(None);
+        };
+        let mut exif = Exif::parse(block)?;
+        exif.apply(&filter);
+        Ok(Some(exif))
+    }
+}
+
+impl Stage for MetadataStage {
+    fn name(&self) -> &str {
+        METADATA
+    }
+
+    fn run(&self, state: &mut PipelineState, options: &JobOptions) -> Result<(), Box<dyn Error>> {
+        let (Some(mut encoded), Some(format)) = (state.encoded.clone(), state.format) else {
+            return Err("No encoded image: the metadata stage must run after encoding".into());
+        };
+        let mut exif = self.input_exif(state, options)?;
+        if !self.edits.is_empty() {
+            exif.get_or_insert_with(Exif::new).apply_edits(&self.edits);
+            // Written first, so that the EXIF segment ends up before it in JPEGs
+            match write_xmp(&encoded, format, &self.edits.to_xmp())? {
+                Some(output) => encoded = output,
+                None => debug!("Cannot write XMP metadata to {:?} outputs", format),
+            }
+        }
+        if let Some(mut exif) = exif {
+            if let Some(image) = &state.image {
+                exif.set_dimensions(image.width(), image.height());
+            }
+            if !exif.is_empty() {
+                match write_exif(&encoded, format, &exif.to_bytes())? {
+                    Some(output) => encoded = output,
+                    None => debug!("Cannot write EXIF metadata to {:?} outputs", format),
+                }
+            }
+        }
+        state.encoded = Some(encoded);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        encode::{encode_jpeg, encode_png, EncodeOptions},
+        pipeline::{Pipeline, ENCODE},
+    };
+    use image::RgbaImage;
+
+    /// An EXIF block with an orientation, a GPS IFD and an Exif IFD holding a
+    /// date, a serial number and the pixel dimensions.
+    fn sample(big_endian: bool) -> Exif {
+        let order = ByteOrder { big_endian };
+        let ascii = |tag: u16, text: &str| Entry {
+            tag,
+            kind: 2,
+            count: text.len() as u32 + 1,
+            value: [text.as_bytes(), b"\0"].concat(),
+        };
+        let long = |tag: u16, value: u32| Entry {
+            tag,
+            kind: LONG,
+            count: 1,
+            value: order.u32_bytes(value).to_vec(),
+        };
+        let exif = Ifd {
+            entries: vec![
+                ascii(0x9003, "2024:05:01 12:00:00"),
+                ascii(0xA431, "SN12345"),
+                long(PIXEL_X_DIMENSION, 4000),
+            ],
+            children: vec![],
+        };
+        let gps = Ifd {
+            entries: vec![ascii(0x0001, "N")],
+            children: vec![],
+        };
+        Exif {
+            order,
+            ifd0: Ifd {
+                entries: vec![Entry {
+                    tag: 0x0112,
+                    kind: 3,
+                    count: 1,
+                    value: order.u16_bytes(6).to_vec(),
+                }],
+                children: vec![(GPS_IFD, gps), (EXIF_IFD, exif)],
+            },
+        }
+    }
+
+    #[test]
+    fn round_trips_and_filters_tags() {
+        for big_endian in [false, true] {
+            let exif = sample(big_endian);
+            let mut parsed = Exif::parse(&exif.to_bytes()).unwrap();
+            assert!(parsed.contains(GPS_IFD) && parsed.contains(0xA431));
+
+            parsed.apply(&ExifFilter {
+                strip_gps: true,
+                ..Default::default()
+            });
+            assert!(!parsed.contains(GPS_IFD));
+            assert!(parsed.contains(0xA431));
+            parsed.apply(&ExifFilter::privacy());
+            assert!(!parsed.contains(0xA431));
+            assert!(parsed.contains(0x0112) && parsed.contains(0x9003));
+
+            parsed.set_dimensions(400, 300);
+            let reparsed = Exif::parse(&parsed.to_bytes()).unwrap();
+            assert_eq!(reparsed, parsed);
+        }
+        assert!(Exif::parse(b"not exif").is_err());
+    }
+
+    #[test]
+    fn safe_level_keeps_orientation_color_space_and_copyright() {
+        let mut exif = sample(false);
+        let order = exif.order;
+        exif.set_text(COPYRIGHT, "© 2024 Jane Doe");
+        exif.set_text(ARTIST, "Jane Doe");
+        exif.ifd0.child_or_insert(EXIF_IFD).set(Entry {
+            tag: COLOR_SPACE,
+            kind: 3,
+            count: 1,
+            value: order.u16_bytes(1).to_vec(),
+        });
+        let mut parsed = Exif::parse(&exif.to_bytes()).unwrap();
+        parsed.apply(&"safe".parse::<MetadataLevel>().unwrap().filter().unwrap());
+
+        for tag in [ORIENTATION, COPYRIGHT, EXIF_IFD, COLOR_SPACE] {
+            assert!(parsed.contains(tag), "{:#06x}", tag);
+        }
+        for tag in [GPS_IFD, ARTIST, 0x9003, 0xA431, PIXEL_X_DIMENSION] {
+            assert!(!parsed.contains(tag), "{:#06x}", tag);
+        }
+        assert_eq!(MetadataLevel::None.filter(), None);
+        assert_eq!(MetadataLevel::All.filter(), Some(ExifFilter::default()));
+        assert!("some".parse::<MetadataLevel>().is_err());
+    }
+
+    #[test]
+    fn writes_exif_into_outputs() {
+        let image = RgbaImage::new(4, 4);
+        let exif = sample(false).to_bytes();
+        let options = EncodeOptions {
+            dpi: Some(300),
+            ..Default::default()
+        };
+        for (format, encoded) in [
+            (ImageFormat::Jpeg, encode_jpeg(&image, &options).unwrap()),
+            (ImageFormat::Png, encode_png(&image, &options).unwrap()),
+        ] {
+            assert_eq!(read_exif(&encoded), None);
+            let output = write_exif(&encoded, format, &exif).unwrap().unwrap();
+            assert_eq!(read_exif(&output), Some(exif.as_slice()));
+            assert_eq!(image::load_from_memory(&output).unwrap().width(), 4);
+        }
+        assert!(write_exif(&[], ImageFormat::Gif, &exif).unwrap().is_none());
+    }
+
+    #[test]
+    fn strips_metadata_of_unchanged_images() {
+        let dir = tempfile::TempDir::new().expect("Failed to create a temp dir");
+        let input = dir.path().join("exif.jpg");
+        let jpeg = encode_jpeg(&RgbaImage::new(8, 6), &EncodeOptions::default()).unwrap();
+        let jpeg = write_exif(&jpeg, ImageFormat::Jpeg, &sample(false).to_bytes()).unwrap();
+        std::fs::write(&input, jpeg.unwrap()).unwrap();
+        let options = JobOptions::default();
+
+        let copy = dir.path().join("copy.jpg");
+        crate::resize_file(&input, &copy, Some(&8), None, None, &options).unwrap();
+        assert!(read_exif(&std::fs::read(&copy).unwrap()).is_some());
+
+        let mut pipeline = Pipeline::standard();
+        let stage = MetadataStage::new(MetadataLevel::None.filter(), MetadataEdits::default());
+        pipeline.insert_after(ENCODE, stage).unwrap();
+        let stripped = dir.path().join("stripped.jpg");
+        let info =
+            crate::resize_file_with(&input, &stripped, Some(&8), None, None, &options, &pipeline)
+                .unwrap();
+        assert_eq!((info.width, info.height), (8, 6));
+        assert_eq!(read_exif(&std::fs::read(&stripped).unwrap()), None);
+    }
+
+    #[test]
+    fn applies_edits() {
+        let edits = MetadataEdits {
+            comment: Some("Café".to_string()),
+            copyright: Some("© 2024 <Jane & Co>".to_string()),
+            artist: None,
+        };
+        let mut exif = sample(true);
+        exif.apply_edits(&edits);
+        let parsed = Exif::parse(&exif.to_bytes()).unwrap();
+        let copyright = parsed.ifd0.entries.iter().find(|e| e.tag == COPYRIGHT);
+        assert_eq!(copyright.unwrap().value, "© 2024 <Jane & Co>\0".as_bytes());
+        let comment = parsed
+            .ifd0
+            .children
+            .iter()
+            .find(|(tag, _)| *tag == EXIF_IFD);
+        let comment = comment
+            .unwrap()
+            .1
+            .entries
+            .iter()
+            .find(|e| e.tag == USER_COMMENT);
+        assert_eq!(comment.unwrap().value, b"UNICODE\0\0C\0a\0f\0\xe9");
+        assert!(!parsed.contains(ARTIST));
+
+        let xmp = edits.to_xmp();
+        assert!(xmp.contains(">© 2024 &lt;Jane &amp; Co&gt;</rdf:li>"));
+        assert!(xmp.contains("<exif:UserComment>") && !xmp.contains("dc:creator>"));
+        let png = encode_png(&RgbaImage::new(2, 2), &EncodeOptions::default()).unwrap();
+        let png = write_xmp(&png, ImageFormat::Png, &xmp).unwrap().unwrap();
+        assert_eq!(image::load_from_memory(&png).unwrap().width(), 2);
+    }
+}
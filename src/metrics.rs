@@ -0,0 +1,215 @@
+//! Image quality metrics.
+//!
+//! This module computes objective quality metrics between two decoded images, such as an
+//! original and its resized or re-encoded version. The metrics can be used to compare
+//! encoder settings: higher values mean the images are more similar.
+//!
+//! Two metrics are provided:
+//! - PSNR (peak signal-to-noise ratio), in decibels, computed over the RGB channels.
+//! - SSIM (structural similarity index), between -1 and 1, computed over the luma channel.
+
+use crate::resize_image;
+use image::{DynamicImage, GrayImage, RgbaImage};
+
+/// The side length of the square windows used to compute SSIM.
+const SSIM_WINDOW: u32 = 8;
+
+/// Quality metrics comparing a reference image with a distorted version of it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct QualityMetrics {
+    /// Peak signal-to-noise ratio in decibels. Infinite if the images are identical.
+    pub psnr: f64,
+    /// Mean structural similarity index. `1.0` means the images are identical.
+    pub ssim: f64,
+}
+
+/// Compares an original image with a resized or re-encoded version of it.
+///
+/// If the dimensions differ, the original is first resized to the dimensions of the
+/// other image, so that only the loss introduced by encoding and resampling is measured.
+///
+/// # Arguments
+///
+/// * `original` - The reference image.
+/// * `other` - The image to evaluate against the reference.
+///
+/// # Returns
+///
+/// A `Result` containing the `QualityMetrics` of `other` relative to `original`.
+///
+/// # Errors
+///
+/// This function will return an error if the original image cannot be resized to the
+/// dimensions of the other image.
+pub fn compare_images(
+    original: DynamicImage,
+    other: DynamicImage,
+) -> Result<QualityMetrics, Box<dyn std::error::Error>> {
+    let other = other.into_rgba8();
+    let reference = if original.width() == other.width() && original.height() == other.height() {
+        original.into_rgba8()
+    } else {
+        resize_image(original, Some(&other.width()), Some(&other.height()))?
+    };
+
+    Ok(QualityMetrics {
+        psnr: psnr(&reference, &other)?,
+        ssim: ssim(&reference, &other)?,
+    })
+}
+
+/// Computes the peak signal-to-noise ratio between two images of the same dimensions.
+///
+/// The alpha channel is ignored.
+///
+/// # Returns
+///
+/// The PSNR in decibels, or `f64::INFINITY` if the images are identical.
+///
+/// # Errors
+///
+/// This function will return an error if the images have different dimensions.
+pub fn psnr(a: &RgbaImage, b: &RgbaImage) -> Result<f64, Box<dyn std::error::Error>> {
+    check_dimensions(a.dimensions(), b.dimensions())?;
+
+    let mut sum = 0.0;
+    for (pa, pb) in a.pixels().zip(b.pixels()) {
+        for channel in 0..3 {
+            let diff = pa[channel] as f64 - pb[channel] as f64;
+            sum += diff * diff;
+        }
+    }
+
+    let mse = sum / (a.width() as f64 * a.height() as f64 * 3.0);
+    if mse == 0.0 {
+        return Ok(f64::INFINITY);
+    }
+
+    Ok(10.0 * (255.0 * 255.0 / mse).log10())
+}
+
+/// Computes the mean structural similarity index between two images of the same dimensions.
+///
+/// Both images are converted to luma and compared over non-overlapping 8x8 windows (smaller
+/// windows are used at the right and bottom edges).
+///
+/// # Errors
+///
+/// This function will return an error if the images have different dimensions.
+pub fn ssim(a: &RgbaImage, b: &RgbaImage) -> Result<f64, Box<dyn std::error::Error>> {
+    check_dimensions(a.dimensions(), b.dimensions())?;
+
+    let a = DynamicImage::ImageRgba8(a.clone()).into_luma8();
+    let b = DynamicImage::ImageRgba8(b.clone()).into_luma8();
+
+    let mut total = 0.0;
+    let mut windows = 0;
+    for y in (0..a.height()).step_by(SSIM_WINDOW as usize) {
+        for x in (0..a.width()).step_by(SSIM_WINDOW as usize) {
+            let w = SSIM_WINDOW.min(a.width() - x);
+            let h = SSIM_WINDOW.min(a.height() - y);
+            total += window_ssim(&a, &b, x, y, w, h);
+            windows += 1;
+        }
+    }
+
+    Ok(total / windows as f64)
+}
+
+/// Computes the SSIM of a single window of two luma images.
+fn window_ssim(a: &GrayImage, b: &GrayImage, x: u32, y: u32, w: u32, h: u32) -> f64 {
+    const C1: f64 = (0.01 * 255.0) * (0.01 * 255.0);
+    const C2: f64 = (0.03 * 255.0) * (0.03 * 255.0);
+
+    let n = (w * h) as f64;
+    let (mut sum_a, mut sum_b) = (0.0, 0.0);
+    let (mut sum_aa, mut sum_bb, mut sum_ab) = (0.0, 0.0, 0.0);
+
+    for j in y..y + h {
+        for i in x..x + w {
+            let va = a.get_pixel(i, j)[0] as f64;
+            let vb = b.get_pixel(i, j)[0] as f64;
+            sum_a += va;
+            sum_b += vb;
+            sum_aa += va * va;
+            sum_bb += vb * vb;
+            sum_ab += va * vb;
+        }
+    }
+
+    let mean_a = sum_a / n;
+    let mean_b = sum_b / n;
+    let var_a = sum_aa / n - mean_a * mean_a;
+    let var_b = sum_bb / n - mean_b * mean_b;
+    let covariance = sum_ab / n - mean_a * mean_b;
+
+    ((2.0 * mean_a * mean_b + C1) * (2.0 * covariance + C2))
+        / ((mean_a * mean_a + mean_b * mean_b + C1) * (var_a + var_b + C2))
+}
+
+/// Checks that two images have the same dimensions.
+fn check_dimensions(a: (u32, u32), b: (u32, u32)) -> Result<(), Box<dyn std::error::Error>> {
+    if a != b {
+        return Err(format!(
+            "Cannot compare images with different dimensions: {}x{} and {}x{}",
+            a.0, a.1, b.0, b.1
+        )
+        .into());
+    }
+    if a.0 == 0 || a.1 == 0 {
+        return Err("Cannot compare empty images".into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    fn gradient(width: u32, height: u32) -> RgbaImage {
+        RgbaImage::from_fn(width, height, |x, y| {
+            Rgba([(x * 8) as u8, (y * 8) as u8, ((x + y) * 4) as u8, 255])
+        })
+    }
+
+    #[test]
+    fn identical_images() {
+        let image = gradient(16, 16);
+        assert_eq!(psnr(&image, &image).unwrap(), f64::INFINITY);
+        assert!((ssim(&image, &image).unwrap() - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn distorted_image_scores_lower() {
+        let image = gradient(16, 16);
+        let mut noisy = image.clone();
+        for (x, y, pixel) in noisy.enumerate_pixels_mut() {
+            if (x + y) % 2 == 0 {
+                pixel[0] = pixel[0].wrapping_add(40);
+            }
+        }
+
+        let value = psnr(&image, &noisy).unwrap();
+        assert!(value.is_finite() && value > 0.0);
+        assert!(ssim(&image, &noisy).unwrap() < 1.0);
+    }
+
+    #[test]
+    fn different_dimensions_are_rejected() {
+        let result = psnr(&gradient(16, 16), &gradient(8, 16));
+        assert!(result
+            .unwrap_err()
+            .to_string()
+            .contains("different dimensions"));
+    }
+
+    #[test]
+    fn compare_resizes_original() {
+        let original = DynamicImage::ImageRgba8(gradient(32, 32));
+        let other = DynamicImage::ImageRgba8(gradient(16, 16));
+        let metrics = compare_images(original, other).unwrap();
+        assert!(metrics.psnr > 0.0);
+        assert!(metrics.ssim <= 1.0);
+    }
+}
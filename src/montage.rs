@@ -0,0 +1,664 @@
+//! Sprite sheets.
+//!
+//! A montage tiles several images into a single sheet, row by row. Every tile gets a cell
+//! the size of the largest tile, and smaller tiles are centered in their cell. The cells
+//! are separated by a configurable spacing, and the space around the tiles is filled with
+//! a background color.
+//!
+//! The position of each tile in the sheet is recorded as a `Sprite`, so that a map of the
+//! sheet can be written as JSON or as CSS classes using the sheet as a background image.
+//!
+//! `compare` builds on this to show an original image and its resized version together,
+//! side by side or as a split view, to judge the quality of the resize settings.
+//!
+//! `join` concatenates images in a single row or column instead, e.g. to stitch
+//! screenshots. Images of different sizes are aligned along the other axis.
+
+use crate::font;
+use image::{imageops, imageops::FilterType, GenericImageView, Pixel, Rgba, RgbaImage};
+use std::{error::Error, fmt, fmt::Write, str::FromStr};
+
+/// The space between the two images of a side-by-side comparison in pixels.
+const COMPARE_GAP: u32 = 4;
+/// The width of the line dividing the two halves of a split comparison in pixels.
+const SPLIT_LINE: u32 = 2;
+/// The image size per unit of label scale in a comparison.
+const LABEL_SCALE_STEP: u32 = 400;
+
+/// The layout settings of a montage.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MontageOptions {
+    /// The number of tiles per row. If `None`, the sheet is kept roughly square.
+    pub columns: Option<u32>,
+    /// The space between two cells in pixels.
+    pub spacing: u32,
+    /// The color of the space around the tiles.
+    pub background: Rgba<u8>,
+}
+
+impl Default for MontageOptions {
+    fn default() -> Self {
+        Self {
+            columns: None,
+            spacing: 0,
+            background: Rgba([0, 0, 0, 0]),
+        }
+    }
+}
+
+/// The position of a tile in a sheet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sprite {
+    /// The name of the tile, usually the file stem of its input.
+    pub name: String,
+    /// The left edge of the tile in pixels.
+    pub x: u32,
+    /// The top edge of the tile in pixels.
+    pub y: u32,
+    /// The width of the tile in pixels.
+    pub width: u32,
+    /// The height of the tile in pixels.
+    pub height: u32,
+}
+
+/// A sheet of tiles and the position of each tile.
+#[derive(Debug, Clone)]
+pub struct Montage {
+    /// The composed sheet.
+    pub image: RgbaImage,
+    /// The tiles of the sheet, in the order they were given.
+    pub sprites: Vec<Sprite>,
+}
+
+impl Montage {
+    /// Returns a JSON map of the sheet, listing its size and the position of every tile.
+    pub fn json(&self) -> String {
+        let sprites: Vec<String> = self
+            .sprites
+            .iter()
+            .map(|sprite| {
+                format!(
+                    "    {{ \"name\": \"{}\", \"x\": {}, \"y\": {}, \"width\": {}, \"height\": {} }}",
+                    escape_json(&sprite.name),
+                    sprite.x,
+                    sprite.y,
+                    sprite.width,
+                    sprite.height
+                )
+            })
+            .collect();
+        format!(
+            "{{\n  \"width\": {},\n  \"height\": {},\n  \"sprites\": [\n{}\n  ]\n}}\n",
+            self.image.width(),
+            self.image.height(),
+            sprites.join(",\n")
+        )
+    }
+
+    /// Returns a CSS map of the sheet: a `.sprite` class using the sheet as background, and
+    /// one `.sprite-<name>` class per tile setting its size and position.
+    ///
+    /// # Arguments
+    ///
+    /// * `sheet_url` - The URL of the sheet image, relative to the stylesheet.
+    pub fn css(&self, sheet_url: &str) -> String {
+        let mut css = format!(
+            ".sprite {{\n  display: inline-block;\n  background-image: url(\"{}\");\n  background-repeat: no-repeat;\n}}\n",
+            sheet_url.replace('"', "\\\"")
+        );
+        for sprite in &self.sprites {
+            let _ = write!(
+                css,
+                "\n.sprite-{} {{\n  width: {}px;\n  height: {}px;\n  background-position: {} {};\n}}\n",
+                class_name(&sprite.name),
+                sprite.width,
+                sprite.height,
+                css_offset(sprite.x),
+                css_offset(sprite.y)
+            );
+        }
+        css
+    }
+}
+
+/// Tiles images into a sheet.
+///
+/// # Arguments
+///
+/// * `tiles` - The images to tile with their names, in order.
+/// * `options` - The layout of the sheet.
+///
+/// # Returns
+///
+/// The sheet and the position of each tile.
+///
+/// # Errors
+///
+/// This function will return an error if there are no tiles, if `options.columns` is zero,
+/// or if the sheet would be larger than `u32::MAX` pixels in either dimension.
+pub fn compose(
+    tiles: &[(String, RgbaImage)],
+    options: &MontageOptions,
+) -> Result<Montage, Box<dyn Error>> {
+    if tiles.is_empty() {
+        return Err("There are no images to compose".into());
+    }
+    let count = tiles.len() as u32;
+    let columns = match options.columns {
+        Some(0) => return Err("The number of columns must be at least 1".into()),
+        Some(columns) => columns.min(count),
+        None => (count as f64).sqrt().ceil() as u32,
+    };
+    let rows = count.div_ceil(columns);
+
+    let cell_width = tiles
+        .iter()
+        .map(|(_, tile)| tile.width())
+        .max()
+        .unwrap_or(0);
+    let cell_height = tiles
+        .iter()
+        .map(|(_, tile)| tile.height())
+        .max()
+        .unwrap_or(0);
+    let extent = |cells: u32, cell: u32| {
+        cells
+            .checked_mul(cell)
+            .and_then(|size| size.checked_add((cells - 1).checked_mul(options.spacing)?))
+            .ok_or("The sheet would be too large")
+    };
+    let width = extent(columns, cell_width)?;
+    let height = extent(rows, cell_height)?;
+
+    let mut image = RgbaImage::from_pixel(width, height, options.background);
+    let mut sprites = Vec::with_capacity(tiles.len());
+    for (index, (name, tile)) in tiles.iter().enumerate() {
+        let (column, row) = (index as u32 % columns, index as u32 / columns);
+        let x = column * (cell_width + options.spacing) + (cell_width - tile.width()) / 2;
+        let y = row * (cell_height + options.spacing) + (cell_height - tile.height()) / 2;
+        imageops::overlay(&mut image, tile, x as i64, y as i64);
+        sprites.push(Sprite {
+            name: name.clone(),
+            x,
+            y,
+            width: tile.width(),
+            height: tile.height(),
+        });
+    }
+
+    Ok(Montage { image, sprites })
+}
+
+/// The ways to lay out a before/after comparison.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CompareLayout {
+    /// The original on the left and the resized image on the right.
+    #[default]
+    SideBySide,
+    /// The left half of the original next to the right half of the resized image.
+    Split,
+}
+
+impl fmt::Display for CompareLayout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            CompareLayout::SideBySide => "side-by-side",
+            CompareLayout::Split => "split",
+        };
+        f.write_str(name)
+    }
+}
+
+impl FromStr for CompareLayout {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "side-by-side" => Ok(CompareLayout::SideBySide),
+            "split" => Ok(CompareLayout::Split),
+            _ => Err(format!("Unknown comparison layout '{}'", s)),
+        }
+    }
+}
+
+/// Builds a before/after comparison of an image and its resized version.
+///
+/// The resized image is scaled back to the size of the original with nearest-neighbor
+/// sampling, so that the detail lost by the resize and the encoding artifacts stay visible.
+/// Each part is labelled in its top-left corner.
+///
+/// # Arguments
+///
+/// * `original` - The original image.
+/// * `resized` - The resized image, usually decoded from the saved output.
+/// * `layout` - How to lay out the two images.
+///
+/// # Errors
+///
+/// This function will return an error if the comparison would be too large.
+pub fn compare(
+    original: &RgbaImage,
+    resized: &RgbaImage,
+    layout: CompareLayout,
+) -> Result<RgbaImage, Box<dyn Error>> {
+    let (width, height) = original.dimensions();
+    let resized_label = format!("Resized {}x{}", resized.width(), resized.height());
+    let scaled = if resized.dimensions() == (width, height) {
+        resized.clone()
+    } else {
+        imageops::resize(resized, width, height, FilterType::Nearest)
+    };
+    let scale = (width.min(height) / LABEL_SCALE_STEP).max(1);
+
+    match layout {
+        CompareLayout::SideBySide => {
+            let mut image = compose(
+                &[
+                    (String::from("original"), original.clone()),
+                    (String::from("resized"), scaled),
+                ],
+                &MontageOptions {
+                    columns: Some(2),
+                    spacing: COMPARE_GAP,
+                    background: Rgba([255, 255, 255, 255]),
+                },
+            )?
+            .image;
+            draw_label(&mut image, "Original", 0, width, scale);
+            draw_label(
+                &mut image,
+                &resized_label,
+                width + COMPARE_GAP,
+                width,
+                scale,
+            );
+            Ok(image)
+        }
+        CompareLayout::Split => {
+            let half = width / 2;
+            let mut image = original.clone();
+            imageops::replace(
+                &mut image,
+                &*scaled.view(half, 0, width - half, height),
+                half as i64,
+                0,
+            );
+            let line = RgbaImage::from_pixel(SPLIT_LINE.min(width), height, Rgba([255; 4]));
+            imageops::replace(
+                &mut image,
+                &line,
+                half.saturating_sub(SPLIT_LINE / 2) as i64,
+                0,
+            );
+            draw_label(&mut image, "Original", 0, half, scale);
+            draw_label(&mut image, &resized_label, half, width - half, scale);
+            Ok(image)
+        }
+    }
+}
+
+/// Draws white text on a translucent black box at the top of an image, starting at `x`.
+///
+/// The text is shortened to fit in `max_width` pixels, and left out if nothing fits.
+fn draw_label(image: &mut RgbaImage, text: &str, x: u32, max_width: u32, scale: u32) {
+    let padding = 3 * scale;
+    let text = font::truncate(text, max_width.saturating_sub(2 * padding), scale);
+    if text.is_empty() {
+        return;
+    }
+    let width = font::text_width(&text, scale) + 2 * padding;
+    let height = font::GLYPH_HEIGHT * scale + 2 * padding;
+    let shade = Rgba([0, 0, 0, 160]);
+    for py in 0..height.min(image.height()) {
+        for px in x..(x + width).min(image.width()) {
+            image.get_pixel_mut(px, py).blend(&shade);
+        }
+    }
+    font::draw_text(
+        image,
+        &text,
+        (x + padding) as i64,
+        padding as i64,
+        scale,
+        Rgba([255, 255, 255, 255]),
+    );
+}
+
+/// The axis along which `join` concatenates images.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Direction {
+    /// Left to right, in a single row.
+    #[default]
+    Horizontal,
+    /// Top to bottom, in a single column.
+    Vertical,
+}
+
+impl fmt::Display for Direction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Direction::Horizontal => "horizontal",
+            Direction::Vertical => "vertical",
+        };
+        f.write_str(name)
+    }
+}
+
+impl FromStr for Direction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "horizontal" => Ok(Direction::Horizontal),
+            "vertical" => Ok(Direction::Vertical),
+            _ => Err(format!("Unknown direction '{}'", s)),
+        }
+    }
+}
+
+/// Where `join` places an image that is smaller than the others across the direction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Align {
+    /// At the top of a row, or at the left of a column.
+    Start,
+    /// In the middle.
+    #[default]
+    Center,
+    /// At the bottom of a row, or at the right of a column.
+    End,
+}
+
+impl fmt::Display for Align {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Align::Start => "start",
+            Align::Center => "center",
+            Align::End => "end",
+        };
+        f.write_str(name)
+    }
+}
+
+impl FromStr for Align {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "start" => Ok(Align::Start),
+            "center" => Ok(Align::Center),
+            "end" => Ok(Align::End),
+            _ => Err(format!("Unknown alignment '{}'", s)),
+        }
+    }
+}
+
+/// The layout settings of `join`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JoinOptions {
+    /// The axis along which the images are concatenated.
+    pub direction: Direction,
+    /// Where smaller images are placed across the direction.
+    pub align: Align,
+    /// The space between two images in pixels.
+    pub gap: u32,
+    /// The color of the gaps and of the space around smaller images.
+    pub background: Rgba<u8>,
+}
+
+impl Default for JoinOptions {
+    fn default() -> Self {
+        Self {
+            direction: Direction::Horizontal,
+            align: Align::Center,
+            gap: 0,
+            background: Rgba([0, 0, 0, 0]),
+        }
+    }
+}
+
+/// Concatenates images in a single row or column.
+///
+/// The images are drawn at their size: resize them to a common height (for a row) or
+/// width (for a column) first to get straight edges.
+///
+/// # Arguments
+///
+/// * `images` - The images to concatenate, in order.
+/// * `options` - The layout of the result.
+///
+/// # Errors
+///
+/// This function will return an error if there are no images, or if the result would be
+/// larger than `u32::MAX` pixels in either dimension.
+pub fn join(images: &[RgbaImage], options: &JoinOptions) -> Result<RgbaImage, Box<dyn Error>> {
+    if images.is_empty() {
+        return Err("There are no images to join".into());
+    }
+    // Work with (along, across) sizes, so that both directions share the same code
+    let size = |image: &RgbaImage| match options.direction {
+        Direction::Horizontal => (image.width(), image.height()),
+        Direction::Vertical => (image.height(), image.width()),
+    };
+    let across = images.iter().map(|image| size(image).1).max().unwrap_or(0);
+    let along = images
+        .iter()
+        .try_fold(0u32, |total, image| total.checked_add(size(image).0))
+        .and_then(|total| total.checked_add((images.len() as u32 - 1).checked_mul(options.gap)?))
+        .ok_or("The joined image would be too large")?;
+
+    let (width, height) = match options.direction {
+        Direction::Horizontal => (along, across),
+        Direction::Vertical => (across, along),
+    };
+    let mut canvas = RgbaImage::from_pixel(width, height, options.background);
+    let mut offset = 0;
+    for image in images {
+        let (length, breadth) = size(image);
+        let shift = match options.align {
+            Align::Start => 0,
+            Align::Center => (across - breadth) / 2,
+            Align::End => across - breadth,
+        };
+        let (x, y) = match options.direction {
+            Direction::Horizontal => (offset, shift),
+            Direction::Vertical => (shift, offset),
+        };
+        imageops::overlay(&mut canvas, image, x as i64, y as i64);
+        offset += length + options.gap;
+    }
+    Ok(canvas)
+}
+
+/// Parses a color given as `#rrggbb`, `#rrggbbaa` or `transparent`.
+///
+/// # Errors
+///
+/// This function will return an error message if the color is not in one of these forms.
+pub fn parse_color(s: &str) -> Result<Rgba<u8>, String> {
+    if s.eq_ignore_ascii_case("transparent") {
+        return Ok(Rgba([0, 0, 0, 0]));
+    }
+    let invalid = || format!("Invalid color '{}', expected #rrggbb or #rrggbbaa", s);
+    let hex = s.strip_prefix('#').ok_or_else(invalid)?;
+    if !(hex.len() == 6 || hex.len() == 8) || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(invalid());
+    }
+    let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).map_err(|_| invalid());
+    let alpha = if hex.len() == 8 { channel(6)? } else { 255 };
+    Ok(Rgba([channel(0)?, channel(2)?, channel(4)?, alpha]))
+}
+
+/// Escapes a string for use inside a JSON string literal.
+pub(crate) fn escape_json(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(escaped, "\\u{:04x}", c as u32);
+            }
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Turns a tile name into a CSS class name, replacing unsupported characters with `-`.
+fn class_name(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '-'
+            }
+        })
+        .collect()
+}
+
+/// Formats a `background-position` offset, which is negative to move the tile into view.
+fn css_offset(offset: u32) -> String {
+    if offset == 0 {
+        "0".to_string()
+    } else {
+        format!("-{}px", offset)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tile(name: &str, width: u32, height: u32, color: Rgba<u8>) -> (String, RgbaImage) {
+        (
+            name.to_string(),
+            RgbaImage::from_pixel(width, height, color),
+        )
+    }
+
+    #[test]
+    fn tiles_images_in_rows() {
+        let red = Rgba([255, 0, 0, 255]);
+        let tiles = [
+            tile("a", 10, 10, red),
+            tile("b", 10, 10, red),
+            tile("c", 6, 4, red),
+        ];
+        let options = MontageOptions {
+            columns: Some(2),
+            spacing: 2,
+            background: Rgba([255, 255, 255, 255]),
+        };
+        let montage = compose(&tiles, &options).unwrap();
+
+        // Two rows of two 10x10 cells, with 2 pixels between them
+        assert_eq!(montage.image.dimensions(), (22, 22));
+        assert_eq!(montage.sprites[1].x, 12);
+        // The smaller tile is centered in its cell
+        let c = &montage.sprites[2];
+        assert_eq!((c.x, c.y, c.width, c.height), (2, 15, 6, 4));
+        assert_eq!(montage.image.get_pixel(2, 15), &red);
+        assert_eq!(montage.image.get_pixel(1, 15), &options.background);
+        assert_eq!(montage.image.get_pixel(11, 0), &options.background);
+
+        // Without columns, the sheet is kept square
+        let montage = compose(&tiles, &MontageOptions::default()).unwrap();
+        assert_eq!(montage.image.dimensions(), (20, 20));
+
+        assert!(compose(&[], &options).is_err());
+    }
+
+    #[test]
+    fn writes_sprite_maps() {
+        let tiles = [
+            tile("icon", 4, 4, Rgba([0, 0, 0, 255])),
+            tile("my \"icon\"", 4, 4, Rgba([0, 0, 0, 255])),
+        ];
+        let montage = compose(&tiles, &MontageOptions::default()).unwrap();
+
+        let json = montage.json();
+        assert!(json.contains("\"width\": 8,"));
+        assert!(json.contains(
+            "{ \"name\": \"my \\\"icon\\\"\", \"x\": 4, \"y\": 0, \"width\": 4, \"height\": 4 }"
+        ));
+
+        let css = montage.css("sheet.png");
+        assert!(css.contains("url(\"sheet.png\")"));
+        assert!(css.contains(
+            ".sprite-icon {\n  width: 4px;\n  height: 4px;\n  background-position: 0 0;"
+        ));
+        assert!(css.contains(".sprite-my--icon- {"));
+        assert!(css.contains("background-position: -4px 0;"));
+    }
+
+    #[test]
+    fn compares_before_and_after() {
+        let original = RgbaImage::from_fn(40, 20, |x, _| Rgba([x as u8 * 6, 0, 0, 255]));
+        let resized = imageops::resize(&original, 10, 5, FilterType::Triangle);
+
+        let image = compare(&original, &resized, CompareLayout::SideBySide).unwrap();
+        assert_eq!(image.dimensions(), (2 * 40 + COMPARE_GAP, 20));
+        // Below the labels, the right part is the resized image scaled back up
+        assert_eq!(image.get_pixel(39, 19), original.get_pixel(39, 19));
+        assert_eq!(image.get_pixel(44, 19), resized.get_pixel(0, 4));
+
+        let image = compare(&original, &resized, CompareLayout::Split).unwrap();
+        assert_eq!(image.dimensions(), (40, 20));
+        assert_eq!(image.get_pixel(5, 19), original.get_pixel(5, 19));
+        assert_eq!(image.get_pixel(20, 19), &Rgba([255; 4]));
+        assert_eq!(image.get_pixel(39, 19), resized.get_pixel(9, 4));
+
+        assert_eq!("split".parse::<CompareLayout>(), Ok(CompareLayout::Split));
+        assert!("overlay".parse::<CompareLayout>().is_err());
+    }
+
+    #[test]
+    fn joins_images_in_a_row_or_column() {
+        let red = Rgba([255, 0, 0, 255]);
+        let white = Rgba([255, 255, 255, 255]);
+        let images = [
+            RgbaImage::from_pixel(10, 8, red),
+            RgbaImage::from_pixel(6, 4, red),
+        ];
+        let options = JoinOptions {
+            gap: 2,
+            background: white,
+            ..Default::default()
+        };
+
+        let row = join(&images, &options).unwrap();
+        assert_eq!(row.dimensions(), (18, 8));
+        assert_eq!(row.get_pixel(11, 0), &white);
+        // The smaller image is centered vertically
+        assert_eq!(row.get_pixel(12, 1), &white);
+        assert_eq!(row.get_pixel(12, 2), &red);
+
+        let column = join(
+            &images,
+            &JoinOptions {
+                direction: Direction::Vertical,
+                align: Align::End,
+                ..options.clone()
+            },
+        )
+        .unwrap();
+        assert_eq!(column.dimensions(), (10, 14));
+        assert_eq!(column.get_pixel(3, 10), &white);
+        assert_eq!(column.get_pixel(4, 10), &red);
+
+        assert!(join(&[], &options).is_err());
+        assert_eq!("Vertical".parse::<Direction>(), Ok(Direction::Vertical));
+        assert_eq!(Align::End.to_string().parse::<Align>(), Ok(Align::End));
+    }
+
+    #[test]
+    fn parses_colors() {
+        assert_eq!(parse_color("#ff8000"), Ok(Rgba([255, 128, 0, 255])));
+        assert_eq!(parse_color("#FF800080"), Ok(Rgba([255, 128, 0, 128])));
+        assert_eq!(parse_color("transparent"), Ok(Rgba([0, 0, 0, 0])));
+        assert!(parse_color("ff8000").is_err());
+        assert!(parse_color("#ff80").is_err());
+        assert!(parse_color("#gg8000").is_err());
+    }
+}
@@ -0,0 +1,275 @@
+//! Options controlling how a resize job is executed.
+//!
+//! `JobOptions` groups the settings that are not part of the requested geometry, such as
+//! resource limits, the choice of resize path, the CPU resources used by the resizer and
+//! the encoder settings.
+
+use crate::{
+    cache::Cache,
+    cancel::CancellationToken,
+    codec::{DecoderRegistry, EncoderRegistry},
+    encode::EncodeOptions,
+    limits::Limits,
+    overwrite::OverwritePolicy,
+    policy::InputPolicy,
+    safety::SafetyLimits,
+    storage::SharedStorage,
+    xattrs::XattrPolicy,
+};
+use fast_image_resize as fr;
+use std::{fmt, str::FromStr};
+
+pub use image_resizer_core::Rounding;
+
+/// Settings applied to every image processed by a job.
+#[derive(Debug, Clone, Default)]
+pub struct JobOptions {
+    /// Resource limits checked before and during decoding.
+    pub limits: Limits,
+    /// The input formats accepted, checked before any decoder runs.
+    pub input_policy: InputPolicy,
+    /// Header dimension limits and the decode timeout, protecting against hostile inputs.
+    pub safety: SafetyLimits,
+    /// Stream PNG and JPEG rows from the decoder to the encoder when the job allows it, and
+    /// otherwise always use the tiled low-memory resize path, regardless of the image size.
+    pub low_memory: bool,
+    /// CPU settings for the resizer, and how output sizes are computed.
+    pub resize: ResizeOptions,
+    /// Custom decoders, offered every input before the decoders of the `image` crate.
+    pub decoders: DecoderRegistry,
+    /// Settings for the output encoder.
+    pub encode: EncodeOptions,
+    /// The encoders of the output formats. Defaults to the built-in encoders.
+    pub encoders: EncoderRegistry,
+    /// Decode and re-encode images even when the output would have the same dimensions and
+    /// format as the source, instead of copying them through (see `copy_through`).
+    pub force_reencode: bool,
+    /// What to do when an output file already exists.
+    pub overwrite: OverwritePolicy,
+    /// Write output files in place instead of through a temporary file that is renamed on
+    /// success (see `save_image_with`). Only needed on filesystems without atomic renames.
+    pub direct_write: bool,
+    /// Which extended attributes of the input are copied to the output.
+    pub xattrs: XattrPolicy,
+    /// Name outputs after a hash of their contents, as `<name>.<hash>.<ext>` (see
+    /// `manifest::hashed_path`). Since a hashed name identifies its contents, an existing
+    /// output with the same name is replaced regardless of the overwrite policy.
+    pub hash_names: bool,
+    /// Read every output back after writing it, decoding it and checking its dimensions,
+    /// and report its checksum in `ImageInfo::verification` (see `verify::verify_output`).
+    /// Outputs written to an `OutputSink` are not verified.
+    pub verify: bool,
+    /// Where encoded outputs are cached, if anywhere (see `cache::Cache`).
+    pub cache: Option<Cache>,
+    /// Where inputs are read from and outputs are written to. Defaults to the local
+    /// filesystem.
+    pub storage: SharedStorage,
+    /// Token checked between pipeline stages. Once cancelled, the pipeline returns
+    /// `ResizeError::Cancelled`.
+    pub cancel: CancellationToken,
+}
+
+impl JobOptions {
+    /// Adjusts the options so that identical inputs always produce byte-identical outputs,
+    /// on any machine.
+    ///
+    /// The encoders never write timestamps, and metadata is only written by custom pipeline
+    /// stages. Images are always re-encoded, since an image copied through would keep its
+    /// metadata, timestamps included. The remaining sources of variation are the resizer,
+    /// whose results depend on the SIMD instruction set and on how the image is split across
+    /// threads, and the extended attributes of the inputs. The resizer is set to run on a
+    /// single thread with scalar code, and extended attributes are not copied.
+    pub fn make_deterministic(&mut self) {
+        self.resize = ResizeOptions {
+            threads: 1,
+            cpu_extension: CpuExtension::None,
+            ..self.resize
+        };
+        self.force_reencode = true;
+        self.xattrs = XattrPolicy::default();
+    }
+}
+
+/// Controls the CPU resources used to resize a single image, and how its size is computed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ResizeOptions {
+    /// The number of threads a single resize is split across. `1` resizes on the calling
+    /// thread and `0` uses one thread per available CPU core.
+    pub threads: usize,
+    /// The SIMD instruction set used by the resizer.
+    pub cpu_extension: CpuExtension,
+    /// How the side computed from the aspect ratio is rounded to whole pixels.
+    pub rounding: Rounding,
+    /// Both sides of the output are rounded to a multiple of this, with `rounding`, e.g. `2`
+    /// for video encoders requiring even dimensions. `1` keeps the sizes as they are.
+    pub multiple_of: u32,
+    /// The maximum number of pixels of the output. Larger outputs are scaled down, keeping
+    /// their aspect ratio; smaller ones are never scaled up. Unlike `Limits::max_pixels`,
+    /// which rejects large sources, this shrinks the output to fit.
+    pub max_output_pixels: Option<u64>,
+}
+
+impl Default for ResizeOptions {
+    fn default() -> Self {
+        Self {
+            threads: 1,
+            cpu_extension: CpuExtension::Auto,
+            rounding: Rounding::default(),
+            multiple_of: 1,
+            max_output_pixels: None,
+        }
+    }
+}
+
+impl ResizeOptions {
+    /// Returns the effective number of threads, resolving `0` to the available parallelism.
+    pub fn thread_count(&self) -> usize {
+        match self.threads {
+            0 => std::thread::available_parallelism()
+                .map(|n| n.get())
+                .unwrap_or(1),
+            n => n,
+        }
+    }
+
+    /// Creates a `fast_image_resize` resizer using the selected CPU extension.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the selected CPU extension is not available on
+    /// this architecture or not supported by the running CPU.
+    pub(crate) fn resizer(&self) -> Result<fr::Resizer, Box<dyn std::error::Error>> {
+        let mut resizer = fr::Resizer::new();
+        if let Some(extension) = self.cpu_extension.to_fr()? {
+            if !extension.is_supported() {
+                return Err(format!(
+                    "The CPU extension {} is not supported by this CPU",
+                    self.cpu_extension
+                )
+                .into());
+            }
+            // SAFETY: the extension was checked to be supported by the running CPU.
+            unsafe { resizer.set_cpu_extensions(extension) };
+        }
+        Ok(resizer)
+    }
+}
+
+/// The SIMD instruction set used by the resizer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CpuExtension {
+    /// Use the best extension supported by the running CPU.
+    #[default]
+    Auto,
+    /// Use plain scalar code.
+    None,
+    /// SSE4.1 (x86_64 only).
+    Sse4_1,
+    /// AVX2 (x86_64 only).
+    Avx2,
+    /// NEON (aarch64 only).
+    Neon,
+}
+
+impl CpuExtension {
+    /// Converts the extension to the `fast_image_resize` equivalent.
+    ///
+    /// Returns `None` for `Auto`, and an error if the extension does not exist on the
+    /// target architecture.
+    fn to_fr(self) -> Result<Option<fr::CpuExtensions>, Box<dyn std::error::Error>> {
+        match self {
+            CpuExtension::Auto => Ok(None),
+            CpuExtension::None => Ok(Some(fr::CpuExtensions::None)),
+            #[cfg(target_arch = "x86_64")]
+            CpuExtension::Sse4_1 => Ok(Some(fr::CpuExtensions::Sse4_1)),
+            #[cfg(target_arch = "x86_64")]
+            CpuExtension::Avx2 => Ok(Some(fr::CpuExtensions::Avx2)),
+            #[cfg(target_arch = "aarch64")]
+            CpuExtension::Neon => Ok(Some(fr::CpuExtensions::Neon)),
+            #[allow(unreachable_patterns)]
+            _ => Err(format!(
+                "The CPU extension {} is not available on this architecture",
+                self
+            )
+            .into()),
+        }
+    }
+}
+
+impl fmt::Display for CpuExtension {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            CpuExtension::Auto => "auto",
+            CpuExtension::None => "none",
+            CpuExtension::Sse4_1 => "sse4.1",
+            CpuExtension::Avx2 => "avx2",
+            CpuExtension::Neon => "neon",
+        };
+        f.write_str(name)
+    }
+}
+
+impl FromStr for CpuExtension {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "auto" => Ok(CpuExtension::Auto),
+            "none" => Ok(CpuExtension::None),
+            "sse4.1" | "sse4_1" | "sse4" => Ok(CpuExtension::Sse4_1),
+            "avx2" => Ok(CpuExtension::Avx2),
+            "neon" => Ok(CpuExtension::Neon),
+            _ => Err(format!("Unknown CPU extension '{}'", s)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cpu_extension_round_trip() {
+        for extension in [
+            CpuExtension::Auto,
+            CpuExtension::None,
+            CpuExtension::Sse4_1,
+            CpuExtension::Avx2,
+            CpuExtension::Neon,
+        ] {
+            assert_eq!(extension.to_string().parse::<CpuExtension>(), Ok(extension));
+        }
+        assert!("mmx".parse::<CpuExtension>().is_err());
+    }
+
+    #[test]
+    fn scalar_resizer_is_always_available() {
+        let options = ResizeOptions {
+            cpu_extension: CpuExtension::None,
+            ..Default::default()
+        };
+        assert!(options.resizer().is_ok());
+    }
+
+    #[test]
+    fn thread_count_resolves_zero() {
+        let options = ResizeOptions {
+            threads: 0,
+            ..Default::default()
+        };
+        assert!(options.thread_count() >= 1);
+        assert_eq!(ResizeOptions::default().thread_count(), 1);
+    }
+
+    #[test]
+    fn rounding_modes() {
+        // 2.5, 2.4 and 0.1 pixels
+        let cases = [(5, 2), (12, 5), (1, 10)];
+        let results = |rounding: Rounding| cases.map(|(n, d)| rounding.divide(n, d));
+        assert_eq!(results(Rounding::Round), [3, 2, 1]);
+        assert_eq!(results(Rounding::Floor), [2, 2, 1]);
+        assert_eq!(results(Rounding::Ceil), [3, 3, 1]);
+        assert_eq!(Rounding::Ceil.divide(3000, 10), 300);
+        assert_eq!("Floor".parse(), Ok(Rounding::Floor));
+    }
+}
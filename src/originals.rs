@@ -0,0 +1,99 @@
+//! Removal of originals.
+//!
+//! Workflows converting images, such as large PNG screenshots to small JPEGs, often have no
+//! use for the originals afterwards. They are only removed once every output has been
+//! written and read back with `verify::verify_output`: an output that is missing, truncated
+//! or of another size keeps its original. By default originals are moved to the trash of
+//! the operating system, so that they can still be restored.
+
+use crate::{storage::Storage, verify::verify_output, ImageInfo};
+use std::{error::Error, fs, path::Path};
+
+/// How originals are removed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Removal {
+    /// Move them to the trash of the operating system.
+    #[default]
+    Trash,
+    /// Delete them permanently.
+    Unlink,
+}
+
+/// Removes the original of some outputs once they are verified with `verify_output`.
+///
+/// # Arguments
+///
+/// * `input` - The path of the original.
+/// * `outputs` - The outputs produced from the original.
+/// * `removal` - Whether the original is moved to the trash or deleted.
+/// * `storage` - The storage the outputs were written to. The original is removed from the
+///   file system.
+///
+/// # Errors
+///
+/// This function will return an error, keeping the original, if there are no outputs, an
+/// output is the original itself or fails the verification. It will also return an error
+/// if the original cannot be removed.
+pub fn remove_original(
+    input: &Path,
+    outputs: &[&ImageInfo],
+    removal: Removal,
+    storage: &dyn Storage,
+) -> Result<(), Box<dyn Error>> {
+    if outputs.is_empty() {
+        return Err(format!("Keeping {:?}: nothing was written from it", input).into());
+    }
+    for output in outputs {
+        let same_file = input == output.path
+            || match (input.canonicalize(), output.path.canonicalize()) {
+                (Ok(a), Ok(b)) => a == b,
+                _ => false,
+            };
+        if same_file {
+            return Err(format!("Keeping {:?}: it is its own output", input).into());
+        }
+        verify_output(storage, output).map_err(|e| format!("Keeping {:?}: {}", input, e))?;
+    }
+    match removal {
+        Removal::Trash => trash::delete(input)
+            .map_err(|e| format!("Failed to move {:?} to the trash: {}", input, e).into()),
+        Removal::Unlink => fs::remove_file(input)
+            .map_err(|e| format!("Failed to delete {:?}: {}", input, e).into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{options::JobOptions, resize_file, storage::FileSystem};
+    use image::RgbImage;
+    use tempfile::TempDir;
+
+    #[test]
+    fn removes_verified_originals() {
+        let dir = TempDir::new().expect("Failed to create a temp dir");
+        let input = dir.path().join("input.png");
+        RgbImage::new(40, 20).save(&input).unwrap();
+        let output = dir.path().join("output.jpg");
+        let options = JobOptions::default();
+        let info = resize_file(&input, &output, Some(&20), None, None, &options).unwrap();
+
+        let own = ImageInfo {
+            path: input.clone(),
+            ..info.clone()
+        };
+        assert!(remove_original(&input, &[&own], Removal::Unlink, &FileSystem).is_err());
+        assert!(remove_original(&input, &[], Removal::Unlink, &FileSystem).is_err());
+        assert!(input.exists());
+        let wrong = ImageInfo {
+            width: 21,
+            ..info.clone()
+        };
+        assert!(remove_original(&input, &[&wrong], Removal::Unlink, &FileSystem).is_err());
+        assert!(input.exists());
+
+        remove_original(&input, &[&info], Removal::Unlink, &FileSystem).unwrap();
+        assert!(!input.exists());
+        assert!(output.exists());
+    }
+}
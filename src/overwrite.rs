@@ -0,0 +1,204 @@
+//! Handling of existing output files.
+//!
+//! By default the user is asked before an existing file is replaced. Unattended runs can
+//! choose to always replace existing files, never replace them, or write to a numbered path
+//! next to them (`output_1.jpg`, `output_2.jpg`, ...) instead. When stdin is not a
+//! terminal there is nobody to answer the prompt, so `OverwritePolicy::Prompt` fails with a
+//! clear error rather than waiting for input forever.
+
+use crate::storage::{FileSystem, Storage};
+use std::{
+    fmt,
+    io::{IsTerminal, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+/// Decides what happens when an output file already exists.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OverwritePolicy {
+    /// Ask on the terminal before replacing the file.
+    #[default]
+    Prompt,
+    /// Replace the file without asking.
+    Always,
+    /// Never replace the file; the image fails instead.
+    Never,
+    /// Keep the file and write to the first free numbered path, such as `output_1.jpg`.
+    Rename,
+}
+
+impl OverwritePolicy {
+    /// Determines the path the output file should actually be written to.
+    ///
+    /// # Returns
+    ///
+    /// `path` itself if it is free or may be replaced, or the first free numbered path next to
+    /// it with `OverwritePolicy::Rename`.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if:
+    /// - The file exists and the policy is `Never`.
+    /// - The file exists, the policy is `Prompt` and the user declines, or stdin is not a
+    ///   terminal.
+    /// - There's an error while checking if the path exists.
+    pub fn resolve(&self, path: &Path) -> Result<PathBuf, Box<dyn std::error::Error>> {
+        self.resolve_in(&FileSystem, path)
+    }
+
+    /// Determines the path the output file should actually be written to, like `resolve`,
+    /// in the given storage backend.
+    ///
+    /// # Errors
+    ///
+    /// This function will return the same errors as `resolve`.
+    pub fn resolve_in(
+        &self,
+        storage: &dyn Storage,
+        path: &Path,
+    ) -> Result<PathBuf, Box<dyn std::error::Error>> {
+        if !exists(storage, path)? {
+            return Ok(path.to_path_buf());
+        }
+        match self {
+            OverwritePolicy::Always => Ok(path.to_path_buf()),
+            OverwritePolicy::Never => Err(format!("{:?} already exists.", path).into()),
+            OverwritePolicy::Prompt => confirm_replace(path).map(|_| path.to_path_buf()),
+            OverwritePolicy::Rename => numbered_path(storage, path),
+        }
+    }
+}
+
+/// Returns `true` if something exists at `path`.
+fn exists(storage: &dyn Storage, path: &Path) -> Result<bool, Box<dyn std::error::Error>> {
+    storage
+        .exists(path)
+        .map_err(|e| format!("Error checking path {:?}: {}", path, e).into())
+}
+
+/// Returns the first path of the form `<stem>_<n>.<extension>` that does not exist yet.
+fn numbered_path(
+    storage: &dyn Storage,
+    path: &Path,
+) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    for n in 1u64.. {
+        let candidate = numbered(path, n);
+        if !exists(storage, &candidate)? {
+            return Ok(candidate);
+        }
+    }
+    unreachable!("an unbounded range always yields a free path or an error")
+}
+
+/// Returns `path` with `_<n>` appended to its file stem.
+pub(crate) fn numbered(path: &Path, n: u64) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match path.extension() {
+        Some(extension) => format!("{}_{}.{}", stem, n, extension.to_string_lossy()),
+        None => format!("{}_{}", stem, n),
+    };
+    path.with_file_name(name)
+}
+
+/// Asks the user whether to replace the existing file at `path`.
+fn confirm_replace(path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let stdin = std::io::stdin();
+    if !stdin.is_terminal() {
+        return Err(format!(
+            "{:?} already exists and stdin is not a terminal to confirm replacing it \
+             (use --yes to replace existing files)",
+            path
+        )
+        .into());
+    }
+
+    print!(
+        "\n{:?} already exists. Do you want to replace it? (y/n): ",
+        path
+    );
+    std::io::stdout().flush()?;
+
+    let mut input = String::new();
+    stdin.read_line(&mut input)?;
+
+    if input.trim().to_lowercase() != "y" {
+        return Err(format!("{:?} already exists. Operation cancelled!", path).into());
+    }
+    Ok(())
+}
+
+impl fmt::Display for OverwritePolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            OverwritePolicy::Prompt => "prompt",
+            OverwritePolicy::Always => "always",
+            OverwritePolicy::Never => "never",
+            OverwritePolicy::Rename => "rename",
+        };
+        f.write_str(name)
+    }
+}
+
+impl FromStr for OverwritePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "prompt" => Ok(OverwritePolicy::Prompt),
+            "always" => Ok(OverwritePolicy::Always),
+            "never" => Ok(OverwritePolicy::Never),
+            "rename" => Ok(OverwritePolicy::Rename),
+            _ => Err(format!("Unknown overwrite policy '{}'", s)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn policy_round_trip() {
+        for policy in [
+            OverwritePolicy::Prompt,
+            OverwritePolicy::Always,
+            OverwritePolicy::Never,
+            OverwritePolicy::Rename,
+        ] {
+            assert_eq!(policy.to_string().parse::<OverwritePolicy>(), Ok(policy));
+        }
+        assert!("sometimes".parse::<OverwritePolicy>().is_err());
+    }
+
+    #[test]
+    fn existing_files() {
+        let dir = tempfile::TempDir::new().expect("Failed to create a temp dir");
+        let path = dir.path().join("output.png");
+        for policy in [
+            OverwritePolicy::Prompt,
+            OverwritePolicy::Always,
+            OverwritePolicy::Never,
+            OverwritePolicy::Rename,
+        ] {
+            assert_eq!(policy.resolve(&path).unwrap(), path);
+        }
+
+        std::fs::write(&path, b"existing").unwrap();
+        assert_eq!(OverwritePolicy::Always.resolve(&path).unwrap(), path);
+        assert!(OverwritePolicy::Never.resolve(&path).is_err());
+    }
+
+    #[test]
+    fn rename_picks_first_free_number() {
+        let dir = tempfile::TempDir::new().expect("Failed to create a temp dir");
+        let path = dir.path().join("output.jpg");
+        std::fs::write(&path, b"existing").unwrap();
+        std::fs::write(dir.path().join("output_1.jpg"), b"existing").unwrap();
+
+        assert_eq!(
+            OverwritePolicy::Rename.resolve(&path).unwrap(),
+            dir.path().join("output_2.jpg")
+        );
+    }
+}
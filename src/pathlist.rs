@@ -0,0 +1,138 @@
+//! Lists of input paths.
+//!
+//! Tools such as `find` and `fd` print the files they find one per line, or separated by
+//! NUL bytes with `-print0` and `--print0`. Reading such a list from a file or stdin instead
+//! of the command line avoids the argument length limit of the system, and lets the batch
+//! start while the list is still being produced. Empty entries are skipped, as is the
+//! carriage return ending the lines of lists written on Windows.
+
+use std::{
+    error::Error,
+    fs::File,
+    io::{self, BufRead, BufReader},
+    path::{Path, PathBuf},
+};
+
+/// An iterator over the paths of a list, read as they are needed.
+pub struct PathList<R> {
+    reader: R,
+    delimiter: u8,
+    done: bool,
+}
+
+impl<R: BufRead> PathList<R> {
+    /// Creates an iterator over the paths read from `reader`.
+    ///
+    /// # Arguments
+    ///
+    /// * `reader` - The reader of the list.
+    /// * `null` - Whether the paths are separated by NUL bytes instead of newlines.
+    pub fn new(reader: R, null: bool) -> Self {
+        PathList {
+            reader,
+            delimiter: if null { b'\0' } else { b'\n' },
+            done: false,
+        }
+    }
+}
+
+impl PathList<Box<dyn BufRead>> {
+    /// Opens a list of paths, reading stdin if `path` is `-`.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the list, or `-` for stdin.
+    /// * `null` - Whether the paths are separated by NUL bytes instead of newlines.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the file cannot be opened.
+    pub fn open(path: &Path, null: bool) -> Result<Self, Box<dyn Error>> {
+        let reader: Box<dyn BufRead> = if path == Path::new("-") {
+            Box::new(io::stdin().lock())
+        } else {
+            let file = File::open(path)
+                .map_err(|e| format!("Failed to open the list of files {:?}: {}", path, e))?;
+            Box::new(BufReader::new(file))
+        };
+        Ok(PathList::new(reader, null))
+    }
+}
+
+impl<R: BufRead> Iterator for PathList<R> {
+    type Item = io::Result<PathBuf>;
+
+    /// Reads the next path. A read error ends the list.
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut entry = Vec::new();
+        while !self.done {
+            entry.clear();
+            match self.reader.read_until(self.delimiter, &mut entry) {
+                Ok(0) => self.done = true,
+                Ok(_) => {
+                    if entry.last() == Some(&self.delimiter) {
+                        entry.pop();
+                    }
+                    if self.delimiter == b'\n' && entry.last() == Some(&b'\r') {
+                        entry.pop();
+                    }
+                    if !entry.is_empty() {
+                        return Some(path_from_bytes(entry));
+                    }
+                }
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e));
+                }
+            }
+        }
+        None
+    }
+}
+
+/// Converts the bytes of an entry into a path. Any bytes are valid on Unix.
+#[cfg(unix)]
+fn path_from_bytes(bytes: Vec<u8>) -> io::Result<PathBuf> {
+    use std::{ffi::OsString, os::unix::ffi::OsStringExt};
+    Ok(PathBuf::from(OsString::from_vec(bytes)))
+}
+
+/// Converts the bytes of an entry into a path, which must be valid UTF-8.
+#[cfg(not(unix))]
+fn path_from_bytes(bytes: Vec<u8>) -> io::Result<PathBuf> {
+    String::from_utf8(bytes)
+        .map(PathBuf::from)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(list: &[u8], null: bool) -> Vec<PathBuf> {
+        PathList::new(list, null)
+            .collect::<io::Result<_>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn reads_lines() {
+        assert_eq!(
+            read(b"a.jpg\r\n\nphotos/b c.png\n  d.png", false),
+            [
+                PathBuf::from("a.jpg"),
+                PathBuf::from("photos/b c.png"),
+                PathBuf::from("  d.png")
+            ]
+        );
+        assert!(read(b"", false).is_empty());
+    }
+
+    #[test]
+    fn reads_nul_separated_paths() {
+        assert_eq!(
+            read(b"a\nb.jpg\0\0c.png\0", true),
+            [PathBuf::from("a\nb.jpg"), PathBuf::from("c.png")]
+        );
+    }
+}
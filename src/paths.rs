@@ -0,0 +1,207 @@
+//! Windows long paths and network shares.
+//!
+//! Windows limits most paths to `MAX_PATH` (260) characters, unless they are given in the
+//! extended-length form: `\\?\C:\photos\a.jpg`, or `\\?\UNC\server\share\a.jpg` for a
+//! network share (`\\server\share\a.jpg`). Windows does not normalize paths in that form, so
+//! they must be absolute, only use backslashes and have no `.` or `..` components.
+//!
+//! `extended_length` converts paths to that form, `for_filesystem` does so for the long paths
+//! passed to Windows by `storage::FileSystem`, including its temporary files, and `join`
+//! appends relative paths, such as output names given with forward slashes, to
+//! extended-length directories. Apart from `for_filesystem`, these functions work on the
+//! text of the paths, so they behave the same on every platform.
+
+use std::{
+    borrow::Cow,
+    path::{Path, PathBuf},
+};
+
+/// The longest path most Windows functions accept, including the final NUL character.
+pub const MAX_PATH: usize = 260;
+/// The length from which paths are converted by `for_filesystem`. Windows limits directory
+/// paths to `MAX_PATH - 12` characters, to leave room for an 8.3 file name.
+pub const LONG_PATH: usize = MAX_PATH - 12;
+
+/// The prefix of extended-length paths.
+const VERBATIM: &str = r"\\?\";
+/// The prefix of extended-length paths on a network share.
+const VERBATIM_UNC: &str = r"\\?\UNC\";
+
+/// Returns whether a path is in the extended-length form.
+pub fn is_extended_length(path: &str) -> bool {
+    path.starts_with(VERBATIM)
+}
+
+/// Returns the extended-length form of an absolute Windows path.
+///
+/// Forward slashes are replaced by backslashes, and `.` and `..` components are resolved.
+///
+/// # Returns
+///
+/// The converted path, or None for relative paths, device paths (`\\.\COM1`) and paths
+/// already in the extended-length form, which are used as they are.
+pub fn extended_length(path: &str) -> Option<String> {
+    let path = path.replace('/', "\\");
+    if is_extended_length(&path) || path.starts_with(r"\\.\") {
+        return None;
+    }
+
+    let (root, rest) = if let Some(unc) = path.strip_prefix(r"\\") {
+        let mut parts = unc.splitn(3, '\\');
+        let server = parts.next().filter(|server| !server.is_empty())?;
+        let share = parts.next().filter(|share| !share.is_empty())?;
+        (
+            format!(r"{}{}\{}", VERBATIM_UNC, server, share),
+            parts.next().unwrap_or(""),
+        )
+    } else {
+        let bytes = path.as_bytes();
+        if bytes.len() < 3 || !bytes[0].is_ascii_alphabetic() || &bytes[1..3] != br":\" {
+            return None;
+        }
+        (format!("{}{}", VERBATIM, &path[..2]), &path[3..])
+    };
+    let mut joined = root.clone();
+    push_components(&mut joined, root.len(), rest);
+    if joined.len() == root.len() {
+        joined.push('\\');
+    }
+    Some(joined)
+}
+
+/// Joins a relative path to a directory.
+///
+/// If the directory is in the extended-length form, the relative path is appended as
+/// Windows expects it: with backslashes and without `.` or `..` components. Otherwise, and
+/// for absolute paths, this is `base.join(path)`.
+pub fn join(base: &Path, path: &Path) -> PathBuf {
+    let (Some(base_text), Some(text)) = (base.to_str(), path.to_str()) else {
+        return base.join(path);
+    };
+    if !is_extended_length(base_text) || text.starts_with('\\') || text.starts_with('/') {
+        return base.join(path);
+    }
+
+    let mut joined = base_text.trim_end_matches('\\').to_string();
+    let root = root_len(&joined);
+    push_components(&mut joined, root, &text.replace('/', "\\"));
+    PathBuf::from(joined)
+}
+
+/// Returns the form of a path to pass to the filesystem: the extended-length form for long
+/// paths on Windows, and the path itself otherwise.
+pub fn for_filesystem(path: &Path) -> Cow<'_, Path> {
+    if cfg!(windows) && path.as_os_str().len() >= LONG_PATH {
+        let absolute = std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+        if let Some(extended) = absolute.to_str().and_then(extended_length) {
+            return Cow::Owned(PathBuf::from(extended));
+        }
+    }
+    Cow::Borrowed(path)
+}
+
+/// Appends the backslash-separated components of `rest` to `path`, resolving `.` and `..`
+/// without going above the first `root` bytes.
+fn push_components(path: &mut String, root: usize, rest: &str) {
+    for component in rest.split('\\') {
+        match component {
+            "" | "." => {}
+            ".." => {
+                if let Some(index) = path.rfind('\\').filter(|&index| index >= root) {
+                    path.truncate(index);
+                }
+            }
+            component => {
+                path.push('\\');
+                path.push_str(component);
+            }
+        }
+    }
+}
+
+/// Returns the length of the root of an extended-length path, `\\?\C:` or
+/// `\\?\UNC\server\share`, which `..` components cannot remove.
+fn root_len(path: &str) -> usize {
+    match path.strip_prefix(VERBATIM_UNC) {
+        Some(unc) => {
+            let share_end = unc
+                .match_indices('\\')
+                .nth(1)
+                .map_or(unc.len(), |(index, _)| index);
+            VERBATIM_UNC.len() + share_end
+        }
+        None => path[VERBATIM.len()..]
+            .find('\\')
+            .map_or(path.len(), |index| VERBATIM.len() + index),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_to_extended_length() {
+        assert_eq!(
+            extended_length(r"C:\photos\.\raw\..\a.jpg").as_deref(),
+            Some(r"\\?\C:\photos\a.jpg")
+        );
+        assert_eq!(
+            extended_length("d:/out/b.png").as_deref(),
+            Some(r"\\?\d:\out\b.png")
+        );
+        assert_eq!(
+            extended_length(r"\\server\share\..\..\web\c.webp").as_deref(),
+            Some(r"\\?\UNC\server\share\web\c.webp")
+        );
+        assert_eq!(extended_length(r"C:\..").as_deref(), Some(r"\\?\C:\"));
+        for unchanged in [
+            r"\\?\C:\a.jpg",
+            r"\\.\COM1",
+            r"photos\a.jpg",
+            r"C:a.jpg",
+            r"\\server",
+        ] {
+            assert_eq!(extended_length(unchanged), None, "{}", unchanged);
+        }
+
+        let long = format!(r"C:\{}\a.jpg", "d".repeat(300));
+        let extended = extended_length(&long).unwrap();
+        assert!(extended.starts_with(r"\\?\C:\ddd") && extended.len() > MAX_PATH);
+    }
+
+    #[test]
+    fn joins_to_extended_length_directories() {
+        let join = |base: &str, path: &str| join(Path::new(base), Path::new(path));
+        assert_eq!(
+            join(r"\\?\C:\photos\", "web/./thumbs/a.jpg"),
+            Path::new(r"\\?\C:\photos\web\thumbs\a.jpg")
+        );
+        assert_eq!(
+            join(r"\\?\UNC\server\share\photos", "../../../a.jpg"),
+            Path::new(r"\\?\UNC\server\share\a.jpg")
+        );
+        assert_eq!(
+            join(r"\\?\C:\photos", r"..\..\a.jpg"),
+            Path::new(r"\\?\C:\a.jpg")
+        );
+        assert_eq!(join("photos", "a.jpg"), Path::new("photos").join("a.jpg"));
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn writes_long_paths() {
+        use crate::storage::{FileSystem, Storage};
+
+        let dir = tempfile::TempDir::new().expect("Failed to create a temp dir");
+        let mut path = dir.path().to_path_buf();
+        while path.as_os_str().len() < MAX_PATH + 20 {
+            path.push("a_long_directory_name");
+        }
+        std::fs::create_dir_all(for_filesystem(&path)).unwrap();
+        let file = path.join("output.png");
+        FileSystem.write_file(&file, b"contents", true).unwrap();
+        assert!(FileSystem.exists(&file).unwrap());
+        assert!(for_filesystem(&file).to_string_lossy().starts_with(r"\\?\"));
+    }
+}
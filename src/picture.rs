@@ -0,0 +1,194 @@
+//! HTML `<picture>` snippets.
+//!
+//! Once a recipe has produced several sizes and formats of an image (see
+//! `recipe::Recipe::process_file`), `render` writes a `<picture>` element offering them to
+//! browsers: a `<source>` for AVIF and one for WebP, then an `<img>` with the other outputs,
+//! typically JPEGs, as the fallback. Every candidate has a width descriptor, so that browsers
+//! download the smallest one large enough for the layout given by `sizes`.
+
+use crate::ImageInfo;
+use image::ImageFormat;
+use std::{
+    error::Error,
+    path::{Component, Path, PathBuf},
+};
+
+/// The formats offered in `<source>` elements, in order of preference.
+const SOURCE_FORMATS: [(ImageFormat, &str); 2] = [
+    (ImageFormat::Avif, "image/avif"),
+    (ImageFormat::WebP, "image/webp"),
+];
+
+/// Settings of a `<picture>` element.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PictureOptions {
+    /// The `sizes` attribute, the width of the image in the layout.
+    pub sizes: String,
+    /// The `alt` text of the image.
+    pub alt: String,
+    /// The directory the URLs are relative to, usually the one of the HTML page. Outputs
+    /// outside of it keep their path.
+    pub base: Option<PathBuf>,
+}
+
+impl Default for PictureOptions {
+    fn default() -> Self {
+        PictureOptions {
+            sizes: "100vw".to_string(),
+            alt: String::new(),
+            base: None,
+        }
+    }
+}
+
+/// Writes a `<picture>` element offering several outputs of the same image.
+///
+/// # Arguments
+///
+/// * `images` - The outputs, in any order.
+/// * `options` - The `sizes` and `alt` attributes and the base of the URLs.
+///
+/// # Returns
+///
+/// The element, indented with two spaces and without a final line break.
+///
+/// # Errors
+///
+/// This function will return an error if no output is in a format other than AVIF or WebP,
+/// since older browsers need such a fallback.
+pub fn render(images: &[&ImageInfo], options: &PictureOptions) -> Result<String, Box<dyn Error>> {
+    let is_source = |info: &&ImageInfo| SOURCE_FORMATS.iter().any(|(f, _)| *f == info.format);
+    let mut fallbacks: Vec<&ImageInfo> = images.iter().copied().filter(|i| !is_source(i)).collect();
+    fallbacks.sort_by_key(|info| info.width);
+    let largest = fallbacks
+        .last()
+        .ok_or("A <picture> needs an output in a format other than AVIF and WebP as fallback")?;
+
+    let sizes = escape(&options.sizes);
+    let mut html = String::from("<picture>\n");
+    for (format, mime) in SOURCE_FORMATS {
+        let mut candidates: Vec<&ImageInfo> = images
+            .iter()
+            .copied()
+            .filter(|info| info.format == format)
+            .collect();
+        if candidates.is_empty() {
+            continue;
+        }
+        candidates.sort_by_key(|info| info.width);
+        html.push_str(&format!(
+            "  <source type=\"{}\" srcset=\"{}\" sizes=\"{}\">\n",
+            mime,
+            srcset(&candidates, options),
+            sizes
+        ));
+    }
+    html.push_str(&format!(
+        "  <img src=\"{}\" srcset=\"{}\" sizes=\"{}\" width=\"{}\" height=\"{}\" alt=\"{}\">\n",
+        url(&largest.path, options),
+        srcset(&fallbacks, options),
+        sizes,
+        largest.width,
+        largest.height,
+        escape(&options.alt)
+    ));
+    html.push_str("</picture>");
+    Ok(html)
+}
+
+/// Lists images with their width descriptors.
+fn srcset(images: &[&ImageInfo], options: &PictureOptions) -> String {
+    images
+        .iter()
+        .map(|info| format!("{} {}w", url(&info.path, options), info.width))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Returns the URL of an output, relative to the base directory if it is inside it.
+fn url(path: &Path, options: &PictureOptions) -> String {
+    let relative = options
+        .base
+        .as_deref()
+        .filter(|base| !base.as_os_str().is_empty())
+        .and_then(|base| path.strip_prefix(base).ok())
+        .unwrap_or(path);
+    let segments: Vec<String> = relative
+        .components()
+        .filter_map(|component| match component {
+            Component::RootDir => Some(String::new()),
+            Component::CurDir => None,
+            other => Some(
+                other
+                    .as_os_str()
+                    .to_string_lossy()
+                    // Spaces and commas would split the candidates of a srcset
+                    .replace('%', "%25")
+                    .replace(' ', "%20")
+                    .replace(',', "%2C"),
+            ),
+        })
+        .collect();
+    escape(&segments.join("/"))
+}
+
+/// Escapes text for an HTML attribute value.
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('"', "&quot;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info(path: &str, format: ImageFormat, width: u32, height: u32) -> ImageInfo {
+        ImageInfo {
+            width,
+            height,
+            format,
+            path: PathBuf::from(path),
+            file_size: 1000,
+            source: None,
+            duration: None,
+            verification: None,
+        }
+    }
+
+    #[test]
+    fn renders_sources_and_fallback() {
+        let images = [
+            info("web/photo_large.jpg", ImageFormat::Jpeg, 1600, 1067),
+            info("web/photo_thumb.avif", ImageFormat::Avif, 400, 267),
+            info("web/photo_thumb.jpg", ImageFormat::Jpeg, 400, 267),
+            info("web/photo_large.avif", ImageFormat::Avif, 1600, 1067),
+            info("web/photo large.webp", ImageFormat::WebP, 1600, 1067),
+        ];
+        let images: Vec<&ImageInfo> = images.iter().collect();
+        let options = PictureOptions {
+            alt: "A \"photo\"".to_string(),
+            base: Some(PathBuf::from("web")),
+            ..PictureOptions::default()
+        };
+        assert_eq!(
+            render(&images, &options).unwrap(),
+            "<picture>\n\
+             \x20 <source type=\"image/avif\" srcset=\"photo_thumb.avif 400w, photo_large.avif 1600w\" sizes=\"100vw\">\n\
+             \x20 <source type=\"image/webp\" srcset=\"photo%20large.webp 1600w\" sizes=\"100vw\">\n\
+             \x20 <img src=\"photo_large.jpg\" srcset=\"photo_thumb.jpg 400w, photo_large.jpg 1600w\" sizes=\"100vw\" width=\"1600\" height=\"1067\" alt=\"A &quot;photo&quot;\">\n\
+             </picture>"
+        );
+    }
+
+    #[test]
+    fn needs_a_fallback() {
+        let avif = info("/srv/a.avif", ImageFormat::Avif, 10, 10);
+        assert!(render(&[&avif], &PictureOptions::default()).is_err());
+
+        let png = info("/srv/a.png", ImageFormat::Png, 10, 10);
+        let html = render(&[&avif, &png], &PictureOptions::default()).unwrap();
+        assert!(html.contains("<img src=\"/srv/a.png\" srcset=\"/srv/a.png 10w\""));
+    }
+}
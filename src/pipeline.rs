@@ -0,0 +1,647 @@
+//! Pluggable processing pipeline.
+//!
+//! Processing an image is split into stages implementing the `Stage` trait. A `Pipeline`
+//! runs its stages in order on a shared `PipelineState`, checking the job's cancellation
+//! token before each stage. The standard pipeline is:
+//!
+//! 1. `DecodeStage` (`"decode"`): reads the header, enforces the limits and decodes the image
+//! 2. `ResizeStage` (`"resize"`): resizes the image with the strategy chosen while decoding
+//! 3. `EncodeStage` (`"encode"`): encodes the image to the output format
+//!
+//! Custom stages, such as transforms before the resize or filters after it, can be inserted
+//! relative to the standard ones with `Pipeline::insert_before` and `Pipeline::insert_after`.
+//! `ImageStage` wraps a plain function over `DynamicImage` for the common case.
+//!
+//! Each stage runs in a `tracing` span named after the standard stage (`decode`, `resize`
+//! or `encode`), or `stage` with a `name` field for custom stages. The span records the
+//! dimensions of the image after the stage, the size of the encoded output once there is
+//! one, and the duration of the stage in milliseconds.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use image_resizer_rust::options::JobOptions;
+//! use image_resizer_rust::pipeline::{ImageStage, Pipeline, PipelineState, RESIZE};
+//!
+//! let mut pipeline = Pipeline::standard();
+//! pipeline
+//!     .insert_after(RESIZE, ImageStage::new("grayscale", |image| Ok(image.grayscale())))
+//!     .unwrap();
+//!
+//! let mut state = PipelineState::new("input.jpg", Some(800), None);
+//! state.format = Some(image::ImageFormat::Png);
+//! pipeline.run(&mut state, &JobOptions::default()).unwrap();
+//! std::fs::write("output.png", state.encoded.unwrap()).unwrap();
+//! ```
+
+use crate::{
+    codec::Decoder,
+    determine_new_dimensions,
+    limits::{self, ResizeStrategy},
+    options::JobOptions,
+    resize_image_with, storage, tiled,
+};
+use image::{DynamicImage, ImageDecoder, ImageFormat};
+use std::{borrow::Cow, error::Error, io::Read, path::PathBuf, time::Instant};
+use tracing::{debug, field::Empty, info_span, Span};
+
+/// The name of the standard decode stage.
+pub const DECODE: &str = "decode";
+/// The name of the standard resize stage.
+pub const RESIZE: &str = "resize";
+/// The name of the standard encode stage.
+pub const ENCODE: &str = "encode";
+
+/// The data passed from stage to stage.
+#[derive(Debug, Clone)]
+pub struct PipelineState {
+    /// The path of the input image file.
+    pub input: PathBuf,
+    /// The requested width. If None, it is calculated based on the height.
+    pub width: Option<u32>,
+    /// The requested height. If None, it is calculated based on the width.
+    pub height: Option<u32>,
+    /// The resize path, selected by the decode stage from the job limits.
+    pub strategy: ResizeStrategy,
+    /// The current image, set by the decode stage.
+    pub image: Option<DynamicImage>,
+    /// The output format used by the encode stage.
+    pub format: Option<ImageFormat>,
+    /// The encoded output, set by the encode stage.
+    pub encoded: Option<Vec<u8>>,
+}
+
+impl PipelineState {
+    /// Creates the state for resizing `input` to the requested dimensions.
+    pub fn new(input: impl Into<PathBuf>, width: Option<u32>, height: Option<u32>) -> Self {
+        PipelineState {
+            input: input.into(),
+            width,
+            height,
+            strategy: ResizeStrategy::Regular,
+            image: None,
+            format: None,
+            encoded: None,
+        }
+    }
+
+    /// Takes the current image out of the state.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if no stage has produced an image yet.
+    pub fn take_image(&mut self) -> Result<DynamicImage, Box<dyn Error>> {
+        self.image
+            .take()
+            .ok_or_else(|| "No image to process: the pipeline has no decode stage".into())
+    }
+}
+
+/// A step of the pipeline.
+pub trait Stage {
+    /// Returns the name of the stage, used to position other stages relative to it.
+    fn name(&self) -> &str;
+
+    /// Runs the stage, reading its input from and writing its output to `state`.
+    ///
+    /// # Errors
+    ///
+    /// Returning an error stops the pipeline.
+    fn run(&self, state: &mut PipelineState, options: &JobOptions) -> Result<(), Box<dyn Error>>;
+}
+
+/// An ordered list of stages.
+#[derive(Default)]
+pub struct Pipeline {
+    stages: Vec<Box<dyn Stage>>,
+}
+
+impl Pipeline {
+    /// Creates an empty pipeline.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates the standard decode, resize and encode pipeline.
+    pub fn standard() -> Self {
+        Pipeline::new()
+            .with_stage(DecodeStage)
+            .with_stage(ResizeStage)
+            .with_stage(EncodeStage)
+    }
+
+    /// Appends a stage and returns the pipeline, for chaining.
+    pub fn with_stage(mut self, stage: impl Stage + 'static) -> Self {
+        self.push(stage);
+        self
+    }
+
+    /// Appends a stage.
+    pub fn push(&mut self, stage: impl Stage + 'static) {
+        self.stages.push(Box::new(stage));
+    }
+
+    /// Inserts a stage before the first stage named `name`.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if there is no stage named `name`.
+    pub fn insert_before(
+        &mut self,
+        name: &str,
+        stage: impl Stage + 'static,
+    ) -> Result<(), Box<dyn Error>> {
+        let index = self.position(name)?;
+        self.stages.insert(index, Box::new(stage));
+        Ok(())
+    }
+
+    /// Inserts a stage after the first stage named `name`.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if there is no stage named `name`.
+    pub fn insert_after(
+        &mut self,
+        name: &str,
+        stage: impl Stage + 'static,
+    ) -> Result<(), Box<dyn Error>> {
+        let index = self.position(name)?;
+        self.stages.insert(index + 1, Box::new(stage));
+        Ok(())
+    }
+
+    /// Replaces the first stage named `name`.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if there is no stage named `name`.
+    pub fn replace(
+        &mut self,
+        name: &str,
+        stage: impl Stage + 'static,
+    ) -> Result<(), Box<dyn Error>> {
+        let index = self.position(name)?;
+        self.stages[index] = Box::new(stage);
+        Ok(())
+    }
+
+    /// Returns the names of the stages, in order.
+    pub fn stage_names(&self) -> Vec<&str> {
+        self.stages.iter().map(|stage| stage.name()).collect()
+    }
+
+    /// Returns whether the pipeline only has the standard decode, resize and encode stages.
+    pub fn is_standard(&self) -> bool {
+        self.stage_names() == [DECODE, RESIZE, ENCODE]
+    }
+
+    /// Returns a pipeline with only the stages up to and including the first stage named
+    /// `name`.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if there is no stage named `name`.
+    pub fn until(mut self, name: &str) -> Result<Self, Box<dyn Error>> {
+        let index = self.position(name)?;
+        self.stages.truncate(index + 1);
+        Ok(self)
+    }
+
+    /// Runs every stage in order.
+    ///
+    /// # Errors
+    ///
+    /// This function will return the first error returned by a stage, or
+    /// `ResizeError::Cancelled` if the job is cancelled between two stages.
+    pub fn run(
+        &self,
+        state: &mut PipelineState,
+        options: &JobOptions,
+    ) -> Result<(), Box<dyn Error>> {
+        for stage in &self.stages {
+            options.cancel.check()?;
+            let span = stage_span(stage.name());
+            let _entered = span.enter();
+            let start = Instant::now();
+            stage.run(state, options)?;
+            let elapsed = start.elapsed();
+            if let Some(image) = &state.image {
+                span.record("width", image.width());
+                span.record("height", image.height());
+            }
+            if let Some(encoded) = &state.encoded {
+                span.record("bytes", encoded.len());
+            }
+            span.record("duration_ms", elapsed.as_secs_f64() * 1000.0);
+            debug!("Stage '{}' took {:.1?}", stage.name(), elapsed);
+        }
+        Ok(())
+    }
+
+    /// Returns the index of the first stage named `name`.
+    fn position(&self, name: &str) -> Result<usize, Box<dyn Error>> {
+        self.stages
+            .iter()
+            .position(|stage| stage.name() == name)
+            .ok_or_else(|| format!("The pipeline has no stage named '{}'", name).into())
+    }
+}
+
+/// Returns the span a stage runs in, with fields recorded once the stage has run.
+fn stage_span(name: &str) -> Span {
+    match name {
+        DECODE => info_span!(
+            "decode",
+            width = Empty,
+            height = Empty,
+            bytes = Empty,
+            duration_ms = Empty
+        ),
+        RESIZE => info_span!(
+            "resize",
+            width = Empty,
+            height = Empty,
+            bytes = Empty,
+            duration_ms = Empty
+        ),
+        ENCODE => info_span!(
+            "encode",
+            width = Empty,
+            height = Empty,
+            bytes = Empty,
+            duration_ms = Empty
+        ),
+        _ => info_span!(
+            "stage",
+            name,
+            width = Empty,
+            height = Empty,
+            bytes = Empty,
+            duration_ms = Empty
+        ),
+    }
+}
+
+/// Computes the dimensions an image is resized to from the requested width and height.
+///
+/// Without a requested size, the image keeps its size unless a custom stage, such as an
+/// upscaler replacing the resize stage, changes it.
+pub(crate) fn output_dimensions(
+    (src_width, src_height): (u32, u32),
+    width: Option<u32>,
+    height: Option<u32>,
+    options: &JobOptions,
+) -> Result<(u32, u32), Box<dyn Error>> {
+    match (width, height) {
+        (None, None) if options.resize.max_output_pixels.is_none() => Ok((src_width, src_height)),
+        (width, height) => determine_new_dimensions(
+            src_width,
+            src_height,
+            width.as_ref(),
+            height.as_ref(),
+            &options.resize,
+        ),
+    }
+}
+
+/// Decodes the input file, enforcing the input policy, the safety limits and the resource
+/// limits of the job options.
+///
+/// The input format is checked against the input policy before any decoder runs. The image
+/// header is read next, so that images exceeding the limits are rejected before their pixels
+/// are decoded, and decoding itself is bounded by the decode timeout. The resize path
+/// (regular or tiled) is selected here with `limits::plan_resize` and stored in the state for
+/// the resize stage.
+///
+/// Inputs handled by a custom decoder of `JobOptions::decoders` are read into memory and
+/// decoded by it instead. Registering a decoder opts its inputs in, so they are not checked
+/// against the input policy, and the decode timeout does not apply to them.
+pub struct DecodeStage;
+
+impl DecodeStage {
+    /// Computes the output dimensions and selects the resize path.
+    fn plan(
+        state: &mut PipelineState,
+        options: &JobOptions,
+        (src_width, src_height): (u32, u32),
+        bytes_per_pixel: u64,
+    ) -> Result<(), Box<dyn Error>> {
+        options.safety.check_dimensions(src_width, src_height)?;
+        let new_dimensions =
+            output_dimensions((src_width, src_height), state.width, state.height, options)?;
+        state.strategy = limits::plan_resize(
+            (src_width, src_height),
+            bytes_per_pixel,
+            new_dimensions,
+            options,
+        )?;
+        Ok(())
+    }
+
+    /// Decodes the input with a custom decoder.
+    fn run_custom(
+        state: &mut PipelineState,
+        options: &JobOptions,
+        decoder: &dyn Decoder,
+    ) -> Result<(), Box<dyn Error>> {
+        let mut data = Vec::new();
+        options
+            .storage
+            .open_read(&state.input)?
+            .read_to_end(&mut data)?;
+        let dimensions = decoder.dimensions(&data)?;
+        // Decoded images are planned as 8-bit RGBA, the format the resizer works in
+        Self::plan(state, options, dimensions, 4)?;
+        debug!(
+            "Decoding {:?} with the {} decoder: {}x{}, {:?} resize path",
+            state.input,
+            decoder.name(),
+            dimensions.0,
+            dimensions.1,
+            state.strategy
+        );
+
+        options.cancel.check()?;
+        state.image = Some(decoder.decode(&data)?);
+        Ok(())
+    }
+}
+
+impl Stage for DecodeStage {
+    fn name(&self) -> &str {
+        DECODE
+    }
+
+    fn run(&self, state: &mut PipelineState, options: &JobOptions) -> Result<(), Box<dyn Error>> {
+        if let Some(decoder) = options.decoders.find_in(&*options.storage, &state.input)? {
+            return Self::run_custom(state, options, decoder);
+        }
+
+        let mut reader = storage::image_reader(&*options.storage, &state.input)?;
+        options.input_policy.check(reader.format())?;
+        reader.limits(options.limits.decoder_limits(&options.safety));
+        let decoder = reader.into_decoder()?;
+
+        let (src_width, src_height) = decoder.dimensions();
+        let bytes_per_pixel = decoder.color_type().bytes_per_pixel() as u64;
+        Self::plan(state, options, (src_width, src_height), bytes_per_pixel)?;
+        debug!(
+            "Decoding {:?}: {}x{}, {:?}, {:?} resize path",
+            state.input,
+            src_width,
+            src_height,
+            decoder.color_type(),
+            state.strategy
+        );
+
+        options.cancel.check()?;
+        state.image = Some(match options.safety.decode_timeout {
+            None => DynamicImage::from_decoder(decoder)?,
+            // The decoder cannot be moved to another thread, so the file is opened again
+            Some(_) => options.safety.decode_file(
+                &options.storage,
+                &state.input,
+                options.limits.decoder_limits(&options.safety),
+            )?,
+        });
+        Ok(())
+    }
+}
+
+/// Resizes the current image to the requested dimensions, producing an RGBA image.
+pub struct ResizeStage;
+
+impl Stage for ResizeStage {
+    fn name(&self) -> &str {
+        RESIZE
+    }
+
+    fn run(&self, state: &mut PipelineState, options: &JobOptions) -> Result<(), Box<dyn Error>> {
+        let image = state.take_image()?;
+        let (width, height) = (state.width.as_ref(), state.height.as_ref());
+        let resized = match state.strategy {
+            ResizeStrategy::Regular => resize_image_with(image, width, height, &options.resize)?,
+            ResizeStrategy::Tiled => tiled::resize_image_tiled(
+                &image,
+                width,
+                height,
+                tiled::DEFAULT_BAND_HEIGHT,
+                &options.resize,
+            )?,
+        };
+        state.image = Some(DynamicImage::ImageRgba8(resized));
+        Ok(())
+    }
+}
+
+/// Encodes the current image to `state.format` with the job's encoder options, using the
+/// encoder registered for that format in `JobOptions::encoders`.
+///
+/// The image is kept in the state, so later stages can still inspect it.
+pub struct EncodeStage;
+
+impl Stage for EncodeStage {
+    fn name(&self) -> &str {
+        ENCODE
+    }
+
+    fn run(&self, state: &mut PipelineState, options: &JobOptions) -> Result<(), Box<dyn Error>> {
+        let format = state
+            .format
+            .ok_or("No output format set for the encode stage")?;
+        let image = state
+            .image
+            .as_ref()
+            .ok_or("No image to encode: the pipeline has no decode stage")?;
+        let rgba = match image {
+            DynamicImage::ImageRgba8(buffer) => Cow::Borrowed(buffer),
+            other => Cow::Owned(other.to_rgba8()),
+        };
+
+        let encoded = options.encoders.encode(&rgba, format, &options.encode)?;
+        let size = encoded.len();
+        debug!(
+            "Encoded {}x{} as {:?} ({} bytes) with {:?}",
+            rgba.width(),
+            rgba.height(),
+            format,
+            size,
+            options.encode
+        );
+        state.encoded = Some(encoded);
+        Ok(())
+    }
+}
+
+/// A stage applying a function to the current image, e.g. a transform or a filter.
+pub struct ImageStage<F> {
+    name: String,
+    apply: F,
+}
+
+impl<F> ImageStage<F>
+where
+    F: Fn(DynamicImage) -> Result<DynamicImage, Box<dyn Error>>,
+{
+    /// Creates a stage named `name` that replaces the current image with `apply(image)`.
+    pub fn new(name: impl Into<String>, apply: F) -> Self {
+        ImageStage {
+            name: name.into(),
+            apply,
+        }
+    }
+}
+
+impl<F> Stage for ImageStage<F>
+where
+    F: Fn(DynamicImage) -> Result<DynamicImage, Box<dyn Error>>,
+{
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn run(&self, state: &mut PipelineState, _options: &JobOptions) -> Result<(), Box<dyn Error>> {
+        let image = state.take_image()?;
+        state.image = Some((self.apply)(image)?);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgba, RgbaImage};
+    use tempfile::TempDir;
+
+    fn write_input(dir: &TempDir) -> PathBuf {
+        let path = dir.path().join("input.png");
+        RgbaImage::from_fn(40, 20, |x, _| Rgba([x as u8 * 6, 0, 0, 255]))
+            .save(&path)
+            .unwrap();
+        path
+    }
+
+    #[test]
+    fn standard_pipeline_resizes_and_encodes() {
+        let dir = TempDir::new().expect("Failed to create a temp dir");
+        let mut state = PipelineState::new(write_input(&dir), Some(10), None);
+        state.format = Some(ImageFormat::Png);
+
+        let pipeline = Pipeline::standard();
+        assert_eq!(pipeline.stage_names(), [DECODE, RESIZE, ENCODE]);
+        assert!(pipeline.is_standard());
+        pipeline.run(&mut state, &JobOptions::default()).unwrap();
+
+        let decoded = image::load_from_memory(&state.encoded.unwrap()).unwrap();
+        assert_eq!((decoded.width(), decoded.height()), (10, 5));
+    }
+
+    #[test]
+    fn custom_stages_run_in_position() {
+        let dir = TempDir::new().expect("Failed to create a temp dir");
+        let mut state = PipelineState::new(write_input(&dir), Some(10), None);
+
+        let mut pipeline = Pipeline::standard().until(RESIZE).unwrap();
+        pipeline
+            .insert_before(
+                RESIZE,
+                ImageStage::new("rotate", |image| Ok(image.rotate90())),
+            )
+            .unwrap();
+        pipeline
+            .insert_after(
+                RESIZE,
+                ImageStage::new("invert", |mut image| {
+                    image.invert();
+                    Ok(image)
+                }),
+            )
+            .unwrap();
+        assert_eq!(pipeline.stage_names(), [DECODE, "rotate", RESIZE, "invert"]);
+        assert!(!pipeline.is_standard());
+        assert!(pipeline
+            .replace("missing", ImageStage::new("noop", Ok))
+            .is_err());
+
+        pipeline.run(&mut state, &JobOptions::default()).unwrap();
+        let image = state.image.unwrap().into_rgba8();
+        // Rotated from 40x20 to 20x40 before resizing to a width of 10
+        assert_eq!(image.dimensions(), (10, 20));
+        assert_eq!(image.get_pixel(0, 0)[1], 255);
+    }
+
+    #[test]
+    fn unknown_stage_name() {
+        let mut pipeline = Pipeline::standard();
+        assert!(pipeline
+            .insert_after("sharpen", ImageStage::new("noop", Ok))
+            .is_err());
+    }
+
+    /// Records the names of the spans created, and the fields recorded on them.
+    #[derive(Default)]
+    struct Recorder {
+        spans: std::sync::Mutex<Vec<(String, Vec<String>)>>,
+    }
+
+    struct FieldNames<'a>(&'a mut Vec<String>);
+
+    impl tracing::field::Visit for FieldNames<'_> {
+        fn record_debug(&mut self, field: &tracing::field::Field, _: &dyn std::fmt::Debug) {
+            self.0.push(field.name().to_string());
+        }
+    }
+
+    impl tracing::Subscriber for Recorder {
+        fn enabled(&self, _: &tracing::Metadata<'_>) -> bool {
+            true
+        }
+
+        fn new_span(&self, span: &tracing::span::Attributes<'_>) -> tracing::span::Id {
+            let mut spans = self.spans.lock().unwrap();
+            let mut fields = Vec::new();
+            span.record(&mut FieldNames(&mut fields));
+            spans.push((span.metadata().name().to_string(), fields));
+            tracing::span::Id::from_u64(spans.len() as u64)
+        }
+
+        fn record(&self, span: &tracing::span::Id, values: &tracing::span::Record<'_>) {
+            let mut spans = self.spans.lock().unwrap();
+            values.record(&mut FieldNames(&mut spans[span.into_u64() as usize - 1].1));
+        }
+
+        fn record_follows_from(&self, _: &tracing::span::Id, _: &tracing::span::Id) {}
+
+        fn event(&self, _: &tracing::Event<'_>) {}
+
+        fn enter(&self, _: &tracing::span::Id) {}
+
+        fn exit(&self, _: &tracing::span::Id) {}
+    }
+
+    #[test]
+    fn stages_run_in_spans() {
+        let dir = TempDir::new().expect("Failed to create a temp dir");
+        let mut state = PipelineState::new(write_input(&dir), Some(10), None);
+        state.format = Some(ImageFormat::Png);
+        let mut pipeline = Pipeline::standard();
+        pipeline
+            .insert_after(
+                RESIZE,
+                ImageStage::new("grayscale", |image| Ok(image.grayscale())),
+            )
+            .unwrap();
+
+        let recorder = std::sync::Arc::new(Recorder::default());
+        tracing::subscriber::with_default(recorder.clone(), || {
+            pipeline.run(&mut state, &JobOptions::default()).unwrap();
+        });
+        let spans = recorder.spans.lock().unwrap();
+        let names: Vec<_> = spans.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["decode", "resize", "stage", "encode"]);
+        assert_eq!(spans[0].1, ["width", "height", "duration_ms"]);
+        assert_eq!(spans[2].1, ["name", "width", "height", "duration_ms"]);
+        assert_eq!(spans[3].1, ["width", "height", "bytes", "duration_ms"]);
+    }
+}
@@ -0,0 +1,93 @@
+//! Input format policy.
+//!
+//! Security-sensitive deployments may want to process only a few well-known formats and
+//! reject everything else before any decoder runs. `InputPolicy` holds an optional allow
+//! list and a deny list of formats. The format is detected from the file contents, not
+//! from its extension, so a renamed file cannot bypass the policy.
+
+use crate::error::ResizeError;
+use image::ImageFormat;
+
+/// Restricts which input formats are processed.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct InputPolicy {
+    /// If set, only these formats are accepted.
+    pub allow: Option<Vec<ImageFormat>>,
+    /// Formats that are always rejected, even if they are in the allow list.
+    pub deny: Vec<ImageFormat>,
+}
+
+impl InputPolicy {
+    /// Creates a policy accepting only the given formats.
+    pub fn allow_only(formats: impl IntoIterator<Item = ImageFormat>) -> Self {
+        InputPolicy {
+            allow: Some(formats.into_iter().collect()),
+            deny: Vec::new(),
+        }
+    }
+
+    /// Returns `true` if the policy accepts every format.
+    pub fn is_unrestricted(&self) -> bool {
+        self.allow.is_none() && self.deny.is_empty()
+    }
+
+    /// Checks a detected input format against the policy.
+    ///
+    /// An unknown format (`None`) is only accepted by an unrestricted policy.
+    ///
+    /// # Errors
+    ///
+    /// This function will return `ResizeError::FormatRejected` if the format is not accepted.
+    pub fn check(&self, format: Option<ImageFormat>) -> Result<(), ResizeError> {
+        if self.is_unrestricted() {
+            return Ok(());
+        }
+
+        let accepted = format.is_some_and(|format| {
+            !self.deny.contains(&format)
+                && self
+                    .allow
+                    .as_ref()
+                    .is_none_or(|allow| allow.contains(&format))
+        });
+        if accepted {
+            Ok(())
+        } else {
+            Err(ResizeError::FormatRejected(format))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unrestricted_accepts_everything() {
+        let policy = InputPolicy::default();
+        assert!(policy.check(Some(ImageFormat::Gif)).is_ok());
+        assert!(policy.check(None).is_ok());
+    }
+
+    #[test]
+    fn allow_and_deny_lists() {
+        let mut policy = InputPolicy::allow_only([ImageFormat::Jpeg, ImageFormat::Png]);
+        assert!(policy.check(Some(ImageFormat::Png)).is_ok());
+        assert_eq!(
+            policy.check(Some(ImageFormat::Tiff)),
+            Err(ResizeError::FormatRejected(Some(ImageFormat::Tiff)))
+        );
+        assert!(policy.check(None).is_err());
+
+        policy.deny.push(ImageFormat::Png);
+        assert!(policy.check(Some(ImageFormat::Png)).is_err());
+        assert!(policy.check(Some(ImageFormat::Jpeg)).is_ok());
+
+        let deny_only = InputPolicy {
+            allow: None,
+            deny: vec![ImageFormat::Gif],
+        };
+        assert!(deny_only.check(Some(ImageFormat::Gif)).is_err());
+        assert!(deny_only.check(Some(ImageFormat::WebP)).is_ok());
+    }
+}
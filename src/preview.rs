@@ -0,0 +1,342 @@
+//! Terminal image previews.
+//!
+//! A preview is a small rendering of an image as terminal output, to check a resize before
+//! the result is saved. Terminals with a graphics protocol get a real image: the kitty
+//! graphics protocol, iTerm2 inline images or sixels. Every other terminal gets a
+//! rendering with Unicode half blocks, where each character cell shows two pixels stacked
+//! vertically using 24-bit colors.
+//!
+//! Transparent pixels are drawn over black, since not every protocol supports alpha.
+
+use crate::quantize::{self, Dither};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use image::{DynamicImage, ImageFormat, Rgba, RgbaImage};
+use std::{
+    env,
+    error::Error,
+    fmt::{self, Write},
+    io::Cursor,
+    str::FromStr,
+};
+
+/// The default width of a preview, in character cells.
+pub const PREVIEW_COLUMNS: u32 = 40;
+/// The default height of a preview, in character cells.
+pub const PREVIEW_ROWS: u32 = 20;
+
+/// The assumed size of a character cell in pixels, used to size graphics previews.
+const CELL_SIZE: (u32, u32) = (8, 16);
+/// The size of the payload chunks of the kitty graphics protocol.
+const KITTY_CHUNK: usize = 4096;
+/// The number of colors of a sixel preview.
+const SIXEL_COLORS: usize = 256;
+
+/// The ways an image can be drawn on a terminal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Protocol {
+    /// The kitty graphics protocol, also supported by WezTerm and Ghostty.
+    Kitty,
+    /// The inline images protocol of iTerm2.
+    Iterm,
+    /// DEC sixel graphics.
+    Sixel,
+    /// Unicode half blocks with 24-bit colors, supported by nearly every terminal.
+    Blocks,
+}
+
+impl Protocol {
+    /// Detects the best protocol supported by the current terminal from its environment
+    /// variables, falling back to `Protocol::Blocks`.
+    pub fn detect() -> Self {
+        Self::detect_from(|name| env::var(name).ok())
+    }
+
+    /// Detects the protocol from the environment variables returned by `var`.
+    fn detect_from(var: impl Fn(&str) -> Option<String>) -> Self {
+        let term = var("TERM").unwrap_or_default();
+        let program = var("TERM_PROGRAM").unwrap_or_default();
+        if var("KITTY_WINDOW_ID").is_some()
+            || term.contains("kitty")
+            || term.contains("ghostty")
+            || program == "WezTerm"
+        {
+            Protocol::Kitty
+        } else if program == "iTerm.app" || var("LC_TERMINAL").as_deref() == Some("iTerm2") {
+            Protocol::Iterm
+        } else if term.contains("sixel") || term == "mlterm" || term.starts_with("foot") {
+            Protocol::Sixel
+        } else {
+            Protocol::Blocks
+        }
+    }
+}
+
+impl fmt::Display for Protocol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Protocol::Kitty => "kitty",
+            Protocol::Iterm => "iterm",
+            Protocol::Sixel => "sixel",
+            Protocol::Blocks => "blocks",
+        };
+        f.write_str(name)
+    }
+}
+
+impl FromStr for Protocol {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "kitty" => Ok(Protocol::Kitty),
+            "iterm" => Ok(Protocol::Iterm),
+            "sixel" => Ok(Protocol::Sixel),
+            "blocks" => Ok(Protocol::Blocks),
+            _ => Err(format!("Unknown preview protocol '{}'", s)),
+        }
+    }
+}
+
+/// Renders an image as terminal output.
+///
+/// The image is scaled to fit in `columns` by `rows` character cells, keeping its aspect
+/// ratio. The output ends with a newline.
+///
+/// # Arguments
+///
+/// * `image` - The image to render.
+/// * `protocol` - The protocol to draw the image with.
+/// * `columns` - The maximum width of the preview in character cells.
+/// * `rows` - The maximum height of the preview in character cells.
+///
+/// # Errors
+///
+/// This function will return an error if the image cannot be encoded for the protocol.
+pub fn render(
+    image: &DynamicImage,
+    protocol: Protocol,
+    columns: u32,
+    rows: u32,
+) -> Result<String, Box<dyn Error>> {
+    let (max_width, max_height) = match protocol {
+        Protocol::Blocks => (columns, rows * 2),
+        _ => (columns * CELL_SIZE.0, rows * CELL_SIZE.1),
+    };
+    let thumbnail = flatten(
+        &image
+            .thumbnail(max_width.max(1), max_height.max(1))
+            .into_rgba8(),
+    );
+
+    match protocol {
+        Protocol::Kitty => Ok(kitty(&thumbnail)?),
+        Protocol::Iterm => Ok(iterm(&thumbnail)?),
+        Protocol::Sixel => sixel(&thumbnail),
+        Protocol::Blocks => Ok(blocks(&thumbnail)),
+    }
+}
+
+/// Draws transparent pixels over black.
+fn flatten(image: &RgbaImage) -> RgbaImage {
+    RgbaImage::from_fn(image.width(), image.height(), |x, y| {
+        let Rgba([r, g, b, a]) = *image.get_pixel(x, y);
+        let blend = |c: u8| (c as u16 * a as u16 / 255) as u8;
+        Rgba([blend(r), blend(g), blend(b), 255])
+    })
+}
+
+/// Encodes an image as PNG data in base64.
+fn png_base64(image: &RgbaImage) -> Result<String, image::ImageError> {
+    let mut png = Cursor::new(Vec::new());
+    image.write_to(&mut png, ImageFormat::Png)?;
+    Ok(STANDARD.encode(png.into_inner()))
+}
+
+/// Renders an image with the kitty graphics protocol, sending the PNG data in chunks.
+fn kitty(image: &RgbaImage) -> Result<String, image::ImageError> {
+    let data = png_base64(image)?;
+    // Base64 data is ASCII, so it can be split at any byte
+    let chunks: Vec<&str> = (0..data.len())
+        .step_by(KITTY_CHUNK)
+        .map(|start| &data[start..(start + KITTY_CHUNK).min(data.len())])
+        .collect();
+    let mut output = String::new();
+    for (i, chunk) in chunks.iter().enumerate() {
+        let more = u8::from(i + 1 < chunks.len());
+        let control = if i == 0 {
+            format!("a=T,f=100,m={}", more)
+        } else {
+            format!("m={}", more)
+        };
+        let _ = write!(output, "\x1b_G{};{}\x1b\\", control, chunk);
+    }
+    output.push('\n');
+    Ok(output)
+}
+
+/// Renders an image with the iTerm2 inline images protocol.
+fn iterm(image: &RgbaImage) -> Result<String, image::ImageError> {
+    let data = png_base64(image)?;
+    Ok(format!(
+        "\x1b]1337;File=inline=1;size={}:{}\x07\n",
+        data.len() * 3 / 4,
+        data
+    ))
+}
+
+/// Renders an image as sixels, with a palette computed by the quantizer.
+fn sixel(image: &RgbaImage) -> Result<String, Box<dyn Error>> {
+    let indexed = quantize::quantize(image, SIXEL_COLORS, Dither::FloydSteinberg)?;
+    let (width, height) = (indexed.width as usize, indexed.height as usize);
+
+    let mut output = format!("\x1bPq\"1;1;{};{}", width, height);
+    for (i, Rgba([r, g, b, _])) in indexed.palette.iter().enumerate() {
+        let percent = |c: &u8| *c as u32 * 100 / 255;
+        let _ = write!(
+            output,
+            "#{};2;{};{};{}",
+            i,
+            percent(r),
+            percent(g),
+            percent(b)
+        );
+    }
+
+    for band in (0..height).step_by(6) {
+        let rows = band..(band + 6).min(height);
+        let mut colors: Vec<u8> = rows
+            .clone()
+            .flat_map(|y| indexed.indices[y * width..(y + 1) * width].iter().copied())
+            .collect();
+        colors.sort_unstable();
+        colors.dedup();
+
+        for color in colors {
+            let sixels = (0..width).map(|x| {
+                let bits = rows
+                    .clone()
+                    .filter(|&y| indexed.indices[y * width + x] == color)
+                    .fold(0, |bits, y| bits | 1 << (y - band));
+                (63 + bits) as u8 as char
+            });
+            let _ = write!(output, "#{}", color);
+            push_run_length(&mut output, sixels);
+            output.push('$');
+        }
+        output.push('-');
+    }
+    output.push_str("\x1b\\\n");
+    Ok(output)
+}
+
+/// Appends sixel characters, replacing runs of more than three characters with a repeat.
+fn push_run_length(output: &mut String, sixels: impl Iterator<Item = char>) {
+    let flush = |output: &mut String, sixel: char, count: usize| {
+        if count > 3 {
+            let _ = write!(output, "!{}{}", count, sixel);
+        } else {
+            output.extend(std::iter::repeat_n(sixel, count));
+        }
+    };
+    let mut run: Option<(char, usize)> = None;
+    for sixel in sixels {
+        run = match run {
+            Some((current, count)) if current == sixel => Some((current, count + 1)),
+            Some((current, count)) => {
+                flush(output, current, count);
+                Some((sixel, 1))
+            }
+            None => Some((sixel, 1)),
+        };
+    }
+    if let Some((sixel, count)) = run {
+        flush(output, sixel, count);
+    }
+}
+
+/// Renders an image with upper half blocks, the top pixel of each cell in the foreground
+/// color and the bottom pixel in the background color.
+fn blocks(image: &RgbaImage) -> String {
+    let mut output = String::new();
+    for y in (0..image.height()).step_by(2) {
+        for x in 0..image.width() {
+            let Rgba([r, g, b, _]) = *image.get_pixel(x, y);
+            let _ = write!(output, "\x1b[38;2;{};{};{}m", r, g, b);
+            match image.get_pixel_checked(x, y + 1) {
+                Some(Rgba([r, g, b, _])) => {
+                    let _ = write!(output, "\x1b[48;2;{};{};{}m", r, g, b);
+                }
+                None => output.push_str("\x1b[49m"),
+            }
+            output.push('▀');
+        }
+        output.push_str("\x1b[0m\n");
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gradient(width: u32, height: u32) -> DynamicImage {
+        DynamicImage::ImageRgba8(RgbaImage::from_fn(width, height, |x, y| {
+            Rgba([(x * 4) as u8, (y * 4) as u8, 128, 255])
+        }))
+    }
+
+    #[test]
+    fn detects_protocols() {
+        let detect = |vars: &[(&str, &str)]| {
+            Protocol::detect_from(|name| {
+                vars.iter()
+                    .find(|(key, _)| *key == name)
+                    .map(|(_, value)| value.to_string())
+            })
+        };
+        assert_eq!(detect(&[("TERM", "xterm-kitty")]), Protocol::Kitty);
+        assert_eq!(detect(&[("TERM_PROGRAM", "iTerm.app")]), Protocol::Iterm);
+        assert_eq!(detect(&[("TERM", "foot")]), Protocol::Sixel);
+        assert_eq!(detect(&[("TERM", "xterm-256color")]), Protocol::Blocks);
+
+        for protocol in [
+            Protocol::Kitty,
+            Protocol::Iterm,
+            Protocol::Sixel,
+            Protocol::Blocks,
+        ] {
+            assert_eq!(protocol.to_string().parse::<Protocol>(), Ok(protocol));
+        }
+    }
+
+    #[test]
+    fn renders_half_blocks() {
+        let output = render(&gradient(60, 30), Protocol::Blocks, 20, 10).unwrap();
+        // 60x30 fits in 20x20 pixels as 20x10, drawn on 5 lines of 20 cells
+        assert_eq!(output.lines().count(), 5);
+        assert_eq!(output.lines().next().unwrap().matches('▀').count(), 20);
+    }
+
+    #[test]
+    fn renders_graphics_protocols() {
+        let image = gradient(64, 48);
+        let kitty = render(&image, Protocol::Kitty, 4, 4).unwrap();
+        assert!(kitty.starts_with("\x1b_Ga=T,f=100,m=0;iVBOR"));
+
+        let iterm = render(&image, Protocol::Iterm, 4, 4).unwrap();
+        assert!(iterm.starts_with("\x1b]1337;File=inline=1;"));
+
+        let sixel = render(&image, Protocol::Sixel, 4, 4).unwrap();
+        assert!(sixel.starts_with("\x1bPq\"1;1;32;24#0;2;"));
+        assert!(sixel.ends_with("\x1b\\\n"));
+        // 24 rows make 4 bands of sixels
+        assert_eq!(sixel.matches('-').count(), 4);
+    }
+
+    #[test]
+    fn run_length_encodes_sixels() {
+        let mut output = String::new();
+        push_run_length(&mut output, "aaaaabbb?".chars());
+        assert_eq!(output, "!5abbb?");
+    }
+}
@@ -0,0 +1,160 @@
+//! Interactive prompts.
+//!
+//! When image files are dropped onto the binary, it is started with their paths and no other
+//! arguments. Instead of failing because no size was given, the tool asks for the target
+//! size and format on the terminal. Every answer is validated, and the question is asked
+//! again until the answer is valid.
+
+use std::io::{self, BufRead, Write};
+
+/// The settings entered interactively.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Settings {
+    /// The target width, if given.
+    pub width: Option<u32>,
+    /// The target height, if given.
+    pub height: Option<u32>,
+    /// The output format (`jpeg` or `png`), or `None` to keep the input format.
+    pub format: Option<String>,
+}
+
+/// Asks a question until `parse` accepts the answer.
+///
+/// # Arguments
+///
+/// * `input` - Where answers are read from.
+/// * `output` - Where questions and validation errors are written to.
+/// * `question` - The question, without the trailing colon.
+/// * `parse` - Validates and converts the trimmed answer.
+///
+/// # Errors
+///
+/// This function will return an error if reading or writing fails, or if the input ends
+/// before a valid answer is given.
+pub fn ask<T>(
+    input: &mut impl BufRead,
+    output: &mut impl Write,
+    question: &str,
+    parse: impl Fn(&str) -> Result<T, String>,
+) -> io::Result<T> {
+    loop {
+        write!(output, "{}: ", question)?;
+        output.flush()?;
+
+        let mut answer = String::new();
+        if input.read_line(&mut answer)? == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "No answer was given",
+            ));
+        }
+        match parse(answer.trim()) {
+            Ok(value) => return Ok(value),
+            Err(e) => writeln!(output, "{}", e)?,
+        }
+    }
+}
+
+/// Asks for the target size and output format.
+///
+/// At least one of the width and the height must be given; the other one is then computed
+/// to keep the aspect ratio.
+///
+/// # Errors
+///
+/// This function will return an error if reading or writing fails, or if the input ends
+/// before every answer is given.
+pub fn ask_settings(input: &mut impl BufRead, output: &mut impl Write) -> io::Result<Settings> {
+    writeln!(
+        output,
+        "No size was given. Leave the width or the height empty to keep the aspect ratio."
+    )?;
+    let (width, height) = loop {
+        let width = ask(input, output, "Width in pixels", parse_dimension)?;
+        let height = ask(input, output, "Height in pixels", parse_dimension)?;
+        if width.is_some() || height.is_some() {
+            break (width, height);
+        }
+        writeln!(output, "Enter at least a width or a height.")?;
+    };
+    let format = ask(
+        input,
+        output,
+        "Output format (jpeg or png, empty to keep the input format)",
+        parse_format,
+    )?;
+
+    Ok(Settings {
+        width,
+        height,
+        format,
+    })
+}
+
+/// Waits until the user presses Enter, so that a console window opened for a dropped file
+/// stays open until the results have been read.
+pub fn wait_for_enter(input: &mut impl BufRead, output: &mut impl Write) -> io::Result<()> {
+    write!(output, "\nPress Enter to exit.")?;
+    output.flush()?;
+    input.read_line(&mut String::new())?;
+    Ok(())
+}
+
+/// Parses an optional dimension in pixels.
+fn parse_dimension(answer: &str) -> Result<Option<u32>, String> {
+    if answer.is_empty() {
+        return Ok(None);
+    }
+    match answer.parse::<u32>() {
+        Ok(pixels) if pixels > 0 => Ok(Some(pixels)),
+        _ => Err(format!("'{}' is not a valid number of pixels", answer)),
+    }
+}
+
+/// Parses an optional output format.
+fn parse_format(answer: &str) -> Result<Option<String>, String> {
+    match answer.to_lowercase().as_str() {
+        "" => Ok(None),
+        "jpeg" | "jpg" => Ok(Some("jpeg".to_string())),
+        "png" => Ok(Some("png".to_string())),
+        _ => Err(format!(
+            "'{}' is not a supported format (jpeg or png)",
+            answer
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn asks_until_valid() {
+        let mut input = Cursor::new("abc\n0\n800\n");
+        let mut output = Vec::new();
+        let width = ask(&mut input, &mut output, "Width", parse_dimension).unwrap();
+        assert_eq!(width, Some(800));
+
+        let output = String::from_utf8(output).unwrap();
+        assert_eq!(output.matches("Width: ").count(), 3);
+        assert!(output.contains("'abc' is not a valid number of pixels"));
+    }
+
+    #[test]
+    fn asks_for_settings() {
+        let mut input = Cursor::new("\n\n\n600\nbmp\nJPG\n");
+        let settings = ask_settings(&mut input, &mut Vec::new()).unwrap();
+        assert_eq!(
+            settings,
+            Settings {
+                width: None,
+                height: Some(600),
+                format: Some("jpeg".to_string()),
+            }
+        );
+
+        let mut input = Cursor::new("800\n");
+        assert!(ask_settings(&mut input, &mut Vec::new()).is_err());
+    }
+}
@@ -0,0 +1,323 @@
+//! Multi-resolution tile pyramids.
+//!
+//! Deep-zoom viewers such as OpenSeadragon or Leaflet display huge images by loading only
+//! the tiles of the visible area at the current zoom level. A pyramid holds these tiles for
+//! every level: the full image, then the image halved in size, and so on.
+//!
+//! Two layouts are supported:
+//! - DZI (Deep Zoom Image): a `<name>.dzi` XML descriptor and the tiles in
+//!   `<name>_files/<level>/<column>_<row>.<ext>`. Levels go from 1x1 pixel (level 0) up to
+//!   the full image, and tiles overlap their neighbors by a few pixels on every side.
+//! - z/x/y: the tiles in `<z>/<x>/<y>.<ext>`, as used by web maps. Level 0 is the first
+//!   level fitting in a single tile, and tiles do not overlap.
+//!
+//! Each level is resized from the previous one with the band-based resizer of the `tiled`
+//! module, and tiles are cut from the level as they are written, so that memory stays close
+//! to two levels of the pyramid.
+
+use crate::{
+    encode::EncodeOptions,
+    estimate_size_and_encode,
+    options::ResizeOptions,
+    storage::{FileSystem, Storage},
+    tiled::{resize_image_tiled, DEFAULT_BAND_HEIGHT},
+};
+use image::{DynamicImage, GenericImageView, ImageFormat};
+use std::{error::Error, fmt, fs, path::Path, str::FromStr};
+
+/// The default tile size of a DZI pyramid, which makes tiles 256 pixels wide with overlap.
+pub const DZI_TILE_SIZE: u32 = 254;
+/// The default overlap of a DZI pyramid.
+pub const DZI_OVERLAP: u32 = 1;
+/// The default tile size of a z/x/y pyramid.
+pub const ZXY_TILE_SIZE: u32 = 256;
+
+/// The file and folder layouts of a pyramid.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Layout {
+    /// Deep Zoom Image, with an XML descriptor.
+    #[default]
+    Dzi,
+    /// `z/x/y` folders, as used by web maps.
+    Zxy,
+}
+
+impl fmt::Display for Layout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Layout::Dzi => "dzi",
+            Layout::Zxy => "zxy",
+        };
+        f.write_str(name)
+    }
+}
+
+impl FromStr for Layout {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "dzi" => Ok(Layout::Dzi),
+            "zxy" => Ok(Layout::Zxy),
+            _ => Err(format!("Unknown pyramid layout '{}'", s)),
+        }
+    }
+}
+
+/// The settings of a pyramid.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PyramidOptions {
+    /// The file and folder layout.
+    pub layout: Layout,
+    /// The width and height of a tile, without overlap.
+    pub tile_size: u32,
+    /// The number of pixels each DZI tile shares with its neighbors on every side. Ignored
+    /// by the z/x/y layout.
+    pub overlap: u32,
+    /// The format of the tiles, `ImageFormat::Jpeg` or `ImageFormat::Png`.
+    pub format: ImageFormat,
+    /// The encoder settings of the tiles.
+    pub encode: EncodeOptions,
+}
+
+impl PyramidOptions {
+    /// Returns the default settings of a layout.
+    pub fn new(layout: Layout) -> Self {
+        let (tile_size, overlap) = match layout {
+            Layout::Dzi => (DZI_TILE_SIZE, DZI_OVERLAP),
+            Layout::Zxy => (ZXY_TILE_SIZE, 0),
+        };
+        Self {
+            layout,
+            tile_size,
+            overlap,
+            format: ImageFormat::Jpeg,
+            encode: EncodeOptions::default(),
+        }
+    }
+}
+
+/// A summary of a generated pyramid.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Pyramid {
+    /// The number of levels.
+    pub levels: usize,
+    /// The number of tiles written.
+    pub tiles: usize,
+}
+
+/// Returns the dimensions of the levels of a pyramid, from the full image down to the
+/// smallest level.
+///
+/// Each level is half the size of the previous one, rounded up. DZI pyramids go down to
+/// 1x1 pixel, z/x/y pyramids down to the first level fitting in one tile.
+pub fn level_dimensions(width: u32, height: u32, options: &PyramidOptions) -> Vec<(u32, u32)> {
+    let is_last = |(width, height): (u32, u32)| match options.layout {
+        Layout::Dzi => width <= 1 && height <= 1,
+        Layout::Zxy => width <= options.tile_size && height <= options.tile_size,
+    };
+    let mut levels = vec![(width, height)];
+    let mut size = (width, height);
+    while !is_last(size) {
+        size = (size.0.div_ceil(2), size.1.div_ceil(2));
+        levels.push(size);
+    }
+    levels
+}
+
+/// Generates a tile pyramid from an image.
+///
+/// # Arguments
+///
+/// * `source` - The full-size image.
+/// * `dir` - The folder to write the pyramid to. It is created if needed.
+/// * `name` - The name of the DZI descriptor and tile folder (`<name>.dzi` and
+///   `<name>_files`). Unused by the z/x/y layout.
+/// * `options` - The layout and tile settings.
+/// * `resize` - The CPU settings of the resizer.
+///
+/// # Returns
+///
+/// The number of levels and tiles written.
+///
+/// # Errors
+///
+/// This function will return an error if the tile size is zero, the format is not JPEG or
+/// PNG, or if a level cannot be resized or a tile cannot be encoded or written.
+pub fn generate(
+    source: DynamicImage,
+    dir: &Path,
+    name: &str,
+    options: &PyramidOptions,
+    resize: &ResizeOptions,
+) -> Result<Pyramid, Box<dyn Error>> {
+    if options.tile_size == 0 {
+        return Err("The tile size must be at least 1 pixel".into());
+    }
+    let extension = match options.format {
+        ImageFormat::Jpeg => "jpg",
+        ImageFormat::Png => "png",
+        format => return Err(format!("Unsupported tile format {:?}", format).into()),
+    };
+    let overlap = match options.layout {
+        Layout::Dzi => options.overlap,
+        Layout::Zxy => 0,
+    };
+
+    let (width, height) = source.dimensions();
+    let levels = level_dimensions(width, height, options);
+    let tiles_dir = match options.layout {
+        Layout::Dzi => dir.join(format!("{}_files", name)),
+        Layout::Zxy => dir.to_path_buf(),
+    };
+
+    let mut tiles = 0;
+    let mut level_image = source;
+    for (index, &(level_width, level_height)) in levels.iter().enumerate() {
+        if index > 0 {
+            level_image = DynamicImage::ImageRgba8(resize_image_tiled(
+                &level_image,
+                Some(&level_width),
+                Some(&level_height),
+                DEFAULT_BAND_HEIGHT,
+                resize,
+            )?);
+        }
+        // Level numbers grow with the resolution
+        let level = levels.len() - 1 - index;
+        let level_dir = tiles_dir.join(level.to_string());
+
+        let columns = level_width.div_ceil(options.tile_size);
+        let rows = level_height.div_ceil(options.tile_size);
+        for column in 0..columns {
+            let column_dir = match options.layout {
+                Layout::Dzi => level_dir.clone(),
+                Layout::Zxy => level_dir.join(column.to_string()),
+            };
+            fs::create_dir_all(&column_dir)?;
+            for row in 0..rows {
+                let (x, tile_width) = tile_span(column, options.tile_size, overlap, level_width);
+                let (y, tile_height) = tile_span(row, options.tile_size, overlap, level_height);
+                let tile = level_image.view(x, y, tile_width, tile_height).to_image();
+                let (_, buffer) = estimate_size_and_encode(&tile, options.format, &options.encode)?;
+                let file_name = match options.layout {
+                    Layout::Dzi => format!("{}_{}.{}", column, row, extension),
+                    Layout::Zxy => format!("{}.{}", row, extension),
+                };
+                let path = column_dir.join(file_name);
+                FileSystem
+                    .write_file(&path, &buffer, false)
+                    .map_err(|e| format!("Failed to write {:?}: {}", path, e))?;
+                tiles += 1;
+            }
+        }
+    }
+
+    if options.layout == Layout::Dzi {
+        let path = dir.join(format!("{}.dzi", name));
+        let descriptor = dzi_descriptor(width, height, options.tile_size, overlap, extension);
+        FileSystem
+            .write_file(&path, descriptor.as_bytes(), true)
+            .map_err(|e| format!("Failed to write {:?}: {}", path, e))?;
+    }
+
+    Ok(Pyramid {
+        levels: levels.len(),
+        tiles,
+    })
+}
+
+/// Returns the start and length of a tile along one axis, including its overlap with the
+/// previous and next tiles.
+fn tile_span(index: u32, tile_size: u32, overlap: u32, length: u32) -> (u32, u32) {
+    let start = (index * tile_size).saturating_sub(overlap);
+    let end = ((index + 1) * tile_size + overlap).min(length);
+    (start, end - start)
+}
+
+/// Returns the XML descriptor of a DZI pyramid.
+fn dzi_descriptor(width: u32, height: u32, tile_size: u32, overlap: u32, format: &str) -> String {
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<Image xmlns=\"http://schemas.microsoft.com/deepzoom/2008\" Format=\"{}\" Overlap=\"{}\" TileSize=\"{}\">\n  <Size Width=\"{}\" Height=\"{}\"/>\n</Image>\n",
+        format, overlap, tile_size, width, height
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgba, RgbaImage};
+    use tempfile::TempDir;
+
+    #[test]
+    fn computes_levels() {
+        let dzi = PyramidOptions::new(Layout::Dzi);
+        let levels = level_dimensions(600, 5, &dzi);
+        // A DZI pyramid has ceil(log2(600)) + 1 levels
+        assert_eq!(levels.len(), 11);
+        assert_eq!(levels[1], (300, 3));
+        assert_eq!(levels[10], (1, 1));
+
+        let zxy = PyramidOptions::new(Layout::Zxy);
+        assert_eq!(
+            level_dimensions(600, 5, &zxy),
+            vec![(600, 5), (300, 3), (150, 2)]
+        );
+        assert_eq!(level_dimensions(100, 100, &zxy), vec![(100, 100)]);
+
+        // Tiles overlap on both sides, except at the edges
+        assert_eq!(tile_span(0, 254, 1, 600), (0, 255));
+        assert_eq!(tile_span(1, 254, 1, 600), (253, 256));
+        assert_eq!(tile_span(2, 254, 1, 600), (507, 93));
+    }
+
+    #[test]
+    fn generates_pyramids() {
+        let dir = TempDir::new().expect("Failed to create a temp dir");
+        let source = DynamicImage::ImageRgba8(RgbaImage::from_fn(300, 200, |x, y| {
+            Rgba([x as u8, y as u8, 128, 255])
+        }));
+
+        let options = PyramidOptions {
+            format: ImageFormat::Png,
+            ..PyramidOptions::new(Layout::Dzi)
+        };
+        let pyramid = generate(
+            source.clone(),
+            dir.path(),
+            "photo",
+            &options,
+            &ResizeOptions::default(),
+        )
+        .unwrap();
+        // 2x1 tiles at 300x200, then one tile for each of the 9 levels below
+        assert_eq!(pyramid.levels, 10);
+        assert_eq!(pyramid.tiles, 2 + 9);
+        let descriptor = fs::read_to_string(dir.path().join("photo.dzi")).unwrap();
+        assert!(descriptor.contains("Format=\"png\" Overlap=\"1\" TileSize=\"254\""));
+        assert!(descriptor.contains("<Size Width=\"300\" Height=\"200\"/>"));
+        let tile = image::open(dir.path().join("photo_files/9/1_0.png")).unwrap();
+        assert_eq!(tile.dimensions(), (300 - 253, 200));
+        let tile = image::open(dir.path().join("photo_files/8/0_0.png")).unwrap();
+        assert_eq!(tile.dimensions(), (150, 100));
+
+        let options = PyramidOptions {
+            tile_size: 128,
+            ..PyramidOptions::new(Layout::Zxy)
+        };
+        let pyramid = generate(
+            source,
+            &dir.path().join("map"),
+            "",
+            &options,
+            &ResizeOptions::default(),
+        )
+        .unwrap();
+        // 3x2 tiles at 300x200, 2x1 at 150x100 and 1 at 75x50
+        assert_eq!(pyramid.levels, 3);
+        assert_eq!(pyramid.tiles, 6 + 2 + 1);
+        let tile = image::open(dir.path().join("map/2/2/1.jpg")).unwrap();
+        assert_eq!(tile.dimensions(), (300 - 256, 200 - 128));
+        assert!(dir.path().join("map/0/0/0.jpg").exists());
+    }
+}
@@ -0,0 +1,377 @@
+//! Color quantization.
+//!
+//! This module reduces an RGBA image to a palette of at most 256 colors, so that it can be
+//! stored as an 8-bit indexed PNG. The palette is computed with the median cut algorithm,
+//! and pixels are mapped to it with one of the `Dither` algorithms, which hide most of the
+//! banding caused by the reduced number of colors.
+
+use image::{Rgba, RgbaImage};
+use std::{collections::HashMap, fmt, str::FromStr};
+
+/// The maximum number of colors of an 8-bit palette.
+pub const MAX_PALETTE_SIZE: usize = 256;
+
+/// The 8x8 Bayer threshold matrix used by ordered dithering, with values from 0 to 63.
+#[rustfmt::skip]
+const BAYER_8X8: [[u8; 8]; 8] = [
+    [ 0, 32,  8, 40,  2, 34, 10, 42],
+    [48, 16, 56, 24, 50, 18, 58, 26],
+    [12, 44,  4, 36, 14, 46,  6, 38],
+    [60, 28, 52, 20, 62, 30, 54, 22],
+    [ 3, 35, 11, 43,  1, 33,  9, 41],
+    [51, 19, 59, 27, 49, 17, 57, 25],
+    [15, 47,  7, 39, 13, 45,  5, 37],
+    [63, 31, 55, 23, 61, 29, 53, 21],
+];
+
+/// How pixels are mapped to the palette.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Dither {
+    /// Each pixel takes the nearest palette color, which shows bands in gradients.
+    None,
+    /// A fixed Bayer pattern is added to the pixels before mapping them. The pattern is
+    /// regular and compresses well, and does not shimmer between the frames of an animation.
+    Ordered,
+    /// Floyd–Steinberg error diffusion, which gives the most faithful colors.
+    #[default]
+    FloydSteinberg,
+}
+
+impl fmt::Display for Dither {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Dither::None => "none",
+            Dither::Ordered => "ordered",
+            Dither::FloydSteinberg => "floyd-steinberg",
+        };
+        f.write_str(name)
+    }
+}
+
+impl FromStr for Dither {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "none" => Ok(Dither::None),
+            "ordered" | "bayer" => Ok(Dither::Ordered),
+            "floyd-steinberg" | "fs" => Ok(Dither::FloydSteinberg),
+            _ => Err(format!("Unknown dithering algorithm '{}'", s)),
+        }
+    }
+}
+
+/// An image whose pixels are indices into a palette.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IndexedImage {
+    /// The width of the image in pixels.
+    pub width: u32,
+    /// The height of the image in pixels.
+    pub height: u32,
+    /// The palette colors.
+    pub palette: Vec<Rgba<u8>>,
+    /// One palette index per pixel, in row-major order.
+    pub indices: Vec<u8>,
+}
+
+/// A color and the number of pixels using it.
+pub(crate) type ColorCount = ([u8; 4], u32);
+
+/// Computes a palette of at most `max_colors` colors with the median cut algorithm.
+///
+/// If the image has no more than `max_colors` distinct colors, the palette contains
+/// exactly those colors.
+///
+/// # Arguments
+///
+/// * `image` - The image to compute the palette for.
+/// * `max_colors` - The maximum number of palette entries.
+pub fn median_cut(image: &RgbaImage, max_colors: usize) -> Vec<Rgba<u8>> {
+    let mut histogram: HashMap<[u8; 4], u32> = HashMap::new();
+    for pixel in image.pixels() {
+        *histogram.entry(pixel.0).or_insert(0) += 1;
+    }
+
+    median_cut_boxes(histogram.into_iter().collect(), max_colors)
+        .iter()
+        .map(|colors| average(colors))
+        .collect()
+}
+
+/// Splits a color histogram into at most `max_colors` boxes with the median cut algorithm.
+///
+/// If the histogram has no more than `max_colors` colors, each box holds a single color.
+pub(crate) fn median_cut_boxes(
+    mut colors: Vec<ColorCount>,
+    max_colors: usize,
+) -> Vec<Vec<ColorCount>> {
+    // Sort for a deterministic result, independent of the hash map's iteration order
+    colors.sort_unstable();
+
+    if colors.len() <= max_colors {
+        return colors.into_iter().map(|color| vec![color]).collect();
+    }
+
+    let mut boxes = vec![colors];
+    while boxes.len() < max_colors {
+        // Split the box with the widest channel range
+        let Some((index, channel, _)) = boxes
+            .iter()
+            .enumerate()
+            .filter(|(_, colors)| colors.len() > 1)
+            .map(|(index, colors)| {
+                let (channel, range) = widest_channel(colors);
+                (index, channel, range)
+            })
+            .max_by_key(|&(_, _, range)| range)
+        else {
+            break;
+        };
+
+        let mut colors = boxes.swap_remove(index);
+        colors.sort_unstable_by_key(|(color, _)| color[channel]);
+
+        // Split at the weighted median
+        let total: u64 = colors.iter().map(|&(_, count)| count as u64).sum();
+        let mut accumulated = 0;
+        let mut split = 1;
+        for (i, &(_, count)) in colors.iter().enumerate() {
+            accumulated += count as u64;
+            if accumulated * 2 >= total {
+                split = (i + 1).clamp(1, colors.len() - 1);
+                break;
+            }
+        }
+
+        let upper = colors.split_off(split);
+        boxes.push(colors);
+        boxes.push(upper);
+    }
+
+    boxes
+}
+
+/// Reduces an image to an indexed image with at most `max_colors` colors.
+///
+/// # Arguments
+///
+/// * `image` - The image to quantize.
+/// * `max_colors` - The maximum number of palette entries, between 2 and 256.
+/// * `dither` - How pixels are mapped to the palette.
+///
+/// # Returns
+///
+/// A `Result` containing the `IndexedImage`.
+///
+/// # Errors
+///
+/// This function will return an error if `max_colors` is not between 2 and 256.
+pub fn quantize(
+    image: &RgbaImage,
+    max_colors: usize,
+    dither: Dither,
+) -> Result<IndexedImage, Box<dyn std::error::Error>> {
+    if !(2..=MAX_PALETTE_SIZE).contains(&max_colors) {
+        return Err(format!(
+            "The palette size must be between 2 and {}, got {}",
+            MAX_PALETTE_SIZE, max_colors
+        )
+        .into());
+    }
+
+    let palette = median_cut(image, max_colors);
+    let (width, height) = image.dimensions();
+    let mut indices = Vec::with_capacity(width as usize * height as usize);
+    let mut cache: HashMap<[u8; 4], u8> = HashMap::new();
+    let mut lookup = |color: [u8; 4]| -> u8 {
+        *cache
+            .entry(color)
+            .or_insert_with(|| nearest(&palette, color))
+    };
+
+    match dither {
+        Dither::None => indices.extend(image.pixels().map(|pixel| lookup(pixel.0))),
+        Dither::Ordered => {
+            // The pattern spans about one step between palette colors along each channel
+            let spread = 255.0 / (palette.len() as f32).cbrt();
+            for (x, y, pixel) in image.enumerate_pixels() {
+                let threshold = BAYER_8X8[y as usize % 8][x as usize % 8] as f32;
+                let offset = ((threshold + 0.5) / 64.0 - 0.5) * spread;
+                let mut wanted = pixel.0;
+                // The alpha channel is left alone, so that opaque pixels stay opaque
+                for value in &mut wanted[..3] {
+                    *value = (*value as f32 + offset).round().clamp(0.0, 255.0) as u8;
+                }
+                indices.push(lookup(wanted));
+            }
+        }
+        Dither::FloydSteinberg => quantize_diffused(image, &palette, &mut indices, lookup),
+    }
+
+    Ok(IndexedImage {
+        width,
+        height,
+        palette,
+        indices,
+    })
+}
+
+/// Maps the pixels of an image to palette indices with Floyd–Steinberg error diffusion.
+fn quantize_diffused(
+    image: &RgbaImage,
+    palette: &[Rgba<u8>],
+    indices: &mut Vec<u8>,
+    mut lookup: impl FnMut([u8; 4]) -> u8,
+) {
+    let (width, height) = image.dimensions();
+    // Quantization errors carried to the current and the next row
+    let row_len = width as usize + 2;
+    let mut current = vec![[0f32; 4]; row_len];
+    let mut next = vec![[0f32; 4]; row_len];
+
+    for y in 0..height {
+        for x in 0..width {
+            let i = x as usize + 1;
+            let pixel = image.get_pixel(x, y).0;
+            let mut wanted = [0u8; 4];
+            for c in 0..4 {
+                wanted[c] = (pixel[c] as f32 + current[i][c]).round().clamp(0.0, 255.0) as u8;
+            }
+
+            let index = lookup(wanted);
+            indices.push(index);
+
+            let chosen = palette[index as usize].0;
+            for c in 0..4 {
+                let error = wanted[c] as f32 - chosen[c] as f32;
+                current[i + 1][c] += error * 7.0 / 16.0;
+                next[i - 1][c] += error * 3.0 / 16.0;
+                next[i][c] += error * 5.0 / 16.0;
+                next[i + 1][c] += error / 16.0;
+            }
+        }
+        std::mem::swap(&mut current, &mut next);
+        next.iter_mut().for_each(|error| *error = [0.0; 4]);
+    }
+}
+
+/// Returns the channel with the widest value range in a box, and that range.
+fn widest_channel(colors: &[ColorCount]) -> (usize, u8) {
+    (0..4)
+        .map(|channel| {
+            let min = colors.iter().map(|(c, _)| c[channel]).min().unwrap_or(0);
+            let max = colors.iter().map(|(c, _)| c[channel]).max().unwrap_or(0);
+            (channel, max - min)
+        })
+        .max_by_key(|&(_, range)| range)
+        .unwrap_or((0, 0))
+}
+
+/// Returns the pixel-count-weighted average color of a box.
+pub(crate) fn average(colors: &[ColorCount]) -> Rgba<u8> {
+    let total: u64 = colors.iter().map(|&(_, count)| count as u64).sum();
+    let mut sums = [0u64; 4];
+    for &(color, count) in colors {
+        for c in 0..4 {
+            sums[c] += color[c] as u64 * count as u64;
+        }
+    }
+    Rgba(sums.map(|sum| ((sum + total / 2) / total.max(1)) as u8))
+}
+
+/// Returns the index of the palette color closest to `color`.
+fn nearest(palette: &[Rgba<u8>], color: [u8; 4]) -> u8 {
+    palette
+        .iter()
+        .enumerate()
+        .min_by_key(|(_, candidate)| {
+            (0..4)
+                .map(|c| {
+                    let diff = candidate.0[c] as i32 - color[c] as i32;
+                    diff * diff
+                })
+                .sum::<i32>()
+        })
+        .map(|(index, _)| index as u8)
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gradient() -> RgbaImage {
+        RgbaImage::from_fn(64, 16, |x, _| Rgba([(x * 4) as u8, 100, 200, 255]))
+    }
+
+    #[test]
+    fn few_colors_are_kept_exactly() {
+        let image = RgbaImage::from_fn(4, 4, |x, _| {
+            if x < 2 {
+                Rgba([255, 0, 0, 255])
+            } else {
+                Rgba([0, 0, 255, 128])
+            }
+        });
+        let indexed = quantize(&image, 16, Dither::FloydSteinberg).unwrap();
+        assert_eq!(indexed.palette.len(), 2);
+
+        for (pixel, &index) in image.pixels().zip(&indexed.indices) {
+            assert_eq!(&indexed.palette[index as usize], pixel);
+        }
+    }
+
+    #[test]
+    fn palette_size_is_capped() {
+        let image = gradient();
+        assert_eq!(median_cut(&image, 8).len(), 8);
+
+        let indexed = quantize(&image, 8, Dither::None).unwrap();
+        assert_eq!(indexed.indices.len(), 64 * 16);
+        assert!(indexed.indices.iter().all(|&index| index < 8));
+    }
+
+    #[test]
+    fn dithering_preserves_average_color() {
+        let image = gradient();
+        let mean =
+            |values: Vec<u8>| values.iter().map(|&v| v as f64).sum::<f64>() / values.len() as f64;
+        let original = mean(image.pixels().map(|p| p[0]).collect());
+
+        for dither in [Dither::FloydSteinberg, Dither::Ordered] {
+            let indexed = quantize(&image, 4, dither).unwrap();
+            let dithered = mean(
+                indexed
+                    .indices
+                    .iter()
+                    .map(|&i| indexed.palette[i as usize][0])
+                    .collect(),
+            );
+            assert!((original - dithered).abs() < 4.0, "{}", dither);
+        }
+    }
+
+    #[test]
+    fn ordered_dithering_keeps_alpha() {
+        let image = RgbaImage::from_fn(16, 16, |x, y| {
+            Rgba([
+                (x * 16) as u8,
+                (y * 16) as u8,
+                0,
+                if x < 8 { 255 } else { 0 },
+            ])
+        });
+        let indexed = quantize(&image, 16, Dither::Ordered).unwrap();
+        for (pixel, &index) in image.pixels().zip(&indexed.indices) {
+            assert_eq!(indexed.palette[index as usize][3], pixel[3]);
+        }
+        assert_eq!("bayer".parse::<Dither>(), Ok(Dither::Ordered));
+        assert_eq!(Dither::None.to_string().parse(), Ok(Dither::None));
+    }
+
+    #[test]
+    fn invalid_palette_size() {
+        assert!(quantize(&gradient(), 1, Dither::None).is_err());
+        assert!(quantize(&gradient(), 257, Dither::None).is_err());
+    }
+}
@@ -0,0 +1,322 @@
+//! Resizing of decoded pixel buffers.
+//!
+//! Pixels that are already decoded, such as camera frames, GPU readbacks or the output of
+//! another decoder, can be resized without wrapping them in a `DynamicImage` and converting
+//! them to RGBA. The buffer is resized in its own layout, with the same resizer, CPU
+//! settings and threads as `resize_image_with`.
+//!
+//! Rows are packed, without padding, and channels have 8 bits. Alpha is straight, not
+//! premultiplied: it is premultiplied while resizing, so that transparent pixels do not
+//! bleed their color into their neighbours.
+//!
+//! Sources are always borrowed. Callers resizing a stream of frames, such as video, can
+//! also keep their output buffer with `resize_raw_into`, so that no frame is copied or
+//! allocated besides the resizer's own scratch memory.
+
+use crate::{context::ResizeContext, error::ResizeError, options::ResizeOptions};
+use fast_image_resize as fr;
+use std::error::Error;
+
+/// The channels of a pixel in a raw buffer, one byte each.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PixelLayout {
+    /// Luma.
+    Gray,
+    /// Luma and alpha.
+    GrayAlpha,
+    /// Red, green and blue.
+    Rgb,
+    /// Red, green, blue and alpha.
+    Rgba,
+}
+
+impl PixelLayout {
+    /// Returns the number of bytes of a pixel.
+    pub fn bytes_per_pixel(self) -> usize {
+        self.pixel_type().size()
+    }
+
+    /// Returns the matching pixel type of `fast_image_resize`.
+    pub(crate) fn pixel_type(self) -> fr::PixelType {
+        match self {
+            PixelLayout::Gray => fr::PixelType::U8,
+            PixelLayout::GrayAlpha => fr::PixelType::U8x2,
+            PixelLayout::Rgb => fr::PixelType::U8x3,
+            PixelLayout::Rgba => fr::PixelType::U8x4,
+        }
+    }
+}
+
+/// A borrowed raw pixel buffer, checked to hold a whole image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RawImage<'a> {
+    pixels: &'a [u8],
+    width: u32,
+    height: u32,
+    layout: PixelLayout,
+}
+
+impl<'a> RawImage<'a> {
+    /// Borrows the pixels of an image, row by row.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if a side is zero
+    /// (`ResizeError::InvalidDimensions`), or if `pixels` does not hold exactly `width` by
+    /// `height` pixels of `layout`.
+    pub fn new(
+        pixels: &'a [u8],
+        width: u32,
+        height: u32,
+        layout: PixelLayout,
+    ) -> Result<Self, Box<dyn Error>> {
+        check_buffer(pixels.len(), width, height, layout)?;
+        Ok(RawImage {
+            pixels,
+            width,
+            height,
+            layout,
+        })
+    }
+
+    /// Returns the pixels.
+    pub fn pixels(&self) -> &'a [u8] {
+        self.pixels
+    }
+
+    /// Returns the width in pixels.
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    /// Returns the height in pixels.
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// Returns the channels of the pixels.
+    pub fn layout(&self) -> PixelLayout {
+        self.layout
+    }
+}
+
+/// Checks that a buffer of `len` bytes holds exactly an image of the given size and layout.
+pub(crate) fn check_buffer(
+    len: usize,
+    width: u32,
+    height: u32,
+    layout: PixelLayout,
+) -> Result<(), Box<dyn Error>> {
+    if width == 0 || height == 0 {
+        return Err(ResizeError::InvalidDimensions { width, height }.into());
+    }
+    let expected = buffer_len(width, height, layout)?;
+    if len != expected {
+        return Err(format!(
+            "Expected {} bytes for a {}x{} {:?} image, got {}",
+            expected, width, height, layout, len
+        )
+        .into());
+    }
+    Ok(())
+}
+
+/// Returns the number of bytes of an image of the given size and layout.
+///
+/// # Errors
+///
+/// This function will return an error if the size does not fit in a `usize`.
+fn buffer_len(width: u32, height: u32, layout: PixelLayout) -> Result<usize, Box<dyn Error>> {
+    (width as usize)
+        .checked_mul(height as usize)
+        .and_then(|pixels| pixels.checked_mul(layout.bytes_per_pixel()))
+        .ok_or_else(|| {
+            format!(
+                "A {}x{} {:?} image is too large to be allocated",
+                width, height, layout
+            )
+            .into()
+        })
+}
+
+/// Resizes a raw pixel buffer to exactly `dst_width` by `dst_height` pixels.
+///
+/// # Arguments
+///
+/// * `pixels` - The pixels of the source, row by row.
+/// * `src_width` - The width of the source in pixels.
+/// * `src_height` - The height of the source in pixels.
+/// * `layout` - The channels of the source pixels, which the output has too.
+/// * `dst_width` - The width of the output in pixels.
+/// * `dst_height` - The height of the output in pixels.
+///
+/// # Returns
+///
+/// A `Result` containing the pixels of the output, row by row, in the same layout.
+///
+/// # Errors
+///
+/// This function will return an error in the same cases as `resize_raw_with`.
+pub fn resize_raw(
+    pixels: &[u8],
+    src_width: u32,
+    src_height: u32,
+    layout: PixelLayout,
+    dst_width: u32,
+    dst_height: u32,
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    resize_raw_with(
+        pixels,
+        src_width,
+        src_height,
+        layout,
+        dst_width,
+        dst_height,
+        &ResizeOptions::default(),
+    )
+}
+
+/// Resizes a raw pixel buffer like `resize_raw`, using the given CPU settings.
+///
+/// Only the threads and CPU extension of `options` are used: the output size is given
+/// exactly, so rounding rules do not apply.
+///
+/// # Errors
+///
+/// This function will return an error if:
+/// - A side of the source or of the output is zero (`ResizeError::InvalidDimensions`).
+/// - `pixels` does not hold exactly `src_width` by `src_height` pixels of `layout`.
+/// - The output is too large to be allocated.
+/// - The selected CPU extension is not available.
+/// - The resizing operation fails.
+pub fn resize_raw_with(
+    pixels: &[u8],
+    src_width: u32,
+    src_height: u32,
+    layout: PixelLayout,
+    dst_width: u32,
+    dst_height: u32,
+    options: &ResizeOptions,
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    let src = RawImage::new(pixels, src_width, src_height, layout)?;
+    let mut dst = vec![0; buffer_len(dst_width, dst_height, layout)?];
+    resize_raw_into(&src, &mut dst, dst_width, dst_height, options)?;
+    Ok(dst)
+}
+
+/// Resizes a borrowed image into a buffer owned by the caller, e.g. to reuse the same
+/// buffer for every frame of a video.
+///
+/// # Arguments
+///
+/// * `src` - The source image.
+/// * `dst` - The buffer receiving the output, in the layout of the source. It must hold
+///   exactly `dst_width` by `dst_height` pixels.
+/// * `dst_width` - The width of the output in pixels.
+/// * `dst_height` - The height of the output in pixels.
+/// * `options` - The threads and CPU extension used by the resizer.
+///
+/// # Errors
+///
+/// This function will return an error if:
+/// - A side of the output is zero (`ResizeError::InvalidDimensions`).
+/// - `dst` does not hold exactly `dst_width` by `dst_height` pixels.
+/// - The selected CPU extension is not available.
+/// - The resizing operation fails.
+pub fn resize_raw_into(
+    src: &RawImage<'_>,
+    dst: &mut [u8],
+    dst_width: u32,
+    dst_height: u32,
+    options: &ResizeOptions,
+) -> Result<(), Box<dyn Error>> {
+    ResizeContext::new(*options)?.resize_into(src, dst, dst_width, dst_height)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resize_image;
+    use image::{DynamicImage, RgbaImage};
+
+    #[test]
+    fn matches_resize_image() {
+        let source = RgbaImage::from_fn(64, 48, |x, y| {
+            image::Rgba([(x * 4) as u8, (y * 5) as u8, ((x + y) * 2) as u8, 200])
+        });
+        let expected = resize_image(DynamicImage::ImageRgba8(source.clone()), Some(&20), None)
+            .unwrap()
+            .into_raw();
+        let resized = resize_raw(source.as_raw(), 64, 48, PixelLayout::Rgba, 20, 15).unwrap();
+        assert_eq!(resized, expected);
+
+        let parallel = ResizeOptions {
+            threads: 4,
+            ..Default::default()
+        };
+        let rgb: Vec<u8> = source
+            .pixels()
+            .flat_map(|pixel| [pixel[0], pixel[1], pixel[2]])
+            .collect();
+        let single = resize_raw(&rgb, 64, 48, PixelLayout::Rgb, 20, 15).unwrap();
+        let threaded = resize_raw_with(&rgb, 64, 48, PixelLayout::Rgb, 20, 15, &parallel).unwrap();
+        assert_eq!(single.len(), 20 * 15 * 3);
+        assert_eq!(threaded.len(), single.len());
+        // Chunk boundaries may round differently
+        assert!(single
+            .iter()
+            .zip(&threaded)
+            .all(|(a, b)| a.abs_diff(*b) <= 1));
+    }
+
+    #[test]
+    fn checks_buffer_sizes() {
+        let gray = vec![100; 10 * 4];
+        let resized = resize_raw(&gray, 10, 4, PixelLayout::Gray, 5, 2).unwrap();
+        assert_eq!(resized, vec![100; 5 * 2]);
+        assert_eq!(
+            resize_raw(&gray, 10, 4, PixelLayout::Gray, 10, 4).unwrap(),
+            gray
+        );
+
+        let error = resize_raw(&gray, 10, 4, PixelLayout::GrayAlpha, 5, 2).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Expected 80 bytes for a 10x4 GrayAlpha image, got 40"
+        );
+        let error = resize_raw(&gray, 10, 4, PixelLayout::Gray, 0, 2).unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<ResizeError>(),
+            Some(&ResizeError::InvalidDimensions {
+                width: 0,
+                height: 2
+            })
+        );
+    }
+
+    #[test]
+    fn resizes_into_reused_buffers() {
+        let options = ResizeOptions::default();
+        let mut dst = vec![0; 8 * 6 * 4];
+        for shade in [0, 90, 255] {
+            let frame = [shade, shade, shade, 255].repeat(32 * 24);
+            let src = RawImage::new(&frame, 32, 24, PixelLayout::Rgba).unwrap();
+            resize_raw_into(&src, &mut dst, 8, 6, &options).unwrap();
+            assert_eq!(dst, [shade, shade, shade, 255].repeat(8 * 6));
+        }
+
+        let frame: Vec<u8> = (0..32 * 24 * 3).map(|i| (i % 251) as u8).collect();
+        let src = RawImage::new(&frame, 32, 24, PixelLayout::Rgb).unwrap();
+        assert_eq!((src.width(), src.height()), (32, 24));
+        let mut dst = vec![0; 10 * 7 * 3];
+        resize_raw_into(&src, &mut dst, 10, 7, &options).unwrap();
+        assert_eq!(
+            dst,
+            resize_raw(&frame, 32, 24, PixelLayout::Rgb, 10, 7).unwrap()
+        );
+        let mut short = vec![0; 10 * 7 * 3 - 1];
+        assert!(resize_raw_into(&src, &mut short, 10, 7, &options).is_err());
+        assert!(resize_raw_into(&src, &mut short, u32::MAX, u32::MAX, &options).is_err());
+        assert!(RawImage::new(&frame[1..], 32, 24, PixelLayout::Rgb).is_err());
+    }
+}
@@ -0,0 +1,1045 @@
+//! Recipes.
+//!
+//! A recipe describes how images are processed, from the crop to the encoder settings, in
+//! a YAML or TOML file that can be kept under version control next to the assets it
+//! applies to. It lists the steps to run between the decode and encode stages, in order,
+//! and the encoder settings:
+//!
+//! ```yaml
+//! # web-thumbs.yaml
+//! steps:
+//!   - crop: { width: 400, height: 300, gravity: smart }
+//!   - sharpen: { sigma: 0.8 }
+//!   - watermark: { image: logo.png, position: southeast, opacity: 0.6 }
+//! encode:
+//!   format: jpeg
+//!   quality: 80
+//!   progressive: true
+//! ```
+//!
+//! The same recipe in TOML:
+//!
+//! ```toml
+//! [[steps]]
+//! crop = { width = 400, height = 300, gravity = "smart" }
+//! [[steps]]
+//! sharpen = { sigma = 0.8 }
+//! [[steps]]
+//! watermark = { image = "logo.png", position = "southeast", opacity = 0.6 }
+//!
+//! [encode]
+//! format = "jpeg"
+//! quality = 80
+//! progressive = true
+//! ```
+//!
+//! The steps, with their settings and defaults, are:
+//!
+//! - `resize`: `width` and `height`, at least one of them.
+//! - `crop`: a cover crop (see `crop::cover_crop`) to `width` and `height`, keeping the
+//!   part given by `gravity` (`center`).
+//! - `trim`: crops uniform borders with a color tolerance of `fuzz` percent (`0`).
+//! - `denoise`: a median filter of `strength` 1 to 5 (`1`).
+//! - `sharpen`: an unsharp mask with a blur of `sigma` pixels (`1.0`), ignoring differences
+//!   below `threshold` (`0`).
+//! - `watermark`: blends an `image`, or a line of `text` drawn at `scale` (`2`) in `color`
+//!   (`#ffffff`), at `position` (`southeast`) with `opacity` (`1.0`), `margin` pixels (`8`)
+//!   from the edges. Image paths are relative to the recipe.
+//! - `tone`: a tone `preset` (`sepia`, `cool` or `warm`).
+//! - `vignette`: darkens the corners by `strength` percent.
+//! - `negate`: inverts the colors.
+//!
+//! A recipe has at most one `resize` or `crop` step, which gives the output size; without
+//! one, images keep their size. The encoder settings are `format`, `quality` (of JPEG and
+//! AVIF outputs), `png-compression`, `progressive`, `chroma-subsampling` (`"444"`, `"422"`
+//! or `"420"`), `png-filter` and `avif-speed`. Options given on the command line take
+//! precedence over the recipe.
+//!
+//! A recipe can also list `outputs`, to produce several files from each image with a single
+//! decode (see `Recipe::process_file`). The steps of the recipe run once, then each output
+//! runs its own `steps`, with at most one `resize` or `crop`, and is encoded with its own
+//! `encode` settings, falling back to the ones of the recipe. An output can instead be a
+//! `blurhash` placeholder, with `x` and `y` components, or an `lqip` placeholder, a data URI
+//! of a tiny blurred image with a largest side of `size` pixels (16):
+//!
+//! ```yaml
+//! outputs:
+//!   - name: large
+//!     steps: [{ resize: { width: 1600 } }]
+//!   - name: thumb
+//!     steps: [{ crop: { width: 400, height: 400 } }]
+//!     encode: { format: jpeg, quality: 70 }
+//!   - name: placeholder
+//!     blurhash: { x: 4, y: 3 }
+//!   - name: inline
+//!     lqip: { size: 16 }
+//! ```
+
+use crate::{
+    blurhash,
+    crop::{CoverCropStage, Gravity},
+    detect_format_in,
+    encode::{ChromaSubsampling, EncodeOptions, PngFilter},
+    filters::{self, DenoiseStage, Tone, MAX_DENOISE_STRENGTH},
+    format::OutputFormat,
+    infer_format_from_path, lqip, manifest,
+    montage::parse_color,
+    options::JobOptions,
+    pipeline::{DecodeStage, EncodeStage, ImageStage, Pipeline, PipelineState, ResizeStage},
+    save_format_and_path, save_to, trim, verify_if_requested,
+    watermark::{Watermark, WatermarkStage},
+    ImageInfo, SourceInfo,
+};
+use image::{GenericImageView, Rgba};
+use serde_json::{Map, Value};
+use std::{
+    error::Error,
+    fs,
+    ops::RangeInclusive,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Instant,
+};
+
+/// What a watermark step draws.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Mark {
+    /// An image file.
+    Image(PathBuf),
+    /// A line of text drawn with the built-in font.
+    Text {
+        text: String,
+        scale: u32,
+        color: Rgba<u8>,
+    },
+}
+
+/// A step of a recipe.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Step {
+    Resize {
+        width: Option<u32>,
+        height: Option<u32>,
+    },
+    Crop {
+        width: Option<u32>,
+        height: Option<u32>,
+        gravity: Gravity,
+    },
+    Trim {
+        fuzz: f32,
+    },
+    Denoise {
+        strength: u32,
+    },
+    Sharpen {
+        sigma: f32,
+        threshold: i32,
+    },
+    Watermark {
+        mark: Mark,
+        position: Gravity,
+        opacity: f32,
+        margin: u32,
+    },
+    Tone(Tone),
+    Vignette {
+        strength: u8,
+    },
+    Negate,
+}
+
+/// Encoder settings of a recipe or of one of its outputs.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EncodeSettings {
+    /// The output format (`jpeg`, `png`, `bmp`, `tga`, `webp` or `avif`).
+    pub format: Option<String>,
+    /// The quality of JPEG and AVIF outputs, from 1 to 100.
+    pub quality: Option<u8>,
+    /// The zlib compression level of PNG outputs, from 0 to 9.
+    pub png_compression: Option<u8>,
+    /// Whether JPEGs are progressive and PNGs interlaced.
+    pub progressive: Option<bool>,
+    /// The chroma subsampling of JPEG outputs.
+    pub chroma_subsampling: Option<ChromaSubsampling>,
+    /// The scanline filter of PNG outputs.
+    pub png_filter: Option<PngFilter>,
+    /// The speed of the AVIF encoder, from 1 to 10.
+    pub avif_speed: Option<u8>,
+}
+
+impl EncodeSettings {
+    /// Returns these settings, with the ones not set taken from `defaults`.
+    pub fn or(&self, defaults: &EncodeSettings) -> EncodeSettings {
+        EncodeSettings {
+            format: self.format.clone().or_else(|| defaults.format.clone()),
+            quality: self.quality.or(defaults.quality),
+            png_compression: self.png_compression.or(defaults.png_compression),
+            progressive: self.progressive.or(defaults.progressive),
+            chroma_subsampling: self.chroma_subsampling.or(defaults.chroma_subsampling),
+            png_filter: self.png_filter.or(defaults.png_filter),
+            avif_speed: self.avif_speed.or(defaults.avif_speed),
+        }
+    }
+
+    /// Returns `options` completed with these settings. Settings already set in `options`,
+    /// e.g. from the command line, are kept.
+    pub fn apply_to(&self, options: &EncodeOptions) -> EncodeOptions {
+        EncodeOptions {
+            progressive: options.progressive || self.progressive.unwrap_or(false),
+            png_compression: options.png_compression.or(self.png_compression),
+            jpeg_quality: options.jpeg_quality.or(self.quality),
+            jpeg_subsampling: options.jpeg_subsampling.or(self.chroma_subsampling),
+            png_filter: options.png_filter.or(self.png_filter),
+            avif_quality: options.avif_quality.or(self.quality),
+            avif_speed: options.avif_speed.or(self.avif_speed),
+            ..options.clone()
+        }
+    }
+}
+
+/// An output of a recipe with several outputs.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Output {
+    /// The name of the output, appended to the name of the output file.
+    pub name: String,
+    /// What the output is.
+    pub kind: OutputKind,
+}
+
+/// What an output of a recipe is.
+#[derive(Debug, Clone, PartialEq)]
+pub enum OutputKind {
+    /// An image file, processed with its own steps and encoder settings.
+    Image {
+        steps: Vec<Step>,
+        encode: EncodeSettings,
+    },
+    /// A BlurHash placeholder (see `blurhash::encode`), with its numbers of components.
+    Blurhash {
+        x_components: u32,
+        y_components: u32,
+    },
+    /// A low-quality image placeholder (see `lqip::data_uri`), with its largest side.
+    Lqip { size: u32 },
+}
+
+/// What `Recipe::process_file` produced for one input.
+#[derive(Debug, Clone, Default)]
+pub struct Produced {
+    /// The images saved, as output names and image information, in the order of the outputs.
+    pub images: Vec<(String, ImageInfo)>,
+    /// The BlurHash outputs, as output names and BlurHash strings.
+    pub blurhashes: Vec<(String, String)>,
+    /// The LQIP outputs, as output names and data URIs.
+    pub lqips: Vec<(String, String)>,
+}
+
+/// A parsed recipe.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Recipe {
+    /// The steps run between the decode and encode stages, in order. With several
+    /// outputs, the steps run once, before the steps of each output.
+    pub steps: Vec<Step>,
+    /// The encoder settings, which are also the defaults of the outputs.
+    pub encode: EncodeSettings,
+    /// The outputs produced from each input. If empty, each input has a single output,
+    /// produced by the pipeline returned by `pipeline`.
+    pub outputs: Vec<Output>,
+}
+
+impl Recipe {
+    /// Reads a recipe, as TOML if its extension is `.toml` and as YAML otherwise. The paths
+    /// of watermark images are resolved against the directory of the recipe.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the file cannot be read or is invalid.
+    pub fn load(path: &Path) -> Result<Recipe, Box<dyn Error>> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read the recipe {:?}: {}", path, e))?;
+        let is_toml = path
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("toml"));
+        let recipe = if is_toml {
+            Recipe::from_toml(&text)
+        } else {
+            Recipe::from_yaml(&text)
+        };
+        let mut recipe = recipe.map_err(|e| format!("Invalid recipe {:?}: {}", path, e))?;
+        let dir = path.parent().unwrap_or(Path::new(""));
+        for step in recipe.all_steps_mut() {
+            if let Step::Watermark {
+                mark: Mark::Image(image),
+                ..
+            } = step
+            {
+                *image = dir.join(&*image);
+            }
+        }
+        Ok(recipe)
+    }
+
+    /// Parses a YAML recipe.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the YAML is invalid or does not describe a
+    /// valid recipe.
+    pub fn from_yaml(text: &str) -> Result<Recipe, Box<dyn Error>> {
+        Ok(Recipe::from_value(&serde_yaml::from_str(text)?)?)
+    }
+
+    /// Parses a TOML recipe.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the TOML is invalid or does not describe a
+    /// valid recipe.
+    pub fn from_toml(text: &str) -> Result<Recipe, Box<dyn Error>> {
+        Ok(Recipe::from_value(&toml::from_str(text)?)?)
+    }
+
+    /// Returns the width and height of the `resize` or `crop` step, if there is one.
+    pub fn size(&self) -> Option<(Option<u32>, Option<u32>)> {
+        size_of(&self.steps)
+    }
+
+    /// Returns the files read by the steps, i.e. the watermark images.
+    pub fn files(&self) -> Vec<&Path> {
+        let mut files = Vec::new();
+        for step in self.all_steps() {
+            if let Step::Watermark {
+                mark: Mark::Image(path),
+                ..
+            } = step
+            {
+                files.push(path.as_path());
+            }
+        }
+        files
+    }
+
+    /// Builds the pipeline running the steps between the standard decode and encode stages.
+    ///
+    /// The size of the `resize` or `crop` step is not part of the pipeline: it is read from
+    /// the pipeline state, where it is set from `size`. The steps of the outputs are left
+    /// out; recipes with outputs are run with `process_file`.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if a watermark image cannot be read.
+    pub fn pipeline(&self) -> Result<Pipeline, Box<dyn Error>> {
+        let mut pipeline = Pipeline::new().with_stage(DecodeStage);
+        push_steps(&mut pipeline, &self.steps)?;
+        Ok(pipeline.with_stage(EncodeStage))
+    }
+
+    /// Returns `options` completed with the encoder settings of the recipe. Settings already
+    /// set in `options`, e.g. from the command line, are kept.
+    pub fn encode_options(&self, options: &EncodeOptions) -> EncodeOptions {
+        self.encode.apply_to(options)
+    }
+
+    /// Produces every output of the recipe from an image file, decoding it only once.
+    ///
+    /// The image is decoded and processed with the shared steps, then each output starts
+    /// from a copy of the result. Image outputs are saved next to `output_path`, which only
+    /// gives their directory and base name: an output named `thumb` of `out/photo.jpg` is
+    /// saved as `out/photo_thumb.jpg`, with the extension of its format. Outputs are not
+    /// cached.
+    ///
+    /// # Arguments
+    ///
+    /// * `input` - The path of the image file.
+    /// * `output_path` - The base path of the outputs. Its extension gives the format of the
+    ///   outputs without one.
+    /// * `options` - The job options. Their encoder settings take precedence over the ones
+    ///   of the recipe, as in `encode_options`.
+    ///
+    /// # Returns
+    ///
+    /// The saved images, the BlurHash strings and the LQIP data URIs.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error in the same cases as `resize_file_with`, at the
+    /// first output that fails. Earlier outputs are kept.
+    pub fn process_file(
+        &self,
+        input: &Path,
+        output_path: &Path,
+        options: &JobOptions,
+    ) -> Result<Produced, Box<dyn Error>> {
+        let start = Instant::now();
+        options.cancel.check()?;
+        options
+            .input_policy
+            .check(detect_format_in(&*options.storage, input)?)?;
+        let source = SourceInfo::read_from(&*options.storage, input)?;
+
+        let mut shared = Pipeline::new().with_stage(DecodeStage);
+        push_steps(&mut shared, &self.steps)?;
+        let (width, height) = self.size().unwrap_or_default();
+        let mut state = PipelineState::new(input, width, height);
+        shared.run(&mut state, options)?;
+
+        let stem = output_path
+            .file_stem()
+            .ok_or("Output path has no file name")?
+            .to_string_lossy();
+        let extension = output_path
+            .extension()
+            .unwrap_or_default()
+            .to_string_lossy();
+        let mut produced = Produced::default();
+        for output in &self.outputs {
+            options.cancel.check()?;
+            let image = state
+                .image
+                .as_ref()
+                .ok_or("The shared steps lost the image")?;
+            match &output.kind {
+                OutputKind::Blurhash {
+                    x_components,
+                    y_components,
+                } => {
+                    let thumbnail = image
+                        .thumbnail(blurhash::THUMBNAIL_SIZE, blurhash::THUMBNAIL_SIZE)
+                        .into_rgba8();
+                    let hash = blurhash::encode(&thumbnail, *x_components, *y_components)?;
+                    produced.blurhashes.push((output.name.clone(), hash));
+                }
+                OutputKind::Lqip { size } => {
+                    let uri = lqip::data_uri(image, *size)?;
+                    produced.lqips.push((output.name.clone(), uri));
+                }
+                OutputKind::Image { steps, encode } => {
+                    let encode = encode.or(&self.encode);
+                    let path = output_path
+                        .with_file_name(format!("{}_{}.{}", stem, output.name, extension));
+                    let (save_format, path) = save_format_and_path(
+                        &path,
+                        encode.format.as_ref(),
+                        &options.encoders,
+                        || infer_format_from_path(Some(output_path)),
+                    )?;
+                    let path = if options.hash_names {
+                        path
+                    } else {
+                        options.overwrite.resolve_in(&*options.storage, &path)?
+                    };
+
+                    let mut pipeline = Pipeline::new();
+                    push_steps(&mut pipeline, steps)?;
+                    pipeline.push(EncodeStage);
+                    let (width, height) = size_of(steps).unwrap_or_default();
+                    let mut output_state = PipelineState {
+                        width,
+                        height,
+                        image: Some(image.clone()),
+                        format: Some(save_format),
+                        encoded: None,
+                        ..state.clone()
+                    };
+                    let output_options = JobOptions {
+                        encode: encode.apply_to(&options.encode),
+                        ..options.clone()
+                    };
+                    pipeline.run(&mut output_state, &output_options)?;
+                    options.cancel.check()?;
+
+                    let encoded = output_state
+                        .encoded
+                        .ok_or("The pipeline did not produce an encoded image")?;
+                    let (new_width, new_height) = output_state
+                        .image
+                        .as_ref()
+                        .map(|image| image.dimensions())
+                        .ok_or("The pipeline did not keep the final image")?;
+                    let path = if options.hash_names {
+                        manifest::hashed_path(&path, &manifest::content_hash(&encoded))
+                    } else {
+                        path
+                    };
+                    let info = save_to(
+                        &*options.storage,
+                        encoded,
+                        new_width,
+                        new_height,
+                        &path,
+                        save_format,
+                        !options.direct_write,
+                    )?;
+                    options.xattrs.apply(input, &info.path)?;
+                    let info = ImageInfo {
+                        verification: verify_if_requested(options, &info)?,
+                        source: Some(source.clone()),
+                        duration: Some(start.elapsed()),
+                        ..info
+                    };
+                    produced.images.push((output.name.clone(), info));
+                }
+            }
+        }
+        Ok(produced)
+    }
+
+    /// Returns the steps of the recipe and of its outputs.
+    fn all_steps(&self) -> impl Iterator<Item = &Step> {
+        let outputs = self.outputs.iter().flat_map(|output| match &output.kind {
+            OutputKind::Image { steps, .. } => steps.as_slice(),
+            OutputKind::Blurhash { .. } | OutputKind::Lqip { .. } => &[],
+        });
+        self.steps.iter().chain(outputs)
+    }
+
+    /// Returns the steps of the recipe and of its outputs, for modification.
+    fn all_steps_mut(&mut self) -> impl Iterator<Item = &mut Step> {
+        let outputs = self
+            .outputs
+            .iter_mut()
+            .flat_map(|output| match &mut output.kind {
+                OutputKind::Image { steps, .. } => steps.as_mut_slice(),
+                OutputKind::Blurhash { .. } | OutputKind::Lqip { .. } => &mut [],
+            });
+        self.steps.iter_mut().chain(outputs)
+    }
+
+    /// Interprets a parsed YAML or TOML document.
+    fn from_value(value: &Value) -> Result<Recipe, String> {
+        let root = Settings::new("The recipe", value, &["steps", "encode", "outputs"])?;
+        let mut recipe = Recipe::default();
+        if let Some(steps) = root.get("steps") {
+            recipe.steps = parse_steps(steps)?;
+        }
+        if let Some(encode) = root.get("encode") {
+            recipe.encode = parse_encode(encode)?;
+        }
+        if let Some(outputs) = root.get("outputs") {
+            let outputs = outputs.as_array().ok_or("'outputs' must be a list")?;
+            for (index, output) in outputs.iter().enumerate() {
+                let output =
+                    parse_output(output).map_err(|e| format!("Output {}: {}", index + 1, e))?;
+                if recipe.outputs.iter().any(|other| other.name == output.name) {
+                    return Err(format!("Several outputs are named '{}'", output.name));
+                }
+                recipe.outputs.push(output);
+            }
+        }
+        Ok(recipe)
+    }
+}
+
+/// Returns the width and height of the `resize` or `crop` step of a list of steps.
+fn size_of(steps: &[Step]) -> Option<(Option<u32>, Option<u32>)> {
+    steps.iter().find_map(|step| match *step {
+        Step::Resize { width, height } | Step::Crop { width, height, .. } => Some((width, height)),
+        _ => None,
+    })
+}
+
+/// Appends the stages running a list of steps to a pipeline.
+///
+/// # Errors
+///
+/// This function will return an error if a watermark image cannot be read.
+fn push_steps(pipeline: &mut Pipeline, steps: &[Step]) -> Result<(), Box<dyn Error>> {
+    for step in steps {
+        match *step {
+            Step::Resize { .. } => pipeline.push(ResizeStage),
+            Step::Crop { gravity, .. } => pipeline.push(CoverCropStage::new(gravity)),
+            Step::Trim { fuzz } => pipeline.push(ImageStage::new("trim", move |image| {
+                Ok(trim::trim(image, fuzz))
+            })),
+            Step::Denoise { strength } => pipeline.push(DenoiseStage::new(strength)?),
+            Step::Sharpen { sigma, threshold } => pipeline
+                .push(ImageStage::new("sharpen", move |image| {
+                    Ok(filters::sharpen(&image.into_rgba8(), sigma, threshold).into())
+                })),
+            Step::Watermark {
+                ref mark,
+                position,
+                opacity,
+                margin,
+            } => {
+                let mark = match mark {
+                    Mark::Image(path) => image::open(path)
+                        .map_err(|e| format!("Failed to read the watermark {:?}: {}", path, e))?
+                        .into_rgba8(),
+                    Mark::Text { text, scale, color } => Watermark::text_mark(text, *scale, *color),
+                };
+                let watermark = Watermark::new(mark, position, opacity, margin)?;
+                pipeline.push(WatermarkStage::new(watermark));
+            }
+            Step::Tone(tone) => pipeline.push(ImageStage::new("tone", move |image| {
+                let mut image = image.into_rgba8();
+                filters::apply_tone(&mut image, tone);
+                Ok(image.into())
+            })),
+            Step::Vignette { strength } => {
+                pipeline.push(ImageStage::new("vignette", move |image| {
+                    let mut image = image.into_rgba8();
+                    filters::vignette(&mut image, strength as f32 / 100.0);
+                    Ok(image.into())
+                }))
+            }
+            Step::Negate => pipeline.push(ImageStage::new("negate", |image| {
+                let mut image = image.into_rgba8();
+                filters::negate(&mut image);
+                Ok(image.into())
+            })),
+        }
+    }
+    Ok(())
+}
+
+/// Parses a list of steps, with at most one `resize` or `crop` step.
+fn parse_steps(value: &Value) -> Result<Vec<Step>, String> {
+    let values = value.as_array().ok_or("'steps' must be a list")?;
+    let mut steps = Vec::with_capacity(values.len());
+    for (index, step) in values.iter().enumerate() {
+        steps.push(parse_step(step).map_err(|e| format!("Step {}: {}", index + 1, e))?);
+    }
+    let sizes = steps
+        .iter()
+        .filter(|step| matches!(step, Step::Resize { .. } | Step::Crop { .. }))
+        .count();
+    if sizes > 1 {
+        return Err("There can be only one 'resize' or 'crop' step".to_string());
+    }
+    Ok(steps)
+}
+
+/// Parses encoder settings.
+fn parse_encode(value: &Value) -> Result<EncodeSettings, String> {
+    let encode = Settings::new(
+        "'encode'",
+        value,
+        &[
+            "format",
+            "quality",
+            "png-compression",
+            "progressive",
+            "chroma-subsampling",
+            "png-filter",
+            "avif-speed",
+        ],
+    )?;
+    let format = match encode.text("format")? {
+        Some(format) => Some(format.parse::<OutputFormat>()?.to_string()),
+        None => None,
+    };
+    Ok(EncodeSettings {
+        format,
+        quality: encode.integer("quality", 1..=100)?.map(|q| q as u8),
+        png_compression: encode.integer("png-compression", 0..=9)?.map(|c| c as u8),
+        progressive: encode.boolean("progressive")?,
+        chroma_subsampling: encode.parse("chroma-subsampling")?,
+        png_filter: encode.parse("png-filter")?,
+        avif_speed: encode.integer("avif-speed", 1..=10)?.map(|s| s as u8),
+    })
+}
+
+/// Parses an output: a table with a `name`, and either `steps` and `encode` settings,
+/// `blurhash` settings or `lqip` settings.
+fn parse_output(value: &Value) -> Result<Output, String> {
+    let output = Settings::new(
+        "The output",
+        value,
+        &["name", "steps", "encode", "blurhash", "lqip"],
+    )?;
+    let name = output.text("name")?.ok_or("The output has no name")?;
+    let valid = |c: char| c.is_ascii_alphanumeric() || c == '-' || c == '_';
+    if name.is_empty() || !name.chars().all(valid) {
+        return Err(format!(
+            "Invalid output name '{}': use letters, digits, '-' and '_'",
+            name
+        ));
+    }
+    let placeholder = ["blurhash", "lqip"]
+        .into_iter()
+        .find(|key| output.get(key).is_some());
+    if let Some(key) = placeholder {
+        if output.get("steps").is_some() || output.get("encode").is_some() {
+            return Err(format!(
+                "A '{}' output cannot have steps or encoder settings",
+                key
+            ));
+        }
+    }
+    let kind = match (output.get("blurhash"), output.get("lqip")) {
+        (Some(_), Some(_)) => {
+            return Err("An output cannot be both a 'blurhash' and an 'lqip'".to_string());
+        }
+        (Some(blurhash), None) => {
+            let blurhash = Settings::new("'blurhash'", blurhash, &["x", "y"])?;
+            OutputKind::Blurhash {
+                x_components: blurhash.integer("x", 1..=9)?.unwrap_or(4),
+                y_components: blurhash.integer("y", 1..=9)?.unwrap_or(3),
+            }
+        }
+        (None, Some(settings)) => {
+            let settings = Settings::new("'lqip'", settings, &["size"])?;
+            OutputKind::Lqip {
+                size: settings
+                    .integer("size", 1..=lqip::MAX_SIZE)?
+                    .unwrap_or(lqip::DEFAULT_SIZE),
+            }
+        }
+        (None, None) => OutputKind::Image {
+            steps: match output.get("steps") {
+                Some(steps) => parse_steps(steps)?,
+                None => Vec::new(),
+            },
+            encode: match output.get("encode") {
+                Some(encode) => parse_encode(encode)?,
+                None => EncodeSettings::default(),
+            },
+        },
+    };
+    Ok(Output {
+        name: name.to_string(),
+        kind,
+    })
+}
+
+/// Parses a step: a table with a single key, the name of the step, whose value holds its
+/// settings.
+fn parse_step(value: &Value) -> Result<Step, String> {
+    let (name, value) = match value {
+        Value::Object(map) if map.len() == 1 => map.iter().next().unwrap(),
+        // A step without settings may be given by its name alone
+        Value::String(name) => (name, &Value::Null),
+        _ => return Err("Expected a table with a single key, the name of the step".to_string()),
+    };
+    let keys: &[&str] = match name.as_str() {
+        "resize" => &["width", "height"],
+        "crop" => &["width", "height", "gravity"],
+        "trim" => &["fuzz"],
+        "denoise" => &["strength"],
+        "sharpen" => &["sigma", "threshold"],
+        "watermark" => &[
+            "image", "text", "scale", "color", "position", "opacity", "margin",
+        ],
+        "tone" => &["preset"],
+        "vignette" => &["strength"],
+        "negate" => &[],
+        _ => return Err(format!("Unknown step '{}'", name)),
+    };
+    let settings = Settings::new(&format!("'{}'", name), value, keys)?;
+    let size = |settings: &Settings| -> Result<_, String> {
+        let width = settings.integer("width", 1..=u32::MAX)?;
+        let height = settings.integer("height", 1..=u32::MAX)?;
+        if width.is_none() && height.is_none() {
+            return Err(format!("'{}' needs a width or a height", name));
+        }
+        Ok((width, height))
+    };
+
+    Ok(match name.as_str() {
+        "resize" => {
+            let (width, height) = size(&settings)?;
+            Step::Resize { width, height }
+        }
+        "crop" => {
+            let (width, height) = size(&settings)?;
+            Step::Crop {
+                width,
+                height,
+                gravity: settings.parse("gravity")?.unwrap_or_default(),
+            }
+        }
+        "trim" => Step::Trim {
+            fuzz: settings.number("fuzz", 0.0..=100.0)?.unwrap_or(0.0) as f32,
+        },
+        "denoise" => Step::Denoise {
+            strength: settings
+                .integer("strength", 1..=MAX_DENOISE_STRENGTH)?
+                .unwrap_or(1),
+        },
+        "sharpen" => Step::Sharpen {
+            sigma: settings.number("sigma", 0.1..=100.0)?.unwrap_or(1.0) as f32,
+            threshold: settings.integer("threshold", 0..=255)?.unwrap_or(0) as i32,
+        },
+        "watermark" => {
+            let mark = match (settings.text("image")?, settings.text("text")?) {
+                (Some(image), None) => Mark::Image(PathBuf::from(image)),
+                (None, Some(text)) => Mark::Text {
+                    text: text.to_string(),
+                    scale: settings.integer("scale", 1..=64)?.unwrap_or(2),
+                    color: match settings.text("color")? {
+                        Some(color) => parse_color(color)?,
+                        None => Rgba([255, 255, 255, 255]),
+                    },
+                },
+                _ => return Err("'watermark' needs either an image or a text".to_string()),
+            };
+            Step::Watermark {
+                mark,
+                position: settings.parse("position")?.unwrap_or(Gravity::SouthEast),
+                opacity: settings.number("opacity", 0.0..=1.0)?.unwrap_or(1.0) as f32,
+                margin: settings.integer("margin", 0..=u32::MAX)?.unwrap_or(8),
+            }
+        }
+        "tone" => Step::Tone(settings.parse("preset")?.ok_or("'tone' needs a preset")?),
+        "vignette" => Step::Vignette {
+            strength: settings
+                .integer("strength", 0..=100)?
+                .ok_or("'vignette' needs a strength")? as u8,
+        },
+        _ => Step::Negate,
+    })
+}
+
+/// The settings of a table of the recipe, checked against the keys it accepts.
+struct Settings<'a> {
+    what: String,
+    map: Option<&'a Map<String, Value>>,
+}
+
+impl<'a> Settings<'a> {
+    /// Reads the table `value`, which may be null for a table without settings.
+    fn new(what: &str, value: &'a Value, keys: &[&str]) -> Result<Self, String> {
+        let map = match value {
+            Value::Null => None,
+            Value::Object(map) => Some(map),
+            _ => return Err(format!("{} must be a table", what)),
+        };
+        let unknown = map
+            .into_iter()
+            .flat_map(|map| map.keys())
+            .find(|key| !keys.contains(&key.as_str()));
+        if let Some(key) = unknown {
+            return Err(format!("{} has no setting '{}'", what, key));
+        }
+        Ok(Settings {
+            what: what.to_string(),
+            map,
+        })
+    }
+
+    /// Returns the value of a setting, treating null as missing.
+    fn get(&self, key: &str) -> Option<&'a Value> {
+        self.map
+            .and_then(|map| map.get(key))
+            .filter(|value| !value.is_null())
+    }
+
+    fn invalid(&self, key: &str, expected: &str) -> String {
+        format!("The {} of {} must be {}", key, self.what, expected)
+    }
+
+    fn number(&self, key: &str, range: RangeInclusive<f64>) -> Result<Option<f64>, String> {
+        let expected = || format!("a number from {} to {}", range.start(), range.end());
+        self.get(key)
+            .map(|value| match value.as_f64() {
+                Some(number) if range.contains(&number) => Ok(number),
+                _ => Err(self.invalid(key, &expected())),
+            })
+            .transpose()
+    }
+
+    fn integer(&self, key: &str, range: RangeInclusive<u32>) -> Result<Option<u32>, String> {
+        let expected = || format!("an integer from {} to {}", range.start(), range.end());
+        self.get(key)
+            .map(|value| match value.as_u64().map(u32::try_from) {
+                Some(Ok(integer)) if range.contains(&integer) => Ok(integer),
+                _ => Err(self.invalid(key, &expected())),
+            })
+            .transpose()
+    }
+
+    fn boolean(&self, key: &str) -> Result<Option<bool>, String> {
+        self.get(key)
+            .map(|value| {
+                value
+                    .as_bool()
+                    .ok_or_else(|| self.invalid(key, "true or false"))
+            })
+            .transpose()
+    }
+
+    fn text(&self, key: &str) -> Result<Option<&'a str>, String> {
+        self.get(key)
+            .map(|value| value.as_str().ok_or_else(|| self.invalid(key, "a string")))
+            .transpose()
+    }
+
+    fn parse<T: FromStr<Err = String>>(&self, key: &str) -> Result<Option<T>, String> {
+        self.text(key)?.map(str::parse).transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{options::JobOptions, pipeline::PipelineState};
+    use image::{ImageFormat, RgbaImage};
+    use tempfile::TempDir;
+
+    const YAML: &str = "
+steps:
+  - crop: { width: 20, height: 20, gravity: west }
+  - sharpen: { sigma: 0.5 }
+  - watermark: { text: 'hi', position: northwest, opacity: 0.5, margin: 0 }
+  - negate
+encode:
+  format: PNG
+  quality: 80
+  png-filter: adaptive
+";
+
+    const TOML: &str = r#"
+[[steps]]
+crop = { width = 20, height = 20, gravity = "west" }
+[[steps]]
+sharpen = { sigma = 0.5 }
+[[steps]]
+watermark = { text = "hi", position = "northwest", opacity = 0.5, margin = 0 }
+[[steps]]
+negate = {}
+
+[encode]
+format = "PNG"
+quality = 80
+png-filter = "adaptive"
+"#;
+
+    #[test]
+    fn parses_yaml_and_toml() {
+        let recipe = Recipe::from_yaml(YAML).unwrap();
+        assert_eq!(Recipe::from_toml(TOML).unwrap(), recipe);
+        assert_eq!(recipe.size(), Some((Some(20), Some(20))));
+        assert_eq!(recipe.encode.format.as_deref(), Some("png"));
+        assert_eq!(recipe.steps.len(), 4);
+        assert_eq!(recipe.steps[3], Step::Negate);
+
+        let options = EncodeOptions {
+            jpeg_quality: Some(90),
+            ..Default::default()
+        };
+        assert_eq!(recipe.encode_options(&options).jpeg_quality, Some(90));
+        let options = recipe.encode_options(&EncodeOptions::default());
+        assert_eq!(options.jpeg_quality, Some(80));
+        assert_eq!(options.avif_quality, Some(80));
+        assert_eq!(options.png_filter, Some(PngFilter::Adaptive));
+        assert!(!options.progressive);
+
+        let error = |text: &str| Recipe::from_yaml(text).unwrap_err().to_string();
+        assert!(error("steps: [blur]").contains("Step 1: Unknown step 'blur'"));
+        assert!(error("steps: [{resize: {}}]").contains("needs a width or a height"));
+        assert!(error("steps: [{resize: {width: 0}}]").contains("integer from 1"));
+        assert!(error("steps: [{resize: {width: 1}}, {crop: {width: 1}}]").contains("only one"));
+        assert!(error("steps: [{sharpen: {radius: 1}}]").contains("no setting 'radius'"));
+        assert!(error("steps: [{watermark: {opacity: 1}}]").contains("image or a text"));
+        assert!(error("encode: {format: gif}").contains("gif"));
+        assert!(error("encode: {avif-speed: 11}").contains("integer from 1 to 10"));
+        assert!(error("output: a.png").contains("no setting 'output'"));
+    }
+
+    #[test]
+    fn runs_the_steps_in_order() {
+        let dir = TempDir::new().expect("Failed to create a temp dir");
+        let input = dir.path().join("input.png");
+        RgbaImage::from_fn(40, 20, |x, _| Rgba([x as u8 * 6, 0, 0, 255]))
+            .save(&input)
+            .unwrap();
+        let recipe = Recipe::from_yaml(YAML).unwrap();
+        let pipeline = recipe.pipeline().unwrap();
+        assert_eq!(
+            pipeline.stage_names(),
+            [
+                "decode",
+                "cover-crop",
+                "sharpen",
+                "watermark",
+                "negate",
+                "encode"
+            ]
+        );
+
+        let (width, height) = recipe.size().unwrap();
+        let mut state = PipelineState::new(&input, width, height);
+        state.format = Some(ImageFormat::Png);
+        pipeline.run(&mut state, &JobOptions::default()).unwrap();
+        let output = image::load_from_memory(&state.encoded.unwrap())
+            .unwrap()
+            .into_rgba8();
+        assert_eq!(output.dimensions(), (20, 20));
+        // The west end of the gradient, negated
+        assert!(output.get_pixel(19, 19)[0] > 100);
+
+        // Watermark images are relative to the recipe
+        let path = dir.path().join("recipe.yml");
+        fs::write(&path, "steps: [{watermark: {image: logo.png}}]").unwrap();
+        let recipe = Recipe::load(&path).unwrap();
+        assert_eq!(recipe.files(), [dir.path().join("logo.png")]);
+        assert!(recipe.pipeline().is_err());
+    }
+
+    #[test]
+    fn produces_several_outputs_from_one_decode() {
+        let dir = TempDir::new().expect("Failed to create a temp dir");
+        let input = dir.path().join("photo.png");
+        RgbaImage::from_pixel(40, 20, Rgba([200, 40, 40, 255]))
+            .save(&input)
+            .unwrap();
+        let recipe = Recipe::from_yaml(
+            "
+steps: [negate]
+encode: { quality: 70 }
+outputs:
+  - name: large
+    steps: [{resize: {width: 30}}]
+  - name: thumb
+    steps: [{crop: {width: 10, height: 10}}]
+    encode: { format: jpeg }
+  - name: placeholder
+    blurhash: { x: 3 }
+  - name: inline
+    lqip: { size: 8 }
+",
+        )
+        .unwrap();
+        assert_eq!(recipe.outputs.len(), 4);
+        assert_eq!(recipe.outputs[3].kind, OutputKind::Lqip { size: 8 });
+        assert_eq!(
+            recipe.outputs[2].kind,
+            OutputKind::Blurhash {
+                x_components: 3,
+                y_components: 3
+            }
+        );
+
+        let produced = recipe
+            .process_file(&input, &dir.path().join("out.png"), &JobOptions::default())
+            .unwrap();
+        let names: Vec<&str> = produced
+            .images
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect();
+        assert_eq!(names, ["large", "thumb"]);
+        let large = &produced.images[0].1;
+        assert_eq!(large.path, dir.path().join("out_large.png"));
+        assert_eq!((large.width, large.height), (30, 15));
+        let thumb = image::open(dir.path().join("out_thumb.jpg")).unwrap();
+        assert_eq!(thumb.dimensions(), (10, 10));
+        // The shared steps ran before each output
+        assert!(thumb.to_rgba8().get_pixel(5, 5)[1] > 150);
+
+        assert_eq!(produced.blurhashes.len(), 1);
+        assert_eq!(produced.blurhashes[0].0, "placeholder");
+        assert_eq!(produced.blurhashes[0].1.len(), 4 + 2 * 9);
+        assert_eq!(produced.lqips.len(), 1);
+        assert_eq!(produced.lqips[0].0, "inline");
+        assert!(produced.lqips[0].1.starts_with("data:image/jpeg;base64,"));
+
+        let error = |text: &str| Recipe::from_yaml(text).unwrap_err().to_string();
+        assert!(error("outputs: [{steps: []}]").contains("Output 1: The output has no name"));
+        assert!(error("outputs: [{name: a}, {name: a}]").contains("Several outputs"));
+        assert!(error("outputs: [{name: a b}]").contains("Invalid output name"));
+        assert!(error("outputs: [{name: a, blurhash: {}, steps: []}]").contains("cannot have"));
+    }
+}
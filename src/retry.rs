@@ -0,0 +1,223 @@
+//! Retries of transient storage failures.
+//!
+//! Inputs and outputs on network storage, such as NFS or SMB shares, can fail for a moment
+//! when the connection drops or the server is busy. `Retrying` wraps a storage backend and
+//! retries the operations failing with a transient error, waiting longer before each new
+//! attempt. Errors that would fail again, such as a missing file or a denied permission,
+//! are returned at once.
+
+use crate::storage::{ReadSeek, StagedFile, Storage};
+use std::{
+    io::{self, Cursor, ErrorKind, Read, Write},
+    path::{Path, PathBuf},
+    thread,
+    time::Duration,
+};
+use tracing::warn;
+
+/// How many times, and after how long, failed operations are retried.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// The number of retries after the first attempt. `0` disables retries.
+    pub retries: u32,
+    /// The delay before the first retry. It doubles before every further retry.
+    pub delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            retries: 0,
+            delay: Duration::from_millis(500),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Runs `operation` until it succeeds, fails with an error that is not transient, or
+    /// has been retried `retries` times.
+    ///
+    /// # Arguments
+    ///
+    /// * `what` - A description of the operation, used in log messages.
+    /// * `operation` - The operation to run.
+    ///
+    /// # Errors
+    ///
+    /// This function will return the error of the last attempt.
+    pub fn run<T>(
+        &self,
+        what: &str,
+        mut operation: impl FnMut() -> io::Result<T>,
+    ) -> io::Result<T> {
+        let mut delay = self.delay;
+        let mut attempt = 0;
+        loop {
+            match operation() {
+                Err(e) if attempt < self.retries && is_transient(&e) => {
+                    attempt += 1;
+                    warn!(
+                        "Failed to {} ({}), retrying in {:?} ({} of {})",
+                        what, e, delay, attempt, self.retries
+                    );
+                    thread::sleep(delay);
+                    delay = delay.saturating_mul(2);
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+/// Returns `true` if an operation failing with `error` may succeed if attempted again.
+pub fn is_transient(error: &io::Error) -> bool {
+    matches!(
+        error.kind(),
+        ErrorKind::Interrupted
+            | ErrorKind::WouldBlock
+            | ErrorKind::TimedOut
+            | ErrorKind::ConnectionReset
+            | ErrorKind::ConnectionAborted
+            | ErrorKind::ConnectionRefused
+            | ErrorKind::NotConnected
+            | ErrorKind::BrokenPipe
+            | ErrorKind::NetworkDown
+            | ErrorKind::NetworkUnreachable
+            | ErrorKind::HostUnreachable
+            | ErrorKind::ResourceBusy
+            | ErrorKind::StaleNetworkFileHandle
+    )
+}
+
+/// A storage backend whose operations are retried on transient errors.
+///
+/// Files are read completely when opened, so that a failure midway through a file is
+/// retried too. Writes are only retried through `Storage::write_file`, since a stream
+/// returned by `create_write` cannot be replayed.
+#[derive(Debug)]
+pub struct Retrying<S> {
+    inner: S,
+    policy: RetryPolicy,
+}
+
+impl<S: Storage> Retrying<S> {
+    /// Wraps a storage backend.
+    pub fn new(inner: S, policy: RetryPolicy) -> Self {
+        Retrying { inner, policy }
+    }
+}
+
+impl<S: Storage> Storage for Retrying<S> {
+    fn open_read(&self, path: &Path) -> io::Result<Box<dyn ReadSeek>> {
+        let contents = self.policy.run(&format!("read {:?}", path), || {
+            let mut contents = Vec::new();
+            self.inner.open_read(path)?.read_to_end(&mut contents)?;
+            Ok(contents)
+        })?;
+        Ok(Box::new(Cursor::new(contents)))
+    }
+
+    fn create_write(&self, path: &Path) -> io::Result<Box<dyn Write>> {
+        self.policy.run(&format!("create {:?}", path), || {
+            self.inner.create_write(path)
+        })
+    }
+
+    fn exists(&self, path: &Path) -> io::Result<bool> {
+        self.policy
+            .run(&format!("check {:?}", path), || self.inner.exists(path))
+    }
+
+    fn list(&self, dir: &Path) -> io::Result<Vec<PathBuf>> {
+        self.policy
+            .run(&format!("list {:?}", dir), || self.inner.list(dir))
+    }
+
+    fn write_file(&self, path: &Path, contents: &[u8], atomic: bool) -> io::Result<()> {
+        self.policy.run(&format!("write {:?}", path), || {
+            self.inner.write_file(path, contents, atomic)
+        })
+    }
+
+    fn create_staged(&self, path: &Path, atomic: bool) -> io::Result<Box<dyn StagedFile>> {
+        self.policy.run(&format!("create {:?}", path), || {
+            self.inner.create_staged(path, atomic)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStorage;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    /// Fails the first `failures` reads with `kind`, then reads from memory.
+    #[derive(Debug)]
+    struct Flaky {
+        files: MemoryStorage,
+        failures: AtomicU32,
+        kind: ErrorKind,
+    }
+
+    impl Storage for Flaky {
+        fn open_read(&self, path: &Path) -> io::Result<Box<dyn ReadSeek>> {
+            if self.failures.load(Ordering::SeqCst) > 0 {
+                self.failures.fetch_sub(1, Ordering::SeqCst);
+                return Err(self.kind.into());
+            }
+            self.files.open_read(path)
+        }
+
+        fn create_write(&self, path: &Path) -> io::Result<Box<dyn Write>> {
+            self.files.create_write(path)
+        }
+
+        fn exists(&self, path: &Path) -> io::Result<bool> {
+            self.files.exists(path)
+        }
+
+        fn list(&self, dir: &Path) -> io::Result<Vec<PathBuf>> {
+            self.files.list(dir)
+        }
+    }
+
+    fn flaky(failures: u32, kind: ErrorKind, retries: u32) -> Retrying<Flaky> {
+        let files = MemoryStorage::new();
+        files.insert("photo.jpg", b"contents".to_vec());
+        let flaky = Flaky {
+            files,
+            failures: AtomicU32::new(failures),
+            kind,
+        };
+        let policy = RetryPolicy {
+            retries,
+            delay: Duration::from_millis(1),
+        };
+        Retrying::new(flaky, policy)
+    }
+
+    #[test]
+    fn retries_transient_errors() {
+        let storage = flaky(2, ErrorKind::TimedOut, 2);
+        let mut contents = Vec::new();
+        let mut reader = storage.open_read(Path::new("photo.jpg")).unwrap();
+        reader.read_to_end(&mut contents).unwrap();
+        assert_eq!(contents, b"contents");
+
+        let storage = flaky(3, ErrorKind::TimedOut, 2);
+        let error = storage.open_read(Path::new("photo.jpg")).err().unwrap();
+        assert_eq!(error.kind(), ErrorKind::TimedOut);
+        assert_eq!(storage.inner.failures.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn returns_permanent_errors_at_once() {
+        let storage = flaky(1, ErrorKind::PermissionDenied, 5);
+        let error = storage.open_read(Path::new("photo.jpg")).err().unwrap();
+        assert_eq!(error.kind(), ErrorKind::PermissionDenied);
+        assert!(storage.open_read(Path::new("photo.jpg")).is_ok());
+        assert!(!is_transient(&ErrorKind::NotFound.into()));
+        assert!(is_transient(&ErrorKind::ConnectionReset.into()));
+    }
+}
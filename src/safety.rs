@@ -0,0 +1,295 @@
+//! Protection against hostile inputs.
+//!
+//! A small compressed file can claim enormous dimensions (a decompression bomb), or be
+//! crafted to make a decoder spin for a long time. `SafetyLimits` is consulted by the
+//! decode stage: the dimensions claimed by the header are checked before any pixel is
+//! decoded, and decoding can be bounded by a timeout.
+//!
+//! The `image` crate cannot interrupt a decoder, so a decode with a timeout runs on a
+//! background thread and the caller gets control back as soon as the timeout expires, which
+//! is what matters for batch and server workloads. `SafetyLimits::decode_file` also fails
+//! every read of the input once its decode has timed out, which stops most decoders at
+//! their next read. Decoders working on data they have already read keep running until they
+//! finish; at most `MAX_ABANDONED_DECODES` such threads may be left running, after which
+//! decodes with a timeout are refused until some of them finish.
+
+use crate::{
+    error::ResizeError,
+    storage::{self, SharedStorage},
+};
+use image::{DynamicImage, ImageResult};
+use std::{
+    io::{self, BufReader, Read, Seek, SeekFrom},
+    path::Path,
+    sync::{
+        atomic::{AtomicU8, AtomicUsize, Ordering},
+        mpsc, Arc,
+    },
+    thread,
+    time::Duration,
+};
+
+/// The maximum number of decoder threads still running after their decode timed out. Decodes
+/// with a timeout fail at once while this many are running.
+pub const MAX_ABANDONED_DECODES: usize = 8;
+
+/// The number of decoder threads still running after their decode timed out.
+static ABANDONED_DECODES: AtomicUsize = AtomicUsize::new(0);
+
+/// The state of a decode still running on its background thread.
+const RUNNING: u8 = 0;
+/// The state of a decode whose thread has finished.
+const FINISHED: u8 = 1;
+/// The state of a decode that timed out while its thread was running.
+const ABANDONED: u8 = 2;
+
+/// Limits applied to untrusted inputs before and during decoding.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SafetyLimits {
+    /// The maximum width claimed by an image header.
+    pub max_width: Option<u32>,
+    /// The maximum height claimed by an image header.
+    pub max_height: Option<u32>,
+    /// The maximum time spent decoding an image.
+    pub decode_timeout: Option<Duration>,
+}
+
+impl SafetyLimits {
+    /// Checks the dimensions claimed by an image header.
+    ///
+    /// # Errors
+    ///
+    /// This function will return `ResizeError::DimensionsExceeded` if the width or the height
+    /// exceeds its limit.
+    pub fn check_dimensions(&self, width: u32, height: u32) -> Result<(), ResizeError> {
+        let too_wide = self.max_width.is_some_and(|max| width > max);
+        let too_tall = self.max_height.is_some_and(|max| height > max);
+        if too_wide || too_tall {
+            return Err(ResizeError::DimensionsExceeded { width, height });
+        }
+        Ok(())
+    }
+
+    /// Decodes an image file, giving up after `decode_timeout` if it is set.
+    ///
+    /// # Arguments
+    ///
+    /// * `storage` - The storage backend holding the image file.
+    /// * `path` - The path of the image file.
+    /// * `limits` - The allocation limits passed to the decoder.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the file cannot be read or decoded, or
+    /// `ResizeError::DecodeTimeout` if decoding does not finish in time.
+    pub fn decode_file(
+        &self,
+        storage: &SharedStorage,
+        path: &Path,
+        limits: image::Limits,
+    ) -> Result<DynamicImage, Box<dyn std::error::Error>> {
+        let Some(timeout) = self.decode_timeout else {
+            let mut reader = storage::image_reader(&**storage, path)?;
+            reader.limits(limits);
+            return Ok(reader.decode()?);
+        };
+
+        let storage = storage.clone();
+        let path = path.to_path_buf();
+        run_with_timeout(timeout, move |state| {
+            let source = InterruptibleReader {
+                inner: storage.open_read(&path)?,
+                state,
+            };
+            let mut reader = storage::image_reader_from(BufReader::new(source), &path)?;
+            reader.limits(limits);
+            reader.decode()
+        })
+    }
+}
+
+/// Returns the number of decoder threads still running after their decode timed out.
+pub fn abandoned_decodes() -> usize {
+    ABANDONED_DECODES.load(Ordering::SeqCst)
+}
+
+/// A reader failing every read once the decode it feeds has been abandoned.
+struct InterruptibleReader<R> {
+    inner: R,
+    state: Arc<AtomicU8>,
+}
+
+impl<R> InterruptibleReader<R> {
+    fn check(&self) -> io::Result<()> {
+        if self.state.load(Ordering::SeqCst) == ABANDONED {
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "The decode timed out",
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for InterruptibleReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.check()?;
+        self.inner.read(buf)
+    }
+}
+
+impl<R: Seek> Seek for InterruptibleReader<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.check()?;
+        self.inner.seek(pos)
+    }
+}
+
+/// Runs a decode function on a background thread and waits for it at most `timeout`.
+///
+/// A decode that times out keeps running until it finishes, and counts towards
+/// `MAX_ABANDONED_DECODES` until then.
+///
+/// # Errors
+///
+/// This function will return the error of `decode`, `ResizeError::DecodeTimeout` if it
+/// does not finish in time, or an error if `MAX_ABANDONED_DECODES` timed out decodes are
+/// still running.
+pub fn decode_with_timeout<F>(
+    timeout: Duration,
+    decode: F,
+) -> Result<DynamicImage, Box<dyn std::error::Error>>
+where
+    F: FnOnce() -> ImageResult<DynamicImage> + Send + 'static,
+{
+    run_with_timeout(timeout, |_| decode())
+}
+
+/// Runs a decode function on a background thread like `decode_with_timeout`, passing it the
+/// state of the decode, which turns to `ABANDONED` when the decode times out.
+fn run_with_timeout<F>(
+    timeout: Duration,
+    decode: F,
+) -> Result<DynamicImage, Box<dyn std::error::Error>>
+where
+    F: FnOnce(Arc<AtomicU8>) -> ImageResult<DynamicImage> + Send + 'static,
+{
+    if abandoned_decodes() >= MAX_ABANDONED_DECODES {
+        return Err(format!(
+            "{} timed out decodes are still running, refusing to start another one",
+            MAX_ABANDONED_DECODES
+        )
+        .into());
+    }
+
+    let state = Arc::new(AtomicU8::new(RUNNING));
+    let (sender, receiver) = mpsc::channel();
+    let thread_state = state.clone();
+    thread::spawn(move || {
+        let result = decode(thread_state.clone());
+        if thread_state.swap(FINISHED, Ordering::SeqCst) == ABANDONED {
+            ABANDONED_DECODES.fetch_sub(1, Ordering::SeqCst);
+        }
+        // The receiver is gone if the decode timed out, so the result is dropped
+        let _ = sender.send(result);
+    });
+
+    match receiver.recv_timeout(timeout) {
+        Ok(result) => Ok(result?),
+        Err(mpsc::RecvTimeoutError::Timeout) => {
+            // Counted before the state changes, so that the thread never decrements first
+            ABANDONED_DECODES.fetch_add(1, Ordering::SeqCst);
+            if state
+                .compare_exchange(RUNNING, ABANDONED, Ordering::SeqCst, Ordering::SeqCst)
+                .is_err()
+            {
+                // The decode finished right after the timeout
+                ABANDONED_DECODES.fetch_sub(1, Ordering::SeqCst);
+            }
+            Err(ResizeError::DecodeTimeout(timeout).into())
+        }
+        Err(mpsc::RecvTimeoutError::Disconnected) => Err("The decoder thread panicked".into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::RgbaImage;
+
+    #[test]
+    fn rejects_large_dimensions() {
+        let limits = SafetyLimits {
+            max_width: Some(100),
+            max_height: Some(50),
+            ..Default::default()
+        };
+        assert!(limits.check_dimensions(100, 50).is_ok());
+        assert_eq!(
+            limits.check_dimensions(101, 10),
+            Err(ResizeError::DimensionsExceeded {
+                width: 101,
+                height: 10
+            })
+        );
+        assert!(limits.check_dimensions(10, 51).is_err());
+        assert!(SafetyLimits::default()
+            .check_dimensions(u32::MAX, u32::MAX)
+            .is_ok());
+    }
+
+    #[test]
+    fn decodes_file_within_timeout() {
+        let dir = tempfile::TempDir::new().expect("Failed to create a temp dir");
+        let path = dir.path().join("input.png");
+        RgbaImage::new(8, 4).save(&path).unwrap();
+
+        let limits = SafetyLimits {
+            decode_timeout: Some(Duration::from_secs(10)),
+            ..Default::default()
+        };
+        let image = limits
+            .decode_file(&SharedStorage::default(), &path, image::Limits::default())
+            .unwrap();
+        assert_eq!((image.width(), image.height()), (8, 4));
+    }
+
+    #[test]
+    fn times_out() {
+        let error = decode_with_timeout(Duration::from_millis(10), || {
+            thread::sleep(Duration::from_secs(1));
+            Ok(DynamicImage::new_rgba8(1, 1))
+        })
+        .unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<ResizeError>(),
+            Some(&ResizeError::DecodeTimeout(Duration::from_millis(10)))
+        );
+    }
+
+    #[test]
+    fn abandoned_decodes_stop_reading() {
+        let state = Arc::new(AtomicU8::new(RUNNING));
+        let mut reader = InterruptibleReader {
+            inner: io::Cursor::new(vec![0u8; 16]),
+            state: state.clone(),
+        };
+        let mut buf = [0u8; 4];
+        assert_eq!(reader.read(&mut buf).unwrap(), 4);
+        state.store(ABANDONED, Ordering::SeqCst);
+        let error = reader.read(&mut buf).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::TimedOut);
+    }
+
+    #[test]
+    fn counts_abandoned_decodes() {
+        let (sender, receiver) = mpsc::channel::<()>();
+        decode_with_timeout(Duration::from_millis(10), move || {
+            let _ = receiver.recv();
+            Ok(DynamicImage::new_rgba8(1, 1))
+        })
+        .unwrap_err();
+        assert!(abandoned_decodes() >= 1);
+        drop(sender);
+    }
+}
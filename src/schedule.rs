@@ -0,0 +1,282 @@
+//! Scheduling of resize jobs from concurrent clients.
+//!
+//! A service resizing images on behalf of several clients, such as an HTTP server, must
+//! keep one client from exhausting the CPU. A `Scheduler` runs jobs on the calling thread,
+//! but admits at most `max_concurrent` of them at a time: other jobs wait in a queue, and
+//! fail with `ResizeError::QueueTimeout` if no slot becomes free in time. Each client can
+//! also be limited to a number of jobs per period, and jobs over the limit fail at once
+//! with `ResizeError::RateLimited`, telling the client when to retry.
+//!
+//! The rate limit is a token bucket: a client can send `requests` jobs in a burst, then
+//! one more every `per / requests`.
+
+use crate::error::ResizeError;
+use std::{
+    collections::HashMap,
+    error::Error,
+    sync::{Arc, Condvar, Mutex, PoisonError},
+    time::{Duration, Instant},
+};
+
+/// The number of clients whose rate limits are tracked before idle clients are forgotten.
+const MAX_TRACKED_CLIENTS: usize = 1024;
+
+/// A maximum number of jobs per period, for each client.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimit {
+    /// The number of jobs a client can send in a burst, and per period.
+    pub requests: u32,
+    /// The period over which `requests` jobs are allowed.
+    pub per: Duration,
+}
+
+/// Settings of a `Scheduler`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SchedulerConfig {
+    /// The maximum number of jobs running at the same time.
+    pub max_concurrent: usize,
+    /// How long a job waits for a free slot before failing.
+    pub queue_timeout: Duration,
+    /// The rate limit of each client, if any.
+    pub rate_limit: Option<RateLimit>,
+}
+
+impl Default for SchedulerConfig {
+    fn default() -> Self {
+        SchedulerConfig {
+            max_concurrent: std::thread::available_parallelism().map_or(1, |n| n.get()),
+            queue_timeout: Duration::from_secs(30),
+            rate_limit: None,
+        }
+    }
+}
+
+/// The jobs a client can still send, refilled over time.
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+#[derive(Debug)]
+struct Inner {
+    config: SchedulerConfig,
+    running: Mutex<usize>,
+    freed: Condvar,
+    clients: Mutex<HashMap<String, Bucket>>,
+}
+
+/// Admits jobs according to a `SchedulerConfig`. Clones share the same slots and rate
+/// limits.
+#[derive(Debug, Clone)]
+pub struct Scheduler {
+    inner: Arc<Inner>,
+}
+
+impl Scheduler {
+    /// Creates a scheduler with no job running.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the rate limit allows no request, or has a zero
+    /// period.
+    pub fn new(config: SchedulerConfig) -> Result<Self, Box<dyn Error>> {
+        if let Some(limit) = config.rate_limit {
+            if limit.requests == 0 || limit.per.is_zero() {
+                return Err(format!(
+                    "Invalid rate limit of {} requests per {:?}: both must be greater than zero",
+                    limit.requests, limit.per
+                )
+                .into());
+            }
+        }
+        Ok(Scheduler {
+            inner: Arc::new(Inner {
+                config,
+                running: Mutex::new(0),
+                freed: Condvar::new(),
+                clients: Mutex::new(HashMap::new()),
+            }),
+        })
+    }
+
+    /// Returns the number of jobs currently running.
+    pub fn running(&self) -> usize {
+        *self
+            .inner
+            .running
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Runs a job once the rate limit of its client and a free slot allow it.
+    ///
+    /// # Arguments
+    ///
+    /// * `client` - Identifies the client sending the job, e.g. its IP address.
+    /// * `job` - The job to run, typically a call to `resize_file`.
+    ///
+    /// # Returns
+    ///
+    /// The result of the job.
+    ///
+    /// # Errors
+    ///
+    /// This function will return `ResizeError::RateLimited` if the client exceeded its rate
+    /// limit, `ResizeError::QueueTimeout` if no slot became free in time, or the error of
+    /// the job.
+    pub fn run<T>(
+        &self,
+        client: &str,
+        job: impl FnOnce() -> Result<T, Box<dyn Error>>,
+    ) -> Result<T, Box<dyn Error>> {
+        self.admit(client)?;
+        let _slot = self.acquire()?;
+        job()
+    }
+
+    /// Takes a job from the bucket of `client`.
+    fn admit(&self, client: &str) -> Result<(), ResizeError> {
+        let Some(limit) = self.inner.config.rate_limit else {
+            return Ok(());
+        };
+        let capacity = f64::from(limit.requests);
+        let refill_rate = capacity / limit.per.as_secs_f64();
+        let now = Instant::now();
+        let refilled = |bucket: &Bucket| {
+            let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+            (bucket.tokens + elapsed * refill_rate).min(capacity)
+        };
+
+        let mut clients = self
+            .inner
+            .clients
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        if !clients.contains_key(client) && clients.len() >= MAX_TRACKED_CLIENTS {
+            // A client with a full bucket is in the same state as one never seen
+            clients.retain(|_, bucket| refilled(bucket) < capacity);
+        }
+        let bucket = clients.entry(client.to_string()).or_insert(Bucket {
+            tokens: capacity,
+            updated: now,
+        });
+        bucket.tokens = refilled(bucket);
+        bucket.updated = now;
+        if bucket.tokens < 1.0 {
+            let retry_after = Duration::from_secs_f64((1.0 - bucket.tokens) / refill_rate);
+            return Err(ResizeError::RateLimited { retry_after });
+        }
+        bucket.tokens -= 1.0;
+        Ok(())
+    }
+
+    /// Waits for a free slot, for at most the queue timeout.
+    fn acquire(&self) -> Result<Slot<'_>, ResizeError> {
+        let config = &self.inner.config;
+        let running = self
+            .inner
+            .running
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        let (mut running, _) = self
+            .inner
+            .freed
+            .wait_timeout_while(running, config.queue_timeout, |running| {
+                *running >= config.max_concurrent
+            })
+            .unwrap_or_else(PoisonError::into_inner);
+        if *running >= config.max_concurrent {
+            return Err(ResizeError::QueueTimeout(config.queue_timeout));
+        }
+        *running += 1;
+        Ok(Slot(&self.inner))
+    }
+}
+
+/// A running job, freeing its slot when dropped, even if the job panics.
+struct Slot<'a>(&'a Inner);
+
+impl Drop for Slot<'_> {
+    fn drop(&mut self) {
+        *self
+            .0
+            .running
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner) -= 1;
+        self.0.freed.notify_one();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{sync::mpsc, thread};
+
+    #[test]
+    fn caps_concurrent_jobs() {
+        let scheduler = Scheduler::new(SchedulerConfig {
+            max_concurrent: 1,
+            queue_timeout: Duration::from_millis(50),
+            rate_limit: None,
+        })
+        .unwrap();
+        let (started, wait_started) = mpsc::channel();
+        let (finish, wait_finish) = mpsc::channel::<()>();
+        let running = scheduler.clone();
+        let worker = thread::spawn(move || {
+            running
+                .run("a", || {
+                    started.send(()).unwrap();
+                    wait_finish.recv().unwrap();
+                    Ok(1)
+                })
+                .is_ok()
+        });
+        wait_started.recv().unwrap();
+        assert_eq!(scheduler.running(), 1);
+
+        let error = scheduler.run("b", || Ok(2)).unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<ResizeError>(),
+            Some(&ResizeError::QueueTimeout(Duration::from_millis(50)))
+        );
+
+        finish.send(()).unwrap();
+        assert!(worker.join().unwrap());
+        assert_eq!(scheduler.running(), 0);
+        assert_eq!(scheduler.run("b", || Ok(2)).unwrap(), 2);
+    }
+
+    #[test]
+    fn limits_the_rate_of_each_client() {
+        let scheduler = Scheduler::new(SchedulerConfig {
+            rate_limit: Some(RateLimit {
+                requests: 2,
+                per: Duration::from_secs(60),
+            }),
+            ..Default::default()
+        })
+        .unwrap();
+        assert!(scheduler.run("a", || Ok(())).is_ok());
+        assert!(scheduler.run("a", || Ok(())).is_ok());
+        let error = scheduler.run("a", || Ok(())).unwrap_err();
+        match error.downcast_ref::<ResizeError>() {
+            Some(ResizeError::RateLimited { retry_after }) => {
+                assert!(*retry_after > Duration::from_secs(29));
+                assert!(*retry_after <= Duration::from_secs(30));
+            }
+            other => panic!("unexpected error {:?}", other),
+        }
+        // Other clients are not affected
+        assert!(scheduler.run("b", || Ok(())).is_ok());
+
+        for (requests, per) in [(0, Duration::from_secs(1)), (10, Duration::ZERO)] {
+            let config = SchedulerConfig {
+                rate_limit: Some(RateLimit { requests, per }),
+                ..Default::default()
+            };
+            assert!(Scheduler::new(config).is_err());
+        }
+    }
+}
@@ -0,0 +1,315 @@
+//! Content-aware resizing with seam carving.
+//!
+//! Seam carving changes the aspect ratio of an image by removing or inserting seams:
+//! connected paths of pixels, one per row (or per column), running through the areas with
+//! the least detail. Subjects and edges are kept in proportion, while uniform areas such as
+//! sky, water or walls shrink or grow instead.
+//!
+//! The energy of a pixel is the sum of the absolute horizontal and vertical luma gradients
+//! around it, and the seam with the lowest total energy is found by dynamic programming.
+//! Seams are inserted by finding as many low-energy seams as needed, as if they were to be
+//! removed, and duplicating them instead.
+//!
+//! `SeamCarveStage` replaces the resize stage of a pipeline: the image is first scaled
+//! uniformly to cover the requested size, then carved down to it along the dimension that
+//! is too large. This is experimental, and much slower than scaling.
+
+use crate::{
+    determine_new_dimensions,
+    options::{JobOptions, ResizeOptions},
+    pipeline::{PipelineState, Stage},
+    resize_image_with,
+};
+use image::{DynamicImage, RgbaImage};
+use std::error::Error;
+
+/// The name of the seam carving stage.
+pub const SEAM_CARVE: &str = "seam-carve";
+
+/// The pixels of an image being carved, in row-major order.
+#[derive(Debug, Clone)]
+struct Grid {
+    width: usize,
+    height: usize,
+    pixels: Vec<[u8; 4]>,
+}
+
+impl Grid {
+    fn from_image(image: &RgbaImage) -> Self {
+        Grid {
+            width: image.width() as usize,
+            height: image.height() as usize,
+            pixels: image.pixels().map(|pixel| pixel.0).collect(),
+        }
+    }
+
+    fn into_image(self) -> RgbaImage {
+        let raw = self.pixels.into_iter().flatten().collect();
+        RgbaImage::from_raw(self.width as u32, self.height as u32, raw)
+            .expect("The grid has one pixel per position")
+    }
+
+    /// Swaps rows and columns, so that horizontal seams can be handled as vertical ones.
+    fn transpose(&self) -> Self {
+        let mut pixels = Vec::with_capacity(self.pixels.len());
+        for x in 0..self.width {
+            pixels.extend((0..self.height).map(|y| self.pixels[y * self.width + x]));
+        }
+        Grid {
+            width: self.height,
+            height: self.width,
+            pixels,
+        }
+    }
+
+    /// Returns the energy of every pixel.
+    fn energy(&self) -> Vec<u32> {
+        let luma: Vec<i32> = self
+            .pixels
+            .iter()
+            .map(|[r, g, b, _]| (299 * *r as i32 + 587 * *g as i32 + 114 * *b as i32) / 1000)
+            .collect();
+        let (width, height) = (self.width, self.height);
+        let at = |x: usize, y: usize| luma[y * width + x];
+        let mut energy = Vec::with_capacity(luma.len());
+        for y in 0..height {
+            for x in 0..width {
+                let dx = at((x + 1).min(width - 1), y) - at(x.saturating_sub(1), y);
+                let dy = at(x, (y + 1).min(height - 1)) - at(x, y.saturating_sub(1));
+                energy.push(dx.unsigned_abs() + dy.unsigned_abs());
+            }
+        }
+        energy
+    }
+
+    /// Returns the vertical seam with the lowest energy, as one column per row.
+    fn find_seam(&self) -> Vec<usize> {
+        let (width, height) = (self.width, self.height);
+        let mut cost = self.energy();
+        for y in 1..height {
+            for x in 0..width {
+                let above = &cost[(y - 1) * width..y * width];
+                let best = above[x.saturating_sub(1)..=(x + 1).min(width - 1)]
+                    .iter()
+                    .min()
+                    .copied()
+                    .unwrap_or(0);
+                cost[y * width + x] += best;
+            }
+        }
+
+        let last = &cost[(height - 1) * width..];
+        let mut x = (0..width).min_by_key(|&x| last[x]).unwrap_or(0);
+        let mut seam = vec![0; height];
+        seam[height - 1] = x;
+        for y in (0..height - 1).rev() {
+            let row = &cost[y * width..(y + 1) * width];
+            x = (x.saturating_sub(1)..=(x + 1).min(width - 1))
+                .min_by_key(|&candidate| row[candidate])
+                .unwrap_or(x);
+            seam[y] = x;
+        }
+        seam
+    }
+
+    /// Removes a vertical seam.
+    fn remove_seam(&mut self, seam: &[usize]) {
+        let width = self.width;
+        let mut y = 0;
+        let mut i = 0;
+        self.pixels.retain(|_| {
+            let keep = i % width != seam[y];
+            i += 1;
+            if i % width == 0 {
+                y += 1;
+            }
+            keep
+        });
+        self.width -= 1;
+    }
+
+    /// Removes `count` vertical seams, one at a time.
+    fn remove_seams(&mut self, count: usize) {
+        for _ in 0..count {
+            let seam = self.find_seam();
+            self.remove_seam(&seam);
+        }
+    }
+
+    /// Inserts `count` vertical seams, at most `width - 1` of them.
+    ///
+    /// The seams are found by removing them from a copy while keeping track of the original
+    /// column of every pixel, so that each inserted seam goes through a different path. Each
+    /// inserted pixel is the average of the seam pixel and its right neighbor.
+    fn insert_seams(&mut self, count: usize) {
+        let (width, height) = (self.width, self.height);
+        let mut work = self.clone();
+        let mut columns: Vec<Vec<usize>> = vec![(0..width).collect(); height];
+        let mut duplicated = vec![false; width * height];
+        for _ in 0..count {
+            let seam = work.find_seam();
+            for (y, &x) in seam.iter().enumerate() {
+                duplicated[y * width + columns[y].remove(x)] = true;
+            }
+            work.remove_seam(&seam);
+        }
+
+        let mut pixels = Vec::with_capacity((width + count) * height);
+        for y in 0..height {
+            for x in 0..width {
+                let pixel = self.pixels[y * width + x];
+                pixels.push(pixel);
+                if duplicated[y * width + x] {
+                    let next = self.pixels[y * width + (x + 1).min(width - 1)];
+                    let mut average = [0u8; 4];
+                    for c in 0..4 {
+                        average[c] = (pixel[c] as u16 + next[c] as u16).div_ceil(2) as u8;
+                    }
+                    pixels.push(average);
+                }
+            }
+        }
+        self.pixels = pixels;
+        self.width += count;
+    }
+
+    /// Removes or inserts vertical seams until the grid is `width` pixels wide.
+    fn carve_width(&mut self, width: usize) {
+        while self.width < width {
+            // Inserting more seams than half the width would stretch the same paths
+            let count = (width - self.width).min((self.width / 2).max(1));
+            self.insert_seams(count);
+        }
+        if self.width > width {
+            self.remove_seams(self.width - width);
+        }
+    }
+}
+
+/// Changes the size of an image with seam carving, without scaling it.
+///
+/// The width is changed first, with vertical seams, then the height, with horizontal
+/// seams.
+///
+/// # Arguments
+///
+/// * `image` - The image to carve.
+/// * `width` - The new width.
+/// * `height` - The new height.
+///
+/// # Returns
+///
+/// A `Result` containing the carved image.
+///
+/// # Errors
+///
+/// This function will return an error if the image or the new size is empty.
+pub fn carve(image: &RgbaImage, width: u32, height: u32) -> Result<RgbaImage, Box<dyn Error>> {
+    if image.width() == 0 || image.height() == 0 || width == 0 || height == 0 {
+        return Err("Seam carving needs a non-empty image and size".into());
+    }
+    let mut grid = Grid::from_image(image);
+    grid.carve_width(width as usize);
+    if grid.height != height as usize {
+        let mut transposed = grid.transpose();
+        transposed.carve_width(height as usize);
+        grid = transposed.transpose();
+    }
+    Ok(grid.into_image())
+}
+
+/// Resizes an image to an exact size, changing its aspect ratio with seam carving.
+///
+/// The image is first scaled uniformly to the smallest size covering `width` x `height`,
+/// so that only seams along one dimension have to be removed.
+///
+/// # Errors
+///
+/// This function will return an error if the uniform resize or the carving fails.
+pub fn resize_carved(
+    image: DynamicImage,
+    width: u32,
+    height: u32,
+    options: &ResizeOptions,
+) -> Result<RgbaImage, Box<dyn Error>> {
+    let (src_width, src_height) = (image.width() as f64, image.height() as f64);
+    let scale = (width as f64 / src_width).max(height as f64 / src_height);
+    let cover_width = ((src_width * scale).round() as u32).max(width);
+    let cover_height = ((src_height * scale).round() as u32).max(height);
+    let scaled = if (cover_width, cover_height) == (image.width(), image.height()) {
+        image.into_rgba8()
+    } else {
+        resize_image_with(image, Some(&cover_width), Some(&cover_height), options)?
+    };
+    carve(&scaled, width, height)
+}
+
+/// A stage resizing the current image with seam carving, meant to replace the standard
+/// resize stage.
+///
+/// If only one of the width and height is requested, the aspect ratio is kept and the
+/// image is simply scaled.
+pub struct SeamCarveStage;
+
+impl Stage for SeamCarveStage {
+    fn name(&self) -> &str {
+        SEAM_CARVE
+    }
+
+    fn run(&self, state: &mut PipelineState, options: &JobOptions) -> Result<(), Box<dyn Error>> {
+        let image = state.take_image()?;
+        let (width, height) = determine_new_dimensions(
+            image.width(),
+            image.height(),
+            state.width.as_ref(),
+            state.height.as_ref(),
+            &options.resize,
+        )?;
+        let resized = resize_carved(image, width, height, &options.resize)?;
+        state.image = Some(DynamicImage::ImageRgba8(resized));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    /// A flat gray image with a black square, whose edges have all the energy.
+    fn subject() -> RgbaImage {
+        RgbaImage::from_fn(20, 10, |x, y| {
+            if (8..12).contains(&x) && (3..7).contains(&y) {
+                Rgba([0, 0, 0, 255])
+            } else {
+                Rgba([128, 128, 128, 255])
+            }
+        })
+    }
+
+    fn square_size(image: &RgbaImage) -> (usize, usize) {
+        let black = |x: u32, y: u32| image.get_pixel(x, y)[0] == 0;
+        let columns = (0..image.width())
+            .filter(|&x| (0..image.height()).any(|y| black(x, y)))
+            .count();
+        let rows = (0..image.height())
+            .filter(|&y| (0..image.width()).any(|x| black(x, y)))
+            .count();
+        (columns, rows)
+    }
+
+    #[test]
+    fn removes_seams_around_the_subject() {
+        let carved = carve(&subject(), 10, 6).unwrap();
+        assert_eq!(carved.dimensions(), (10, 6));
+        assert_eq!(square_size(&carved), (4, 4));
+    }
+
+    #[test]
+    fn inserts_seams_around_the_subject() {
+        let carved = carve(&subject(), 30, 10).unwrap();
+        assert_eq!(carved.dimensions(), (30, 10));
+        assert_eq!(square_size(&carved), (4, 4));
+        assert!(carve(&subject(), 0, 10).is_err());
+    }
+}
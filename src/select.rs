@@ -0,0 +1,335 @@
+//! Input file selection.
+//!
+//! Inputs can be given as glob patterns, such as `photos/**/*.jpg`, which are expanded here
+//! rather than by the shell, so they work the same on Windows, where the shell leaves them
+//! as they are. `*` and `?` match within a path component, `**` matches any number of
+//! directories, and `[abc]` matches one of the listed characters. Like in shells, they do not
+//! match hidden files unless the pattern starts the name with a dot.
+//!
+//! Exclude patterns remove inputs from the batch. A pattern without a path separator is
+//! matched against the file name, so `*_thumb.jpg` excludes thumbnails in any directory,
+//! and other patterns against the whole path, e.g. `photos/private/**`.
+//!
+//! `Criteria` select inputs by their dimensions, file size and modification date. The
+//! dimensions are read from the image header, without decoding the pixels, so that large
+//! batches can be narrowed down quickly.
+
+use crate::{storage::Storage, SourceInfo};
+use glob::{MatchOptions, Pattern};
+use std::{
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// How patterns match paths.
+const MATCH_OPTIONS: MatchOptions = MatchOptions {
+    case_sensitive: true,
+    require_literal_separator: true,
+    require_literal_leading_dot: true,
+};
+
+/// Returns true if `input` contains glob wildcards.
+pub fn is_pattern(input: &str) -> bool {
+    input.contains(['*', '?', '['])
+}
+
+/// Lists the files matching a glob pattern, in alphabetical order.
+///
+/// # Errors
+///
+/// This function will return an error if the pattern is invalid, a directory cannot be
+/// read or no file matches.
+pub fn expand(pattern: &str) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let mut files = Vec::new();
+    let paths = glob::glob_with(pattern, MATCH_OPTIONS)
+        .map_err(|e| format!("Invalid pattern '{}': {}", pattern, e))?;
+    for path in paths {
+        let path = path?;
+        if path.is_file() {
+            files.push(path);
+        }
+    }
+    if files.is_empty() {
+        return Err(format!("No files match '{}'", pattern).into());
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// A set of exclude patterns.
+#[derive(Debug, Clone, Default)]
+pub struct Excludes {
+    patterns: Vec<Pattern>,
+}
+
+impl Excludes {
+    /// Parses exclude patterns.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if a pattern is invalid.
+    pub fn new<S: AsRef<str>>(patterns: &[S]) -> Result<Self, Box<dyn Error>> {
+        let patterns = patterns
+            .iter()
+            .map(|pattern| {
+                let pattern = pattern.as_ref();
+                Pattern::new(pattern)
+                    .map_err(|e| format!("Invalid exclude pattern '{}': {}", pattern, e))
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Excludes { patterns })
+    }
+
+    /// Returns true if `path` matches one of the patterns.
+    pub fn matches(&self, path: &Path) -> bool {
+        self.patterns.iter().any(|pattern| {
+            if pattern.as_str().contains(['/', '\\']) {
+                pattern.matches_path_with(path, MATCH_OPTIONS)
+            } else {
+                path.file_name().is_some_and(|name| {
+                    pattern.matches_with(&name.to_string_lossy(), MATCH_OPTIONS)
+                })
+            }
+        })
+    }
+}
+
+/// Conditions an input must meet to be processed. Conditions that are not set always pass.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Criteria {
+    /// The minimum width, in pixels.
+    pub min_width: Option<u32>,
+    /// The maximum width, in pixels.
+    pub max_width: Option<u32>,
+    /// The minimum height, in pixels.
+    pub min_height: Option<u32>,
+    /// The maximum height, in pixels.
+    pub max_height: Option<u32>,
+    /// The file size the input must exceed, in bytes.
+    pub larger_than: Option<u64>,
+    /// The file size the input must stay below, in bytes.
+    pub smaller_than: Option<u64>,
+    /// The time the input must have been modified at or after.
+    pub newer_than: Option<SystemTime>,
+    /// The time the input must have been modified before.
+    pub older_than: Option<SystemTime>,
+}
+
+impl Criteria {
+    /// Returns true if no condition is set.
+    pub fn is_empty(&self) -> bool {
+        *self == Criteria::default()
+    }
+
+    /// Checks whether an input meets the conditions. Only the header of the image is read,
+    /// and only if a condition on the dimensions or file size is set. The modification date
+    /// is read from the file system, whatever the storage.
+    ///
+    /// # Arguments
+    ///
+    /// * `storage` - The storage the input is read from.
+    /// * `path` - The path of the input.
+    ///
+    /// # Returns
+    ///
+    /// The reason the input does not meet the conditions, or `None` if it does.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the input cannot be read or its format is not
+    /// recognized.
+    pub fn reject_reason(
+        &self,
+        storage: &dyn Storage,
+        path: &Path,
+    ) -> Result<Option<String>, Box<dyn Error>> {
+        let needs_header = self.min_width.is_some()
+            || self.max_width.is_some()
+            || self.min_height.is_some()
+            || self.max_height.is_some();
+        if needs_header || self.larger_than.is_some() || self.smaller_than.is_some() {
+            let source = SourceInfo::read_from(storage, path)?;
+            let (width, height, size) = (source.width, source.height, source.file_size);
+            let reason = if self.min_width.is_some_and(|min| width < min)
+                || self.max_width.is_some_and(|max| width > max)
+                || self.min_height.is_some_and(|min| height < min)
+                || self.max_height.is_some_and(|max| height > max)
+            {
+                Some(format!("{}x{}", width, height))
+            } else if self.larger_than.is_some_and(|min| size <= min)
+                || self.smaller_than.is_some_and(|max| size >= max)
+            {
+                Some(format!("{} bytes", size))
+            } else {
+                None
+            };
+            if reason.is_some() {
+                return Ok(reason);
+            }
+        }
+
+        if self.newer_than.is_some() || self.older_than.is_some() {
+            let modified = fs::metadata(path)?.modified()?;
+            if self.newer_than.is_some_and(|time| modified < time)
+                || self.older_than.is_some_and(|time| modified >= time)
+            {
+                return Ok(Some(format!(
+                    "modified on {}",
+                    crate::contact::format_date(modified)
+                )));
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// Parses a `YYYY-MM-DD` date as midnight UTC.
+///
+/// # Errors
+///
+/// This function will return an error if the date is malformed or does not exist.
+pub fn parse_date(text: &str) -> Result<SystemTime, String> {
+    let invalid = || format!("'{}' is not a date in the YYYY-MM-DD format", text);
+    let mut parts = text.trim().splitn(3, '-');
+    let mut next = |digits: usize| {
+        parts
+            .next()
+            .filter(|part| part.len() == digits && part.bytes().all(|b| b.is_ascii_digit()))
+            .and_then(|part| part.parse::<i64>().ok())
+            .ok_or_else(invalid)
+    };
+    let (year, month, day) = (next(4)?, next(2)?, next(2)?);
+    let leap = year % 4 == 0 && (year % 100 != 0 || year % 400 == 0);
+    let days_in_month = match month {
+        2 if leap => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        1..=12 => 31,
+        _ => 0,
+    };
+    if !(1..=days_in_month).contains(&day) {
+        return Err(invalid());
+    }
+    // Converts a civil date to days since 1970-01-01 (Howard Hinnant's algorithm)
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let mp = (month + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146_097 + doe - 719_468;
+    let seconds = Duration::from_secs(days.unsigned_abs() * 86_400);
+    Ok(if days >= 0 {
+        UNIX_EPOCH + seconds
+    } else {
+        UNIX_EPOCH - seconds
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    #[test]
+    fn expands_patterns() {
+        let dir = TempDir::new().expect("Failed to create a temp dir");
+        fs::create_dir_all(dir.path().join("a/b")).unwrap();
+        for file in [
+            "top.jpg",
+            "a/one.jpg",
+            "a/b/two.jpg",
+            "a/b/three.png",
+            "a/.hidden.jpg",
+        ] {
+            fs::write(dir.path().join(file), b"").unwrap();
+        }
+        let root = dir.path().to_string_lossy();
+        let found = expand(&format!("{}/**/*.jpg", root)).unwrap();
+        let names: Vec<_> = found
+            .iter()
+            .map(|path| path.strip_prefix(dir.path()).unwrap().to_path_buf())
+            .collect();
+        assert_eq!(
+            names,
+            [
+                PathBuf::from("a/b/two.jpg"),
+                PathBuf::from("a/one.jpg"),
+                PathBuf::from("top.jpg")
+            ]
+        );
+        assert_eq!(expand(&format!("{}/*/*.jpg", root)).unwrap().len(), 1);
+        assert!(expand(&format!("{}/*.gif", root)).is_err());
+        assert!(is_pattern("photos/*.jpg"));
+        assert!(!is_pattern("photo.jpg"));
+    }
+
+    #[test]
+    fn matches_excludes() {
+        let excludes = Excludes::new(&["*_thumb.jpg", "photos/private/**"]).unwrap();
+        assert!(excludes.matches(Path::new("a/b/cat_thumb.jpg")));
+        assert!(excludes.matches(Path::new("photos/private/2024/me.jpg")));
+        assert!(!excludes.matches(Path::new("photos/cat.jpg")));
+        assert!(!excludes.matches(Path::new("other/photos/private/me.jpg")));
+        assert!(Excludes::new(&["[a"]).is_err());
+    }
+
+    #[test]
+    fn selects_by_criteria() {
+        use crate::storage::FileSystem;
+        use image::RgbImage;
+
+        let dir = TempDir::new().expect("Failed to create a temp dir");
+        let path = dir.path().join("photo.png");
+        RgbImage::new(40, 20).save(&path).unwrap();
+        let check = |criteria: Criteria| criteria.reject_reason(&FileSystem, &path).unwrap();
+
+        assert!(Criteria::default().is_empty());
+        assert_eq!(check(Criteria::default()), None);
+        let wide = Criteria {
+            min_width: Some(40),
+            max_height: Some(20),
+            ..Default::default()
+        };
+        assert_eq!(check(wide), None);
+        let large = Criteria {
+            min_width: Some(41),
+            ..Default::default()
+        };
+        assert_eq!(check(large).as_deref(), Some("40x20"));
+        let heavy = Criteria {
+            larger_than: Some(1 << 20),
+            ..Default::default()
+        };
+        assert!(check(heavy).unwrap().ends_with(" bytes"));
+        let recent = Criteria {
+            newer_than: Some(parse_date("2000-01-01").unwrap()),
+            ..Default::default()
+        };
+        assert_eq!(check(recent), None);
+        let old = Criteria {
+            older_than: Some(parse_date("2000-01-01").unwrap()),
+            ..Default::default()
+        };
+        assert!(check(old).unwrap().starts_with("modified on "));
+
+        assert_eq!(
+            parse_date("1970-01-02"),
+            Ok(UNIX_EPOCH + Duration::from_secs(86_400))
+        );
+        assert_eq!(
+            parse_date("2024-02-29"),
+            Ok(UNIX_EPOCH + Duration::from_secs(1_709_164_800))
+        );
+        assert_eq!(
+            parse_date("1969-12-31"),
+            Ok(UNIX_EPOCH - Duration::from_secs(86_400))
+        );
+        assert!(parse_date("2023-02-29").is_err());
+        assert!(parse_date("2024-1-01").is_err());
+        assert!(parse_date("yesterday").is_err());
+    }
+}
@@ -0,0 +1,464 @@
+//! Frame sequences.
+//!
+//! Video tools such as ffmpeg exchange frames either as numbered image files, named after
+//! a pattern like `frame_%04d.png`, or as raw pixels on a pipe (`-f rawvideo`). Both are
+//! resized here with a single `ResizeContext`, so that the resizers and their buffers are
+//! set up once for the whole sequence instead of once per frame.
+//!
+//! The output size is computed from the first frame, and every frame is resized to it:
+//! encoders need all the frames of a video to have the same size.
+
+use crate::{
+    cancel::CancellationToken,
+    context::ResizeContext,
+    determine_new_dimensions,
+    encode::EncodeOptions,
+    estimate_size_and_encode,
+    raw::{PixelLayout, RawImage},
+    storage::{FileSystem, Storage},
+};
+use image::{GenericImageView, ImageFormat};
+use std::{
+    error::Error,
+    fmt,
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+use tracing::{debug, info};
+
+/// The number of frame numbers searched for the first frame of a sequence when no start
+/// number is given, as ffmpeg does.
+pub const START_NUMBER_RANGE: u64 = 5;
+
+/// The file names of a numbered sequence, such as `frame_%04d.png`.
+///
+/// The pattern holds exactly one `%d`, or `%0Nd` for numbers padded with zeros to `N`
+/// digits. `%%` stands for a literal `%`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FramePattern {
+    prefix: String,
+    digits: usize,
+    suffix: String,
+}
+
+impl FramePattern {
+    /// Returns the path of the frame with the given number.
+    pub fn path(&self, number: u64) -> PathBuf {
+        PathBuf::from(format!(
+            "{}{:0digits$}{}",
+            self.prefix,
+            number,
+            self.suffix,
+            digits = self.digits
+        ))
+    }
+
+    /// Returns the number of the first frame that exists, searching `START_NUMBER_RANGE`
+    /// numbers from zero.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if none of these frames exists.
+    pub fn first_frame(&self) -> Result<u64, Box<dyn Error>> {
+        (0..START_NUMBER_RANGE)
+            .find(|&number| self.path(number).is_file())
+            .ok_or_else(|| {
+                format!(
+                    "No frame matching '{}' numbered 0 to {}",
+                    self,
+                    START_NUMBER_RANGE - 1
+                )
+                .into()
+            })
+    }
+}
+
+impl fmt::Display for FramePattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let escape = |part: &str| part.replace('%', "%%");
+        match self.digits {
+            0 => write!(f, "{}%d{}", escape(&self.prefix), escape(&self.suffix)),
+            digits => write!(
+                f,
+                "{}%0{}d{}",
+                escape(&self.prefix),
+                digits,
+                escape(&self.suffix)
+            ),
+        }
+    }
+}
+
+impl FromStr for FramePattern {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = [String::new(), String::new()];
+        let mut digits = None;
+        let mut chars = s.chars().peekable();
+        while let Some(c) = chars.next() {
+            if c != '%' {
+                parts[digits.is_some() as usize].push(c);
+                continue;
+            }
+            if chars.next_if_eq(&'%').is_some() {
+                parts[digits.is_some() as usize].push('%');
+                continue;
+            }
+            let mut width = String::new();
+            while let Some(digit) = chars.next_if(char::is_ascii_digit) {
+                width.push(digit);
+            }
+            if chars.next() != Some('d') || (!width.is_empty() && !width.starts_with('0')) {
+                return Err(format!(
+                    "Invalid frame pattern '{}', expected %d or %0Nd for the frame number",
+                    s
+                ));
+            }
+            if digits.is_some() {
+                return Err(format!(
+                    "Invalid frame pattern '{}', expected a single frame number",
+                    s
+                ));
+            }
+            digits = Some(width.parse().unwrap_or(0));
+        }
+        let [prefix, suffix] = parts;
+        match digits {
+            Some(digits) => Ok(FramePattern {
+                prefix,
+                digits,
+                suffix,
+            }),
+            None => Err(format!(
+                "Invalid frame pattern '{}', expected %d or %0Nd for the frame number",
+                s
+            )),
+        }
+    }
+}
+
+/// The settings of a resized sequence.
+#[derive(Debug, Clone, Default)]
+pub struct SequenceOptions {
+    /// The width of the output frames. If None, it is computed from the height.
+    pub width: Option<u32>,
+    /// The height of the output frames. If None, it is computed from the width.
+    pub height: Option<u32>,
+    /// The number of the first input frame. If None, it is searched with
+    /// `FramePattern::first_frame`.
+    pub start_number: Option<u64>,
+    /// The encoder settings of the output frames.
+    pub encode: EncodeOptions,
+    /// Stops the sequence between frames when cancelled.
+    pub cancel: CancellationToken,
+}
+
+/// A summary of a resized sequence.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sequence {
+    /// The number of frames resized.
+    pub frames: u64,
+    /// The width of the output frames.
+    pub width: u32,
+    /// The height of the output frames.
+    pub height: u32,
+}
+
+/// Resizes the numbered frames of `input` into the numbered frames of `output`.
+///
+/// Frames are read from the start number until a number is missing, and every output
+/// frame keeps the number of its input frame. The format of the output frames is given by
+/// the extension of the output pattern.
+///
+/// # Arguments
+///
+/// * `input` - The file names of the input frames.
+/// * `output` - The file names of the output frames.
+/// * `options` - The output size, start number and encoder settings.
+/// * `context` - The resizers used for every frame.
+///
+/// # Returns
+///
+/// The number of frames and their output size.
+///
+/// # Errors
+///
+/// This function will return an error if:
+/// - The output format is not JPEG or PNG, or no input frame is found.
+/// - The output size cannot be determined, e.g. if neither width nor height is specified.
+/// - A frame cannot be decoded, resized, encoded or written.
+/// - The sequence is cancelled (`ResizeError::Cancelled`).
+pub fn resize_sequence(
+    input: &FramePattern,
+    output: &FramePattern,
+    options: &SequenceOptions,
+    context: &mut ResizeContext,
+) -> Result<Sequence, Box<dyn Error>> {
+    let format = match ImageFormat::from_path(output.path(0)) {
+        Ok(format @ (ImageFormat::Jpeg | ImageFormat::Png)) => format,
+        _ => return Err(format!("Unsupported output frame format '{}'", output).into()),
+    };
+    let start = match options.start_number {
+        Some(start) => start,
+        None => input.first_frame()?,
+    };
+
+    let mut size = None;
+    let mut frames = 0;
+    for number in start.. {
+        let path = input.path(number);
+        if !path.is_file() {
+            break;
+        }
+        options.cancel.check()?;
+        let image = image::ImageReader::open(&path)?
+            .with_guessed_format()?
+            .decode()
+            .map_err(|e| format!("Failed to decode {:?}: {}", path, e))?;
+        let (width, height) = match size {
+            Some(size) => size,
+            None => {
+                let (src_width, src_height) = image.dimensions();
+                let new_size = determine_new_dimensions(
+                    src_width,
+                    src_height,
+                    options.width.as_ref(),
+                    options.height.as_ref(),
+                    context.options(),
+                )?;
+                info!(
+                    "Resizing frames from {} to {}x{}",
+                    path.display(),
+                    new_size.0,
+                    new_size.1
+                );
+                *size.insert(new_size)
+            }
+        };
+        let resized = context.resize_image(image, Some(&width), Some(&height))?;
+        let (_, buffer) = estimate_size_and_encode(&resized, format, &options.encode)?;
+        write_frame(&output.path(number), &buffer)?;
+        debug!("Resized frame {}", number);
+        frames += 1;
+    }
+
+    match size {
+        Some((width, height)) => Ok(Sequence {
+            frames,
+            width,
+            height,
+        }),
+        None => Err(format!("No frame matching '{}' numbered {}", input, start).into()),
+    }
+}
+
+/// Writes an encoded frame, through a renamed temporary file.
+fn write_frame(path: &Path, buffer: &[u8]) -> Result<(), Box<dyn Error>> {
+    FileSystem
+        .write_file(path, buffer, true)
+        .map_err(|e| format!("Failed to write {:?}: {}", path, e).into())
+}
+
+/// The size and channels of raw frames.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RawFormat {
+    /// The width of a frame in pixels.
+    pub width: u32,
+    /// The height of a frame in pixels.
+    pub height: u32,
+    /// The channels of the pixels, which the output frames have too.
+    pub layout: PixelLayout,
+}
+
+/// Resizes raw frames read one after the other from `reader`, e.g. the standard output of
+/// `ffmpeg -f rawvideo`, and writes them to `writer` in the same layout.
+///
+/// The input and output buffers are allocated once and reused for every frame.
+///
+/// # Arguments
+///
+/// * `reader` - The input frames, packed without headers.
+/// * `writer` - Receives the output frames, packed without headers.
+/// * `format` - The size and layout of the input frames.
+/// * `options` - The output size; the start number and encoder settings are unused.
+/// * `context` - The resizers used for every frame.
+///
+/// # Returns
+///
+/// The number of frames and their output size.
+///
+/// # Errors
+///
+/// This function will return an error if:
+/// - The output size cannot be determined, e.g. if neither width nor height is specified.
+/// - The input ends within a frame, or reading or writing fails.
+/// - The sequence is cancelled (`ResizeError::Cancelled`).
+pub fn resize_raw_frames(
+    mut reader: impl Read,
+    mut writer: impl Write,
+    format: &RawFormat,
+    options: &SequenceOptions,
+    context: &mut ResizeContext,
+) -> Result<Sequence, Box<dyn Error>> {
+    let (width, height) = determine_new_dimensions(
+        format.width,
+        format.height,
+        options.width.as_ref(),
+        options.height.as_ref(),
+        context.options(),
+    )?;
+    let bytes_per_pixel = format.layout.bytes_per_pixel();
+    let mut frame = vec![0; format.width as usize * format.height as usize * bytes_per_pixel];
+    let mut resized = vec![0; width as usize * height as usize * bytes_per_pixel];
+
+    let mut frames = 0;
+    while read_frame(&mut reader, &mut frame)? {
+        options.cancel.check()?;
+        let source = RawImage::new(&frame, format.width, format.height, format.layout)?;
+        context.resize_into(&source, &mut resized, width, height)?;
+        writer.write_all(&resized)?;
+        frames += 1;
+    }
+    writer.flush()?;
+
+    Ok(Sequence {
+        frames,
+        width,
+        height,
+    })
+}
+
+/// Fills `frame` from `reader`.
+///
+/// # Returns
+///
+/// `false` if the reader ended before the frame.
+///
+/// # Errors
+///
+/// This function will return an error if reading fails, or if the reader ends within the
+/// frame.
+fn read_frame(reader: &mut impl Read, frame: &mut [u8]) -> io::Result<bool> {
+    let mut filled = 0;
+    while filled < frame.len() {
+        match reader.read(&mut frame[filled..]) {
+            Ok(0) if filled == 0 => return Ok(false),
+            Ok(0) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    format!(
+                        "The input ended within a frame, after {} of {} bytes",
+                        filled,
+                        frame.len()
+                    ),
+                ))
+            }
+            Ok(read) => filled += read,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::options::ResizeOptions;
+    use image::{Rgba, RgbaImage};
+    use tempfile::TempDir;
+
+    #[test]
+    fn parses_frame_patterns() {
+        let pattern: FramePattern = "out/frame_%04d.png".parse().unwrap();
+        assert_eq!(pattern.path(7), PathBuf::from("out/frame_0007.png"));
+        assert_eq!(pattern.path(12345), PathBuf::from("out/frame_12345.png"));
+        assert_eq!(pattern.to_string(), "out/frame_%04d.png");
+
+        let pattern: FramePattern = "100%%_%d.jpg".parse().unwrap();
+        assert_eq!(pattern.path(3), PathBuf::from("100%_3.jpg"));
+        assert_eq!(pattern.to_string(), "100%%_%d.jpg");
+
+        for invalid in [
+            "frame.png",
+            "frame_%d_%d.png",
+            "frame_%4d.png",
+            "frame_%s.png",
+        ] {
+            assert!(invalid.parse::<FramePattern>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn resizes_numbered_frames() {
+        let dir = TempDir::new().unwrap();
+        let input: FramePattern = format!("{}/in_%03d.png", dir.path().display())
+            .parse()
+            .unwrap();
+        let output: FramePattern = format!("{}/out_%03d.png", dir.path().display())
+            .parse()
+            .unwrap();
+        for number in 1..=3 {
+            RgbaImage::from_pixel(40, 20, Rgba([number as u8 * 50, 0, 0, 255]))
+                .save(input.path(number))
+                .unwrap();
+        }
+        // A gap ends the sequence
+        RgbaImage::new(40, 20).save(input.path(5)).unwrap();
+
+        let options = SequenceOptions {
+            width: Some(10),
+            ..Default::default()
+        };
+        let mut context = ResizeContext::new(ResizeOptions::default()).unwrap();
+        let sequence = resize_sequence(&input, &output, &options, &mut context).unwrap();
+        assert_eq!(
+            sequence,
+            Sequence {
+                frames: 3,
+                width: 10,
+                height: 5
+            }
+        );
+        let frame = image::open(output.path(2)).unwrap().into_rgba8();
+        assert_eq!(frame.dimensions(), (10, 5));
+        assert_eq!(frame.get_pixel(4, 2), &Rgba([100, 0, 0, 255]));
+        assert!(!output.path(0).exists() && !output.path(4).exists());
+    }
+
+    #[test]
+    fn resizes_raw_frames() {
+        let format = RawFormat {
+            width: 8,
+            height: 4,
+            layout: PixelLayout::Rgb,
+        };
+        let input: Vec<u8> = [10, 20, 30, 40, 50, 60]
+            .iter()
+            .flat_map(|&shade| vec![shade; 8 * 4 * 3])
+            .collect();
+        let options = SequenceOptions {
+            height: Some(2),
+            ..Default::default()
+        };
+        let mut context = ResizeContext::new(ResizeOptions::default()).unwrap();
+        let mut output = Vec::new();
+        let sequence =
+            resize_raw_frames(&input[..], &mut output, &format, &options, &mut context).unwrap();
+        assert_eq!(
+            (sequence.frames, sequence.width, sequence.height),
+            (6, 4, 2)
+        );
+        assert_eq!(output.len(), 6 * 4 * 2 * 3);
+        assert!(output[4 * 2 * 3 * 5..].iter().all(|&value| value == 60));
+
+        let error = resize_raw_frames(&input[..100], io::sink(), &format, &options, &mut context)
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "The input ended within a frame, after 4 of 96 bytes"
+        );
+    }
+}
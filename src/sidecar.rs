@@ -0,0 +1,313 @@
+//! XMP sidecars.
+//!
+//! Photo managers such as Lightroom and darktable do not modify the images they catalog:
+//! ratings and edits are stored in an `.xmp` file next to each image, named either after the
+//! image without its extension (`IMG_0001.xmp`, shared by RAW/JPEG pairs) or after the whole
+//! file name (`IMG_0001.jpg.xmp`). This module finds and reads these sidecars, applies the
+//! crop they store, and copies them next to outputs.
+//!
+//! Only the properties used here are read, with the standard prefixes, either as attributes
+//! (`xmp:Rating="3"`) or as elements (`<xmp:Rating>3</xmp:Rating>`):
+//! - `xmp:Rating`: from -1 (rejected) to 5 stars
+//! - `crs:HasCrop`, `crs:CropLeft`, `crs:CropTop`, `crs:CropRight` and `crs:CropBottom`: the
+//!   crop, as fractions of the image size
+//! - `crs:CropAngle`: the rotation of the crop; rotated crops are not supported and ignored
+
+use crate::{
+    options::JobOptions,
+    pipeline::{PipelineState, Stage},
+    storage::Storage,
+};
+use image::DynamicImage;
+use std::{
+    error::Error,
+    io::Read,
+    path::{Path, PathBuf},
+};
+use tracing::{debug, warn};
+
+/// The name of the sidecar crop stage.
+pub const SIDECAR_CROP: &str = "sidecar-crop";
+
+/// A crop, as fractions of the width and height of the image.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Crop {
+    /// The left edge, from 0.0 to 1.0.
+    pub left: f64,
+    /// The top edge, from 0.0 to 1.0.
+    pub top: f64,
+    /// The right edge, from 0.0 to 1.0.
+    pub right: f64,
+    /// The bottom edge, from 0.0 to 1.0.
+    pub bottom: f64,
+}
+
+impl Crop {
+    /// Crops an image. The crop always keeps at least one pixel.
+    pub fn apply(&self, image: DynamicImage) -> DynamicImage {
+        let (width, height) = (image.width(), image.height());
+        let edge =
+            |fraction: f64, size: u32| (fraction.clamp(0.0, 1.0) * size as f64).round() as u32;
+        let (left, top) = (edge(self.left, width), edge(self.top, height));
+        let right = edge(self.right, width).max(left + 1).min(width);
+        let bottom = edge(self.bottom, height).max(top + 1).min(height);
+        let (left, top) = (
+            left.min(right.saturating_sub(1)),
+            top.min(bottom.saturating_sub(1)),
+        );
+        if (left, top, right, bottom) == (0, 0, width, height) {
+            return image;
+        }
+        image.crop_imm(left, top, right - left, bottom - top)
+    }
+}
+
+/// The sidecar of an image.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sidecar {
+    /// The path of the sidecar.
+    pub path: PathBuf,
+    /// The XMP packet.
+    pub text: String,
+    /// The rating, from -1 (rejected) to 5.
+    pub rating: Option<i32>,
+    /// The crop, if the image is cropped.
+    pub crop: Option<Crop>,
+}
+
+/// Returns the value of an XMP property given as an attribute or as a simple element.
+fn property<'a>(text: &'a str, name: &str) -> Option<&'a str> {
+    let attribute = format!("{}=", name);
+    let mut rest = text;
+    while let Some(start) = rest.find(&attribute) {
+        let after = &rest[start + attribute.len()..];
+        // Only match whole attribute names
+        let preceded = rest[..start].ends_with(|c: char| c.is_whitespace());
+        if let Some(quote) = after.chars().next().filter(|c| *c == '"' || *c == '\'') {
+            if let Some(end) = after[1..].find(quote) {
+                if preceded {
+                    return Some(&after[1..1 + end]);
+                }
+            }
+        }
+        rest = after;
+    }
+
+    let open = format!("<{}>", name);
+    let start = text.find(&open)? + open.len();
+    let end = text[start..].find(&format!("</{}>", name))?;
+    Some(text[start..start + end].trim())
+}
+
+impl Sidecar {
+    /// Parses the properties of a sidecar.
+    pub fn parse(path: impl Into<PathBuf>, text: String) -> Self {
+        let path = path.into();
+        let number = |name: &str| property(&text, name).and_then(|v| v.trim().parse::<f64>().ok());
+        let rating = number("xmp:Rating").map(|rating| rating.round() as i32);
+        let has_crop =
+            property(&text, "crs:HasCrop").is_some_and(|v| v.eq_ignore_ascii_case("true"));
+        let crop = match (
+            number("crs:CropLeft"),
+            number("crs:CropTop"),
+            number("crs:CropRight"),
+            number("crs:CropBottom"),
+        ) {
+            (Some(left), Some(top), Some(right), Some(bottom)) if has_crop => {
+                let angle = number("crs:CropAngle").unwrap_or(0.0);
+                if angle.abs() > 0.01 {
+                    warn!(
+                        "Ignoring the crop of {:?}: rotated crops are not supported",
+                        path
+                    );
+                    None
+                } else {
+                    Some(Crop {
+                        left,
+                        top,
+                        right,
+                        bottom,
+                    })
+                }
+            }
+            _ => None,
+        };
+        Sidecar {
+            path,
+            text,
+            rating,
+            crop,
+        }
+    }
+
+    /// Finds and reads the sidecar of an image.
+    ///
+    /// # Returns
+    ///
+    /// The sidecar, or None if the image has none.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if a sidecar exists but cannot be read.
+    pub fn load(storage: &dyn Storage, input: &Path) -> Result<Option<Self>, Box<dyn Error>> {
+        let Some(path) = find(storage, input)? else {
+            return Ok(None);
+        };
+        let mut text = String::new();
+        storage
+            .open_read(&path)?
+            .read_to_string(&mut text)
+            .map_err(|e| format!("Failed to read the sidecar {:?}: {}", path, e))?;
+        Ok(Some(Sidecar::parse(path, text)))
+    }
+
+    /// Writes a copy of the sidecar next to an output, named after it with the `.xmp`
+    /// extension.
+    ///
+    /// # Arguments
+    ///
+    /// * `storage` - Where the output is.
+    /// * `output` - The path of the output.
+    /// * `cropped` - Whether the crop of the sidecar was applied to the output, in which
+    ///   case the copy is marked as not cropped, so that it is not applied twice.
+    ///
+    /// # Returns
+    ///
+    /// The path of the copy, or None if it would replace the sidecar itself, e.g. when the
+    /// output is next to the input with another extension.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the copy cannot be written.
+    pub fn copy_next_to(
+        &self,
+        storage: &dyn Storage,
+        output: &Path,
+        cropped: bool,
+    ) -> Result<Option<PathBuf>, Box<dyn Error>> {
+        let path = output.with_extension("xmp");
+        if path == self.path {
+            return Ok(None);
+        }
+        let text = if cropped && self.crop.is_some() {
+            self.text
+                .replace("crs:HasCrop=\"True\"", "crs:HasCrop=\"False\"")
+                .replace(
+                    "<crs:HasCrop>True</crs:HasCrop>",
+                    "<crs:HasCrop>False</crs:HasCrop>",
+                )
+        } else {
+            self.text.clone()
+        };
+        storage.write_file(&path, text.as_bytes(), true)?;
+        Ok(Some(path))
+    }
+}
+
+/// Returns the path of the sidecar of an image, if it has one.
+///
+/// `<name>.<ext>.xmp` is preferred over `<name>.xmp`, since it cannot belong to another
+/// image.
+///
+/// # Errors
+///
+/// This function will return an error if the storage cannot be queried.
+pub fn find(storage: &dyn Storage, input: &Path) -> Result<Option<PathBuf>, Box<dyn Error>> {
+    let Some(name) = input.file_name() else {
+        return Ok(None);
+    };
+    let mut full = name.to_os_string();
+    full.push(".xmp");
+    let candidates = [
+        input.with_file_name(full),
+        input.with_extension("xmp"),
+        input.with_extension("XMP"),
+    ];
+    for candidate in candidates {
+        if candidate != input && storage.exists(&candidate)? {
+            return Ok(Some(candidate));
+        }
+    }
+    Ok(None)
+}
+
+/// A stage cropping the current image as stored in its sidecar, meant to run before the
+/// resize stage. Images without a sidecar or without a crop are left unchanged.
+pub struct SidecarCropStage;
+
+impl Stage for SidecarCropStage {
+    fn name(&self) -> &str {
+        SIDECAR_CROP
+    }
+
+    fn run(&self, state: &mut PipelineState, options: &JobOptions) -> Result<(), Box<dyn Error>> {
+        let Some(crop) = Sidecar::load(&*options.storage, &state.input)?.and_then(|s| s.crop)
+        else {
+            return Ok(());
+        };
+        let image = state.take_image()?;
+        debug!("Applying the sidecar crop {:?}", crop);
+        state.image = Some(crop.apply(image));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStorage;
+    use image::{GenericImageView, RgbaImage};
+
+    const LIGHTROOM: &str = r#"<x:xmpmeta xmlns:x="adobe:ns:meta/">
+ <rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+  <rdf:Description rdf:about=""
+    xmp:Rating="4"
+    crs:CropTop="0.1"
+    crs:CropLeft="0.25"
+    crs:CropBottom="0.9"
+    crs:CropRight="0.75"
+    crs:CropAngle="0"
+    crs:HasCrop="True"/>
+ </rdf:RDF>
+</x:xmpmeta>"#;
+
+    #[test]
+    fn reads_ratings_and_crops() {
+        let sidecar = Sidecar::parse("a.xmp", LIGHTROOM.to_string());
+        assert_eq!(sidecar.rating, Some(4));
+        let crop = sidecar.crop.unwrap();
+        let cropped = crop.apply(DynamicImage::ImageRgba8(RgbaImage::new(100, 50)));
+        assert_eq!(cropped.dimensions(), (50, 40));
+
+        let elements = "<xmp:Rating>-1</xmp:Rating><crs:HasCrop>False</crs:HasCrop>";
+        let sidecar = Sidecar::parse("b.xmp", elements.to_string());
+        assert_eq!((sidecar.rating, sidecar.crop), (Some(-1), None));
+    }
+
+    #[test]
+    fn finds_and_copies_sidecars() {
+        let storage = MemoryStorage::default();
+        storage
+            .write_file(Path::new("IMG_1.xmp"), LIGHTROOM.as_bytes(), false)
+            .unwrap();
+        assert_eq!(
+            find(&storage, Path::new("IMG_1.jpg")).unwrap(),
+            Some(PathBuf::from("IMG_1.xmp"))
+        );
+        assert_eq!(find(&storage, Path::new("IMG_2.jpg")).unwrap(), None);
+
+        let sidecar = Sidecar::load(&storage, Path::new("IMG_1.jpg"))
+            .unwrap()
+            .unwrap();
+        let copy = sidecar
+            .copy_next_to(&storage, Path::new("out/IMG_1_small.jpg"), true)
+            .unwrap();
+        assert_eq!(copy, Some(PathBuf::from("out/IMG_1_small.xmp")));
+        let copied = Sidecar::load(&storage, Path::new("out/IMG_1_small.jpg"))
+            .unwrap()
+            .unwrap();
+        assert_eq!((copied.rating, copied.crop), (Some(4), None));
+        let copy = sidecar.copy_next_to(&storage, Path::new("IMG_1.png"), true);
+        assert_eq!(copy.unwrap(), None);
+    }
+}
@@ -0,0 +1,141 @@
+//! Output sinks.
+//!
+//! By default every resized image is saved as a loose file next to its input or in the
+//! output directory. An `OutputSink` receives the encoded images instead, so that a batch
+//! can write them somewhere else. `ZipSink` streams each image into a ZIP archive as soon
+//! as it is encoded, so the images never have to be kept in memory together.
+
+use crate::overwrite;
+use std::{
+    collections::HashSet,
+    error::Error,
+    fs::File,
+    io::{Seek, Write},
+    path::{Component, Path, PathBuf},
+};
+use zip::{write::SimpleFileOptions, CompressionMethod, DateTime, ZipWriter};
+
+/// A destination for encoded images.
+pub trait OutputSink {
+    /// Writes an encoded image under the relative path `name`.
+    ///
+    /// # Returns
+    ///
+    /// The path the image was actually written to, which may differ from `name` to avoid
+    /// a conflict.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the image cannot be written.
+    fn write(&mut self, name: &Path, contents: &[u8]) -> Result<PathBuf, Box<dyn Error>>;
+}
+
+/// Writes images as entries of a ZIP archive.
+///
+/// Images are stored without further compression, since JPEG and PNG data is already
+/// compressed. If an entry name is taken, the image is stored under a numbered name such as
+/// `photo_1.jpg`. The archive is only valid once `finish` has been called.
+pub struct ZipSink<W: Write + Seek> {
+    writer: ZipWriter<W>,
+    names: HashSet<String>,
+}
+
+impl ZipSink<File> {
+    /// Creates a ZIP archive at `path`, replacing any existing file.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the file cannot be created.
+    pub fn create(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let file = File::create(path)
+            .map_err(|e| format!("Failed to create archive {:?}: {}", path, e))?;
+        Ok(ZipSink::new(file))
+    }
+}
+
+impl<W: Write + Seek> ZipSink<W> {
+    /// Creates a sink writing a ZIP archive to `inner`.
+    pub fn new(inner: W) -> Self {
+        ZipSink {
+            writer: ZipWriter::new(inner),
+            names: HashSet::new(),
+        }
+    }
+
+    /// Writes the central directory of the archive and returns the inner writer.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the archive cannot be written.
+    pub fn finish(self) -> Result<W, Box<dyn Error>> {
+        Ok(self.writer.finish()?)
+    }
+
+    /// Returns a free entry name for `name`, using `/` as the separator.
+    fn entry_name(&mut self, name: &Path) -> Result<String, Box<dyn Error>> {
+        let mut candidate = zip_name(name)?;
+        let mut n = 0;
+        while self.names.contains(&candidate) {
+            n += 1;
+            candidate = zip_name(&overwrite::numbered(name, n))?;
+        }
+        self.names.insert(candidate.clone());
+        Ok(candidate)
+    }
+}
+
+impl<W: Write + Seek> OutputSink for ZipSink<W> {
+    fn write(&mut self, name: &Path, contents: &[u8]) -> Result<PathBuf, Box<dyn Error>> {
+        let entry = self.entry_name(name)?;
+        // A fixed timestamp, so that archives of identical images are identical
+        let options = SimpleFileOptions::default()
+            .compression_method(CompressionMethod::Stored)
+            .last_modified_time(DateTime::default());
+        self.writer.start_file(entry.as_str(), options)?;
+        self.writer.write_all(contents)?;
+        Ok(PathBuf::from(entry))
+    }
+}
+
+/// Converts a relative path to a ZIP entry name.
+fn zip_name(path: &Path) -> Result<String, Box<dyn Error>> {
+    let parts = path
+        .components()
+        .map(|component| match component {
+            Component::Normal(part) => Ok(part.to_string_lossy()),
+            _ => Err(format!("Invalid archive entry name {:?}", path)),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    if parts.is_empty() {
+        return Err(format!("Invalid archive entry name {:?}", path).into());
+    }
+    Ok(parts.join("/"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Cursor, Read};
+
+    #[test]
+    fn writes_zip_entries() {
+        let mut sink = ZipSink::new(Cursor::new(Vec::new()));
+        let first = sink.write(Path::new("a.png"), b"first").unwrap();
+        let second = sink.write(Path::new("a.png"), b"second").unwrap();
+        let nested = sink.write(Path::new("dir/b.jpg"), b"nested").unwrap();
+        assert_eq!(first, PathBuf::from("a.png"));
+        assert_eq!(second, PathBuf::from("a_1.png"));
+        assert_eq!(nested, PathBuf::from("dir/b.jpg"));
+        assert!(sink.write(Path::new("../c.png"), b"outside").is_err());
+
+        let mut archive = zip::ZipArchive::new(sink.finish().unwrap()).unwrap();
+        assert_eq!(archive.len(), 3);
+        let mut contents = String::new();
+        archive
+            .by_name("a_1.png")
+            .unwrap()
+            .read_to_string(&mut contents)
+            .unwrap();
+        assert_eq!(contents, "second");
+    }
+}
@@ -0,0 +1,326 @@
+//! Splitting images into tiles.
+//!
+//! This is the inverse of a montage: an image is cut into a grid of tiles, given either as
+//! a number of columns and rows or as a tile size, e.g. to print a poster on several
+//! sheets or to serve a large map as tiles.
+//!
+//! A grid of columns and rows is split evenly, with tile sizes differing by at most one
+//! pixel. With a tile size, the tiles of the last column and row are smaller than the
+//! others when the image does not divide evenly. They can be kept as they are, padded to
+//! the full tile size with a background color, or dropped. Tiles can also overlap: each
+//! tile then extends by the overlap into its right and bottom neighbors, which helps to
+//! align printed sheets.
+
+use image::{imageops, GenericImageView, Rgba, RgbaImage};
+use std::{error::Error, fmt, str::FromStr};
+
+/// How the grid of tiles is defined.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Grid {
+    /// A number of columns and rows, splitting the image evenly.
+    Count { columns: u32, rows: u32 },
+    /// A tile size in pixels. The number of columns and rows is rounded up to cover the
+    /// whole image.
+    Size { width: u32, height: u32 },
+}
+
+/// What to do with the tiles of the last column and row when they are smaller than the
+/// tile size of a `Grid::Size`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EdgeTiles {
+    /// Keep them at their smaller size.
+    #[default]
+    Keep,
+    /// Pad them to the full tile size with the background color.
+    Pad,
+    /// Leave them out.
+    Drop,
+}
+
+impl fmt::Display for EdgeTiles {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            EdgeTiles::Keep => "keep",
+            EdgeTiles::Pad => "pad",
+            EdgeTiles::Drop => "drop",
+        };
+        f.write_str(name)
+    }
+}
+
+impl FromStr for EdgeTiles {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "keep" => Ok(EdgeTiles::Keep),
+            "pad" => Ok(EdgeTiles::Pad),
+            "drop" => Ok(EdgeTiles::Drop),
+            _ => Err(format!("Unknown edge tile handling '{}'", s)),
+        }
+    }
+}
+
+/// The settings of a split.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SplitOptions {
+    /// The grid of tiles.
+    pub grid: Grid,
+    /// The number of pixels each tile extends into its right and bottom neighbors.
+    pub overlap: u32,
+    /// What to do with the smaller tiles of the last column and row.
+    pub edges: EdgeTiles,
+    /// The color used to pad edge tiles with `EdgeTiles::Pad`.
+    pub background: Rgba<u8>,
+}
+
+/// A tile cut from an image.
+#[derive(Debug, Clone)]
+pub struct Tile {
+    /// The row of the tile, starting at 0 at the top.
+    pub row: u32,
+    /// The column of the tile, starting at 0 at the left.
+    pub column: u32,
+    /// The left edge of the tile in the image, in pixels.
+    pub x: u32,
+    /// The top edge of the tile in the image, in pixels.
+    pub y: u32,
+    /// The pixels of the tile.
+    pub image: RgbaImage,
+}
+
+/// Cuts an image into tiles.
+///
+/// # Arguments
+///
+/// * `image` - The image to split.
+/// * `options` - The grid and the handling of edge tiles and overlap.
+///
+/// # Returns
+///
+/// The tiles, row by row from the top left.
+///
+/// # Errors
+///
+/// This function will return an error if the grid has zero columns, rows or tile size, or
+/// if it has more columns or rows than the image has pixels.
+pub fn split(image: &RgbaImage, options: &SplitOptions) -> Result<Vec<Tile>, Box<dyn Error>> {
+    let (width, height) = image.dimensions();
+    let (columns, rows, edges) = match options.grid {
+        Grid::Count { columns, rows } => {
+            if columns == 0 || rows == 0 {
+                return Err("The grid must have at least one column and one row".into());
+            }
+            if columns > width || rows > height {
+                return Err(format!(
+                    "A {}x{} image cannot be split into {} columns and {} rows",
+                    width, height, columns, rows
+                )
+                .into());
+            }
+            // Even tiles have no smaller edge tiles to handle
+            (
+                even_spans(width, columns),
+                even_spans(height, rows),
+                EdgeTiles::Keep,
+            )
+        }
+        Grid::Size {
+            width: tile_width,
+            height: tile_height,
+        } => {
+            if tile_width == 0 || tile_height == 0 {
+                return Err("The tile size must be at least 1x1 pixels".into());
+            }
+            (
+                sized_spans(width, tile_width),
+                sized_spans(height, tile_height),
+                options.edges,
+            )
+        }
+    };
+
+    let mut tiles = Vec::new();
+    for (row, &(y, tile_height, full_row)) in rows.iter().enumerate() {
+        for (column, &(x, tile_width, full_column)) in columns.iter().enumerate() {
+            let is_edge = !(full_row && full_column);
+            let (full_width, full_height) = (
+                tile_width.saturating_add(options.overlap),
+                tile_height.saturating_add(options.overlap),
+            );
+            let visible_width = full_width.min(width - x);
+            let visible_height = full_height.min(height - y);
+            let view = image.view(x, y, visible_width, visible_height);
+            let tile = match edges {
+                EdgeTiles::Drop if is_edge => continue,
+                EdgeTiles::Pad if (visible_width, visible_height) != (full_width, full_height) => {
+                    let mut padded =
+                        RgbaImage::from_pixel(full_width, full_height, options.background);
+                    imageops::replace(&mut padded, &*view, 0, 0);
+                    padded
+                }
+                _ => view.to_image(),
+            };
+            tiles.push(Tile {
+                row: row as u32,
+                column: column as u32,
+                x,
+                y,
+                image: tile,
+            });
+        }
+    }
+    Ok(tiles)
+}
+
+/// Splits a length into `count` nearly equal spans, as `(start, length, true)`.
+fn even_spans(length: u32, count: u32) -> Vec<(u32, u32, bool)> {
+    let boundary = |i: u32| (i as u64 * length as u64 / count as u64) as u32;
+    (0..count)
+        .map(|i| (boundary(i), boundary(i + 1) - boundary(i), true))
+        .collect()
+}
+
+/// Splits a length into spans of `size`, as `(start, nominal length, is full)`. The last
+/// span is not full if `size` does not divide the length; its nominal length is still
+/// `size`, so that it can be padded.
+fn sized_spans(length: u32, size: u32) -> Vec<(u32, u32, bool)> {
+    (0..length)
+        .step_by(size as usize)
+        .map(|start| (start, size, start + size <= length))
+        .collect()
+}
+
+/// Parses a pair of numbers given as `<a>x<b>`, such as `3x2` or `256x256`. A single
+/// number `n` is read as `nxn`.
+///
+/// # Errors
+///
+/// This function will return an error message if the value is not in one of these forms,
+/// or if a number is zero.
+pub fn parse_pair(s: &str) -> Result<(u32, u32), String> {
+    let invalid = || format!("Invalid value '{}', expected a number or <a>x<b>", s);
+    let parse = |part: &str| match part.trim().parse::<u32>() {
+        Ok(0) | Err(_) => Err(invalid()),
+        Ok(value) => Ok(value),
+    };
+    match s.to_lowercase().split_once('x') {
+        Some((a, b)) => Ok((parse(a)?, parse(b)?)),
+        None => parse(s).map(|value| (value, value)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn numbered(width: u32, height: u32) -> RgbaImage {
+        RgbaImage::from_fn(width, height, |x, y| Rgba([x as u8, y as u8, 0, 255]))
+    }
+
+    fn options(grid: Grid) -> SplitOptions {
+        SplitOptions {
+            grid,
+            overlap: 0,
+            edges: EdgeTiles::Keep,
+            background: Rgba([0, 0, 0, 0]),
+        }
+    }
+
+    #[test]
+    fn splits_into_a_grid() {
+        let image = numbered(10, 7);
+        let tiles = split(
+            &image,
+            &options(Grid::Count {
+                columns: 3,
+                rows: 2,
+            }),
+        )
+        .unwrap();
+
+        // Columns of 3, 3 and 4 pixels, rows of 3 and 4 pixels
+        assert_eq!(tiles.len(), 6);
+        let last = &tiles[5];
+        assert_eq!((last.row, last.column, last.x, last.y), (1, 2, 6, 3));
+        assert_eq!(last.image.dimensions(), (4, 4));
+        assert_eq!(last.image.get_pixel(0, 0), image.get_pixel(6, 3));
+        assert_eq!(tiles[0].image.dimensions(), (3, 3));
+
+        // Rounding the tile size up would only make 3 columns of 3 pixels
+        let tiles = split(
+            &numbered(9, 1),
+            &options(Grid::Count {
+                columns: 4,
+                rows: 1,
+            }),
+        )
+        .unwrap();
+        assert_eq!(tiles.len(), 4);
+
+        assert!(split(
+            &image,
+            &options(Grid::Count {
+                columns: 11,
+                rows: 1
+            })
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn handles_edge_tiles_and_overlap() {
+        let image = numbered(10, 4);
+        let grid = Grid::Size {
+            width: 4,
+            height: 4,
+        };
+
+        let dropped = split(
+            &image,
+            &SplitOptions {
+                edges: EdgeTiles::Drop,
+                ..options(grid)
+            },
+        )
+        .unwrap();
+        assert_eq!(dropped.len(), 2);
+
+        let padded = split(
+            &image,
+            &SplitOptions {
+                edges: EdgeTiles::Pad,
+                ..options(grid)
+            },
+        )
+        .unwrap();
+        assert_eq!(padded[2].image.dimensions(), (4, 4));
+        assert_eq!(padded[2].image.get_pixel(2, 0), &Rgba([0, 0, 0, 0]));
+
+        // Each tile extends 2 pixels into the next one
+        let overlapping = split(
+            &image,
+            &SplitOptions {
+                overlap: 2,
+                ..options(grid)
+            },
+        )
+        .unwrap();
+        assert_eq!(overlapping[0].image.dimensions(), (6, 4));
+        assert_eq!(overlapping[1].image.get_pixel(0, 0), image.get_pixel(4, 0));
+        assert_eq!(overlapping[2].image.dimensions(), (2, 4));
+    }
+
+    #[test]
+    fn parses_pairs() {
+        assert_eq!(parse_pair("3x2"), Ok((3, 2)));
+        assert_eq!(parse_pair("256X128"), Ok((256, 128)));
+        assert_eq!(parse_pair("512"), Ok((512, 512)));
+        assert!(parse_pair("0x2").is_err());
+        assert!(parse_pair("3by2").is_err());
+        assert_eq!(
+            EdgeTiles::Pad.to_string().parse::<EdgeTiles>(),
+            Ok(EdgeTiles::Pad)
+        );
+    }
+}
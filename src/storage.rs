@@ -0,0 +1,426 @@
+//! Pluggable storage backends.
+//!
+//! The library reads inputs and writes outputs through the `Storage` trait, so images can
+//! live somewhere other than the local filesystem, such as a database, a blob store or
+//! memory. `FileSystem` is the default backend and the one used by the command-line tool;
+//! `MemoryStorage` keeps files in memory, which is mostly useful in tests. `FileSystem`
+//! passes long paths to Windows in their extended-length form (see `paths`).
+//!
+//! A backend is selected per job with `JobOptions::storage`. Features that only make sense
+//! on a local filesystem, such as extended attributes or archive inputs, bypass it.
+
+use crate::paths;
+use image::{ImageFormat, ImageReader};
+use std::{
+    collections::BTreeMap,
+    fmt,
+    fs::{self, File},
+    io::{self, BufRead, BufReader, Cursor, Read, Seek, Write},
+    ops::Deref,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+/// A readable and seekable stream, as returned by `Storage::open_read`.
+pub trait ReadSeek: Read + Seek + Send {}
+
+impl<T: Read + Seek + Send> ReadSeek for T {}
+
+/// A place where input images are read from and output images are written to.
+pub trait Storage: fmt::Debug + Send + Sync {
+    /// Opens the file at `path` for reading.
+    fn open_read(&self, path: &Path) -> io::Result<Box<dyn ReadSeek>>;
+
+    /// Creates or truncates the file at `path` for writing.
+    fn create_write(&self, path: &Path) -> io::Result<Box<dyn Write>>;
+
+    /// Returns `true` if a file exists at `path`.
+    fn exists(&self, path: &Path) -> io::Result<bool>;
+
+    /// Lists the entries of the directory at `dir`.
+    fn list(&self, dir: &Path) -> io::Result<Vec<PathBuf>>;
+
+    /// Replaces the file at `path` with `contents`.
+    ///
+    /// Backends that can replace a file atomically should do so when `atomic` is set. The
+    /// default implementation writes through `create_write`.
+    fn write_file(&self, path: &Path, contents: &[u8], atomic: bool) -> io::Result<()> {
+        let _ = atomic;
+        let mut writer = self.create_write(path)?;
+        writer.write_all(contents)?;
+        writer.flush()
+    }
+
+    /// Creates the file at `path` for writing piece by piece, e.g. as an image is encoded
+    /// row by row. The file is complete once committed.
+    ///
+    /// Backends that can replace a file atomically should do so when `atomic` is set, leaving
+    /// any existing file untouched until the staged file is committed. The default
+    /// implementation writes through `create_write`.
+    fn create_staged(&self, path: &Path, atomic: bool) -> io::Result<Box<dyn StagedFile>> {
+        let _ = atomic;
+        Ok(Box::new(DirectFile(self.create_write(path)?)))
+    }
+}
+
+/// A file being written with `Storage::create_staged`.
+pub trait StagedFile: Write {
+    /// Finishes writing the file, replacing any file previously at its path.
+    fn commit(self: Box<Self>) -> io::Result<()>;
+}
+
+/// A staged file written in place.
+struct DirectFile<W>(W);
+
+impl<W: Write> Write for DirectFile<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+impl<W: Write> StagedFile for DirectFile<W> {
+    fn commit(mut self: Box<Self>) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+/// Opens an image for reading from a storage backend, guessing its format from its contents,
+/// or from its extension if the contents tell nothing.
+///
+/// # Errors
+///
+/// This function will return an error if the file cannot be opened or read.
+pub fn image_reader(
+    storage: &dyn Storage,
+    path: &Path,
+) -> io::Result<ImageReader<BufReader<Box<dyn ReadSeek>>>> {
+    image_reader_from(BufReader::new(storage.open_read(path)?), path)
+}
+
+/// Wraps an opened image file in an `ImageReader` like `image_reader`, guessing its format
+/// from its contents or from the extension of `path`.
+///
+/// # Errors
+///
+/// This function will return an error if the file cannot be read.
+pub(crate) fn image_reader_from<R: BufRead + Seek>(
+    source: R,
+    path: &Path,
+) -> io::Result<ImageReader<R>> {
+    let mut reader = ImageReader::new(source).with_guessed_format()?;
+    // TGA files have no signature, so their format is taken from their extension
+    if reader.format().is_none() {
+        if let Ok(format) = ImageFormat::from_path(path) {
+            reader.set_format(format);
+        }
+    }
+    Ok(reader)
+}
+
+/// Returns the size in bytes of the file at `path`.
+///
+/// # Errors
+///
+/// This function will return an error if the file cannot be opened or read.
+pub fn file_size(storage: &dyn Storage, path: &Path) -> io::Result<u64> {
+    storage.open_read(path)?.seek(io::SeekFrom::End(0))
+}
+
+/// The number of random characters in the names of temporary files.
+const TEMP_RANDOM_LEN: usize = 6;
+
+/// The local filesystem.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FileSystem;
+
+impl Storage for FileSystem {
+    fn open_read(&self, path: &Path) -> io::Result<Box<dyn ReadSeek>> {
+        Ok(Box::new(File::open(paths::for_filesystem(path))?))
+    }
+
+    fn create_write(&self, path: &Path) -> io::Result<Box<dyn Write>> {
+        Ok(Box::new(File::create(paths::for_filesystem(path))?))
+    }
+
+    fn exists(&self, path: &Path) -> io::Result<bool> {
+        paths::for_filesystem(path).try_exists()
+    }
+
+    fn list(&self, dir: &Path) -> io::Result<Vec<PathBuf>> {
+        fs::read_dir(paths::for_filesystem(dir))?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect()
+    }
+
+    fn write_file(&self, path: &Path, contents: &[u8], atomic: bool) -> io::Result<()> {
+        let mut file = self.create_staged(path, atomic)?;
+        file.write_all(contents)?;
+        file.commit()
+    }
+
+    /// Writes to a temporary file in the destination directory and renames it over `path`
+    /// when `atomic` is set, so that a failed write never leaves a truncated file behind.
+    ///
+    /// Every write gets a temporary file with a unique name, so that threads writing the same
+    /// output do not clobber each other's. The temporary file takes the permissions of the
+    /// file it replaces, or those of a newly created file.
+    fn create_staged(&self, path: &Path, atomic: bool) -> io::Result<Box<dyn StagedFile>> {
+        let path = paths::for_filesystem(path);
+        if !atomic {
+            return Ok(Box::new(DirectFile(File::create(path)?)));
+        }
+
+        // The temporary file name is longer, so its directory may need the extended-length
+        // form even if the path does not
+        let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+        let prefix = format!(".{}.", file_name);
+        let placeholder =
+            path.with_file_name(format!("{}{}.tmp", prefix, "x".repeat(TEMP_RANDOM_LEN)));
+        let placeholder = paths::for_filesystem(&placeholder);
+        let dir = match placeholder.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+
+        let mut builder = tempfile::Builder::new();
+        builder
+            .prefix(&prefix)
+            .suffix(".tmp")
+            .rand_bytes(TEMP_RANDOM_LEN);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            // Like `File::create`, subject to the umask
+            builder.permissions(fs::Permissions::from_mode(0o666));
+        }
+        Ok(Box::new(AtomicFile {
+            file: builder.tempfile_in(dir)?,
+            path: path.into_owned(),
+        }))
+    }
+}
+
+/// A file written to a temporary file, which is renamed over `path` on commit.
+///
+/// The temporary file is removed when dropped, unless it was committed.
+struct AtomicFile {
+    file: tempfile::NamedTempFile,
+    path: PathBuf,
+}
+
+impl Write for AtomicFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.file.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+impl StagedFile for AtomicFile {
+    fn commit(self: Box<Self>) -> io::Result<()> {
+        let AtomicFile { file, path } = *self;
+        if let Ok(metadata) = fs::metadata(&path) {
+            file.as_file().set_permissions(metadata.permissions())?;
+        }
+        file.as_file().sync_all()?;
+        file.persist(&path).map_err(|e| e.error)?;
+        Ok(())
+    }
+}
+
+/// Files kept in memory, keyed by path.
+///
+/// Clones share the same files, so a clone kept by the caller sees the outputs written by a
+/// job.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryStorage {
+    files: Arc<Mutex<BTreeMap<PathBuf, Vec<u8>>>>,
+}
+
+impl MemoryStorage {
+    /// Creates an empty storage.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds or replaces the file at `path`.
+    pub fn insert(&self, path: impl Into<PathBuf>, contents: Vec<u8>) {
+        self.files.lock().unwrap().insert(path.into(), contents);
+    }
+
+    /// Returns a copy of the contents of the file at `path`.
+    pub fn get(&self, path: &Path) -> Option<Vec<u8>> {
+        self.files.lock().unwrap().get(path).cloned()
+    }
+}
+
+impl Storage for MemoryStorage {
+    fn open_read(&self, path: &Path) -> io::Result<Box<dyn ReadSeek>> {
+        let contents = self.get(path).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound, format!("{:?} not found", path))
+        })?;
+        Ok(Box::new(Cursor::new(contents)))
+    }
+
+    fn create_write(&self, path: &Path) -> io::Result<Box<dyn Write>> {
+        self.insert(path, Vec::new());
+        Ok(Box::new(MemoryWriter {
+            storage: self.clone(),
+            path: path.to_path_buf(),
+            buffer: Vec::new(),
+        }))
+    }
+
+    fn exists(&self, path: &Path) -> io::Result<bool> {
+        Ok(self.files.lock().unwrap().contains_key(path))
+    }
+
+    fn list(&self, dir: &Path) -> io::Result<Vec<PathBuf>> {
+        Ok(self
+            .files
+            .lock()
+            .unwrap()
+            .keys()
+            .filter(|path| path.parent() == Some(dir))
+            .cloned()
+            .collect())
+    }
+}
+
+/// A file being written to a `MemoryStorage`. The contents are stored on flush and drop.
+struct MemoryWriter {
+    storage: MemoryStorage,
+    path: PathBuf,
+    buffer: Vec<u8>,
+}
+
+impl Write for MemoryWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.storage.insert(&self.path, self.buffer.clone());
+        Ok(())
+    }
+}
+
+impl Drop for MemoryWriter {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
+
+/// A shared handle to a storage backend, defaulting to the local filesystem.
+#[derive(Debug, Clone)]
+pub struct SharedStorage(Arc<dyn Storage>);
+
+impl SharedStorage {
+    /// Wraps a storage backend.
+    pub fn new(storage: impl Storage + 'static) -> Self {
+        SharedStorage(Arc::new(storage))
+    }
+}
+
+impl Default for SharedStorage {
+    fn default() -> Self {
+        SharedStorage::new(FileSystem)
+    }
+}
+
+impl Deref for SharedStorage {
+    type Target = dyn Storage;
+
+    fn deref(&self) -> &Self::Target {
+        self.0.as_ref()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn memory_storage_round_trip() {
+        let storage = MemoryStorage::new();
+        let path = Path::new("images/a.png");
+        assert!(!storage.exists(path).unwrap());
+        assert!(storage.open_read(path).is_err());
+
+        storage.write_file(path, b"contents", true).unwrap();
+        assert!(storage.exists(path).unwrap());
+        let mut contents = Vec::new();
+        storage
+            .open_read(path)
+            .unwrap()
+            .read_to_end(&mut contents)
+            .unwrap();
+        assert_eq!(contents, b"contents");
+        assert_eq!(file_size(&storage, path).unwrap(), 8);
+        assert_eq!(
+            storage.list(Path::new("images")).unwrap(),
+            vec![path.to_path_buf()]
+        );
+    }
+
+    #[test]
+    fn filesystem_writes_atomically() {
+        let dir = tempfile::TempDir::new().expect("Failed to create a temp dir");
+        let path = dir.path().join("output.png");
+        fs::write(&path, b"old").unwrap();
+
+        FileSystem.write_file(&path, b"new", true).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"new");
+        assert_eq!(FileSystem.list(dir.path()).unwrap(), vec![path]);
+    }
+
+    #[test]
+    fn concurrent_atomic_writes_do_not_collide() {
+        let dir = tempfile::TempDir::new().expect("Failed to create a temp dir");
+        let path = dir.path().join("output.png");
+        std::thread::scope(|scope| {
+            for thread in 0..8u8 {
+                let path = &path;
+                scope.spawn(move || {
+                    for _ in 0..20 {
+                        FileSystem.write_file(path, &[thread; 4096], true).unwrap();
+                    }
+                });
+            }
+        });
+        let contents = fs::read(&path).unwrap();
+        assert!(contents.len() == 4096 && contents.iter().all(|&b| b == contents[0]));
+        assert_eq!(FileSystem.list(dir.path()).unwrap(), vec![path]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn atomic_writes_keep_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::TempDir::new().expect("Failed to create a temp dir");
+        let path = dir.path().join("output.png");
+        fs::write(&path, b"old").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o640)).unwrap();
+        FileSystem.write_file(&path, b"new", true).unwrap();
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o640);
+
+        // New files get the same permissions as without a temporary file
+        let created = dir.path().join("created.png");
+        File::create(&created).unwrap();
+        let new = dir.path().join("new.png");
+        FileSystem.write_file(&new, b"new", true).unwrap();
+        assert_eq!(
+            fs::metadata(&new).unwrap().permissions(),
+            fs::metadata(&created).unwrap().permissions()
+        );
+    }
+}
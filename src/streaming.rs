@@ -0,0 +1,559 @@
+//! Streaming resizes of PNG and JPEG files.
+//!
+//! The tiled path (see the `tiled` module) still decodes the whole source and keeps the whole
+//! destination in memory. A streaming resize instead reads the source rows as they are
+//! decoded, resizes them band by band and hands every finished band to the encoder, which
+//! writes it out right away. Peak memory then depends on the widths of the images and the
+//! band height, not on their heights (see `limits::estimate_streaming_memory`).
+//!
+//! `resize_file` streams the jobs with `JobOptions::low_memory` set whose pipeline is the
+//! standard one, when both the input and the output can be streamed:
+//! - Non-interlaced PNG inputs and outputs. Palettes and Adam7 interlacing need the whole
+//!   image.
+//! - Sequential JPEG inputs and outputs, in builds with the `mozjpeg` feature. JPEG outputs
+//!   are then written by MozJPEG as baseline JPEGs, since progressive files need the whole
+//!   image.
+//!
+//! Jobs writing hashed names or using the output cache need the whole encoded output, and
+//! are not streamed either. Every other job takes the tiled path.
+
+use crate::{
+    encode::PngRowWriter,
+    error::ResizeError,
+    limits::{self, streaming_band},
+    options::JobOptions,
+    pipeline::output_dimensions,
+    storage::{self, ReadSeek, StagedFile},
+    tiled::{band_margin, bands, resize_band},
+    ImageInfo,
+};
+use image::ImageFormat;
+use std::{error::Error, io::BufReader, path::Path, time::Instant};
+use tracing::{debug, info_span};
+
+/// A decoder producing the rows of an image from top to bottom.
+trait RowReader {
+    /// Returns the width and height of the image.
+    fn dimensions(&self) -> (u32, u32);
+
+    /// Decodes the next row into `row`, as RGBA pixels.
+    fn read_row(&mut self, row: &mut [u8]) -> Result<(), Box<dyn Error>>;
+}
+
+/// Decodes a non-interlaced PNG row by row.
+struct PngRows {
+    reader: png::Reader<BufReader<Box<dyn ReadSeek>>>,
+}
+
+impl PngRows {
+    /// Reads the header of a PNG, or returns `None` if the PNG is interlaced.
+    fn open(source: Box<dyn ReadSeek>) -> Result<Option<Self>, Box<dyn Error>> {
+        let mut decoder = png::Decoder::new(BufReader::new(source));
+        // Palettes, transparency chunks and bit depths below 8 are expanded
+        decoder.set_transformations(png::Transformations::EXPAND);
+        let reader = decoder.read_info()?;
+        if reader.info().interlaced {
+            return Ok(None);
+        }
+        Ok(Some(PngRows { reader }))
+    }
+}
+
+impl RowReader for PngRows {
+    fn dimensions(&self) -> (u32, u32) {
+        let info = self.reader.info();
+        (info.width, info.height)
+    }
+
+    fn read_row(&mut self, row: &mut [u8]) -> Result<(), Box<dyn Error>> {
+        let (color_type, bit_depth) = self.reader.output_color_type();
+        let decoded = self
+            .reader
+            .next_row()?
+            .ok_or("The PNG ended before its last row")?;
+        let data = decoded.data();
+        // 16-bit samples are big-endian, and rounded to 8 bits like the `image` crate does
+        let sample = |index: usize| match bit_depth {
+            png::BitDepth::Sixteen => {
+                let value = u16::from_be_bytes([data[2 * index], data[2 * index + 1]]);
+                ((value as u32 * 255 + 32767) / 65535) as u8
+            }
+            _ => data[index],
+        };
+        let channels = color_type.samples();
+        for (x, pixel) in row.chunks_exact_mut(4).enumerate() {
+            let first = x * channels;
+            pixel.copy_from_slice(&match color_type {
+                png::ColorType::Grayscale => {
+                    let gray = sample(first);
+                    [gray, gray, gray, 255]
+                }
+                png::ColorType::GrayscaleAlpha => {
+                    let gray = sample(first);
+                    [gray, gray, gray, sample(first + 1)]
+                }
+                png::ColorType::Rgb => [sample(first), sample(first + 1), sample(first + 2), 255],
+                _ => [
+                    sample(first),
+                    sample(first + 1),
+                    sample(first + 2),
+                    sample(first + 3),
+                ],
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Decodes a sequential JPEG row by row with MozJPEG.
+#[cfg(feature = "mozjpeg")]
+struct JpegRows {
+    started: mozjpeg::decompress::DecompressStarted<BufReader<Box<dyn ReadSeek>>>,
+}
+
+#[cfg(feature = "mozjpeg")]
+impl JpegRows {
+    /// Reads the header of a JPEG, or returns `None` if the JPEG is progressive, or has
+    /// colors MozJPEG cannot convert to RGB, such as CMYK.
+    fn open(storage: &dyn storage::Storage, path: &Path) -> Result<Option<Self>, Box<dyn Error>> {
+        // A progressive JPEG is only complete after its last scan, so the decoder would
+        // keep the whole image
+        if !is_sequential_jpeg(BufReader::new(storage.open_read(path)?))? {
+            return Ok(None);
+        }
+        let source = BufReader::new(storage.open_read(path)?);
+        crate::encode::catch_mozjpeg(|| {
+            let decompress =
+                mozjpeg::Decompress::with_markers(mozjpeg::NO_MARKERS).from_reader(source)?;
+            match decompress.color_space() {
+                mozjpeg::ColorSpace::JCS_GRAYSCALE
+                | mozjpeg::ColorSpace::JCS_YCbCr
+                | mozjpeg::ColorSpace::JCS_RGB => Ok(Some(JpegRows {
+                    started: decompress.rgba()?,
+                })),
+                _ => Ok(None),
+            }
+        })
+    }
+}
+
+#[cfg(feature = "mozjpeg")]
+impl RowReader for JpegRows {
+    fn dimensions(&self) -> (u32, u32) {
+        (self.started.width() as u32, self.started.height() as u32)
+    }
+
+    fn read_row(&mut self, row: &mut [u8]) -> Result<(), Box<dyn Error>> {
+        let started = &mut self.started;
+        crate::encode::catch_mozjpeg(|| started.read_scanlines_into(row).map(|_| ()))
+    }
+}
+
+/// Returns whether a JPEG is sequential (baseline or extended), by reading its markers up
+/// to its frame header.
+///
+/// # Errors
+///
+/// This function will return an error if the JPEG cannot be read or has no frame header.
+#[cfg(feature = "mozjpeg")]
+fn is_sequential_jpeg(mut reader: impl std::io::Read) -> Result<bool, Box<dyn Error>> {
+    let mut marker = [0u8; 2];
+    reader.read_exact(&mut marker)?;
+    if marker != [0xFF, 0xD8] {
+        return Err("Not a JPEG file".into());
+    }
+    loop {
+        reader.read_exact(&mut marker)?;
+        if marker[0] != 0xFF {
+            return Err("Invalid JPEG marker".into());
+        }
+        // Fill bytes may precede a marker
+        while marker[1] == 0xFF {
+            reader.read_exact(&mut marker[1..])?;
+        }
+        match marker[1] {
+            // Huffman coded, baseline or extended sequential
+            0xC0 | 0xC1 => return Ok(true),
+            // Any other frame header: progressive, lossless or arithmetic coded
+            0xC2..=0xC3 | 0xC5..=0xC7 | 0xC9..=0xCB | 0xCD..=0xCF => return Ok(false),
+            _ => {
+                let mut length = [0u8; 2];
+                reader.read_exact(&mut length)?;
+                let length = u16::from_be_bytes(length).saturating_sub(2) as u64;
+                let mut segment = std::io::Read::take(reader.by_ref(), length);
+                std::io::copy(&mut segment, &mut std::io::sink())?;
+            }
+        }
+    }
+}
+
+/// An encoder writing the rows of an image as they are produced.
+enum RowWriter {
+    Png(PngRowWriter<Box<dyn StagedFile>>),
+    #[cfg(feature = "mozjpeg")]
+    Jpeg(Box<crate::encode::JpegRowWriter<Box<dyn StagedFile>>>),
+}
+
+impl RowWriter {
+    /// Starts writing a `width` x `height` image in `format` to `file`.
+    fn new(
+        file: Box<dyn StagedFile>,
+        format: ImageFormat,
+        (width, height): (u32, u32),
+        options: &JobOptions,
+    ) -> Result<Self, Box<dyn Error>> {
+        match format {
+            #[cfg(feature = "mozjpeg")]
+            ImageFormat::Jpeg => Ok(RowWriter::Jpeg(Box::new(
+                crate::encode::JpegRowWriter::new(file, width, height, &options.encode)?,
+            ))),
+            _ => Ok(RowWriter::Png(PngRowWriter::new(
+                file,
+                width,
+                height,
+                &options.encode,
+            )?)),
+        }
+    }
+
+    /// Writes the next rows of RGBA pixels.
+    fn write_rows(&mut self, rows: &[u8]) -> Result<(), Box<dyn Error>> {
+        match self {
+            RowWriter::Png(writer) => writer.write_rows(rows),
+            #[cfg(feature = "mozjpeg")]
+            RowWriter::Jpeg(writer) => writer.write_rows(rows),
+        }
+    }
+
+    /// Writes the end of the image and commits the file.
+    fn finish(self) -> Result<(), Box<dyn Error>> {
+        let file = match self {
+            RowWriter::Png(writer) => writer.finish()?,
+            #[cfg(feature = "mozjpeg")]
+            RowWriter::Jpeg(writer) => writer.finish()?,
+        };
+        Ok(file.commit()?)
+    }
+}
+
+/// Returns whether images can be written in `format` row by row with the job options.
+fn can_write(format: ImageFormat, options: &JobOptions) -> bool {
+    if !options.encoders.is_builtin(format) || options.encode.progressive {
+        return false;
+    }
+    match format {
+        ImageFormat::Png => options.encode.palette.is_none(),
+        ImageFormat::Jpeg => cfg!(feature = "mozjpeg"),
+        _ => false,
+    }
+}
+
+/// Opens a row decoder for an input, if its format can be read row by row.
+fn open_rows(
+    input: &Path,
+    format: ImageFormat,
+    options: &JobOptions,
+) -> Result<Option<Box<dyn RowReader>>, Box<dyn Error>> {
+    let storage = &*options.storage;
+    Ok(match format {
+        ImageFormat::Png => PngRows::open(storage.open_read(input)?)?
+            .map(|rows| Box::new(rows) as Box<dyn RowReader>),
+        #[cfg(feature = "mozjpeg")]
+        ImageFormat::Jpeg => {
+            JpegRows::open(storage, input)?.map(|rows| Box::new(rows) as Box<dyn RowReader>)
+        }
+        _ => None,
+    })
+}
+
+/// Resizes an image file by streaming its rows from the decoder through the resizer to the
+/// encoder, if the input, the output format and the job options allow it.
+///
+/// # Arguments
+///
+/// * `input` - The path of the input image file.
+/// * `input_format` - The format of the input, as read from its header.
+/// * `output_path` - The path of the output file.
+/// * `width` - An optional new width for the image.
+/// * `height` - An optional new height for the image.
+/// * `format` - The output format.
+/// * `options` - The job options. Their storage and atomic write setting are used to write
+///   the output, and the decode timeout bounds the whole resize.
+///
+/// # Returns
+///
+/// An `ImageInfo` describing the output, or `None` if the job cannot be streamed.
+///
+/// # Errors
+///
+/// This function will return an error if:
+/// - The input cannot be read or decoded.
+/// - The image exceeds the limits of the job options.
+/// - The resizing operation fails.
+/// - The output cannot be encoded or written. Atomic writes leave no partial output.
+/// - Decoding and resizing take longer than the decode timeout
+///   (`ResizeError::DecodeTimeout`), or the job is cancelled (`ResizeError::Cancelled`).
+pub(crate) fn resize_file(
+    input: &Path,
+    input_format: ImageFormat,
+    output_path: &Path,
+    width: Option<&u32>,
+    height: Option<&u32>,
+    format: ImageFormat,
+    options: &JobOptions,
+) -> Result<Option<ImageInfo>, Box<dyn Error>> {
+    if options.hash_names || options.cache.is_some() || !can_write(format, options) {
+        return Ok(None);
+    }
+    let Some(mut reader) = open_rows(input, input_format, options)? else {
+        return Ok(None);
+    };
+
+    let (src_width, src_height) = reader.dimensions();
+    let (new_width, new_height) = output_dimensions(
+        (src_width, src_height),
+        width.copied(),
+        height.copied(),
+        options,
+    )?;
+    options.safety.check_dimensions(src_width, src_height)?;
+    limits::check_streaming((src_width, src_height), (new_width, new_height), options)?;
+    crate::check_output_path(output_path, format)?;
+
+    let scale = src_height as f64 / new_height as f64;
+    let (_, rows_per_band) = streaming_band(scale);
+    let _span = info_span!(
+        "stream",
+        path = ?output_path,
+        width = new_width,
+        height = new_height
+    )
+    .entered();
+    debug!(
+        "Streaming {:?} ({}x{}) to {:?} ({}x{}) in bands of {} rows",
+        input, src_width, src_height, output_path, new_width, new_height, rows_per_band
+    );
+
+    let deadline = options
+        .safety
+        .decode_timeout
+        .map(|timeout| (timeout, Instant::now() + timeout));
+    let check_deadline = || match deadline {
+        Some((timeout, deadline)) if Instant::now() > deadline => {
+            Err(ResizeError::DecodeTimeout(timeout))
+        }
+        _ => Ok(()),
+    };
+
+    let file = options
+        .storage
+        .create_staged(output_path, !options.direct_write)?;
+    let mut writer = RowWriter::new(file, format, (new_width, new_height), options)?;
+    let mut resizer = options.resize.resizer()?;
+    let row_bytes = src_width as usize * 4;
+    let mut row = vec![0u8; row_bytes];
+    // The source rows `window_top..next_row`, converted to RGBA
+    let mut window = Vec::new();
+    let mut window_top = 0;
+    let mut next_row = 0;
+
+    for band in bands(src_height, new_height, rows_per_band, band_margin(scale)) {
+        options.cancel.check()?;
+        if band.read_top > window_top {
+            let dropped = (band.read_top - window_top) as usize * row_bytes;
+            window.drain(..dropped.min(window.len()));
+            window_top = band.read_top;
+        }
+        while next_row < band.read_bottom {
+            check_deadline()?;
+            reader.read_row(&mut row)?;
+            if next_row >= window_top {
+                window.extend_from_slice(&row);
+            }
+            next_row += 1;
+        }
+        writer.write_rows(&resize_band(
+            &mut resizer,
+            &window,
+            src_width,
+            new_width,
+            &band,
+        )?)?;
+    }
+    check_deadline()?;
+    options.cancel.check()?;
+    writer.finish()?;
+
+    Ok(Some(ImageInfo {
+        width: new_width,
+        height: new_height,
+        format,
+        path: output_path.to_path_buf(),
+        file_size: storage::file_size(&*options.storage, output_path)?,
+        source: None,
+        duration: None,
+        verification: None,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{metrics::psnr, storage::MemoryStorage, storage::SharedStorage};
+    use image::{DynamicImage, ImageBuffer, Luma, Rgb};
+    use std::{io::Cursor, time::Duration};
+
+    /// Stores `image` as `input.<format>` and returns job options reading from the storage.
+    fn setup(image: DynamicImage, format: ImageFormat) -> (MemoryStorage, JobOptions) {
+        let storage = MemoryStorage::new();
+        let mut data = Vec::new();
+        image.write_to(&mut Cursor::new(&mut data), format).unwrap();
+        let name = format!("input.{}", format.extensions_str()[0]);
+        storage.insert(name, data);
+        let options = JobOptions {
+            storage: SharedStorage::new(storage.clone()),
+            low_memory: true,
+            ..Default::default()
+        };
+        (storage, options)
+    }
+
+    /// A tall gradient spanning several bands.
+    fn tall_image() -> DynamicImage {
+        DynamicImage::ImageRgb8(ImageBuffer::from_fn(30, 1200, |x, y| {
+            Rgb([(x * 8) as u8, (y / 5) as u8, ((x * y) % 256) as u8])
+        }))
+    }
+
+    fn decode(storage: &MemoryStorage, path: &str) -> image::RgbaImage {
+        image::load_from_memory(&storage.get(Path::new(path)).unwrap())
+            .unwrap()
+            .to_rgba8()
+    }
+
+    #[test]
+    fn streams_pngs_like_the_regular_resize() {
+        let (storage, options) = setup(tall_image(), ImageFormat::Png);
+        let input = Path::new("input.png");
+        let info = resize_file(
+            input,
+            ImageFormat::Png,
+            Path::new("streamed.png"),
+            Some(&15),
+            None,
+            ImageFormat::Png,
+            &options,
+        )
+        .unwrap()
+        .expect("the PNG should be streamed");
+        assert_eq!((info.width, info.height), (15, 600));
+        assert_eq!(
+            info.file_size,
+            storage.get(Path::new("streamed.png")).unwrap().len() as u64
+        );
+
+        let regular = JobOptions {
+            low_memory: false,
+            ..options.clone()
+        };
+        crate::resize_file(
+            input,
+            Path::new("regular.png"),
+            Some(&15),
+            None,
+            None,
+            &regular,
+        )
+        .unwrap();
+        let streamed = decode(&storage, "streamed.png");
+        assert!(psnr(&decode(&storage, "regular.png"), &streamed).unwrap() > 50.0);
+    }
+
+    #[test]
+    fn expands_gray_and_16_bit_pngs() {
+        let gray = DynamicImage::ImageLuma16(ImageBuffer::from_fn(40, 300, |x, y| {
+            Luma([(x * 1500 + y * 100) as u16])
+        }));
+        let expected = crate::resize_image(gray.clone(), Some(&20), None).unwrap();
+        let (storage, options) = setup(gray, ImageFormat::Png);
+        resize_file(
+            Path::new("input.png"),
+            ImageFormat::Png,
+            Path::new("output.png"),
+            Some(&20),
+            None,
+            ImageFormat::Png,
+            &options,
+        )
+        .unwrap()
+        .expect("the PNG should be streamed");
+        assert!(psnr(&expected, &decode(&storage, "output.png")).unwrap() > 40.0);
+    }
+
+    #[test]
+    fn leaves_other_jobs_to_the_tiled_path() {
+        let (_, options) = setup(tall_image(), ImageFormat::Png);
+        let (_, bmp_options) = setup(tall_image(), ImageFormat::Bmp);
+        let run = |input: &str, format, options: &JobOptions| {
+            resize_file(
+                Path::new(input),
+                format,
+                Path::new("output.png"),
+                Some(&15),
+                None,
+                ImageFormat::Png,
+                options,
+            )
+            .unwrap()
+        };
+
+        assert!(run("input.bmp", ImageFormat::Bmp, &bmp_options).is_none());
+        let mut palette = options.clone();
+        palette.encode.palette = Some(16);
+        assert!(run("input.png", ImageFormat::Png, &palette).is_none());
+        let hashed = JobOptions {
+            hash_names: true,
+            ..options.clone()
+        };
+        assert!(run("input.png", ImageFormat::Png, &hashed).is_none());
+    }
+
+    #[test]
+    fn enforces_the_decode_timeout() {
+        let (_, mut options) = setup(tall_image(), ImageFormat::Png);
+        options.safety.decode_timeout = Some(Duration::from_nanos(1));
+        let error = resize_file(
+            Path::new("input.png"),
+            ImageFormat::Png,
+            Path::new("output.png"),
+            Some(&15),
+            None,
+            ImageFormat::Png,
+            &options,
+        )
+        .unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<ResizeError>(),
+            Some(ResizeError::DecodeTimeout(_))
+        ));
+    }
+
+    #[cfg(feature = "mozjpeg")]
+    #[test]
+    fn streams_jpegs_with_mozjpeg() {
+        let (storage, options) = setup(tall_image(), ImageFormat::Jpeg);
+        let info = resize_file(
+            Path::new("input.jpg"),
+            ImageFormat::Jpeg,
+            Path::new("output.jpg"),
+            Some(&15),
+            None,
+            ImageFormat::Jpeg,
+            &options,
+        )
+        .unwrap()
+        .expect("the JPEG should be streamed");
+        assert_eq!((info.width, info.height), (15, 600));
+        assert_eq!(decode(&storage, "output.jpg").dimensions(), (15, 600));
+    }
+}
@@ -0,0 +1,340 @@
+//! Helpers for regression tests of resize configurations.
+//!
+//! Applications embedding the resizer can check that their pipelines keep producing the
+//! same images across upgrades:
+//! - `run_deterministic` runs a pipeline in memory with `JobOptions::make_deterministic`,
+//!   so that its output only depends on the input and the settings
+//! - `Rng` draws seeded random sizes and images for property-based tests, so that a failing
+//!   case can be reproduced from its seed
+//! - `check_golden` compares an output with a reference image stored in the repository,
+//!   allowing a maximum difference per channel to absorb changes of rounding. Setting the
+//!   `IMAGE_RESIZER_UPDATE_GOLDEN` environment variable writes the references instead.
+//!
+//! ```
+//! use image_resizer_rust::{
+//!     options::JobOptions,
+//!     pipeline::Pipeline,
+//!     testing::{run_deterministic, Rng},
+//! };
+//!
+//! let mut rng = Rng::new(7);
+//! let (width, height) = (rng.range(16..=64), rng.range(1..=64));
+//! let input = rng.image(width, height);
+//! let output = run_deterministic(
+//!     &input.into(),
+//!     Some(16),
+//!     None,
+//!     image::ImageFormat::Png,
+//!     &Pipeline::standard(),
+//!     JobOptions::default(),
+//! )
+//! .unwrap();
+//! assert_eq!(output.image.width(), 16);
+//! ```
+
+use crate::{
+    generate::{generate, splitmix64, GenerateOptions, Pattern},
+    options::JobOptions,
+    pipeline::Pipeline,
+    resize_file_with,
+    storage::{MemoryStorage, SharedStorage},
+};
+use image::{DynamicImage, ImageFormat, Rgba, RgbaImage};
+use std::{
+    env,
+    error::Error,
+    fs,
+    io::{self, Cursor},
+    ops::RangeInclusive,
+    path::Path,
+};
+
+/// The environment variable that makes `check_golden` write the references instead of
+/// comparing with them.
+pub const UPDATE_GOLDEN_ENV: &str = "IMAGE_RESIZER_UPDATE_GOLDEN";
+
+/// A seeded random number generator, giving the same sequence on every platform.
+#[derive(Debug, Clone)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    /// Creates a generator from a seed.
+    pub fn new(seed: u64) -> Self {
+        Rng { state: seed }
+    }
+
+    /// Returns the next random number.
+    pub fn next_u64(&mut self) -> u64 {
+        splitmix64(&mut self.state)
+    }
+
+    /// Returns a random number within a range.
+    pub fn range(&mut self, range: RangeInclusive<u32>) -> u32 {
+        let span = (*range.end() as u64).saturating_sub(*range.start() as u64) + 1;
+        range.start() + (self.next_u64() % span) as u32
+    }
+
+    /// Returns a random test image: one of the patterns of `generate`, with random colors.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the width or height is zero.
+    pub fn image(&mut self, width: u32, height: u32) -> RgbaImage {
+        const PATTERNS: [Pattern; 5] = [
+            Pattern::Gradient,
+            Pattern::Checkerboard,
+            Pattern::Noise,
+            Pattern::Solid,
+            Pattern::Bars,
+        ];
+        let mut color = || {
+            let [r, g, b, a, ..] = self.next_u64().to_le_bytes();
+            Rgba([r, g, b, a])
+        };
+        let colors = [color(), color()];
+        let options = GenerateOptions {
+            pattern: PATTERNS[self.range(0..=PATTERNS.len() as u32 - 1) as usize],
+            width,
+            height,
+            colors,
+            cell_size: self.range(1..=16),
+            seed: self.next_u64(),
+        };
+        generate(&options).expect("the image should have a width and a height")
+    }
+}
+
+/// The output of `run_deterministic`.
+#[derive(Debug, Clone)]
+pub struct TestOutput {
+    /// The encoded output.
+    pub encoded: Vec<u8>,
+    /// The decoded output.
+    pub image: RgbaImage,
+}
+
+/// Runs a pipeline on an image in memory, with settings making the output reproducible.
+///
+/// The input is stored as a PNG file in a `MemoryStorage`, and resized with
+/// `resize_file_with` using `options` adjusted by `JobOptions::make_deterministic`. The
+/// image is always re-encoded.
+///
+/// # Arguments
+///
+/// * `input` - The input image.
+/// * `width` - An optional new width for the image.
+/// * `height` - An optional new height for the image.
+/// * `format` - The output format.
+/// * `pipeline` - The pipeline to run.
+/// * `options` - The job options. Their storage is replaced by the in-memory one.
+///
+/// # Errors
+///
+/// This function will return an error in the same cases as `resize_file_with`, or if the
+/// output cannot be decoded.
+pub fn run_deterministic(
+    input: &DynamicImage,
+    width: Option<u32>,
+    height: Option<u32>,
+    format: ImageFormat,
+    pipeline: &Pipeline,
+    options: JobOptions,
+) -> Result<TestOutput, Box<dyn Error>> {
+    let storage = MemoryStorage::new();
+    let mut png = Vec::new();
+    input.write_to(&mut Cursor::new(&mut png), ImageFormat::Png)?;
+    storage.insert("input.png", png);
+
+    let mut options = JobOptions {
+        storage: SharedStorage::new(storage.clone()),
+        ..options
+    };
+    options.make_deterministic();
+    let extension = format.extensions_str().first().ok_or("Unknown format")?;
+    let output = Path::new("output").with_extension(extension);
+    let info = resize_file_with(
+        Path::new("input.png"),
+        &output,
+        width.as_ref(),
+        height.as_ref(),
+        None,
+        &options,
+        pipeline,
+    )?;
+
+    let encoded = storage
+        .get(&info.path)
+        .ok_or("The pipeline did not write an output")?;
+    let image = image::load_from_memory(&encoded)?.into_rgba8();
+    Ok(TestOutput { encoded, image })
+}
+
+/// How much two images differ.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Difference {
+    /// The largest difference of a channel, alpha included.
+    pub max_delta: u8,
+    /// The number of pixels with at least one different channel.
+    pub differing_pixels: u64,
+    /// The coordinates of the first pixel with the largest difference, if any.
+    pub worst_pixel: Option<(u32, u32)>,
+}
+
+/// Compares two images pixel by pixel.
+///
+/// # Errors
+///
+/// This function will return an error if the images do not have the same dimensions.
+pub fn diff(actual: &RgbaImage, expected: &RgbaImage) -> Result<Difference, Box<dyn Error>> {
+    if actual.dimensions() != expected.dimensions() {
+        return Err(format!(
+            "The images have different dimensions: {:?} and {:?}",
+            actual.dimensions(),
+            expected.dimensions()
+        )
+        .into());
+    }
+    let mut difference = Difference::default();
+    for ((x, y, a), b) in actual.enumerate_pixels().zip(expected.pixels()) {
+        let delta =
+            a.0.iter()
+                .zip(b.0)
+                .map(|(&a, b)| a.abs_diff(b))
+                .max()
+                .unwrap_or(0);
+        if delta > 0 {
+            difference.differing_pixels += 1;
+        }
+        if delta > difference.max_delta {
+            difference.max_delta = delta;
+            difference.worst_pixel = Some((x, y));
+        }
+    }
+    Ok(difference)
+}
+
+/// Compares an image with the reference image stored at `golden`, a PNG file.
+///
+/// When the `IMAGE_RESIZER_UPDATE_GOLDEN` environment variable is set, the image is written
+/// to `golden` instead, creating its folder if needed, and the comparison always succeeds.
+///
+/// # Arguments
+///
+/// * `actual` - The image to check.
+/// * `golden` - The path of the reference image.
+/// * `max_delta` - The largest difference allowed for any channel of any pixel.
+///
+/// # Errors
+///
+/// This function will return an error if the reference cannot be read or written, if the
+/// dimensions differ, or if a channel differs by more than `max_delta`.
+pub fn check_golden(
+    actual: &RgbaImage,
+    golden: &Path,
+    max_delta: u8,
+) -> Result<(), Box<dyn Error>> {
+    check_golden_with(
+        actual,
+        golden,
+        max_delta,
+        env::var_os(UPDATE_GOLDEN_ENV).is_some(),
+    )
+}
+
+/// Compares an image with a reference like `check_golden`, writing the reference instead if
+/// `update` is set.
+fn check_golden_with(
+    actual: &RgbaImage,
+    golden: &Path,
+    max_delta: u8,
+    update: bool,
+) -> Result<(), Box<dyn Error>> {
+    if update {
+        if let Some(parent) = golden.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        actual.save_with_format(golden, ImageFormat::Png)?;
+        return Ok(());
+    }
+
+    let expected = match image::open(golden) {
+        Ok(expected) => expected.into_rgba8(),
+        Err(image::ImageError::IoError(e)) if e.kind() == io::ErrorKind::NotFound => {
+            return Err(format!(
+                "The reference {:?} does not exist; set {} to create it",
+                golden, UPDATE_GOLDEN_ENV
+            )
+            .into())
+        }
+        Err(e) => return Err(format!("Failed to read the reference {:?}: {}", golden, e).into()),
+    };
+    let difference = diff(actual, &expected)
+        .map_err(|e| format!("The image does not match {:?}: {}", golden, e))?;
+    if difference.max_delta > max_delta {
+        return Err(format!(
+            "The image does not match {:?}: {} pixels differ, by up to {} at {:?} (allowed: {})",
+            golden,
+            difference.differing_pixels,
+            difference.max_delta,
+            difference.worst_pixel.unwrap_or_default(),
+            max_delta
+        )
+        .into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn seeded_runs_are_reproducible() {
+        let run = |seed| {
+            let mut rng = Rng::new(seed);
+            let (width, height) = (rng.range(2..=40), rng.range(2..=40));
+            let input = DynamicImage::ImageRgba8(rng.image(width, height));
+            let target = rng.range(1..=width);
+            let output = run_deterministic(
+                &input,
+                Some(target),
+                None,
+                ImageFormat::Png,
+                &Pipeline::standard(),
+                JobOptions::default(),
+            )
+            .unwrap();
+            assert_eq!(output.image.width(), target);
+            output.encoded
+        };
+        for seed in 0..8 {
+            assert_eq!(run(seed), run(seed), "seed {}", seed);
+        }
+        assert_ne!(run(1), run(2));
+    }
+
+    #[test]
+    fn compares_with_golden_images() {
+        let dir = TempDir::new().unwrap();
+        let golden = dir.path().join("golden/gradient.png");
+        let mut image = Rng::new(3).image(8, 8);
+        assert!(check_golden_with(&image, &golden, 0, false)
+            .unwrap_err()
+            .to_string()
+            .contains(UPDATE_GOLDEN_ENV));
+        check_golden_with(&image, &golden, 0, true).unwrap();
+        check_golden_with(&image, &golden, 0, false).unwrap();
+
+        let pixel = image.get_pixel_mut(5, 6);
+        pixel[1] = pixel[1].wrapping_add(3);
+        check_golden_with(&image, &golden, 3, false).unwrap();
+        let error = check_golden_with(&image, &golden, 2, false).unwrap_err();
+        assert!(error.to_string().contains("(5, 6)"), "{}", error);
+
+        let smaller = RgbaImage::new(4, 8);
+        assert!(check_golden_with(&smaller, &golden, 255, false).is_err());
+    }
+}
@@ -0,0 +1,750 @@
+//! GPU textures with mipmaps.
+//!
+//! Game engines sample textures at many sizes, so they load them with a full mipmap chain:
+//! the image, then the image halved in size, and so on down to 1x1 pixel. Asset bakers
+//! store the chain in a texture container, which the GPU driver uploads as is:
+//! - DDS (DirectDraw Surface), read by DirectX tools and most engines. sRGB textures use
+//!   the DX10 extension of the header, since the legacy header cannot tell them apart.
+//! - KTX2 (Khronos Texture), the container of Vulkan and glTF, with a data format
+//!   descriptor. Levels are stored from the smallest to the largest, as the format requires.
+//!
+//! Levels are generated with a `ResizeContext`, each from the previous level. Pixels are
+//! 8-bit RGBA, or compressed to BC1 (opaque) or BC3 (with alpha) blocks with the `bc`
+//! feature. The block encoder fits every 4x4 block to its inset bounding box: it is fast
+//! and portable, but not as good as the offline encoders shipping with the GPU vendors'
+//! tools.
+
+use crate::{
+    context::ResizeContext,
+    options::ResizeOptions,
+    raw::{PixelLayout, RawImage},
+};
+use image::{DynamicImage, RgbaImage};
+use std::{error::Error, fmt, path::Path, str::FromStr};
+
+/// The first bytes of a KTX2 file.
+const KTX2_IDENTIFIER: [u8; 12] = [
+    0xAB, 0x4B, 0x54, 0x58, 0x20, 0x32, 0x30, 0xBB, 0x0D, 0x0A, 0x1A, 0x0A,
+];
+
+/// The containers a texture can be written to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Container {
+    /// DirectDraw Surface (`.dds`).
+    #[default]
+    Dds,
+    /// Khronos Texture 2 (`.ktx2`).
+    Ktx2,
+}
+
+impl Container {
+    /// Returns the container matching the extension of a path, if any.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        path.extension()?.to_str()?.parse().ok()
+    }
+}
+
+impl fmt::Display for Container {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Container::Dds => "dds",
+            Container::Ktx2 => "ktx2",
+        };
+        f.write_str(name)
+    }
+}
+
+impl FromStr for Container {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "dds" => Ok(Container::Dds),
+            "ktx2" => Ok(Container::Ktx2),
+            _ => Err(format!("Unknown texture container '{}'", s)),
+        }
+    }
+}
+
+/// The pixel formats of a texture.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Compression {
+    /// Uncompressed 8-bit RGBA.
+    #[default]
+    None,
+    /// BC1 (DXT1) blocks of 8 bytes per 4x4 pixels, without alpha.
+    Bc1,
+    /// BC3 (DXT5) blocks of 16 bytes per 4x4 pixels, with interpolated alpha.
+    Bc3,
+}
+
+impl Compression {
+    /// Returns the number of bytes of a level of the given size.
+    pub fn level_size(self, width: u32, height: u32) -> usize {
+        let blocks = width.div_ceil(4) as usize * height.div_ceil(4) as usize;
+        match self {
+            Compression::None => width as usize * height as usize * 4,
+            Compression::Bc1 => blocks * 8,
+            Compression::Bc3 => blocks * 16,
+        }
+    }
+
+    /// Returns the `DXGI_FORMAT` of the pixels, as written in a DX10 DDS header.
+    fn dxgi_format(self, srgb: bool) -> u32 {
+        match self {
+            Compression::None => 28 + srgb as u32,
+            Compression::Bc1 => 71 + srgb as u32,
+            Compression::Bc3 => 77 + srgb as u32,
+        }
+    }
+
+    /// Returns the `VkFormat` of the pixels, as written in a KTX2 header.
+    fn vk_format(self, srgb: bool) -> u32 {
+        match self {
+            Compression::None => 37 + 6 * srgb as u32,
+            Compression::Bc1 => 131 + srgb as u32,
+            Compression::Bc3 => 137 + srgb as u32,
+        }
+    }
+
+    /// Returns the number of bytes of a texel block, of 1 pixel or of 4x4 pixels.
+    fn block_size(self) -> u32 {
+        match self {
+            Compression::None => 4,
+            Compression::Bc1 => 8,
+            Compression::Bc3 => 16,
+        }
+    }
+}
+
+impl fmt::Display for Compression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Compression::None => "none",
+            Compression::Bc1 => "bc1",
+            Compression::Bc3 => "bc3",
+        };
+        f.write_str(name)
+    }
+}
+
+impl FromStr for Compression {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "none" => Ok(Compression::None),
+            "bc1" | "dxt1" => Ok(Compression::Bc1),
+            "bc3" | "dxt5" => Ok(Compression::Bc3),
+            _ => Err(format!("Unknown texture compression '{}'", s)),
+        }
+    }
+}
+
+/// The settings of a texture.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TextureOptions {
+    /// The file format.
+    pub container: Container,
+    /// The pixel format.
+    pub compression: Compression,
+    /// Generate the full mipmap chain, instead of the base level only.
+    pub mipmaps: bool,
+    /// Tag the color channels as sRGB, as for albedo or UI textures. Data textures such as
+    /// normal or roughness maps are linear.
+    pub srgb: bool,
+}
+
+impl Default for TextureOptions {
+    fn default() -> Self {
+        Self {
+            container: Container::default(),
+            compression: Compression::default(),
+            mipmaps: true,
+            srgb: true,
+        }
+    }
+}
+
+/// Returns the dimensions of the mipmap levels of an image, from the full size down to
+/// 1x1 pixel.
+///
+/// Each level is half the size of the previous one, rounded down but at least 1 pixel, as
+/// GPUs expect.
+pub fn mip_dimensions(width: u32, height: u32) -> Vec<(u32, u32)> {
+    let mut levels = vec![(width, height)];
+    let mut size = (width, height);
+    while size.0 > 1 || size.1 > 1 {
+        size = ((size.0 / 2).max(1), (size.1 / 2).max(1));
+        levels.push(size);
+    }
+    levels
+}
+
+/// Generates the mipmap chain of an image, each level resized from the previous one.
+///
+/// # Errors
+///
+/// This function will return an error if the image is empty or if a level cannot be
+/// resized.
+pub fn mip_chain(
+    base: RgbaImage,
+    resize: &ResizeOptions,
+) -> Result<Vec<RgbaImage>, Box<dyn Error>> {
+    let levels = mip_dimensions(base.width(), base.height());
+    let mut context = ResizeContext::new(*resize)?;
+    let mut chain = Vec::with_capacity(levels.len());
+    chain.push(base);
+    for &(width, height) in &levels[1..] {
+        let previous = &chain[chain.len() - 1];
+        let src = RawImage::new(
+            previous.as_raw(),
+            previous.width(),
+            previous.height(),
+            PixelLayout::Rgba,
+        )?;
+        let mut level = RgbaImage::new(width, height);
+        context.resize_into(&src, &mut level, width, height)?;
+        chain.push(level);
+    }
+    Ok(chain)
+}
+
+/// Encodes an image to a texture file, with its mipmaps if enabled.
+///
+/// # Arguments
+///
+/// * `image` - The base level of the texture, at its final size.
+/// * `options` - The container, pixel format and mipmap settings.
+/// * `resize` - The CPU settings of the resizer generating the mipmaps.
+///
+/// # Returns
+///
+/// A `Result` containing the bytes of the texture file.
+///
+/// # Errors
+///
+/// This function will return an error if:
+/// - The image is empty.
+/// - BC compression is requested without the `bc` feature.
+/// - A mipmap level cannot be resized.
+pub fn encode_texture(
+    image: &DynamicImage,
+    options: &TextureOptions,
+    resize: &ResizeOptions,
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    if image.width() == 0 || image.height() == 0 {
+        return Err("Cannot make a texture of an empty image".into());
+    }
+    if options.compression != Compression::None && !cfg!(feature = "bc") {
+        return Err(format!(
+            "{} compression needs a build with the 'bc' feature",
+            options.compression
+        )
+        .into());
+    }
+    let base = image.to_rgba8();
+    let chain = if options.mipmaps {
+        mip_chain(base, resize)?
+    } else {
+        vec![base]
+    };
+    let levels = chain
+        .iter()
+        .map(|level| level_data(level, options.compression))
+        .collect::<Vec<_>>();
+    let (width, height) = (image.width(), image.height());
+    Ok(match options.container {
+        Container::Dds => dds(width, height, &levels, options),
+        Container::Ktx2 => ktx2(width, height, &levels, options),
+    })
+}
+
+/// Returns the pixels of a level in the given format.
+fn level_data(level: &RgbaImage, compression: Compression) -> Vec<u8> {
+    match compression {
+        Compression::None => level.as_raw().clone(),
+        #[cfg(feature = "bc")]
+        Compression::Bc1 | Compression::Bc3 => bc::compress(level, compression == Compression::Bc3),
+        #[cfg(not(feature = "bc"))]
+        Compression::Bc1 | Compression::Bc3 => unreachable!("checked by encode_texture"),
+    }
+}
+
+/// Writes a DDS file holding the given levels, largest first.
+fn dds(width: u32, height: u32, levels: &[Vec<u8>], options: &TextureOptions) -> Vec<u8> {
+    const CAPS: u32 = 0x1;
+    const HEIGHT: u32 = 0x2;
+    const WIDTH: u32 = 0x4;
+    const PITCH: u32 = 0x8;
+    const PIXEL_FORMAT: u32 = 0x1000;
+    const MIPMAP_COUNT: u32 = 0x2_0000;
+    const LINEAR_SIZE: u32 = 0x8_0000;
+    const ALPHA_PIXELS: u32 = 0x1;
+    const FOURCC: u32 = 0x4;
+    const RGB: u32 = 0x40;
+    const CAPS_COMPLEX: u32 = 0x8;
+    const CAPS_TEXTURE: u32 = 0x1000;
+    const CAPS_MIPMAP: u32 = 0x40_0000;
+
+    let compression = options.compression;
+    let mipmaps = levels.len() > 1;
+    let mut flags = CAPS | HEIGHT | WIDTH | PIXEL_FORMAT;
+    flags |= if compression == Compression::None {
+        PITCH
+    } else {
+        LINEAR_SIZE
+    };
+    if mipmaps {
+        flags |= MIPMAP_COUNT;
+    }
+    let pitch_or_linear_size = match compression {
+        Compression::None => width * 4,
+        _ => levels[0].len() as u32,
+    };
+
+    let mut out = Vec::with_capacity(148 + levels.iter().map(Vec::len).sum::<usize>());
+    out.extend_from_slice(b"DDS ");
+    for value in [
+        124,
+        flags,
+        height,
+        width,
+        pitch_or_linear_size,
+        0,
+        levels.len() as u32,
+    ] {
+        out.extend_from_slice(&value.to_le_bytes());
+    }
+    out.extend_from_slice(&[0; 11 * 4]);
+
+    // The pixel format: masks for legacy RGBA, or a FourCC code
+    let (format_flags, fourcc, bits, masks): (u32, [u8; 4], u32, [u32; 4]) =
+        match (options.srgb, compression) {
+            (true, _) => (FOURCC, *b"DX10", 0, [0; 4]),
+            (false, Compression::None) => (
+                RGB | ALPHA_PIXELS,
+                [0; 4],
+                32,
+                [0xFF, 0xFF00, 0xFF_0000, 0xFF00_0000],
+            ),
+            (false, Compression::Bc1) => (FOURCC, *b"DXT1", 0, [0; 4]),
+            (false, Compression::Bc3) => (FOURCC, *b"DXT5", 0, [0; 4]),
+        };
+    out.extend_from_slice(&32u32.to_le_bytes());
+    out.extend_from_slice(&format_flags.to_le_bytes());
+    out.extend_from_slice(&fourcc);
+    for value in [bits, masks[0], masks[1], masks[2], masks[3]] {
+        out.extend_from_slice(&value.to_le_bytes());
+    }
+
+    let mut caps = CAPS_TEXTURE;
+    if mipmaps {
+        caps |= CAPS_COMPLEX | CAPS_MIPMAP;
+    }
+    for value in [caps, 0, 0, 0, 0] {
+        out.extend_from_slice(&value.to_le_bytes());
+    }
+    if options.srgb {
+        // A 2D texture, not an array, with straight alpha
+        for value in [compression.dxgi_format(true), 3, 0, 1, 1] {
+            out.extend_from_slice(&value.to_le_bytes());
+        }
+    }
+    for level in levels {
+        out.extend_from_slice(level);
+    }
+    out
+}
+
+/// Writes a KTX2 file holding the given levels, largest first.
+fn ktx2(width: u32, height: u32, levels: &[Vec<u8>], options: &TextureOptions) -> Vec<u8> {
+    let compression = options.compression;
+    let dfd = data_format_descriptor(compression, options.srgb);
+    let kvd = key_value(
+        b"KTXwriter",
+        concat!("image-resizer-rust ", env!("CARGO_PKG_VERSION")),
+    );
+
+    const HEADER_SIZE: usize = 80;
+    let dfd_offset = HEADER_SIZE + levels.len() * 24;
+    let kvd_offset = dfd_offset + dfd.len();
+    // Every level starts at a multiple of the texel block size and of 4 bytes
+    let alignment = compression.block_size().max(4) as usize;
+    let data_offset = (kvd_offset + kvd.len()).next_multiple_of(alignment);
+
+    // Levels are stored from the smallest to the largest
+    let mut offsets = vec![0; levels.len()];
+    let mut data = Vec::new();
+    for (index, level) in levels.iter().enumerate().rev() {
+        let start = (data_offset + data.len()).next_multiple_of(alignment) - data_offset;
+        data.resize(start, 0);
+        offsets[index] = data_offset + data.len();
+        data.extend_from_slice(level);
+    }
+
+    let mut out = Vec::with_capacity(data_offset + data.len());
+    out.extend_from_slice(&KTX2_IDENTIFIER);
+    let type_size = 1;
+    for value in [
+        compression.vk_format(options.srgb),
+        type_size,
+        width,
+        height,
+        0,
+        0,
+        1,
+        levels.len() as u32,
+        0,
+        dfd_offset as u32,
+        dfd.len() as u32,
+        kvd_offset as u32,
+        kvd.len() as u32,
+    ] {
+        out.extend_from_slice(&value.to_le_bytes());
+    }
+    // No supercompression global data
+    out.extend_from_slice(&[0; 16]);
+    for (offset, level) in offsets.iter().zip(levels) {
+        for value in [*offset, level.len(), level.len()] {
+            out.extend_from_slice(&(value as u64).to_le_bytes());
+        }
+    }
+    out.extend_from_slice(&dfd);
+    out.extend_from_slice(&kvd);
+    out.resize(data_offset, 0);
+    out.extend_from_slice(&data);
+    out
+}
+
+/// Returns the KTX2 data format descriptor of the pixels: a single basic descriptor block,
+/// with one sample per channel.
+fn data_format_descriptor(compression: Compression, srgb: bool) -> Vec<u8> {
+    const CHANNEL_ALPHA: u8 = 15;
+    const LINEAR: u8 = 0x10;
+    // Alpha is never sRGB-encoded
+    let alpha_qualifier = if srgb { LINEAR } else { 0 };
+
+    // As (color model, texel block dimensions, samples of (bit offset, bits, channel))
+    let (color_model, block, samples): (u8, [u8; 4], Vec<(u16, u8, u8)>) = match compression {
+        Compression::None => (
+            1,
+            [0; 4],
+            vec![
+                (0, 8, 0),
+                (8, 8, 1),
+                (16, 8, 2),
+                (24, 8, CHANNEL_ALPHA | alpha_qualifier),
+            ],
+        ),
+        Compression::Bc1 => (128, [3, 3, 0, 0], vec![(0, 64, 0)]),
+        Compression::Bc3 => (
+            130,
+            [3, 3, 0, 0],
+            vec![(0, 64, CHANNEL_ALPHA | alpha_qualifier), (64, 64, 0)],
+        ),
+    };
+    let transfer = if srgb { 2 } else { 1 };
+    let block_size = 24 + 16 * samples.len();
+
+    let mut dfd = Vec::with_capacity(4 + block_size);
+    dfd.extend_from_slice(&(4 + block_size as u32).to_le_bytes());
+    // Khronos vendor, basic descriptor type, version 1.3
+    dfd.extend_from_slice(&0u32.to_le_bytes());
+    dfd.extend_from_slice(&(2 | (block_size as u32) << 16).to_le_bytes());
+    // BT.709 primaries, straight alpha
+    dfd.extend_from_slice(&[color_model, 1, transfer, 0]);
+    dfd.extend_from_slice(&block);
+    dfd.extend_from_slice(&[compression.block_size() as u8, 0, 0, 0, 0, 0, 0, 0]);
+    for (offset, bits, channel) in samples {
+        let upper: u32 = if compression == Compression::None {
+            255
+        } else {
+            u32::MAX
+        };
+        dfd.extend_from_slice(&offset.to_le_bytes());
+        dfd.extend_from_slice(&[bits - 1, channel]);
+        dfd.extend_from_slice(&[0; 4]);
+        dfd.extend_from_slice(&0u32.to_le_bytes());
+        dfd.extend_from_slice(&upper.to_le_bytes());
+    }
+    dfd
+}
+
+/// Returns a KTX2 key/value entry, padded to 4 bytes.
+fn key_value(key: &[u8], value: &str) -> Vec<u8> {
+    let length = key.len() + value.len() + 2;
+    let mut entry = Vec::with_capacity(4 + length.next_multiple_of(4));
+    entry.extend_from_slice(&(length as u32).to_le_bytes());
+    entry.extend_from_slice(key);
+    entry.push(0);
+    entry.extend_from_slice(value.as_bytes());
+    entry.push(0);
+    entry.resize(4 + length.next_multiple_of(4), 0);
+    entry
+}
+
+/// A BC1 and BC3 block encoder.
+#[cfg(feature = "bc")]
+mod bc {
+    use image::RgbaImage;
+
+    /// Compresses an image to BC1 blocks, or to BC3 blocks if `alpha` is set. Blocks
+    /// crossing the right or bottom edge repeat the last column or row.
+    pub fn compress(image: &RgbaImage, alpha: bool) -> Vec<u8> {
+        let (width, height) = image.dimensions();
+        let mut out = Vec::new();
+        for block_y in (0..height).step_by(4) {
+            for block_x in (0..width).step_by(4) {
+                let mut block = [[0u8; 4]; 16];
+                for (index, pixel) in block.iter_mut().enumerate() {
+                    let x = (block_x + index as u32 % 4).min(width - 1);
+                    let y = (block_y + index as u32 / 4).min(height - 1);
+                    *pixel = image.get_pixel(x, y).0;
+                }
+                if alpha {
+                    out.extend_from_slice(&alpha_block(&block));
+                }
+                out.extend_from_slice(&color_block(&block));
+            }
+        }
+        out
+    }
+
+    /// Encodes the colors of a block between two endpoints, inset from its bounding box.
+    fn color_block(block: &[[u8; 4]; 16]) -> [u8; 8] {
+        let mut min = [255u8; 3];
+        let mut max = [0u8; 3];
+        for pixel in block {
+            for channel in 0..3 {
+                min[channel] = min[channel].min(pixel[channel]);
+                max[channel] = max[channel].max(pixel[channel]);
+            }
+        }
+        // Inset the box by 1/16th, since its corners are rarely reached once quantized
+        for channel in 0..3 {
+            let inset = (max[channel] - min[channel]) / 16;
+            min[channel] += inset;
+            max[channel] -= inset;
+        }
+        // The box diagonal follows the correlation of the channels with the widest one
+        let widest = (0..3).max_by_key(|&c| max[c] - min[c]).unwrap_or(1);
+        let center = [0, 1, 2].map(|c| (min[c] as i32 + max[c] as i32) / 2);
+        for channel in (0..3).filter(|&c| c != widest) {
+            let covariance: i32 = block
+                .iter()
+                .map(|pixel| {
+                    (pixel[channel] as i32 - center[channel])
+                        * (pixel[widest] as i32 - center[widest])
+                })
+                .sum();
+            if covariance < 0 {
+                (min[channel], max[channel]) = (max[channel], min[channel]);
+            }
+        }
+
+        let (mut color0, mut color1) = (to_565(max), to_565(min));
+        if color0 < color1 {
+            (color0, color1) = (color1, color0);
+        }
+        let mut out = [0; 8];
+        out[..2].copy_from_slice(&color0.to_le_bytes());
+        out[2..4].copy_from_slice(&color1.to_le_bytes());
+        if color0 == color1 {
+            return out;
+        }
+
+        // Four-color mode: the endpoints and two colors in between
+        let (end0, end1) = (from_565(color0), from_565(color1));
+        let palette = [
+            end0,
+            end1,
+            [0, 1, 2].map(|c| ((2 * end0[c] as u32 + end1[c] as u32) / 3) as u8),
+            [0, 1, 2].map(|c| ((end0[c] as u32 + 2 * end1[c] as u32) / 3) as u8),
+        ];
+        let mut indices = 0u32;
+        for (index, pixel) in block.iter().enumerate() {
+            let nearest = nearest(&palette, |color| {
+                (0..3)
+                    .map(|c| (color[c] as i32 - pixel[c] as i32).pow(2))
+                    .sum()
+            });
+            indices |= (nearest as u32) << (2 * index);
+        }
+        out[4..].copy_from_slice(&indices.to_le_bytes());
+        out
+    }
+
+    /// Encodes the alpha of a block between its minimum and maximum, with 8 levels.
+    fn alpha_block(block: &[[u8; 4]; 16]) -> [u8; 8] {
+        let max = block.iter().map(|pixel| pixel[3]).max().unwrap_or(255);
+        let min = block.iter().map(|pixel| pixel[3]).min().unwrap_or(255);
+        let mut out = [max, min, 0, 0, 0, 0, 0, 0];
+        if max == min {
+            return out;
+        }
+        let palette: [u8; 8] = std::array::from_fn(|index| match index {
+            0 => max,
+            1 => min,
+            _ => (((8 - index) as u32 * max as u32 + (index - 1) as u32 * min as u32) / 7) as u8,
+        });
+        let mut indices = 0u64;
+        for (index, pixel) in block.iter().enumerate() {
+            let nearest = nearest(&palette, |alpha| (*alpha as i32 - pixel[3] as i32).abs());
+            indices |= (nearest as u64) << (3 * index);
+        }
+        out[2..].copy_from_slice(&indices.to_le_bytes()[..6]);
+        out
+    }
+
+    /// Returns the index of the palette entry with the smallest error.
+    fn nearest<T>(palette: &[T], error: impl Fn(&T) -> i32) -> usize {
+        (0..palette.len())
+            .min_by_key(|&index| error(&palette[index]))
+            .unwrap_or(0)
+    }
+
+    /// Quantizes a color to 5 bits of red, 6 of green and 5 of blue.
+    fn to_565([red, green, blue]: [u8; 3]) -> u16 {
+        let quantize =
+            |value: u8, bits: u32| ((value as u32 * ((1 << bits) - 1) + 127) / 255) as u16;
+        quantize(red, 5) << 11 | quantize(green, 6) << 5 | quantize(blue, 5)
+    }
+
+    /// Expands a 5:6:5 color to 8 bits per channel.
+    fn from_565(color: u16) -> [u8; 3] {
+        let expand = |value: u16, bits: u32| {
+            ((value as u32 * 255 + ((1 << bits) - 1) / 2) / ((1 << bits) - 1)) as u8
+        };
+        [
+            expand(color >> 11, 5),
+            expand((color >> 5) & 0x3F, 6),
+            expand(color & 0x1F, 5),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+    }
+
+    fn read_u64(bytes: &[u8], offset: usize) -> usize {
+        u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap()) as usize
+    }
+
+    #[test]
+    fn writes_full_mip_chains() {
+        assert_eq!(
+            mip_dimensions(64, 48),
+            [(64, 48), (32, 24), (16, 12), (8, 6), (4, 3), (2, 1), (1, 1)]
+        );
+        assert_eq!(mip_dimensions(1, 5), [(1, 5), (1, 2), (1, 1)]);
+        let source = RgbaImage::from_fn(64, 48, |x, y| {
+            image::Rgba([(x * 4) as u8, (y * 5) as u8, 90, 255])
+        });
+        let image = DynamicImage::ImageRgba8(source.clone());
+        let pixels: usize = mip_dimensions(64, 48)
+            .iter()
+            .map(|(w, h)| (w * h) as usize)
+            .sum();
+
+        let options = TextureOptions {
+            srgb: false,
+            ..Default::default()
+        };
+        let dds = encode_texture(&image, &options, &ResizeOptions::default()).unwrap();
+        assert_eq!(&dds[..4], b"DDS ");
+        assert_eq!((read_u32(&dds, 12), read_u32(&dds, 16)), (48, 64));
+        assert_eq!(read_u32(&dds, 28), 7);
+        assert_eq!(dds.len(), 128 + pixels * 4);
+        assert_eq!(&dds[128..128 + 64 * 48 * 4], source.as_raw().as_slice());
+        let srgb = encode_texture(
+            &image,
+            &TextureOptions::default(),
+            &ResizeOptions::default(),
+        );
+        let srgb = srgb.unwrap();
+        assert_eq!((&srgb[84..88], read_u32(&srgb, 128)), (&b"DX10"[..], 29));
+
+        let options = TextureOptions {
+            container: "KTX2".parse().unwrap(),
+            ..Default::default()
+        };
+        let ktx2 = encode_texture(&image, &options, &ResizeOptions::default()).unwrap();
+        assert_eq!(ktx2[..12], KTX2_IDENTIFIER);
+        assert_eq!(read_u32(&ktx2, 12), 43);
+        assert_eq!(read_u32(&ktx2, 40), 7);
+        // The base level is stored last, at the end of the file
+        let (offset, length) = (read_u64(&ktx2, 80), read_u64(&ktx2, 88));
+        assert_eq!((length, offset + length), (64 * 48 * 4, ktx2.len()));
+        assert_eq!(&ktx2[offset..], source.as_raw().as_slice());
+        let smallest = read_u64(&ktx2, 80 + 6 * 24);
+        assert_eq!(smallest % 4, 0);
+        assert!(smallest < offset);
+
+        let options = TextureOptions {
+            mipmaps: false,
+            ..options
+        };
+        let single = encode_texture(&image, &options, &ResizeOptions::default()).unwrap();
+        assert_eq!(read_u32(&single, 40), 1);
+        assert_eq!(
+            Container::from_path(Path::new("albedo.ktx2")),
+            Some(Container::Ktx2)
+        );
+        assert_eq!(Container::from_path(Path::new("albedo.png")), None);
+    }
+
+    #[test]
+    fn compresses_blocks_with_the_bc_feature() {
+        // The image decoder only reads whole blocks, so only the mipmaps have partial ones
+        let image = DynamicImage::ImageRgba8(RgbaImage::from_fn(12, 8, |x, _| {
+            if x < 6 {
+                image::Rgba([255, 0, 0, 255])
+            } else {
+                image::Rgba([0, 0, 255, 0])
+            }
+        }));
+        let options = TextureOptions {
+            compression: Compression::Bc3,
+            srgb: false,
+            ..Default::default()
+        };
+        let result = encode_texture(&image, &options, &ResizeOptions::default());
+        if !cfg!(feature = "bc") {
+            assert!(result.unwrap_err().to_string().contains("'bc' feature"));
+            return;
+        }
+        let dds = result.unwrap();
+        let blocks: usize = mip_dimensions(12, 8)
+            .iter()
+            .map(|&(w, h)| Compression::Bc3.level_size(w, h))
+            .sum();
+        assert_eq!(dds.len(), 128 + blocks);
+        // Uniform blocks decode back exactly, and the block mixing both colors closely
+        let decoded = image::load_from_memory_with_format(&dds, image::ImageFormat::Dds)
+            .unwrap()
+            .into_rgba8();
+        assert_eq!(decoded.get_pixel(0, 0).0, [255, 0, 0, 255]);
+        assert_eq!(decoded.get_pixel(11, 7).0, [0, 0, 255, 0]);
+        let [red, green, blue, alpha] = decoded.get_pixel(5, 3).0;
+        assert!(red > 200 && green == 0 && blue < 50 && alpha == 255);
+        let [red, _, blue, alpha] = decoded.get_pixel(6, 3).0;
+        assert!(red < 50 && blue > 200 && alpha == 0);
+
+        let options = TextureOptions {
+            compression: Compression::Bc1,
+            ..options
+        };
+        let dds = encode_texture(&image, &options, &ResizeOptions::default()).unwrap();
+        assert_eq!(&dds[84..88], b"DXT1");
+        assert_eq!(
+            read_u32(&dds, 20),
+            Compression::Bc1.level_size(12, 8) as u32
+        );
+    }
+}
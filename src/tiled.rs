@@ -0,0 +1,232 @@
+//! Low-memory, band-based resizing.
+//!
+//! The regular `resize_image` path converts the whole decoded image to RGBA before resizing,
+//! so a huge source needs its decoded buffer, a full-size RGBA copy and the destination in
+//! memory at the same time. The tiled path keeps the decoded image in its native pixel
+//! format and produces the destination in horizontal bands: for every band, only the source
+//! rows it depends on are converted to RGBA and resized. Peak memory is therefore the
+//! decoded source plus the destination plus one band of scratch buffers.
+//!
+//! The output is the same as the regular path, because every band is resized with the
+//! same scale and a margin of source rows large enough for the resampling filter.
+//!
+//! The `streaming` module runs the same bands without keeping either image: it resizes
+//! PNG and JPEG rows as they are decoded and encodes every band right away.
+
+use crate::{determine_new_dimensions, options::ResizeOptions};
+use fast_image_resize as fr;
+use image::{DynamicImage, GenericImageView, ImageBuffer, Rgba};
+use tracing::trace;
+
+/// The approximate number of source rows converted to RGBA per band.
+pub const DEFAULT_BAND_HEIGHT: u32 = 256;
+
+/// Source images with more pixels than this are resized with the tiled path automatically
+/// (see `limits::plan_resize`).
+pub const LOW_MEMORY_PIXEL_THRESHOLD: u64 = 100_000_000;
+
+/// The support radius of the default resampling filter (Lanczos3).
+const FILTER_SUPPORT: f64 = 3.0;
+
+/// Returns the number of extra source rows read above and below each band, so that the
+/// resampling filter sees the same pixels as in a single-pass resize.
+pub(crate) fn band_margin(scale: f64) -> u32 {
+    (FILTER_SUPPORT * scale.max(1.0)).ceil() as u32 + 1
+}
+
+/// Returns the number of destination rows produced per band.
+pub(crate) fn band_rows(band_height: u32, scale: f64) -> u32 {
+    ((band_height as f64 / scale).floor() as u32).max(1)
+}
+
+/// A band of destination rows, and the source rows it is resized from.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Band {
+    /// The first destination row of the band.
+    pub top: u32,
+    /// The number of destination rows of the band.
+    pub rows: u32,
+    /// The top of the source area covered by the band, which may fall within a row.
+    pub src_top: f64,
+    /// The bottom of the source area covered by the band, which may fall within a row.
+    pub src_bottom: f64,
+    /// The first source row to read, including a margin for the filter kernel.
+    pub read_top: u32,
+    /// The source row after the last one to read, including a margin for the filter kernel.
+    pub read_bottom: u32,
+}
+
+/// Splits the destination rows into bands of `rows_per_band` rows, from top to bottom.
+///
+/// The source rows to read never move up from one band to the next, so a caller reading
+/// the source sequentially only needs to keep the rows of the current band.
+pub(crate) fn bands(
+    src_height: u32,
+    new_height: u32,
+    rows_per_band: u32,
+    margin: u32,
+) -> impl Iterator<Item = Band> {
+    let scale = src_height as f64 / new_height as f64;
+    (0..new_height)
+        .step_by(rows_per_band as usize)
+        .map(move |top| {
+            let rows = rows_per_band.min(new_height - top);
+            let src_top = top as f64 * scale;
+            let src_bottom = ((top + rows) as f64 * scale).min(src_height as f64);
+            Band {
+                top,
+                rows,
+                src_top,
+                src_bottom,
+                read_top: (src_top.floor() as u32).saturating_sub(margin),
+                read_bottom: (src_bottom.ceil() as u32 + margin).min(src_height),
+            }
+        })
+}
+
+/// Resizes the RGBA source rows `band.read_top..band.read_bottom` to the rows of a band.
+///
+/// # Returns
+///
+/// The RGBA pixels of the destination rows of the band.
+///
+/// # Errors
+///
+/// This function will return an error if `strip` does not hold the rows of the band, or if
+/// the resizing operation fails.
+pub(crate) fn resize_band(
+    resizer: &mut fr::Resizer,
+    strip: &[u8],
+    src_width: u32,
+    new_width: u32,
+    band: &Band,
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let src_image = fr::images::ImageRef::new(
+        src_width,
+        band.read_bottom - band.read_top,
+        strip,
+        fr::PixelType::U8x4,
+    )?;
+    let mut dst_image = fr::images::Image::new(new_width, band.rows, fr::PixelType::U8x4);
+
+    let options = fr::ResizeOptions::new().crop(
+        0.0,
+        band.src_top - band.read_top as f64,
+        src_width as f64,
+        band.src_bottom - band.src_top,
+    );
+    resizer.resize(&src_image, &mut dst_image, &options)?;
+    Ok(dst_image.into_vec())
+}
+
+/// Resizes an image band by band to cap peak memory usage.
+///
+/// # Arguments
+///
+/// * `input` - The decoded source image, in any pixel format.
+/// * `width` - An optional new width for the image. If None, it will be calculated based on the height.
+/// * `height` - An optional new height for the image. If None, it will be calculated based on the width.
+/// * `band_height` - The approximate number of source rows to convert per band.
+/// * `options` - The CPU settings for the resizer and the rounding of the computed side.
+///   Bands are always processed sequentially to bound memory usage, so the number of
+///   threads is ignored.
+///
+/// # Returns
+///
+/// A `Result` containing the resized image as an `ImageBuffer<Rgba<u8>, Vec<u8>>`, or an error if the operation fails.
+///
+/// # Errors
+///
+/// This function will return an error if:
+/// - Neither width nor height is specified.
+/// - `band_height` is zero.
+/// - The selected CPU extension is not available.
+/// - The resizing operation fails.
+pub fn resize_image_tiled(
+    input: &DynamicImage,
+    width: Option<&u32>,
+    height: Option<&u32>,
+    band_height: u32,
+    options: &ResizeOptions,
+) -> Result<ImageBuffer<Rgba<u8>, Vec<u8>>, Box<dyn std::error::Error>> {
+    if band_height == 0 {
+        return Err("Band height must be greater than zero".into());
+    }
+
+    let (src_width, src_height) = input.dimensions();
+    let (new_width, new_height) =
+        determine_new_dimensions(src_width, src_height, width, height, options)?;
+
+    let scale = src_height as f64 / new_height as f64;
+    let margin = band_margin(scale);
+    let rows_per_band = band_rows(band_height, scale);
+
+    let mut resizer = options.resizer()?;
+    let mut buffer = Vec::with_capacity(new_width as usize * new_height as usize * 4);
+
+    for band in bands(src_height, new_height, rows_per_band, margin) {
+        trace!(
+            "Band of rows {}..{} from source rows {}..{}",
+            band.top,
+            band.top + band.rows,
+            band.read_top,
+            band.read_bottom
+        );
+        let strip = input
+            .crop_imm(
+                0,
+                band.read_top,
+                src_width,
+                band.read_bottom - band.read_top,
+            )
+            .into_rgba8();
+        buffer.extend_from_slice(&resize_band(
+            &mut resizer,
+            &strip,
+            src_width,
+            new_width,
+            &band,
+        )?);
+    }
+
+    ImageBuffer::from_raw(new_width, new_height, buffer)
+        .ok_or_else(|| "Failed to assemble the resized bands".into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{metrics::psnr, resize_image};
+    use image::RgbImage;
+
+    fn source() -> DynamicImage {
+        DynamicImage::ImageRgb8(RgbImage::from_fn(120, 90, |x, y| {
+            image::Rgb([(x * 2) as u8, (y * 2) as u8, ((x * y) % 256) as u8])
+        }))
+    }
+
+    #[test]
+    fn matches_regular_resize() {
+        let img = source();
+        let regular = resize_image(img.clone(), Some(&50), None).unwrap();
+        let tiled =
+            resize_image_tiled(&img, Some(&50), None, 10, &ResizeOptions::default()).unwrap();
+
+        assert_eq!(tiled.dimensions(), regular.dimensions());
+        assert!(psnr(&regular, &tiled).unwrap() > 50.0);
+    }
+
+    #[test]
+    fn upscales_in_bands() {
+        let tiled =
+            resize_image_tiled(&source(), None, Some(&200), 16, &ResizeOptions::default()).unwrap();
+        assert_eq!(tiled.dimensions(), (267, 200));
+    }
+
+    #[test]
+    fn zero_band_height_is_rejected() {
+        assert!(
+            resize_image_tiled(&source(), Some(&50), None, 0, &ResizeOptions::default()).is_err()
+        );
+    }
+}
@@ -0,0 +1,189 @@
+//! Tone mapping of high dynamic range images.
+//!
+//! OpenEXR and Radiance HDR images are decoded to linear floating-point pixels, whose
+//! values go well above 1.0 in highlights. Converting them straight to 8 bits clips every
+//! value above 1.0 to white, and leaves the midtones too dark since the values are linear.
+//! A tone mapping operator compresses the whole range of the image into 0 to 1 instead,
+//! before the values are encoded with the sRGB transfer curve.
+//!
+//! `ToneMapStage` does this right after the decode stage, so that the stages after it,
+//! including every resize mode, get 8-bit sRGB pixels as for any other input. Images that
+//! are not floating-point are left untouched.
+
+use crate::{
+    options::JobOptions,
+    pipeline::{PipelineState, Stage},
+};
+use image::{DynamicImage, Rgba32FImage, RgbaImage};
+use std::{error::Error, fmt, str::FromStr};
+use tracing::debug;
+
+/// The name of the tone mapping stage.
+pub const TONEMAP: &str = "tonemap";
+
+/// The tone mapping operators.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ToneMap {
+    /// Reinhard's global operator, applied to the luminance so that hues are kept. Soft,
+    /// with highlights approaching white without ever clipping.
+    #[default]
+    Reinhard,
+    /// An approximation of the ACES filmic curve, with more contrast and saturated
+    /// midtones, as in games and film.
+    Aces,
+}
+
+impl ToneMap {
+    /// Maps a linear RGB color of any brightness to a linear color within 0 to 1.
+    pub fn map(self, [red, green, blue]: [f32; 3]) -> [f32; 3] {
+        let color = [red, green, blue].map(|value| value.max(0.0));
+        match self {
+            ToneMap::Reinhard => {
+                let luminance = 0.2126 * color[0] + 0.7152 * color[1] + 0.0722 * color[2];
+                if luminance <= 0.0 {
+                    return [0.0; 3];
+                }
+                let scale = 1.0 / (1.0 + luminance);
+                color.map(|value| (value * scale).min(1.0))
+            }
+            ToneMap::Aces => color.map(|x| {
+                ((x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14)).clamp(0.0, 1.0)
+            }),
+        }
+    }
+}
+
+impl fmt::Display for ToneMap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ToneMap::Reinhard => "reinhard",
+            ToneMap::Aces => "aces",
+        };
+        f.write_str(name)
+    }
+}
+
+impl FromStr for ToneMap {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "reinhard" => Ok(ToneMap::Reinhard),
+            "aces" => Ok(ToneMap::Aces),
+            _ => Err(format!("Unknown tone mapping operator '{}'", s)),
+        }
+    }
+}
+
+/// Returns whether an image has floating-point pixels, as decoded from OpenEXR and
+/// Radiance HDR files.
+pub fn is_hdr(image: &DynamicImage) -> bool {
+    matches!(
+        image,
+        DynamicImage::ImageRgb32F(_) | DynamicImage::ImageRgba32F(_)
+    )
+}
+
+/// Tone maps a linear floating-point image to an 8-bit sRGB image.
+///
+/// Alpha is not tone mapped, only clamped to 0 to 1.
+pub fn tone_map(image: &Rgba32FImage, operator: ToneMap) -> RgbaImage {
+    let mut output = RgbaImage::new(image.width(), image.height());
+    for (pixel, source) in output.pixels_mut().zip(image.pixels()) {
+        let [red, green, blue, alpha] = source.0;
+        let [red, green, blue] = operator.map([red, green, blue]).map(encode_srgb);
+        pixel.0 = [red, green, blue, to_u8(alpha)];
+    }
+    output
+}
+
+/// Encodes a linear value within 0 to 1 with the sRGB transfer curve.
+fn encode_srgb(linear: f32) -> u8 {
+    let encoded = if linear <= 0.003_130_8 {
+        12.92 * linear
+    } else {
+        1.055 * linear.powf(1.0 / 2.4) - 0.055
+    };
+    to_u8(encoded)
+}
+
+/// Rounds a value within 0 to 1 to the nearest byte, clamping it to the valid range.
+fn to_u8(value: f32) -> u8 {
+    (value.clamp(0.0, 1.0) * 255.0).round() as u8
+}
+
+/// A stage tone mapping floating-point images to 8-bit sRGB, meant to run right after the
+/// `DECODE` stage.
+pub struct ToneMapStage {
+    operator: ToneMap,
+}
+
+impl ToneMapStage {
+    /// Creates a stage tone mapping with the given operator.
+    pub fn new(operator: ToneMap) -> Self {
+        ToneMapStage { operator }
+    }
+}
+
+impl Stage for ToneMapStage {
+    fn name(&self) -> &str {
+        TONEMAP
+    }
+
+    fn run(&self, state: &mut PipelineState, _options: &JobOptions) -> Result<(), Box<dyn Error>> {
+        let image = state.take_image()?;
+        if !is_hdr(&image) {
+            state.image = Some(image);
+            return Ok(());
+        }
+        debug!("Tone mapping {:?} with {}", state.input, self.operator);
+        let mapped = tone_map(&image.into_rgba32f(), self.operator);
+        state.image = Some(DynamicImage::ImageRgba8(mapped));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgb, Rgb32FImage};
+
+    #[test]
+    fn compresses_highlights_without_clipping() {
+        for operator in [ToneMap::Reinhard, ToneMap::Aces] {
+            assert_eq!(operator.map([0.0; 3]), [0.0; 3]);
+            let bright = operator.map([4.0, 2.0, 1.0]);
+            let brighter = operator.map([16.0, 8.0, 4.0]);
+            assert!(bright.iter().all(|value| (0.0..=1.0).contains(value)));
+            // Brighter sources stay brighter, and keep the order of their channels
+            assert!(brighter[1] > bright[1], "{}", operator);
+            assert!(
+                bright[0] > bright[1] && bright[1] > bright[2],
+                "{}",
+                operator
+            );
+        }
+        assert_eq!(ToneMap::Reinhard.map([1.0; 3]), [0.5; 3]);
+        assert_eq!("ACES".parse(), Ok(ToneMap::Aces));
+        assert!("filmic".parse::<ToneMap>().is_err());
+    }
+
+    #[test]
+    fn maps_only_floating_point_images() {
+        let stage = ToneMapStage::new(ToneMap::Reinhard);
+        let hdr = Rgb32FImage::from_fn(4, 1, |x, _| Rgb([x as f32 * 10.0, 0.5, 0.0]));
+        let mut state = PipelineState::new("scene.hdr", None, None);
+        state.image = Some(DynamicImage::ImageRgb32F(hdr));
+        stage.run(&mut state, &JobOptions::default()).unwrap();
+        let mapped = state.image.unwrap().into_rgba8();
+        // Clipping would make the two brightest pixels identical
+        assert_ne!(mapped.get_pixel(2, 0), mapped.get_pixel(3, 0));
+        assert!(mapped.pixels().all(|pixel| pixel[3] == 255));
+
+        let rgba = RgbaImage::from_pixel(2, 2, image::Rgba([10, 20, 30, 40]));
+        let mut state = PipelineState::new("photo.png", None, None);
+        state.image = Some(DynamicImage::ImageRgba8(rgba.clone()));
+        stage.run(&mut state, &JobOptions::default()).unwrap();
+        assert_eq!(state.image, Some(DynamicImage::ImageRgba8(rgba)));
+    }
+}
@@ -0,0 +1,134 @@
+//! Trimming uniform borders.
+//!
+//! Product photos on a white background, scans with a margin and video frames with
+//! letterbox bars all have borders of a single color around their content. This module
+//! finds the bounding box of the content by scanning inwards from each edge, comparing the
+//! pixels with the color of the top left corner, and crops the image to it.
+//!
+//! The fuzz is a color tolerance in percent: a pixel belongs to the border if none of its
+//! channels differs from the border color by more than `fuzz` percent of 255. A small fuzz
+//! absorbs JPEG artifacts and sensor noise in otherwise uniform borders.
+
+use image::{DynamicImage, GenericImageView, Rgba};
+
+/// The bounding box of the content of an image, in pixels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Bounds {
+    /// The left edge of the content.
+    pub x: u32,
+    /// The top edge of the content.
+    pub y: u32,
+    /// The width of the content.
+    pub width: u32,
+    /// The height of the content.
+    pub height: u32,
+}
+
+/// Finds the content of an image inside its uniform borders.
+///
+/// # Arguments
+///
+/// * `image` - The image to scan.
+/// * `fuzz` - The color tolerance, from 0.0 (exact color) to 100.0 (any color).
+///
+/// # Returns
+///
+/// The bounding box of the content, or None if the whole image is the border color.
+pub fn content_bounds(
+    image: &impl GenericImageView<Pixel = Rgba<u8>>,
+    fuzz: f32,
+) -> Option<Bounds> {
+    let (width, height) = image.dimensions();
+    if width == 0 || height == 0 {
+        return None;
+    }
+    let border = image.get_pixel(0, 0);
+    let tolerance = (fuzz.clamp(0.0, 100.0) / 100.0 * 255.0).round() as i32;
+    let is_border = |x: u32, y: u32| {
+        let pixel = image.get_pixel(x, y);
+        pixel
+            .0
+            .iter()
+            .zip(border.0)
+            .all(|(&a, b)| (a as i32 - b as i32).abs() <= tolerance)
+    };
+    let row_is_border = |y: u32, left: u32, right: u32| (left..right).all(|x| is_border(x, y));
+    let column_is_border = |x: u32, top: u32, bottom: u32| (top..bottom).all(|y| is_border(x, y));
+
+    let top = (0..height).find(|&y| !row_is_border(y, 0, width))?;
+    // There is content in the row at `top`, so the scans below always stop
+    let bottom = (top..height).rev().find(|&y| !row_is_border(y, 0, width))? + 1;
+    let left = (0..width).find(|&x| !column_is_border(x, top, bottom))?;
+    let right = (left..width)
+        .rev()
+        .find(|&x| !column_is_border(x, top, bottom))?
+        + 1;
+    Some(Bounds {
+        x: left,
+        y: top,
+        width: right - left,
+        height: bottom - top,
+    })
+}
+
+/// Crops the uniform borders of an image.
+///
+/// The image is returned unchanged if it has no borders, or if it is entirely the border
+/// color.
+///
+/// # Arguments
+///
+/// * `image` - The image to trim.
+/// * `fuzz` - The color tolerance, from 0.0 (exact color) to 100.0 (any color).
+pub fn trim(image: DynamicImage, fuzz: f32) -> DynamicImage {
+    match content_bounds(&image, fuzz) {
+        Some(bounds) if (bounds.width, bounds.height) != image.dimensions() => {
+            image.crop_imm(bounds.x, bounds.y, bounds.width, bounds.height)
+        }
+        _ => image,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::RgbaImage;
+
+    #[test]
+    fn finds_content_inside_borders() {
+        let mut image = RgbaImage::from_pixel(10, 8, Rgba([255, 255, 255, 255]));
+        for (x, y) in [(2, 3), (6, 5), (4, 1)] {
+            image.put_pixel(x, y, Rgba([0, 0, 0, 255]));
+        }
+        let bounds = content_bounds(&image, 0.0).unwrap();
+        assert_eq!(
+            bounds,
+            Bounds {
+                x: 2,
+                y: 1,
+                width: 5,
+                height: 5
+            }
+        );
+
+        let trimmed = trim(DynamicImage::ImageRgba8(image), 0.0);
+        assert_eq!(trimmed.dimensions(), (5, 5));
+    }
+
+    #[test]
+    fn tolerates_noise_with_fuzz() {
+        // Letterbox bars with slightly noisy black
+        let image = RgbaImage::from_fn(6, 10, |_, y| match y {
+            0 | 1 | 8 | 9 => Rgba([(y % 2 * 8) as u8, 0, 0, 255]),
+            _ => Rgba([200, 150, 100, 255]),
+        });
+        assert_eq!(content_bounds(&image, 0.0).unwrap().height, 9);
+        let bounds = content_bounds(&image, 5.0).unwrap();
+        assert_eq!((bounds.y, bounds.height, bounds.width), (2, 6, 6));
+
+        let uniform = RgbaImage::from_pixel(4, 4, Rgba([9, 9, 9, 255]));
+        assert_eq!(content_bounds(&uniform, 0.0), None);
+        let uniform = DynamicImage::ImageRgba8(uniform);
+        assert_eq!(trim(uniform, 0.0).dimensions(), (4, 4));
+    }
+}
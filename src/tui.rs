@@ -0,0 +1,497 @@
+//! The terminal user interface of the `interactive` subcommand.
+//!
+//! The interface lists the images found in the given files and directories. The user picks
+//! the images to resize and adjusts the size, the output format and the JPEG quality. The
+//! highlighted image is run through the regular pipeline with the current settings, without
+//! saving it, to show its output dimensions and file size. Confirming leaves the interface
+//! and returns the selected job, which is then run as a regular batch.
+
+use image::ImageFormat;
+use image_resizer_rust::{
+    encode::{EncodeOptions, JPEG_QUALITY},
+    options::JobOptions,
+    resize_file_into,
+    sink::OutputSink,
+    ImageInfo, SourceInfo,
+};
+use log::LevelFilter;
+use ratatui::{
+    crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
+    layout::{Constraint, Layout},
+    style::{Modifier, Style},
+    text::Line,
+    widgets::{Block, Borders, List, ListItem, ListState, Paragraph},
+    DefaultTerminal, Frame,
+};
+use std::{
+    error::Error,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+/// The output formats to choose from. `None` keeps the format of each input.
+const FORMATS: [Option<&str>; 3] = [None, Some("jpeg"), Some("png")];
+
+/// The step by which the arrow keys change the JPEG quality.
+const QUALITY_STEP: u8 = 5;
+
+/// The images and settings confirmed in the interface.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Job {
+    /// The images to resize.
+    pub inputs: Vec<PathBuf>,
+    /// The target width, if given.
+    pub width: Option<u32>,
+    /// The target height, if given.
+    pub height: Option<u32>,
+    /// The output format, or `None` to keep the format of each input.
+    pub format: Option<String>,
+    /// The quality of JPEG outputs.
+    pub quality: u8,
+}
+
+/// Runs the interface on the images found in `paths`.
+///
+/// Directories are searched for images, without descending into subdirectories.
+///
+/// # Returns
+///
+/// The confirmed job, or `None` if the user quit.
+///
+/// # Errors
+///
+/// This function will return an error if a directory cannot be read, no image is found, or
+/// the terminal fails.
+pub fn run(paths: &[PathBuf]) -> Result<Option<Job>, Box<dyn Error>> {
+    let images = collect_images(paths)?;
+    if images.is_empty() {
+        return Err("No images found".into());
+    }
+    let mut app = App::new(images);
+
+    // Log messages would be drawn over the interface
+    let level = log::max_level();
+    log::set_max_level(LevelFilter::Off);
+    let mut terminal = ratatui::init();
+    let result = app.run(&mut terminal);
+    ratatui::restore();
+    log::set_max_level(level);
+
+    Ok(result?)
+}
+
+/// Returns the image files among `paths` and in the directories of `paths`.
+fn collect_images(paths: &[PathBuf]) -> io::Result<Vec<PathBuf>> {
+    let mut images = Vec::new();
+    for path in paths {
+        if path.is_dir() {
+            let mut found: Vec<PathBuf> = fs::read_dir(path)?
+                .map(|entry| entry.map(|entry| entry.path()))
+                .collect::<io::Result<_>>()?;
+            found.retain(|path| path.is_file() && ImageFormat::from_path(path).is_ok());
+            found.sort();
+            images.extend(found);
+        } else if path.is_file() {
+            images.push(path.clone());
+        } else {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("The path {:?} does not exist", path),
+            ));
+        }
+    }
+    Ok(images)
+}
+
+/// What the interface should do after a key press.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Action {
+    Continue,
+    Quit,
+    Run,
+}
+
+/// The panel receiving the key presses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Focus {
+    Files,
+    Settings,
+}
+
+/// The settings that can be edited.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Field {
+    Width,
+    Height,
+    Format,
+    Quality,
+}
+
+const FIELDS: [Field; 4] = [Field::Width, Field::Height, Field::Format, Field::Quality];
+
+/// An image listed in the interface.
+struct FileEntry {
+    path: PathBuf,
+    source: Option<SourceInfo>,
+    selected: bool,
+}
+
+/// The settings an estimate was computed with, to know when it is outdated.
+type EstimateKey = (usize, Option<u32>, Option<u32>, usize, u8);
+
+/// The state of the interface.
+struct App {
+    files: Vec<FileEntry>,
+    list: ListState,
+    focus: Focus,
+    field: usize,
+    width: String,
+    height: String,
+    format: usize,
+    quality: u8,
+    estimate: Option<(EstimateKey, Result<ImageInfo, String>)>,
+    message: Option<String>,
+}
+
+impl App {
+    fn new(images: Vec<PathBuf>) -> Self {
+        let files = images
+            .into_iter()
+            .map(|path| FileEntry {
+                source: SourceInfo::read(&path).ok(),
+                path,
+                selected: true,
+            })
+            .collect();
+        App {
+            files,
+            list: ListState::default().with_selected(Some(0)),
+            focus: Focus::Files,
+            field: 0,
+            width: String::new(),
+            height: String::new(),
+            format: 0,
+            quality: JPEG_QUALITY,
+            estimate: None,
+            message: None,
+        }
+    }
+
+    /// Draws the interface and handles key presses until the user quits or confirms.
+    fn run(&mut self, terminal: &mut DefaultTerminal) -> io::Result<Option<Job>> {
+        loop {
+            self.update_estimate();
+            terminal.draw(|frame| self.draw(frame))?;
+            if let Event::Key(key) = event::read()? {
+                if key.kind != KeyEventKind::Press {
+                    continue;
+                }
+                match self.handle_key(key) {
+                    Action::Continue => (),
+                    Action::Quit => return Ok(None),
+                    Action::Run => return Ok(Some(self.job())),
+                }
+            }
+        }
+    }
+
+    fn width(&self) -> Option<u32> {
+        self.width.parse().ok().filter(|&width| width > 0)
+    }
+
+    fn height(&self) -> Option<u32> {
+        self.height.parse().ok().filter(|&height| height > 0)
+    }
+
+    fn cursor(&self) -> usize {
+        self.list.selected().unwrap_or(0)
+    }
+
+    fn job(&self) -> Job {
+        Job {
+            inputs: self
+                .files
+                .iter()
+                .filter(|file| file.selected)
+                .map(|file| file.path.clone())
+                .collect(),
+            width: self.width(),
+            height: self.height(),
+            format: FORMATS[self.format].map(String::from),
+            quality: self.quality,
+        }
+    }
+
+    fn handle_key(&mut self, key: KeyEvent) -> Action {
+        self.message = None;
+        match key.code {
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => Action::Quit,
+            KeyCode::Char('q') | KeyCode::Esc => Action::Quit,
+            KeyCode::Tab | KeyCode::BackTab => {
+                self.focus = match self.focus {
+                    Focus::Files => Focus::Settings,
+                    Focus::Settings => Focus::Files,
+                };
+                Action::Continue
+            }
+            KeyCode::Enter => {
+                if !self.files.iter().any(|file| file.selected) {
+                    self.message = Some("Select at least one image".to_string());
+                } else if self.width().is_none() && self.height().is_none() {
+                    self.message = Some("Enter a width or a height".to_string());
+                } else {
+                    return Action::Run;
+                }
+                Action::Continue
+            }
+            code => {
+                match self.focus {
+                    Focus::Files => self.handle_files_key(code),
+                    Focus::Settings => self.handle_settings_key(code),
+                }
+                Action::Continue
+            }
+        }
+    }
+
+    fn handle_files_key(&mut self, code: KeyCode) {
+        let cursor = self.cursor();
+        match code {
+            KeyCode::Up | KeyCode::Char('k') => self.list.select(Some(cursor.saturating_sub(1))),
+            KeyCode::Down | KeyCode::Char('j') => {
+                self.list
+                    .select(Some((cursor + 1).min(self.files.len() - 1)));
+            }
+            KeyCode::Char(' ') => self.files[cursor].selected ^= true,
+            KeyCode::Char('a') => {
+                let select = !self.files.iter().all(|file| file.selected);
+                self.files
+                    .iter_mut()
+                    .for_each(|file| file.selected = select);
+            }
+            _ => (),
+        }
+    }
+
+    fn handle_settings_key(&mut self, code: KeyCode) {
+        let field = FIELDS[self.field];
+        match (code, field) {
+            (KeyCode::Up | KeyCode::Char('k'), _) => self.field = self.field.saturating_sub(1),
+            (KeyCode::Down | KeyCode::Char('j'), _) => {
+                self.field = (self.field + 1).min(FIELDS.len() - 1);
+            }
+            (KeyCode::Char(digit @ '0'..='9'), Field::Width | Field::Height) => {
+                let value = self.dimension_mut(field);
+                if value.len() < 6 {
+                    value.push(digit);
+                }
+            }
+            (KeyCode::Backspace, Field::Width | Field::Height) => {
+                self.dimension_mut(field).pop();
+            }
+            (KeyCode::Left, Field::Format) => {
+                self.format = (self.format + FORMATS.len() - 1) % FORMATS.len();
+            }
+            (KeyCode::Right, Field::Format) => self.format = (self.format + 1) % FORMATS.len(),
+            (KeyCode::Left, Field::Quality) => {
+                self.quality = self.quality.saturating_sub(QUALITY_STEP).max(1);
+            }
+            (KeyCode::Right, Field::Quality) => {
+                self.quality = (self.quality + QUALITY_STEP).min(100);
+            }
+            _ => (),
+        }
+    }
+
+    fn dimension_mut(&mut self, field: Field) -> &mut String {
+        match field {
+            Field::Width => &mut self.width,
+            _ => &mut self.height,
+        }
+    }
+
+    /// Runs the highlighted image through the pipeline if the settings changed.
+    fn update_estimate(&mut self) {
+        let (width, height) = (self.width(), self.height());
+        if width.is_none() && height.is_none() {
+            self.estimate = None;
+            return;
+        }
+        let key = (self.cursor(), width, height, self.format, self.quality);
+        if self.estimate.as_ref().is_some_and(|(k, _)| *k == key) {
+            return;
+        }
+
+        let path = &self.files[key.0].path;
+        let name = Path::new(path.file_name().unwrap_or_default());
+        let format = FORMATS[self.format].map(String::from);
+        let options = JobOptions {
+            encode: EncodeOptions {
+                jpeg_quality: Some(self.quality),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let result = resize_file_into(
+            path,
+            name,
+            width.as_ref(),
+            height.as_ref(),
+            format.as_ref(),
+            &options,
+            &mut Measure,
+        )
+        .map_err(|e| e.to_string());
+        self.estimate = Some((key, result));
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [main, help] =
+            Layout::vertical([Constraint::Min(0), Constraint::Length(1)]).areas(frame.area());
+        let [files, right] =
+            Layout::horizontal([Constraint::Percentage(55), Constraint::Percentage(45)])
+                .areas(main);
+        let [settings, preview] =
+            Layout::vertical([Constraint::Length(6), Constraint::Min(0)]).areas(right);
+
+        let items: Vec<ListItem> = self
+            .files
+            .iter()
+            .map(|file| {
+                let name = file.path.file_name().unwrap_or_default().to_string_lossy();
+                let details = file.source.as_ref().map_or("unreadable".to_string(), |s| {
+                    format!("{}x{}, {} bytes", s.width, s.height, s.file_size)
+                });
+                let mark = if file.selected { "[x]" } else { "[ ]" };
+                ListItem::new(format!("{} {} ({})", mark, name, details))
+            })
+            .collect();
+        let list = List::new(items)
+            .block(panel("Images", self.focus == Focus::Files))
+            .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+        frame.render_stateful_widget(list, files, &mut self.list);
+
+        let lines: Vec<Line> = FIELDS
+            .iter()
+            .enumerate()
+            .map(|(i, field)| {
+                let text = match field {
+                    Field::Width => format!("Width:   {}", self.width),
+                    Field::Height => format!("Height:  {}", self.height),
+                    Field::Format => format!("Format:  {}", FORMATS[self.format].unwrap_or("keep")),
+                    Field::Quality => format!("Quality: {}", self.quality),
+                };
+                if self.focus == Focus::Settings && i == self.field {
+                    Line::styled(text, Style::default().add_modifier(Modifier::REVERSED))
+                } else {
+                    Line::raw(text)
+                }
+            })
+            .collect();
+        frame.render_widget(
+            Paragraph::new(lines).block(panel("Settings", self.focus == Focus::Settings)),
+            settings,
+        );
+
+        let text = match &self.estimate {
+            None => "Enter a width or a height to see the output size.".to_string(),
+            Some((_, Ok(info))) => format!(
+                "Output: {}x{}, {:?}\nEstimated size: {} bytes{}",
+                info.width,
+                info.height,
+                info.format,
+                info.file_size,
+                info.compression_ratio()
+                    .map_or(String::new(), |ratio| format!(
+                        "\nCompression ratio: {:.2}",
+                        ratio
+                    ))
+            ),
+            Some((_, Err(e))) => format!("error: {}", e),
+        };
+        frame.render_widget(Paragraph::new(text).block(panel("Preview", false)), preview);
+
+        let message = self.message.clone().unwrap_or_else(|| {
+            "↑↓ move  Space select  a all  Tab settings  ←→ adjust  Enter resize  q quit"
+                .to_string()
+        });
+        frame.render_widget(Paragraph::new(message), help);
+    }
+}
+
+/// An output sink discarding the images, used to measure them.
+struct Measure;
+
+impl OutputSink for Measure {
+    fn write(&mut self, name: &Path, _contents: &[u8]) -> Result<PathBuf, Box<dyn Error>> {
+        Ok(name.to_path_buf())
+    }
+}
+
+/// Returns a bordered block, highlighted if it has the focus.
+fn panel(title: &str, focused: bool) -> Block<'_> {
+    let block = Block::default().borders(Borders::ALL).title(title);
+    if focused {
+        block.border_style(Style::default().add_modifier(Modifier::BOLD))
+    } else {
+        block
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn press(app: &mut App, codes: &[KeyCode]) -> Action {
+        codes
+            .iter()
+            .map(|&code| app.handle_key(KeyEvent::from(code)))
+            .last()
+            .unwrap_or(Action::Continue)
+    }
+
+    #[test]
+    fn collects_images_from_directories() {
+        let dir = tempfile::TempDir::new().expect("Failed to create a temp dir");
+        for name in ["b.png", "a.jpg", "notes.txt"] {
+            fs::write(dir.path().join(name), b"").unwrap();
+        }
+        fs::create_dir(dir.path().join("nested")).unwrap();
+        let extra = dir.path().join("nested/c.png");
+        fs::write(&extra, b"").unwrap();
+
+        let images = collect_images(&[dir.path().to_path_buf(), extra.clone()]).unwrap();
+        assert_eq!(
+            images,
+            vec![dir.path().join("a.jpg"), dir.path().join("b.png"), extra]
+        );
+        assert!(collect_images(&[dir.path().join("missing.png")]).is_err());
+    }
+
+    #[test]
+    fn edits_the_job() {
+        let mut app = App::new(vec![PathBuf::from("a.png"), PathBuf::from("b.png")]);
+        assert_eq!(press(&mut app, &[KeyCode::Enter]), Action::Continue);
+        assert!(app.message.is_some());
+
+        press(&mut app, &[KeyCode::Down, KeyCode::Char(' '), KeyCode::Tab]);
+        press(
+            &mut app,
+            &[KeyCode::Char('8'), KeyCode::Char('0'), KeyCode::Char('x')],
+        );
+        press(&mut app, &[KeyCode::Down, KeyCode::Down, KeyCode::Right]);
+        press(&mut app, &[KeyCode::Down, KeyCode::Left, KeyCode::Left]);
+        assert_eq!(press(&mut app, &[KeyCode::Enter]), Action::Run);
+        assert_eq!(
+            app.job(),
+            Job {
+                inputs: vec![PathBuf::from("a.png")],
+                width: Some(80),
+                height: None,
+                format: Some("jpeg".to_string()),
+                quality: JPEG_QUALITY - 2 * QUALITY_STEP,
+            }
+        );
+        assert_eq!(press(&mut app, &[KeyCode::Char('q')]), Action::Quit);
+    }
+}
@@ -0,0 +1,142 @@
+//! Physical units.
+//!
+//! This module parses print sizes such as `4x6in` or `10x15cm` and converts them to pixel
+//! dimensions at a given resolution in dots per inch (DPI).
+
+use std::{fmt, str::FromStr};
+
+/// The number of millimeters in an inch.
+const MM_PER_INCH: f64 = 25.4;
+
+/// A unit of length.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LengthUnit {
+    /// Inches (`in`).
+    Inch,
+    /// Centimeters (`cm`).
+    Centimeter,
+    /// Millimeters (`mm`).
+    Millimeter,
+}
+
+impl LengthUnit {
+    /// Returns the length of one unit in inches.
+    pub fn inches(self) -> f64 {
+        match self {
+            LengthUnit::Inch => 1.0,
+            LengthUnit::Centimeter => 10.0 / MM_PER_INCH,
+            LengthUnit::Millimeter => 1.0 / MM_PER_INCH,
+        }
+    }
+
+    /// Returns the suffix of the unit.
+    pub fn suffix(self) -> &'static str {
+        match self {
+            LengthUnit::Inch => "in",
+            LengthUnit::Centimeter => "cm",
+            LengthUnit::Millimeter => "mm",
+        }
+    }
+}
+
+/// A physical print size, such as `4x6in`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PrintSize {
+    /// The printed width, in `unit`.
+    pub width: f64,
+    /// The printed height, in `unit`.
+    pub height: f64,
+    /// The unit of `width` and `height`.
+    pub unit: LengthUnit,
+}
+
+impl PrintSize {
+    /// Returns the pixel dimensions needed to print at this size with the given resolution.
+    ///
+    /// Dimensions are rounded to the nearest pixel, and are at least one pixel.
+    pub fn to_pixels(&self, dpi: u16) -> (u32, u32) {
+        let pixels =
+            |length: f64| ((length * self.unit.inches() * dpi as f64).round() as u32).max(1);
+        (pixels(self.width), pixels(self.height))
+    }
+}
+
+impl fmt::Display for PrintSize {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}x{}{}", self.width, self.height, self.unit.suffix())
+    }
+}
+
+impl FromStr for PrintSize {
+    type Err = String;
+
+    /// Parses a print size written as `<width>x<height><unit>`, where the unit is `in`, `cm`
+    /// or `mm` (e.g. `4x6in`, `10.5x14.8cm`).
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim().to_lowercase();
+        let (dimensions, unit) = [
+            ("in", LengthUnit::Inch),
+            ("cm", LengthUnit::Centimeter),
+            ("mm", LengthUnit::Millimeter),
+        ]
+        .into_iter()
+        .find_map(|(suffix, unit)| s.strip_suffix(suffix).map(|rest| (rest, unit)))
+        .ok_or_else(|| format!("Missing unit in print size '{}' (use in, cm or mm)", s))?;
+
+        let (width, height) = dimensions
+            .split_once('x')
+            .ok_or_else(|| format!("Invalid print size '{}', expected e.g. 4x6in", s))?;
+        let parse = |value: &str| -> Result<f64, String> {
+            match value.trim().parse::<f64>() {
+                Ok(length) if length.is_finite() && length > 0.0 => Ok(length),
+                _ => Err(format!("Invalid length '{}' in print size '{}'", value, s)),
+            }
+        };
+
+        Ok(PrintSize {
+            width: parse(width)?,
+            height: parse(height)?,
+            unit,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_print_sizes() {
+        let size: PrintSize = "4x6in".parse().unwrap();
+        assert_eq!(size.unit, LengthUnit::Inch);
+        assert_eq!((size.width, size.height), (4.0, 6.0));
+
+        let size: PrintSize = "10.5X14.8CM".parse().unwrap();
+        assert_eq!(size.unit, LengthUnit::Centimeter);
+        assert_eq!((size.width, size.height), (10.5, 14.8));
+    }
+
+    #[test]
+    fn rejects_invalid_print_sizes() {
+        for input in ["4x6", "4in", "x6in", "0x6in", "-4x6mm", "4x6ft"] {
+            assert!(
+                input.parse::<PrintSize>().is_err(),
+                "{} should be rejected",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn converts_to_pixels() {
+        assert_eq!(
+            "4x6in".parse::<PrintSize>().unwrap().to_pixels(300),
+            (1200, 1800)
+        );
+        assert_eq!(
+            "254x127mm".parse::<PrintSize>().unwrap().to_pixels(100),
+            (1000, 500)
+        );
+        assert_eq!("1x1cm".parse::<PrintSize>().unwrap().to_pixels(1), (1, 1));
+    }
+}
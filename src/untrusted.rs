@@ -0,0 +1,232 @@
+//! Decoding hostile input.
+//!
+//! `decode_untrusted` is the entry point for images from untrusted sources, such as
+//! uploads, and for fuzzing. Unlike the decode stage, it works on bytes already in memory,
+//! always enforces limits, and reports every failure as a `ResizeError`: the size of the
+//! input is checked first, then the dimensions claimed by the header, then the memory the
+//! decoder would need, before any pixel is decoded. A panic inside a decoder is caught and
+//! reported as `ResizeError::DecoderPanicked` instead of unwinding into the caller.
+//!
+//! A fuzz target only needs to call it:
+//!
+//! ```
+//! use image_resizer_rust::untrusted::{decode_untrusted, DecodeLimits};
+//!
+//! fn fuzz_target(data: &[u8]) {
+//!     let _ = decode_untrusted(data, &DecodeLimits::default());
+//! }
+//! # fuzz_target(b"\x89PNG\r\n\x1a\n");
+//! ```
+
+use crate::error::ResizeError;
+use image::{DynamicImage, ImageDecoder, ImageError, ImageReader};
+use std::{
+    io::Cursor,
+    panic::{self, AssertUnwindSafe},
+};
+
+/// Limits applied by `decode_untrusted`. The defaults suit images up to 64 megapixels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecodeLimits {
+    /// The maximum size of the encoded input, in bytes.
+    pub max_size: u64,
+    /// The maximum width claimed by the image header.
+    pub max_width: u32,
+    /// The maximum height claimed by the image header.
+    pub max_height: u32,
+    /// The maximum number of pixels.
+    pub max_pixels: u64,
+    /// The maximum number of bytes the decoder may allocate, including the decoded image.
+    pub max_memory: u64,
+}
+
+impl Default for DecodeLimits {
+    fn default() -> Self {
+        DecodeLimits {
+            max_size: 64 << 20,
+            max_width: 16384,
+            max_height: 16384,
+            max_pixels: 64 << 20,
+            max_memory: 512 << 20,
+        }
+    }
+}
+
+/// Decodes an image from untrusted bytes.
+///
+/// # Arguments
+///
+/// * `bytes` - The encoded image, in any format supported by the `image` crate.
+/// * `limits` - The limits to enforce.
+///
+/// # Returns
+///
+/// The decoded image.
+///
+/// # Errors
+///
+/// This function never panics, and returns:
+/// - `ResizeError::InputTooLarge` if the input is larger than `max_size`.
+/// - `ResizeError::Malformed` if the format is unknown or the data is corrupt.
+/// - `ResizeError::InvalidDimensions` if the header claims a zero width or height.
+/// - `ResizeError::DimensionsExceeded` if the header claims a width or height above the
+///   limits.
+/// - `ResizeError::PixelsExceeded` if the header claims more than `max_pixels` pixels.
+/// - `ResizeError::MemoryExceeded` if decoding needs more than `max_memory` bytes.
+/// - `ResizeError::DecoderPanicked` if the decoder panicked.
+pub fn decode_untrusted(bytes: &[u8], limits: &DecodeLimits) -> Result<DynamicImage, ResizeError> {
+    let size = bytes.len() as u64;
+    if size > limits.max_size {
+        return Err(ResizeError::InputTooLarge {
+            size,
+            max_size: limits.max_size,
+        });
+    }
+
+    panic::catch_unwind(AssertUnwindSafe(|| decode(bytes, limits))).unwrap_or_else(|payload| {
+        let message = payload
+            .downcast_ref::<&str>()
+            .map(|s| s.to_string())
+            .or_else(|| payload.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "unknown panic".to_string());
+        Err(ResizeError::DecoderPanicked(message))
+    })
+}
+
+/// Decodes an image after checking its header against the limits.
+fn decode(bytes: &[u8], limits: &DecodeLimits) -> Result<DynamicImage, ResizeError> {
+    let mut reader = ImageReader::new(Cursor::new(bytes))
+        .with_guessed_format()
+        .map_err(|e| ResizeError::Malformed(e.to_string()))?;
+    if reader.format().is_none() {
+        return Err(ResizeError::Malformed("unknown image format".to_string()));
+    }
+    // The dimensions are checked below, to report them in the error
+    let mut decoder_limits = image::Limits::default();
+    decoder_limits.max_alloc = Some(limits.max_memory);
+    reader.limits(decoder_limits);
+    let decoder = reader.into_decoder().map_err(from_image_error)?;
+
+    let (width, height) = decoder.dimensions();
+    if width == 0 || height == 0 {
+        return Err(ResizeError::InvalidDimensions { width, height });
+    }
+    if width > limits.max_width || height > limits.max_height {
+        return Err(ResizeError::DimensionsExceeded { width, height });
+    }
+    if width as u64 * height as u64 > limits.max_pixels {
+        return Err(ResizeError::PixelsExceeded {
+            width,
+            height,
+            max_pixels: limits.max_pixels,
+        });
+    }
+    if decoder.total_bytes() > limits.max_memory {
+        return Err(ResizeError::MemoryExceeded);
+    }
+    DynamicImage::from_decoder(decoder).map_err(from_image_error)
+}
+
+/// Converts a decoder error into a `ResizeError`.
+fn from_image_error(error: ImageError) -> ResizeError {
+    match error {
+        ImageError::Limits(_) => ResizeError::MemoryExceeded,
+        error => ResizeError::Malformed(error.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Rng;
+    use image::{ImageFormat, RgbaImage};
+
+    fn encode(image: &RgbaImage, format: ImageFormat) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        DynamicImage::ImageRgba8(image.clone())
+            .to_rgb8()
+            .write_to(&mut Cursor::new(&mut bytes), format)
+            .unwrap();
+        bytes
+    }
+
+    #[test]
+    fn enforces_limits() {
+        let png = encode(&RgbaImage::new(40, 30), ImageFormat::Png);
+        let limits = DecodeLimits::default();
+        assert_eq!(decode_untrusted(&png, &limits).unwrap().width(), 40);
+
+        let small = |limits: DecodeLimits| decode_untrusted(&png, &limits).unwrap_err();
+        assert_eq!(
+            small(DecodeLimits {
+                max_size: 10,
+                ..limits
+            }),
+            ResizeError::InputTooLarge {
+                size: png.len() as u64,
+                max_size: 10
+            }
+        );
+        assert_eq!(
+            small(DecodeLimits {
+                max_width: 39,
+                ..limits
+            }),
+            ResizeError::DimensionsExceeded {
+                width: 40,
+                height: 30
+            }
+        );
+        assert!(matches!(
+            small(DecodeLimits {
+                max_pixels: 1199,
+                ..limits
+            }),
+            ResizeError::PixelsExceeded { .. }
+        ));
+        assert_eq!(
+            small(DecodeLimits {
+                max_memory: 100,
+                ..limits
+            }),
+            ResizeError::MemoryExceeded
+        );
+        assert!(matches!(
+            decode_untrusted(b"not an image", &limits),
+            Err(ResizeError::Malformed(_))
+        ));
+    }
+
+    #[test]
+    fn survives_mutated_inputs() {
+        let mut rng = Rng::new(42);
+        let image = rng.image(24, 16);
+        for format in [
+            ImageFormat::Png,
+            ImageFormat::Jpeg,
+            ImageFormat::Bmp,
+            ImageFormat::Gif,
+        ] {
+            let valid = encode(&image, format);
+            for _ in 0..200 {
+                let mut bytes = valid.clone();
+                let cut = rng.range(0..=bytes.len() as u32) as usize;
+                match rng.range(0..=2) {
+                    0 => bytes.truncate(cut),
+                    1 => {
+                        for _ in 0..rng.range(1..=8) {
+                            let position = rng.range(0..=bytes.len() as u32 - 1) as usize;
+                            bytes[position] = rng.next_u64() as u8;
+                        }
+                    }
+                    _ => bytes
+                        .splice(cut..cut, rng.next_u64().to_le_bytes())
+                        .for_each(drop),
+                }
+                if let Ok(decoded) = decode_untrusted(&bytes, &DecodeLimits::default()) {
+                    assert!(decoded.width() > 0 && decoded.height() > 0);
+                }
+            }
+        }
+    }
+}
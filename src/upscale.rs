@@ -0,0 +1,305 @@
+//! Upscaling engines.
+//!
+//! Enlarging an image with a resampling filter only interpolates between the existing
+//! pixels, so enlargements look soft. The `Upscaler` trait lets other engines, such as
+//! super-resolution neural networks that reconstruct plausible detail, be plugged into
+//! the pipeline through `UpscaleStage`.
+//!
+//! Two engines are provided:
+//! - `Engine::Resize`: the regular resizer (`fast_image_resize`), always available
+//! - `Engine::RealEsrgan`: a Real-ESRGAN model in the ONNX format, run with the pure Rust
+//!   `tract` runtime. It needs the `onnx` feature and a model file, which is not shipped
+//!   with this crate (e.g. `RealESRGAN_x4plus.onnx`, converted from the official weights).
+//!
+//! Neural engines upscale by the fixed factor of their model. Their output is then resized
+//! to the requested factor with the regular resizer, and the alpha channel, which they do
+//! not handle, is always upscaled with the regular resizer.
+
+use crate::{
+    options::{JobOptions, ResizeOptions},
+    pipeline::{PipelineState, Stage},
+    resize_image_with,
+};
+use image::{DynamicImage, RgbaImage};
+use std::{error::Error, fmt, path::Path, str::FromStr};
+use tracing::debug;
+
+/// The name of the upscale stage.
+pub const UPSCALE: &str = "upscale";
+/// The largest supported upscale factor.
+pub const MAX_UPSCALE_FACTOR: u32 = 8;
+
+/// Enlarges images by an integer factor.
+pub trait Upscaler {
+    /// Returns the name of the engine, for logging.
+    fn name(&self) -> &str;
+
+    /// Returns `image` enlarged `factor` times along each dimension.
+    ///
+    /// # Errors
+    ///
+    /// Returning an error fails the job.
+    fn upscale(
+        &self,
+        image: &RgbaImage,
+        factor: u32,
+        options: &ResizeOptions,
+    ) -> Result<RgbaImage, Box<dyn Error>>;
+}
+
+/// The available upscaling engines.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Engine {
+    /// The regular resizer.
+    #[default]
+    Resize,
+    /// A Real-ESRGAN ONNX model.
+    RealEsrgan,
+}
+
+impl fmt::Display for Engine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Engine::Resize => "resize",
+            Engine::RealEsrgan => "realesrgan",
+        };
+        f.write_str(name)
+    }
+}
+
+impl FromStr for Engine {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "resize" | "default" => Ok(Engine::Resize),
+            "realesrgan" | "real-esrgan" => Ok(Engine::RealEsrgan),
+            _ => Err(format!("Unknown upscaling engine '{}'", s)),
+        }
+    }
+}
+
+/// Creates the upscaler of an engine.
+///
+/// # Arguments
+///
+/// * `engine` - The engine.
+/// * `model` - The model file of neural engines.
+///
+/// # Errors
+///
+/// This function will return an error if a neural engine is requested without a model, if
+/// the model cannot be loaded, or if the crate was built without the `onnx` feature.
+pub fn upscaler(engine: Engine, model: Option<&Path>) -> Result<Box<dyn Upscaler>, Box<dyn Error>> {
+    match engine {
+        Engine::Resize => Ok(Box::new(ResizeUpscaler)),
+        Engine::RealEsrgan => {
+            let model = model.ok_or("The realesrgan engine needs an ONNX model file")?;
+            onnx_upscaler(model)
+        }
+    }
+}
+
+#[cfg(feature = "onnx")]
+fn onnx_upscaler(model: &Path) -> Result<Box<dyn Upscaler>, Box<dyn Error>> {
+    Ok(Box::new(onnx::OnnxUpscaler::load(model)?))
+}
+
+#[cfg(not(feature = "onnx"))]
+fn onnx_upscaler(_model: &Path) -> Result<Box<dyn Upscaler>, Box<dyn Error>> {
+    Err("Neural upscaling is not available: this build does not have the 'onnx' feature".into())
+}
+
+/// Parses an upscale factor given as `2x` or `2`.
+///
+/// # Errors
+///
+/// This function will return an error message if the value is not a whole number from 2
+/// to `MAX_UPSCALE_FACTOR`, optionally followed by `x`.
+pub fn parse_factor(s: &str) -> Result<u32, String> {
+    let s = s.trim();
+    let number = s.strip_suffix(['x', 'X']).unwrap_or(s);
+    match number.parse::<u32>() {
+        Ok(factor) if (2..=MAX_UPSCALE_FACTOR).contains(&factor) => Ok(factor),
+        _ => Err(format!(
+            "'{}' is not a valid factor, expected 2x to {}x",
+            s, MAX_UPSCALE_FACTOR
+        )),
+    }
+}
+
+/// Upscales with the regular resizer.
+pub struct ResizeUpscaler;
+
+impl Upscaler for ResizeUpscaler {
+    fn name(&self) -> &str {
+        "resize"
+    }
+
+    fn upscale(
+        &self,
+        image: &RgbaImage,
+        factor: u32,
+        options: &ResizeOptions,
+    ) -> Result<RgbaImage, Box<dyn Error>> {
+        let (width, height) = (image.width() * factor, image.height() * factor);
+        resize_image_with(
+            DynamicImage::ImageRgba8(image.clone()),
+            Some(&width),
+            Some(&height),
+            options,
+        )
+    }
+}
+
+#[cfg(feature = "onnx")]
+mod onnx {
+    use super::{ResizeUpscaler, Upscaler};
+    use crate::{options::ResizeOptions, resize_image_with};
+    use image::{DynamicImage, RgbaImage};
+    use std::{error::Error, path::Path};
+    use tract_onnx::prelude::*;
+
+    /// Upscales with a super-resolution ONNX model taking and returning NCHW RGB images
+    /// with values from 0 to 1, such as Real-ESRGAN.
+    pub struct OnnxUpscaler {
+        model: InferenceModel,
+    }
+
+    impl OnnxUpscaler {
+        /// Loads an ONNX model.
+        ///
+        /// # Errors
+        ///
+        /// This function will return an error if the model cannot be read or parsed.
+        pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+            let model = tract_onnx::onnx()
+                .model_for_path(path)
+                .map_err(|e| format!("Failed to load the ONNX model {:?}: {}", path, e))?;
+            Ok(OnnxUpscaler { model })
+        }
+    }
+
+    impl Upscaler for OnnxUpscaler {
+        fn name(&self) -> &str {
+            "realesrgan"
+        }
+
+        fn upscale(
+            &self,
+            image: &RgbaImage,
+            factor: u32,
+            options: &ResizeOptions,
+        ) -> Result<RgbaImage, Box<dyn Error>> {
+            let (width, height) = (image.width() as usize, image.height() as usize);
+            // The model is optimized for the size of every image
+            let plan = self
+                .model
+                .clone()
+                .with_input_fact(0, f32::fact([1, 3, height, width]).into())?
+                .into_optimized()?
+                .into_runnable()?;
+            let input: Tensor =
+                tract_ndarray::Array4::from_shape_fn((1, 3, height, width), |(_, c, y, x)| {
+                    image.get_pixel(x as u32, y as u32)[c] as f32 / 255.0
+                })
+                .into();
+            let outputs = plan.run(tvec!(input.into()))?;
+            let output = outputs[0].to_array_view::<f32>()?;
+            let shape = output.shape();
+            if shape.len() != 4 || shape[1] != 3 {
+                return Err(format!("Unexpected model output shape {:?}", shape).into());
+            }
+            let (out_height, out_width) = (shape[2], shape[3]);
+
+            // The model does not handle transparency
+            let (width, height) = (image.width() * factor, image.height() * factor);
+            let alpha = ResizeUpscaler.upscale(image, factor, options)?;
+            let upscaled = RgbaImage::from_fn(out_width as u32, out_height as u32, |x, y| {
+                let (x, y) = (x as usize, y as usize);
+                let channel =
+                    |c: usize| (output[[0, c, y, x]] * 255.0).round().clamp(0.0, 255.0) as u8;
+                image::Rgba([channel(0), channel(1), channel(2), 255])
+            });
+            let mut upscaled = if upscaled.dimensions() == (width, height) {
+                upscaled
+            } else {
+                resize_image_with(
+                    DynamicImage::ImageRgba8(upscaled),
+                    Some(&width),
+                    Some(&height),
+                    options,
+                )?
+            };
+            for (pixel, alpha) in upscaled.pixels_mut().zip(alpha.pixels()) {
+                pixel[3] = alpha[3];
+            }
+            Ok(upscaled)
+        }
+    }
+}
+
+/// A stage enlarging the current image by a factor, meant to replace the standard resize
+/// stage.
+pub struct UpscaleStage {
+    upscaler: Box<dyn Upscaler>,
+    factor: u32,
+}
+
+impl UpscaleStage {
+    /// Creates a stage enlarging images `factor` times with `upscaler`.
+    pub fn new(upscaler: Box<dyn Upscaler>, factor: u32) -> Self {
+        UpscaleStage { upscaler, factor }
+    }
+}
+
+impl Stage for UpscaleStage {
+    fn name(&self) -> &str {
+        UPSCALE
+    }
+
+    fn run(&self, state: &mut PipelineState, options: &JobOptions) -> Result<(), Box<dyn Error>> {
+        let image = state.take_image()?.into_rgba8();
+        debug!(
+            "Upscaling {}x{} {}x with the {} engine",
+            image.width(),
+            image.height(),
+            self.factor,
+            self.upscaler.name()
+        );
+        let upscaled = self
+            .upscaler
+            .upscale(&image, self.factor, &options.resize)?;
+        state.image = Some(DynamicImage::ImageRgba8(upscaled));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    #[test]
+    fn upscales_with_the_resizer() {
+        let image = RgbaImage::from_pixel(3, 2, Rgba([10, 20, 30, 255]));
+        let resizer = upscaler(Engine::Resize, None).unwrap();
+        let upscaled = resizer
+            .upscale(&image, 3, &ResizeOptions::default())
+            .unwrap();
+        assert_eq!(upscaled.dimensions(), (9, 6));
+        assert_eq!(upscaled.get_pixel(4, 4), &Rgba([10, 20, 30, 255]));
+
+        assert!(upscaler(Engine::RealEsrgan, None).is_err());
+    }
+
+    #[test]
+    fn parses_factors_and_engines() {
+        assert_eq!(parse_factor("2x"), Ok(2));
+        assert_eq!(parse_factor("4"), Ok(4));
+        assert!(parse_factor("1x").is_err());
+        assert!(parse_factor("9x").is_err());
+        assert_eq!("Real-ESRGAN".parse::<Engine>(), Ok(Engine::RealEsrgan));
+        assert_eq!(Engine::Resize.to_string().parse(), Ok(Engine::Resize));
+    }
+}
@@ -0,0 +1,151 @@
+//! Verification of written outputs.
+//!
+//! A disk running out of space, a flaky network share or a failing drive can leave an
+//! output truncated or corrupted without the write reporting any error. Verifying an
+//! output reads it back from the storage, decodes it completely and checks that it has the
+//! size, dimensions and format it was saved with. The SHA-256 checksum of the file is
+//! returned, so that copies of the output can be checked later, e.g. with `sha256sum`.
+
+use crate::{manifest, storage::Storage, ImageInfo};
+use image::{GenericImageView, ImageFormat};
+use std::{error::Error, io::Read};
+
+/// The result of a successful verification.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Verification {
+    /// The hexadecimal SHA-256 checksum of the output file.
+    pub checksum: String,
+}
+
+/// Reads an output back and checks that it is the image that was written.
+///
+/// # Arguments
+///
+/// * `storage` - The storage the output was written to.
+/// * `output` - The output, as returned when it was saved.
+///
+/// # Returns
+///
+/// The checksum of the output.
+///
+/// # Errors
+///
+/// This function will return an error if the output cannot be read or decoded, or if its
+/// file size, dimensions or format differ from `output`.
+pub fn verify_output(
+    storage: &dyn Storage,
+    output: &ImageInfo,
+) -> Result<Verification, Box<dyn Error>> {
+    let failed = |reason: String| format!("Verification of {:?} failed: {}", output.path, reason);
+    let mut contents = Vec::new();
+    storage
+        .open_read(&output.path)
+        .and_then(|mut reader| reader.read_to_end(&mut contents))
+        .map_err(|e| failed(e.to_string()))?;
+    if contents.len() as u64 != output.file_size {
+        return Err(failed(format!(
+            "{} bytes were read instead of {}",
+            contents.len(),
+            output.file_size
+        ))
+        .into());
+    }
+    let format = match image::guess_format(&contents) {
+        Ok(format) => format,
+        // TGA files have no signature to guess the format from
+        Err(_) if output.format == ImageFormat::Tga => ImageFormat::Tga,
+        Err(e) => return Err(failed(e.to_string()).into()),
+    };
+    if format != output.format {
+        return Err(failed(format!(
+            "it is a {:?} image, not {:?}",
+            format, output.format
+        ))
+        .into());
+    }
+    let image = image::load_from_memory_with_format(&contents, format)
+        .map_err(|e| failed(e.to_string()))?;
+    if image.dimensions() != (output.width, output.height) {
+        let (width, height) = image.dimensions();
+        return Err(failed(format!(
+            "it is {}x{} instead of {}x{}",
+            width, height, output.width, output.height
+        ))
+        .into());
+    }
+
+    Ok(Verification {
+        checksum: manifest::sha256_hex(&contents),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{options::JobOptions, resize_file, storage::FileSystem};
+    use image::RgbImage;
+    use std::fs;
+    use tempfile::TempDir;
+
+    #[test]
+    fn verifies_outputs() {
+        let dir = TempDir::new().expect("Failed to create a temp dir");
+        let input = dir.path().join("input.png");
+        RgbImage::new(40, 20).save(&input).unwrap();
+        let output = dir.path().join("output.png");
+        let options = JobOptions::default();
+        let info = resize_file(&input, &output, Some(&20), None, None, &options).unwrap();
+        let verification = verify_output(&FileSystem, &info).unwrap();
+        assert_eq!(verification.checksum.len(), 64);
+
+        let wrong = ImageInfo {
+            width: 21,
+            ..info.clone()
+        };
+        let error = verify_output(&FileSystem, &wrong).unwrap_err();
+        assert!(error.to_string().contains("it is 20x10 instead of 21x10"));
+
+        // A truncated output is detected even if its header is intact
+        let contents = fs::read(&output).unwrap();
+        fs::write(&output, &contents[..contents.len() - 20]).unwrap();
+        let truncated = ImageInfo {
+            file_size: contents.len() as u64 - 20,
+            ..info
+        };
+        assert!(verify_output(&FileSystem, &truncated).is_err());
+    }
+
+    #[test]
+    fn reports_verification_in_results() {
+        let dir = TempDir::new().expect("Failed to create a temp dir");
+        let input = dir.path().join("input.png");
+        RgbImage::new(40, 20).save(&input).unwrap();
+        let options = JobOptions::default();
+        let plain = dir.path().join("plain.jpg");
+        let info = resize_file(&input, &plain, Some(&20), None, None, &options).unwrap();
+        assert_eq!(info.verification, None);
+
+        let options = JobOptions {
+            verify: true,
+            ..Default::default()
+        };
+        let output = dir.path().join("output.jpg");
+        let info = resize_file(&input, &output, Some(&20), None, None, &options).unwrap();
+        let checksum = &info.verification.unwrap().checksum;
+        let contents = fs::read(&output).unwrap();
+        assert_eq!(checksum[..8], crate::manifest::content_hash(&contents));
+
+        // Images copied through unchanged are verified too
+        let copy = dir.path().join("copy.png");
+        let info = resize_file(
+            &input,
+            &copy,
+            Some(&40),
+            None,
+            Some(&"png".into()),
+            &options,
+        )
+        .unwrap();
+        assert!(info.verification.is_some());
+    }
+}
@@ -0,0 +1,212 @@
+//! Watermarks.
+//!
+//! A watermark is an image, such as a logo, or a line of text drawn with the built-in font,
+//! blended over each output at one of the positions of `Gravity`, inset by a margin. The
+//! watermark keeps its size whatever the size of the output, so it is normally applied
+//! after the resize; a watermark larger than the output is clipped.
+
+use crate::{
+    crop::Gravity,
+    font,
+    options::JobOptions,
+    pipeline::{PipelineState, Stage},
+};
+use image::{DynamicImage, Rgba, RgbaImage};
+use std::error::Error;
+
+/// The name of the watermark stage.
+pub const WATERMARK: &str = "watermark";
+
+/// A mark blended over images.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Watermark {
+    /// The mark, with its alpha already scaled by the opacity.
+    mark: RgbaImage,
+    position: Gravity,
+    margin: u32,
+}
+
+impl Watermark {
+    /// Creates a watermark.
+    ///
+    /// # Arguments
+    ///
+    /// * `mark` - The image blended over the outputs, according to its alpha channel.
+    /// * `position` - Where the mark is placed. `Gravity::Smart` is not supported.
+    /// * `opacity` - The opacity of the mark, from 0.0 (invisible) to 1.0 (as is).
+    /// * `margin` - The distance between the mark and the edges it is placed against.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the position is `Gravity::Smart` or the
+    /// opacity is out of range.
+    pub fn new(
+        mut mark: RgbaImage,
+        position: Gravity,
+        opacity: f32,
+        margin: u32,
+    ) -> Result<Self, Box<dyn Error>> {
+        if position == Gravity::Smart {
+            return Err("A watermark cannot be placed with the smart gravity".into());
+        }
+        if !(0.0..=1.0).contains(&opacity) {
+            return Err(format!(
+                "The watermark opacity must be between 0 and 1, not {}",
+                opacity
+            )
+            .into());
+        }
+        for pixel in mark.pixels_mut() {
+            pixel[3] = (pixel[3] as f32 * opacity).round() as u8;
+        }
+        Ok(Watermark {
+            mark,
+            position,
+            margin,
+        })
+    }
+
+    /// Renders a line of text as a mark for `Watermark::new`.
+    ///
+    /// # Arguments
+    ///
+    /// * `text` - The text, drawn with the built-in font.
+    /// * `scale` - The size of a font pixel in image pixels.
+    /// * `color` - The color of the text.
+    pub fn text_mark(text: &str, scale: u32, color: Rgba<u8>) -> RgbaImage {
+        let scale = scale.max(1);
+        let mut mark = RgbaImage::new(
+            font::text_width(text, scale).max(1),
+            font::line_height(scale),
+        );
+        font::draw_text(&mut mark, text, 0, 0, scale, color);
+        mark
+    }
+
+    /// Returns the position of the top-left corner of the mark on an image of the given
+    /// size. It is negative if the mark does not fit.
+    pub fn position_on(&self, width: u32, height: u32) -> (i64, i64) {
+        let margin = self.margin as i64;
+        let place = |size: u32, mark: u32, start: bool, end: bool| {
+            let free = size as i64 - mark as i64;
+            match (start, end) {
+                (true, false) => margin,
+                (false, true) => free - margin,
+                _ => free / 2,
+            }
+        };
+        let (west, east, north, south) = match self.position {
+            Gravity::North => (false, false, true, false),
+            Gravity::South => (false, false, false, true),
+            Gravity::East => (false, true, false, false),
+            Gravity::West => (true, false, false, false),
+            Gravity::NorthEast => (false, true, true, false),
+            Gravity::NorthWest => (true, false, true, false),
+            Gravity::SouthEast => (false, true, false, true),
+            Gravity::SouthWest => (true, false, false, true),
+            Gravity::Center | Gravity::Smart => (false, false, false, false),
+        };
+        (
+            place(width, self.mark.width(), west, east),
+            place(height, self.mark.height(), north, south),
+        )
+    }
+
+    /// Blends the mark over an image. Pixels of the mark falling outside of the image are
+    /// left out.
+    pub fn apply(&self, image: &mut RgbaImage) {
+        let (left, top) = self.position_on(image.width(), image.height());
+        for (x, y, mark) in self.mark.enumerate_pixels() {
+            let (Ok(x), Ok(y)) = (
+                u32::try_from(left + x as i64),
+                u32::try_from(top + y as i64),
+            ) else {
+                continue;
+            };
+            if let Some(pixel) = image.get_pixel_mut_checked(x, y) {
+                blend_over(pixel, mark);
+            }
+        }
+    }
+}
+
+/// Composites `source` over `target`. Unlike `Pixel::blend`, an opaque target stays fully
+/// opaque.
+fn blend_over(target: &mut Rgba<u8>, source: &Rgba<u8>) {
+    let source_alpha = source[3] as f32 / 255.0;
+    let target_alpha = target[3] as f32 / 255.0 * (1.0 - source_alpha);
+    let alpha = source_alpha + target_alpha;
+    if alpha == 0.0 {
+        return;
+    }
+    for channel in 0..3 {
+        let value = source[channel] as f32 * source_alpha + target[channel] as f32 * target_alpha;
+        target[channel] = (value / alpha).round() as u8;
+    }
+    target[3] = (alpha * 255.0).round() as u8;
+}
+
+/// A stage blending a watermark over the current image.
+pub struct WatermarkStage {
+    watermark: Watermark,
+}
+
+impl WatermarkStage {
+    /// Creates a stage applying `watermark`.
+    pub fn new(watermark: Watermark) -> Self {
+        WatermarkStage { watermark }
+    }
+}
+
+impl Stage for WatermarkStage {
+    fn name(&self) -> &str {
+        WATERMARK
+    }
+
+    fn run(&self, state: &mut PipelineState, _options: &JobOptions) -> Result<(), Box<dyn Error>> {
+        let mut image = state.take_image()?.into_rgba8();
+        self.watermark.apply(&mut image);
+        state.image = Some(DynamicImage::ImageRgba8(image));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn places_marks_by_gravity() {
+        let mark = RgbaImage::from_pixel(4, 2, Rgba([255, 0, 0, 255]));
+        let at = |position| {
+            Watermark::new(mark.clone(), position, 1.0, 3)
+                .unwrap()
+                .position_on(20, 10)
+        };
+        assert_eq!(at(Gravity::SouthEast), (13, 5));
+        assert_eq!(at(Gravity::NorthWest), (3, 3));
+        assert_eq!(at(Gravity::Center), (8, 4));
+        assert_eq!(at(Gravity::North), (8, 3));
+        assert!(Watermark::new(mark.clone(), Gravity::Smart, 1.0, 0).is_err());
+        assert!(Watermark::new(mark, Gravity::Center, 1.5, 0).is_err());
+    }
+
+    #[test]
+    fn blends_marks_with_their_opacity() {
+        let mark = RgbaImage::from_pixel(2, 2, Rgba([255, 255, 255, 255]));
+        let watermark = Watermark::new(mark, Gravity::SouthEast, 0.5, 1).unwrap();
+        let mut image = RgbaImage::from_pixel(6, 6, Rgba([0, 0, 0, 255]));
+        watermark.apply(&mut image);
+        assert_eq!(image.get_pixel(0, 0), &Rgba([0, 0, 0, 255]));
+        assert_eq!(image.get_pixel(5, 5), &Rgba([0, 0, 0, 255]));
+        let blended = image.get_pixel(4, 4);
+        assert_eq!(blended, &Rgba([128, 128, 128, 255]));
+
+        // Marks larger than the image are clipped
+        let text = Watermark::text_mark("(c) 2024", 2, Rgba([255, 255, 255, 255]));
+        let watermark = Watermark::new(text, Gravity::Center, 1.0, 0).unwrap();
+        let mut small = RgbaImage::from_pixel(10, 4, Rgba([0, 0, 0, 255]));
+        watermark.apply(&mut small);
+        assert!(small.pixels().any(|pixel| pixel[0] == 255));
+    }
+}
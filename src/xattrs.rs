@@ -0,0 +1,116 @@
+//! Extended attribute passthrough.
+//!
+//! Photo libraries and file managers store metadata such as Finder tags and comments in
+//! extended attributes. A resized file is a new file, so these attributes are lost unless
+//! they are copied from the source. `XattrPolicy` copies them after the output is written,
+//! optionally leaving out the macOS quarantine flag (`com.apple.quarantine`).
+//!
+//! Extended attributes are only supported on Unix platforms (macOS, Linux and the BSDs).
+
+use std::path::Path;
+
+/// The attribute macOS uses to mark files downloaded from the internet.
+pub const QUARANTINE: &str = "com.apple.quarantine";
+
+/// Controls which extended attributes are copied from an input to its output.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct XattrPolicy {
+    /// Copy the extended attributes of the input to the output.
+    pub copy: bool,
+    /// Do not copy the quarantine flag, so the output opens without a Gatekeeper warning.
+    pub strip_quarantine: bool,
+}
+
+impl XattrPolicy {
+    /// Copies the extended attributes of `source` to `destination`, following the policy.
+    ///
+    /// # Returns
+    ///
+    /// The number of attributes copied.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the attributes cannot be read or written, for
+    /// example because the filesystem does not support them, or if copying is requested on a
+    /// platform without extended attributes.
+    pub fn apply(
+        &self,
+        source: &Path,
+        destination: &Path,
+    ) -> Result<usize, Box<dyn std::error::Error>> {
+        if !self.copy {
+            return Ok(0);
+        }
+        copy_xattrs(source, destination, |name| {
+            !(self.strip_quarantine && name == QUARANTINE)
+        })
+    }
+}
+
+/// Copies the extended attributes of `source` accepted by `filter` to `destination`.
+#[cfg(unix)]
+fn copy_xattrs(
+    source: &Path,
+    destination: &Path,
+    filter: impl Fn(&str) -> bool,
+) -> Result<usize, Box<dyn std::error::Error>> {
+    let mut copied = 0;
+    for name in xattr::list_deref(source)? {
+        if !filter(&name.to_string_lossy()) {
+            continue;
+        }
+        if let Some(value) = xattr::get_deref(source, &name)? {
+            xattr::set_deref(destination, &name, &value).map_err(|e| {
+                format!(
+                    "Failed to copy extended attribute {:?} to {:?}: {}",
+                    name, destination, e
+                )
+            })?;
+            copied += 1;
+        }
+    }
+    Ok(copied)
+}
+
+/// Extended attributes are not available on this platform.
+#[cfg(not(unix))]
+fn copy_xattrs(
+    _source: &Path,
+    _destination: &Path,
+    _filter: impl Fn(&str) -> bool,
+) -> Result<usize, Box<dyn std::error::Error>> {
+    Err("Copying extended attributes is not supported on this platform".into())
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn copies_attributes() {
+        let dir = tempfile::TempDir::new().expect("Failed to create a temp dir");
+        let source = dir.path().join("input.png");
+        let destination = dir.path().join("output.png");
+        std::fs::write(&source, b"input").unwrap();
+        std::fs::write(&destination, b"output").unwrap();
+
+        // Some filesystems used for temporary directories do not support user attributes
+        if xattr::set(&source, "user.tag", b"red").is_err() {
+            return;
+        }
+
+        assert_eq!(
+            XattrPolicy::default().apply(&source, &destination).unwrap(),
+            0
+        );
+        let policy = XattrPolicy {
+            copy: true,
+            strip_quarantine: true,
+        };
+        assert_eq!(policy.apply(&source, &destination).unwrap(), 1);
+        assert_eq!(
+            xattr::get(&destination, "user.tag").unwrap(),
+            Some(b"red".to_vec())
+        );
+    }
+}